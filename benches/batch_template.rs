@@ -0,0 +1,62 @@
+use bytes::Bytes;
+use criterion::*;
+use samsa::prelude::{
+    encode::ToByte,
+    protocol::produce::request::{
+        BatchProducer, Message, Record, RecordBatch, RecordBatchAttributes, RecordBatchTemplate,
+    },
+    Compression,
+};
+
+fn record_batch(compression: Option<Compression>) -> RecordBatch {
+    let mut batch = RecordBatch::new(RecordBatchAttributes::new(compression));
+    for i in 0..100 {
+        batch.push(Record::new(
+            Message {
+                key: Some(Bytes::from(format!("key {i}"))),
+                value: Some(Bytes::from(format!("value {i}"))),
+                headers: vec![],
+            },
+            0,
+            0,
+        ));
+    }
+    batch.set_producer(BatchProducer {
+        producer_id: 42,
+        producer_epoch: 0,
+        base_sequence: 0,
+    });
+    batch
+}
+
+fn criterion_benchmark(c: &mut Criterion) {
+    let mut group = c.benchmark_group("batch_template");
+
+    for (name, compression) in [("none", None), ("gzip", Some(Compression::Gzip))] {
+        let batch = record_batch(compression);
+        group.bench_with_input(BenchmarkId::new("encode", name), &batch, |b, batch| {
+            b.iter(|| {
+                let mut buf = Vec::new();
+                batch.encode(&mut buf).unwrap();
+                buf
+            })
+        });
+
+        let mut template = RecordBatchTemplate::new(&batch).unwrap();
+        let mut sequence = 0;
+        group.bench_function(BenchmarkId::new("template", name), |b| {
+            b.iter(|| {
+                sequence += 100;
+                template
+                    .encode_with(sequence as i64, sequence)
+                    .unwrap()
+                    .len()
+            })
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
@@ -2,7 +2,7 @@ use bytes::{Bytes, BytesMut};
 use criterion::*;
 use samsa::prelude::{
     encode::ToByte,
-    protocol::{self, produce::request::Attributes},
+    protocol::{self, produce::request::RecordBatchAttributes},
 };
 
 fn criterion_benchmark(c: &mut Criterion) {
@@ -11,8 +11,13 @@ fn criterion_benchmark(c: &mut Criterion) {
     let topic_name = "purchases";
     let partition_id = 3;
 
-    let mut produce_req =
-        protocol::ProduceRequest::new(0, 1000, correlation_id, client_id, Attributes::new(None));
+    let mut produce_req = protocol::ProduceRequest::new(
+        0,
+        1000,
+        correlation_id,
+        client_id,
+        RecordBatchAttributes::new(None),
+    );
     produce_req.add(
         topic_name,
         partition_id,
@@ -1,8 +1,13 @@
-use crate::prelude::{protocol, BrokerConnection, Result};
-use std::collections::HashMap;
+use crate::prelude::{protocol, BrokerConnection, Error, KafkaCode, Result, TopicPartitions};
+use std::{collections::HashMap, time::Duration};
+
+const TOPIC_READY_POLL_INTERVAL: Duration = Duration::from_millis(100);
 
 /// Create a topic in the cluster.
 ///
+/// With `validate_only`, the broker checks that the topics could be created
+/// and reports any errors, without actually creating them.
+///
 /// See this [protocol spec] for more information.
 ///
 /// [protocol spec]: protocol::create_topics
@@ -11,9 +16,10 @@ pub async fn create_topics(
     correlation_id: i32,
     client_id: &str,
     topics_with_partition_count: HashMap<&str, i32>,
+    validate_only: bool,
 ) -> Result<protocol::CreateTopicsResponse> {
     let mut create_topics =
-        protocol::CreateTopicsRequest::new(correlation_id, client_id, 4000, false)?;
+        protocol::CreateTopicsRequest::new(correlation_id, client_id, 4000, validate_only)?;
 
     for (topic_name, num_partitions) in topics_with_partition_count {
         create_topics.add(topic_name, num_partitions, 1);
@@ -26,6 +32,192 @@ pub async fn create_topics(
     protocol::CreateTopicsResponse::try_from(create_topics_response.freeze())
 }
 
+/// The layout of a topic to create.
+///
+/// Either a partition count and replication factor for the broker to place,
+/// or an explicit assignment of each partition to the brokers holding its
+/// replicas. Setting both is rejected by [`create_topics_with_specs`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct TopicSpec {
+    /// The number of partitions, or -1 with manual assignments.
+    pub num_partitions: i32,
+    /// The number of replicas of each partition, or -1 with manual assignments.
+    pub replication_factor: i16,
+    /// The broker ids of the replicas of each partition, first one the
+    /// preferred leader. Empty to let the broker place the partitions.
+    pub assignments: Vec<(i32, Vec<i32>)>,
+}
+
+impl TopicSpec {
+    /// A topic the broker places itself.
+    pub fn new(num_partitions: i32, replication_factor: i16) -> Self {
+        Self {
+            num_partitions,
+            replication_factor,
+            assignments: vec![],
+        }
+    }
+
+    /// A topic with its replicas placed on the given brokers.
+    pub fn with_assignments(assignments: Vec<(i32, Vec<i32>)>) -> Self {
+        Self {
+            num_partitions: -1,
+            replication_factor: -1,
+            assignments,
+        }
+    }
+}
+
+/// Create topics with explicit layouts, see [`TopicSpec`].
+///
+/// Returns an `ArgError` without contacting the broker when a spec sets a
+/// partition count or replication factor alongside manual assignments.
+///
+/// See this [protocol spec] for more information.
+///
+/// [protocol spec]: protocol::create_topics
+pub async fn create_topics_with_specs(
+    mut conn: impl BrokerConnection,
+    correlation_id: i32,
+    client_id: &str,
+    topics: HashMap<&str, TopicSpec>,
+    validate_only: bool,
+) -> Result<protocol::CreateTopicsResponse> {
+    let mut create_topics =
+        protocol::CreateTopicsRequest::new(correlation_id, client_id, 4000, validate_only)?;
+
+    for (topic_name, spec) in topics {
+        if spec.assignments.is_empty() {
+            create_topics.add(topic_name, spec.num_partitions, spec.replication_factor);
+            continue;
+        }
+        if spec.num_partitions != -1 || spec.replication_factor != -1 {
+            return Err(Error::ArgError(format!(
+                "topic {topic_name} sets manual assignments and a replication factor"
+            )));
+        }
+        create_topics.add_with_assignments(topic_name, spec.assignments);
+    }
+
+    conn.send_request(&create_topics).await?;
+
+    let create_topics_response = conn.receive_response().await?;
+
+    protocol::CreateTopicsResponse::try_from(create_topics_response.freeze())
+}
+
+/// Create the topics that do not exist yet.
+///
+/// Topics that already exist count as success, as long as they have the
+/// requested number of partitions, otherwise this returns an `ArgError`.
+/// Returns the names of the topics that were newly created.
+///
+/// See this [protocol spec] for more information.
+///
+/// [protocol spec]: protocol::create_topics
+pub async fn ensure_topics(
+    mut conn: impl BrokerConnection,
+    correlation_id: i32,
+    client_id: &str,
+    topics_with_partition_count: HashMap<&str, i32>,
+) -> Result<Vec<String>> {
+    let mut create_topics =
+        protocol::CreateTopicsRequest::new(correlation_id, client_id, 4000, false)?;
+
+    for (topic_name, num_partitions) in topics_with_partition_count.iter() {
+        create_topics.add(topic_name, *num_partitions, 1);
+    }
+
+    conn.send_request(&create_topics).await?;
+    let create_topics_response =
+        protocol::CreateTopicsResponse::try_from(conn.receive_response().await?.freeze())?;
+
+    let mut created = vec![];
+    let mut existing = vec![];
+    for topic in create_topics_response.topics {
+        let name = String::from_utf8(topic.name.to_vec()).map_err(|_| Error::DecodingUtf8Error)?;
+        match topic.error_code {
+            KafkaCode::None => created.push(name),
+            KafkaCode::TopicAlreadyExists => existing.push(name),
+            error_code => {
+                tracing::error!("Could not create topic {}: {:?}", name, error_code);
+                return Err(Error::KafkaError(error_code));
+            }
+        }
+    }
+
+    if !existing.is_empty() {
+        let topics: Vec<&str> = existing.iter().map(String::as_str).collect();
+        let metadata_request = protocol::MetadataRequest::new(correlation_id, client_id, &topics);
+        conn.send_request(&metadata_request).await?;
+        let metadata_response =
+            protocol::MetadataResponse::try_from(conn.receive_response().await?.freeze())?;
+
+        for metadata in metadata_response.topics {
+            let Some(expected) = topics_with_partition_count
+                .iter()
+                .find(|(name, _)| name.as_bytes() == metadata.name)
+                .map(|(_, num_partitions)| *num_partitions)
+            else {
+                continue;
+            };
+            if metadata.partitions.len() as i32 != expected {
+                return Err(Error::ArgError(format!(
+                    "topic {} already exists with {} partitions, expected {}",
+                    String::from_utf8_lossy(&metadata.name),
+                    metadata.partitions.len(),
+                    expected
+                )));
+            }
+        }
+    }
+
+    Ok(created)
+}
+
+/// Wait until a topic is ready to be produced to.
+///
+/// A newly created topic is unknown to the brokers until the metadata
+/// propagates, so this polls metadata until the topic has partitions and
+/// each of them reports a leader. Returns a `RequestTimedOut` error if the
+/// topic is not ready within the timeout.
+pub async fn await_topic_ready(
+    mut conn: impl BrokerConnection,
+    correlation_id: i32,
+    client_id: &str,
+    topic: &str,
+    timeout: Duration,
+) -> Result<()> {
+    let deadline = tokio::time::Instant::now() + timeout;
+    let topics = [topic];
+
+    loop {
+        let metadata_request = protocol::MetadataRequest::new(correlation_id, client_id, &topics);
+        conn.send_request(&metadata_request).await?;
+        let metadata_response =
+            protocol::MetadataResponse::try_from(conn.receive_response().await?.freeze())?;
+
+        let ready = metadata_response.topics.iter().any(|metadata| {
+            metadata.name == topic.as_bytes()
+                && metadata.error_code == KafkaCode::None
+                && !metadata.partitions.is_empty()
+                && metadata
+                    .partitions
+                    .iter()
+                    .all(|partition| partition.leader_id >= 0)
+        });
+        if ready {
+            return Ok(());
+        }
+
+        if tokio::time::Instant::now() + TOPIC_READY_POLL_INTERVAL > deadline {
+            tracing::error!("Topic {} is not ready after {:?}", topic, timeout);
+            return Err(Error::KafkaError(KafkaCode::RequestTimedOut));
+        }
+        tokio::time::sleep(TOPIC_READY_POLL_INTERVAL).await;
+    }
+}
+
 /// Delete a topic in the cluster.
 ///
 /// See this [protocol spec] for more information.
@@ -49,3 +241,296 @@ pub async fn delete_topics(
 
     protocol::DeleteTopicsResponse::try_from(delete_topics_response.freeze())
 }
+
+/// Delete topics in the cluster by their topic ids.
+///
+/// This needs version 6 of the DeleteTopics API, so the broker is asked
+/// for its API versions first, and older brokers fail with
+/// `UNSUPPORTED_VERSION`. A topic id that the cluster does not know is
+/// answered with `UNKNOWN_TOPIC_ID` in the response.
+///
+/// See this [protocol spec] for more information.
+///
+/// [protocol spec]: protocol::delete_topics
+pub async fn delete_topics_by_id(
+    mut conn: impl BrokerConnection + Clone,
+    correlation_id: i32,
+    client_id: &str,
+    topic_ids: Vec<[u8; 16]>,
+) -> Result<protocol::DeleteTopicsResponse> {
+    let supported = api_versions(conn.clone(), correlation_id, client_id).await?;
+    let delete_topics_max = supported
+        .max_version(protocol::delete_topics::request::API_KEY_DELETE_TOPICS)
+        .unwrap_or_default();
+    if delete_topics_max < protocol::delete_topics::request::FIRST_TOPIC_ID_VERSION {
+        tracing::error!(
+            "Cannot delete topics by id, the broker supports DeleteTopics v{}",
+            delete_topics_max
+        );
+        return Err(Error::KafkaError(KafkaCode::UnsupportedVersion));
+    }
+
+    let mut delete_topics = protocol::DeleteTopicsRequest::new(correlation_id, client_id, 4000)?;
+    delete_topics.header.api_version = protocol::delete_topics::request::FIRST_TOPIC_ID_VERSION;
+    for topic_id in topic_ids {
+        delete_topics.add_id(topic_id);
+    }
+
+    conn.send_request(&delete_topics).await?;
+
+    let delete_topics_response = conn.receive_response().await?;
+
+    protocol::DeleteTopicsResponse::try_from_version(
+        delete_topics_response.freeze(),
+        delete_topics.header.api_version,
+    )
+}
+
+/// Describe the active producers of topic partitions.
+///
+/// For each partition this lists the producer id, epoch, last sequence
+/// number and last timestamp of every producer that is writing to it,
+/// which is useful for debugging idempotent and transactional producers.
+/// The connection must be to the leader of the partitions.
+///
+/// See this [protocol spec] for more information.
+///
+/// [protocol spec]: protocol::describe_producers
+pub async fn describe_producers(
+    mut conn: impl BrokerConnection,
+    correlation_id: i32,
+    client_id: &str,
+    topic_partitions: &TopicPartitions,
+) -> Result<protocol::DescribeProducersResponse> {
+    let mut describe_producers = protocol::DescribeProducersRequest::new(correlation_id, client_id);
+
+    for (topic_name, partitions) in topic_partitions.iter() {
+        for partition_index in partitions.iter() {
+            describe_producers.add(topic_name, *partition_index);
+        }
+    }
+
+    conn.send_request(&describe_producers).await?;
+
+    let describe_producers_response = conn.receive_response().await?;
+
+    let response =
+        protocol::DescribeProducersResponse::try_from(describe_producers_response.freeze())?;
+    response.is_error()?;
+
+    Ok(response)
+}
+
+/// List the transactions a broker is coordinating.
+///
+/// Each entry holds the transactional id, the producer id and the
+/// current state of the transaction, e.g. "Ongoing". Only the transactions
+/// coordinated by the broker behind `conn` are listed, so to see every
+/// transaction in the cluster this must be called against each broker.
+///
+/// See this [protocol spec] for more information.
+///
+/// [protocol spec]: protocol::list_transactions
+pub async fn list_transactions(
+    mut conn: impl BrokerConnection,
+    correlation_id: i32,
+    client_id: &str,
+) -> Result<protocol::ListTransactionsResponse> {
+    let list_transactions = protocol::ListTransactionsRequest::new(correlation_id, client_id);
+
+    conn.send_request(&list_transactions).await?;
+
+    let list_transactions_response = conn.receive_response().await?;
+
+    let response =
+        protocol::ListTransactionsResponse::try_from(list_transactions_response.freeze())?;
+    response.is_error()?;
+
+    Ok(response)
+}
+
+/// Describe the state of transactions.
+///
+/// For each transactional id this returns the transaction state, the
+/// producer id and epoch, and the topic partitions enrolled in the
+/// current transaction. The connection must be to the transaction
+/// coordinator of the transactional ids.
+///
+/// See this [protocol spec] for more information.
+///
+/// [protocol spec]: protocol::describe_transactions
+pub async fn describe_transactions(
+    mut conn: impl BrokerConnection,
+    correlation_id: i32,
+    client_id: &str,
+    transactional_ids: Vec<&str>,
+) -> Result<protocol::DescribeTransactionsResponse> {
+    let mut describe_transactions =
+        protocol::DescribeTransactionsRequest::new(correlation_id, client_id);
+
+    for transactional_id in transactional_ids {
+        describe_transactions.add(transactional_id);
+    }
+
+    conn.send_request(&describe_transactions).await?;
+
+    let describe_transactions_response = conn.receive_response().await?;
+
+    let response =
+        protocol::DescribeTransactionsResponse::try_from(describe_transactions_response.freeze())?;
+    response.is_error()?;
+
+    Ok(response)
+}
+
+/// Describe the ACLs matching a filter.
+///
+/// The broker must have an authorizer configured, otherwise this fails
+/// with [`KafkaCode::SecurityDisabled`].
+///
+/// See this [protocol spec] for more information.
+///
+/// [protocol spec]: protocol::describe_acls
+pub async fn describe_acls(
+    mut conn: impl BrokerConnection,
+    correlation_id: i32,
+    client_id: &str,
+    filter: &protocol::acl::AclBindingFilter,
+) -> Result<protocol::DescribeAclsResponse> {
+    let describe_acls = protocol::DescribeAclsRequest::new(correlation_id, client_id, filter);
+
+    conn.send_request(&describe_acls).await?;
+
+    let describe_acls_response = conn.receive_response().await?;
+
+    let response = protocol::DescribeAclsResponse::try_from(describe_acls_response.freeze())?;
+    response.is_error()?;
+
+    Ok(response)
+}
+
+/// Create ACLs, allowing or denying principals operations on resources.
+///
+/// The broker must have an authorizer configured, otherwise this fails
+/// with [`KafkaCode::SecurityDisabled`].
+///
+/// See this [protocol spec] for more information.
+///
+/// [protocol spec]: protocol::create_acls
+pub async fn create_acls(
+    mut conn: impl BrokerConnection,
+    correlation_id: i32,
+    client_id: &str,
+    bindings: &[protocol::acl::AclBinding],
+) -> Result<protocol::CreateAclsResponse> {
+    let mut create_acls = protocol::CreateAclsRequest::new(correlation_id, client_id);
+
+    for binding in bindings {
+        create_acls.add(binding);
+    }
+
+    conn.send_request(&create_acls).await?;
+
+    let create_acls_response = conn.receive_response().await?;
+
+    let response = protocol::CreateAclsResponse::try_from(create_acls_response.freeze())?;
+    response.is_error()?;
+
+    Ok(response)
+}
+
+/// Delete the ACLs matching any of the filters.
+///
+/// The response lists the deleted ACLs for each filter. The broker must
+/// have an authorizer configured, otherwise this fails with
+/// [`KafkaCode::SecurityDisabled`].
+///
+/// See this [protocol spec] for more information.
+///
+/// [protocol spec]: protocol::delete_acls
+pub async fn delete_acls(
+    mut conn: impl BrokerConnection,
+    correlation_id: i32,
+    client_id: &str,
+    filters: &[protocol::acl::AclBindingFilter],
+) -> Result<protocol::DeleteAclsResponse> {
+    let mut delete_acls = protocol::DeleteAclsRequest::new(correlation_id, client_id);
+
+    for filter in filters {
+        delete_acls.add(filter);
+    }
+
+    conn.send_request(&delete_acls).await?;
+
+    let delete_acls_response = conn.receive_response().await?;
+
+    let response = protocol::DeleteAclsResponse::try_from(delete_acls_response.freeze())?;
+    response.is_error()?;
+
+    Ok(response)
+}
+
+/// Ask a broker which API versions and features it supports.
+///
+/// Newer brokers also advertise the features finalized for the whole
+/// cluster, e.g. "transaction.version", see KIP-584.
+///
+/// See this [protocol spec] for more information.
+///
+/// [protocol spec]: protocol::api_versions
+pub async fn api_versions(
+    mut conn: impl BrokerConnection,
+    correlation_id: i32,
+    client_id: &str,
+) -> Result<protocol::ApiVersionsResponse> {
+    let api_versions = protocol::ApiVersionsRequest::new(correlation_id, client_id);
+
+    conn.send_request(&api_versions).await?;
+
+    let api_versions_response = conn.receive_response().await?;
+
+    let response = protocol::ApiVersionsResponse::try_from(api_versions_response.freeze())?;
+    response.is_error()?;
+
+    Ok(response)
+}
+
+/// The version to retry a request at, after the broker rejected
+/// `rejected_version` of the API with `UNSUPPORTED_VERSION`.
+///
+/// This steps down a single version, or further if the broker advertises a
+/// lower maximum, and fails unless that version is supported by both the
+/// broker and the caller, so a request is downgraded at most once.
+pub(crate) async fn downgrade_version(
+    conn: impl BrokerConnection,
+    correlation_id: i32,
+    client_id: &str,
+    api_key: i16,
+    rejected_version: i16,
+    min_version: i16,
+) -> Result<i16> {
+    let supported = api_versions(conn, correlation_id, client_id).await?;
+    let Some(range) = supported.api_keys.iter().find(|api| api.api_key == api_key) else {
+        return Err(Error::KafkaError(KafkaCode::UnsupportedVersion));
+    };
+
+    let version = (rejected_version - 1).min(range.max_version);
+    if version < range.min_version.max(min_version) {
+        tracing::error!(
+            "No version of API {} below {} is supported by both the broker ({}..={}) and the client",
+            api_key,
+            rejected_version,
+            range.min_version,
+            range.max_version
+        );
+        return Err(Error::KafkaError(KafkaCode::UnsupportedVersion));
+    }
+    tracing::warn!(
+        "Broker does not support version {} of API {}, retrying with version {}",
+        rejected_version,
+        api_key,
+        version
+    );
+
+    Ok(version)
+}
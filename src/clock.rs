@@ -0,0 +1,101 @@
+//! Sources of time for the timers of the crate.
+//!
+//! The producer waits on a [`Clock`] for its batches to linger, instead of
+//! on the runtime directly. [`TokioClock`] is used unless another clock is
+//! given. With the `test-internals` feature, `MockClock` only moves when
+//! told to, so tests can step through timeouts without waiting for them.
+
+use std::fmt::Debug;
+#[cfg(any(test, feature = "test-internals"))]
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use futures::future::BoxFuture;
+#[cfg(any(test, feature = "test-internals"))]
+use tokio::sync::watch;
+
+/// A source of time.
+pub trait Clock: Debug + Send + Sync {
+    /// The current time.
+    fn now(&self) -> Instant;
+
+    /// Wait for `duration` to pass.
+    ///
+    /// The wait starts when this is called, not when the future is first polled.
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()>;
+}
+
+/// The clock of the tokio runtime, the default.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TokioClock;
+
+impl Clock for TokioClock {
+    fn now(&self) -> Instant {
+        tokio::time::Instant::now().into_std()
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
+
+/// A clock that stands still until it is advanced.
+///
+/// Clones share the same time, so a test can keep a clone to move the clock
+/// it handed to a producer.
+#[cfg(any(test, feature = "test-internals"))]
+#[derive(Clone, Debug)]
+pub struct MockClock {
+    start: Instant,
+    elapsed: Arc<watch::Sender<Duration>>,
+}
+
+#[cfg(any(test, feature = "test-internals"))]
+impl Default for MockClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(any(test, feature = "test-internals"))]
+impl MockClock {
+    /// A clock standing still at the current time, until it is advanced.
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            elapsed: Arc::new(watch::channel(Duration::ZERO).0),
+        }
+    }
+
+    /// Move the clock forward, waking the sleeps that are now over.
+    pub fn advance(&self, duration: Duration) {
+        self.elapsed.send_modify(|elapsed| *elapsed += duration);
+    }
+
+    /// Number of sleeps that have not finished yet.
+    pub fn sleeping(&self) -> usize {
+        self.elapsed.receiver_count()
+    }
+}
+
+#[cfg(any(test, feature = "test-internals"))]
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.start + *self.elapsed.borrow()
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        let mut elapsed = self.elapsed.subscribe();
+        let deadline = *elapsed.borrow() + duration;
+        Box::pin(async move {
+            if elapsed
+                .wait_for(|elapsed| *elapsed >= deadline)
+                .await
+                .is_err()
+            {
+                // a dropped clock never moves again
+                std::future::pending::<()>().await;
+            }
+        })
+    }
+}
@@ -1,18 +1,31 @@
 //! Client that consumes records from a cluster.
 
-use std::{collections::HashMap, fmt::Debug};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Debug,
+    future::Future,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
 
 use async_stream::try_stream;
 use bytes::Bytes;
 use nom::AsBytes;
+use tokio::sync::{mpsc::channel, watch};
 use tokio_stream::{Stream, StreamExt};
 use tracing::instrument;
 
 use crate::{
-    error::{Error, Result},
+    consumer_builder::{list_offsets, offsets_for_timestamp, EARLIEST_TIMESTAMP, LATEST_TIMESTAMP},
+    encode::{try_usize_to_int, ToByte},
+    error::{Error, KafkaCode, Result},
     metadata::ClusterMetadata,
     network::BrokerConnection,
-    protocol, DEFAULT_CLIENT_ID, DEFAULT_CORRELATION_ID,
+    protocol::{
+        self,
+        fetch::response::{Decompression, Header},
+    },
+    DEFAULT_CLIENT_ID, DEFAULT_CORRELATION_ID,
 };
 
 const DEFAULT_MAX_WAIT_MS: i32 = 200;
@@ -20,18 +33,45 @@ const DEFAULT_MIN_BYTES: i32 = 100;
 const DEFAULT_MAX_BYTES: i32 = 30000;
 const DEFAULT_MAX_PARTITION_BYTES: i32 = 20000;
 const DEFAULT_ISOLATION_LEVEL: i8 = 0;
+const DEFAULT_MAX_BUFFERED_RECORDS: usize = 10000;
+const DEFAULT_MAX_NOT_LEADER_RETRIES: usize = 3;
 
 /// Common consumed message format.
 #[derive(Clone, Debug, PartialEq)]
 pub struct ConsumeMessage {
-    pub key: Bytes,
-    pub value: Bytes,
+    /// The key, sharing the buffer of the fetch response.
+    pub key: Option<Bytes>,
+    /// The value, sharing the buffer of the fetch response.
+    pub value: Option<Bytes>,
+    /// The record headers, in the order they were produced.
+    pub headers: Vec<Header>,
     pub offset: usize,
     pub timestamp: usize,
     pub topic_name: String,
     pub partition_index: i32,
 }
 
+/// The records that arrived in a single fetch response.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FetchBatch {
+    /// The records, in offset order within each topic partition.
+    pub messages: Vec<ConsumeMessage>,
+    /// The high watermark of each topic partition in the response.
+    pub high_watermarks: PartitionOffsets,
+}
+
+/// Where a consumer continues from when its position is out of range of the log.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum OffsetReset {
+    /// Move to the start of the log, the oldest record that was kept.
+    #[default]
+    Earliest,
+    /// Move to the end of the log, only reading records written from now on.
+    Latest,
+    /// Stay put and fail the fetch, until the consumer is sought elsewhere.
+    None,
+}
+
 #[derive(Clone, Debug)]
 pub struct FetchParams {
     pub correlation_id: i32,
@@ -41,6 +81,21 @@ pub struct FetchParams {
     pub max_bytes: i32,
     pub max_partition_bytes: i32,
     pub isolation_level: i8,
+    pub client_rack: String,
+    /// Keep compressed batches compressed until their records are iterated.
+    pub lazy_decompression: bool,
+    /// The most bytes the records of a fetched batch may decompress to.
+    pub max_decompressed_batch_bytes: usize,
+    /// How many fetched records a buffered stream holds before it stops fetching.
+    pub max_buffered_records: usize,
+    /// The version of the Fetch requests, from version 13 topics are
+    /// identified by their id.
+    pub fetch_version: i16,
+    /// How many times to refresh metadata and fetch again when a broker is
+    /// no longer the leader of a topic partition.
+    pub max_not_leader_retries: usize,
+    /// Where to continue from when the position is out of range of the log.
+    pub offset_reset: OffsetReset,
 }
 
 impl Default for FetchParams {
@@ -59,11 +114,20 @@ impl FetchParams {
             max_bytes: DEFAULT_MAX_BYTES,
             max_partition_bytes: DEFAULT_MAX_PARTITION_BYTES,
             isolation_level: DEFAULT_ISOLATION_LEVEL,
+            client_rack: String::new(),
+            lazy_decompression: false,
+            max_decompressed_batch_bytes:
+                protocol::fetch::response::DEFAULT_MAX_DECOMPRESSED_BATCH_BYTES,
+            max_buffered_records: DEFAULT_MAX_BUFFERED_RECORDS,
+            fetch_version: protocol::fetch::request::API_VERSION,
+            offset_reset: OffsetReset::default(),
+            max_not_leader_retries: DEFAULT_MAX_NOT_LEADER_RETRIES,
         }
     }
 }
 
-type TopicPartitionKey = (String, i32);
+/// Used to represent a single topic partition.
+pub type TopicPartition = (String, i32);
 
 /// Used to represent topic-partition assignments.
 ///
@@ -110,7 +174,105 @@ impl Default for TopicPartitionsBuilder {
 }
 
 /// Used to represent topic partition offsets.
-pub type PartitionOffsets = HashMap<TopicPartitionKey, i64>;
+pub type PartitionOffsets = HashMap<TopicPartition, i64>;
+
+/// An incremental fetch session with a broker, see KIP-227.
+///
+/// Once the broker created a session, fetches only carry the partitions
+/// whose fetch position changed and the partitions to remove from the
+/// session, instead of every partition each time.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct FetchSession {
+    /// The id the broker gave the session, 0 while there is none.
+    id: i32,
+    /// The epoch of the next fetch, 0 for a full fetch creating a session.
+    epoch: i32,
+    /// The offset and current leader epoch of each partition in the
+    /// session, as last sent to the broker.
+    partitions: HashMap<TopicPartition, (i64, i32)>,
+}
+
+impl FetchSession {
+    /// Whether the next fetch only carries the changes to the session.
+    fn is_incremental(&self) -> bool {
+        self.epoch > 0
+    }
+
+    /// Move to the next epoch after a fetch sending `partitions`, or start
+    /// over with a full fetch when the broker has no session for us.
+    fn update(
+        &mut self,
+        error_code: KafkaCode,
+        session_id: i32,
+        partitions: HashMap<TopicPartition, (i64, i32)>,
+    ) {
+        if error_code != KafkaCode::None || session_id == 0 {
+            if matches!(
+                error_code,
+                KafkaCode::FetchSessionIdNotFound | KafkaCode::InvalidFetchSessionEpoch
+            ) {
+                tracing::warn!(
+                    "Fetch session {} is gone ({:?}), starting a new one",
+                    self.id,
+                    error_code
+                );
+            }
+            *self = FetchSession::default();
+            return;
+        }
+        self.id = session_id;
+        // the epoch wraps around to 1, as 0 would create a new session
+        self.epoch = self.epoch.checked_add(1).unwrap_or(1);
+        self.partitions = partitions;
+    }
+}
+
+/// How a consumer is doing, for health checks like a Kubernetes liveness
+/// probe.
+///
+/// This is a handle the consumer keeps updating, so it can be held on to
+/// after the consumer is turned into a stream. Clones share the same state.
+#[derive(Clone, Debug, Default)]
+pub struct ConsumerHealth {
+    state: Arc<Mutex<HealthState>>,
+}
+
+#[derive(Debug, Default)]
+struct HealthState {
+    last_fetch: Option<Instant>,
+    last_heartbeat: Option<Instant>,
+    assignment: TopicPartitions,
+}
+
+impl ConsumerHealth {
+    /// When a fetch last succeeded, whether or not it returned records.
+    pub fn last_fetch(&self) -> Option<Instant> {
+        self.state.lock().unwrap().last_fetch
+    }
+
+    /// When the group coordinator last accepted a heartbeat, always `None`
+    /// for a consumer outside of a group.
+    pub fn last_heartbeat(&self) -> Option<Instant> {
+        self.state.lock().unwrap().last_heartbeat
+    }
+
+    /// The topic partitions currently assigned to the consumer.
+    pub fn assignment(&self) -> TopicPartitions {
+        self.state.lock().unwrap().assignment.clone()
+    }
+
+    pub(crate) fn record_fetch(&self) {
+        self.state.lock().unwrap().last_fetch = Some(Instant::now());
+    }
+
+    pub(crate) fn record_heartbeat(&self) {
+        self.state.lock().unwrap().last_heartbeat = Some(Instant::now());
+    }
+
+    pub(crate) fn set_assignment(&self, assignment: TopicPartitions) {
+        self.state.lock().unwrap().assignment = assignment;
+    }
+}
 
 /// Kafka/Redpanda Consumer.
 ///
@@ -167,21 +329,118 @@ pub struct Consumer<T: BrokerConnection> {
     pub(crate) assigned_topic_partitions: TopicPartitions,
     /// Offsets to read from for each assigned topic partition.
     pub(crate) offsets: PartitionOffsets,
+    /// Replicas the leaders asked us to fetch from instead of themselves.
+    pub(crate) preferred_read_replicas: HashMap<TopicPartition, i32>,
+    /// High watermark of each assigned topic partition in the first fetch that returned it.
+    pub(crate) initial_high_watermarks: HashMap<TopicPartition, i64>,
+    /// High watermark of each assigned topic partition in the last fetch that returned it.
+    pub(crate) high_watermarks: HashMap<TopicPartition, i64>,
+    /// Set once every assigned topic partition reached its initial high watermark.
+    pub(crate) caught_up: Arc<watch::Sender<bool>>,
+    /// Exclusive offsets to stop reading each bounded topic partition at.
+    pub(crate) end_offsets: PartitionOffsets,
+    /// Whether the initial high watermarks become the end offsets.
+    pub(crate) stop_at_end: bool,
+    /// Leader epoch of the record before the position of each topic
+    /// partition sought with one, kept up to date as batches are read.
+    pub(crate) leader_epochs: HashMap<TopicPartition, i32>,
+    /// Positions to check for log truncation before fetching them again.
+    pub(crate) positions_to_validate: HashSet<TopicPartition>,
+    /// Updated as the consumer makes progress.
+    pub(crate) health: ConsumerHealth,
+    /// The fetch session with each broker fetched from.
+    pub(crate) fetch_sessions: HashMap<i32, FetchSession>,
 }
 
 impl<'a, T: BrokerConnection + Clone + Debug + 'a> Consumer<T> {
+    /// Group the assigned topic partitions by the broker to fetch them from.
+    ///
+    /// This is the partition leader, unless the leader told us to read from
+    /// one of its replicas instead.
+    fn get_connections_for_fetch(&self) -> Result<Vec<(i32, T, TopicPartitions)>> {
+        let mut brokers_and_their_topic_partitions = self
+            .cluster_metadata
+            .get_leaders_for_topic_partitions(&self.unfinished_topic_partitions())?;
+
+        for ((topic_name, partition_index), replica_id) in self.preferred_read_replicas.iter() {
+            if !self
+                .cluster_metadata
+                .broker_connections
+                .contains_key(replica_id)
+            {
+                tracing::debug!("No connection to preferred read replica {}", replica_id);
+                continue;
+            }
+            let Some(partitions) = brokers_and_their_topic_partitions
+                .values_mut()
+                .filter_map(|topic_partitions| topic_partitions.get_mut(topic_name))
+                .find(|partitions| partitions.contains(partition_index))
+            else {
+                continue;
+            };
+            partitions.retain(|partition| partition != partition_index);
+            brokers_and_their_topic_partitions
+                .entry(*replica_id)
+                .or_default()
+                .entry(topic_name.to_owned())
+                .or_default()
+                .push(*partition_index);
+        }
+
+        let mut connections = vec![];
+        for (broker_id, topic_partitions) in brokers_and_their_topic_partitions.into_iter() {
+            let topic_partitions: TopicPartitions = topic_partitions
+                .into_iter()
+                .filter(|(_, partitions)| !partitions.is_empty())
+                .collect();
+            if topic_partitions.is_empty() {
+                continue;
+            }
+            let broker_conn = self
+                .cluster_metadata
+                .broker_connections
+                .get(&broker_id)
+                .ok_or(Error::MetadataNeedsSync)?
+                .clone();
+            tracing::debug!("Fetching {:?} from broker {}", topic_partitions, broker_id);
+            connections.push((broker_id, broker_conn, topic_partitions));
+        }
+
+        Ok(connections)
+    }
+
     #[instrument]
-    async fn consume(&self) -> Result<Vec<protocol::FetchResponse>> {
-        // TODO: Push this into the metadata
-        let brokers_and_their_topic_partitions = self
+    async fn consume(&mut self) -> Result<Vec<protocol::FetchResponse>> {
+        let brokers_and_their_topic_partitions = self.get_connections_for_fetch()?;
+        // fence fetches of the partitions we track epochs for, should the leader move
+        let current_leader_epochs: HashMap<TopicPartition, i32> = self
+            .leader_epochs
+            .keys()
+            .filter_map(|(topic_name, partition_index)| {
+                let leader_epoch = self
+                    .cluster_metadata
+                    .get_leader_epoch_for_topic_partition(topic_name, *partition_index)?;
+                Some(((topic_name.to_owned(), *partition_index), leader_epoch))
+            })
+            .collect();
+        let topic_ids: HashMap<String, [u8; 16]> = self
             .cluster_metadata
-            .get_connections_for_topic_partitions(&self.assigned_topic_partitions)?;
+            .topic_names
+            .iter()
+            .filter_map(|topic_name| {
+                let topic_id = self.cluster_metadata.get_topic_id(topic_name)?;
+                Some((topic_name.to_owned(), topic_id))
+            })
+            .collect();
         let mut responses = vec![];
 
         // TODO: Make these all calls run async
         // try this https://docs.rs/tokio/latest/tokio/task/join_set/struct.JoinSet.html#examples
-        for (broker_conn, topic_partitions) in brokers_and_their_topic_partitions.into_iter() {
-            let response = fetch(
+        for (broker_id, broker_conn, topic_partitions) in
+            brokers_and_their_topic_partitions.into_iter()
+        {
+            let session = self.fetch_sessions.entry(broker_id).or_default();
+            let response = fetch_with(
                 broker_conn,
                 self.fetch_params.correlation_id,
                 &self.fetch_params.client_id,
@@ -190,10 +449,23 @@ impl<'a, T: BrokerConnection + Clone + Debug + 'a> Consumer<T> {
                 self.fetch_params.max_bytes,
                 self.fetch_params.max_partition_bytes,
                 self.fetch_params.isolation_level,
+                &self.fetch_params.client_rack,
                 &topic_partitions,
                 &self.offsets,
+                &current_leader_epochs,
+                self.fetch_params.fetch_version,
+                &topic_ids,
+                Decompression {
+                    lazy: self.fetch_params.lazy_decompression,
+                    max_batch_bytes: self.fetch_params.max_decompressed_batch_bytes,
+                },
+                Some(session),
             )
-            .await?;
+            .await
+            .inspect_err(|_| {
+                // the broker might have moved on to the next epoch already
+                self.fetch_sessions.remove(&broker_id);
+            })?;
 
             responses.push(response);
         }
@@ -204,7 +476,110 @@ impl<'a, T: BrokerConnection + Clone + Debug + 'a> Consumer<T> {
     pub async fn next_batch(
         &mut self,
     ) -> Result<(impl Iterator<Item = ConsumeMessage>, PartitionOffsets)> {
-        let responses = self.consume().await?;
+        let (responses, bounds) = self.fetch_responses().await?;
+        let max_decompressed_bytes = self.fetch_params.max_decompressed_batch_bytes;
+        let messages = responses
+            .into_iter()
+            .flat_map(move |response| response_messages(response, max_decompressed_bytes))
+            .filter(move |message| within_bounds(&bounds, message));
+
+        Ok((messages, self.offsets.clone()))
+    }
+
+    /// Fetch once, keeping the records of each fetch response together.
+    ///
+    /// Responses without records in the bounds of the consumer are left out.
+    async fn next_fetch_batches(&mut self) -> Result<Vec<FetchBatch>> {
+        let (responses, bounds) = self.fetch_responses().await?;
+        let max_decompressed_bytes = self.fetch_params.max_decompressed_batch_bytes;
+        let batches = responses
+            .into_iter()
+            .map(|response| {
+                let high_watermarks = response_high_watermarks(&response);
+                let messages = response_messages(response, max_decompressed_bytes)
+                    .filter(|message| within_bounds(&bounds, message))
+                    .collect();
+                FetchBatch {
+                    messages,
+                    high_watermarks,
+                }
+            })
+            .filter(|batch| !batch.messages.is_empty())
+            .collect();
+
+        Ok(batches)
+    }
+
+    /// Fetch once and update the positions, returning the fetch responses
+    /// along with the offset bounds their records have to be in.
+    async fn fetch_responses(
+        &mut self,
+    ) -> Result<(
+        Vec<protocol::FetchResponse>,
+        HashMap<TopicPartition, (usize, usize)>,
+    )> {
+        if !self.positions_to_validate.is_empty() {
+            self.validate_positions().await?;
+        }
+        // batches can start before the fetch offset and run past the end offset
+        let bounds: HashMap<TopicPartition, (usize, usize)> = self
+            .end_offsets
+            .iter()
+            .map(|(topic_partition, end_offset)| {
+                let start_offset = self.offsets.get(topic_partition).copied().unwrap_or(0);
+                (
+                    topic_partition.clone(),
+                    (start_offset as usize, *end_offset as usize),
+                )
+            })
+            .collect();
+        let mut responses = self.consume().await?;
+        let mut not_leader_retries = 0;
+        loop {
+            let not_leader = partitions_with_error(&responses, KafkaCode::NotLeaderForPartition);
+            if not_leader.is_empty() {
+                break;
+            }
+            if not_leader_retries == self.fetch_params.max_not_leader_retries {
+                tracing::error!("Could not find the leader of {:?}", not_leader);
+                return Err(Error::KafkaError(KafkaCode::NotLeaderForPartition));
+            }
+            not_leader_retries += 1;
+            tracing::warn!(
+                "The leader of {:?} moved, refreshing metadata and fetching again",
+                not_leader
+            );
+            for topic_partition in not_leader.iter() {
+                self.preferred_read_replicas.remove(topic_partition);
+            }
+            self.cluster_metadata.refresh().await?;
+            // nothing was read yet, the other topic partitions are fetched
+            // again from the same offsets
+            responses = self.consume().await?;
+        }
+        self.health.record_fetch();
+        let out_of_range: Vec<(TopicPartition, i64)> = responses
+            .iter()
+            .flat_map(|response| response.topics.iter())
+            .flat_map(|topic| {
+                let topic_name = String::from_utf8_lossy(topic.name.as_bytes()).into_owned();
+                topic
+                    .partitions
+                    .iter()
+                    .filter(|partition| partition.error_code == KafkaCode::OffsetOutOfRange)
+                    .map(move |partition| {
+                        (
+                            (topic_name.clone(), partition.id),
+                            partition.log_start_offset,
+                        )
+                    })
+            })
+            .collect();
+        if !out_of_range.is_empty() {
+            // the other topic partitions are fetched again from where they were
+            self.reset_out_of_range(out_of_range).await?;
+        }
+        let mut unknown_topic_id = false;
         // for each group of broker reponses
         for response in responses.iter() {
             for topic in response.topics.iter() {
@@ -224,6 +599,46 @@ impl<'a, T: BrokerConnection + Clone + Debug + 'a> Consumer<T> {
                     .find(|my_topic| **my_topic == topic_name)
                     .unwrap();
                 for partition in topic.partitions.iter() {
+                    let topic_partition = (topic_name.to_owned(), partition.id);
+                    if partition.error_code == KafkaCode::None {
+                        let initial_high_watermark = *self
+                            .initial_high_watermarks
+                            .entry(topic_partition.clone())
+                            .or_insert(partition.high_water_mark);
+                        if self.stop_at_end {
+                            // records past it are not in this response, only later ones
+                            self.end_offsets
+                                .entry(topic_partition.clone())
+                                .or_insert(initial_high_watermark);
+                        }
+                        self.high_watermarks
+                            .insert(topic_partition.clone(), partition.high_water_mark);
+                    }
+                    if matches!(
+                        partition.error_code,
+                        KafkaCode::FencedLeaderEpoch | KafkaCode::UnknownLeaderEpoch
+                    ) && self.leader_epochs.contains_key(&topic_partition)
+                    {
+                        // the leader moved, the log might have been truncated since
+                        self.positions_to_validate.insert(topic_partition.clone());
+                    }
+                    if partition.error_code == KafkaCode::UnknownTopicId {
+                        // the topic was created again, look up its new id
+                        unknown_topic_id = true;
+                    }
+                    if partition.error_code != KafkaCode::None {
+                        // go back to the leader, the replica might be gone or lagging behind
+                        self.preferred_read_replicas.remove(&topic_partition);
+                    } else if partition.preferred_read_replica >= 0 {
+                        tracing::debug!(
+                            "Fetching {:?} from preferred read replica {}",
+                            topic_partition,
+                            partition.preferred_read_replica
+                        );
+                        self.preferred_read_replicas
+                            .insert(topic_partition, partition.preferred_read_replica);
+                    }
+
                     // TODO: handle kafka error code here
                     /*
                      * OFFSET_OUT_OF_RANGE (1)
@@ -238,50 +653,393 @@ impl<'a, T: BrokerConnection + Clone + Debug + 'a> Consumer<T> {
                             (topic_name.to_owned(), partition.id),
                             base_offset + (record_batch.record_count() as i64),
                         );
+                        if let Some(leader_epoch) = self
+                            .leader_epochs
+                            .get_mut(&(topic_name.to_owned(), partition.id))
+                        {
+                            *leader_epoch = record_batch.partition_leader_epoch;
+                        }
                     }
                 }
             }
         }
+        self.update_caught_up();
+        if unknown_topic_id {
+            tracing::warn!("Fetched a topic by an unknown id, refreshing metadata");
+            self.cluster_metadata.refresh().await?;
+        }
 
-        let iterators = responses.into_iter().flat_map(|response| {
-            response.topics.into_iter().flat_map(|topic| {
-                let topic_name = std::string::String::from_utf8(topic.name.to_vec()).unwrap();
-                topic.partitions.into_iter().flat_map(move |partition| {
-                    let topic_name = topic_name.clone();
+        Ok((responses, bounds))
+    }
 
-                    let partition_id = partition.id;
-                    partition.record_batch.into_iter().flat_map(move |batch| {
-                        let topic_name = topic_name.clone();
+    /// A handle to the health of the consumer, which stays up to date after
+    /// the consumer is turned into a stream.
+    pub fn health(&self) -> ConsumerHealth {
+        self.health.clone()
+    }
+
+    /// Replace the assigned topic partitions.
+    ///
+    /// Partitions dropped from the assignment are removed from the fetch
+    /// sessions with the brokers on the next fetch, so the brokers stop
+    /// tracking them.
+    pub async fn reassign(&mut self, assigned_topic_partitions: TopicPartitions) -> Result<()> {
+        let topics: Vec<String> = assigned_topic_partitions.keys().cloned().collect();
+        self.cluster_metadata.add_topics(&topics).await?;
 
-                        let base_timestamp = batch.base_timestamp;
-                        let base_offset = batch.base_offset;
-                        batch.records.into_iter().map(move |record| {
-                            let topic_name = topic_name.clone();
+        self.preferred_read_replicas
+            .retain(|(topic_name, partition_index), _| {
+                assigned_topic_partitions
+                    .get(topic_name)
+                    .is_some_and(|partitions| partitions.contains(partition_index))
+            });
+        self.assigned_topic_partitions = assigned_topic_partitions;
+        self.health
+            .set_assignment(self.assigned_topic_partitions.clone());
+        Ok(())
+    }
 
-                            let new_offset = (record.offset_delta / 2) + (base_offset as usize);
+    /// How many records the position of a topic partition is behind its
+    /// high watermark, as of the last fetch that returned the partition.
+    pub fn lag(&self, topic_partition: &TopicPartition) -> Option<i64> {
+        let high_watermark = self.high_watermarks.get(topic_partition)?;
+        // missing offsets are fetched from 0
+        let position = self.offsets.get(topic_partition).copied().unwrap_or(0);
+        Some((high_watermark - position).max(0))
+    }
 
-                            ConsumeMessage {
-                                key: record.key.clone(),
-                                value: record.value.clone(),
-                                offset: new_offset,
-                                timestamp: base_timestamp as usize + record.timestamp_delta,
-                                topic_name: topic_name.clone(),
-                                partition_index: partition_id,
+    /// The largest lag across the assigned topic partitions, like the
+    /// records-lag-max metric of the Java client.
+    ///
+    /// Updated on each fetch, `None` until a fetch returned one of the
+    /// assigned topic partitions.
+    pub fn max_lag(&self) -> Option<i64> {
+        self.assigned_topic_partitions
+            .iter()
+            .flat_map(|(topic_name, partitions)| {
+                partitions.iter().filter_map(|partition_index| {
+                    self.lag(&(topic_name.to_owned(), *partition_index))
+                })
+            })
+            .max()
+    }
+
+    /// Whether every assigned topic partition has been read up to the high
+    /// watermark it had when the consumer started.
+    pub fn is_caught_up(&self) -> bool {
+        *self.caught_up.borrow()
+    }
+
+    /// Resolve once every assigned topic partition has been read up to the
+    /// high watermark it had when the consumer started.
+    ///
+    /// This is meant for bootstrapping state from a topic before tailing it.
+    /// The future does not borrow the consumer, so it can be taken before the
+    /// consumer is turned into a stream. The high watermark of a topic
+    /// partition is taken from the first fetch that returns it. If the
+    /// consumer is dropped before catching up, the future never resolves.
+    pub fn caught_up(&self) -> impl Future<Output = ()> + Send + 'static {
+        let mut receiver = self.caught_up.subscribe();
+        async move {
+            if receiver.wait_for(|caught_up| *caught_up).await.is_err() {
+                std::future::pending::<()>().await;
+            }
+        }
+    }
+
+    fn update_caught_up(&self) {
+        if self.is_caught_up() {
+            return;
+        }
+        let caught_up = self
+            .assigned_topic_partitions
+            .iter()
+            .all(|(topic_name, partitions)| {
+                partitions.iter().all(|partition_index| {
+                    let topic_partition = (topic_name.to_owned(), *partition_index);
+                    let Some(high_watermark) = self.initial_high_watermarks.get(&topic_partition)
+                    else {
+                        return false;
+                    };
+                    // missing offsets are fetched from 0
+                    self.offsets.get(&topic_partition).copied().unwrap_or(0) >= *high_watermark
+                })
+            });
+        if caught_up {
+            tracing::debug!("Caught up with the initial high watermarks");
+            self.caught_up.send_replace(true);
+        }
+    }
+
+    /// Whether every assigned topic partition has been read up to its end
+    /// offset. Always false for a consumer without end offsets.
+    pub fn is_finished(&self) -> bool {
+        !self.end_offsets.is_empty() && self.unfinished_topic_partitions().is_empty()
+    }
+
+    /// The assigned topic partitions that have not reached their end offset.
+    fn unfinished_topic_partitions(&self) -> TopicPartitions {
+        if self.end_offsets.is_empty() {
+            return self.assigned_topic_partitions.clone();
+        }
+        self.assigned_topic_partitions
+            .iter()
+            .map(|(topic_name, partitions)| {
+                let partitions: Vec<i32> = partitions
+                    .iter()
+                    .filter(|partition_index| {
+                        let topic_partition = (topic_name.to_owned(), **partition_index);
+                        match self.end_offsets.get(&topic_partition) {
+                            // missing offsets are fetched from 0
+                            Some(end_offset) => {
+                                self.offsets.get(&topic_partition).copied().unwrap_or(0)
+                                    < *end_offset
                             }
-                        })
+                            None => true,
+                        }
                     })
-                })
+                    .copied()
+                    .collect();
+                (topic_name.to_owned(), partitions)
             })
-        });
+            .filter(|(_, partitions)| !partitions.is_empty())
+            .collect()
+    }
+
+    /// Move the positions a leader found out of range of its log, according
+    /// to the [`OffsetReset`] policy.
+    ///
+    /// A position below the start of the log means records were deleted, by
+    /// retention or on request, before they were read. That is reported as
+    /// [`Error::DataLoss`] once the positions were moved, so the next fetch
+    /// carries on from there.
+    async fn reset_out_of_range(&mut self, out_of_range: Vec<(TopicPartition, i64)>) -> Result<()> {
+        let mut unknown = TopicPartitions::new();
+        for ((topic_name, partition_index), log_start_offset) in out_of_range.iter() {
+            if *log_start_offset < 0 {
+                unknown
+                    .entry(topic_name.to_owned())
+                    .or_default()
+                    .push(*partition_index);
+            }
+        }
+        // older brokers leave the log start offset out of error responses
+        let listed = if unknown.is_empty() {
+            PartitionOffsets::new()
+        } else {
+            offsets_for_timestamp(
+                &self.cluster_metadata,
+                &self.fetch_params,
+                &unknown,
+                EARLIEST_TIMESTAMP,
+            )
+            .await?
+        };
+
+        let mut data_loss = None;
+        let mut log_start_offsets = PartitionOffsets::new();
+        for (topic_partition, log_start_offset) in out_of_range {
+            let log_start_offset = listed
+                .get(&topic_partition)
+                .copied()
+                .unwrap_or(log_start_offset);
+            let position = self.offsets.get(&topic_partition).copied().unwrap_or(0);
+            if position < log_start_offset {
+                tracing::warn!(
+                    "Records {}..{} of {:?} were deleted before they were read",
+                    position,
+                    log_start_offset,
+                    topic_partition
+                );
+                data_loss.get_or_insert(Error::DataLoss(
+                    topic_partition.0.clone(),
+                    topic_partition.1,
+                    position,
+                    log_start_offset,
+                ));
+            }
+            log_start_offsets.insert(topic_partition, log_start_offset);
+        }
+
+        let tps: Vec<TopicPartition> = log_start_offsets.keys().cloned().collect();
+        match self.fetch_params.offset_reset {
+            OffsetReset::Earliest => {
+                tracing::debug!("Resetting {:?} to the start of the log", tps);
+                for topic_partition in tps.iter() {
+                    self.leader_epochs.remove(topic_partition);
+                    self.positions_to_validate.remove(topic_partition);
+                }
+                self.offsets.extend(log_start_offsets);
+            }
+            OffsetReset::Latest => {
+                tracing::debug!("Resetting {:?} to the end of the log", tps);
+                self.seek_to_timestamp(&tps, LATEST_TIMESTAMP).await?;
+            }
+            OffsetReset::None => {
+                return Err(data_loss.unwrap_or(Error::KafkaError(KafkaCode::OffsetOutOfRange)));
+            }
+        }
 
-        Ok((iterators, self.offsets.clone()))
+        match data_loss {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    /// Seek topic partitions to a given timestamp.
+    ///
+    /// Given a timestamp in milliseconds, move the offsets for each of the
+    /// topic partitions to the first record at or after that time. This is
+    /// how to replay from a point in time, e.g. an hour ago.
+    ///
+    /// Offsets for topic partitions not listed are left as they are.
+    pub async fn seek_to_timestamp(
+        &mut self,
+        tps: &[TopicPartition],
+        timestamp_ms: i64,
+    ) -> Result<()> {
+        tracing::debug!("Seeking {:?} to timestamp {}", tps, timestamp_ms);
+        let mut topic_partitions = TopicPartitions::new();
+        for (topic_name, partition_index) in tps {
+            topic_partitions
+                .entry(topic_name.to_owned())
+                .or_default()
+                .push(*partition_index);
+        }
+
+        let offsets = offsets_for_timestamp(
+            &self.cluster_metadata,
+            &self.fetch_params,
+            &topic_partitions,
+            timestamp_ms,
+        )
+        .await?;
+        for topic_partition in offsets.keys() {
+            // the epoch of the old position says nothing about the new one
+            self.leader_epochs.remove(topic_partition);
+            self.positions_to_validate.remove(topic_partition);
+        }
+        self.offsets.extend(offsets);
+        tracing::trace!("Offsets set to {:?}", self.offsets);
+
+        Ok(())
+    }
+
+    /// Seek a topic partition to an offset, along with the leader epoch of
+    /// the record before it.
+    ///
+    /// The epoch is how a position taken from one leader is checked against
+    /// the log of the next: before fetching the partition again its leader is
+    /// asked where that epoch ends with OffsetForLeaderEpoch. If the log was
+    /// truncated below the offset, e.g. after an unclean leader election, the
+    /// position is moved back to the end of the epoch instead of skipping the
+    /// records written in its place. Later fetches carry the current leader
+    /// epoch so a leader change is noticed and the position checked again.
+    pub fn seek_with_epoch(&mut self, tp: TopicPartition, offset: i64, leader_epoch: i32) {
+        tracing::debug!(
+            "Seeking {:?} to offset {} in leader epoch {}",
+            tp,
+            offset,
+            leader_epoch
+        );
+        self.offsets.insert(tp.clone(), offset);
+        self.leader_epochs.insert(tp.clone(), leader_epoch);
+        self.positions_to_validate.insert(tp);
     }
 
-    fn stream(
+    /// Check the positions waiting for validation against the logs of their
+    /// leaders, moving truncated ones back to the end of their epoch.
+    ///
+    /// When a leader does not agree with our view of its epoch the metadata
+    /// is refreshed and the error returned, so the next batch tries again.
+    async fn validate_positions(&mut self) -> Result<()> {
+        let mut topic_partitions = TopicPartitions::new();
+        for (topic_name, partition_index) in self.positions_to_validate.iter() {
+            topic_partitions
+                .entry(topic_name.to_owned())
+                .or_default()
+                .push(*partition_index);
+        }
+
+        let connections = self
+            .cluster_metadata
+            .get_connections_for_topic_partitions(&topic_partitions)?;
+        let mut stale_leader_epoch = None;
+        for (mut broker_conn, topic_partitions) in connections.into_iter() {
+            let mut request = protocol::OffsetForLeaderEpochRequest::new(
+                self.fetch_params.correlation_id,
+                &self.fetch_params.client_id,
+            );
+            for (topic_name, partitions) in topic_partitions.iter() {
+                for partition_index in partitions.iter() {
+                    let topic_partition = (topic_name.to_owned(), *partition_index);
+                    let current_leader_epoch = self
+                        .cluster_metadata
+                        .get_leader_epoch_for_topic_partition(topic_name, *partition_index)
+                        .unwrap_or(-1);
+                    let leader_epoch = self
+                        .leader_epochs
+                        .get(&topic_partition)
+                        .copied()
+                        .unwrap_or(-1);
+                    request.add(
+                        topic_name,
+                        *partition_index,
+                        current_leader_epoch,
+                        leader_epoch,
+                    );
+                }
+            }
+            broker_conn.send_request(&request).await?;
+            let response = broker_conn.receive_response().await?;
+            let response = protocol::OffsetForLeaderEpochResponse::try_from(response.freeze())?;
+
+            for (topic_name, partition) in response.into_box_iter() {
+                let topic_name = std::str::from_utf8(topic_name.as_bytes()).map_err(|err| {
+                    tracing::error!("Error converting from UTF8 {:?}", err);
+                    Error::DecodingUtf8Error
+                })?;
+                let topic_partition = (topic_name.to_owned(), partition.partition);
+                match partition.error_code {
+                    KafkaCode::None => {}
+                    KafkaCode::FencedLeaderEpoch | KafkaCode::UnknownLeaderEpoch => {
+                        stale_leader_epoch = Some(partition.error_code);
+                        continue;
+                    }
+                    error_code => return Err(Error::KafkaError(error_code)),
+                }
+
+                let position = self.offsets.get(&topic_partition).copied().unwrap_or(0);
+                if partition.end_offset >= 0 && partition.end_offset < position {
+                    tracing::warn!(
+                        "Log of {:?} truncated to {}, moving the position back from {}",
+                        topic_partition,
+                        partition.end_offset,
+                        position
+                    );
+                    self.offsets
+                        .insert(topic_partition.clone(), partition.end_offset);
+                    self.leader_epochs
+                        .insert(topic_partition.clone(), partition.leader_epoch);
+                }
+                self.positions_to_validate.remove(&topic_partition);
+            }
+        }
+
+        if let Some(error_code) = stale_leader_epoch {
+            tracing::debug!("Validating positions failed with {:?}", error_code);
+            self.cluster_metadata.refresh().await?;
+            return Err(Error::KafkaError(error_code));
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn stream(
         mut self,
     ) -> impl Stream<Item = Result<(impl Iterator<Item = ConsumeMessage>, PartitionOffsets)>> {
         async_stream::stream! {
-            loop {
+            while !self.is_finished() {
                 yield self.next_batch().await;
             }
         }
@@ -296,6 +1054,134 @@ impl<'a, T: BrokerConnection + Clone + Debug + 'a> Consumer<T> {
         self.stream().map(|messages| messages.map(|m| m.0))
     }
 
+    /// Convert consumer into an asynchronous iterator of fetched batches.
+    ///
+    /// Each item holds the records of a single fetch response, which is
+    /// useful to commit once per fetch. A fetch from several brokers yields
+    /// a batch for each of them.
+    #[must_use = "stream does nothingby itself"]
+    pub fn into_batch_stream(mut self) -> impl Stream<Item = Result<FetchBatch>> {
+        async_stream::stream! {
+            while !self.is_finished() {
+                match self.next_fetch_batches().await {
+                    Ok(batches) => {
+                        for batch in batches {
+                            yield Ok(batch);
+                        }
+                    }
+                    Err(err) => yield Err(err),
+                }
+            }
+        }
+    }
+
+    /// Convert consumer into an asynchronous iterator of deserialized values.
+    ///
+    /// Each record value is handed to the `deserializer`, null values as an
+    /// empty slice. A value that cannot be deserialized yields an
+    /// [`Error::DeserializationError`] and the stream carries on with the
+    /// next record.
+    #[must_use = "stream does nothingby itself"]
+    pub fn into_typed_stream<V, E: std::fmt::Display>(
+        self,
+        deserializer: impl Fn(&[u8]) -> std::result::Result<V, E>,
+    ) -> impl Stream<Item = Result<V>> {
+        async_stream::stream! {
+            for await batch in self.stream() {
+                match batch {
+                    Ok((messages, _)) => {
+                        for message in messages {
+                            yield deserializer(message.value.as_deref().unwrap_or_default()).map_err(|err| {
+                                tracing::warn!(
+                                    "Could not deserialize record at offset {} of {} partition {}: {}",
+                                    message.offset,
+                                    message.topic_name,
+                                    message.partition_index,
+                                    err
+                                );
+                                Error::DeserializationError(err.to_string())
+                            });
+                        }
+                    }
+                    Err(err) => yield Err(err),
+                }
+            }
+        }
+    }
+
+    /// Convert consumer into an asynchronous iterator that fetches ahead.
+    ///
+    /// Unlike [`into_stream`](Self::into_stream), which only fetches when it is
+    /// polled, this keeps fetching in the background while the records that
+    /// were handed out are being worked on. Fetching pauses once
+    /// [`max_buffered_records`](crate::prelude::ConsumerBuilder::max_buffered_records)
+    /// records are waiting to be read, and resumes as they are drained.
+    ///
+    /// Dropping the stream stops the background fetches, including one that
+    /// is waiting on a broker.
+    #[must_use = "stream does nothingby itself"]
+    pub fn into_buffered_stream(
+        self,
+    ) -> impl Stream<Item = Result<impl Iterator<Item = ConsumeMessage>>>
+    where
+        T: Send + Sync + 'static,
+    {
+        let (sender, mut receiver) = channel(self.fetch_params.max_buffered_records.max(1));
+        let fetcher = tokio::spawn(async move {
+            let stream = self.stream();
+            tokio::pin!(stream);
+            while let Some(batch) = stream.next().await {
+                match batch {
+                    Ok((messages, _)) => {
+                        for message in messages {
+                            // waits while the buffer is full
+                            if sender.send(Ok(message)).await.is_err() {
+                                return;
+                            }
+                        }
+                    }
+                    Err(err) => {
+                        let _ = sender.send(Err(err)).await;
+                        return;
+                    }
+                }
+                if sender.is_closed() {
+                    return;
+                }
+            }
+        });
+        let fetcher = AbortOnDrop(fetcher.abort_handle());
+
+        async_stream::stream! {
+            // aborts the fetches once the stream is dropped
+            let _fetcher = fetcher;
+            while let Some(message) = receiver.recv().await {
+                let mut messages = vec![];
+                let mut error = None;
+                match message {
+                    Ok(message) => messages.push(message),
+                    Err(err) => error = Some(err),
+                }
+                // hand out everything that is buffered at once
+                while error.is_none() {
+                    match receiver.try_recv() {
+                        Ok(Ok(message)) => messages.push(message),
+                        Ok(Err(err)) => error = Some(err),
+                        Err(_) => break,
+                    }
+                }
+
+                if !messages.is_empty() {
+                    yield Ok(messages.into_iter());
+                }
+                if let Some(err) = error {
+                    yield Err(err);
+                    break;
+                }
+            }
+        }
+    }
+
     /// Apply auto-commit to the consumer.
     ///
     /// Each time a message is pulled from this stream, the highest offsets
@@ -341,11 +1227,49 @@ pub async fn commit_offset(
     correlation_id: i32,
     client_id: &str,
     group_id: &str,
-    mut coordinator_conn: impl BrokerConnection + Debug,
+    coordinator_conn: impl BrokerConnection + Debug,
     generation_id: i32,
     member_id: Bytes,
     offsets: PartitionOffsets,
     retention_time_ms: i64,
+) -> Result<protocol::OffsetCommitResponse> {
+    let offsets = offsets
+        .into_iter()
+        // TODO: find out why using None or Some("") causes an error in broker
+        .map(|(topic_partition, offset)| (topic_partition, offset, Some("metadata".to_owned())))
+        .collect();
+
+    commit_offsets(
+        correlation_id,
+        client_id,
+        group_id,
+        coordinator_conn,
+        generation_id,
+        member_id,
+        offsets,
+        retention_time_ms,
+    )
+    .await
+}
+
+/// Commit a set of offsets for a consumer group, each with its own metadata
+/// string (e.g. a checkpoint token), which is handed back by
+/// [`fetch_committed_offsets`](crate::prelude::fetch_committed_offsets).
+///
+/// See this [protocol spec] for more information.
+///
+/// [protocol spec]: protocol::commit_offset
+#[instrument(level = "debug")]
+#[allow(clippy::too_many_arguments)]
+pub async fn commit_offsets(
+    correlation_id: i32,
+    client_id: &str,
+    group_id: &str,
+    mut coordinator_conn: impl BrokerConnection + Debug,
+    generation_id: i32,
+    member_id: Bytes,
+    offsets: Vec<(TopicPartition, i64, Option<String>)>,
+    retention_time_ms: i64,
 ) -> Result<protocol::OffsetCommitResponse> {
     let mut offset_request = protocol::OffsetCommitRequest::new(
         correlation_id,
@@ -358,13 +1282,12 @@ pub async fn commit_offset(
 
     tracing::info!("Member {:?} - Committing offsets {:?}", member_id, offsets);
 
-    for ((topic_name, partition_index), committed_offset) in offsets.iter() {
+    for ((topic_name, partition_index), committed_offset, metadata) in offsets.iter() {
         offset_request.add(
             topic_name,
             *partition_index,
             *committed_offset,
-            // TODO: find out why using None or Some("") causes an error in broker
-            Some("metadata"),
+            metadata.as_deref(),
         );
     }
 
@@ -424,6 +1347,200 @@ async fn commit_offset_wrapper(
 #[instrument(level = "debug")]
 #[allow(clippy::too_many_arguments)]
 pub async fn fetch(
+    broker_conn: impl BrokerConnection + Debug,
+    correlation_id: i32,
+    client_id: &str,
+    max_wait_ms: i32,
+    min_bytes: i32,
+    max_bytes: i32,
+    max_partition_bytes: i32,
+    isolation_level: i8,
+    client_rack: &str,
+    topic_partitions: &TopicPartitions,
+    offsets: &PartitionOffsets,
+) -> Result<protocol::FetchResponse> {
+    fetch_with(
+        broker_conn,
+        correlation_id,
+        client_id,
+        max_wait_ms,
+        min_bytes,
+        max_bytes,
+        max_partition_bytes,
+        isolation_level,
+        client_rack,
+        topic_partitions,
+        offsets,
+        &HashMap::new(),
+        protocol::fetch::request::API_VERSION,
+        &HashMap::new(),
+        Decompression::new(false),
+        None,
+    )
+    .await
+}
+
+/// Encode the Fetch request for the given topic partitions, as it is
+/// written to the socket, size included.
+///
+/// This is what [`fetch`] sends, without needing a broker to send it to,
+/// with up to `max_bytes` from each partition. Missing offsets are fetched
+/// from 0, and topics and partitions are sorted so the bytes are the same
+/// on every call.
+#[allow(clippy::too_many_arguments)]
+pub fn build_fetch_request(
+    assignments: &TopicPartitions,
+    offsets: &PartitionOffsets,
+    max_wait_ms: i32,
+    min_bytes: i32,
+    max_bytes: i32,
+    isolation_level: i8,
+    client_id: &str,
+    correlation_id: i32,
+) -> Result<Bytes> {
+    let mut request = protocol::FetchRequest::new(
+        correlation_id,
+        client_id,
+        max_wait_ms,
+        min_bytes,
+        max_bytes,
+        isolation_level,
+    );
+    let mut topic_names: Vec<&String> = assignments.keys().collect();
+    topic_names.sort();
+    for topic_name in topic_names {
+        let mut partitions = assignments[topic_name].clone();
+        partitions.sort();
+        for partition_index in partitions {
+            let offset = offsets
+                .get(&(topic_name.to_owned(), partition_index))
+                .unwrap_or(&0);
+            request.add(topic_name, partition_index, *offset, max_bytes);
+        }
+    }
+
+    let mut buffer = vec![0, 0, 0, 0];
+    request.encode(&mut buffer)?;
+    let size = try_usize_to_int!(buffer.len() - 4, i32);
+    size.encode(&mut &mut buffer[..4])?;
+
+    Ok(Bytes::from(buffer))
+}
+
+/// Fetch the last `n` records of a topic partition, oldest first.
+///
+/// The high watermark is looked up with ListOffsets and reading starts `n`
+/// records before it, or at the earliest offset when the partition holds
+/// fewer records. The connection must be to the leader of the partition.
+pub async fn tail(
+    broker_conn: impl BrokerConnection + Clone + Debug,
+    correlation_id: i32,
+    client_id: &str,
+    topic_partition: &TopicPartition,
+    n: usize,
+) -> Result<Vec<ConsumeMessage>> {
+    let (topic_name, partition_index) = topic_partition;
+    let topic_partitions = TopicPartitionsBuilder::new()
+        .assign(topic_name.to_owned(), vec![*partition_index])
+        .build();
+
+    let mut bounds = vec![];
+    for timestamp in [LATEST_TIMESTAMP, EARLIEST_TIMESTAMP] {
+        let response = list_offsets(
+            broker_conn.clone(),
+            correlation_id,
+            client_id,
+            &topic_partitions,
+            timestamp,
+        )
+        .await?;
+        let (_, partition) = response
+            .into_box_iter()
+            .next()
+            .ok_or_else(|| Error::MissingData("No offset for the partition".to_owned()))?;
+        if partition.error_code != KafkaCode::None {
+            return Err(Error::KafkaError(partition.error_code));
+        }
+        bounds.push(partition.offset);
+    }
+    let (high_watermark, earliest) = (bounds[0], bounds[1]);
+    let start = (high_watermark - n as i64).max(earliest);
+    tracing::debug!(
+        "Reading the last {} records of {:?} from offset {} to {}",
+        n,
+        topic_partition,
+        start,
+        high_watermark
+    );
+
+    let mut messages = vec![];
+    let mut offset = start;
+    while offset < high_watermark {
+        let offsets = HashMap::from([(topic_partition.clone(), offset)]);
+        let response = fetch(
+            broker_conn.clone(),
+            correlation_id,
+            client_id,
+            DEFAULT_MAX_WAIT_MS,
+            DEFAULT_MIN_BYTES,
+            DEFAULT_MAX_BYTES,
+            DEFAULT_MAX_PARTITION_BYTES,
+            DEFAULT_ISOLATION_LEVEL,
+            "",
+            &topic_partitions,
+            &offsets,
+        )
+        .await?;
+
+        let fetched = messages.len();
+        for topic in response.topics {
+            for partition in topic.partitions {
+                if partition.error_code != KafkaCode::None {
+                    return Err(Error::KafkaError(partition.error_code));
+                }
+                for batch in partition.record_batch {
+                    let base_timestamp = batch.base_timestamp;
+                    let base_offset = batch.base_offset;
+                    offset = offset.max(base_offset + batch.record_count() as i64);
+                    for record in batch.into_records() {
+                        let record_offset = (record.offset_delta / 2) as i64 + base_offset;
+                        // a batch can start before the first offset asked for
+                        if record_offset < start || record_offset >= high_watermark {
+                            continue;
+                        }
+                        messages.push(ConsumeMessage {
+                            key: record.key(),
+                            value: record.value(),
+                            headers: record.headers,
+                            offset: record_offset as usize,
+                            timestamp: base_timestamp as usize + record.timestamp_delta,
+                            topic_name: topic_name.to_owned(),
+                            partition_index: partition.id,
+                        });
+                    }
+                }
+            }
+        }
+        if messages.len() == fetched {
+            break;
+        }
+    }
+
+    Ok(messages)
+}
+
+/// Same as [fetch], but compressed batches are decompressed as configured,
+/// only as their records are iterated when `lazy` is set.
+///
+/// With a fetch `session`, partitions whose position did not change since
+/// the last fetch are left out, and partitions no longer fetched from the
+/// broker are sent as forgotten topics.
+///
+/// From [`FIRST_TOPIC_ID_VERSION`](protocol::fetch::request::FIRST_TOPIC_ID_VERSION)
+/// the topics are sent by the ids in `topic_ids`, and the names of the topics
+/// in the response are filled in from them.
+#[allow(clippy::too_many_arguments)]
+async fn fetch_with(
     mut broker_conn: impl BrokerConnection + Debug,
     correlation_id: i32,
     client_id: &str,
@@ -432,8 +1549,14 @@ pub async fn fetch(
     max_bytes: i32,
     max_partition_bytes: i32,
     isolation_level: i8,
+    client_rack: &str,
     topic_partitions: &TopicPartitions,
     offsets: &PartitionOffsets,
+    current_leader_epochs: &HashMap<TopicPartition, i32>,
+    fetch_version: i16,
+    topic_ids: &HashMap<String, [u8; 16]>,
+    decompression: Decompression,
+    session: Option<&mut FetchSession>,
 ) -> Result<protocol::FetchResponse> {
     tracing::debug!(
         "Consuming {:?} with offsets {:?}",
@@ -448,26 +1571,1243 @@ pub async fn fetch(
         max_bytes,
         isolation_level,
     );
+    request.header.api_version = fetch_version;
+    request.rack_id = client_rack;
+    let by_topic_id = fetch_version >= protocol::fetch::request::FIRST_TOPIC_ID_VERSION;
+    let topic_id_for = |topic_name: &str| -> Result<[u8; 16]> {
+        if !by_topic_id {
+            return Ok([0; 16]);
+        }
+        topic_ids
+            .get(topic_name)
+            .copied()
+            .ok_or(Error::KafkaError(KafkaCode::UnknownTopicId))
+    };
+    if let Some(session) = &session {
+        request.session_id = session.id;
+        request.session_epoch = session.epoch;
+    }
+    let incremental = session
+        .as_ref()
+        .is_some_and(|session| session.is_incremental());
 
     // tracing::info!("Reading with offset {:?}", offsets);
 
+    let mut fetched = HashMap::new();
     for (topic_name, partitions) in topic_partitions.iter() {
         for partition_index in partitions.iter() {
+            let topic_partition = (topic_name.to_owned(), *partition_index);
             // Default missing offsets to 0
-            let offset = offsets
-                .get(&(topic_name.to_owned(), *partition_index))
-                .unwrap_or(&0);
-            request.add(topic_name, *partition_index, *offset, max_partition_bytes);
+            let offset = offsets.get(&topic_partition).unwrap_or(&0);
+            let current_leader_epoch = current_leader_epochs.get(&topic_partition).unwrap_or(&-1);
+            let position = (*offset, *current_leader_epoch);
+            let unchanged = session
+                .as_ref()
+                .is_some_and(|session| session.partitions.get(&topic_partition) == Some(&position));
+            fetched.insert(topic_partition, position);
+            if incremental && unchanged {
+                continue;
+            }
+            let topic_id = topic_id_for(topic_name)?;
+            request.add_with_topic_id(
+                topic_name,
+                topic_id,
+                *partition_index,
+                *offset,
+                *current_leader_epoch,
+                max_partition_bytes,
+            );
         }
     }
 
+    let forgotten: Vec<TopicPartition> = match &session {
+        Some(session) if incremental => session
+            .partitions
+            .keys()
+            .filter(|topic_partition| !fetched.contains_key(*topic_partition))
+            .cloned()
+            .collect(),
+        _ => vec![],
+    };
+    for (topic_name, partition_index) in forgotten.iter() {
+        tracing::debug!(
+            "Removing topic {} partition {} from fetch session {}",
+            topic_name,
+            partition_index,
+            request.session_id
+        );
+        request.forget(topic_name, topic_id_for(topic_name)?, *partition_index);
+    }
+
     broker_conn.send_request(&request).await?;
-    let response =
-        protocol::FetchResponse::try_from(broker_conn.receive_response().await?.freeze())?;
+    let bytes = broker_conn.receive_response().await?.freeze();
+    let mut response = protocol::FetchResponse::decompressing_from_version(
+        bytes,
+        request.header.api_version,
+        decompression,
+    )?;
+    if let Some(session) = session {
+        session.update(response.error_code, response.session_id, fetched);
+    }
+
+    if by_topic_id {
+        for topic in response.topics.iter_mut() {
+            let topic_name = request
+                .topics
+                .iter()
+                .find(|requested| requested.topic_id == topic.topic_id)
+                .ok_or_else(|| {
+                    tracing::error!("Fetched an unknown topic id {:?}", topic.topic_id);
+                    Error::KafkaError(KafkaCode::UnknownTopicId)
+                })?
+                .topic_name;
+            topic.name = Bytes::copy_from_slice(topic_name.as_bytes());
+        }
+    }
 
     Ok(response)
 }
 
+/// Aborts a background task when dropped, along with the stream owning it.
+struct AbortOnDrop(tokio::task::AbortHandle);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// The records of a fetch response, in the order the broker returned them.
+fn response_messages(
+    response: protocol::FetchResponse,
+    max_decompressed_bytes: usize,
+) -> impl Iterator<Item = ConsumeMessage> {
+    response.topics.into_iter().flat_map(move |topic| {
+        let topic_name = std::string::String::from_utf8(topic.name.to_vec()).unwrap();
+        topic.partitions.into_iter().flat_map(move |partition| {
+            let topic_name = topic_name.clone();
+
+            let partition_id = partition.id;
+            partition.record_batch.into_iter().flat_map(move |batch| {
+                let topic_name = topic_name.clone();
+
+                let base_timestamp = batch.base_timestamp;
+                let base_offset = batch.base_offset;
+                batch
+                    .into_records_with_limit(max_decompressed_bytes)
+                    .map(move |record| {
+                        let topic_name = topic_name.clone();
+
+                        let new_offset = (record.offset_delta / 2) + (base_offset as usize);
+
+                        ConsumeMessage {
+                            key: record.key(),
+                            value: record.value(),
+                            headers: record.headers,
+                            offset: new_offset,
+                            timestamp: base_timestamp as usize + record.timestamp_delta,
+                            topic_name: topic_name.clone(),
+                            partition_index: partition_id,
+                        }
+                    })
+            })
+        })
+    })
+}
+
+/// Whether a record is within the offset bounds of its topic partition,
+/// batches can start before the fetch offset and run past the end offset.
+fn within_bounds(
+    bounds: &HashMap<TopicPartition, (usize, usize)>,
+    message: &ConsumeMessage,
+) -> bool {
+    if bounds.is_empty() {
+        return true;
+    }
+    let topic_partition = (message.topic_name.clone(), message.partition_index);
+    match bounds.get(&topic_partition) {
+        Some((start_offset, end_offset)) => {
+            *start_offset <= message.offset && message.offset < *end_offset
+        }
+        None => true,
+    }
+}
+
+/// The high watermarks of the topic partitions a fetch response returned
+/// without an error.
+fn response_high_watermarks(response: &protocol::FetchResponse) -> PartitionOffsets {
+    response
+        .topics
+        .iter()
+        .flat_map(|topic| {
+            let topic_name = String::from_utf8_lossy(topic.name.as_bytes()).into_owned();
+            topic
+                .partitions
+                .iter()
+                .filter(|partition| partition.error_code == KafkaCode::None)
+                .map(move |partition| {
+                    (
+                        (topic_name.clone(), partition.id),
+                        partition.high_water_mark,
+                    )
+                })
+        })
+        .collect()
+}
+
+/// The topic partitions a broker answered with the given error code.
+fn partitions_with_error(
+    responses: &[protocol::FetchResponse],
+    error_code: KafkaCode,
+) -> Vec<TopicPartition> {
+    responses
+        .iter()
+        .flat_map(|response| response.topics.iter())
+        .flat_map(|topic| {
+            let topic_name = String::from_utf8_lossy(topic.name.as_bytes()).into_owned();
+            topic
+                .partitions
+                .iter()
+                .filter(move |partition| partition.error_code == error_code)
+                .map(move |partition| (topic_name.clone(), partition.id))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::VecDeque;
+    use std::sync::atomic::{AtomicBool, AtomicI32, AtomicI64, Ordering};
+    use std::sync::Mutex;
+
+    use bytes::BufMut;
+    use futures::FutureExt;
+    use tokio::net::{TcpListener, TcpStream};
+
+    use crate::{
+        consumer_builder::ConsumerBuilder,
+        encode::{ToByte, UnsignedVarint},
+        network::tcp::TcpConnection,
+        protocol::produce::request::{Message, RecordBatch, RecordBatchAttributes},
+        test_support::{
+            api_key, api_version, body_offset, broker_address, listen, put_compact_string,
+            put_string, read_i32, read_i64, read_request, write_response,
+        },
+    };
+
+    use super::*;
+
+    const TOPIC: &str = "purchases";
+    const LEADER_ID: i32 = 1;
+    const FOLLOWER_ID: i32 = 2;
+    /// Where the leaders say epoch 0 of the partitions ends.
+    const EPOCH_0_END_OFFSET: i64 = 5;
+    const TOPIC_ID: [u8; 16] = [9; 16];
+
+    struct MockBroker {
+        node_id: i32,
+        /// Ports of the leader and the follower.
+        ports: [u16; 2],
+        fetch_requests: AtomicI32,
+        last_fetch_request: Mutex<Vec<u8>>,
+        offset_for_leader_epoch_requests: AtomicI32,
+        metadata_requests: AtomicI32,
+        list_offsets_requests: AtomicI32,
+        /// How many fetches by topic id to answer with UNKNOWN_TOPIC_ID.
+        unknown_topic_id_fetches: AtomicI32,
+        /// The fetch session id handed out, 0 to not create sessions.
+        fetch_session_id: AtomicI32,
+        /// Error codes to answer the next fetches of partition 0 with, one per fetch.
+        fetch_errors: Mutex<VecDeque<KafkaCode>>,
+        /// Log start offset of every partition.
+        log_start_offset: AtomicI64,
+        /// High watermark of each partition of the topic.
+        high_watermarks: Vec<i64>,
+        /// Encoded record batches to return, one per fetch, shared by the cluster.
+        record_batches: Arc<Mutex<VecDeque<Vec<u8>>>>,
+        /// Leave fetches unanswered, as if waiting for records to arrive.
+        stall_fetches: AtomicBool,
+        /// Connections that the client has not closed yet.
+        open_connections: AtomicI32,
+    }
+
+    impl MockBroker {
+        /// Start a leader and a follower for a single partition, where the
+        /// leader always points fetches at the follower.
+        async fn start_cluster() -> (Arc<Self>, Arc<Self>) {
+            Self::start_cluster_with_records(0, vec![]).await
+        }
+
+        /// Start a cluster that returns the given record batches, one per fetch.
+        async fn start_cluster_with_records(
+            high_watermark: i64,
+            record_batches: Vec<Vec<u8>>,
+        ) -> (Arc<Self>, Arc<Self>) {
+            Self::start_cluster_with_partitions(vec![high_watermark], record_batches).await
+        }
+
+        /// Start a cluster for a topic with a partition for each high
+        /// watermark, where the record batches are returned for partition 0.
+        async fn start_cluster_with_partitions(
+            high_watermarks: Vec<i64>,
+            record_batches: Vec<Vec<u8>>,
+        ) -> (Arc<Self>, Arc<Self>) {
+            let record_batches = Arc::new(Mutex::new(VecDeque::from(record_batches)));
+            let (leader_listener, leader_port) = listen().await;
+            let (follower_listener, follower_port) = listen().await;
+            let ports = [leader_port, follower_port];
+
+            let leader = Self::serve_on(
+                leader_listener,
+                LEADER_ID,
+                ports,
+                high_watermarks.clone(),
+                record_batches.clone(),
+            );
+            let follower = Self::serve_on(
+                follower_listener,
+                FOLLOWER_ID,
+                ports,
+                high_watermarks,
+                record_batches,
+            );
+            (leader, follower)
+        }
+
+        fn serve_on(
+            listener: TcpListener,
+            node_id: i32,
+            ports: [u16; 2],
+            high_watermarks: Vec<i64>,
+            record_batches: Arc<Mutex<VecDeque<Vec<u8>>>>,
+        ) -> Arc<Self> {
+            let broker = Arc::new(MockBroker {
+                node_id,
+                ports,
+                fetch_requests: AtomicI32::new(0),
+                last_fetch_request: Mutex::new(vec![]),
+                offset_for_leader_epoch_requests: AtomicI32::new(0),
+                metadata_requests: AtomicI32::new(0),
+                list_offsets_requests: AtomicI32::new(0),
+                unknown_topic_id_fetches: AtomicI32::new(0),
+                fetch_session_id: AtomicI32::new(0),
+                fetch_errors: Mutex::new(VecDeque::new()),
+                log_start_offset: AtomicI64::new(0),
+                high_watermarks,
+                record_batches,
+                stall_fetches: AtomicBool::new(false),
+                open_connections: AtomicI32::new(0),
+            });
+            let accepting = broker.clone();
+            tokio::spawn(async move {
+                while let Ok((socket, _)) = listener.accept().await {
+                    tokio::spawn(accepting.clone().serve(socket));
+                }
+            });
+
+            broker
+        }
+
+        fn api_versions_response(&self) -> Vec<u8> {
+            let mut buf = vec![];
+            buf.put_i16(0); // error_code
+            buf.put_u8(3);
+            for (api_key, max_version) in [(1, 13), (3, 12)] {
+                buf.put_i16(api_key);
+                buf.put_i16(0); // min_version
+                buf.put_i16(max_version);
+                buf.put_u8(0);
+            }
+            buf.put_i32(0); // throttle_time_ms
+            buf.put_u8(0);
+            buf
+        }
+
+        fn metadata_response(&self, request: Vec<u8>) -> Vec<u8> {
+            self.metadata_requests.fetch_add(1, Ordering::SeqCst);
+            if api_version(&request) >= 10 {
+                return self.flexible_metadata_response();
+            }
+            let mut buf = vec![];
+            buf.put_i32(0); // throttle_time_ms
+            buf.put_i32(2);
+            for (node_id, port) in [LEADER_ID, FOLLOWER_ID].into_iter().zip(self.ports) {
+                buf.put_i32(node_id);
+                put_string(&mut buf, "127.0.0.1");
+                buf.put_i32(port as i32);
+                buf.put_i16(-1); // rack
+            }
+            buf.put_i16(-1); // cluster_id
+            buf.put_i32(LEADER_ID); // controller_id
+            buf.put_i32(1);
+            buf.put_i16(0);
+            put_string(&mut buf, TOPIC);
+            buf.put_i8(0); // is_internal
+            buf.put_i32(self.high_watermarks.len() as i32);
+            for partition_index in 0..self.high_watermarks.len() {
+                buf.put_i16(0);
+                buf.put_i32(partition_index as i32);
+                buf.put_i32(LEADER_ID);
+                buf.put_i32(1); // leader_epoch
+                buf.put_slice(&[0, 0, 0, 2, 0, 0, 0, 1, 0, 0, 0, 2]); // replica_nodes
+                buf.put_slice(&[0, 0, 0, 2, 0, 0, 0, 1, 0, 0, 0, 2]); // isr_nodes
+                buf.put_i32(0); // offline_replicas
+            }
+            buf
+        }
+
+        /// Metadata v10, with the topic id.
+        fn flexible_metadata_response(&self) -> Vec<u8> {
+            let mut buf = vec![0];
+            buf.put_i32(0); // throttle_time_ms
+            buf.put_u8(3);
+            for (node_id, port) in [LEADER_ID, FOLLOWER_ID].into_iter().zip(self.ports) {
+                buf.put_i32(node_id);
+                put_compact_string(&mut buf, "127.0.0.1");
+                buf.put_i32(port as i32);
+                buf.put_slice(&[0, 0]); // rack
+            }
+            buf.put_u8(0); // cluster_id
+            buf.put_i32(LEADER_ID); // controller_id
+            buf.put_u8(2);
+            buf.put_i16(0);
+            put_compact_string(&mut buf, TOPIC);
+            buf.put_slice(&TOPIC_ID);
+            buf.put_i8(0); // is_internal
+            buf.put_u8(self.high_watermarks.len() as u8 + 1);
+            for partition_index in 0..self.high_watermarks.len() {
+                buf.put_i16(0);
+                buf.put_i32(partition_index as i32);
+                buf.put_i32(LEADER_ID);
+                buf.put_i32(1); // leader_epoch
+                buf.put_slice(&[3, 0, 0, 0, 1, 0, 0, 0, 2]); // replica_nodes
+                buf.put_slice(&[3, 0, 0, 0, 1, 0, 0, 0, 2]); // isr_nodes
+                buf.put_slice(&[1, 0]); // offline_replicas
+            }
+            buf.put_i32(0); // topic_authorized_operations
+            buf.put_u8(0);
+            buf.put_i32(0); // cluster_authorized_operations
+            buf.put_u8(0);
+            buf
+        }
+
+        fn fetch_response(&self, request: Vec<u8>) -> Vec<u8> {
+            self.fetch_requests.fetch_add(1, Ordering::SeqCst);
+            let by_topic_id = api_version(&request) >= 13;
+            *self.last_fetch_request.lock().unwrap() = request;
+            if by_topic_id {
+                return self.fetch_by_topic_id_response();
+            }
+            let preferred_read_replica = if self.node_id == LEADER_ID {
+                FOLLOWER_ID
+            } else {
+                -1
+            };
+            let fetch_error = self
+                .fetch_errors
+                .lock()
+                .unwrap()
+                .pop_front()
+                .unwrap_or(KafkaCode::None);
+            let mut buf = vec![];
+            buf.put_i32(0); // throttle_time_ms
+            buf.put_i16(0); // error_code
+            buf.put_i32(self.fetch_session_id.load(Ordering::SeqCst));
+            buf.put_i32(1);
+            put_string(&mut buf, TOPIC);
+            buf.put_i32(self.high_watermarks.len() as i32);
+            for (partition_index, high_watermark) in self.high_watermarks.iter().enumerate() {
+                let error_code = if partition_index == 0 {
+                    fetch_error
+                } else {
+                    KafkaCode::None
+                };
+                buf.put_i32(partition_index as i32);
+                buf.put_i16(error_code as i16);
+                buf.put_i64(*high_watermark);
+                buf.put_i64(*high_watermark); // last_stable_offset
+                buf.put_i64(self.log_start_offset.load(Ordering::SeqCst));
+                buf.put_i32(-1); // aborted_transactions
+                buf.put_i32(preferred_read_replica);
+                let records = if partition_index == 0 && error_code == KafkaCode::None {
+                    self.record_batches
+                        .lock()
+                        .unwrap()
+                        .pop_front()
+                        .unwrap_or_default()
+                } else {
+                    vec![]
+                };
+                buf.put_i32(records.len() as i32);
+                buf.put_slice(&records);
+            }
+            buf
+        }
+
+        /// Fetch v13, keyed by the topic id.
+        fn fetch_by_topic_id_response(&self) -> Vec<u8> {
+            let unknown_topic_id = self
+                .unknown_topic_id_fetches
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+                    (n > 0).then(|| n - 1)
+                })
+                .is_ok();
+            let mut buf = vec![0];
+            buf.put_i32(0); // throttle_time_ms
+            buf.put_i16(0); // error_code
+            buf.put_i32(0); // session_id
+            buf.put_u8(2);
+            buf.put_slice(&TOPIC_ID);
+            buf.put_u8(self.high_watermarks.len() as u8 + 1);
+            for (partition_index, high_watermark) in self.high_watermarks.iter().enumerate() {
+                buf.put_i32(partition_index as i32);
+                buf.put_i16(if unknown_topic_id { 100 } else { 0 }); // error_code
+                buf.put_i64(*high_watermark);
+                buf.put_i64(*high_watermark); // last_stable_offset
+                buf.put_i64(0); // log_start_offset
+                buf.put_u8(0); // aborted_transactions
+                buf.put_i32(-1); // preferred_read_replica
+                let records = if partition_index == 0 && !unknown_topic_id {
+                    self.record_batches
+                        .lock()
+                        .unwrap()
+                        .pop_front()
+                        .unwrap_or_default()
+                } else {
+                    vec![]
+                };
+                UnsignedVarint(records.len() + 1).encode(&mut buf).unwrap();
+                buf.put_slice(&records);
+                buf.put_u8(0);
+            }
+            buf.put_u8(0);
+            buf.put_u8(0);
+            buf
+        }
+
+        fn offset_for_leader_epoch_response(&self) -> Vec<u8> {
+            self.offset_for_leader_epoch_requests
+                .fetch_add(1, Ordering::SeqCst);
+            let mut buf = vec![];
+            buf.put_i32(0); // throttle_time_ms
+            buf.put_i32(1);
+            put_string(&mut buf, TOPIC);
+            buf.put_i32(1);
+            buf.put_i16(0); // error_code
+            buf.put_i32(0); // partition
+            buf.put_i32(0); // leader_epoch
+            buf.put_i64(EPOCH_0_END_OFFSET);
+            buf
+        }
+
+        /// ListOffsets v1, with the high watermark of every partition.
+        fn list_offsets_response(&self) -> Vec<u8> {
+            self.list_offsets_requests.fetch_add(1, Ordering::SeqCst);
+            let mut buf = vec![];
+            buf.put_i32(1);
+            put_string(&mut buf, TOPIC);
+            buf.put_i32(self.high_watermarks.len() as i32);
+            for (partition_index, high_watermark) in self.high_watermarks.iter().enumerate() {
+                buf.put_i32(partition_index as i32);
+                buf.put_i16(0); // error_code
+                buf.put_i64(-1); // timestamp
+                buf.put_i64(*high_watermark);
+            }
+            buf
+        }
+
+        async fn serve(self: Arc<Self>, mut socket: TcpStream) {
+            self.open_connections.fetch_add(1, Ordering::SeqCst);
+            while let Some(request) = read_request(&mut socket).await {
+                let api_key = api_key(&request);
+                if api_key == 1 && self.stall_fetches.load(Ordering::SeqCst) {
+                    self.fetch_requests.fetch_add(1, Ordering::SeqCst);
+                    continue;
+                }
+                let body = match api_key {
+                    1 => self.fetch_response(request.clone()),
+                    2 => self.list_offsets_response(),
+                    3 => self.metadata_response(request.clone()),
+                    18 => self.api_versions_response(),
+                    23 => self.offset_for_leader_epoch_response(),
+                    api_key => panic!("Unexpected api key {}", api_key),
+                };
+                write_response(&mut socket, &request, &body).await;
+            }
+            self.open_connections.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    #[tokio::test]
+    async fn it_lists_the_offsets_of_every_partition_in_one_request() {
+        let (leader, _follower) =
+            MockBroker::start_cluster_with_partitions(vec![5, 7, 9], vec![]).await;
+        let mut cluster_metadata = ClusterMetadata::<TcpConnection>::new(
+            vec![broker_address(leader.ports[0])],
+            1,
+            "test".to_owned(),
+            vec![],
+        )
+        .await
+        .unwrap();
+
+        let offsets = crate::consumer_builder::list_topic_offsets(&mut cluster_metadata, TOPIC, -1)
+            .await
+            .unwrap();
+
+        assert_eq!(offsets, HashMap::from([(0, 5), (1, 7), (2, 9)]));
+        assert_eq!(leader.list_offsets_requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn it_builds_the_bytes_of_a_fetch_request() {
+        // size, header, replica -1, max wait 2000, min bytes 100, max bytes
+        // 30000, read uncommitted, no session, then partition 1 of
+        // "purchases" from offset 30000 and no rack
+        let encoded_buf = [
+            0, 0, 0, 92, 0, 1, 0, 11, 0, 0, 0, 1, 0, 4, 114, 117, 115, 116, 255, 255, 255, 255, 0,
+            0, 7, 208, 0, 0, 0, 100, 0, 0, 117, 48, 0, 0, 0, 0, 0, 255, 255, 255, 255, 0, 0, 0, 1,
+            0, 9, 112, 117, 114, 99, 104, 97, 115, 101, 115, 0, 0, 0, 1, 0, 0, 0, 1, 255, 255, 255,
+            255, 0, 0, 0, 0, 0, 0, 117, 48, 255, 255, 255, 255, 255, 255, 255, 255, 0, 0, 117, 48,
+            0, 0, 0, 0, 0, 0,
+        ];
+        let assignment = TopicPartitionsBuilder::new()
+            .assign(TOPIC.to_owned(), vec![1])
+            .build();
+        let offsets = HashMap::from([((TOPIC.to_owned(), 1), 30000)]);
+
+        let buffer =
+            build_fetch_request(&assignment, &offsets, 2000, 100, 30000, 0, "rust", 1).unwrap();
+
+        assert_eq!(buffer, encoded_buf[..]);
+    }
+
+    #[tokio::test]
+    async fn it_fetches_from_the_preferred_read_replica() {
+        let (leader, follower) = MockBroker::start_cluster().await;
+        let assignment = TopicPartitionsBuilder::new()
+            .assign(TOPIC.to_owned(), vec![0])
+            .build();
+        let mut consumer = ConsumerBuilder::<TcpConnection>::new(
+            vec![broker_address(leader.ports[0])],
+            assignment,
+        )
+        .await
+        .unwrap()
+        .client_rack("az1".to_owned())
+        .build();
+
+        let _ = consumer.next_batch().await.unwrap();
+        assert_eq!(leader.fetch_requests.load(Ordering::SeqCst), 1);
+        assert_eq!(follower.fetch_requests.load(Ordering::SeqCst), 0);
+        // rack_id is the last field of the request
+        assert!(leader
+            .last_fetch_request
+            .lock()
+            .unwrap()
+            .ends_with(&[0, 3, b'a', b'z', b'1']));
+
+        let _ = consumer.next_batch().await.unwrap();
+        assert_eq!(leader.fetch_requests.load(Ordering::SeqCst), 1);
+        assert_eq!(follower.fetch_requests.load(Ordering::SeqCst), 1);
+    }
+
+    /// Encode a record batch with the given number of records at `base_offset`.
+    fn record_batch(base_offset: i64, record_count: usize) -> Vec<u8> {
+        let mut batch = RecordBatch::new(RecordBatchAttributes::new(None));
+        for _ in 0..record_count {
+            batch.add(Message::new(None, Some(Bytes::from("value")), vec![]));
+        }
+        let mut buf = vec![];
+        batch.encode(&mut buf).unwrap();
+        // the base offset is set by the broker and not covered by the crc
+        buf[..8].copy_from_slice(&base_offset.to_be_bytes());
+        buf
+    }
+
+    #[tokio::test]
+    async fn it_signals_when_caught_up_with_the_initial_high_watermark() {
+        let (leader, _follower) = MockBroker::start_cluster_with_records(
+            100,
+            vec![
+                record_batch(0, 40),
+                record_batch(40, 40),
+                record_batch(80, 20),
+            ],
+        )
+        .await;
+        let assignment = TopicPartitionsBuilder::new()
+            .assign(TOPIC.to_owned(), vec![0])
+            .build();
+        let mut consumer = ConsumerBuilder::<TcpConnection>::new(
+            vec![broker_address(leader.ports[0])],
+            assignment,
+        )
+        .await
+        .unwrap()
+        .build();
+        let mut caught_up = Box::pin(consumer.caught_up());
+
+        let mut consumed = 0;
+        for _ in 0..3 {
+            assert!(!consumer.is_caught_up());
+            assert!((&mut caught_up).now_or_never().is_none());
+            consumed += consumer.next_batch().await.unwrap().0.count();
+        }
+
+        assert_eq!(consumed, 100);
+        assert!(consumer.is_caught_up());
+        assert!(caught_up.now_or_never().is_some());
+    }
+
+    #[tokio::test]
+    async fn it_reports_the_largest_lag_of_the_assigned_partitions() {
+        let (leader, _follower) =
+            MockBroker::start_cluster_with_partitions(vec![10, 25], vec![record_batch(0, 4)]).await;
+        let assignment = TopicPartitionsBuilder::new()
+            .assign(TOPIC.to_owned(), vec![0, 1])
+            .build();
+        let mut consumer = ConsumerBuilder::<TcpConnection>::new(
+            vec![broker_address(leader.ports[0])],
+            assignment,
+        )
+        .await
+        .unwrap()
+        .seek(&HashMap::from([((TOPIC.to_owned(), 1), 20)]))
+        .build();
+        assert_eq!(consumer.max_lag(), None);
+
+        let _ = consumer.next_batch().await.unwrap();
+        assert_eq!(consumer.lag(&(TOPIC.to_owned(), 0)), Some(6));
+        assert_eq!(consumer.lag(&(TOPIC.to_owned(), 1)), Some(5));
+        assert_eq!(consumer.max_lag(), Some(6));
+    }
+
+    #[tokio::test]
+    async fn it_consumes_a_bounded_offset_range() {
+        let (leader, follower) = MockBroker::start_cluster_with_records(
+            32,
+            vec![record_batch(8, 8), record_batch(16, 8), record_batch(24, 8)],
+        )
+        .await;
+        let assignment = TopicPartitionsBuilder::new()
+            .assign(TOPIC.to_owned(), vec![0])
+            .build();
+        let consumer = ConsumerBuilder::<TcpConnection>::new(
+            vec![broker_address(leader.ports[0])],
+            assignment,
+        )
+        .await
+        .unwrap()
+        .seek(&HashMap::from([((TOPIC.to_owned(), 0), 10)]))
+        .end_offsets(&HashMap::from([((TOPIC.to_owned(), 0), 20)]))
+        .build();
+
+        let stream = consumer.into_stream();
+        tokio::pin!(stream);
+        let mut offsets = vec![];
+        while let Some(batch) = stream.next().await {
+            offsets.extend(batch.unwrap().map(|message| message.offset));
+        }
+
+        assert_eq!(offsets, (10..20).collect::<Vec<usize>>());
+        let fetch_requests = leader.fetch_requests.load(Ordering::SeqCst)
+            + follower.fetch_requests.load(Ordering::SeqCst);
+        assert_eq!(fetch_requests, 2);
+    }
+
+    #[tokio::test]
+    async fn it_completes_the_stream_at_the_end_of_the_log() {
+        let record_batches = (0..10).map(|i| record_batch(i * 10, 10)).collect();
+        let (leader, _follower) = MockBroker::start_cluster_with_records(100, record_batches).await;
+        let assignment = TopicPartitionsBuilder::new()
+            .assign(TOPIC.to_owned(), vec![0])
+            .build();
+        let consumer = ConsumerBuilder::<TcpConnection>::new(
+            vec![broker_address(leader.ports[0])],
+            assignment,
+        )
+        .await
+        .unwrap()
+        .stop_at_end()
+        .build();
+
+        let stream = consumer.into_stream();
+        tokio::pin!(stream);
+        let mut offsets = vec![];
+        while let Some(batch) = stream.next().await {
+            offsets.extend(batch.unwrap().map(|message| message.offset));
+        }
+
+        assert_eq!(offsets, (0..100).collect::<Vec<usize>>());
+    }
+
+    #[tokio::test]
+    async fn it_yields_the_records_of_each_fetch_response_together() {
+        let (leader, follower) = MockBroker::start_cluster_with_records(
+            100,
+            vec![
+                record_batch(0, 40),
+                record_batch(40, 40),
+                record_batch(80, 20),
+            ],
+        )
+        .await;
+        let assignment = TopicPartitionsBuilder::new()
+            .assign(TOPIC.to_owned(), vec![0])
+            .build();
+        let consumer = ConsumerBuilder::<TcpConnection>::new(
+            vec![broker_address(leader.ports[0])],
+            assignment,
+        )
+        .await
+        .unwrap()
+        .stop_at_end()
+        .build();
+
+        let stream = consumer.into_batch_stream();
+        tokio::pin!(stream);
+        let mut batches = vec![];
+        while let Some(batch) = stream.next().await {
+            batches.push(batch.unwrap());
+        }
+
+        let fetch_requests = leader.fetch_requests.load(Ordering::SeqCst)
+            + follower.fetch_requests.load(Ordering::SeqCst);
+        assert_eq!(batches.len(), fetch_requests as usize);
+        let offsets: Vec<Vec<usize>> = batches
+            .iter()
+            .map(|batch| {
+                batch
+                    .messages
+                    .iter()
+                    .map(|message| message.offset)
+                    .collect()
+            })
+            .collect();
+        assert_eq!(
+            offsets,
+            vec![
+                (0..40).collect::<Vec<usize>>(),
+                (40..80).collect(),
+                (80..100).collect()
+            ]
+        );
+        for batch in batches {
+            assert_eq!(
+                batch.high_watermarks,
+                HashMap::from([((TOPIC.to_owned(), 0), 100)])
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn it_signals_data_loss_when_resuming_before_the_log_start() {
+        let (leader, follower) =
+            MockBroker::start_cluster_with_records(60, vec![record_batch(50, 10)]).await;
+        for broker in [&leader, &follower] {
+            broker.log_start_offset.store(50, Ordering::SeqCst);
+        }
+        leader
+            .fetch_errors
+            .lock()
+            .unwrap()
+            .push_back(KafkaCode::OffsetOutOfRange);
+        let assignment = TopicPartitionsBuilder::new()
+            .assign(TOPIC.to_owned(), vec![0])
+            .build();
+        let consumer = ConsumerBuilder::<TcpConnection>::new(
+            vec![broker_address(leader.ports[0])],
+            assignment,
+        )
+        .await
+        .unwrap()
+        .seek(&HashMap::from([((TOPIC.to_owned(), 0), 10)]))
+        .end_offsets(&HashMap::from([((TOPIC.to_owned(), 0), 60)]))
+        .build();
+
+        let stream = consumer.into_stream();
+        tokio::pin!(stream);
+        let mut errors = vec![];
+        let mut offsets = vec![];
+        while let Some(batch) = stream.next().await {
+            match batch {
+                Ok(batch) => offsets.extend(batch.map(|message| message.offset)),
+                Err(err) => errors.push(err),
+            }
+        }
+
+        assert_eq!(errors, vec![Error::DataLoss(TOPIC.to_owned(), 0, 10, 50)]);
+        assert_eq!(offsets, (50..60).collect::<Vec<usize>>());
+    }
+
+    #[tokio::test]
+    async fn it_refreshes_metadata_and_fetches_again_from_the_new_leader() {
+        let (leader, follower) =
+            MockBroker::start_cluster_with_records(10, vec![record_batch(0, 10)]).await;
+        leader
+            .fetch_errors
+            .lock()
+            .unwrap()
+            .push_back(KafkaCode::NotLeaderForPartition);
+        let assignment = TopicPartitionsBuilder::new()
+            .assign(TOPIC.to_owned(), vec![0])
+            .build();
+        let mut consumer = ConsumerBuilder::<TcpConnection>::new(
+            vec![broker_address(leader.ports[0])],
+            assignment,
+        )
+        .await
+        .unwrap()
+        .build();
+        let metadata_requests = || {
+            leader.metadata_requests.load(Ordering::SeqCst)
+                + follower.metadata_requests.load(Ordering::SeqCst)
+        };
+        let initial_metadata_requests = metadata_requests();
+
+        let (messages, _) = consumer.next_batch().await.unwrap();
+
+        assert_eq!(messages.count(), 10);
+        assert_eq!(metadata_requests(), initial_metadata_requests + 1);
+        assert_eq!(leader.fetch_requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn it_deserializes_values_and_carries_on_after_a_malformed_one() {
+        let mut batch = RecordBatch::new(RecordBatchAttributes::new(None));
+        for value in ["1", "2", "x", "4"] {
+            batch.add(Message::new(None, Some(Bytes::from(value)), vec![]));
+        }
+        let mut buf = vec![];
+        batch.encode(&mut buf).unwrap();
+        let (leader, _follower) = MockBroker::start_cluster_with_records(4, vec![buf]).await;
+        let assignment = TopicPartitionsBuilder::new()
+            .assign(TOPIC.to_owned(), vec![0])
+            .build();
+        let consumer = ConsumerBuilder::<TcpConnection>::new(
+            vec![broker_address(leader.ports[0])],
+            assignment,
+        )
+        .await
+        .unwrap()
+        .end_offsets(&HashMap::from([((TOPIC.to_owned(), 0), 4)]))
+        .build();
+
+        let stream = consumer.into_typed_stream(|value| {
+            std::str::from_utf8(value)
+                .map_err(|err| err.to_string())?
+                .parse::<i32>()
+                .map_err(|err| err.to_string())
+        });
+        let values: Vec<Result<i32>> = stream.collect().await;
+
+        assert_eq!(values.len(), 4);
+        assert_eq!(values[0], Ok(1));
+        assert_eq!(values[1], Ok(2));
+        assert!(matches!(values[2], Err(Error::DeserializationError(_))));
+        assert_eq!(values[3], Ok(4));
+    }
+
+    #[tokio::test]
+    async fn it_stops_fetching_while_the_buffer_is_full() {
+        let record_batches = (0..100).map(|i| record_batch(i * 5, 5)).collect();
+        let (leader, follower) = MockBroker::start_cluster_with_records(500, record_batches).await;
+        let assignment = TopicPartitionsBuilder::new()
+            .assign(TOPIC.to_owned(), vec![0])
+            .build();
+        let consumer = ConsumerBuilder::<TcpConnection>::new(
+            vec![broker_address(leader.ports[0])],
+            assignment,
+        )
+        .await
+        .unwrap()
+        .max_buffered_records(10)
+        .build();
+        let fetched_records = || {
+            5 * (leader.fetch_requests.load(Ordering::SeqCst)
+                + follower.fetch_requests.load(Ordering::SeqCst)) as usize
+        };
+
+        let stream = consumer.into_buffered_stream();
+        tokio::pin!(stream);
+        let mut consumed = 0;
+        for _ in 0..3 {
+            consumed += stream.next().await.unwrap().unwrap().count();
+            // a slow consumer, the background fetches fill up the buffer
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+            // the buffer, plus the batch waiting to get into it
+            assert!(fetched_records() - consumed <= 10 + 5);
+        }
+
+        while consumed < 500 {
+            consumed += stream.next().await.unwrap().unwrap().count();
+        }
+        assert_eq!(consumed, 500);
+    }
+
+    #[tokio::test]
+    async fn it_stops_fetching_in_the_background_once_the_stream_is_dropped() {
+        let (leader, follower) = MockBroker::start_cluster().await;
+        leader.stall_fetches.store(true, Ordering::SeqCst);
+        let assignment = TopicPartitionsBuilder::new()
+            .assign(TOPIC.to_owned(), vec![0])
+            .build();
+        let consumer = ConsumerBuilder::<TcpConnection>::new(
+            vec![broker_address(leader.ports[0])],
+            assignment,
+        )
+        .await
+        .unwrap()
+        .build();
+
+        let mut stream = Box::pin(consumer.into_buffered_stream());
+        let next = tokio::time::timeout(std::time::Duration::from_millis(100), stream.next()).await;
+        assert!(next.is_err());
+        assert_eq!(leader.fetch_requests.load(Ordering::SeqCst), 1);
+        drop(stream);
+
+        // the consumer, and the connections it owns, went away with the task
+        let open_connections = || {
+            leader.open_connections.load(Ordering::SeqCst)
+                + follower.open_connections.load(Ordering::SeqCst)
+        };
+        tokio::time::timeout(std::time::Duration::from_secs(1), async {
+            while open_connections() > 0 {
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn it_moves_back_a_position_past_the_end_of_its_leader_epoch() {
+        let (leader, _follower) =
+            MockBroker::start_cluster_with_records(15, vec![record_batch(5, 10)]).await;
+        let assignment = TopicPartitionsBuilder::new()
+            .assign(TOPIC.to_owned(), vec![0])
+            .build();
+        let mut consumer = ConsumerBuilder::<TcpConnection>::new(
+            vec![broker_address(leader.ports[0])],
+            assignment,
+        )
+        .await
+        .unwrap()
+        .build();
+
+        // offset 8 was read in epoch 0, which the new leader ends at offset 5
+        consumer.seek_with_epoch((TOPIC.to_owned(), 0), 8, 0);
+        let (messages, offsets) = consumer.next_batch().await.unwrap();
+
+        assert_eq!(
+            leader
+                .offset_for_leader_epoch_requests
+                .load(Ordering::SeqCst),
+            1
+        );
+        let messages: Vec<usize> = messages.map(|message| message.offset).collect();
+        assert_eq!(messages, (5..15).collect::<Vec<usize>>());
+        assert_eq!(offsets.get(&(TOPIC.to_owned(), 0)), Some(&15));
+        // current_leader_epoch and fetch_offset follow the header, the
+        // request fields, the topic name and the partition index
+        let request = leader.last_fetch_request.lock().unwrap().clone();
+        let offset = body_offset(&request) + 25 + 4 + 2 + TOPIC.len() + 4 + 4;
+        assert_eq!(read_i32(&request, offset), 1);
+        assert_eq!(read_i64(&request, offset + 4), EPOCH_0_END_OFFSET);
+
+        // the position is only validated once
+        let _ = consumer.next_batch().await.unwrap();
+        assert_eq!(
+            leader
+                .offset_for_leader_epoch_requests
+                .load(Ordering::SeqCst),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn it_reports_a_recent_fetch_in_its_health() {
+        let (leader, _follower) =
+            MockBroker::start_cluster_with_records(1, vec![record_batch(0, 1)]).await;
+        let assignment = TopicPartitionsBuilder::new()
+            .assign(TOPIC.to_owned(), vec![0])
+            .build();
+        let mut consumer = ConsumerBuilder::<TcpConnection>::new(
+            vec![broker_address(leader.ports[0])],
+            assignment.clone(),
+        )
+        .await
+        .unwrap()
+        .build();
+        let health = consumer.health();
+        assert_eq!(health.last_fetch(), None);
+        assert_eq!(health.assignment(), assignment);
+
+        let before = std::time::Instant::now();
+        let (messages, _) = consumer.next_batch().await.unwrap();
+        assert_eq!(messages.count(), 1);
+
+        let last_fetch = health.last_fetch().unwrap();
+        assert!(last_fetch >= before);
+        assert!(last_fetch.elapsed() < std::time::Duration::from_secs(1));
+        // only group members heartbeat
+        assert_eq!(health.last_heartbeat(), None);
+    }
+
+    #[tokio::test]
+    async fn it_fetches_by_topic_id_when_the_broker_supports_it() {
+        let (leader, _follower) =
+            MockBroker::start_cluster_with_records(2, vec![record_batch(0, 2)]).await;
+        leader.unknown_topic_id_fetches.store(1, Ordering::SeqCst);
+        let assignment = TopicPartitionsBuilder::new()
+            .assign(TOPIC.to_owned(), vec![0])
+            .build();
+        let mut consumer = ConsumerBuilder::<TcpConnection>::new(
+            vec![broker_address(leader.ports[0])],
+            assignment,
+        )
+        .await
+        .unwrap()
+        .negotiate_fetch_version()
+        .await
+        .unwrap()
+        .build();
+        assert_eq!(
+            consumer.cluster_metadata.get_topic_id(TOPIC),
+            Some(TOPIC_ID)
+        );
+
+        // the broker does not know the topic id, so the metadata is refreshed
+        let metadata_requests = leader.metadata_requests.load(Ordering::SeqCst);
+        let (messages, _) = consumer.next_batch().await.unwrap();
+        assert_eq!(messages.count(), 0);
+        assert_eq!(
+            leader.metadata_requests.load(Ordering::SeqCst),
+            metadata_requests + 1
+        );
+
+        let (messages, offsets) = consumer.next_batch().await.unwrap();
+        let messages: Vec<ConsumeMessage> = messages.collect();
+        assert_eq!(messages.len(), 2);
+        assert!(messages.iter().all(|message| message.topic_name == TOPIC));
+        assert_eq!(offsets.get(&(TOPIC.to_owned(), 0)), Some(&2));
+
+        let request = leader.last_fetch_request.lock().unwrap().clone();
+        assert_eq!(api_version(&request), 13);
+        assert!(request.windows(16).any(|window| window == TOPIC_ID));
+        assert!(!request
+            .windows(TOPIC.len())
+            .any(|window| window == TOPIC.as_bytes()));
+    }
+
+    #[tokio::test]
+    async fn it_fails_a_batch_that_decompresses_past_the_limit() {
+        // a megabyte of zeros compresses to about a kilobyte
+        let mut batch = RecordBatch::new(RecordBatchAttributes::new(Some(
+            crate::prelude::Compression::Gzip,
+        )));
+        for _ in 0..100 {
+            batch.add(Message::new(
+                None,
+                Some(Bytes::from(vec![0; 10_000])),
+                vec![],
+            ));
+        }
+        let mut records = vec![];
+        batch.encode(&mut records).unwrap();
+        assert!(records.len() < 64 * 1024);
+
+        let (leader, _follower) = MockBroker::start_cluster_with_records(100, vec![records]).await;
+        let assignment = TopicPartitionsBuilder::new()
+            .assign(TOPIC.to_owned(), vec![0])
+            .build();
+        let mut consumer = ConsumerBuilder::<TcpConnection>::new(
+            vec![broker_address(leader.ports[0])],
+            assignment,
+        )
+        .await
+        .unwrap()
+        .max_decompressed_batch_bytes(64 * 1024)
+        .build();
+
+        assert_eq!(
+            consumer.next_batch().await.err(),
+            Some(Error::DecompressedSizeExceeded)
+        );
+    }
+
+    #[tokio::test]
+    async fn it_forgets_partitions_dropped_from_the_assignment() {
+        let (leader, follower) =
+            MockBroker::start_cluster_with_partitions(vec![0, 0], vec![]).await;
+        leader.fetch_session_id.store(7, Ordering::SeqCst);
+        follower.fetch_session_id.store(7, Ordering::SeqCst);
+        let assignment = TopicPartitionsBuilder::new()
+            .assign(TOPIC.to_owned(), vec![0, 1])
+            .build();
+        let mut consumer = ConsumerBuilder::<TcpConnection>::new(
+            vec![broker_address(leader.ports[0])],
+            assignment,
+        )
+        .await
+        .unwrap()
+        .build();
+
+        // the leader points the consumer at the follower, which starts a
+        // session with a full fetch of both partitions
+        let _ = consumer.next_batch().await.unwrap();
+        let _ = consumer.next_batch().await.unwrap();
+        assert_eq!(follower.fetch_requests.load(Ordering::SeqCst), 1);
+
+        consumer
+            .reassign(
+                TopicPartitionsBuilder::new()
+                    .assign(TOPIC.to_owned(), vec![0])
+                    .build(),
+            )
+            .await
+            .unwrap();
+        let _ = consumer.next_batch().await.unwrap();
+        assert_eq!(follower.fetch_requests.load(Ordering::SeqCst), 2);
+
+        let request = follower.last_fetch_request.lock().unwrap().clone();
+        // session_id and session_epoch follow the header, replica_id,
+        // max_wait_ms, min_bytes, max_bytes and isolation_level
+        let offset = body_offset(&request) + 17;
+        assert_eq!(read_i32(&request, offset), 7);
+        assert_eq!(read_i32(&request, offset + 4), 1);
+        // partition 0 did not move, so no topics are fetched, and partition 1
+        // is forgotten, before the empty rack_id
+        let mut forgotten = vec![0, 0, 0, 0, 0, 0, 0, 1, 0, TOPIC.len() as u8];
+        forgotten.extend_from_slice(TOPIC.as_bytes());
+        forgotten.extend_from_slice(&[0, 0, 0, 1, 0, 0, 0, 1, 0, 0]);
+        assert_eq!(&request[offset + 8..], forgotten.as_slice());
+    }
+
+    #[tokio::test]
+    async fn it_sends_the_fetch_max_bytes() {
+        let (leader, _follower) = MockBroker::start_cluster().await;
+        let assignment = TopicPartitionsBuilder::new()
+            .assign(TOPIC.to_owned(), vec![0])
+            .build();
+        let mut consumer = ConsumerBuilder::<TcpConnection>::new(
+            vec![broker_address(leader.ports[0])],
+            assignment,
+        )
+        .await
+        .unwrap()
+        .fetch_max_bytes(1234)
+        .build();
+
+        let _ = consumer.next_batch().await.unwrap();
+        // max_bytes follows the header, replica_id, max_wait_ms and min_bytes
+        let request = leader.last_fetch_request.lock().unwrap().clone();
+        let offset = body_offset(&request) + 12;
+        assert_eq!(read_i32(&request, offset), 1234);
+    }
+}
+
 // #[cfg(test)]
 // mod test {
 // use crate::network::{tcp::TcpConnection, BrokerConnection};
@@ -1,14 +1,25 @@
-use crate::consumer::{Consumer, FetchParams, PartitionOffsets, TopicPartitions};
+use crate::consumer::{
+    Consumer, ConsumerHealth, FetchParams, OffsetReset, PartitionOffsets, TopicPartition,
+    TopicPartitions,
+};
 use crate::metadata::ClusterMetadata;
 use crate::{
+    admin,
     error::{Error, KafkaCode, Result},
     metadata::{self},
     network::BrokerConnection,
     protocol, DEFAULT_CLIENT_ID, DEFAULT_CORRELATION_ID,
 };
 use nom::AsBytes;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
+use std::sync::Arc;
+use tokio::sync::watch;
+
+/// Special ListOffsets timestamp asking for the offset of the next record.
+pub(crate) const LATEST_TIMESTAMP: i64 = -1;
+/// Special ListOffsets timestamp asking for the earliest available offset.
+pub(crate) const EARLIEST_TIMESTAMP: i64 = -2;
 
 /// Configure a [`Consumer`].
 ///
@@ -47,6 +58,10 @@ pub struct ConsumerBuilder<T: BrokerConnection> {
     pub(crate) assigned_topic_partitions: TopicPartitions,
     /// Offsets to read from for each assigned topic partition.
     pub(crate) offsets: PartitionOffsets,
+    /// Exclusive offsets to stop reading each bounded topic partition at.
+    pub(crate) end_offsets: PartitionOffsets,
+    /// Whether to stop at the high watermark of each topic partition.
+    pub(crate) stop_at_end: bool,
 }
 
 impl<T: BrokerConnection + Clone + Debug> ConsumerBuilder<T> {
@@ -73,6 +88,8 @@ impl<T: BrokerConnection + Clone + Debug> ConsumerBuilder<T> {
             fetch_params: FetchParams::new(),
             assigned_topic_partitions,
             offsets: HashMap::new(),
+            end_offsets: HashMap::new(),
+            stop_at_end: false,
         })
     }
 
@@ -84,49 +101,13 @@ impl<T: BrokerConnection + Clone + Debug> ConsumerBuilder<T> {
     /// Note: This method overwrites the entire offsets object.
     pub async fn seek_to_timestamp(mut self, timestamp: i64) -> Result<Self> {
         tracing::debug!("Seeking offsets to timestamp {}", timestamp);
-        // TODO: Push this into the metadata
-        let brokers_and_their_topic_partitions = self
-            .cluster_metadata
-            .get_connections_for_topic_partitions(&self.assigned_topic_partitions)?;
-        self.offsets = HashMap::new();
-
-        // TODO: Make these all calls run async
-        // try this https://docs.rs/tokio/latest/tokio/task/join_set/struct.JoinSet.html
-        for (broker_conn, topic_partitions) in brokers_and_their_topic_partitions.into_iter() {
-            let offsets_list = list_offsets(
-                broker_conn,
-                self.fetch_params.correlation_id,
-                &self.fetch_params.client_id,
-                &topic_partitions,
-                timestamp,
-            )
-            .await?;
-
-            let partition_offsets = offsets_list.into_box_iter();
-            for (topic_name, partition) in partition_offsets {
-                if partition.error_code != KafkaCode::None {
-                    return Err(Error::KafkaError(partition.error_code));
-                }
-
-                let topic_name = std::str::from_utf8(topic_name.as_bytes()).map_err(|err| {
-                    tracing::error!("Error converting from UTF8 {:?}", err);
-                    Error::DecodingUtf8Error
-                })?;
-
-                // this is a sneaky way to use data that we own :)
-                let topic_name = self
-                    .cluster_metadata
-                    .topic_names
-                    .iter()
-                    .find(|my_topic| **my_topic == topic_name)
-                    .ok_or(Error::MetadataNeedsSync)?;
-
-                self.offsets.insert(
-                    (topic_name.to_owned(), partition.partition_index),
-                    partition.offset,
-                );
-            }
-        }
+        self.offsets = resolve_offsets(
+            &self.cluster_metadata,
+            &self.fetch_params,
+            &self.assigned_topic_partitions,
+            timestamp,
+        )
+        .await?;
         tracing::trace!("Offsets set to {:?}", self.offsets);
 
         Ok(self)
@@ -210,6 +191,30 @@ impl<T: BrokerConnection + Clone + Debug> ConsumerBuilder<T> {
         self
     }
 
+    /// Stop reading topic partitions at the given exclusive end offsets.
+    ///
+    /// Together with [`seek`](Self::seek), this consumes a bounded range of
+    /// offsets, e.g. to reprocess a known window. Records at or past the end
+    /// offset are dropped, and the streams complete once every assigned topic
+    /// partition has reached its end offset. Topic partitions without an end
+    /// offset are read as usual, so the streams never complete.
+    pub fn end_offsets(mut self, end_offsets: &PartitionOffsets) -> Self {
+        self.end_offsets = end_offsets.clone();
+        self
+    }
+
+    /// Stop reading topic partitions at the end of the log.
+    ///
+    /// Each assigned topic partition is read up to the high watermark of the
+    /// first fetch that returns it, and the streams complete once all of them
+    /// got there. Records written after that are left for the next consumer.
+    /// Topic partitions given an end offset with
+    /// [`end_offsets`](Self::end_offsets) stop at that offset instead.
+    pub fn stop_at_end(mut self) -> Self {
+        self.stop_at_end = true;
+        self
+    }
+
     pub fn correlation_id(mut self, correlation_id: i32) -> Self {
         self.fetch_params.correlation_id = correlation_id;
         self
@@ -238,28 +243,232 @@ impl<T: BrokerConnection + Clone + Debug> ConsumerBuilder<T> {
         self
     }
 
+    /// The maximum bytes to fetch across all partitions of a fetch request, which bounds the size of
+    /// the response when following many partitions. Same as `max_bytes`.
+    pub fn fetch_max_bytes(self, fetch_max_bytes: i32) -> Self {
+        self.max_bytes(fetch_max_bytes)
+    }
+
     /// The maximum bytes to fetch from the partitions. See KIP-74 for cases where this limit may not be honored.
     pub fn max_partition_bytes(mut self, max_partition_bytes: i32) -> Self {
         self.fetch_params.max_partition_bytes = max_partition_bytes;
         self
     }
 
+    /// Where to continue from when the position in a topic partition is out
+    /// of range of its log, e.g. because the records were deleted before they
+    /// were read. Defaults to the start of the log.
+    pub fn offset_reset(mut self, offset_reset: OffsetReset) -> Self {
+        self.fetch_params.offset_reset = offset_reset;
+        self
+    }
+
+    /// How many times to refresh metadata and fetch again when a broker is no
+    /// longer the leader of a topic partition, before failing the fetch with
+    /// [`KafkaCode::NotLeaderForPartition`](crate::prelude::KafkaCode::NotLeaderForPartition).
+    pub fn max_not_leader_retries(mut self, max_not_leader_retries: usize) -> Self {
+        self.fetch_params.max_not_leader_retries = max_not_leader_retries;
+        self
+    }
+
     /// This setting controls the visibility of transactional records. Using READ_UNCOMMITTED (isolation_level = 0) makes all records visible. With READ_COMMITTED (isolation_level = 1), non-transactional and COMMITTED transactional records are visible. To be more concrete, READ_COMMITTED returns all data from offsets smaller than the current LSO (last stable offset), and enables the inclusion of the list of aborted transactions in the result, which allows consumers to discard ABORTED transactional records
     pub fn isolation_level(mut self, isolation_level: i8) -> Self {
         self.fetch_params.isolation_level = isolation_level;
         self
     }
 
+    /// The rack of the consumer. Brokers that are rack aware use this to point the consumer at a replica in the same rack, instead of always reading from the leader.
+    pub fn client_rack(mut self, client_rack: String) -> Self {
+        self.fetch_params.client_rack = client_rack;
+        self
+    }
+
+    /// The max number of fetched records a
+    /// [buffered stream](crate::prelude::Consumer::into_buffered_stream) holds
+    /// before it stops fetching, until the application drains them.
+    pub fn max_buffered_records(mut self, max_buffered_records: usize) -> Self {
+        self.fetch_params.max_buffered_records = max_buffered_records;
+        self
+    }
+
+    /// Decompress record batches as their records are consumed instead of all at once when the
+    /// response arrives. This keeps memory flat when consuming large compressed batches.
+    pub fn lazy_decompression(mut self, lazy_decompression: bool) -> Self {
+        self.fetch_params.lazy_decompression = lazy_decompression;
+        self
+    }
+
+    /// The most bytes the records of a fetched batch may decompress to. A batch that decompresses
+    /// to more fails the fetch with [`Error::DecompressedSizeExceeded`] rather than running out of
+    /// memory, and a lazily decompressed batch stops yielding records.
+    pub fn max_decompressed_batch_bytes(mut self, max_decompressed_batch_bytes: usize) -> Self {
+        self.fetch_params.max_decompressed_batch_bytes = max_decompressed_batch_bytes;
+        self
+    }
+
+    /// Fetch by topic id when the brokers support it.
+    ///
+    /// Asks a broker for its API versions and, when it supports Fetch v13 and
+    /// Metadata v10, fetches the metadata again to learn the topic ids and
+    /// sends Fetch requests by topic id from then on. Otherwise Fetch v11 is
+    /// used, identifying topics by name.
+    pub async fn negotiate_fetch_version(mut self) -> Result<Self> {
+        let conn = self
+            .cluster_metadata
+            .broker_connections
+            .values()
+            .next()
+            .ok_or(Error::MetadataNeedsSync)?
+            .clone();
+        let supported = admin::api_versions(
+            conn,
+            self.fetch_params.correlation_id,
+            &self.fetch_params.client_id,
+        )
+        .await?;
+
+        let fetch_max = supported
+            .max_version(protocol::fetch::request::API_KEY_FETCH)
+            .unwrap_or_default();
+        let metadata_max = supported
+            .max_version(protocol::metadata::request::API_KEY_METADATA)
+            .unwrap_or_default();
+        if fetch_max < protocol::fetch::request::FIRST_TOPIC_ID_VERSION
+            || metadata_max < protocol::metadata::request::FIRST_TOPIC_ID_VERSION
+        {
+            tracing::debug!(
+                "Fetching by topic name, the broker supports Fetch v{} and Metadata v{}",
+                fetch_max,
+                metadata_max
+            );
+            return Ok(self);
+        }
+
+        self.cluster_metadata.metadata_version =
+            protocol::metadata::request::FIRST_TOPIC_ID_VERSION;
+        self.cluster_metadata.refresh().await?;
+        self.fetch_params.fetch_version = protocol::fetch::request::FIRST_TOPIC_ID_VERSION;
+        tracing::debug!("Fetching by topic id");
+
+        Ok(self)
+    }
+
     pub fn build(self) -> Consumer<T> {
+        let health = ConsumerHealth::default();
+        health.set_assignment(self.assigned_topic_partitions.clone());
         Consumer {
             cluster_metadata: self.cluster_metadata,
             fetch_params: self.fetch_params,
             assigned_topic_partitions: self.assigned_topic_partitions,
             offsets: self.offsets,
+            preferred_read_replicas: HashMap::new(),
+            initial_high_watermarks: HashMap::new(),
+            high_watermarks: HashMap::new(),
+            caught_up: Arc::new(watch::channel(false).0),
+            end_offsets: self.end_offsets,
+            stop_at_end: self.stop_at_end,
+            leader_epochs: HashMap::new(),
+            positions_to_validate: HashSet::new(),
+            health,
+            fetch_sessions: HashMap::new(),
         }
     }
 }
 
+/// Resolve the offset at a given timestamp for each topic partition.
+///
+/// Topic partitions with no record at or after the timestamp resolve to the
+/// end of the log, which is where new records will be written.
+pub(crate) async fn offsets_for_timestamp<T: BrokerConnection + Clone + Debug>(
+    cluster_metadata: &ClusterMetadata<T>,
+    fetch_params: &FetchParams,
+    topic_partitions: &TopicPartitions,
+    timestamp: i64,
+) -> Result<PartitionOffsets> {
+    let mut offsets =
+        resolve_offsets(cluster_metadata, fetch_params, topic_partitions, timestamp).await?;
+
+    let mut past_the_end = TopicPartitions::new();
+    for ((topic_name, partition_index), offset) in offsets.iter() {
+        if *offset == -1 {
+            past_the_end
+                .entry(topic_name.to_owned())
+                .or_default()
+                .push(*partition_index);
+        }
+    }
+    if !past_the_end.is_empty() && timestamp != LATEST_TIMESTAMP {
+        tracing::debug!(
+            "No records after timestamp {} for {:?}, using the latest offsets",
+            timestamp,
+            past_the_end
+        );
+        let latest = resolve_offsets(
+            cluster_metadata,
+            fetch_params,
+            &past_the_end,
+            LATEST_TIMESTAMP,
+        )
+        .await?;
+        offsets.extend(latest);
+    }
+
+    Ok(offsets)
+}
+
+/// Look up the offset at a given timestamp for each topic partition, as
+/// listed by the brokers: `-1` when there is no record at or after it.
+async fn resolve_offsets<T: BrokerConnection + Clone + Debug>(
+    cluster_metadata: &ClusterMetadata<T>,
+    fetch_params: &FetchParams,
+    topic_partitions: &TopicPartitions,
+    timestamp: i64,
+) -> Result<PartitionOffsets> {
+    // TODO: Push this into the metadata
+    let brokers_and_their_topic_partitions =
+        cluster_metadata.get_connections_for_topic_partitions(topic_partitions)?;
+    let mut offsets = HashMap::new();
+
+    // TODO: Make these all calls run async
+    // try this https://docs.rs/tokio/latest/tokio/task/join_set/struct.JoinSet.html
+    for (broker_conn, topic_partitions) in brokers_and_their_topic_partitions.into_iter() {
+        let offsets_list = list_offsets(
+            broker_conn,
+            fetch_params.correlation_id,
+            &fetch_params.client_id,
+            &topic_partitions,
+            timestamp,
+        )
+        .await?;
+
+        let partition_offsets = offsets_list.into_box_iter();
+        for (topic_name, partition) in partition_offsets {
+            if partition.error_code != KafkaCode::None {
+                return Err(Error::KafkaError(partition.error_code));
+            }
+
+            let topic_name = std::str::from_utf8(topic_name.as_bytes()).map_err(|err| {
+                tracing::error!("Error converting from UTF8 {:?}", err);
+                Error::DecodingUtf8Error
+            })?;
+
+            // this is a sneaky way to use data that we own :)
+            let topic_name = cluster_metadata
+                .topic_names
+                .iter()
+                .find(|my_topic| **my_topic == topic_name)
+                .ok_or(Error::MetadataNeedsSync)?;
+
+            offsets.insert(
+                (topic_name.to_owned(), partition.partition_index),
+                partition.offset,
+            );
+        }
+    }
+
+    Ok(offsets)
+}
+
 /// Fetch a set of offsets for a consumer group.
 // #[instrument(level = "debug")]
 pub async fn fetch_offset(
@@ -286,6 +495,61 @@ pub async fn fetch_offset(
     protocol::OffsetFetchResponse::try_from(offset_response.freeze())
 }
 
+/// Fetch the committed offset of each topic partition for a consumer group,
+/// together with the metadata string it was committed with.
+///
+/// Topic partitions without a committed offset are left out.
+pub async fn fetch_committed_offsets(
+    correlation_id: i32,
+    client_id: &str,
+    group_id: &str,
+    coordinator_conn: impl BrokerConnection,
+    topic_partitions: &TopicPartitions,
+) -> Result<HashMap<TopicPartition, (i64, Option<String>)>> {
+    let offset_response = fetch_offset(
+        correlation_id,
+        client_id,
+        group_id,
+        coordinator_conn,
+        topic_partitions,
+    )
+    .await?;
+
+    if offset_response.error_code != KafkaCode::None {
+        return Err(Error::KafkaError(offset_response.error_code));
+    }
+
+    let mut offsets = HashMap::new();
+    for (topic_name, partition) in offset_response.into_box_iter() {
+        if partition.error_code != KafkaCode::None {
+            return Err(Error::KafkaError(partition.error_code));
+        }
+        if partition.committed_offset == -1 {
+            continue;
+        }
+
+        let topic_name = String::from_utf8(topic_name.to_vec()).map_err(|err| {
+            tracing::error!("Error converting from UTF8 {:?}", err);
+            Error::DecodingUtf8Error
+        })?;
+        let metadata = partition
+            .metadata
+            .map(|metadata| String::from_utf8(metadata.to_vec()))
+            .transpose()
+            .map_err(|err| {
+                tracing::error!("Error converting from UTF8 {:?}", err);
+                Error::DecodingUtf8Error
+            })?;
+
+        offsets.insert(
+            (topic_name, partition.partition_index),
+            (partition.committed_offset, metadata),
+        );
+    }
+
+    Ok(offsets)
+}
+
 /// Get information about the available offsets for a given topic partition.
 ///
 /// Used to ask for all messages before a certain time (ms). There are two special values. Specify -1 to receive the latest offset (i.e. the offset of the next coming message) and -2 to receive the earliest available offset. This applies to all versions of the API. Note that because offsets are pulled in descending order, asking for the earliest offset will always return you a single element.
@@ -315,3 +579,52 @@ pub async fn list_offsets(
     let list_offsets_response = broker_conn.receive_response().await?;
     protocol::ListOffsetsResponse::try_from(list_offsets_response.freeze())
 }
+
+/// Get the offset at a timestamp of every partition of a topic.
+///
+/// The partitions are looked up in the metadata, which learns about the topic
+/// first when needed. All the partitions a broker leads are covered by one
+/// ListOffsets request, so a topic led by a single broker takes a single
+/// request. Like [`list_offsets`], -1 asks for the latest offsets and -2 for
+/// the earliest.
+pub async fn list_topic_offsets<T: BrokerConnection + Clone + Debug>(
+    cluster_metadata: &mut ClusterMetadata<T>,
+    topic_name: &str,
+    timestamp: i64,
+) -> Result<HashMap<i32, i64>> {
+    cluster_metadata
+        .add_topics(&[topic_name.to_owned()])
+        .await?;
+    let partitions = cluster_metadata
+        .topics
+        .iter()
+        .find(|topic| topic.name.as_bytes() == topic_name.as_bytes())
+        .ok_or(Error::MetadataNeedsSync)?
+        .partitions
+        .iter()
+        .map(|partition| partition.partition_index)
+        .collect();
+    let topic_partitions = HashMap::from([(topic_name.to_owned(), partitions)]);
+
+    let mut offsets = HashMap::new();
+    for (broker_conn, topic_partitions) in
+        cluster_metadata.get_connections_for_topic_partitions(&topic_partitions)?
+    {
+        let response = list_offsets(
+            broker_conn,
+            cluster_metadata.correlation_id,
+            &cluster_metadata.client_id,
+            &topic_partitions,
+            timestamp,
+        )
+        .await?;
+        for (_, partition) in response.into_box_iter() {
+            if partition.error_code != KafkaCode::None {
+                return Err(Error::KafkaError(partition.error_code));
+            }
+            offsets.insert(partition.partition_index, partition.offset);
+        }
+    }
+
+    Ok(offsets)
+}
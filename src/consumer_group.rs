@@ -1,6 +1,6 @@
 //! Consumer which cooperates with others to consume data.
 
-use std::{collections::HashMap, fmt::Debug};
+use std::{collections::HashMap, fmt::Debug, future::Future};
 
 use bytes::Bytes;
 use nom::AsBytes;
@@ -8,8 +8,12 @@ use tokio_stream::{Stream, StreamExt};
 
 use crate::{
     assignor::{assign, ROUND_ROBIN_PROTOCOL},
-    consumer::{ConsumeMessage, FetchParams, TopicPartitions},
+    consumer::{
+        commit_offset, ConsumeMessage, Consumer, ConsumerHealth, FetchParams, PartitionOffsets,
+        TopicPartitions,
+    },
     consumer_builder::ConsumerBuilder,
+    consumer_group_builder::{GroupCoordinators, MAX_COORDINATOR_RETRIES},
     error::{Error, KafkaCode, Result},
     network::BrokerConnection,
     protocol::{
@@ -18,6 +22,7 @@ use crate::{
         sync_group::response::MemberAssignment,
         Assignment,
     },
+    utils::retry_backoff,
 };
 
 const DEFAULT_PROTOCOL_TYPE: &str = "consumer";
@@ -71,176 +76,45 @@ pub struct ConsumerGroup<T: BrokerConnection> {
     pub retention_time_ms: i64,
     pub group_topic_partitions: TopicPartitions,
     pub fetch_params: FetchParams,
+    pub coordinators: GroupCoordinators<T>,
+    /// Shared with the consumer of each generation.
+    pub health: ConsumerHealth,
+    /// Offsets kept outside of Kafka, where each consumer of the member
+    /// starts instead of at the committed offsets. When set, the group never
+    /// fetches nor commits offsets, and the offsets are moved along as
+    /// records are consumed.
+    pub external_offsets: Option<PartitionOffsets>,
 }
 
 impl<T: BrokerConnection + Clone + Debug> ConsumerGroup<T> {
-    pub fn into_stream(
-        mut self,
-    ) -> impl Stream<Item = Result<impl Iterator<Item = ConsumeMessage>>> {
+    /// Convert the group member into an asynchronous iterator.
+    ///
+    /// Dropping the stream cancels the fetch in flight and leaves the group,
+    /// so the coordinator hands the partitions of this member to the others
+    /// right away instead of once its session timed out.
+    pub fn into_stream(mut self) -> impl Stream<Item = Result<impl Iterator<Item = ConsumeMessage>>>
+    where
+        T: Send + Sync + 'static,
+    {
         async_stream::stream! {
-            let coordinator_conn = self.coordinator_conn.clone();
+            let mut membership = GroupMembership {
+                coordinator_conn: self.coordinator_conn.clone(),
+                correlation_id: self.correlation_id,
+                client_id: self.client_id.clone(),
+                group_id: self.group_id.clone(),
+                member_id: None,
+            };
+            let mut joined = false;
             loop {
-                tracing::info!(
-                    "Member {:?} | Joining group {} for generation {}",
-                    self.member_id,
-                    self.group_id,
-                    self.generation_id
-                );
-                let protocols = [ROUND_ROBIN_PROTOCOL]
-                    .iter()
-                    .map(|protocol| Protocol {
-                        name: protocol,
-                        metadata: Metadata {
-                            version: 3,
-                            subscription: self
-                                .group_topic_partitions.keys().map(|k| k.as_ref())
-                                .collect::<Vec<&str>>(),
-                            user_data: None,
-                        },
-                    })
-                    .collect();
-
-                let join = join_group(
-                    coordinator_conn.clone(),
-                    self.correlation_id,
-                    &self.client_id,
-                    &self.group_id,
-                    self.session_timeout_ms,
-                    self.rebalance_timeout_ms,
-                    self.member_id.clone(),
-                    DEFAULT_PROTOCOL_TYPE,
-                    protocols,
-                )
-                .await?;
-
-                /*
-                * GROUP_LOAD_IN_PROGRESS (14)
-                * GROUP_COORDINATOR_NOT_AVAILABLE (15)
-                * NOT_COORDINATOR_FOR_GROUP (16)
-                * INCONSISTENT_GROUP_PROTOCOL (23)
-                * UNKNOWN_MEMBER_ID (25)
-                * INVALID_SESSION_TIMEOUT (26)
-                * GROUP_AUTHORIZATION_FAILED (30)
-                */
-                // if join.error_code != KafkaCode::None {
-                //     return Err(Error::KafkaError(join.error_code));
-                // }
-
-                self.member_id = join.member_id;
-                self.generation_id = join.generation_id;
-
-                tracing::info!(
-                    "Member {:?} | group info: {} members, {:?} protocol, {:?} leader",
-                    self.member_id,
-                    join.members.len(),
-                    join.protocol_name,
-                    join.leader
-                );
-
-                let assignments = if self.member_id == join.leader {
-                    //ToDo:: make partitions configurable
-                    let number_of_consumers = join.members.len();
-
-                    // hmm is this leaky?? I gotta do this because of memory and lifetimes
-                    let assigned_topic_partitions: Vec<(&str, &Vec<i32>)> = self.group_topic_partitions
-                        .iter()
-                        .map(|(a, b)| (a.as_ref(), b))
-                        .collect();
-
-                    let partition_assignments = assign(
-                        std::str::from_utf8(join.protocol_name.as_bytes()).map_err(|err| {
-                            tracing::error!("Error converting from UTF8 {:?}", err);
-                            Error::DecodingUtf8Error
-                        })?,
-                        assigned_topic_partitions,
-                        number_of_consumers,
-                    )?;
-
-                    join.members
-                        .iter()
-                        .enumerate()
-                        .map(|(i, member)| {
-                            protocol::Assignment::new(
-                                member.member_id.clone(),
-                                partition_assignments[i].clone(),
-                            )
-                        })
-                        .collect::<Result<Vec<Assignment>>>()?
-                } else {
-                    vec![]
-                };
-
-                tracing::info!(
-                    "Member {:?} | making assignments {:?}",
-                    self.member_id,
-                    assignments
-                );
-                let sync = sync_group(
-                    coordinator_conn.clone(),
-                    self.correlation_id,
-                    &self.client_id,
-                    &self.group_id,
-                    self.generation_id,
-                    self.member_id.clone(),
-                    assignments,
-                )
-                .await?;
-
-                /*
-                * GROUP_COORDINATOR_NOT_AVAILABLE (15)
-                * NOT_COORDINATOR_FOR_GROUP (16)
-                * ILLEGAL_GENERATION (22)
-                * UNKNOWN_MEMBER_ID (25)
-                * REBALANCE_IN_PROGRESS (27)
-                * GROUP_AUTHORIZATION_FAILED (30)
-                */
-                // if sync.error_code != KafkaCode::None {
-                //     return Err(Error::KafkaError(sync.error_code));
-                // }
-
-                tracing::info!(
-                    "Member {:?} | Assigned to {:?}",
-                    self.member_id,
-                    sync.assignment
-                );
-
-                self.assignment = Some(sync.assignment);
-
-                tracing::info!(
-                    "Member {:?} | Assigned to {:?}",
-                    self.member_id,
-                    self.assignment
-                );
+                if !joined {
+                    self.rejoin().await?;
+                }
+                joined = false;
+                // the coordinator and member id may change on each join
+                membership.coordinator_conn = self.coordinator_conn.clone();
+                membership.member_id = Some(self.member_id.clone());
 
-                let assigned_topic_partitions: TopicPartitions =
-                    self.assignment
-                        .iter()
-                        .fold(HashMap::new(), |mut acc, assignment| {
-                            for assignment in assignment.partition_assignments.clone() {
-                                let topic_name =
-                                    std::str::from_utf8(assignment.topic_name.as_bytes()).unwrap();
-                                let topic = self
-                                    .group_topic_partitions
-                                    .keys()
-                                    .find(|topic| *topic == topic_name)
-                                    .unwrap();
-                                acc.insert(topic.to_owned(), assignment.partitions);
-                            }
-                            acc
-                        });
-
-                let consumer = ConsumerBuilder::<T>::new(self.connection_params.clone(), assigned_topic_partitions)
-                    .await?
-                    .seek_to_group(coordinator_conn.clone(), &self.group_id)
-                    .await?
-                    .build()
-                    .into_autocommit_stream(
-                        coordinator_conn.clone(),
-                        &self.group_id,
-                        self.generation_id,
-                        self.member_id.clone(),
-                        self.retention_time_ms,
-                    );
+                let consumer = self.consumer().await?.stream();
 
                 tokio::pin!(consumer);
 
@@ -251,12 +125,21 @@ impl<T: BrokerConnection + Clone + Debug> ConsumerGroup<T> {
                     // (the consumer would yield None if it was done?
                     // not really a thing in kafka, and ours doesn't ever do a None)
                     if let Some(v) = consumer.next().await {
-                        yield v;
+                        let (messages, offsets) = v?;
+                        yield Ok(messages);
+                        if let Some(external_offsets) = self.external_offsets.as_mut() {
+                            // the next consumer of this member picks up from here
+                            external_offsets.extend(offsets);
+                        } else if self.commit(offsets).await? {
+                            // the assignment may have changed while rejoining
+                            joined = true;
+                            break;
+                        }
                     }
 
                     tracing::info!("Member {:?} | Heartbeat", self.member_id);
                     let hb = heartbeat(
-                        coordinator_conn.clone(),
+                        self.coordinator_conn.clone(),
                         self.correlation_id,
                         &self.client_id,
                         &self.group_id,
@@ -264,6 +147,9 @@ impl<T: BrokerConnection + Clone + Debug> ConsumerGroup<T> {
                         self.member_id.clone(),
                     )
                     .await?;
+                    if hb.error_code == KafkaCode::None {
+                        self.health.record_heartbeat();
+                    }
 
                     /*
                     * GROUP_COORDINATOR_NOT_AVAILABLE (15)
@@ -273,7 +159,9 @@ impl<T: BrokerConnection + Clone + Debug> ConsumerGroup<T> {
                     * REBALANCE_IN_PROGRESS (27)
                     * GROUP_AUTHORIZATION_FAILED (30)
                     */
-                    if hb.error_code == KafkaCode::RebalanceInProgress {
+                    if hb.error_code == KafkaCode::RebalanceInProgress
+                        || hb.error_code.is_coordinator_error()
+                    {
                         // TODO: Include a state here that symbols a need to rebalance
                         break;
                     }
@@ -281,6 +169,335 @@ impl<T: BrokerConnection + Clone + Debug> ConsumerGroup<T> {
             }
         }
     }
+
+    /// A consumer of the topic partitions assigned to this member.
+    ///
+    /// It starts from the [`external_offsets`](Self::external_offsets) when
+    /// they are set, and from the offsets committed for the group otherwise.
+    pub async fn consumer(&self) -> Result<Consumer<T>> {
+        let assigned_topic_partitions: TopicPartitions =
+            self.assignment
+                .iter()
+                .fold(HashMap::new(), |mut acc, assignment| {
+                    for assignment in assignment.partition_assignments.clone() {
+                        let topic_name =
+                            std::str::from_utf8(assignment.topic_name.as_bytes()).unwrap();
+                        let topic = self
+                            .group_topic_partitions
+                            .keys()
+                            .find(|topic| *topic == topic_name)
+                            .unwrap();
+                        acc.insert(topic.to_owned(), assignment.partitions);
+                    }
+                    acc
+                });
+
+        let builder =
+            ConsumerBuilder::<T>::new(self.connection_params.clone(), assigned_topic_partitions)
+                .await?;
+        let mut consumer = match &self.external_offsets {
+            Some(external_offsets) => {
+                tracing::debug!(
+                    "Member {:?} | starting from the offsets stored outside the group",
+                    self.member_id
+                );
+                let offsets: PartitionOffsets = external_offsets
+                    .iter()
+                    .filter(|((topic_name, partition_index), _)| {
+                        builder
+                            .assigned_topic_partitions
+                            .get(topic_name)
+                            .is_some_and(|partitions| partitions.contains(partition_index))
+                    })
+                    .map(|(topic_partition, offset)| (topic_partition.clone(), *offset))
+                    .collect();
+                builder.seek(&offsets).build()
+            }
+            None => builder
+                .seek_to_group(self.coordinator_conn.clone(), &self.group_id)
+                .await?
+                .build(),
+        };
+        self.health
+            .set_assignment(consumer.assigned_topic_partitions.clone());
+        consumer.health = self.health.clone();
+
+        Ok(consumer)
+    }
+
+    /// A handle to the health of the group member, which stays up to date
+    /// after the group is turned into a stream.
+    pub fn health(&self) -> ConsumerHealth {
+        self.health.clone()
+    }
+
+    /// Hand every batch of records to `handler`, committing its offsets as
+    /// soon as the handler returns.
+    ///
+    /// There is no revoke handling of its own: it relies on
+    /// [`into_stream`](Self::into_stream) committing the offsets of every
+    /// batch before it heartbeats. So by the time a heartbeat learns that a
+    /// rebalance revokes partitions, the records handled from them are
+    /// committed and their next owner starts after them. Only a batch whose
+    /// handler did not return can be handled again. Runs until the handler
+    /// or the group fails.
+    pub async fn consume_with<F, Fut>(self, mut handler: F) -> Result<()>
+    where
+        T: Send + Sync + 'static,
+        F: FnMut(Vec<ConsumeMessage>) -> Fut,
+        Fut: Future<Output = Result<()>>,
+    {
+        let stream = self.into_stream();
+        tokio::pin!(stream);
+        // asking for the next batch commits the one just handled, before
+        // the stream heartbeats and rejoins on a rebalance
+        while let Some(messages) = stream.next().await {
+            handler(messages?.collect()).await?;
+        }
+        Ok(())
+    }
+
+    /// Join the group and sync up with the other members, tracking the new
+    /// generation id, member id and assignment of this member.
+    ///
+    /// While the coordinator is loading or has moved, it is looked up again
+    /// and the join is retried.
+    pub async fn rejoin(&mut self) -> Result<()> {
+        let mut coordinator_retries = 0;
+        let join = loop {
+            tracing::info!(
+                "Member {:?} | Joining group {} for generation {}",
+                self.member_id,
+                self.group_id,
+                self.generation_id
+            );
+            let protocols = [ROUND_ROBIN_PROTOCOL]
+                .iter()
+                .map(|protocol| Protocol {
+                    name: protocol,
+                    metadata: Metadata {
+                        version: 3,
+                        subscription: self
+                            .group_topic_partitions
+                            .keys()
+                            .map(|k| k.as_ref())
+                            .collect::<Vec<&str>>(),
+                        user_data: None,
+                    },
+                })
+                .collect();
+
+            let join = join_group(
+                self.coordinator_conn.clone(),
+                self.correlation_id,
+                &self.client_id,
+                &self.group_id,
+                self.session_timeout_ms,
+                self.rebalance_timeout_ms,
+                self.member_id.clone(),
+                DEFAULT_PROTOCOL_TYPE,
+                protocols,
+            )
+            .await?;
+
+            /*
+             * GROUP_LOAD_IN_PROGRESS (14)
+             * GROUP_COORDINATOR_NOT_AVAILABLE (15)
+             * NOT_COORDINATOR_FOR_GROUP (16)
+             * INCONSISTENT_GROUP_PROTOCOL (23)
+             * UNKNOWN_MEMBER_ID (25)
+             * INVALID_SESSION_TIMEOUT (26)
+             * GROUP_AUTHORIZATION_FAILED (30)
+             */
+            // if join.error_code != KafkaCode::None {
+            //     return Err(Error::KafkaError(join.error_code));
+            // }
+            if join.error_code.is_coordinator_error() {
+                if coordinator_retries == MAX_COORDINATOR_RETRIES {
+                    return Err(Error::KafkaError(join.error_code));
+                }
+                coordinator_retries += 1;
+                tracing::warn!(
+                    "Member {:?} | coordinator is not ready ({:?}), looking it up again",
+                    self.member_id,
+                    join.error_code
+                );
+                tokio::time::sleep(retry_backoff(coordinator_retries as u32)).await;
+                self.coordinators.forget(&self.group_id);
+                self.coordinator_conn = self
+                    .coordinators
+                    .connect(self.correlation_id, &self.client_id, &self.group_id)
+                    .await?;
+                continue;
+            }
+            if join.error_code == KafkaCode::InvalidSessionTimeout {
+                // rejoining with the same timeouts can never succeed
+                tracing::error!(
+                    "Member {:?} | session timeout {} ms is outside the range allowed by the broker",
+                    self.member_id,
+                    self.session_timeout_ms
+                );
+                return Err(Error::KafkaError(join.error_code));
+            }
+            break join;
+        };
+
+        self.member_id = join.member_id;
+        self.generation_id = join.generation_id;
+
+        tracing::info!(
+            "Member {:?} | group info: {} members, {:?} protocol, {:?} leader",
+            self.member_id,
+            join.members.len(),
+            join.protocol_name,
+            join.leader
+        );
+
+        let assignments = if self.member_id == join.leader {
+            //ToDo:: make partitions configurable
+            let number_of_consumers = join.members.len();
+
+            // hmm is this leaky?? I gotta do this because of memory and lifetimes
+            let assigned_topic_partitions: Vec<(&str, &Vec<i32>)> = self
+                .group_topic_partitions
+                .iter()
+                .map(|(a, b)| (a.as_ref(), b))
+                .collect();
+
+            let partition_assignments = assign(
+                std::str::from_utf8(join.protocol_name.as_bytes()).map_err(|err| {
+                    tracing::error!("Error converting from UTF8 {:?}", err);
+                    Error::DecodingUtf8Error
+                })?,
+                assigned_topic_partitions,
+                number_of_consumers,
+            )?;
+
+            join.members
+                .iter()
+                .enumerate()
+                .map(|(i, member)| {
+                    protocol::Assignment::new(
+                        member.member_id.clone(),
+                        partition_assignments[i].clone(),
+                    )
+                })
+                .collect::<Result<Vec<Assignment>>>()?
+        } else {
+            vec![]
+        };
+
+        tracing::info!(
+            "Member {:?} | making assignments {:?}",
+            self.member_id,
+            assignments
+        );
+        let sync = sync_group(
+            self.coordinator_conn.clone(),
+            self.correlation_id,
+            &self.client_id,
+            &self.group_id,
+            self.generation_id,
+            self.member_id.clone(),
+            assignments,
+        )
+        .await?;
+
+        /*
+         * GROUP_COORDINATOR_NOT_AVAILABLE (15)
+         * NOT_COORDINATOR_FOR_GROUP (16)
+         * ILLEGAL_GENERATION (22)
+         * UNKNOWN_MEMBER_ID (25)
+         * REBALANCE_IN_PROGRESS (27)
+         * GROUP_AUTHORIZATION_FAILED (30)
+         */
+        // if sync.error_code != KafkaCode::None {
+        //     return Err(Error::KafkaError(sync.error_code));
+        // }
+
+        self.assignment = Some(sync.assignment);
+
+        tracing::info!(
+            "Member {:?} | Assigned to {:?}",
+            self.member_id,
+            self.assignment
+        );
+
+        Ok(())
+    }
+
+    /// Commit offsets for the group with the current generation and member id.
+    ///
+    /// When those are stale, or the group is rebalancing, the member rejoins
+    /// the group first and commits again with the new ones, only for the
+    /// partitions it is still assigned: the others may already be read and
+    /// committed by their new owner. Returns whether it rejoined, as the
+    /// assignment may have changed.
+    pub async fn commit(&mut self, offsets: PartitionOffsets) -> Result<bool> {
+        let result = commit_offset(
+            self.correlation_id,
+            &self.client_id,
+            &self.group_id,
+            self.coordinator_conn.clone(),
+            self.generation_id,
+            self.member_id.clone(),
+            offsets.clone(),
+            self.retention_time_ms,
+        )
+        .await;
+
+        match result {
+            Err(Error::KafkaError(
+                error_code @ (KafkaCode::IllegalGeneration
+                | KafkaCode::UnknownMemberId
+                | KafkaCode::RebalanceInProgress),
+            )) => {
+                tracing::warn!(
+                    "Member {:?} | commit for generation {} failed with {:?}, rejoining first",
+                    self.member_id,
+                    self.generation_id,
+                    error_code
+                );
+                if matches!(result, Err(Error::KafkaError(KafkaCode::UnknownMemberId))) {
+                    // the coordinator forgot about us, join as a new member
+                    self.member_id = Bytes::from_static(b"");
+                }
+                self.rejoin().await?;
+                let offsets: PartitionOffsets = offsets
+                    .into_iter()
+                    .filter(|((topic_name, partition_index), _)| {
+                        self.is_assigned(topic_name, *partition_index)
+                    })
+                    .collect();
+                if offsets.is_empty() {
+                    return Ok(true);
+                }
+                commit_offset(
+                    self.correlation_id,
+                    &self.client_id,
+                    &self.group_id,
+                    self.coordinator_conn.clone(),
+                    self.generation_id,
+                    self.member_id.clone(),
+                    offsets,
+                    self.retention_time_ms,
+                )
+                .await?;
+                Ok(true)
+            }
+            result => result.map(|_| false),
+        }
+    }
+
+    /// Whether a topic partition is assigned to this member.
+    fn is_assigned(&self, topic_name: &str, partition_index: i32) -> bool {
+        self.assignment.iter().any(|assignment| {
+            assignment.partition_assignments.iter().any(|assigned| {
+                assigned.topic_name == topic_name.as_bytes()
+                    && assigned.partitions.contains(&partition_index)
+            })
+        })
+    }
 }
 
 /// Synchronize state for all members of a group (e.g. distribute partition assignments to consumers).
@@ -372,6 +589,45 @@ pub async fn heartbeat(
     protocol::HeartbeatResponse::try_from(heartbeat_response.freeze())
 }
 
+/// The membership of a group, which is left when this is dropped.
+struct GroupMembership<T: BrokerConnection + Clone + Send + 'static> {
+    coordinator_conn: T,
+    correlation_id: i32,
+    client_id: String,
+    group_id: String,
+    /// The member id, once the group was joined.
+    member_id: Option<Bytes>,
+}
+
+impl<T: BrokerConnection + Clone + Send + 'static> Drop for GroupMembership<T> {
+    fn drop(&mut self) {
+        let Some(member_id) = self.member_id.take() else {
+            return;
+        };
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            tracing::warn!(
+                "Member {:?} | cannot leave group {} outside of a runtime",
+                member_id,
+                self.group_id
+            );
+            return;
+        };
+        // dropping cannot wait, the coordinator is told in the background
+        let conn = self.coordinator_conn.clone();
+        let correlation_id = self.correlation_id;
+        let client_id = self.client_id.clone();
+        let group_id = self.group_id.clone();
+        runtime.spawn(async move {
+            tracing::info!("Member {:?} | Leaving group {}", member_id, group_id);
+            if let Err(err) =
+                leave_group(conn, correlation_id, &client_id, &group_id, member_id).await
+            {
+                tracing::warn!("Could not leave group {}: {:?}", group_id, err);
+            }
+        });
+    }
+}
+
 /// Directly depart a group.
 ///
 /// See this [protocol spec] for more information.
@@ -391,3 +647,543 @@ pub async fn leave_group(
 
     protocol::LeaveGroupResponse::try_from(leave_response.freeze())
 }
+
+#[cfg(test)]
+mod test {
+    use std::collections::VecDeque;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    use bytes::BufMut;
+
+    use super::*;
+    use crate::consumer_group_builder::ConsumerGroupBuilder;
+    use crate::encode::ToByte;
+    use crate::network::{tcp::TcpConnection, BrokerAddress};
+    use crate::protocol::produce::request::{Message, RecordBatch, RecordBatchAttributes};
+    use crate::test_support::{
+        api_key, body_offset, broker_address, listen, put_string, read_i32, read_i64, read_request,
+        read_string, write_response,
+    };
+
+    const GROUP_ID: &str = "group";
+    const TOPIC: &str = "purchases";
+
+    #[derive(Default)]
+    struct MockCoordinator {
+        /// The joins of each group, which is also its generation id.
+        joins: Mutex<HashMap<String, i32>>,
+        /// The generation id of every commit, the first of which fails.
+        commit_generations: Mutex<Vec<i32>>,
+        /// The error of the first commit, ILLEGAL_GENERATION when `None`.
+        first_commit_error: Option<KafkaCode>,
+        /// The partitions and offsets of every commit.
+        commits: Mutex<Vec<Vec<(i32, i64)>>>,
+        /// The record batches of partition 0 of `TOPIC` to answer fetches
+        /// with, one per fetch. Once they run out, fetches wait forever.
+        record_batches: Mutex<VecDeque<Vec<u8>>>,
+        /// The error code of every heartbeat.
+        heartbeat_error: Option<KafkaCode>,
+        /// The topic and partition assigned to each group, the partition 0
+        /// of `TOPIC` by default.
+        assignments: HashMap<String, (&'static str, i32)>,
+        /// The partition of `TOPIC` assigned from the second generation on,
+        /// if it differs from the first.
+        reassigned_partition: Option<i32>,
+        find_coordinator_requests: AtomicUsize,
+        connections: AtomicUsize,
+        /// The api key of every request.
+        api_keys: Mutex<Vec<i16>>,
+    }
+
+    impl MockCoordinator {
+        /// The group id, or key, that starts the body of the request.
+        fn group_id(request: &[u8]) -> String {
+            read_string(request, body_offset(request)).0.unwrap()
+        }
+
+        fn find_coordinator_response(&self, port: u16) -> Vec<u8> {
+            self.find_coordinator_requests
+                .fetch_add(1, Ordering::SeqCst);
+            let mut buf = vec![];
+            buf.put_i16(0); // error_code
+            buf.put_i32(1); // node_id
+            put_string(&mut buf, "127.0.0.1");
+            buf.put_i32(port as i32);
+            buf
+        }
+
+        /// Metadata v7, with this coordinator leading every partition of `TOPIC`.
+        fn metadata_response(&self, port: u16) -> Vec<u8> {
+            let mut buf = vec![];
+            buf.put_i32(0); // throttle_time_ms
+            buf.put_i32(1);
+            buf.put_i32(1); // node_id
+            put_string(&mut buf, "127.0.0.1");
+            buf.put_i32(port as i32);
+            buf.put_i16(-1); // rack
+            buf.put_i16(-1); // cluster_id
+            buf.put_i32(1); // controller_id
+            buf.put_i32(1);
+            buf.put_i16(0); // error_code
+            put_string(&mut buf, TOPIC);
+            buf.put_i8(0); // is_internal
+            buf.put_i32(2);
+            for partition_index in 0..2 {
+                buf.put_i16(0); // error_code
+                buf.put_i32(partition_index);
+                buf.put_i32(1); // leader_id
+                buf.put_i32(0); // leader_epoch
+                buf.put_slice(&[0, 0, 0, 1, 0, 0, 0, 1]); // replica_nodes
+                buf.put_slice(&[0, 0, 0, 1, 0, 0, 0, 1]); // isr_nodes
+                buf.put_i32(0); // offline_replicas
+            }
+            buf
+        }
+
+        fn join_group_response(&self, request: &[u8]) -> Vec<u8> {
+            let mut joins = self.joins.lock().unwrap();
+            let generation_id = joins.entry(Self::group_id(request)).or_default();
+            *generation_id += 1;
+            let mut buf = vec![];
+            buf.put_i32(0); // throttle_time_ms
+            buf.put_i16(0); // error_code
+            buf.put_i32(*generation_id); // generation_id
+            put_string(&mut buf, ROUND_ROBIN_PROTOCOL);
+            put_string(&mut buf, "leader"); // another member leads
+            put_string(&mut buf, "member");
+            buf.put_i32(0); // members
+            buf
+        }
+
+        fn sync_group_response(&self, request: &[u8]) -> Vec<u8> {
+            let group_id = Self::group_id(request);
+            let generation_id = self.joins.lock().unwrap()[&group_id];
+            let (topic, partition) = match self.reassigned_partition {
+                Some(partition) if generation_id > 1 => (TOPIC, partition),
+                _ => self
+                    .assignments
+                    .get(&group_id)
+                    .copied()
+                    .unwrap_or((TOPIC, 0)),
+            };
+            let mut assignment = vec![];
+            assignment.put_i16(0); // version
+            assignment.put_i32(1);
+            put_string(&mut assignment, topic);
+            assignment.put_i32(1);
+            assignment.put_i32(partition);
+            assignment.put_i32(-1); // user_data
+
+            let mut buf = vec![];
+            buf.put_i32(0); // throttle_time_ms
+            buf.put_i16(0); // error_code
+            buf.put_i32(assignment.len() as i32);
+            buf.put_slice(&assignment);
+            buf
+        }
+
+        fn offset_commit_response(&self, request: &[u8]) -> Vec<u8> {
+            // the generation id follows the client id and group id
+            let (_, generation) = read_string(request, body_offset(request));
+            let generation_id = read_i32(request, generation);
+
+            // past the member id and retention time, a single topic
+            let (_, retention_time) = read_string(request, generation + 4);
+            let (_, partitions) = read_string(request, retention_time + 8 + 4);
+            let mut offsets = vec![];
+            let mut partition = partitions + 4;
+            for _ in 0..read_i32(request, partitions) {
+                let offset = read_i64(request, partition + 4);
+                offsets.push((read_i32(request, partition), offset));
+                // past the committed metadata
+                partition = read_string(request, partition + 4 + 8).1;
+            }
+            self.commits.lock().unwrap().push(offsets);
+
+            let mut commit_generations = self.commit_generations.lock().unwrap();
+            let error_code = if commit_generations.is_empty() {
+                self.first_commit_error
+                    .unwrap_or(KafkaCode::IllegalGeneration)
+            } else {
+                KafkaCode::None
+            };
+            commit_generations.push(generation_id);
+
+            let mut buf = vec![];
+            buf.put_i32(1);
+            put_string(&mut buf, TOPIC);
+            buf.put_i32(1);
+            buf.put_i32(0); // partition_index
+            buf.put_i16(error_code as i16);
+            buf
+        }
+
+        /// No offsets are committed yet for partition 0 of `TOPIC`.
+        fn offset_fetch_response() -> Vec<u8> {
+            let mut buf = vec![];
+            buf.put_i32(1);
+            put_string(&mut buf, TOPIC);
+            buf.put_i32(1);
+            buf.put_i32(0); // partition_index
+            buf.put_i64(-1); // committed_offset
+            buf.put_i16(-1); // metadata
+            buf.put_i16(0); // error_code
+            buf.put_i16(0); // error_code
+            buf
+        }
+
+        /// Fetch v11, with the next record batch of partition 0 of `TOPIC`.
+        fn fetch_response(records: Vec<u8>) -> Vec<u8> {
+            let mut buf = vec![];
+            buf.put_i32(0); // throttle_time_ms
+            buf.put_i16(0); // error_code
+            buf.put_i32(0); // session_id
+            buf.put_i32(1);
+            put_string(&mut buf, TOPIC);
+            buf.put_i32(1);
+            buf.put_i32(0); // partition_index
+            buf.put_i16(0); // error_code
+            buf.put_i64(100); // high_watermark
+            buf.put_i64(100); // last_stable_offset
+            buf.put_i64(0); // log_start_offset
+            buf.put_i32(-1); // aborted_transactions
+            buf.put_i32(-1); // preferred_read_replica
+            buf.put_i32(records.len() as i32);
+            buf.put_slice(&records);
+            buf
+        }
+
+        async fn start(self: Arc<Self>) -> BrokerAddress {
+            let (listener, port) = listen().await;
+            tokio::spawn(async move {
+                while let Ok((mut socket, _)) = listener.accept().await {
+                    self.connections.fetch_add(1, Ordering::SeqCst);
+                    let coordinator = self.clone();
+                    tokio::spawn(async move {
+                        while let Some(request) = read_request(&mut socket).await {
+                            let api_key = api_key(&request);
+                            coordinator.api_keys.lock().unwrap().push(api_key);
+                            let records = match api_key {
+                                1 => coordinator.record_batches.lock().unwrap().pop_front(),
+                                _ => None,
+                            };
+                            if api_key == 1 && records.is_none() {
+                                // no records arrive, the fetch waits forever
+                                continue;
+                            }
+                            let body = match api_key {
+                                1 => Self::fetch_response(records.unwrap()),
+                                3 => coordinator.metadata_response(port),
+                                8 => coordinator.offset_commit_response(&request),
+                                9 => Self::offset_fetch_response(),
+                                10 => coordinator.find_coordinator_response(port),
+                                11 => coordinator.join_group_response(&request),
+                                12 => (coordinator.heartbeat_error.unwrap_or(KafkaCode::None)
+                                    as i16)
+                                    .to_be_bytes()
+                                    .to_vec(),
+                                13 => 0_i16.to_be_bytes().to_vec(), // error_code
+                                14 => coordinator.sync_group_response(&request),
+                                api_key => panic!("Unexpected api key {}", api_key),
+                            };
+                            write_response(&mut socket, &request, &body).await;
+                        }
+                    });
+                }
+            });
+
+            broker_address(port)
+        }
+    }
+
+    #[tokio::test]
+    async fn it_rejoins_and_commits_again_on_a_stale_generation() {
+        let coordinator = Arc::new(MockCoordinator::default());
+        let addr = coordinator.clone().start().await;
+        let mut group = ConsumerGroup {
+            connection_params: vec![addr.clone()],
+            coordinator_conn: TcpConnection::new(vec![addr.clone()]).await.unwrap(),
+            correlation_id: 1,
+            client_id: "rust".to_owned(),
+            session_timeout_ms: 10000,
+            rebalance_timeout_ms: 10000,
+            group_id: GROUP_ID.to_owned(),
+            member_id: Bytes::from_static(b"member"),
+            generation_id: 0,
+            assignment: None,
+            retention_time_ms: 1000,
+            group_topic_partitions: HashMap::from([(TOPIC.to_owned(), vec![0])]),
+            fetch_params: FetchParams::new(),
+            coordinators: GroupCoordinators::new(vec![addr.clone()]),
+            health: ConsumerHealth::default(),
+            external_offsets: None,
+        };
+
+        let offsets = HashMap::from([((TOPIC.to_owned(), 0), 42)]);
+        let rejoined = group.commit(offsets).await.unwrap();
+
+        assert!(rejoined);
+        assert_eq!(coordinator.joins.lock().unwrap()[GROUP_ID], 1);
+        assert_eq!(*coordinator.commit_generations.lock().unwrap(), vec![0, 1]);
+        assert_eq!(group.generation_id, 1);
+        assert_eq!(
+            group.assignment.unwrap().partition_assignments[0].partitions,
+            vec![0]
+        );
+    }
+
+    #[tokio::test]
+    async fn it_rejoins_and_commits_again_while_the_group_is_rebalancing() {
+        let coordinator = Arc::new(MockCoordinator {
+            first_commit_error: Some(KafkaCode::RebalanceInProgress),
+            ..Default::default()
+        });
+        let addr = coordinator.clone().start().await;
+        let mut group = ConsumerGroup {
+            connection_params: vec![addr.clone()],
+            coordinator_conn: TcpConnection::new(vec![addr.clone()]).await.unwrap(),
+            correlation_id: 1,
+            client_id: "rust".to_owned(),
+            session_timeout_ms: 10000,
+            rebalance_timeout_ms: 10000,
+            group_id: GROUP_ID.to_owned(),
+            member_id: Bytes::from_static(b"member"),
+            generation_id: 3,
+            assignment: None,
+            retention_time_ms: 1000,
+            group_topic_partitions: HashMap::from([(TOPIC.to_owned(), vec![0])]),
+            fetch_params: FetchParams::new(),
+            coordinators: GroupCoordinators::new(vec![addr.clone()]),
+            health: ConsumerHealth::default(),
+            external_offsets: None,
+        };
+
+        let offsets = HashMap::from([((TOPIC.to_owned(), 0), 42)]);
+        let rejoined = group.commit(offsets).await.unwrap();
+
+        // the second commit went out with the generation of the rejoin
+        assert!(rejoined);
+        assert_eq!(*coordinator.commit_generations.lock().unwrap(), vec![3, 1]);
+        assert_eq!(group.generation_id, 1);
+    }
+
+    #[tokio::test]
+    async fn it_only_commits_the_partitions_still_assigned_after_rejoining() {
+        let coordinator = Arc::new(MockCoordinator {
+            first_commit_error: Some(KafkaCode::RebalanceInProgress),
+            reassigned_partition: Some(1),
+            ..Default::default()
+        });
+        let addr = coordinator.clone().start().await;
+        let mut group = ConsumerGroupBuilder::<TcpConnection>::new(
+            vec![addr],
+            GROUP_ID.to_owned(),
+            HashMap::from([(TOPIC.to_owned(), vec![0, 1])]),
+        )
+        .await
+        .unwrap()
+        .build()
+        .await
+        .unwrap();
+        group.rejoin().await.unwrap();
+
+        let offsets = HashMap::from([((TOPIC.to_owned(), 0), 42), ((TOPIC.to_owned(), 1), 7)]);
+        let rejoined = group.commit(offsets).await.unwrap();
+
+        // partition 0 moved to another member while rebalancing
+        assert!(rejoined);
+        let mut commits = coordinator.commits.lock().unwrap().clone();
+        commits[0].sort();
+        assert_eq!(commits, vec![vec![(0, 42), (1, 7)], vec![(1, 7)]]);
+        assert_eq!(*coordinator.commit_generations.lock().unwrap(), vec![1, 2]);
+    }
+
+    #[tokio::test]
+    async fn it_leaves_the_group_once_the_stream_is_dropped_mid_fetch() {
+        let coordinator = Arc::new(MockCoordinator::default());
+        let addr = coordinator.clone().start().await;
+        let group = ConsumerGroup {
+            connection_params: vec![addr.clone()],
+            coordinator_conn: TcpConnection::new(vec![addr.clone()]).await.unwrap(),
+            correlation_id: 1,
+            client_id: "rust".to_owned(),
+            session_timeout_ms: 10000,
+            rebalance_timeout_ms: 10000,
+            group_id: GROUP_ID.to_owned(),
+            member_id: Bytes::from_static(b"member"),
+            generation_id: 0,
+            assignment: None,
+            retention_time_ms: 1000,
+            group_topic_partitions: HashMap::from([(TOPIC.to_owned(), vec![0])]),
+            fetch_params: FetchParams::new(),
+            coordinators: GroupCoordinators::new(vec![addr.clone()]),
+            health: ConsumerHealth::default(),
+            external_offsets: Some(HashMap::new()),
+        };
+        let api_keys = || coordinator.api_keys.lock().unwrap().clone();
+
+        let mut stream = Box::pin(group.into_stream());
+        let next = tokio::time::timeout(std::time::Duration::from_millis(100), stream.next()).await;
+        assert!(next.is_err());
+        assert_eq!(api_keys().last(), Some(&1));
+        drop(stream);
+
+        tokio::time::timeout(std::time::Duration::from_secs(1), async {
+            while !api_keys().contains(&13) {
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+    }
+
+    /// Encode a record batch with the given number of records at `base_offset`.
+    fn record_batch(base_offset: i64, record_count: usize) -> Vec<u8> {
+        let mut batch = RecordBatch::new(RecordBatchAttributes::new(None));
+        for _ in 0..record_count {
+            batch.add(Message::new(None, Some(Bytes::from("value")), vec![]));
+        }
+        let mut buf = vec![];
+        batch.encode(&mut buf).unwrap();
+        // the base offset is set by the broker and not covered by the crc
+        buf[..8].copy_from_slice(&base_offset.to_be_bytes());
+        buf
+    }
+
+    #[tokio::test]
+    async fn it_commits_the_handled_records_before_rejoining_on_a_rebalance() {
+        let coordinator = Arc::new(MockCoordinator {
+            first_commit_error: Some(KafkaCode::None),
+            record_batches: Mutex::new(VecDeque::from([record_batch(0, 2)])),
+            heartbeat_error: Some(KafkaCode::RebalanceInProgress),
+            ..Default::default()
+        });
+        let addr = coordinator.clone().start().await;
+        let group = ConsumerGroupBuilder::<TcpConnection>::new(
+            vec![addr],
+            GROUP_ID.to_owned(),
+            HashMap::from([(TOPIC.to_owned(), vec![0])]),
+        )
+        .await
+        .unwrap()
+        .build()
+        .await
+        .unwrap();
+
+        let handled = Arc::new(Mutex::new(vec![]));
+        let handling = handled.clone();
+        let consuming = tokio::spawn(group.consume_with(move |messages| {
+            let offsets = messages.iter().map(|message| message.offset);
+            handling.lock().unwrap().extend(offsets);
+            async { Ok(()) }
+        }));
+        let joins = || coordinator.joins.lock().unwrap().get(GROUP_ID).copied();
+        tokio::time::timeout(std::time::Duration::from_secs(1), async {
+            while joins() != Some(2) {
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        consuming.abort();
+
+        assert_eq!(*handled.lock().unwrap(), vec![0, 1]);
+        assert_eq!(*coordinator.commits.lock().unwrap(), vec![vec![(0, 2)]]);
+        // commit, then the heartbeat that learns of the rebalance, then rejoin
+        let api_keys = coordinator.api_keys.lock().unwrap().clone();
+        let commit = api_keys.iter().position(|api_key| *api_key == 8).unwrap();
+        let heartbeat = api_keys.iter().position(|api_key| *api_key == 12).unwrap();
+        let rejoin = api_keys.iter().rposition(|api_key| *api_key == 11).unwrap();
+        assert!(commit < heartbeat && heartbeat < rejoin, "{:?}", api_keys);
+    }
+
+    #[tokio::test]
+    async fn it_runs_groups_side_by_side_on_shared_coordinator_lookups() {
+        let coordinator = Arc::new(MockCoordinator {
+            assignments: HashMap::from([
+                ("orders".to_owned(), ("orders", 1)),
+                ("payments".to_owned(), ("payments", 2)),
+            ]),
+            ..Default::default()
+        });
+        let addr = coordinator.clone().start().await;
+        let coordinators = GroupCoordinators::<TcpConnection>::new(vec![addr.clone()]);
+
+        let build = |group_id: &str, topic: &str| {
+            let coordinators = coordinators.clone();
+            let addr = addr.clone();
+            let group_id = group_id.to_owned();
+            let topic = topic.to_owned();
+            async move {
+                ConsumerGroupBuilder::<TcpConnection>::new(
+                    vec![addr],
+                    group_id,
+                    HashMap::from([(topic, vec![0, 1, 2])]),
+                )
+                .await
+                .unwrap()
+                .coordinators(coordinators)
+                .build()
+                .await
+                .unwrap()
+            }
+        };
+        let (mut orders, mut payments) =
+            tokio::join!(build("orders", "orders"), build("payments", "payments"));
+
+        let (joined_orders, joined_payments) = tokio::join!(orders.rejoin(), payments.rejoin());
+        joined_orders.unwrap();
+        joined_payments.unwrap();
+        // only the orders group goes through another generation
+        orders.rejoin().await.unwrap();
+
+        let assigned = |group: &ConsumerGroup<TcpConnection>| {
+            let assignment = &group.assignment.as_ref().unwrap().partition_assignments[0];
+            (assignment.topic_name.clone(), assignment.partitions.clone())
+        };
+        assert_eq!(assigned(&orders), (Bytes::from("orders"), vec![1]));
+        assert_eq!(assigned(&payments), (Bytes::from("payments"), vec![2]));
+        assert_eq!(orders.generation_id, 2);
+        assert_eq!(payments.generation_id, 1);
+
+        // one lookup per group over the shared bootstrap connection, and a
+        // coordinator connection of each group's own
+        assert_eq!(
+            coordinator.find_coordinator_requests.load(Ordering::SeqCst),
+            2
+        );
+        assert_eq!(coordinator.connections.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn it_starts_from_external_offsets_without_fetching_committed_ones() {
+        let coordinator = Arc::new(MockCoordinator::default());
+        let addr = coordinator.clone().start().await;
+        let mut group = ConsumerGroupBuilder::<TcpConnection>::new(
+            vec![addr],
+            GROUP_ID.to_owned(),
+            HashMap::from([(TOPIC.to_owned(), vec![0, 1])]),
+        )
+        .await
+        .unwrap()
+        .external_offsets(&HashMap::from([
+            ((TOPIC.to_owned(), 0), 42),
+            ((TOPIC.to_owned(), 1), 7),
+        ]))
+        .build()
+        .await
+        .unwrap();
+
+        group.rejoin().await.unwrap();
+        let consumer = group.consumer().await.unwrap();
+
+        // only partition 0 is assigned to this member
+        assert_eq!(
+            consumer.offsets,
+            HashMap::from([((TOPIC.to_owned(), 0), 42)])
+        );
+        let api_keys = coordinator.api_keys.lock().unwrap();
+        assert!(!api_keys.contains(&9), "fetched offsets: {:?}", api_keys);
+    }
+}
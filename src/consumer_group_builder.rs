@@ -1,17 +1,27 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
 use bytes::Bytes;
 use nom::AsBytes;
 
 use crate::{
-    consumer::{FetchParams, TopicPartitions},
+    admin::downgrade_version,
+    consumer::{ConsumerHealth, FetchParams, PartitionOffsets, TopicPartitions},
     consumer_group::ConsumerGroup,
     error::{Error, KafkaCode, Result},
     network::{BrokerAddress, BrokerConnection},
-    protocol, DEFAULT_CLIENT_ID, DEFAULT_CORRELATION_ID,
+    protocol,
+    utils::retry_backoff,
+    DEFAULT_CLIENT_ID, DEFAULT_CORRELATION_ID,
 };
 
 const DEFAULT_RETENTION_TIME_MS: i64 = 100000;
 const DEFAULT_SESSION_TIMEOUT_MS: i32 = 10000;
 const DEFAULT_REBALANCE_TIMEOUT_MS: i32 = 10000;
+/// How many times to look up the coordinator while it is loading or not available.
+pub(crate) const MAX_COORDINATOR_RETRIES: usize = 5;
 
 /// Configure a [`ConsumerGroup`].
 #[derive(Clone)]
@@ -25,9 +35,11 @@ pub struct ConsumerGroupBuilder<T: BrokerConnection> {
     pub retention_time_ms: i64,
     pub group_topic_partitions: TopicPartitions,
     pub fetch_params: FetchParams,
+    pub coordinators: GroupCoordinators<T>,
+    pub external_offsets: Option<PartitionOffsets>,
 }
 
-impl<T: BrokerConnection> ConsumerGroupBuilder<T> {
+impl<T: BrokerConnection + Clone> ConsumerGroupBuilder<T> {
     /// Start a consumer group builder. To complete, use the [`build`](Self::build) method.
     pub async fn new(
         connection_params: T::ConnConfig,
@@ -35,6 +47,7 @@ impl<T: BrokerConnection> ConsumerGroupBuilder<T> {
         group_topic_partitions: TopicPartitions,
     ) -> Result<Self> {
         Ok(Self {
+            coordinators: GroupCoordinators::new(connection_params.clone()),
             connection_params,
             correlation_id: DEFAULT_CORRELATION_ID,
             client_id: DEFAULT_CLIENT_ID.to_owned(),
@@ -44,6 +57,7 @@ impl<T: BrokerConnection> ConsumerGroupBuilder<T> {
             retention_time_ms: DEFAULT_RETENTION_TIME_MS,
             group_topic_partitions,
             fetch_params: FetchParams::new(),
+            external_offsets: None,
         })
     }
 
@@ -57,14 +71,38 @@ impl<T: BrokerConnection> ConsumerGroupBuilder<T> {
         self
     }
 
+    /// Look up the coordinator with the [`GroupCoordinators`] shared by the
+    /// other groups of the process, instead of on a connection of its own.
+    pub fn coordinators(mut self, coordinators: GroupCoordinators<T>) -> Self {
+        self.coordinators = coordinators;
+        self
+    }
+
     pub fn retention_time_ms(mut self, retention_time_ms: i64) -> Self {
         self.retention_time_ms = retention_time_ms;
         self
     }
+
+    /// Start from offsets stored outside of Kafka, e.g. next to the results
+    /// of processing the records, instead of the offsets committed for the group.
+    ///
+    /// The group is still joined for the assignment of partitions, but offsets
+    /// are neither fetched nor committed. Assigned partitions without an
+    /// offset are read from the start.
+    pub fn external_offsets(mut self, offsets: &PartitionOffsets) -> Self {
+        self.external_offsets = Some(offsets.clone());
+        self
+    }
+
+    /// The time in milliseconds without a heartbeat after which the coordinator removes the member from the group.
+    /// It must fall within the group.min.session.timeout.ms and group.max.session.timeout.ms of the broker,
+    /// otherwise joining the group fails with [`KafkaCode::InvalidSessionTimeout`].
     pub fn session_timeout_ms(mut self, session_timeout_ms: i32) -> Self {
         self.session_timeout_ms = session_timeout_ms;
         self
     }
+
+    /// The maximum time in milliseconds the coordinator waits for each member to rejoin when rebalancing the group.
     pub fn rebalance_timeout_ms(mut self, rebalance_timeout_ms: i32) -> Self {
         self.rebalance_timeout_ms = rebalance_timeout_ms;
         self
@@ -88,6 +126,12 @@ impl<T: BrokerConnection> ConsumerGroupBuilder<T> {
         self
     }
 
+    /// The maximum bytes to fetch across all partitions of a fetch request, which bounds the size of
+    /// the response when following many partitions. Same as `max_bytes`.
+    pub fn fetch_max_bytes(self, fetch_max_bytes: i32) -> Self {
+        self.max_bytes(fetch_max_bytes)
+    }
+
     /// The maximum bytes to fetch from the partitions. See KIP-74 for cases where this limit may not be honored.
     pub fn max_partition_bytes(mut self, max_partition_bytes: i32) -> Self {
         self.fetch_params.max_partition_bytes = max_partition_bytes;
@@ -100,35 +144,39 @@ impl<T: BrokerConnection> ConsumerGroupBuilder<T> {
         self
     }
 
-    pub async fn build(self) -> Result<ConsumerGroup<T>> {
-        let conn = T::new(self.connection_params.clone()).await?;
-        let coordinator =
-            find_coordinator(conn, self.correlation_id, &self.client_id, &self.group_id).await?;
+    /// Decompress record batches as their records are consumed instead of all at once when the
+    /// response arrives. This keeps memory flat when consuming large compressed batches.
+    pub fn lazy_decompression(mut self, lazy_decompression: bool) -> Self {
+        self.fetch_params.lazy_decompression = lazy_decompression;
+        self
+    }
+
+    /// The most bytes the records of a fetched batch may decompress to. A batch that decompresses
+    /// to more fails the fetch with [`Error::DecompressedSizeExceeded`] rather than running out of
+    /// memory, and a lazily decompressed batch stops yielding records.
+    pub fn max_decompressed_batch_bytes(mut self, max_decompressed_batch_bytes: usize) -> Self {
+        self.fetch_params.max_decompressed_batch_bytes = max_decompressed_batch_bytes;
+        self
+    }
 
-        if coordinator.error_code != KafkaCode::None {
-            return Err(Error::KafkaError(coordinator.error_code));
+    pub async fn build(self) -> Result<ConsumerGroup<T>> {
+        if self.session_timeout_ms <= 0 {
+            return Err(Error::ArgError(format!(
+                "session_timeout_ms must be positive, got {}",
+                self.session_timeout_ms
+            )));
+        }
+        if self.rebalance_timeout_ms <= 0 {
+            return Err(Error::ArgError(format!(
+                "rebalance_timeout_ms must be positive, got {}",
+                self.rebalance_timeout_ms
+            )));
         }
 
-        let host = std::str::from_utf8(coordinator.host.as_bytes()).map_err(|err| {
-            tracing::error!("Error converting from UTF8 {:?}", err);
-            Error::DecodingUtf8Error
-        })?;
-        let port = coordinator.port;
-
-        let coordinator_conn = T::from_addr(
-            self.connection_params.clone(),
-            BrokerAddress {
-                host: host.to_string(),
-                port: port.try_into().map_err(|err| {
-                    tracing::error!(
-                        "Error decoding Broker connection port from metadata {:?}",
-                        err
-                    );
-                    Error::MetadataNeedsSync
-                })?,
-            },
-        )
-        .await?;
+        let coordinator_conn = self
+            .coordinators
+            .connect(self.correlation_id, &self.client_id, &self.group_id)
+            .await?;
 
         Ok(ConsumerGroup {
             connection_params: self.connection_params,
@@ -144,10 +192,133 @@ impl<T: BrokerConnection> ConsumerGroupBuilder<T> {
             member_id: Bytes::from_static(b""),
             generation_id: 0,
             assignment: None,
+            coordinators: self.coordinators,
+            health: ConsumerHealth::default(),
+            external_offsets: self.external_offsets,
         })
     }
 }
 
+/// Coordinator lookups shared by the consumer groups of a process.
+///
+/// Groups built with the same `GroupCoordinators` look up their coordinators
+/// over a single bootstrap connection, and remember where each group is
+/// coordinated until the coordinator moves. Each group still opens its own
+/// connection to its coordinator: the broker holds back the other requests
+/// on a connection while a JoinGroup waits for the rebalance, which would
+/// delay the heartbeats of the other groups.
+#[derive(Clone, Debug)]
+pub struct GroupCoordinators<T: BrokerConnection> {
+    connection_params: T::ConnConfig,
+    bootstrap_conn: Arc<tokio::sync::Mutex<Option<T>>>,
+    /// The coordinator address of each group that was looked up.
+    addresses: Arc<Mutex<HashMap<String, BrokerAddress>>>,
+}
+
+impl<T: BrokerConnection + Clone> GroupCoordinators<T> {
+    pub fn new(connection_params: T::ConnConfig) -> Self {
+        Self {
+            connection_params,
+            bootstrap_conn: Arc::new(tokio::sync::Mutex::new(None)),
+            addresses: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Connect to the coordinator of a group, looking it up unless it is
+    /// known already.
+    pub async fn connect(&self, correlation_id: i32, client_id: &str, group_id: &str) -> Result<T> {
+        let known = self.addresses.lock().unwrap().get(group_id).cloned();
+        let addr = match known {
+            Some(addr) => addr,
+            None => {
+                let addr = self.find(correlation_id, client_id, group_id).await?;
+                self.addresses
+                    .lock()
+                    .unwrap()
+                    .insert(group_id.to_owned(), addr.clone());
+                addr
+            }
+        };
+
+        T::from_addr(self.connection_params.clone(), addr).await
+    }
+
+    /// Look up the coordinator of a group again the next time it connects,
+    /// e.g. after the coordinator moved.
+    pub fn forget(&self, group_id: &str) {
+        self.addresses.lock().unwrap().remove(group_id);
+    }
+
+    /// Locate the current coordinator of a group.
+    ///
+    /// While the coordinator is loading the group or is not available yet, it is
+    /// looked up again with an exponential backoff, distinct from the retries on
+    /// stale partition leaders.
+    async fn find(
+        &self,
+        correlation_id: i32,
+        client_id: &str,
+        group_id: &str,
+    ) -> Result<BrokerAddress> {
+        let mut attempts = 0;
+        let coordinator = loop {
+            let conn = self.bootstrap_conn().await?;
+            let coordinator =
+                match find_coordinator(conn, correlation_id, client_id, group_id).await {
+                    Ok(coordinator) => coordinator,
+                    Err(err) => {
+                        // connect again for the next lookup
+                        self.bootstrap_conn.lock().await.take();
+                        return Err(err);
+                    }
+                };
+
+            if coordinator.error_code.is_coordinator_error() && attempts < MAX_COORDINATOR_RETRIES {
+                attempts += 1;
+                let backoff = retry_backoff(attempts as u32);
+                tracing::warn!(
+                    "Coordinator of group {} is not ready ({:?}), retrying in {:?}",
+                    group_id,
+                    coordinator.error_code,
+                    backoff
+                );
+                tokio::time::sleep(backoff).await;
+                continue;
+            }
+            if coordinator.error_code != KafkaCode::None {
+                return Err(Error::KafkaError(coordinator.error_code));
+            }
+            break coordinator;
+        };
+
+        let host = std::str::from_utf8(coordinator.host.as_bytes()).map_err(|err| {
+            tracing::error!("Error converting from UTF8 {:?}", err);
+            Error::DecodingUtf8Error
+        })?;
+        Ok(BrokerAddress {
+            host: host.to_string(),
+            port: coordinator.port.try_into().map_err(|err| {
+                tracing::error!(
+                    "Error decoding Broker connection port from metadata {:?}",
+                    err
+                );
+                Error::MetadataNeedsSync
+            })?,
+        })
+    }
+
+    /// A handle onto the bootstrap connection, connecting on first use.
+    async fn bootstrap_conn(&self) -> Result<T> {
+        let mut bootstrap_conn = self.bootstrap_conn.lock().await;
+        if let Some(conn) = bootstrap_conn.as_ref() {
+            return Ok(conn.clone());
+        }
+        let conn = T::new(self.connection_params.clone()).await?;
+        *bootstrap_conn = Some(conn.clone());
+        Ok(conn)
+    }
+}
+
 /// Locate the current coordinator of a group.
 ///
 /// See this [protocol spec] for more information.
@@ -167,3 +338,278 @@ pub async fn find_coordinator(
 
     protocol::FindCoordinatorResponse::try_from(find_coordinator_response.freeze())
 }
+
+/// Locate the coordinator broker of a group or of a transactional id.
+///
+/// Unlike [`find_coordinator`], the lookup names what kind of key is being
+/// resolved and fails with the broker error instead of returning it. A
+/// broker that does not support key types yet is asked again with the
+/// version before, which can still look up groups.
+///
+/// See this [protocol spec] for more information.
+///
+/// [protocol spec]: protocol::find_coordinator
+pub async fn find_coordinator_broker(
+    mut conn: impl BrokerConnection + Clone,
+    correlation_id: i32,
+    client_id: &str,
+    key: &str,
+    key_type: protocol::CoordinatorType,
+) -> Result<BrokerAddress> {
+    let mut find_coordinator_request =
+        protocol::FindCoordinatorRequest::with_key_type(correlation_id, client_id, key, key_type);
+    let mut coordinator = send_find_coordinator(&mut conn, &find_coordinator_request).await?;
+
+    if coordinator.error_code == KafkaCode::UnsupportedVersion {
+        // transactional ids can only be looked up from version 1
+        let min_version = match key_type {
+            protocol::CoordinatorType::Group => 0,
+            protocol::CoordinatorType::Transaction => 1,
+        };
+        find_coordinator_request.header.api_version = downgrade_version(
+            conn.clone(),
+            correlation_id,
+            client_id,
+            find_coordinator_request.header.api_key,
+            find_coordinator_request.header.api_version,
+            min_version,
+        )
+        .await?;
+        coordinator = send_find_coordinator(&mut conn, &find_coordinator_request).await?;
+    }
+    if coordinator.error_code != KafkaCode::None {
+        return Err(Error::KafkaError(coordinator.error_code));
+    }
+
+    let host = std::str::from_utf8(coordinator.host.as_bytes()).map_err(|err| {
+        tracing::error!("Error converting from UTF8 {:?}", err);
+        Error::DecodingUtf8Error
+    })?;
+    Ok(BrokerAddress {
+        host: host.to_string(),
+        port: coordinator.port.try_into().map_err(|err| {
+            tracing::error!(
+                "Error decoding Broker connection port from metadata {:?}",
+                err
+            );
+            Error::MetadataNeedsSync
+        })?,
+    })
+}
+
+async fn send_find_coordinator(
+    conn: &mut impl BrokerConnection,
+    find_coordinator_request: &protocol::FindCoordinatorRequest<'_>,
+) -> Result<protocol::FindCoordinatorResponse> {
+    conn.send_request(find_coordinator_request).await?;
+
+    let find_coordinator_response = conn.receive_response().await?;
+    protocol::FindCoordinatorResponse::try_from_version(
+        find_coordinator_response.freeze(),
+        find_coordinator_request.header.api_version,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicI32, Ordering};
+    use std::sync::Arc;
+
+    use bytes::BufMut;
+
+    use super::*;
+    use crate::network::tcp::TcpConnection;
+    use crate::test_support::{api_key, api_version, broker_address, listen, put_string, serve};
+
+    /// Start a coordinator that answers the first FindCoordinator requests
+    /// with the given error, then points at itself.
+    async fn start_coordinator(
+        error_code: KafkaCode,
+        failing_requests: i32,
+    ) -> (Vec<BrokerAddress>, Arc<AtomicI32>) {
+        let (listener, port) = listen().await;
+        let requests = Arc::new(AtomicI32::new(0));
+
+        let counter = requests.clone();
+        serve(listener, move |request| {
+            assert_eq!(api_key(request), 10);
+
+            let mut body = vec![];
+            // v1 adds the throttle time and error message
+            let version = api_version(request);
+            if version >= 1 {
+                body.put_i32(0);
+            }
+            let error_message = |body: &mut Vec<u8>| {
+                if version >= 1 {
+                    body.put_i16(-1);
+                }
+            };
+            if counter.fetch_add(1, Ordering::SeqCst) < failing_requests {
+                body.put_i16(error_code as i16);
+                error_message(&mut body);
+                body.put_i32(-1); // node_id
+                body.put_i16(0); // host
+                body.put_i32(-1); // port
+            } else {
+                body.put_i16(0);
+                error_message(&mut body);
+                body.put_i32(1); // node_id
+                put_string(&mut body, "127.0.0.1");
+                body.put_i32(port as i32);
+            }
+            Some(body)
+        });
+
+        (vec![broker_address(port)], requests)
+    }
+
+    #[tokio::test]
+    async fn it_finds_the_coordinator_again_while_it_is_loading() {
+        let (addrs, requests) = start_coordinator(KafkaCode::GroupLoadInProgress, 1).await;
+
+        let result = ConsumerGroupBuilder::<TcpConnection>::new(
+            addrs,
+            "group".to_owned(),
+            TopicPartitions::default(),
+        )
+        .await
+        .unwrap()
+        .build()
+        .await;
+
+        assert!(result.is_ok());
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn it_gives_up_when_the_coordinator_stays_unavailable() {
+        let (addrs, requests) =
+            start_coordinator(KafkaCode::GroupCoordinatorNotAvailable, i32::MAX).await;
+
+        let result = ConsumerGroupBuilder::<TcpConnection>::new(
+            addrs,
+            "group".to_owned(),
+            TopicPartitions::default(),
+        )
+        .await
+        .unwrap()
+        .build()
+        .await;
+
+        assert!(matches!(
+            result,
+            Err(Error::KafkaError(KafkaCode::GroupCoordinatorNotAvailable))
+        ));
+        assert_eq!(
+            requests.load(Ordering::SeqCst),
+            MAX_COORDINATOR_RETRIES as i32 + 1
+        );
+    }
+
+    #[tokio::test]
+    async fn it_finds_the_coordinator_broker_of_a_group() {
+        let (addrs, _) = start_coordinator(KafkaCode::None, 0).await;
+
+        let conn = TcpConnection::new(addrs.clone()).await.unwrap();
+        let broker =
+            find_coordinator_broker(conn, 1, "client", "group", protocol::CoordinatorType::Group)
+                .await
+                .unwrap();
+
+        assert_eq!(broker, addrs[0]);
+    }
+
+    /// Start a coordinator that only supports version 0 of FindCoordinator,
+    /// recording the versions it is asked with.
+    async fn start_legacy_coordinator() -> (Vec<BrokerAddress>, Arc<std::sync::Mutex<Vec<i16>>>) {
+        let (listener, port) = listen().await;
+        let versions = Arc::new(std::sync::Mutex::new(vec![]));
+
+        let seen = versions.clone();
+        serve(listener, move |request| {
+            let version = api_version(request);
+            let mut body = vec![];
+            match api_key(request) {
+                10 if version > 0 => {
+                    seen.lock().unwrap().push(version);
+                    body.put_i32(0); // throttle_time_ms
+                    body.put_i16(KafkaCode::UnsupportedVersion as i16);
+                    body.put_i16(-1); // error_message
+                    body.put_i32(-1); // node_id
+                    body.put_i16(0); // host
+                    body.put_i32(-1); // port
+                }
+                10 => {
+                    seen.lock().unwrap().push(version);
+                    body.put_i16(0);
+                    body.put_i32(1); // node_id
+                    put_string(&mut body, "127.0.0.1");
+                    body.put_i32(port as i32);
+                }
+                18 => {
+                    body.put_i16(0);
+                    body.put_u8(2); // api_keys
+                    body.put_slice(&[0, 10, 0, 0, 0, 0, 0]);
+                    body.put_i32(0); // throttle_time_ms
+                    body.put_u8(0); // tagged fields
+                }
+                api_key => panic!("Unexpected api key {}", api_key),
+            }
+            Some(body)
+        });
+
+        (vec![broker_address(port)], versions)
+    }
+
+    #[tokio::test]
+    async fn it_downgrades_when_the_version_is_not_supported() {
+        let (addrs, versions) = start_legacy_coordinator().await;
+
+        let conn = TcpConnection::new(addrs.clone()).await.unwrap();
+        let broker =
+            find_coordinator_broker(conn, 1, "client", "group", protocol::CoordinatorType::Group)
+                .await
+                .unwrap();
+
+        assert_eq!(broker, addrs[0]);
+        assert_eq!(*versions.lock().unwrap(), vec![1, 0]);
+    }
+
+    #[tokio::test]
+    async fn it_does_not_downgrade_below_the_key_type() {
+        let (addrs, versions) = start_legacy_coordinator().await;
+
+        let conn = TcpConnection::new(addrs).await.unwrap();
+        let result = find_coordinator_broker(
+            conn,
+            1,
+            "client",
+            "transactional-id",
+            protocol::CoordinatorType::Transaction,
+        )
+        .await;
+
+        assert_eq!(
+            result,
+            Err(Error::KafkaError(KafkaCode::UnsupportedVersion))
+        );
+        assert_eq!(*versions.lock().unwrap(), vec![1]);
+    }
+
+    #[tokio::test]
+    async fn it_rejects_timeouts_that_are_not_positive() {
+        let builder = ConsumerGroupBuilder::<TcpConnection>::new(
+            vec![],
+            "group".to_owned(),
+            TopicPartitions::default(),
+        )
+        .await
+        .unwrap();
+
+        let result = builder.clone().session_timeout_ms(0).build().await;
+        assert!(matches!(result, Err(Error::ArgError(_))));
+        let result = builder.rebalance_timeout_ms(-1).build().await;
+        assert!(matches!(result, Err(Error::ArgError(_))));
+    }
+}
@@ -1,12 +1,13 @@
 //! Serialize data into the bytecode protocol.
 use bytes::{BufMut, Bytes};
 
-use crate::error::{Error, Result};
+use crate::error::Result;
 
 // Helper macro to safely convert an usize expression into a signed
-// integer.  If the conversion is not possible the macro issues a
-// `CodecError`, otherwise returns the expression
-// in the requested target type.
+// integer.  If the conversion is not possible the macro returns an
+// `EncodingError`, otherwise returns the expression
+// in the requested target type. Every length written to the wire
+// goes through it.
 macro_rules! try_usize_to_int {
     // ~ $ttype should actually be a 'ty' ... but rust complains for
     // some reason :/
@@ -16,10 +17,11 @@ macro_rules! try_usize_to_int {
         if (x as u64) <= (maxv as u64) {
             x as $ttype
         } else {
-            return Err(Error::EncodingError);
+            return Err($crate::error::Error::EncodingError);
         }
     }};
 }
+pub(crate) use try_usize_to_int;
 
 pub trait ToByte {
     fn encode<T: BufMut>(&self, buffer: &mut T) -> Result<()>;
@@ -93,6 +95,12 @@ impl ToByte for usize {
     }
 }
 
+/// The number of bytes the varint encoding of `n` takes.
+pub(crate) fn varint_len(n: usize) -> usize {
+    let bits = 64 - zigzag_encode(n).leading_zeros() as usize;
+    bits.div_ceil(7).max(1)
+}
+
 impl ToByte for str {
     fn encode<T: BufMut>(&self, buffer: &mut T) -> Result<()> {
         let l = try_usize_to_int!(self.len(), i16);
@@ -125,6 +133,48 @@ fn test_string_too_long() {
     assert!(buf.is_empty());
 }
 
+#[test]
+fn test_array_too_long() {
+    #[derive(Debug)]
+    struct Empty;
+
+    impl ToByte for Empty {
+        fn encode<T: BufMut>(&self, _buffer: &mut T) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    // `Empty` is zero-sized, so any length is valid for a dangling pointer
+    let xs: &[Empty] = unsafe {
+        std::slice::from_raw_parts(
+            std::ptr::NonNull::dangling().as_ptr(),
+            i32::MAX as usize + 1,
+        )
+    };
+    let mut buf = Vec::new();
+    match xs.encode(&mut buf) {
+        Err(crate::error::Error::EncodingError) => {}
+        _ => panic!(),
+    }
+    assert!(buf.is_empty());
+}
+
+#[test]
+fn test_compact_encoding() {
+    let mut buf = Vec::new();
+    CompactArray(&[CompactString("rust")])
+        .encode(&mut buf)
+        .unwrap();
+    CompactNullableString(None).encode(&mut buf).unwrap();
+    UnsignedVarint(300).encode(&mut buf).unwrap();
+    TaggedFields.encode(&mut buf).unwrap();
+    CompactBytes(&[7, 8]).encode(&mut buf).unwrap();
+    assert_eq!(
+        buf,
+        [2, 5, b'r', b'u', b's', b't', 0, 0xac, 0x02, 0, 3, 7, 8]
+    );
+}
+
 impl<V: ToByte> ToByte for [V] {
     fn encode<T: BufMut>(&self, buffer: &mut T) -> Result<()> {
         encode_as_array(buffer, self, |buffer, x| x.encode(buffer))
@@ -140,6 +190,17 @@ impl ToByte for [u8] {
     }
 }
 
+/// Fixed-width bytes written verbatim, without a length prefix, e.g. UUIDs
+/// or CRCs.
+pub struct RawBytes<'a>(pub &'a [u8]);
+
+impl ToByte for RawBytes<'_> {
+    fn encode<W: BufMut>(&self, buffer: &mut W) -> Result<()> {
+        buffer.put(self.0);
+        Ok(())
+    }
+}
+
 // ~ this allows to render a slice of various types (typically &str
 // and String) as strings
 pub struct AsStrings<'a, T>(pub &'a [T]);
@@ -166,6 +227,22 @@ where
     Ok(())
 }
 
+/// ~ Renders a nullable protocol array: a length of `-1` for `None`,
+/// otherwise the same as [`encode_as_array`].
+pub fn encode_nullable_array<T, F, W>(buffer: &mut W, xs: Option<&[T]>, f: F) -> Result<()>
+where
+    F: FnMut(&mut W, &T) -> Result<()>,
+    W: BufMut,
+{
+    match xs {
+        Some(xs) => encode_as_array(buffer, xs, f),
+        None => {
+            buffer.put_i32(-1);
+            Ok(())
+        }
+    }
+}
+
 fn _encode_struct_as_array<T, F, W>(buffer: &mut W, xs: &[T], mut f: F) -> Result<()>
 where
     T: ToByte,
@@ -180,6 +257,91 @@ where
     Ok(())
 }
 
+/// An unsigned varint, used for lengths in flexible versions of the protocol.
+pub struct UnsignedVarint(pub usize);
+
+impl ToByte for UnsignedVarint {
+    fn encode<W: BufMut>(&self, buffer: &mut W) -> Result<()> {
+        let mut n = self.0 as u64;
+
+        while n >= 0x80 {
+            buffer.put_u8(MSB | (n as u8));
+            n >>= 7;
+        }
+
+        buffer.put_u8(n as u8);
+
+        Ok(())
+    }
+}
+
+/// A string prefixed with its length + 1 as an unsigned varint.
+pub struct CompactString<'a>(pub &'a str);
+
+impl ToByte for CompactString<'_> {
+    fn encode<W: BufMut>(&self, buffer: &mut W) -> Result<()> {
+        UnsignedVarint(self.0.len() + 1).encode(buffer)?;
+        buffer.put(self.0.as_bytes());
+        Ok(())
+    }
+}
+
+/// A compact string where null is encoded as a length of 0.
+pub struct CompactNullableString<'a>(pub Option<&'a str>);
+
+impl ToByte for CompactNullableString<'_> {
+    fn encode<W: BufMut>(&self, buffer: &mut W) -> Result<()> {
+        match self.0 {
+            Some(s) => CompactString(s).encode(buffer),
+            None => UnsignedVarint(0).encode(buffer),
+        }
+    }
+}
+
+/// Bytes prefixed with their length + 1 as an unsigned varint.
+pub struct CompactBytes<'a>(pub &'a [u8]);
+
+impl ToByte for CompactBytes<'_> {
+    fn encode<W: BufMut>(&self, buffer: &mut W) -> Result<()> {
+        UnsignedVarint(self.0.len() + 1).encode(buffer)?;
+        buffer.put(self.0);
+        Ok(())
+    }
+}
+
+/// An array prefixed with its length + 1 as an unsigned varint.
+pub struct CompactArray<'a, T>(pub &'a [T]);
+
+impl<T: ToByte> ToByte for CompactArray<'_, T> {
+    fn encode<W: BufMut>(&self, buffer: &mut W) -> Result<()> {
+        encode_as_compact_array(buffer, self.0, |buffer, x| x.encode(buffer))
+    }
+}
+
+/// ~ Renders the length of `xs` to `buffer` as the start of a
+/// compact protocol array and then for each element of `xs` invokes `f`.
+pub fn encode_as_compact_array<T, F, W>(buffer: &mut W, xs: &[T], mut f: F) -> Result<()>
+where
+    F: FnMut(&mut W, &T) -> Result<()>,
+    W: BufMut,
+{
+    UnsignedVarint(xs.len() + 1).encode(buffer)?;
+    for x in xs {
+        f(buffer, x)?;
+    }
+    Ok(())
+}
+
+/// An empty set of tagged fields, which ends every structure in flexible
+/// versions of the protocol.
+pub struct TaggedFields;
+
+impl ToByte for TaggedFields {
+    fn encode<W: BufMut>(&self, buffer: &mut W) -> Result<()> {
+        UnsignedVarint(0).encode(buffer)
+    }
+}
+
 impl ToByte for Option<&[u8]> {
     fn encode<W: BufMut>(&self, buffer: &mut W) -> Result<()> {
         match *self {
@@ -217,6 +379,21 @@ impl ToByte for Option<String> {
     }
 }
 
+/// Nullable integers are sent as -1, which the protocol reserves for "not set"
+/// in fields such as the producer id, leader epoch or throttle time.
+impl ToByte for Option<i32> {
+    fn encode<W: BufMut>(&self, buffer: &mut W) -> Result<()> {
+        self.unwrap_or(-1).encode(buffer)
+    }
+}
+
+/// Encoded with the same -1 sentinel as `Option<i32>`.
+impl ToByte for Option<i64> {
+    fn encode<W: BufMut>(&self, buffer: &mut W) -> Result<()> {
+        self.unwrap_or(-1).encode(buffer)
+    }
+}
+
 #[test]
 fn codec_i8() {
     let mut buf = vec![];
@@ -257,6 +434,22 @@ fn codec_i64() {
     assert_eq!(buf, [0, 0, 0, 0, 0, 0, 0, 5]);
 }
 
+#[test]
+fn codec_nullable_integers() {
+    let mut buf = vec![];
+    Some(5_i32).encode(&mut buf).unwrap();
+    None::<i32>.encode(&mut buf).unwrap();
+    assert_eq!(buf, [0, 0, 0, 5, 255, 255, 255, 255]);
+
+    let mut buf = vec![];
+    Some(5_i64).encode(&mut buf).unwrap();
+    None::<i64>.encode(&mut buf).unwrap();
+    assert_eq!(
+        buf,
+        [0, 0, 0, 0, 0, 0, 0, 5, 255, 255, 255, 255, 255, 255, 255, 255]
+    );
+}
+
 #[test]
 fn codec_varint_simple() {
     let mut buf = vec![];
@@ -305,6 +498,68 @@ fn codec_vec_u8() {
     assert_eq!(buf, [0, 0, 0, 3, 1, 2, 3]);
 }
 
+#[test]
+fn codec_raw_bytes() {
+    let mut buf = vec![];
+    RawBytes(&[1, 2, 3]).encode(&mut buf).unwrap();
+    assert_eq!(buf, [1, 2, 3]);
+}
+
+#[test]
+fn codec_slice_i32() {
+    let mut buf = vec![];
+    let orig: &[i32] = &[1, 2, 3];
+
+    // Encode into buffer
+    orig.encode(&mut buf).unwrap();
+    assert_eq!(buf, [0, 0, 0, 3, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 3]);
+}
+
+#[test]
+fn codec_vec_i32() {
+    let mut buf = vec![];
+    let orig: Vec<i32> = vec![1, 2, 3];
+
+    // Encode into buffer
+    orig.encode(&mut buf).unwrap();
+    assert_eq!(buf, [0, 0, 0, 3, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 3]);
+}
+
+#[test]
+fn codec_vec_i16() {
+    let mut buf = vec![];
+    let orig: Vec<i16> = vec![1, 2, 3];
+
+    // Encode into buffer
+    orig.encode(&mut buf).unwrap();
+    assert_eq!(buf, [0, 0, 0, 3, 0, 1, 0, 2, 0, 3]);
+}
+
+#[test]
+fn codec_empty_vec_i32() {
+    let mut buf = vec![];
+    let orig: Vec<i32> = vec![];
+
+    // Encode into buffer
+    orig.encode(&mut buf).unwrap();
+    assert_eq!(buf, [0, 0, 0, 0]);
+}
+
+#[test]
+fn codec_nullable_array() {
+    let mut buf = vec![];
+    encode_nullable_array::<i32, _, _>(&mut buf, None, |buffer, x| x.encode(buffer)).unwrap();
+    assert_eq!(buf, [255, 255, 255, 255]);
+
+    let mut buf = vec![];
+    encode_nullable_array::<i32, _, _>(&mut buf, Some(&[]), |buffer, x| x.encode(buffer)).unwrap();
+    assert_eq!(buf, [0, 0, 0, 0]);
+
+    let mut buf = vec![];
+    encode_nullable_array(&mut buf, Some(&[1i16, 2]), |buffer, x| x.encode(buffer)).unwrap();
+    assert_eq!(buf, [0, 0, 0, 2, 0, 1, 0, 2]);
+}
+
 #[test]
 fn codec_as_strings() {
     macro_rules! enc_dec_cmp {
@@ -12,18 +12,43 @@ pub enum Error {
     NoConnectionForBroker(i32),
     /// The given topic and partition do not have a leader represented in the metadata.
     NoLeaderForTopicPartition(String, i32),
+    /// The given partition is out of range for the partition count of the topic in the metadata.
+    InvalidPartition(String, i32),
+    /// The key or value of a message to the given topic is larger than the
+    /// producer allows. Holds its size and the maximum in bytes.
+    MessageTooLarge(String, usize, usize),
     /// We could not encode the data into a bytestream correctly.
     EncodingError,
     /// An argument validation error.
     ArgError(String),
     /// An error in the network.
     IoError(io::ErrorKind),
+    /// The connection to the cluster was lost and could not be reopened
+    /// within the allowed reconnect attempts.
+    ConnectionClosed,
+    /// The producer was closed before a record handed to it was written.
+    ProducerClosed,
+    /// A newer producer with the same transactional id, or a newer epoch of
+    /// the producer id, fenced this producer. It cannot write anymore and has
+    /// to be replaced.
+    ProducerFenced,
     /// Error code provided by the kafka broker.
     KafkaError(KafkaCode),
     /// Could not decode bytes into valid UTF-8
     DecodingUtf8Error,
     /// Could not parse the data
     ParsingError(Bytes),
+    /// The records of a fetched batch decompress to more than the
+    /// configured maximum, see `max_decompressed_batch_bytes`.
+    DecompressedSizeExceeded,
+    /// A response arrived whose correlation id matches no request in flight.
+    UnknownCorrelationId(i32),
+    /// A record value could not be turned into the type a consumer asked for.
+    DeserializationError(String),
+    /// The position of a consumer in the given topic partition was below the
+    /// start of its log, the records in between were deleted before they were
+    /// read. Holds the position and the log start offset.
+    DataLoss(String, i32, i64, i64),
     MissingData(String),
     MetadataNeedsSync,
     AssignmentStrategyNotSupported(String),
@@ -161,8 +186,86 @@ pub enum KafkaCode {
     TopicAlreadyExists = 36,
     /// This is not the correct controller for this cluster.
     NotController = 41,
+    /// The producer attempted to use a producer epoch which is not the
+    /// current one, a newer producer fenced it.
+    InvalidProducerEpoch = 47,
+    /// The producer attempted a transactional operation in an invalid
+    /// state, e.g. ending a transaction it never started.
+    InvalidTxnState = 48,
+    /// The transaction timeout is larger than the `transaction.max.timeout.ms`
+    /// of the broker.
+    InvalidTransactionTimeout = 50,
+    /// The producer attempted to update a transaction while another
+    /// update of it was still going on.
+    ConcurrentTransactions = 51,
+    /// Security features are disabled, e.g. there is no authorizer to
+    /// manage ACLs with.
+    SecurityDisabled = 54,
     /// SASL Authentication failed.
     SaslAuthenticationFailed = 58,
+    /// The fetch session ID was not found, the broker evicted the session.
+    FetchSessionIdNotFound = 70,
+    /// The fetch session epoch is invalid.
+    InvalidFetchSessionEpoch = 71,
+    /// The leader epoch in the request is older than the epoch on the
+    /// broker.
+    FencedLeaderEpoch = 74,
+    /// The leader epoch in the request is newer than the epoch on the
+    /// broker.
+    UnknownLeaderEpoch = 75,
+    /// A newer producer with the same transactional id fenced this one.
+    ProducerFenced = 90,
+    /// This server does not host this topic ID, e.g. the topic was deleted
+    /// and created again under the same name.
+    UnknownTopicId = 100,
+}
+
+impl KafkaCode {
+    /// Whether the request may succeed if it is retried, as the error is
+    /// a transient condition of the cluster.
+    pub fn is_retriable(&self) -> bool {
+        matches!(
+            self,
+            KafkaCode::CorruptMessage
+                | KafkaCode::UnknownTopicOrPartition
+                | KafkaCode::LeaderNotAvailable
+                | KafkaCode::NotLeaderForPartition
+                | KafkaCode::RequestTimedOut
+                | KafkaCode::ReplicaNotAvailable
+                | KafkaCode::NetworkException
+                | KafkaCode::GroupLoadInProgress
+                | KafkaCode::GroupCoordinatorNotAvailable
+                | KafkaCode::NotCoordinatorForGroup
+                | KafkaCode::NotEnoughReplicas
+                | KafkaCode::NotEnoughReplicasAfterAppend
+                | KafkaCode::NotController
+                | KafkaCode::ConcurrentTransactions
+                | KafkaCode::FencedLeaderEpoch
+                | KafkaCode::UnknownLeaderEpoch
+                | KafkaCode::UnknownTopicId
+        )
+    }
+
+    /// Whether a newer instance of the producer fenced it, so it must not
+    /// retry nor write again.
+    pub fn is_producer_fenced(&self) -> bool {
+        matches!(
+            self,
+            KafkaCode::InvalidProducerEpoch | KafkaCode::ProducerFenced
+        )
+    }
+
+    /// Whether the group or transaction coordinator is loading its state, not
+    /// available or has moved, so it has to be looked up again before
+    /// retrying.
+    pub fn is_coordinator_error(&self) -> bool {
+        matches!(
+            self,
+            KafkaCode::GroupLoadInProgress
+                | KafkaCode::GroupCoordinatorNotAvailable
+                | KafkaCode::NotCoordinatorForGroup
+        )
+    }
 }
 
 #[cfg(feature = "redpanda")]
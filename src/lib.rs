@@ -265,6 +265,7 @@
 
 mod admin;
 mod assignor;
+mod clock;
 mod consumer;
 mod consumer_builder;
 mod consumer_group;
@@ -274,9 +275,12 @@ mod error;
 mod metadata;
 mod network;
 mod parser;
+mod partitioner;
 mod producer;
 mod producer_builder;
 mod protocol;
+#[cfg(test)]
+mod test_support;
 mod utils;
 
 #[cfg(feature = "redpanda")]
@@ -659,27 +663,53 @@ pub mod prelude {
     //! while (output_stream.next().await).is_some() {}
     //! ```
     //!
-    pub use crate::admin::{create_topics, delete_topics};
+    pub use crate::admin::{
+        api_versions, await_topic_ready, create_acls, create_topics, create_topics_with_specs,
+        delete_acls, delete_topics, delete_topics_by_id, describe_acls, describe_producers,
+        describe_transactions, ensure_topics, list_transactions, TopicSpec,
+    };
     pub use crate::assignor::ROUND_ROBIN_PROTOCOL;
+    #[cfg(any(test, feature = "test-internals"))]
+    pub use crate::clock::MockClock;
+    pub use crate::clock::{Clock, TokioClock};
     pub use crate::consumer::{
-        commit_offset, fetch, ConsumeMessage, Consumer, PartitionOffsets, TopicPartitions,
+        build_fetch_request, commit_offset, commit_offsets, fetch, tail, ConsumeMessage, Consumer,
+        ConsumerHealth, FetchBatch, OffsetReset, PartitionOffsets, TopicPartition, TopicPartitions,
         TopicPartitionsBuilder,
     };
-    pub use crate::consumer_builder::{fetch_offset, list_offsets, ConsumerBuilder};
+    pub use crate::consumer_builder::{
+        fetch_committed_offsets, fetch_offset, list_offsets, list_topic_offsets, ConsumerBuilder,
+    };
     pub use crate::consumer_group::{
         heartbeat, join_group, leave_group, sync_group, ConsumerGroup,
     };
-    pub use crate::consumer_group_builder::{find_coordinator, ConsumerGroupBuilder};
+    pub use crate::consumer_group_builder::{
+        find_coordinator, find_coordinator_broker, ConsumerGroupBuilder, GroupCoordinators,
+    };
     pub use crate::error::{Error, KafkaCode, Result};
-    pub use crate::metadata::ClusterMetadata;
+    pub use crate::metadata::{ClusterMetadata, ReplicationState};
+    #[cfg(feature = "tls")]
+    pub use crate::network::tls::{
+        SaslTlsConfig, SaslTlsConnection, TlsConnection, TlsConnectionOptions,
+    };
     pub use crate::network::{
-        sasl::{do_sasl, SaslConfig},
+        boxed::{BoxedConnection, BoxedConnectionConfig},
+        sasl::{
+            do_sasl, do_sasl_with_mechanism, OAuthBearer, SaslConfig, SaslMechanism, SaslResponse,
+        },
         tcp::{SaslTcpConfig, SaslTcpConnection, TcpConnection},
-        tls::{SaslTlsConfig, SaslTlsConnection, TlsConnection, TlsConnectionOptions},
-        BrokerAddress, BrokerConnection,
+        BrokerAddress, BrokerConnection, SocketOptions,
+    };
+    pub use crate::producer::{
+        add_partitions_to_txn, build_produce_request, end_txn, init_producer_id, produce,
+        transaction_coordinator, DeliveryReport, Interceptor, ProduceMessage, Producer,
     };
-    pub use crate::producer::{produce, ProduceMessage, Producer};
     pub use crate::producer_builder::ProducerBuilder;
+    pub use crate::protocol::acl::{
+        AclBinding, AclBindingFilter, AclOperation, PatternType, PermissionType, ResourceType,
+    };
+    pub use crate::protocol::produce::request::{RecordBatchAttributes, TimestampType};
+    pub use crate::protocol::CoordinatorType;
     /// Message Header.
     pub use crate::protocol::Header;
 
@@ -690,6 +720,11 @@ pub mod prelude {
         pub use crate::encode::*;
     }
 
+    pub mod parser {
+        //! Deserialize data from the bytecode protocol.
+        pub use crate::parser::*;
+    }
+
     pub mod protocol {
         //! Bytecode protocol requests & responses.
         //!
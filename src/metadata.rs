@@ -1,15 +1,25 @@
 //! Cluster metadata & operations.
-use std::{collections::HashMap, fmt::Debug};
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    sync::{Arc, Mutex},
+};
 
 use nom::AsBytes;
 use tracing::instrument;
 
 use crate::{
-    error::{Error, Result},
+    error::{Error, KafkaCode, Result},
     network::{BrokerAddress, BrokerConnection},
     protocol::{self, metadata::response::*},
+    utils::retry_backoff,
 };
 
+/// How many times to try reconnecting to the cluster by default.
+pub const DEFAULT_MAX_RECONNECT_ATTEMPTS: Option<u32> = Some(5);
+/// How many times to fetch metadata again while partitions have no leader.
+const MAX_LEADERLESS_RETRIES: usize = 3;
+
 /// Cluster metadata & operations.
 #[derive(Clone, Default, Debug)]
 pub struct ClusterMetadata<T: BrokerConnection> {
@@ -21,10 +31,39 @@ pub struct ClusterMetadata<T: BrokerConnection> {
     pub client_id: String,
     pub topic_names: Vec<String>,
     pub controller_id: i32,
+    /// How many times to try reconnecting before giving up, `None` to keep
+    /// trying forever.
+    pub max_reconnect_attempts: Option<u32>,
+    /// The version of the Metadata requests, from version 10 the topic ids
+    /// are known.
+    pub metadata_version: i16,
+    /// The connection to the transaction coordinator of each transactional
+    /// id, shared by the clones of the metadata until the coordinator moves.
+    pub transaction_coordinators: Arc<Mutex<HashMap<String, T>>>,
 }
 
 type TopicPartition = HashMap<String, Vec<i32>>;
 
+/// Where the replicas of a partition are and which of them keep up.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReplicationState {
+    /// The id of the leader broker, -1 while there is none.
+    pub leader: i32,
+    /// Every broker hosting the partition.
+    pub replicas: Vec<i32>,
+    /// The brokers in sync with the leader.
+    pub isr: Vec<i32>,
+    /// The brokers hosting the partition that are offline.
+    pub offline_replicas: Vec<i32>,
+}
+
+impl ReplicationState {
+    /// Whether some replicas fell out of sync with the leader.
+    pub fn is_under_replicated(&self) -> bool {
+        self.isr.len() < self.replicas.len()
+    }
+}
+
 impl<'a, T: BrokerConnection + Clone + Debug> ClusterMetadata<T> {
     pub async fn new(
         connection_params: T::ConnConfig,
@@ -42,6 +81,9 @@ impl<'a, T: BrokerConnection + Clone + Debug> ClusterMetadata<T> {
             correlation_id,
             client_id,
             topic_names: topics,
+            max_reconnect_attempts: DEFAULT_MAX_RECONNECT_ATTEMPTS,
+            metadata_version: protocol::metadata::request::API_VERSION,
+            transaction_coordinators: Arc::new(Mutex::new(HashMap::new())),
         };
         let bootstrap_connection = T::new(connection_params).await?;
 
@@ -67,6 +109,83 @@ impl<'a, T: BrokerConnection + Clone + Debug> ClusterMetadata<T> {
             .find(|b| b.partition_index == partition_id)
     }
 
+    /// The number of partitions of a topic, if the topic is in the metadata.
+    pub fn get_partition_count(&self, topic_name: &'a str) -> Option<usize> {
+        let topic = self.topics.iter().find(|t| t.name == topic_name)?;
+        Some(topic.partitions.len())
+    }
+
+    /// The partitions of a topic that have a leader, in order, or all of
+    /// them while none has. `None` if the topic is not in the metadata.
+    pub fn get_available_partitions(&self, topic_name: &'a str) -> Option<Vec<i32>> {
+        let topic = self.topics.iter().find(|t| t.name == topic_name)?;
+        let mut partitions: Vec<i32> = topic
+            .partitions
+            .iter()
+            .filter(|partition| partition.has_leader())
+            .map(|partition| partition.partition_index)
+            .collect();
+        if partitions.is_empty() {
+            partitions = topic
+                .partitions
+                .iter()
+                .map(|partition| partition.partition_index)
+                .collect();
+        }
+        partitions.sort();
+        Some(partitions)
+    }
+
+    pub fn get_leader_epoch_for_topic_partition(
+        &self,
+        topic_name: &'a str,
+        partition_id: i32,
+    ) -> Option<i32> {
+        let partition = self.get_topic_partition_by_id(topic_name, partition_id)?;
+        Some(partition.leader_epoch)
+    }
+
+    /// The id of a topic, if the metadata was fetched with a version that
+    /// has topic ids.
+    pub fn get_topic_id(&self, topic_name: &'a str) -> Option<[u8; 16]> {
+        let topic = self.topics.iter().find(|t| t.name == topic_name)?;
+        (topic.topic_id != [0; 16]).then_some(topic.topic_id)
+    }
+
+    /// The replicas of a partition as of the last metadata fetched.
+    pub fn get_replication_state(
+        &self,
+        topic_name: &'a str,
+        partition_id: i32,
+    ) -> Option<ReplicationState> {
+        let partition = self.get_topic_partition_by_id(topic_name, partition_id)?;
+        Some(ReplicationState {
+            leader: partition.leader_id,
+            replicas: partition.replica_nodes.clone(),
+            isr: partition.isr_nodes.clone(),
+            offline_replicas: partition.offline_replicas.clone(),
+        })
+    }
+
+    /// Fetch the latest metadata and return the replicas of a partition,
+    /// e.g. to alert on partitions that are under-replicated.
+    ///
+    /// The topic is tracked from now on if it was not yet. Returns `None`
+    /// when the topic has no such partition.
+    pub async fn partition_replication_state(
+        &mut self,
+        topic_name: &'a str,
+        partition_id: i32,
+    ) -> Result<Option<ReplicationState>> {
+        if self.topic_names.iter().any(|topic| topic == topic_name) {
+            self.refresh().await?;
+        } else {
+            self.add_topics(&[topic_name.to_owned()]).await?;
+        }
+
+        Ok(self.get_replication_state(topic_name, partition_id))
+    }
+
     pub fn get_leader_id_for_cluster(&self) -> i32 {
         self.controller_id
     }
@@ -77,6 +196,15 @@ impl<'a, T: BrokerConnection + Clone + Debug> ClusterMetadata<T> {
         partition_id: i32,
     ) -> Option<i32> {
         let partition = self.get_topic_partition_by_id(topic_name, partition_id)?;
+        if !partition.has_leader() {
+            tracing::debug!(
+                "No leader for topic {} and partition {} ({:?})",
+                topic_name,
+                partition_id,
+                partition.error_code
+            );
+            return None;
+        }
         let leader = self.get_broker_by_id(partition.leader_id)?;
         tracing::trace!(
             "Leader is {:?} for topic {} and partition {}",
@@ -113,29 +241,172 @@ impl<'a, T: BrokerConnection + Clone + Debug> ClusterMetadata<T> {
     #[instrument(name = "metadata-fetch")]
     pub async fn fetch(&mut self, mut conn: T) -> Result<()> {
         tracing::debug!("Fetching metadata");
-        let metadata_request =
-            protocol::MetadataRequest::new(self.correlation_id, &self.client_id, &self.topic_names);
-        conn.send_request(&metadata_request).await?;
-
-        let response_bytes = conn.receive_response().await?;
-        let metadata_response = protocol::MetadataResponse::try_from(response_bytes.freeze())?;
-
-        metadata_response.is_error()?;
+        let mut attempts = 0;
+        let metadata_response = loop {
+            let mut metadata_request = protocol::MetadataRequest::new(
+                self.correlation_id,
+                &self.client_id,
+                &self.topic_names,
+            );
+            metadata_request.header.api_version = self.metadata_version;
+            conn.send_request(&metadata_request).await?;
+
+            let response_bytes = conn.receive_response().await?;
+            let metadata_response = protocol::MetadataResponse::try_from_version(
+                response_bytes.freeze(),
+                self.metadata_version,
+            )?;
+
+            // a leader is usually elected shortly, ask again rather than
+            // caching partitions that cannot be routed to
+            let leaderless = leaderless_partitions(&metadata_response.topics);
+            if leaderless.is_empty() || attempts >= MAX_LEADERLESS_RETRIES {
+                break metadata_response;
+            }
+            attempts += 1;
+            let backoff = retry_backoff(attempts as u32);
+            tracing::warn!(
+                "No leader for {:?}, fetching metadata again in {:?}",
+                leaderless,
+                backoff
+            );
+            tokio::time::sleep(backoff).await;
+        };
 
-        self.topics = metadata_response.topics.clone();
-        self.brokers = metadata_response.brokers;
-        self.controller_id = metadata_response.controller_id;
+        // partitions that are still without a leader are kept, so the
+        // partition count stays right, but are not routed to
+        for topic in &metadata_response.topics {
+            if topic.error_code == KafkaCode::LeaderNotAvailable {
+                continue;
+            }
+            let mut topic = topic.clone();
+            topic.partitions.retain(Partition::has_leader);
+            topic.is_error()?;
+        }
 
         // insert topic names into self.topic_names
         for topic in &metadata_response.topics {
             let vec = topic.name.to_vec();
             let name = String::from_utf8(vec).map_err(|_| Error::DecodingUtf8Error)?;
-            self.topic_names.push(name);
+            if !self.topic_names.contains(&name) {
+                self.topic_names.push(name);
+            }
         }
 
+        self.topics = metadata_response
+            .topics
+            .into_iter()
+            .map(|topic| self.keep_newer_leader_epochs(topic))
+            .collect();
+        self.brokers = metadata_response.brokers;
+        self.controller_id = metadata_response.controller_id;
+
         Ok(())
     }
 
+    /// Fetch the latest metadata from the cluster and reconnect to its brokers.
+    ///
+    /// Used when a broker tells us our view of a partition leader is out of date.
+    /// Network errors are retried with an exponential backoff, up to
+    /// [`max_reconnect_attempts`](Self::max_reconnect_attempts) attempts, after
+    /// which this fails with [`Error::ConnectionClosed`].
+    pub async fn refresh(&mut self) -> Result<()> {
+        let mut attempt = 1;
+        loop {
+            tracing::debug!("Refreshing metadata (attempt {})", attempt);
+            match self.reconnect().await {
+                Err(Error::IoError(kind)) => {
+                    if self
+                        .max_reconnect_attempts
+                        .is_some_and(|max_attempts| attempt >= max_attempts)
+                    {
+                        tracing::error!(
+                            "Giving up reconnecting to the cluster after {} attempts",
+                            attempt
+                        );
+                        return Err(Error::ConnectionClosed);
+                    }
+                    let backoff = retry_backoff(attempt);
+                    tracing::warn!(
+                        "Reconnecting to the cluster failed with {:?}, retrying in {:?}",
+                        kind,
+                        backoff
+                    );
+                    tokio::time::sleep(backoff).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Start keeping track of topics that are not known yet, fetching their
+    /// metadata from the cluster. Topics that are already known are skipped.
+    ///
+    /// If the metadata cannot be fetched the new topics are forgotten again,
+    /// so an unknown topic does not break later refreshes.
+    pub async fn add_topics(&mut self, topics: &[String]) -> Result<()> {
+        let mut new_topics = vec![];
+        for topic in topics {
+            if !self.topic_names.contains(topic) && !new_topics.contains(topic) {
+                new_topics.push(topic.to_owned());
+            }
+        }
+        if new_topics.is_empty() {
+            return Ok(());
+        }
+
+        tracing::debug!("Fetching metadata for new topics {:?}", new_topics);
+        self.topic_names.extend(new_topics.iter().cloned());
+        if let Err(err) = self.refresh().await {
+            self.topic_names.retain(|topic| !new_topics.contains(topic));
+            return Err(err);
+        }
+
+        Ok(())
+    }
+
+    async fn reconnect(&mut self) -> Result<()> {
+        let bootstrap_connection = T::new(self.connection_params.clone()).await?;
+
+        self.fetch(bootstrap_connection).await?;
+        self.sync().await
+    }
+
+    /// A broker that has not caught up yet can hand back metadata older than
+    /// what we already know. Keep any partition whose leader epoch is newer
+    /// than the one we were just given.
+    fn keep_newer_leader_epochs(&self, mut topic: Topic) -> Topic {
+        let Some(existing_topic) = self.topics.iter().find(|t| t.name == topic.name) else {
+            return topic;
+        };
+        if existing_topic.topic_id != topic.topic_id {
+            // the topic was created again, its epochs start over
+            return topic;
+        }
+
+        for partition in topic.partitions.iter_mut() {
+            let existing_partition = existing_topic
+                .partitions
+                .iter()
+                .find(|p| p.partition_index == partition.partition_index);
+            if let Some(existing_partition) = existing_partition {
+                if existing_partition.leader_epoch > partition.leader_epoch {
+                    tracing::debug!(
+                        "Ignoring stale leader epoch {} for topic {:?} partition {}, keeping {}",
+                        partition.leader_epoch,
+                        topic.name,
+                        partition.partition_index,
+                        existing_partition.leader_epoch
+                    );
+                    *partition = existing_partition.clone();
+                }
+            }
+        }
+
+        topic
+    }
+
     pub fn get_connections_for_topic_partitions(
         &'a self,
         topic_partitions: &TopicPartition,
@@ -194,10 +465,7 @@ impl<'a, T: BrokerConnection + Clone + Debug> ClusterMetadata<T> {
                 // Do we have this topic already?
                 if let Some(existing_partitions) = broker_ownership.get_mut(&new_topic_name) {
                     // Don't push the partition on more than once
-                    if !existing_partitions
-                        .iter()
-                        .any(|existing_partition| *existing_partition == *new_partition)
-                    {
+                    if !existing_partitions.contains(new_partition) {
                         existing_partitions.push(*new_partition);
                     }
                 } else {
@@ -214,6 +482,24 @@ impl<'a, T: BrokerConnection + Clone + Debug> ClusterMetadata<T> {
     }
 }
 
+/// The topic partitions that are waiting for a leader, with a partition of -1
+/// for topics that are still being created.
+fn leaderless_partitions(topics: &[Topic]) -> Vec<(String, i32)> {
+    let mut leaderless = vec![];
+    for topic in topics {
+        let name = String::from_utf8_lossy(&topic.name).to_string();
+        if topic.error_code == KafkaCode::LeaderNotAvailable {
+            leaderless.push((name.clone(), -1));
+        }
+        for partition in topic.partitions.iter() {
+            if !partition.has_leader() {
+                leaderless.push((name.clone(), partition.partition_index));
+            }
+        }
+    }
+    leaderless
+}
+
 impl Broker {
     pub fn addr(&self) -> Result<BrokerAddress> {
         let host = std::str::from_utf8(self.host.as_bytes()).map_err(|err| {
@@ -235,12 +521,17 @@ impl Broker {
 
 #[cfg(test)]
 mod test {
-    use bytes::Bytes;
+    use std::sync::atomic::{AtomicI32, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use bytes::{BufMut, Bytes};
 
     use super::*;
     use crate::{
         error::KafkaCode,
         network::{tcp::TcpConnection, BrokerAddress},
+        test_support::{api_key, broker_address, listen, put_string, serve},
     };
 
     macro_rules! test_metadata {
@@ -255,6 +546,9 @@ mod test {
                 correlation_id: 1,
                 client_id: String::from("client_id"),
                 controller_id: 1,
+                max_reconnect_attempts: DEFAULT_MAX_RECONNECT_ATTEMPTS,
+                metadata_version: protocol::metadata::request::API_VERSION,
+                transaction_coordinators: Default::default(),
                 brokers: vec![
                     Broker {
                         node_id: 1,
@@ -272,35 +566,44 @@ mod test {
                 topics: vec![Topic {
                     error_code: KafkaCode::None,
                     name: Bytes::from("purchases"),
+                    topic_id: [0; 16],
                     is_internal: false,
                     partitions: vec![
                         Partition {
                             error_code: KafkaCode::None,
                             partition_index: 0,
                             leader_id: 2,
+                            leader_epoch: 1,
                             replica_nodes: vec![2],
                             isr_nodes: vec![2],
+                            offline_replicas: vec![],
                         },
                         Partition {
                             error_code: KafkaCode::None,
                             partition_index: 1,
                             leader_id: 1,
+                            leader_epoch: 1,
                             replica_nodes: vec![1],
                             isr_nodes: vec![1],
+                            offline_replicas: vec![],
                         },
                         Partition {
                             error_code: KafkaCode::None,
                             partition_index: 2,
                             leader_id: 2,
+                            leader_epoch: 1,
                             replica_nodes: vec![2],
                             isr_nodes: vec![2],
+                            offline_replicas: vec![],
                         },
                         Partition {
                             error_code: KafkaCode::None,
                             partition_index: 3,
                             leader_id: 1,
+                            leader_epoch: 1,
                             replica_nodes: vec![1],
                             isr_nodes: vec![1],
+                            offline_replicas: vec![],
                         },
                     ],
                 }],
@@ -385,4 +688,184 @@ mod test {
             &HashMap::from([(String::from("purchases"), vec![0, 2])])
         );
     }
+
+    #[test]
+    fn test_partition_leader_epoch() {
+        let cluster: ClusterMetadata<TcpConnection> = test_metadata!();
+
+        let epoch = cluster.get_leader_epoch_for_topic_partition("purchases", 1);
+
+        assert_eq!(epoch, Some(1));
+        assert_eq!(
+            cluster.get_leader_epoch_for_topic_partition("purchases", 9),
+            None
+        );
+    }
+
+    #[test]
+    fn test_keep_newer_leader_epochs() {
+        let cluster: ClusterMetadata<TcpConnection> = test_metadata!();
+        let mut topic = cluster.topics[0].clone();
+        // partition 0 moved to a new leader, partition 1 is from a lagging broker
+        topic.partitions[0].leader_id = 1;
+        topic.partitions[0].leader_epoch = 2;
+        topic.partitions[1].leader_id = 2;
+        topic.partitions[1].leader_epoch = 0;
+
+        let topic = cluster.keep_newer_leader_epochs(topic);
+
+        assert_eq!(topic.partitions[0].leader_id, 1);
+        assert_eq!(topic.partitions[0].leader_epoch, 2);
+        assert_eq!(topic.partitions[1], cluster.topics[0].partitions[1]);
+    }
+
+    #[tokio::test]
+    async fn test_refresh_gives_up_after_max_reconnect_attempts() {
+        // a dead broker hangs up on every connection
+        let (listener, port) = listen().await;
+        let broker = tokio::spawn(async move {
+            let mut connections = 0;
+            while let Ok(Ok((socket, _))) =
+                tokio::time::timeout(Duration::from_secs(2), listener.accept()).await
+            {
+                drop(socket);
+                connections += 1;
+            }
+            connections
+        });
+
+        let mut cluster: ClusterMetadata<TcpConnection> = test_metadata!();
+        cluster.connection_params = vec![broker_address(port)];
+        cluster.max_reconnect_attempts = Some(3);
+
+        assert_eq!(cluster.refresh().await, Err(Error::ConnectionClosed));
+        assert_eq!(broker.await.unwrap(), 3);
+    }
+
+    /// Start a broker leading both partitions of "purchases", except for
+    /// the first metadata responses, in which partition 1 has no leader.
+    async fn start_broker(leaderless_responses: i32) -> (Vec<BrokerAddress>, Arc<AtomicI32>) {
+        let (listener, port) = listen().await;
+        let requests = Arc::new(AtomicI32::new(0));
+
+        let counter = requests.clone();
+        serve(listener, move |request| {
+            assert_eq!(api_key(request), 3);
+            let leaderless = counter.fetch_add(1, Ordering::SeqCst) < leaderless_responses;
+
+            let mut body = vec![];
+            body.put_i32(0); // throttle_time_ms
+            body.put_i32(1);
+            body.put_i32(1); // node_id
+            put_string(&mut body, "127.0.0.1");
+            body.put_i32(port as i32);
+            body.put_i16(-1); // rack
+            body.put_i16(-1); // cluster_id
+            body.put_i32(1); // controller_id
+            body.put_i32(1);
+            body.put_i16(0);
+            put_string(&mut body, "purchases");
+            body.put_i8(0); // is_internal
+            body.put_i32(2);
+            for partition in 0..2 {
+                if partition == 1 && leaderless {
+                    body.put_i16(KafkaCode::LeaderNotAvailable as i16);
+                    body.put_i32(partition);
+                    body.put_i32(-1); // leader_id
+                } else {
+                    body.put_i16(0);
+                    body.put_i32(partition);
+                    body.put_i32(1); // leader_id
+                }
+                body.put_i32(1); // leader_epoch
+                body.put_slice(&[0, 0, 0, 1, 0, 0, 0, 1]); // replica_nodes
+                body.put_slice(&[0, 0, 0, 1, 0, 0, 0, 1]); // isr_nodes
+                body.put_i32(0); // offline_replicas
+            }
+            Some(body)
+        });
+
+        (vec![broker_address(port)], requests)
+    }
+
+    #[tokio::test]
+    async fn test_partition_replication_state() {
+        let (addrs, requests) = start_broker(0).await;
+        let mut cluster = ClusterMetadata::<TcpConnection>::new(
+            addrs,
+            1,
+            "client_id".to_owned(),
+            vec!["purchases".to_owned()],
+        )
+        .await
+        .unwrap();
+
+        let state = cluster
+            .partition_replication_state("purchases", 0)
+            .await
+            .unwrap()
+            .unwrap();
+
+        // a single replica is its own leader and the whole ISR
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+        assert_eq!(state.replicas, vec![state.leader]);
+        assert_eq!(state.isr, vec![state.leader]);
+        assert!(state.offline_replicas.is_empty());
+        assert!(!state.is_under_replicated());
+        assert_eq!(
+            cluster
+                .partition_replication_state("purchases", 2)
+                .await
+                .unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fetch_again_while_a_partition_has_no_leader() {
+        let (addrs, requests) = start_broker(1).await;
+
+        let cluster = ClusterMetadata::<TcpConnection>::new(
+            addrs,
+            1,
+            "client_id".to_owned(),
+            vec!["purchases".to_owned()],
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+        assert_eq!(
+            cluster.get_leader_id_for_topic_partition("purchases", 1),
+            Some(1)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_do_not_route_to_a_partition_without_leader() {
+        let (addrs, requests) = start_broker(i32::MAX).await;
+
+        let cluster = ClusterMetadata::<TcpConnection>::new(
+            addrs,
+            1,
+            "client_id".to_owned(),
+            vec!["purchases".to_owned()],
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            requests.load(Ordering::SeqCst),
+            MAX_LEADERLESS_RETRIES as i32 + 1
+        );
+        assert_eq!(cluster.get_partition_count("purchases"), Some(2));
+        assert_eq!(
+            cluster.get_leader_id_for_topic_partition("purchases", 0),
+            Some(1)
+        );
+        assert_eq!(
+            cluster.get_leader_id_for_topic_partition("purchases", 1),
+            None
+        );
+    }
 }
@@ -0,0 +1,151 @@
+//! Connection whose transport is picked at runtime.
+
+use std::fmt::Debug;
+
+use async_trait::async_trait;
+use bytes::{BufMut, BytesMut};
+
+#[cfg(feature = "tls")]
+use super::tls::{SaslTlsConfig, SaslTlsConnection, TlsConnection, TlsConnectionOptions};
+use super::{
+    tcp::{SaslTcpConfig, SaslTcpConnection, TcpConnection},
+    BrokerAddress, BrokerConnection,
+};
+use crate::{encode::ToByte, error::Result};
+
+/// Options of a [`BoxedConnection`], one variant per transport.
+#[derive(Clone, Debug)]
+pub enum BoxedConnectionConfig {
+    Tcp(Vec<BrokerAddress>),
+    SaslTcp(SaslTcpConfig),
+    #[cfg(feature = "tls")]
+    Tls(TlsConnectionOptions),
+    #[cfg(feature = "tls")]
+    SaslTls(SaslTlsConfig),
+}
+
+/// Connection to a Kafka/Redpanda broker over any transport.
+///
+/// The builders are generic over the connection type, which has to be known
+/// at compile time. `BrokerConnection` itself can not be made into a trait
+/// object, so this type boxes the connection instead. It lets an application
+/// choose TCP or TLS from its configuration, and store connections of
+/// different transports side by side.
+///
+/// ### Example
+/// ```rust
+/// let options = if use_tls {
+///     BoxedConnectionConfig::Tls(tls_options)
+/// } else {
+///     BoxedConnectionConfig::Tcp(bootstrap_addrs)
+/// };
+///
+/// let producer = ProducerBuilder::<BoxedConnection>::new(options, vec![topic_name])
+///     .await?
+///     .build()
+///     .await;
+/// ```
+#[derive(Debug)]
+pub struct BoxedConnection(Box<dyn DynConnection>);
+
+impl BoxedConnection {
+    /// Box an open connection.
+    pub fn new<T>(conn: T) -> Self
+    where
+        T: BrokerConnection + Clone + Debug + Send + Sync + 'static,
+    {
+        Self(Box::new(conn))
+    }
+}
+
+impl Clone for BoxedConnection {
+    fn clone(&self) -> Self {
+        Self(self.0.clone_box())
+    }
+}
+
+/// A request that was already serialized, so it can cross the trait object.
+struct Encoded(Vec<u8>);
+
+impl ToByte for Encoded {
+    fn encode<W: BufMut>(&self, buffer: &mut W) -> Result<()> {
+        buffer.put_slice(&self.0);
+        Ok(())
+    }
+}
+
+/// The object safe part of [`BrokerConnection`].
+#[async_trait]
+trait DynConnection: Debug + Send + Sync {
+    async fn send_encoded(&mut self, req: &Encoded) -> Result<()>;
+    async fn receive_response(&mut self) -> Result<BytesMut>;
+    async fn close(self: Box<Self>) -> Result<()>;
+    fn clone_box(&self) -> Box<dyn DynConnection>;
+}
+
+#[async_trait]
+impl<T> DynConnection for T
+where
+    T: BrokerConnection + Clone + Debug + Send + Sync + 'static,
+{
+    async fn send_encoded(&mut self, req: &Encoded) -> Result<()> {
+        self.send_request(req).await
+    }
+
+    async fn receive_response(&mut self) -> Result<BytesMut> {
+        BrokerConnection::receive_response(self).await
+    }
+
+    async fn close(self: Box<Self>) -> Result<()> {
+        BrokerConnection::close(*self).await
+    }
+
+    fn clone_box(&self) -> Box<dyn DynConnection> {
+        Box::new(self.clone())
+    }
+}
+
+#[async_trait]
+impl BrokerConnection for BoxedConnection {
+    type ConnConfig = BoxedConnectionConfig;
+
+    async fn send_request<R: ToByte + Sync + Send>(&mut self, req: &R) -> Result<()> {
+        let mut buffer = vec![];
+        req.encode(&mut buffer)?;
+        self.0.send_encoded(&Encoded(buffer)).await
+    }
+
+    async fn receive_response(&mut self) -> Result<BytesMut> {
+        self.0.receive_response().await
+    }
+
+    async fn close(self) -> Result<()> {
+        self.0.close().await
+    }
+
+    async fn new(p: Self::ConnConfig) -> Result<Self> {
+        Ok(match p {
+            BoxedConnectionConfig::Tcp(p) => Self::new(TcpConnection::new(p).await?),
+            BoxedConnectionConfig::SaslTcp(p) => Self::new(SaslTcpConnection::new(p).await?),
+            #[cfg(feature = "tls")]
+            BoxedConnectionConfig::Tls(p) => Self::new(TlsConnection::new(p).await?),
+            #[cfg(feature = "tls")]
+            BoxedConnectionConfig::SaslTls(p) => Self::new(SaslTlsConnection::new(p).await?),
+        })
+    }
+
+    async fn from_addr(p: Self::ConnConfig, addr: BrokerAddress) -> Result<Self> {
+        Ok(match p {
+            BoxedConnectionConfig::Tcp(p) => Self::new(TcpConnection::from_addr(p, addr).await?),
+            BoxedConnectionConfig::SaslTcp(p) => {
+                Self::new(SaslTcpConnection::from_addr(p, addr).await?)
+            }
+            #[cfg(feature = "tls")]
+            BoxedConnectionConfig::Tls(p) => Self::new(TlsConnection::from_addr(p, addr).await?),
+            #[cfg(feature = "tls")]
+            BoxedConnectionConfig::SaslTls(p) => {
+                Self::new(SaslTlsConnection::from_addr(p, addr).await?)
+            }
+        })
+    }
+}
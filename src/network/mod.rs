@@ -36,13 +36,26 @@
 //! disconnected.
 //!
 use std::fmt::Debug;
+use std::io;
+use std::net::SocketAddr;
 
-use crate::prelude::{encode::ToByte, Result};
+use crate::{
+    encode::ToByte,
+    error::{Error, Result},
+    parser::FromByte,
+    protocol::{parse_header_response, HeaderRequest},
+    DEFAULT_CLIENT_ID, DEFAULT_CORRELATION_ID,
+};
 use async_trait::async_trait;
-use bytes::BytesMut;
+use bytes::{BufMut, BytesMut};
+use nombytes::NomBytes;
+use tokio::net::{TcpSocket, TcpStream};
 
+pub mod boxed;
+mod multiplex;
 pub mod sasl;
 pub mod tcp;
+#[cfg(feature = "tls")]
 pub mod tls;
 
 /// Address of a broker
@@ -52,6 +65,60 @@ pub struct BrokerAddress {
     pub port: u16,
 }
 
+/// Options applied to the socket of a connection before it connects.
+///
+/// Options left unset keep the defaults of the operating system. Larger
+/// buffers help high-throughput reads, where the broker can otherwise only
+/// send as much as fits in the receive window before waiting on the client.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SocketOptions {
+    pub recv_buffer_bytes: Option<u32>,
+    pub send_buffer_bytes: Option<u32>,
+    pub nodelay: Option<bool>,
+}
+
+impl SocketOptions {
+    /// Size of the socket receive buffer, `SO_RCVBUF`.
+    pub fn socket_recv_buffer_bytes(mut self, recv_buffer_bytes: u32) -> Self {
+        self.recv_buffer_bytes = Some(recv_buffer_bytes);
+        self
+    }
+
+    /// Size of the socket send buffer, `SO_SNDBUF`.
+    pub fn socket_send_buffer_bytes(mut self, send_buffer_bytes: u32) -> Self {
+        self.send_buffer_bytes = Some(send_buffer_bytes);
+        self
+    }
+
+    /// Whether to send small writes right away instead of waiting to fill a
+    /// segment, `TCP_NODELAY`.
+    pub fn tcp_nodelay(mut self, nodelay: bool) -> Self {
+        self.nodelay = Some(nodelay);
+        self
+    }
+
+    /// Open a socket with these options and connect it to `addr`.
+    pub(crate) async fn connect(&self, addr: SocketAddr) -> io::Result<TcpStream> {
+        let socket = match addr {
+            SocketAddr::V4(_) => TcpSocket::new_v4()?,
+            SocketAddr::V6(_) => TcpSocket::new_v6()?,
+        };
+        // the buffer sizes have to be set before connecting to affect the
+        // window advertised in the handshake
+        if let Some(recv_buffer_bytes) = self.recv_buffer_bytes {
+            socket.set_recv_buffer_size(recv_buffer_bytes)?;
+        }
+        if let Some(send_buffer_bytes) = self.send_buffer_bytes {
+            socket.set_send_buffer_size(send_buffer_bytes)?;
+        }
+        let stream = socket.connect(addr).await?;
+        if let Some(nodelay) = self.nodelay {
+            stream.set_nodelay(nodelay)?;
+        }
+        Ok(stream)
+    }
+}
+
 /// Trait abstracting connections across multiple protocols
 #[async_trait]
 pub trait BrokerConnection {
@@ -76,6 +143,64 @@ pub trait BrokerConnection {
     /// into a response type. To see how this would be done, visit the
     /// protocol module.
     async fn receive_response(&mut self) -> Result<BytesMut>;
+    /// Send a request for any API and wait for its response.
+    ///
+    /// This is an escape hatch for APIs the crate does not wrap yet. The
+    /// request header is written for you, so `req` only needs to encode the
+    /// request body for the given API key and version. Likewise, the response
+    /// header is checked and stripped before the body is decoded.
+    ///
+    /// ### Example
+    /// ```rust
+    /// // ApiVersions (Version: 0) has an empty request body
+    /// let api_versions: ApiVersionsResponse = conn
+    ///     .send_custom_request(18, 0, &ApiVersionsRequest)
+    ///     .await?;
+    /// ```
+    async fn send_custom_request<Req, Resp>(
+        &mut self,
+        api_key: i16,
+        api_version: i16,
+        req: &Req,
+    ) -> Result<Resp>
+    where
+        Self: Send,
+        Req: ToByte + Sync + Send,
+        Resp: FromByte,
+    {
+        let request = CustomRequest {
+            header: HeaderRequest::new(
+                api_key,
+                api_version,
+                DEFAULT_CORRELATION_ID,
+                DEFAULT_CLIENT_ID,
+            ),
+            body: req,
+        };
+        self.send_request(&request).await?;
+
+        let response = self.receive_response().await?.freeze();
+        let (body, header) = parse_header_response(NomBytes::new(response.clone()))
+            .map_err(|_| Error::ParsingError(response))?;
+        if header.correlation_id != DEFAULT_CORRELATION_ID {
+            tracing::error!(
+                "Expected correlation id {}, got {}",
+                DEFAULT_CORRELATION_ID,
+                header.correlation_id
+            );
+            return Err(Error::IncorrectConnectionUsage);
+        }
+
+        Resp::decode(body.into_bytes())
+    }
+    /// Close the connection to a Kafka/Redpanda broker.
+    ///
+    /// Flushes any pending writes and shuts down the underlying stream,
+    /// returning once the broker has been notified. Clones of this
+    /// connection share the same stream, so they are closed as well.
+    async fn close(self) -> Result<()>
+    where
+        Self: Sized;
     /// Connect to a Kafka/Redpanda cluster
     async fn new(p: Self::ConnConfig) -> Result<Self>
     where
@@ -86,3 +211,18 @@ pub trait BrokerConnection {
     where
         Self: Sized;
 }
+
+/// A request to an API the crate does not wrap.
+#[derive(Debug)]
+struct CustomRequest<'a, R> {
+    header: HeaderRequest<'a>,
+    body: &'a R,
+}
+
+impl<R: ToByte> ToByte for CustomRequest<'_, R> {
+    fn encode<W: BufMut>(&self, buffer: &mut W) -> Result<()> {
+        self.header.encode(buffer)?;
+        self.body.encode(buffer)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,282 @@
+//! Sharing a single broker socket between cloned connection handles.
+//!
+//! Cloning a connection hands out another handle onto the same socket.
+//! Since each handle may have requests in flight at the same time, the
+//! correlation id of every outgoing request is swapped for one that is
+//! unique to the socket. When a response comes back, that id is used to
+//! find the handle it belongs to and the original correlation id is put
+//! back before the bytes are handed out.
+//!
+//! Responses are matched strictly by correlation id, so a broker answering
+//! out of order still hands every request its own response, and a handle
+//! gets its responses back in the order it sent the requests.
+
+use std::collections::{HashMap, VecDeque};
+
+use bytes::BytesMut;
+
+use crate::error::{Error, Result};
+
+/// Offset of the correlation id in a size delimited request frame:
+/// size (4) + api_key (2) + api_version (2).
+const REQUEST_CORRELATION_ID_OFFSET: usize = 8;
+/// Offset of the client id in a size delimited request frame.
+const REQUEST_CLIENT_ID_OFFSET: usize = 12;
+/// Produce requests with acks of 0 never get a response.
+const API_KEY_PRODUCE: i16 = 0;
+/// Owner of responses whose handle was dropped before they arrived.
+const RELEASED_HANDLE: usize = usize::MAX;
+
+/// Bookkeeping for the requests in flight on a shared socket.
+#[derive(Debug, Default)]
+pub(crate) struct Multiplexer {
+    next_handle: usize,
+    next_correlation_id: i32,
+    /// Socket correlation id -> (handle, original correlation id).
+    in_flight: HashMap<i32, (usize, i32)>,
+    /// Socket correlation ids of each handle, in the order they were sent.
+    pending: HashMap<usize, VecDeque<i32>>,
+    /// Responses already read off the socket, by socket correlation id.
+    ready: HashMap<i32, BytesMut>,
+}
+
+impl Multiplexer {
+    /// Hand out an id for a new handle onto the socket.
+    pub fn register_handle(&mut self) -> usize {
+        let handle = self.next_handle;
+        self.next_handle += 1;
+        handle
+    }
+
+    /// Forget about a handle that has been dropped.
+    ///
+    /// Responses to its outstanding requests are discarded when they arrive.
+    pub fn release_handle(&mut self, handle: usize) {
+        for correlation_id in self.pending.remove(&handle).unwrap_or_default() {
+            self.ready.remove(&correlation_id);
+        }
+        for (owner, _) in self.in_flight.values_mut() {
+            if *owner == handle {
+                *owner = RELEASED_HANDLE;
+            }
+        }
+    }
+
+    /// Rewrite the correlation id of an encoded request frame so the
+    /// response can be routed back to `handle`.
+    pub fn tag_request(&mut self, handle: usize, frame: &mut [u8]) {
+        let Some(original) = read_i32(frame, REQUEST_CORRELATION_ID_OFFSET) else {
+            // not a kafka request, nothing to correlate
+            return;
+        };
+        if !expects_response(frame) {
+            return;
+        }
+
+        let correlation_id = self.next_correlation_id;
+        self.next_correlation_id = self.next_correlation_id.wrapping_add(1);
+        frame[REQUEST_CORRELATION_ID_OFFSET..REQUEST_CORRELATION_ID_OFFSET + 4]
+            .copy_from_slice(&correlation_id.to_be_bytes());
+        self.in_flight.insert(correlation_id, (handle, original));
+        self.pending
+            .entry(handle)
+            .or_default()
+            .push_back(correlation_id);
+    }
+
+    /// Take the response to the oldest request of `handle`, if it has
+    /// already been read off the socket.
+    pub fn take_ready(&mut self, handle: usize) -> Option<BytesMut> {
+        let pending = self.pending.get_mut(&handle)?;
+        let response = self.ready.remove(pending.front()?)?;
+        pending.pop_front();
+        Some(response)
+    }
+
+    /// Route a response read off the socket.
+    ///
+    /// Returns the response to the oldest request of `handle` once it is
+    /// available, any other response is kept until its own request is next
+    /// in line. Responses that cannot be matched to a request in flight are
+    /// an error.
+    pub fn route_response(
+        &mut self,
+        handle: usize,
+        mut response: BytesMut,
+    ) -> Result<Option<BytesMut>> {
+        let Some(correlation_id) = read_i32(&response, 0) else {
+            return Err(Error::ParsingError(response.freeze()));
+        };
+        let Some((owner, original)) = self.in_flight.remove(&correlation_id) else {
+            tracing::error!(
+                "Received response with unknown correlation id {}",
+                correlation_id
+            );
+            return Err(Error::UnknownCorrelationId(correlation_id));
+        };
+
+        if owner == RELEASED_HANDLE {
+            tracing::trace!("Discarding response for released handle");
+            return Ok(None);
+        }
+
+        tracing::trace!("Routing response to handle {}", owner);
+        response[0..4].copy_from_slice(&original.to_be_bytes());
+        self.ready.insert(correlation_id, response);
+        Ok(self.take_ready(handle))
+    }
+}
+
+fn read_i32(buf: &[u8], offset: usize) -> Option<i32> {
+    let bytes = buf.get(offset..offset + 4)?;
+    Some(i32::from_be_bytes(bytes.try_into().ok()?))
+}
+
+fn read_i16(buf: &[u8], offset: usize) -> Option<i16> {
+    let bytes = buf.get(offset..offset + 2)?;
+    Some(i16::from_be_bytes(bytes.try_into().ok()?))
+}
+
+/// Whether the broker will answer this request frame.
+fn expects_response(frame: &[u8]) -> bool {
+    if read_i16(frame, 4) != Some(API_KEY_PRODUCE) {
+        return true;
+    }
+
+    // skip past client_id and transactional_id to find acks
+    let Some(client_id_len) = read_i16(frame, REQUEST_CLIENT_ID_OFFSET) else {
+        return true;
+    };
+    let transactional_id_offset = REQUEST_CLIENT_ID_OFFSET + 2 + client_id_len.max(0) as usize;
+    let Some(transactional_id_len) = read_i16(frame, transactional_id_offset) else {
+        return true;
+    };
+    let acks_offset = transactional_id_offset + 2 + transactional_id_len.max(0) as usize;
+
+    read_i16(frame, acks_offset) != Some(0)
+}
+
+#[cfg(test)]
+mod test {
+    use bytes::BufMut;
+
+    use super::*;
+
+    fn request_frame(api_key: i16, correlation_id: i32) -> Vec<u8> {
+        let mut frame = vec![0, 0, 0, 0];
+        frame.put_i16(api_key);
+        frame.put_i16(0);
+        frame.put_i32(correlation_id);
+        frame.put_i16(3);
+        frame.put_slice(b"abc");
+        frame
+    }
+
+    fn response_for(frame: &[u8]) -> BytesMut {
+        BytesMut::from(&frame[REQUEST_CORRELATION_ID_OFFSET..REQUEST_CLIENT_ID_OFFSET])
+    }
+
+    #[test]
+    fn it_routes_responses_to_their_handle() {
+        let mut mux = Multiplexer::default();
+        let first = mux.register_handle();
+        let second = mux.register_handle();
+
+        let mut first_frame = request_frame(3, 7);
+        let mut second_frame = request_frame(3, 7);
+        mux.tag_request(first, &mut first_frame);
+        mux.tag_request(second, &mut second_frame);
+        assert_ne!(first_frame, second_frame);
+
+        // second handle reads the first response off the socket
+        assert_eq!(
+            mux.route_response(second, response_for(&first_frame)),
+            Ok(None)
+        );
+        assert_eq!(
+            mux.route_response(second, response_for(&second_frame)),
+            Ok(Some(BytesMut::from(&7_i32.to_be_bytes()[..])))
+        );
+        assert_eq!(
+            mux.take_ready(first),
+            Some(BytesMut::from(&7_i32.to_be_bytes()[..]))
+        );
+        assert_eq!(mux.take_ready(first), None);
+    }
+
+    #[test]
+    fn it_discards_responses_for_released_handles() {
+        let mut mux = Multiplexer::default();
+        let first = mux.register_handle();
+        let second = mux.register_handle();
+
+        let mut frame = request_frame(3, 7);
+        mux.tag_request(first, &mut frame);
+        mux.release_handle(first);
+
+        assert_eq!(mux.route_response(second, response_for(&frame)), Ok(None));
+        assert!(mux.in_flight.is_empty());
+        assert!(mux.ready.is_empty());
+    }
+
+    #[test]
+    fn it_matches_out_of_order_responses_by_correlation_id() {
+        let mut mux = Multiplexer::default();
+        let handle = mux.register_handle();
+
+        let mut first_frame = request_frame(3, 1);
+        let mut second_frame = request_frame(3, 2);
+        mux.tag_request(handle, &mut first_frame);
+        mux.tag_request(handle, &mut second_frame);
+
+        // the second response overtakes the first one
+        assert_eq!(
+            mux.route_response(handle, response_for(&second_frame)),
+            Ok(None)
+        );
+        assert_eq!(
+            mux.route_response(handle, response_for(&first_frame)),
+            Ok(Some(BytesMut::from(&1_i32.to_be_bytes()[..])))
+        );
+        assert_eq!(
+            mux.take_ready(handle),
+            Some(BytesMut::from(&2_i32.to_be_bytes()[..]))
+        );
+    }
+
+    #[test]
+    fn it_rejects_responses_without_a_pending_request() {
+        let mut mux = Multiplexer::default();
+        let handle = mux.register_handle();
+
+        let mut frame = request_frame(3, 1);
+        mux.tag_request(handle, &mut frame);
+        let response = response_for(&frame);
+        assert!(matches!(
+            mux.route_response(handle, response.clone()),
+            Ok(Some(_))
+        ));
+
+        // answered twice
+        let correlation_id = read_i32(&frame, REQUEST_CORRELATION_ID_OFFSET).unwrap();
+        assert_eq!(
+            mux.route_response(handle, response),
+            Err(Error::UnknownCorrelationId(correlation_id))
+        );
+    }
+
+    #[test]
+    fn it_does_not_track_produce_without_acks() {
+        let mut mux = Multiplexer::default();
+        let handle = mux.register_handle();
+
+        let mut frame = request_frame(API_KEY_PRODUCE, 1);
+        frame.put_i16(-1); // transactional_id
+        frame.put_i16(0); // acks
+        let original = frame.clone();
+        mux.tag_request(handle, &mut frame);
+
+        assert_eq!(frame, original);
+        assert!(mux.in_flight.is_empty());
+    }
+}
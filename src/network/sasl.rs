@@ -9,16 +9,70 @@ use crate::{
     DEFAULT_CLIENT_ID, DEFAULT_CORRELATION_ID,
 };
 use bytes::Bytes;
+use futures::future::BoxFuture;
 use rsasl::prelude::*;
-use std::io::Cursor;
+use std::{fmt, io::Cursor, sync::Arc, time::Duration};
+use tokio::task::JoinHandle;
 
 /// SASL Credentials
-#[derive(Clone, Debug)]
+#[derive(Clone)]
 pub struct SaslConfig {
     pub username: String,
     pub password: String,
     pub correlation_id: i32,
     pub client_id: String,
+    /// Authenticate with a custom mechanism instead of the credentials.
+    pub mechanism: Option<SaslMechanismFactory>,
+    /// Authenticate with OAUTHBEARER, fetching a fresh token each time.
+    pub token_provider: Option<TokenProvider>,
+}
+
+/// Fetches the token of each OAUTHBEARER authentication.
+pub type TokenProvider = Arc<dyn Fn() -> BoxFuture<'static, String> + Send + Sync>;
+
+/// What a [`SaslMechanism`] does after a challenge of the broker.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SaslResponse {
+    /// Send these bytes to the broker and wait for the next challenge.
+    Continue(Bytes),
+    /// The exchange is complete.
+    Done,
+}
+
+/// A SASL mechanism that is not built in, such as OAUTHBEARER with a token
+/// provider or a vendor specific handshake.
+///
+/// A new mechanism is created for every connection, so it can keep the
+/// state of its exchange.
+pub trait SaslMechanism: Send {
+    /// The name sent in the SaslHandshake request, e.g. `OAUTHBEARER`.
+    fn name(&self) -> String;
+
+    /// The first bytes sent to the broker.
+    fn initial_response(&mut self) -> Result<Bytes>;
+
+    /// Answer a challenge of the broker.
+    fn next(&mut self, challenge: Bytes) -> Result<SaslResponse>;
+}
+
+/// Creates the [`SaslMechanism`] of each new connection.
+pub type SaslMechanismFactory = Arc<dyn Fn() -> Box<dyn SaslMechanism> + Send + Sync>;
+
+impl fmt::Debug for SaslConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SaslConfig")
+            .field("username", &self.username)
+            // never log the credential
+            .field("password", &"<redacted>")
+            .field("correlation_id", &self.correlation_id)
+            .field("client_id", &self.client_id)
+            .field("mechanism", &self.mechanism.as_ref().map(|_| "custom"))
+            .field(
+                "token_provider",
+                &self.token_provider.as_ref().map(|_| "OAUTHBEARER"),
+            )
+            .finish()
+    }
 }
 
 impl SaslConfig {
@@ -33,8 +87,34 @@ impl SaslConfig {
             password,
             correlation_id: correlation_id.unwrap_or(DEFAULT_CORRELATION_ID),
             client_id: client_id.unwrap_or(DEFAULT_CLIENT_ID.to_owned()),
+            mechanism: None,
+            token_provider: None,
         }
     }
+
+    /// Authenticate with OAUTHBEARER.
+    ///
+    /// The token provider is called for every authentication, on each new
+    /// connection and whenever a session is about to expire, so it can hand
+    /// out short-lived tokens.
+    pub fn oauth_bearer(
+        token_provider: impl Fn() -> BoxFuture<'static, String> + Send + Sync + 'static,
+        correlation_id: Option<i32>,
+        client_id: Option<String>,
+    ) -> Self {
+        let mut config = Self::new(String::new(), String::new(), correlation_id, client_id);
+        config.token_provider = Some(Arc::new(token_provider));
+        config
+    }
+
+    /// Authenticate with a custom mechanism, created anew for each connection.
+    pub fn with_mechanism(
+        mut self,
+        mechanism: impl Fn() -> Box<dyn SaslMechanism> + Send + Sync + 'static,
+    ) -> Self {
+        self.mechanism = Some(Arc::new(mechanism));
+        self
+    }
 }
 
 pub async fn sasl_handshake(
@@ -68,6 +148,33 @@ pub async fn do_sasl(
     client_id: &str,
     config: SaslConfig,
 ) -> Result<()> {
+    authenticate(broker_conn, correlation_id, client_id, config).await?;
+    Ok(())
+}
+
+/// Authenticate and return the session lifetime the broker handed out, if any.
+async fn authenticate(
+    broker_conn: impl BrokerConnection + Clone,
+    correlation_id: i32,
+    client_id: &str,
+    config: SaslConfig,
+) -> Result<Option<Duration>> {
+    if let Some(token_provider) = &config.token_provider {
+        let mut mechanism = OAuthBearer::new(token_provider().await);
+        return authenticate_with_mechanism(broker_conn, correlation_id, client_id, &mut mechanism)
+            .await;
+    }
+    if let Some(mechanism) = &config.mechanism {
+        let mut mechanism = mechanism();
+        return authenticate_with_mechanism(
+            broker_conn,
+            correlation_id,
+            client_id,
+            mechanism.as_mut(),
+        )
+        .await;
+    }
+
     let mechanism = String::from("SCRAM-SHA-256");
     let handshake_response =
         sasl_handshake(broker_conn.clone(), correlation_id, client_id, mechanism).await?;
@@ -95,6 +202,7 @@ pub async fn do_sasl(
     tracing::debug!("Using {:?} for our SASL Mechanism", selected_mechanism);
 
     let mut data: Option<Vec<u8>> = None;
+    let mut session_lifetime = None;
 
     // stepping the authentication exchange to completion
     while {
@@ -118,8 +226,451 @@ pub async fn do_sasl(
             Bytes::from(data.unwrap()),
         )
         .await?;
+        session_lifetime = lifetime_of(&authentication_response);
         data = Some(authentication_response.auth_bytes.to_vec());
     }
 
+    Ok(session_lifetime)
+}
+
+/// Authenticate with a custom [`SaslMechanism`].
+pub async fn do_sasl_with_mechanism(
+    broker_conn: impl BrokerConnection + Clone,
+    correlation_id: i32,
+    client_id: &str,
+    mechanism: &mut dyn SaslMechanism,
+) -> Result<()> {
+    authenticate_with_mechanism(broker_conn, correlation_id, client_id, mechanism).await?;
     Ok(())
 }
+
+async fn authenticate_with_mechanism(
+    broker_conn: impl BrokerConnection + Clone,
+    correlation_id: i32,
+    client_id: &str,
+    mechanism: &mut dyn SaslMechanism,
+) -> Result<Option<Duration>> {
+    let handshake_response = sasl_handshake(
+        broker_conn.clone(),
+        correlation_id,
+        client_id,
+        mechanism.name(),
+    )
+    .await?;
+    if handshake_response.error_code != KafkaCode::None {
+        tracing::error!(
+            "Broker only supports {:?} for SASL",
+            handshake_response.mechanisms
+        );
+        return Err(Error::KafkaError(handshake_response.error_code));
+    }
+
+    let mut data = mechanism.initial_response()?;
+    loop {
+        let authentication_response =
+            sasl_authentication(broker_conn.clone(), correlation_id, client_id, data).await?;
+        if authentication_response.error_code != KafkaCode::None {
+            tracing::error!(
+                "SASL authentication failed {:?}",
+                authentication_response.error_message
+            );
+            return Err(Error::KafkaError(authentication_response.error_code));
+        }
+        let session_lifetime = lifetime_of(&authentication_response);
+        match mechanism.next(authentication_response.auth_bytes)? {
+            SaslResponse::Continue(response) => data = response,
+            SaslResponse::Done => return Ok(session_lifetime),
+        }
+    }
+}
+
+/// The session lifetime of a successful authentication, zero meaning the
+/// session never expires.
+fn lifetime_of(response: &SaslAuthenticationResponse) -> Option<Duration> {
+    (response.session_lifetime_ms > 0)
+        .then(|| Duration::from_millis(response.session_lifetime_ms as u64))
+}
+
+/// The OAUTHBEARER mechanism of RFC 7628, sending a bearer token.
+#[derive(Clone, Debug)]
+pub struct OAuthBearer {
+    token: String,
+}
+
+impl OAuthBearer {
+    pub fn new(token: String) -> Self {
+        Self { token }
+    }
+}
+
+impl SaslMechanism for OAuthBearer {
+    fn name(&self) -> String {
+        "OAUTHBEARER".to_owned()
+    }
+
+    fn initial_response(&mut self) -> Result<Bytes> {
+        Ok(Bytes::from(format!(
+            "n,,\x01auth=Bearer {}\x01\x01",
+            self.token
+        )))
+    }
+
+    fn next(&mut self, challenge: Bytes) -> Result<SaslResponse> {
+        if challenge.is_empty() {
+            return Ok(SaslResponse::Done);
+        }
+        // the broker rejected the token, the exchange has to be finished
+        // with a single 0x01 before the broker reports the failure
+        tracing::error!("OAUTHBEARER token rejected {:?}", challenge);
+        Ok(SaslResponse::Continue(Bytes::from_static(b"\x01")))
+    }
+}
+
+/// Keeps a connection authenticated past the session lifetime handed out by
+/// the broker (KIP-368), by authenticating again in the background shortly
+/// before the session expires.
+///
+/// The exchange runs on its own handle of the connection, so requests in
+/// flight on the other handles are not disturbed.
+#[derive(Clone, Debug)]
+pub(crate) struct SaslSession {
+    /// Stops reauthenticating once the last clone of the connection is dropped.
+    reauthentication: Option<Arc<AbortOnDrop>>,
+}
+
+#[derive(Debug)]
+struct AbortOnDrop(JoinHandle<()>);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+impl SaslSession {
+    /// Authenticate a new connection and schedule its reauthentication.
+    pub(crate) async fn start(
+        broker_conn: impl BrokerConnection + Clone + Send + Sync + 'static,
+        config: SaslConfig,
+    ) -> Result<Self> {
+        let Some(session_lifetime) = authenticate_session(broker_conn.clone(), &config).await?
+        else {
+            return Ok(Self {
+                reauthentication: None,
+            });
+        };
+
+        let reauthentication = tokio::spawn(async move {
+            let mut session_lifetime = session_lifetime;
+            loop {
+                tokio::time::sleep(reauthentication_delay(session_lifetime)).await;
+                tracing::debug!("SASL session is about to expire, authenticating again");
+                match authenticate_session(broker_conn.clone(), &config).await {
+                    Ok(Some(lifetime)) => session_lifetime = lifetime,
+                    Ok(None) => return,
+                    Err(err) => {
+                        tracing::error!("Error reauthenticating SASL session {:?}", err);
+                        return;
+                    }
+                }
+            }
+        });
+        Ok(Self {
+            reauthentication: Some(Arc::new(AbortOnDrop(reauthentication))),
+        })
+    }
+
+    /// Stop reauthenticating, even while other clones of the connection
+    /// are still around.
+    pub(crate) fn stop(&self) {
+        if let Some(reauthentication) = &self.reauthentication {
+            reauthentication.0.abort();
+        }
+    }
+}
+
+async fn authenticate_session(
+    broker_conn: impl BrokerConnection + Clone,
+    config: &SaslConfig,
+) -> Result<Option<Duration>> {
+    authenticate(
+        broker_conn,
+        config.correlation_id,
+        &config.client_id,
+        config.clone(),
+    )
+    .await
+}
+
+/// How long to wait before authenticating again.
+///
+/// Like the Java client, a tenth of the lifetime is left to authenticate
+/// before the broker closes the connection.
+fn reauthentication_delay(session_lifetime: Duration) -> Duration {
+    session_lifetime * 9 / 10
+}
+
+#[cfg(test)]
+mod test {
+    use std::{sync::Mutex, time::Instant};
+
+    use bytes::BufMut;
+    use tokio::net::TcpListener;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::{
+        network::tcp::{SaslTcpConfig, SaslTcpConnection, TcpConnection},
+        protocol::HeaderRequest,
+        test_support::{
+            api_key, body_offset, broker_address, listen, put_string, read_request, serve,
+            write_response,
+        },
+    };
+
+    /// Sends a token, then proves it saw the challenge of the broker.
+    struct TokenMechanism {
+        challenges: Arc<Mutex<Vec<Bytes>>>,
+    }
+
+    impl SaslMechanism for TokenMechanism {
+        fn name(&self) -> String {
+            "X-TOKEN".to_owned()
+        }
+
+        fn initial_response(&mut self) -> Result<Bytes> {
+            Ok(Bytes::from_static(b"token"))
+        }
+
+        fn next(&mut self, challenge: Bytes) -> Result<SaslResponse> {
+            let mut challenges = self.challenges.lock().unwrap();
+            challenges.push(challenge.clone());
+            if challenges.len() == 1 {
+                Ok(SaslResponse::Continue(challenge))
+            } else {
+                Ok(SaslResponse::Done)
+            }
+        }
+    }
+
+    /// Accepts the X-TOKEN mechanism, answers the token with a nonce and
+    /// expects the nonce back. Returns the auth bytes it received.
+    async fn start_broker(listener: TcpListener) -> Vec<Bytes> {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut received = vec![];
+        for step in 0..3 {
+            let request = read_request(&mut socket).await.unwrap();
+            let body = &request[body_offset(&request)..];
+
+            let mut buf = vec![];
+            if step == 0 {
+                assert_eq!(api_key(&request), 17);
+                assert_eq!(body, b"\0\x07X-TOKEN");
+                buf.put_i16(0);
+                buf.put_i32(1);
+                put_string(&mut buf, "X-TOKEN");
+            } else {
+                assert_eq!(api_key(&request), 36);
+                received.push(Bytes::copy_from_slice(&body[4..]));
+                buf.put_i16(0);
+                buf.put_i16(-1); // error_message
+                let auth_bytes: &[u8] = if step == 1 { b"nonce" } else { b"" };
+                buf.put_i32(auth_bytes.len() as i32);
+                buf.put_slice(auth_bytes);
+                buf.put_i64(0); // session_lifetime_ms
+            }
+            write_response(&mut socket, &request, &buf).await;
+        }
+        received
+    }
+
+    #[test]
+    fn it_leaves_the_password_out_of_the_debug_output() {
+        let config = SaslConfig::new("alice".to_owned(), "hunter2".to_owned(), None, None);
+
+        let debug = format!("{:?}", config);
+        assert!(debug.contains("alice"));
+        assert!(!debug.contains("hunter2"));
+    }
+
+    #[tokio::test]
+    async fn it_authenticates_with_a_custom_mechanism() {
+        let (listener, port) = listen().await;
+        let broker = tokio::spawn(start_broker(listener));
+
+        let challenges = Arc::new(Mutex::new(vec![]));
+        let mechanism_challenges = challenges.clone();
+        let config =
+            SaslConfig::new(String::new(), String::new(), None, None).with_mechanism(move || {
+                Box::new(TokenMechanism {
+                    challenges: mechanism_challenges.clone(),
+                })
+            });
+        let conn = TcpConnection::new_(vec![broker_address(port)])
+            .await
+            .unwrap();
+
+        do_sasl(conn, 1, "rust", config).await.unwrap();
+
+        assert_eq!(
+            broker.await.unwrap(),
+            vec![Bytes::from_static(b"token"), Bytes::from_static(b"nonce")]
+        );
+        assert_eq!(
+            *challenges.lock().unwrap(),
+            vec![Bytes::from_static(b"nonce"), Bytes::new()]
+        );
+    }
+
+    /// Accepts OAUTHBEARER on every connection, handing out sessions of the
+    /// given lifetime, and answers any other request with an empty body.
+    /// Returns when each authentication happened and its auth bytes.
+    async fn start_oauth_broker(
+        listener: TcpListener,
+        session_lifetime_ms: i64,
+    ) -> Arc<Mutex<Vec<(Instant, Bytes)>>> {
+        let payloads = Arc::new(Mutex::new(vec![]));
+        let received = payloads.clone();
+        serve(listener, move |request| {
+            let mut buf = vec![];
+            match api_key(request) {
+                17 => {
+                    buf.put_i16(0);
+                    buf.put_i32(1);
+                    put_string(&mut buf, "OAUTHBEARER");
+                }
+                36 => {
+                    let body = &request[body_offset(request)..];
+                    received
+                        .lock()
+                        .unwrap()
+                        .push((Instant::now(), Bytes::copy_from_slice(&body[4..])));
+                    buf.put_i16(0);
+                    buf.put_i16(-1); // error_message
+                    buf.put_i32(0); // auth_bytes
+                    buf.put_i64(session_lifetime_ms);
+                }
+                _ => {}
+            }
+            Some(buf)
+        });
+        payloads
+    }
+
+    /// Connect with a token provider that hands out `token-1`, `token-2`, ...
+    async fn connect_with_tokens(port: u16, tokens: Arc<AtomicUsize>) -> SaslTcpConnection {
+        let config = SaslConfig::oauth_bearer(
+            move || {
+                let token = tokens.fetch_add(1, Ordering::SeqCst) + 1;
+                Box::pin(async move { format!("token-{token}") })
+            },
+            None,
+            None,
+        );
+        SaslTcpConnection::new(SaslTcpConfig {
+            tcp_config: vec![broker_address(port)],
+            sasl_config: config,
+        })
+        .await
+        .unwrap()
+    }
+
+    fn sent_tokens(payloads: &Mutex<Vec<(Instant, Bytes)>>) -> Vec<Bytes> {
+        let payloads = payloads.lock().unwrap();
+        payloads
+            .iter()
+            .map(|(_, payload)| payload.clone())
+            .collect()
+    }
+
+    fn bearer(token: &str) -> Bytes {
+        Bytes::from(format!("n,,\x01auth=Bearer {token}\x01\x01"))
+    }
+
+    #[tokio::test]
+    async fn it_sends_a_fresh_oauth_token_per_connection() {
+        let (listener, port) = listen().await;
+        let payloads = start_oauth_broker(listener, 0).await;
+        let tokens = Arc::new(AtomicUsize::new(0));
+
+        let mut conn = connect_with_tokens(port, tokens.clone()).await;
+        // a session without lifetime is never authenticated again
+        conn.send_request(&HeaderRequest::new(18, 0, 2, "rust"))
+            .await
+            .unwrap();
+        conn.receive_response().await.unwrap();
+        assert_eq!(sent_tokens(&payloads), vec![bearer("token-1")]);
+
+        // reconnecting fetches a new token
+        let _conn = connect_with_tokens(port, tokens).await;
+        assert_eq!(
+            sent_tokens(&payloads),
+            vec![bearer("token-1"), bearer("token-2")]
+        );
+    }
+
+    #[tokio::test]
+    async fn it_reauthenticates_before_the_session_expires() {
+        let (listener, port) = listen().await;
+        let payloads = start_oauth_broker(listener, 100).await;
+        let tokens = Arc::new(AtomicUsize::new(0));
+
+        let mut conn = connect_with_tokens(port, tokens).await;
+        conn.send_request(&HeaderRequest::new(18, 0, 2, "rust"))
+            .await
+            .unwrap();
+        conn.receive_response().await.unwrap();
+        assert_eq!(payloads.lock().unwrap().len(), 1);
+
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        conn.send_request(&HeaderRequest::new(18, 0, 3, "rust"))
+            .await
+            .unwrap();
+        conn.receive_response().await.unwrap();
+        assert_eq!(
+            sent_tokens(&payloads),
+            vec![bearer("token-1"), bearer("token-2")]
+        );
+    }
+
+    #[tokio::test]
+    async fn it_reauthenticates_an_idle_connection_before_the_deadline() {
+        let (listener, port) = listen().await;
+        let payloads = start_oauth_broker(listener, 200).await;
+        let tokens = Arc::new(AtomicUsize::new(0));
+
+        let conn = connect_with_tokens(port, tokens).await;
+        tokio::time::sleep(Duration::from_millis(300)).await;
+
+        let authentications = payloads.lock().unwrap().clone();
+        assert!(authentications.len() >= 2);
+        for pair in authentications.windows(2) {
+            assert!(pair[1].0 - pair[0].0 < Duration::from_millis(200));
+        }
+        assert_eq!(authentications[1].1, bearer("token-2"));
+
+        // no more reauthentication once the connection is dropped
+        drop(conn);
+        let count = payloads.lock().unwrap().len();
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(payloads.lock().unwrap().len(), count);
+    }
+
+    #[tokio::test]
+    async fn it_stops_reauthenticating_once_the_connection_is_closed() {
+        let (listener, port) = listen().await;
+        let payloads = start_oauth_broker(listener, 100).await;
+        let tokens = Arc::new(AtomicUsize::new(0));
+
+        let conn = connect_with_tokens(port, tokens.clone()).await;
+        // a clone still holding the session must not keep it alive
+        let _clone = conn.clone();
+        conn.close().await.unwrap();
+
+        // the reauthentication task is gone, so no new token is fetched
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(tokens.load(Ordering::SeqCst), 1);
+        assert_eq!(sent_tokens(&payloads), vec![bearer("token-1")]);
+    }
+}
@@ -1,19 +1,27 @@
+use std::collections::HashMap;
 use std::io::ErrorKind;
 use std::net::ToSocketAddrs;
+use std::sync::{MutexGuard, PoisonError};
+use std::time::Duration;
 use std::{io, sync::Arc};
 
 use async_trait::async_trait;
 use bytes::{Buf, BytesMut};
+use tokio::io::AsyncWriteExt;
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
 use tokio::net::TcpStream;
+use tokio::sync::Mutex;
 use tracing::instrument;
 
 use crate::{
-    encode::ToByte,
+    encode::{try_usize_to_int, ToByte},
     error::{Error, Result},
+    protocol, DEFAULT_CLIENT_ID, DEFAULT_CORRELATION_ID,
 };
 
-use super::sasl::{do_sasl, SaslConfig};
-use super::{BrokerAddress, BrokerConnection};
+use super::multiplex::Multiplexer;
+use super::sasl::{SaslConfig, SaslSession};
+use super::{BrokerAddress, BrokerConnection, SocketOptions};
 
 /// TCP connection to a Kafka/Redpanda broker.
 ///
@@ -25,14 +33,71 @@ use super::{BrokerAddress, BrokerConnection};
 ///     port: 9092,
 /// }];
 /// ```
-#[derive(Clone, Debug)]
+///
+/// Cloning a connection does not open a new socket. Every clone is a handle
+/// onto the same socket, and responses are routed back to the handle that
+/// sent the matching request.
+#[derive(Debug)]
 pub struct TcpConnection {
-    stream: Arc<TcpStream>,
+    reader: Arc<Mutex<OwnedReadHalf>>,
+    writer: Arc<Mutex<OwnedWriteHalf>>,
+    mux: Arc<std::sync::Mutex<Multiplexer>>,
+    queue: Arc<std::sync::Mutex<WriteQueue>>,
+    /// The version range of each API key, once negotiated with the broker.
+    api_versions: Arc<std::sync::Mutex<HashMap<i16, (i16, i16)>>>,
+    handle: usize,
+}
+
+/// Requests waiting to be written together, see [`TcpConnection::coalesce_writes`].
+#[derive(Debug, Default)]
+struct WriteQueue {
+    /// How long to wait for more requests before writing, `None` when disabled.
+    window: Option<Duration>,
+    pending: Vec<u8>,
+    /// Number of writes of queued requests.
+    flushes: usize,
+}
+
+impl Clone for TcpConnection {
+    fn clone(&self) -> Self {
+        let handle = self.mux().register_handle();
+        Self {
+            reader: self.reader.clone(),
+            writer: self.writer.clone(),
+            mux: self.mux.clone(),
+            queue: self.queue.clone(),
+            api_versions: self.api_versions.clone(),
+            handle,
+        }
+    }
+}
+
+impl Drop for TcpConnection {
+    fn drop(&mut self) {
+        let handle = self.handle;
+        self.mux().release_handle(handle);
+    }
 }
 
 impl TcpConnection {
     /// Connect to a Kafka/Redpanda cluster
     pub async fn new_(bootstrap_addrs: Vec<BrokerAddress>) -> Result<Self> {
+        Self::with_socket_options(bootstrap_addrs, &SocketOptions::default()).await
+    }
+
+    /// Connect to a Kafka/Redpanda cluster, applying the given options to the socket.
+    ///
+    /// ### Example
+    /// ```rust
+    /// let options = SocketOptions::default()
+    ///     .socket_recv_buffer_bytes(1024 * 1024)
+    ///     .tcp_nodelay(true);
+    /// let conn = TcpConnection::with_socket_options(bootstrap_addrs, &options).await?;
+    /// ```
+    pub async fn with_socket_options(
+        bootstrap_addrs: Vec<BrokerAddress>,
+        options: &SocketOptions,
+    ) -> Result<Self> {
         let mut propagated_err: Option<Error> = None;
         let mut stream: Option<TcpStream> = None;
         for bootstrap_addr in bootstrap_addrs.iter() {
@@ -50,7 +115,7 @@ impl TcpConnection {
                 })?
                 .next()
                 .unwrap();
-            match TcpStream::connect(addr).await {
+            match options.connect(addr).await {
                 Ok(s) => {
                     stream = Some(s);
                     break;
@@ -66,24 +131,98 @@ impl TcpConnection {
             }
             return Err(Error::IoError(ErrorKind::NotFound));
         }
-        let stream = Arc::new(stream.unwrap());
-        Ok(Self { stream })
+        let (reader, writer) = stream.unwrap().into_split();
+        let mut mux = Multiplexer::default();
+        let handle = mux.register_handle();
+        Ok(Self {
+            reader: Arc::new(Mutex::new(reader)),
+            writer: Arc::new(Mutex::new(writer)),
+            mux: Arc::new(std::sync::Mutex::new(mux)),
+            queue: Arc::new(std::sync::Mutex::new(WriteQueue::default())),
+            api_versions: Arc::new(std::sync::Mutex::new(HashMap::new())),
+            handle,
+        })
     }
 
-    #[instrument(name = "network-read", level = "trace")]
-    async fn read(&mut self, size: usize) -> Result<BytesMut> {
+    /// Coalesce requests sent around the same time into fewer writes.
+    ///
+    /// Each request is queued and written together with the other queued
+    /// requests after waiting up to `window` for more to arrive, trading a
+    /// little latency for fewer syscalls. A zero window only coalesces the
+    /// requests that queue up behind a write in progress. Coalescing is off
+    /// by default, passing `None` turns it off again. Since clones share the
+    /// same socket, this applies to all of them.
+    pub fn coalesce_writes(&self, window: Option<Duration>) {
+        self.queue().window = window;
+    }
+
+    /// Ask the broker which versions of each API it supports, keeping the
+    /// answer for [`api_versions`](Self::api_versions).
+    ///
+    /// ### Example
+    /// ```rust
+    /// let mut conn = TcpConnection::new_(bootstrap_addrs).await?;
+    /// conn.negotiate_api_versions().await?;
+    /// let (_, produce_max) = conn.api_versions()[&0];
+    /// ```
+    pub async fn negotiate_api_versions(&mut self) -> Result<HashMap<i16, (i16, i16)>> {
+        let request = protocol::ApiVersionsRequest::new(DEFAULT_CORRELATION_ID, DEFAULT_CLIENT_ID);
+        self.send_request_(&request).await?;
+        let response =
+            protocol::ApiVersionsResponse::try_from(self.receive_response_().await?.freeze())?;
+        response.is_error()?;
+
+        let api_versions: HashMap<i16, (i16, i16)> = response
+            .api_keys
+            .iter()
+            .map(|api| (api.api_key, (api.min_version, api.max_version)))
+            .collect();
+        tracing::debug!("Broker supports the API versions {:?}", api_versions);
+        *self
+            .api_versions
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = api_versions.clone();
+        Ok(api_versions)
+    }
+
+    /// The minimum and maximum version the broker supports for each API key,
+    /// empty until [`negotiate_api_versions`](Self::negotiate_api_versions)
+    /// completes. Since clones share the same socket, they share the
+    /// negotiated versions too.
+    pub fn api_versions(&self) -> HashMap<i16, (i16, i16)> {
+        self.api_versions
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    fn mux(&self) -> MutexGuard<'_, Multiplexer> {
+        // the bookkeeping is never left half updated, so a poisoned lock is still usable
+        self.mux.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn queue(&self) -> MutexGuard<'_, WriteQueue> {
+        self.queue.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    #[instrument(name = "network-read", level = "trace", skip(stream))]
+    async fn read(stream: &OwnedReadHalf, size: usize) -> Result<BytesMut> {
         let mut buf = BytesMut::zeroed(size);
         let mut index = 0_usize;
         loop {
             // Wait for the socket to be readable
-            self.stream
+            stream
                 .readable()
                 .await
                 .map_err(|e| Error::IoError(e.kind()))?;
 
             // Try to read data, this may still fail with `WouldBlock`
             // if the readiness event is a false positive.
-            match self.stream.try_read(&mut buf[index..]) {
+            match stream.try_read(&mut buf[index..]) {
+                Ok(0) if size > 0 => {
+                    tracing::error!("ERROR: Socket closed by peer");
+                    return Err(Error::IoError(io::ErrorKind::UnexpectedEof));
+                }
                 Ok(n) => {
                     index += n;
                     tracing::trace!("Read {} bytes", n);
@@ -105,20 +244,20 @@ impl TcpConnection {
         }
     }
 
-    #[instrument(name = "network-write", level = "trace")]
-    async fn write(&mut self, buf: &[u8]) -> Result<usize> {
+    #[instrument(name = "network-write", level = "trace", skip(stream))]
+    async fn write(stream: &OwnedWriteHalf, buf: &[u8]) -> Result<usize> {
         let size = buf.len();
         let mut index = 0_usize;
         loop {
             // Wait for the socket to be writable
-            self.stream
+            stream
                 .writable()
                 .await
                 .map_err(|e| Error::IoError(e.kind()))?;
 
             // Try to write data, this may still fail with `WouldBlock`
             // if the readiness event is a false positive.
-            match self.stream.try_write(&buf[index..]) {
+            match stream.try_write(&buf[index..]) {
                 Ok(n) => {
                     index += n;
                     tracing::trace!("Wrote {} bytes", n);
@@ -157,11 +296,50 @@ impl TcpConnection {
         buffer.extend_from_slice(&[0, 0, 0, 0]);
         req.encode(&mut buffer)?;
 
-        let size = buffer.len() as i32 - 4;
+        let size = try_usize_to_int!(buffer.len() - 4, i32);
         size.encode(&mut &mut buffer[..])?;
+        self.mux().tag_request(self.handle, &mut buffer);
 
-        tracing::trace!("Sending bytes {}", buffer.len());
-        self.write(&buffer).await?;
+        let window = {
+            let mut queue = self.queue();
+            if queue.window.is_some() {
+                queue.pending.extend_from_slice(&buffer);
+            }
+            queue.window
+        };
+
+        match window {
+            Some(window) => self.flush_queue(window).await,
+            None => {
+                tracing::trace!("Sending bytes {}", buffer.len());
+                let stream = self.writer.lock().await;
+                Self::write(&stream, &buffer).await?;
+                Ok(())
+            }
+        }
+    }
+
+    /// Write the queued requests, including the one just queued by this handle.
+    async fn flush_queue(&self, window: Duration) -> Result<()> {
+        let stream = self.writer.lock().await;
+        // another handle may have written our request while we waited
+        if self.queue().pending.is_empty() {
+            return Ok(());
+        }
+
+        // give requests sent around the same time a chance to join this write,
+        // they queue up behind the lock we are holding
+        if !window.is_zero() {
+            tokio::time::sleep(window).await;
+        }
+
+        let pending = {
+            let mut queue = self.queue();
+            queue.flushes += 1;
+            std::mem::take(&mut queue.pending)
+        };
+        tracing::trace!("Sending coalesced bytes {}", pending.len());
+        Self::write(&stream, &pending).await?;
 
         Ok(())
     }
@@ -175,12 +353,47 @@ impl TcpConnection {
     /// into a response type. To see how this would be done, visit the
     /// protocol module.
     pub async fn receive_response_(&mut self) -> Result<BytesMut> {
-        // figure out the message size
-        let mut size = self.read(4).await?;
+        loop {
+            if let Some(response) = self.mux().take_ready(self.handle) {
+                return Ok(response);
+            }
+
+            let stream = self.reader.lock().await;
+            // another handle may have read our response while we waited
+            if let Some(response) = self.mux().take_ready(self.handle) {
+                return Ok(response);
+            }
+
+            // figure out the message size
+            let mut size = Self::read(&stream, 4).await?;
+
+            let length = size.get_u32();
+            tracing::trace!("Reading {} bytes", length);
+            let response = Self::read(&stream, length as usize).await?;
+
+            // route while still holding the stream, so a handle waiting on it
+            // will find its response once it gets the lock
+            if let Some(response) = self.mux().route_response(self.handle, response)? {
+                return Ok(response);
+            }
+        }
+    }
+
+    /// Close the connection to the Kafka/Redpanda broker.
+    ///
+    /// Any pending writes are flushed before the write half of the socket
+    /// is shut down, which sends a FIN to the broker. Since clones share
+    /// the same socket, closing one handle closes it for all of them.
+    pub async fn close_(self) -> Result<()> {
+        tracing::debug!("Closing connection");
+        let mut writer = self.writer.lock().await;
+        writer.flush().await.map_err(|e| Error::IoError(e.kind()))?;
+        writer
+            .shutdown()
+            .await
+            .map_err(|e| Error::IoError(e.kind()))?;
 
-        let length = size.get_u32();
-        tracing::trace!("Reading {} bytes", length);
-        self.read(length as usize).await
+        Ok(())
     }
 }
 
@@ -196,6 +409,10 @@ impl BrokerConnection for TcpConnection {
         self.receive_response_().await
     }
 
+    async fn close(self) -> Result<()> {
+        self.close_().await
+    }
+
     async fn new(p: Self::ConnConfig) -> Result<Self> {
         Self::new_(p).await
     }
@@ -225,6 +442,7 @@ pub struct SaslTcpConfig {
 #[derive(Clone, Debug)]
 pub struct SaslTcpConnection {
     tcp_conn: TcpConnection,
+    session: SaslSession,
 }
 
 #[async_trait]
@@ -239,27 +457,295 @@ impl BrokerConnection for SaslTcpConnection {
         self.tcp_conn.receive_response_().await
     }
 
+    async fn close(self) -> Result<()> {
+        self.session.stop();
+        self.tcp_conn.close_().await
+    }
+
     async fn new(p: Self::ConnConfig) -> Result<Self> {
         let conn = TcpConnection::new_(p.tcp_config).await?;
-        do_sasl(
-            conn.clone(),
-            p.sasl_config.correlation_id,
-            &p.sasl_config.client_id,
-            p.sasl_config.clone(),
-        )
-        .await?;
-        Ok(Self { tcp_conn: conn })
+        let session = SaslSession::start(conn.clone(), p.sasl_config).await?;
+        Ok(Self {
+            tcp_conn: conn,
+            session,
+        })
     }
 
     async fn from_addr(p: Self::ConnConfig, addr: BrokerAddress) -> Result<Self> {
         let conn = TcpConnection::new_(vec![addr]).await?;
-        do_sasl(
-            conn.clone(),
-            p.sasl_config.correlation_id,
-            &p.sasl_config.client_id,
-            p.sasl_config.clone(),
-        )
-        .await?;
-        Ok(Self { tcp_conn: conn })
+        let session = SaslSession::start(conn.clone(), p.sasl_config).await?;
+        Ok(Self {
+            tcp_conn: conn,
+            session,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bytes::BufMut;
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::{
+        parser::FromByte,
+        protocol::HeaderRequest,
+        test_support::{broker_address, client_id, listen, read_request, write_response},
+    };
+
+    async fn connect(listener: &TcpListener) -> TcpConnection {
+        let port = listener.local_addr().unwrap().port();
+        TcpConnection::new_(vec![broker_address(port)])
+            .await
+            .unwrap()
+    }
+
+    /// Answers every request with its correlation id followed by the
+    /// client id of the request.
+    async fn echo_broker(listener: TcpListener, requests: usize) {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        for _ in 0..requests {
+            let request = read_request(&mut socket).await.unwrap();
+            write_response(&mut socket, &request, client_id(&request).as_bytes()).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn it_closes_the_socket_cleanly() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut conn = connect(&listener).await;
+
+        let broker = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = vec![];
+            // read_to_end only returns once the client has sent a FIN
+            socket.read_to_end(&mut buf).await.unwrap();
+            buf
+        });
+
+        conn.send_request_(&1_i32).await.unwrap();
+        conn.close().await.unwrap();
+
+        let received = broker.await.unwrap();
+        assert_eq!(received, [0, 0, 0, 4, 0, 0, 0, 1]);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn it_correlates_responses_across_clones() {
+        const REQUESTS_PER_CLONE: i32 = 50;
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let conn = connect(&listener).await;
+        let broker = tokio::spawn(echo_broker(listener, 4 * REQUESTS_PER_CLONE as usize));
+
+        let mut clients = vec![];
+        for client_id in ["first", "second"] {
+            let mut conn = conn.clone();
+            clients.push(tokio::spawn(async move {
+                for correlation_id in 0..REQUESTS_PER_CLONE {
+                    let request = HeaderRequest::new(3, 1, correlation_id, client_id);
+                    conn.send_request_(&request).await.unwrap();
+                    // pipeline a second request before reading the first response
+                    let request = HeaderRequest::new(3, 1, -correlation_id, client_id);
+                    conn.send_request_(&request).await.unwrap();
+
+                    for expected in [correlation_id, -correlation_id] {
+                        let mut response = conn.receive_response_().await.unwrap();
+                        assert_eq!(response.get_i32(), expected);
+                        assert_eq!(&response[..], client_id.as_bytes());
+                    }
+                }
+            }));
+        }
+
+        for client in clients {
+            client.await.unwrap();
+        }
+        broker.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn it_matches_responses_that_arrive_out_of_order() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut first = connect(&listener).await;
+        let mut second = first.clone();
+        let broker = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut requests = vec![];
+            for _ in 0..3 {
+                requests.push(read_request(&mut socket).await.unwrap());
+            }
+
+            // answer the last request first
+            for request in requests.iter().rev() {
+                write_response(&mut socket, request, client_id(request).as_bytes()).await;
+            }
+        });
+
+        for (correlation_id, client_id) in [(1, "a"), (2, "b")] {
+            let request = HeaderRequest::new(3, 1, correlation_id, client_id);
+            first.send_request_(&request).await.unwrap();
+        }
+        let request = HeaderRequest::new(3, 1, 1, "c");
+        second.send_request_(&request).await.unwrap();
+
+        let (first_responses, second_response) = tokio::join!(
+            async {
+                let a = first.receive_response_().await.unwrap();
+                let b = first.receive_response_().await.unwrap();
+                (a, b)
+            },
+            second.receive_response_(),
+        );
+        let (mut a, mut b) = first_responses;
+        let mut c = second_response.unwrap();
+
+        assert_eq!((a.get_i32(), &a[..]), (1, &b"a"[..]));
+        assert_eq!((b.get_i32(), &b[..]), (2, &b"b"[..]));
+        assert_eq!((c.get_i32(), &c[..]), (1, &b"c"[..]));
+        broker.await.unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn it_coalesces_writes() {
+        const CLONES: usize = 10;
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let conn = connect(&listener).await;
+        conn.coalesce_writes(Some(Duration::from_millis(50)));
+        let broker = tokio::spawn(echo_broker(listener, CLONES));
+
+        let mut clients = vec![];
+        for correlation_id in 0..CLONES as i32 {
+            let mut conn = conn.clone();
+            clients.push(tokio::spawn(async move {
+                let client_id = format!("client {correlation_id}");
+                let request = HeaderRequest::new(12, 0, correlation_id, &client_id);
+                conn.send_request_(&request).await.unwrap();
+
+                let mut response = conn.receive_response_().await.unwrap();
+                assert_eq!(response.get_i32(), correlation_id);
+                assert_eq!(&response[..], client_id.as_bytes());
+            }));
+        }
+
+        for client in clients {
+            client.await.unwrap();
+        }
+        broker.await.unwrap();
+
+        let queue = conn.queue();
+        assert!(queue.flushes < CLONES, "{} writes", queue.flushes);
+        assert!(queue.pending.is_empty());
+    }
+
+    #[tokio::test]
+    async fn it_applies_the_socket_options() {
+        let (_listener, port) = listen().await;
+        let addrs = vec![broker_address(port)];
+
+        for nodelay in [true, false] {
+            let options = SocketOptions::default()
+                .socket_recv_buffer_bytes(256 * 1024)
+                .socket_send_buffer_bytes(256 * 1024)
+                .tcp_nodelay(nodelay);
+            let conn = TcpConnection::with_socket_options(addrs.clone(), &options)
+                .await
+                .unwrap();
+
+            let writer = conn.writer.lock().await;
+            assert_eq!(writer.as_ref().nodelay().unwrap(), nodelay);
+        }
+    }
+
+    #[tokio::test]
+    async fn it_keeps_the_negotiated_api_versions() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut conn = connect(&listener).await;
+        let broker = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let request = read_request(&mut socket).await.unwrap();
+
+            let mut body = vec![];
+            body.put_i16(0); // error_code
+            body.put_u8(3);
+            for (api_key, min_version, max_version) in [(0, 3, 9), (18, 0, 3)] {
+                body.put_i16(api_key);
+                body.put_i16(min_version);
+                body.put_i16(max_version);
+                body.put_u8(0);
+            }
+            body.put_i32(0); // throttle_time_ms
+            body.put_u8(0);
+            write_response(&mut socket, &request, &body).await;
+            request
+        });
+        assert!(conn.api_versions().is_empty());
+
+        conn.negotiate_api_versions().await.unwrap();
+
+        let request = broker.await.unwrap();
+        assert_eq!(&request[..2], &[0, 18]);
+        let api_versions = conn.clone().api_versions();
+        assert_eq!(api_versions.len(), 2);
+        assert_eq!(api_versions[&0], (3, 9));
+    }
+
+    /// ApiVersions (Version: 0), which has an empty request body.
+    struct ApiVersionsRequest;
+
+    impl ToByte for ApiVersionsRequest {
+        fn encode<T: bytes::BufMut>(&self, _buffer: &mut T) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct ApiVersionsResponse {
+        error_code: i16,
+        api_keys: Vec<(i16, i16, i16)>,
+    }
+
+    impl FromByte for ApiVersionsResponse {
+        fn decode(mut body: bytes::Bytes) -> Result<Self> {
+            let error_code = body.get_i16();
+            let api_keys = (0..body.get_i32())
+                .map(|_| (body.get_i16(), body.get_i16(), body.get_i16()))
+                .collect();
+            Ok(Self {
+                error_code,
+                api_keys,
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn it_sends_requests_for_unwrapped_apis() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut conn = connect(&listener).await;
+        let broker = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let request = read_request(&mut socket).await.unwrap();
+
+            let body = [0, 0, 0, 0, 0, 1, 0, 18, 0, 0, 0, 3];
+            write_response(&mut socket, &request, &body).await;
+            request
+        });
+
+        let response: ApiVersionsResponse = conn
+            .send_custom_request(18, 0, &ApiVersionsRequest)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            response,
+            ApiVersionsResponse {
+                error_code: 0,
+                api_keys: vec![(18, 0, 3)],
+            }
+        );
+        // api_key, api_version, correlation_id, client_id and nothing else
+        let request = broker.await.unwrap();
+        assert_eq!(&request[..4], &[0, 18, 0, 0]);
+        assert_eq!(&request[8..], &[0, 5, b's', b'a', b'm', b's', b'a']);
     }
 }
@@ -1,28 +1,44 @@
-use std::fs::File;
-use std::io::BufReader;
+//! TLS transport, backed by rustls with the `tls-rustls` feature or by the
+//! platform's native TLS library with the `tls-native-tls` feature.
+//!
+//! Both backends expose the same [`TlsConnection`]. Only one of the features
+//! may be enabled, otherwise both libraries would be linked in.
+
 use std::io::ErrorKind;
-use std::path::{Path, PathBuf};
-use std::{io, sync::Arc};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::{MutexGuard, PoisonError};
 
 use async_trait::async_trait;
 use bytes::BytesMut;
-use rustls_pemfile::{certs, pkcs8_private_keys};
-use rustls_pki_types::{CertificateDer, PrivateKeyDer};
 use std::net::ToSocketAddrs;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::io::{AsyncReadExt, AsyncWriteExt, ReadHalf, WriteHalf};
 use tokio::net::TcpStream;
 use tokio::sync::Mutex;
-use tokio_rustls::{client::TlsStream, rustls, TlsConnector};
 
 use crate::{
-    encode::ToByte,
+    encode::{try_usize_to_int, ToByte},
     error::{Error, Result},
 };
 
-use super::sasl::do_sasl;
+use super::multiplex::Multiplexer;
 use super::sasl::SaslConfig;
+use super::sasl::SaslSession;
 use super::{BrokerAddress, BrokerConnection};
 
+#[cfg(all(feature = "tls-rustls", feature = "tls-native-tls"))]
+compile_error!("the `tls-rustls` and `tls-native-tls` features are mutually exclusive");
+
+#[cfg(all(feature = "tls-native-tls", not(feature = "tls-rustls")))]
+mod native_tls;
+#[cfg(feature = "tls-rustls")]
+mod rustls;
+
+#[cfg(all(feature = "tls-native-tls", not(feature = "tls-rustls")))]
+use self::native_tls::{TlsConnector, TlsStream};
+#[cfg(feature = "tls-rustls")]
+use self::rustls::{TlsConnector, TlsStream};
+
 /// TLS connection to a Kafka/Redpanda broker.
 ///
 /// # Example
@@ -38,9 +54,35 @@ use super::{BrokerAddress, BrokerConnection};
 ///         cafile: Some("/path_to_ca_file".into()),
 ///     };
 /// ```
-#[derive(Clone, Debug)]
+///
+/// Cloning a connection does not open a new socket. Every clone is a handle
+/// onto the same socket, and responses are routed back to the handle that
+/// sent the matching request.
+#[derive(Debug)]
 pub struct TlsConnection {
-    stream: Arc<Mutex<TlsStream<TcpStream>>>,
+    reader: Arc<Mutex<ReadHalf<TlsStream>>>,
+    writer: Arc<Mutex<WriteHalf<TlsStream>>>,
+    mux: Arc<std::sync::Mutex<Multiplexer>>,
+    handle: usize,
+}
+
+impl Clone for TlsConnection {
+    fn clone(&self) -> Self {
+        let handle = self.mux().register_handle();
+        Self {
+            reader: self.reader.clone(),
+            writer: self.writer.clone(),
+            mux: self.mux.clone(),
+            handle,
+        }
+    }
+}
+
+impl Drop for TlsConnection {
+    fn drop(&mut self) {
+        let handle = self.handle;
+        self.mux().release_handle(handle);
+    }
 }
 
 /// TLS connection options.
@@ -68,16 +110,7 @@ impl TlsConnection {
         );
         let mut propagated_err: Option<Error> = None;
 
-        // need to figure out what this is
-        let mut root_cert_store = rustls::RootCertStore::empty();
-        if let Some(cafile) = &options.cafile {
-            let mut pem = BufReader::new(File::open(cafile).unwrap());
-            for cert in rustls_pemfile::certs(&mut pem) {
-                root_cert_store.add(cert.unwrap()).unwrap();
-            }
-        } else {
-            root_cert_store.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
-        }
+        let connector = TlsConnector::new(&options)?;
 
         for broker_option in options.broker_options.iter() {
             let addr = (broker_option.host.as_str(), broker_option.port)
@@ -88,35 +121,21 @@ impl TlsConnection {
                 .unwrap();
 
             tracing::debug!("Connecting to {}", broker_option.host);
-            let certs = load_certs(&options.cert).map_err(|e| Error::IoError(e.kind()))?;
-            let key = load_keys(&options.key).map_err(|e| Error::IoError(e.kind()))?;
-            tracing::debug!("keys ready");
-
             match TcpStream::connect(addr).await {
                 Ok(s) => {
                     tracing::debug!("connected on tcp");
 
-                    let config = rustls::ClientConfig::builder()
-                        .with_root_certificates(root_cert_store)
-                        .with_client_auth_cert(certs, key)
-                        .unwrap();
-
-                    let connector = TlsConnector::from(Arc::new(config));
-                    tracing::debug!("tls connected");
-
-                    let domain = rustls_pki_types::ServerName::try_from(broker_option.host.clone())
-                        .map_err(|_| Error::IoError(ErrorKind::InvalidInput))?
-                        .to_owned();
-                    tracing::debug!("dns ready");
-
-                    let stream = connector
-                        .connect(domain, s)
-                        .await
-                        .map_err(|e| Error::IoError(e.kind()))?;
+                    let stream = connector.connect(&broker_option.host, s).await?;
                     tracing::debug!("tls connected to tcp");
 
+                    let (reader, writer) = tokio::io::split(stream);
+                    let mut mux = Multiplexer::default();
+                    let handle = mux.register_handle();
                     return Ok(Self {
-                        stream: Arc::new(Mutex::new(stream)),
+                        reader: Arc::new(Mutex::new(reader)),
+                        writer: Arc::new(Mutex::new(writer)),
+                        mux: Arc::new(std::sync::Mutex::new(mux)),
+                        handle,
                     });
                 }
                 Err(e) => {
@@ -133,6 +152,11 @@ impl TlsConnection {
         Err(Error::IoError(ErrorKind::NotFound))
     }
 
+    fn mux(&self) -> MutexGuard<'_, Multiplexer> {
+        // the bookkeeping is never left half updated, so a poisoned lock is still usable
+        self.mux.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
     /// Serialize a given request and send to Kafka/Redpanda broker.
     ///
     /// The Kafka protocol specifies that all requests will
@@ -157,11 +181,12 @@ impl TlsConnection {
         buffer.extend_from_slice(&[0, 0, 0, 0]);
         req.encode(&mut buffer)?;
 
-        let size = buffer.len() as i32 - 4;
+        let size = try_usize_to_int!(buffer.len() - 4, i32);
         size.encode(&mut &mut buffer[..])?;
+        self.mux().tag_request(self.handle, &mut buffer);
 
         tracing::trace!("Sending bytes {}", buffer.len());
-        self.stream
+        self.writer
             .lock()
             .await
             .write_all(&buffer)
@@ -186,39 +211,58 @@ impl TlsConnection {
     /// let response_bytes = conn.receive_response().await?;
     /// ```
     pub async fn receive_response_(&mut self) -> Result<BytesMut> {
-        // figure out the message size
-        let mut stream = self.stream.lock().await;
+        loop {
+            if let Some(response) = self.mux().take_ready(self.handle) {
+                return Ok(response);
+            }
 
-        let length = stream
-            .read_u32()
-            .await
-            .map_err(|e| Error::IoError(e.kind()))?;
+            let mut stream = self.reader.lock().await;
+            // another handle may have read our response while we waited
+            if let Some(response) = self.mux().take_ready(self.handle) {
+                return Ok(response);
+            }
 
-        tracing::trace!("Reading {} bytes", length);
-        let mut buffer = BytesMut::zeroed(length as usize);
-        tracing::trace!("before {:?}", buffer);
+            // figure out the message size
+            let length = stream
+                .read_u32()
+                .await
+                .map_err(|e| Error::IoError(e.kind()))?;
+
+            tracing::trace!("Reading {} bytes", length);
+            let mut buffer = BytesMut::zeroed(length as usize);
+            tracing::trace!("before {:?}", buffer);
+
+            stream
+                .read_exact(&mut buffer)
+                .await
+                .map_err(|e| Error::IoError(e.kind()))?;
+            tracing::trace!("Read {:?}", buffer);
+
+            // route while still holding the stream, so a handle waiting on it
+            // will find its response once it gets the lock
+            if let Some(response) = self.mux().route_response(self.handle, buffer)? {
+                return Ok(response);
+            }
+        }
+    }
 
-        stream
-            .read_exact(&mut buffer)
+    /// Close the connection to the Kafka/Redpanda broker.
+    ///
+    /// Sends the TLS close_notify alert and shuts down the write half of
+    /// the underlying socket.
+    pub async fn close_(self) -> Result<()> {
+        tracing::debug!("Closing connection");
+        self.writer
+            .lock()
+            .await
+            .shutdown()
             .await
             .map_err(|e| Error::IoError(e.kind()))?;
-        tracing::trace!("Read {:?}", buffer);
 
-        Ok(buffer)
+        Ok(())
     }
 }
 
-fn load_certs(path: &Path) -> io::Result<Vec<CertificateDer<'static>>> {
-    certs(&mut BufReader::new(File::open(path)?)).collect()
-}
-
-fn load_keys(path: &Path) -> io::Result<PrivateKeyDer<'static>> {
-    pkcs8_private_keys(&mut BufReader::new(File::open(path)?))
-        .next()
-        .unwrap()
-        .map(Into::into)
-}
-
 #[async_trait]
 impl BrokerConnection for TlsConnection {
     type ConnConfig = TlsConnectionOptions;
@@ -231,6 +275,10 @@ impl BrokerConnection for TlsConnection {
         self.receive_response_().await
     }
 
+    async fn close(self) -> Result<()> {
+        self.close_().await
+    }
+
     async fn new(p: Self::ConnConfig) -> Result<Self> {
         Self::new_(p).await
     }
@@ -280,6 +328,7 @@ pub struct SaslTlsConfig {
 #[derive(Clone, Debug)]
 pub struct SaslTlsConnection {
     tls_conn: TlsConnection,
+    session: SaslSession,
 }
 
 #[async_trait]
@@ -294,17 +343,19 @@ impl BrokerConnection for SaslTlsConnection {
         self.tls_conn.receive_response_().await
     }
 
+    async fn close(self) -> Result<()> {
+        self.session.stop();
+        self.tls_conn.close_().await
+    }
+
     /// Connect to a Kafka/Redpanda broker
     async fn new(p: Self::ConnConfig) -> Result<Self> {
         let conn = TlsConnection::new_(p.tls_config).await?;
-        do_sasl(
-            conn.clone(),
-            p.sasl_config.correlation_id,
-            &p.sasl_config.client_id,
-            p.sasl_config.clone(),
-        )
-        .await?;
-        Ok(Self { tls_conn: conn })
+        let session = SaslSession::start(conn.clone(), p.sasl_config).await?;
+        Ok(Self {
+            tls_conn: conn,
+            session,
+        })
     }
 
     async fn from_addr(p: Self::ConnConfig, addr: BrokerAddress) -> Result<Self> {
@@ -317,13 +368,10 @@ impl BrokerConnection for SaslTlsConnection {
             cafile,
         };
         let conn = TlsConnection::new_(options).await?;
-        do_sasl(
-            conn.clone(),
-            p.sasl_config.correlation_id,
-            &p.sasl_config.client_id,
-            p.sasl_config.clone(),
-        )
-        .await?;
-        Ok(Self { tls_conn: conn })
+        let session = SaslSession::start(conn.clone(), p.sasl_config).await?;
+        Ok(Self {
+            tls_conn: conn,
+            session,
+        })
     }
 }
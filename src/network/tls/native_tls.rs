@@ -0,0 +1,91 @@
+//! TLS backend built on the platform's native TLS library, e.g. OpenSSL.
+
+use std::io::ErrorKind;
+use std::path::Path;
+
+use tokio::net::TcpStream;
+use tokio_native_tls::native_tls::{self, Certificate, Identity};
+
+use super::TlsConnectionOptions;
+use crate::error::{Error, Result};
+
+pub(super) type TlsStream = tokio_native_tls::TlsStream<TcpStream>;
+
+/// Client config shared by the connections to every broker.
+pub(super) struct TlsConnector(tokio_native_tls::TlsConnector);
+
+impl TlsConnector {
+    pub(super) fn new(options: &TlsConnectionOptions) -> Result<Self> {
+        let mut builder = native_tls::TlsConnector::builder();
+        // without a CA file, the certificates trusted by the system are used
+        if let Some(cafile) = &options.cafile {
+            let ca = Certificate::from_pem(&read(cafile)?)
+                .map_err(|_| Error::IoError(ErrorKind::InvalidData))?;
+            builder.add_root_certificate(ca);
+        }
+
+        let identity = Identity::from_pkcs8(&read(&options.cert)?, &read(&options.key)?)
+            .map_err(|_| Error::IoError(ErrorKind::InvalidData))?;
+        builder.identity(identity);
+        tracing::debug!("keys ready");
+
+        let connector = builder
+            .build()
+            .map_err(|_| Error::IoError(ErrorKind::InvalidData))?;
+
+        Ok(Self(connector.into()))
+    }
+
+    pub(super) async fn connect(&self, host: &str, stream: TcpStream) -> Result<TlsStream> {
+        self.0.connect(host, stream).await.map_err(|err| {
+            tracing::error!("TLS handshake with {} failed: {}", host, err);
+            Error::IoError(handshake_error_kind(&err))
+        })
+    }
+}
+
+/// The kind of the io error under a failed handshake, or invalid data when
+/// the handshake itself failed, e.g. on a certificate or hostname that does
+/// not verify, like the rustls backend reports it.
+fn handshake_error_kind(err: &native_tls::Error) -> ErrorKind {
+    let mut source = std::error::Error::source(err);
+    while let Some(err) = source {
+        if let Some(io_err) = err.downcast_ref::<std::io::Error>() {
+            return io_err.kind();
+        }
+        source = err.source();
+    }
+    ErrorKind::InvalidData
+}
+
+fn read(path: &Path) -> Result<Vec<u8>> {
+    std::fs::read(path).map_err(|e| Error::IoError(e.kind()))
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn it_tells_a_failed_handshake_from_a_refused_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            // a plaintext listener answers the client hello with garbage
+            socket
+                .write_all(b"HTTP/1.1 400 Bad Request\r\n\r\n")
+                .await
+                .unwrap();
+        });
+        let connector = TlsConnector(native_tls::TlsConnector::new().unwrap().into());
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let result = connector.connect("localhost", stream).await;
+
+        assert_eq!(result.err(), Some(Error::IoError(ErrorKind::InvalidData)));
+    }
+}
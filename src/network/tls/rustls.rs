@@ -0,0 +1,67 @@
+//! TLS backend built on rustls.
+
+use std::fs::File;
+use std::io::{self, BufReader, ErrorKind};
+use std::path::Path;
+use std::sync::Arc;
+
+use rustls_pemfile::{certs, pkcs8_private_keys};
+use rustls_pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use tokio::net::TcpStream;
+use tokio_rustls::rustls;
+
+use super::TlsConnectionOptions;
+use crate::error::{Error, Result};
+
+pub(super) type TlsStream = tokio_rustls::client::TlsStream<TcpStream>;
+
+/// Client config shared by the connections to every broker.
+pub(super) struct TlsConnector(tokio_rustls::TlsConnector);
+
+impl TlsConnector {
+    pub(super) fn new(options: &TlsConnectionOptions) -> Result<Self> {
+        let mut root_cert_store = rustls::RootCertStore::empty();
+        if let Some(cafile) = &options.cafile {
+            for cert in load_certs(cafile).map_err(|e| Error::IoError(e.kind()))? {
+                root_cert_store
+                    .add(cert)
+                    .map_err(|_| Error::IoError(ErrorKind::InvalidData))?;
+            }
+        } else {
+            root_cert_store.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+        }
+
+        let certs = load_certs(&options.cert).map_err(|e| Error::IoError(e.kind()))?;
+        let key = load_keys(&options.key).map_err(|e| Error::IoError(e.kind()))?;
+        tracing::debug!("keys ready");
+
+        let config = rustls::ClientConfig::builder()
+            .with_root_certificates(root_cert_store)
+            .with_client_auth_cert(certs, key)
+            .map_err(|_| Error::IoError(ErrorKind::InvalidData))?;
+
+        Ok(Self(tokio_rustls::TlsConnector::from(Arc::new(config))))
+    }
+
+    pub(super) async fn connect(&self, host: &str, stream: TcpStream) -> Result<TlsStream> {
+        let domain = ServerName::try_from(host.to_owned())
+            .map_err(|_| Error::IoError(ErrorKind::InvalidInput))?;
+        tracing::debug!("dns ready");
+
+        self.0
+            .connect(domain, stream)
+            .await
+            .map_err(|e| Error::IoError(e.kind()))
+    }
+}
+
+fn load_certs(path: &Path) -> io::Result<Vec<CertificateDer<'static>>> {
+    certs(&mut BufReader::new(File::open(path)?)).collect()
+}
+
+fn load_keys(path: &Path) -> io::Result<PrivateKeyDer<'static>> {
+    pkcs8_private_keys(&mut BufReader::new(File::open(path)?))
+        .next()
+        .ok_or_else(|| io::Error::from(ErrorKind::InvalidData))?
+        .map(Into::into)
+}
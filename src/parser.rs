@@ -13,7 +13,14 @@ use nom::{
 use nombytes::NomBytes;
 use num_traits::FromPrimitive;
 
-use crate::error::KafkaCode;
+use crate::error::{KafkaCode, Result};
+
+/// Deserialize a response from the bytecode protocol.
+pub trait FromByte: Sized {
+    /// Decode the body of a response, i.e. the bytes following the
+    /// response header.
+    fn decode(body: Bytes) -> Result<Self>;
+}
 
 pub fn parse_kafka_code(s: NomBytes) -> IResult<NomBytes, KafkaCode> {
     map(be_i16, |n| match FromPrimitive::from_i16(n) {
@@ -65,6 +72,14 @@ pub fn parse_bytes(s: NomBytes) -> IResult<NomBytes, Bytes> {
     Ok((s, string.into_bytes()))
 }
 
+/// Parse a UUID, 16 bytes without a length prefix, e.g. a topic id.
+pub fn parse_uuid(s: NomBytes) -> IResult<NomBytes, [u8; 16]> {
+    let (s, bytes) = take(16_usize)(s)?;
+    let mut uuid = [0; 16];
+    uuid.copy_from_slice(&bytes.into_bytes());
+    Ok((s, uuid))
+}
+
 pub fn parse_array<O, E, F>(f: F) -> impl FnMut(NomBytes) -> IResult<NomBytes, Vec<O>, E>
 where
     F: nom::Parser<NomBytes, O, E> + Copy,
@@ -121,6 +136,52 @@ pub fn parse_nullable_bytes(s: NomBytes) -> IResult<NomBytes, Option<Bytes>> {
     Ok((s, Some(bytes.into_bytes())))
 }
 
+pub fn parse_compact_string(s: NomBytes) -> IResult<NomBytes, Bytes> {
+    let (s, length) = take_varint(s)?;
+    let (s, string) = take(length.saturating_sub(1))(s)?;
+    Ok((s, string.into_bytes()))
+}
+
+pub fn parse_compact_nullable_string(s: NomBytes) -> IResult<NomBytes, Option<Bytes>> {
+    let (s, length) = take_varint(s)?;
+    if length == 0 {
+        return Ok((s, None));
+    }
+
+    let (s, string) = take(length - 1)(s)?;
+    Ok((s, Some(string.into_bytes())))
+}
+
+pub fn parse_compact_array<O, E, F>(f: F) -> impl FnMut(NomBytes) -> IResult<NomBytes, Vec<O>, E>
+where
+    F: nom::Parser<NomBytes, O, E> + Copy,
+    E: nom::error::ParseError<NomBytes>,
+{
+    move |input: NomBytes| {
+        let i = input.clone();
+        let (i, length) = take_varint(i)?;
+        // 0 is a null array
+        if length <= 1 {
+            return Ok((i, vec![]));
+        }
+        many_m_n(length - 1, length - 1, f)(i)
+    }
+}
+
+/// Skip over the tagged fields at the end of a structure in flexible versions.
+///
+/// None of the tagged fields are used yet, so they are thrown away.
+pub fn parse_tagged_fields(s: NomBytes) -> IResult<NomBytes, ()> {
+    let (mut s, count) = take_varint(s)?;
+    for _ in 0..count {
+        let (rest, _tag) = take_varint(s)?;
+        let (rest, size) = take_varint(rest)?;
+        let (rest, _) = take(size)(rest)?;
+        s = rest;
+    }
+    Ok((s, ()))
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -203,4 +264,47 @@ mod test {
             vec![String::from("rust"), String::from("rust")]
         );
     }
+
+    #[test]
+    fn test_parse_compact_string() {
+        let buf = NomBytes::from(b"\x05\x72\x75\x73\x74\x00" as &[u8]);
+
+        assert_eq!(
+            parse_compact_string(buf),
+            Ok((
+                NomBytes::from(b"\x00" as &[u8]),
+                Bytes::from_static(b"rust")
+            ))
+        );
+    }
+
+    #[test]
+    fn test_parse_compact_nullable_string() {
+        let buf = NomBytes::from(b"\x00" as &[u8]);
+
+        assert_eq!(parse_compact_nullable_string(buf).unwrap().1, None);
+    }
+
+    #[test]
+    fn test_parse_compact_array() {
+        let buf = NomBytes::from([3, 0, 0, 0, 1, 0, 0, 0, 2].as_ref());
+
+        assert_eq!(
+            parse_compact_array(be_i32::<NomBytes, nom::error::Error<NomBytes>>)(buf)
+                .unwrap()
+                .1,
+            vec![1, 2]
+        );
+    }
+
+    #[test]
+    fn test_parse_tagged_fields() {
+        // two fields, tag 0 with 2 bytes and tag 1 with 1 byte
+        let buf = NomBytes::from([2, 0, 2, 9, 9, 1, 1, 9, 7].as_ref());
+
+        assert_eq!(
+            parse_tagged_fields(buf),
+            Ok((NomBytes::from([7].as_ref()), ()))
+        );
+    }
 }
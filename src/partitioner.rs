@@ -0,0 +1,77 @@
+//! Partition selection for keyed records.
+//!
+//! Uses the same murmur2 hash as the default partitioner of the Java client
+//! and librdkafka, so records with the same key land on the same partition
+//! no matter which client produced them.
+
+const SEED: u32 = 0x9747b28c;
+const M: u32 = 0x5bd1e995;
+const R: u32 = 24;
+
+/// The 32-bit murmur2 hash of the key bytes, as computed by the Java client.
+pub fn murmur2(data: &[u8]) -> i32 {
+    let length = data.len();
+    let mut h = SEED ^ length as u32;
+
+    let mut chunks = data.chunks_exact(4);
+    for chunk in &mut chunks {
+        let mut k = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        k = k.wrapping_mul(M);
+        k ^= k >> R;
+        k = k.wrapping_mul(M);
+        h = h.wrapping_mul(M);
+        h ^= k;
+    }
+
+    let tail = chunks.remainder();
+    if tail.len() == 3 {
+        h ^= (tail[2] as u32) << 16;
+    }
+    if tail.len() >= 2 {
+        h ^= (tail[1] as u32) << 8;
+    }
+    if !tail.is_empty() {
+        h ^= tail[0] as u32;
+        h = h.wrapping_mul(M);
+    }
+
+    h ^= h >> 13;
+    h = h.wrapping_mul(M);
+    h ^= h >> 15;
+
+    h as i32
+}
+
+/// The partition the default partitioner picks for a key.
+pub fn partition_for_key(key: &[u8], partition_count: usize) -> i32 {
+    // the Java client drops the sign bit rather than taking the absolute value
+    let hash = (murmur2(key) & 0x7fffffff) as usize;
+    (hash % partition_count) as i32
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_hashes_like_the_java_client() {
+        assert_eq!(murmur2(b"21"), -973932308);
+        assert_eq!(murmur2(b"foobar"), -790332482);
+        assert_eq!(murmur2(b"a-little-bit-long-string"), -985981536);
+        assert_eq!(murmur2(b"a-little-bit-longer-string"), -1486304829);
+        assert_eq!(
+            murmur2(b"lkjh234lh9fiuh90y23oiuhsafujhadof229phr9h19h89h8"),
+            -58897971
+        );
+        assert_eq!(murmur2(b"abc"), 479470107);
+    }
+
+    #[test]
+    fn it_picks_the_partition_for_a_key() {
+        assert_eq!(partition_for_key(b"foobar", 10), 6);
+        assert_eq!(partition_for_key(b"21", 10), 0);
+        // not valid UTF-8, the key is hashed as raw bytes
+        assert_eq!(murmur2(&[0xff, 0xfe, 0x00, 0x80, 0x7f]), 998457672);
+        assert_eq!(partition_for_key(&[0xff, 0xfe, 0x00, 0x80, 0x7f], 7), 2);
+    }
+}
@@ -1,24 +1,49 @@
 //! Client that sends records to a cluster.
 
-use std::{collections::HashMap, fmt::Debug};
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::Debug,
+    future::Future,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
 
 use bytes::Bytes;
 use tokio::{
-    sync::mpsc::{Sender, UnboundedReceiver},
-    task::JoinSet,
+    sync::{
+        mpsc::{channel, Sender, UnboundedReceiver},
+        oneshot,
+    },
+    task::{JoinHandle, JoinSet},
 };
 use tracing::instrument;
 
 use crate::{
-    error::{Error, Result},
+    consumer::TopicPartition,
+    consumer_group_builder::{find_coordinator_broker, MAX_COORDINATOR_RETRIES},
+    encode::{try_usize_to_int, ToByte},
+    error::{Error, KafkaCode, Result},
     metadata::ClusterMetadata,
     network::BrokerConnection,
-    protocol::{produce::request::Attributes, Header, ProduceRequest, ProduceResponse},
+    partitioner::partition_for_key,
+    prelude::Compression,
+    protocol::{
+        self,
+        produce::request::{BatchProducer, RecordBatchAttributes},
+        Header, ProduceRequest, ProduceResponse,
+    },
+    utils::retry_backoff,
     DEFAULT_CLIENT_ID, DEFAULT_CORRELATION_ID,
 };
 
 const DEFAULT_REQUIRED_ACKS: i16 = 0;
 const DEFAULT_TIMEOUT_MS: i32 = 1000;
+/// How many times to refresh metadata and retry a partition whose leader is stale.
+const MAX_STALE_METADATA_RETRIES: usize = 3;
+/// Only used by transactional producers, the broker ignores it otherwise.
+const DEFAULT_TRANSACTION_TIMEOUT_MS: i32 = 60000;
 
 #[derive(Clone)]
 pub(crate) struct ProduceParams {
@@ -26,6 +51,51 @@ pub(crate) struct ProduceParams {
     pub client_id: String,
     pub required_acks: i16,
     pub timeout_ms: i32,
+    pub validate_partitions: bool,
+    /// The largest key a message may have, unchecked when `None`.
+    pub max_key_bytes: Option<usize>,
+    /// The largest value a message may have, unchecked when `None`.
+    pub max_value_bytes: Option<usize>,
+    /// The producer id and sequence numbers, when producing idempotently.
+    pub idempotence: Option<Arc<Mutex<IdempotentProducer>>>,
+    /// Registered with the producer id, when producing transactionally.
+    pub transactional_id: Option<String>,
+    pub transaction_timeout_ms: i32,
+    /// Records handed to a [`Producer`] that have not been flushed yet.
+    pub unflushed_records: Option<Arc<AtomicUsize>>,
+    /// Bytes reserved for each record batch when it is encoded.
+    pub initial_batch_capacity: usize,
+    /// The Produce version to send, record batches need version 3.
+    pub produce_version: i16,
+    /// Called with each message before it is sent and once it is acknowledged.
+    pub interceptors: Vec<Arc<dyn Interceptor>>,
+    /// Counts the messages without a key nor a partition, to spread them
+    /// round-robin over the partitions of their topic.
+    pub next_partition: Arc<AtomicUsize>,
+    /// Added to each message that does not have a header with the same key.
+    pub default_headers: Vec<Header>,
+}
+
+/// How each topic partition was written, one part after the other in the
+/// order of its messages: the records in the part and the base offset it was
+/// written at, -1 without acks, or the error it was rejected with.
+///
+/// A batch split for being too large is written in several parts.
+pub(crate) type WrittenParts = HashMap<TopicPartition, Vec<(usize, PartResult)>>;
+
+type PartResult = std::result::Result<i64, KafkaCode>;
+
+/// The state of an idempotent producer, shared between the [`Producer`]
+/// and its background worker.
+#[derive(Debug, Default)]
+pub(crate) struct IdempotentProducer {
+    /// The producer id and epoch, once the cluster has handed them out.
+    pub producer: Option<(i64, i16)>,
+    /// The sequence number of the next record written to each topic partition.
+    pub sequences: HashMap<TopicPartition, i32>,
+    /// Set once a newer instance fenced the producer, after which it refuses
+    /// to write.
+    pub fenced: bool,
 }
 
 impl ProduceParams {
@@ -35,6 +105,18 @@ impl ProduceParams {
             client_id: DEFAULT_CLIENT_ID.to_owned(),
             required_acks: DEFAULT_REQUIRED_ACKS,
             timeout_ms: DEFAULT_TIMEOUT_MS,
+            validate_partitions: false,
+            max_key_bytes: None,
+            max_value_bytes: None,
+            idempotence: None,
+            transactional_id: None,
+            transaction_timeout_ms: DEFAULT_TRANSACTION_TIMEOUT_MS,
+            unflushed_records: None,
+            initial_batch_capacity: 0,
+            produce_version: protocol::produce::request::API_VERSION,
+            interceptors: vec![],
+            next_partition: Arc::new(AtomicUsize::new(0)),
+            default_headers: vec![],
         }
     }
 }
@@ -82,11 +164,55 @@ impl ProduceParams {
 /// tokio::pin!(output_stream);
 /// while (output_stream.next().await).is_some() {}
 /// ```
+///
+/// Call [`close`](Self::close) before dropping a producer, so the records
+/// that are still buffered get flushed.
 pub struct Producer {
     /// Direct connection to the background worker.
-    pub sender: Sender<ProduceMessage>,
+    pub sender: Sender<(ProduceMessage, Option<DeliverySender>)>,
     /// Responses of the
     pub receiver: UnboundedReceiver<Vec<Option<ProduceResponse>>>,
+    pub(crate) unflushed_records: Arc<AtomicUsize>,
+    /// The background worker, until the producer is closed.
+    pub(crate) worker: Option<JoinHandle<()>>,
+    pub(crate) idempotence: Option<Arc<Mutex<IdempotentProducer>>>,
+}
+
+/// Called with each message that could not be delivered, once retries are
+/// exhausted, together with the reason it failed.
+pub type DeliveryFailureCallback = Arc<dyn Fn(ProduceMessage, Error) + Send + Sync>;
+
+/// Resolved with the partition and offset a message was written at, once
+/// the response to its batch arrives.
+pub type DeliverySender = oneshot::Sender<Result<(i32, i64)>>;
+
+/// The outcome of producing a message, handed to
+/// [`Interceptor::on_acknowledgement`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct DeliveryReport {
+    pub topic: String,
+    /// The partition the message was written to, the one the producer
+    /// picked for a message produced with a partition of -1.
+    pub partition_id: i32,
+    /// The offset the message was written at, -1 without acks, or the
+    /// reason it could not be delivered.
+    pub offset: Result<i64>,
+}
+
+/// Hooks into every message a [`Producer`] sends, e.g. to add tracing
+/// headers or to count deliveries.
+///
+/// Interceptors run on the background worker in the order they were added
+/// to the [`ProducerBuilder`](crate::prelude::ProducerBuilder), so they
+/// should be quick and must not block.
+pub trait Interceptor: Send + Sync {
+    /// Called with each message before it is partitioned and batched. Changes
+    /// made to the message are what gets written.
+    fn on_send(&self, _message: &mut ProduceMessage) {}
+
+    /// Called with each message once the broker answered for its batch, or
+    /// the batch could not be written.
+    fn on_acknowledgement(&self, _report: &DeliveryReport) {}
 }
 
 /// Common produce message format.
@@ -96,28 +222,417 @@ pub struct ProduceMessage {
     pub value: Option<Bytes>,
     pub headers: Vec<Header>,
     pub topic: String,
+    /// The partition to write to. With -1, a keyed message goes to the
+    /// partition the murmur2 hash of its key picks, like the Java client,
+    /// and a message without a key goes round-robin to the partitions that
+    /// have a leader.
     pub partition_id: i32,
 }
 
 impl Producer {
+    /// Produce a message without waiting for it to be written.
+    ///
+    /// Messages that cannot be delivered, including every message once the
+    /// producer is [fenced](Self::is_fenced), are handed to the
+    /// [`on_delivery_failure`](crate::prelude::ProducerBuilder::on_delivery_failure)
+    /// callback.
     pub async fn produce(&self, message: ProduceMessage) {
-        if self.sender.send(message).await.is_err() {
+        // counted before sending, so the worker never flushes it first
+        self.unflushed_records.fetch_add(1, Ordering::SeqCst);
+        if self.sender.send((message, None)).await.is_err() {
+            self.unflushed_records.fetch_sub(1, Ordering::SeqCst);
             tracing::warn!("Producer has hung up channel");
         }
     }
+
+    /// Produce a message and wait until it is written, returning the
+    /// partition and offset it was written at.
+    ///
+    /// The message is batched with the others like [`produce`](Self::produce)
+    /// does, the future resolves when the response to its batch arrives.
+    /// Without acks the broker does not answer, so the offset is -1.
+    pub async fn send(&self, message: ProduceMessage) -> Result<(i32, i64)> {
+        if self.is_fenced() {
+            return Err(Error::ProducerFenced);
+        }
+        let (delivery_sender, delivery) = oneshot::channel();
+        self.unflushed_records.fetch_add(1, Ordering::SeqCst);
+        if self
+            .sender
+            .send((message, Some(delivery_sender)))
+            .await
+            .is_err()
+        {
+            self.unflushed_records.fetch_sub(1, Ordering::SeqCst);
+            return Err(Error::ProducerClosed);
+        }
+
+        delivery.await.map_err(|_| Error::ProducerClosed)?
+    }
+
+    /// Whether a newer instance fenced this idempotent or transactional
+    /// producer.
+    ///
+    /// A fenced producer does not retry, every record handed to it from then
+    /// on fails with [`Error::ProducerFenced`]. It has to be replaced by a new
+    /// producer.
+    pub fn is_fenced(&self) -> bool {
+        match &self.idempotence {
+            Some(idempotence) => idempotence.lock().unwrap().fenced,
+            None => false,
+        }
+    }
+
+    /// How many of the produced records have not been flushed yet.
+    pub fn unflushed_records(&self) -> usize {
+        self.unflushed_records.load(Ordering::SeqCst)
+    }
+
+    /// Flush the buffered records and stop the background worker.
+    ///
+    /// Responses to the flushed records can still be read from the
+    /// [`receiver`](Self::receiver) afterwards, while new records are refused.
+    pub async fn close(&mut self) {
+        // dropping the only sender ends the stream of the worker
+        let (closed, _) = channel(1);
+        drop(std::mem::replace(&mut self.sender, closed));
+
+        if let Some(worker) = self.worker.take() {
+            if let Err(err) = worker.await {
+                tracing::error!("Error closing producer worker {:?}", err);
+            }
+        }
+    }
+
+    /// The sequence number the next record written to a topic partition
+    /// will get, or `None` if the producer is not idempotent or has not
+    /// written to the partition yet.
+    #[cfg(feature = "test-internals")]
+    pub fn current_sequence(&self, topic_partition: &TopicPartition) -> Option<i32> {
+        let idempotence = self.idempotence.as_ref()?;
+        let state = idempotence.lock().unwrap();
+        state.sequences.get(topic_partition).copied()
+    }
+}
+
+impl Drop for Producer {
+    fn drop(&mut self) {
+        let unflushed_records = self.unflushed_records();
+        if self.worker.is_none() || unflushed_records == 0 {
+            return;
+        }
+
+        // the worker keeps flushing in the background, unless the runtime
+        // shuts down first
+        if tokio::runtime::Handle::try_current().is_err() {
+            tracing::warn!(
+                "Producer dropped outside of a runtime, {} unflushed records are lost",
+                unflushed_records
+            );
+        } else {
+            tracing::warn!(
+                "Producer dropped without calling close, {} unflushed records are lost if the runtime shuts down",
+                unflushed_records
+            );
+        }
+    }
 }
 
-// vector for the results from each broker
-#[instrument(skip(messages, produce_params, cluster_metadata))]
+/// Write the messages to the leaders of their topic partitions, retrying
+/// the partitions that can be retried.
+///
+/// The response of each broker is added to `responses`, and the parts it
+/// wrote to `parts`, as it comes in, so the records that were written are
+/// known even when the flush fails.
+#[instrument(skip(messages, produce_params, cluster_metadata, responses, parts))]
 pub(crate) async fn flush_producer<T: BrokerConnection + Clone + Debug + Send + 'static>(
+    cluster_metadata: &mut ClusterMetadata<T>,
+    produce_params: &ProduceParams,
+    messages: &[ProduceMessage],
+    attributes: RecordBatchAttributes,
+    responses: &mut Vec<Option<ProduceResponse>>,
+    parts: &mut WrittenParts,
+) -> Result<()> {
+    if messages.is_empty() {
+        return Ok(());
+    }
+
+    // Sequences are handed out once, so retries write the same batches and
+    // the broker can drop any that already made it.
+    let batch_producers = match &produce_params.idempotence {
+        Some(idempotence) => {
+            if idempotence.lock().unwrap().fenced {
+                return Err(Error::ProducerFenced);
+            }
+            match assign_sequences(cluster_metadata, produce_params, idempotence, messages).await {
+                Err(Error::KafkaError(error_code)) if error_code.is_producer_fenced() => {
+                    return Err(poison(idempotence));
+                }
+                batch_producers => batch_producers?,
+            }
+        }
+        None => HashMap::new(),
+    };
+    produce_to_leaders(
+        cluster_metadata,
+        produce_params,
+        messages,
+        &attributes,
+        &batch_producers,
+        responses,
+        parts,
+    )
+    .await?;
+    // a fenced producer must not retry, a newer instance took over
+    let fenced = failed_partitions(responses)
+        .iter()
+        .any(|(_, error_code)| error_code.is_producer_fenced());
+    if let (true, Some(idempotence)) = (fenced, &produce_params.idempotence) {
+        return Err(poison(idempotence));
+    }
+
+    // each partition has its own budget, so a flaky partition cannot use up
+    // the retries of the others in the batch
+    let mut retries: HashMap<(String, i32), usize> = HashMap::new();
+    loop {
+        let stale: Vec<(String, i32)> = stale_partitions(responses)
+            .into_iter()
+            .filter(|topic_partition| {
+                retries.get(topic_partition).copied().unwrap_or(0) < MAX_STALE_METADATA_RETRIES
+            })
+            .collect();
+        if stale.is_empty() {
+            break;
+        }
+        remove_partitions(responses, parts, &stale);
+
+        for topic_partition in stale.iter() {
+            let attempt = retries.entry(topic_partition.clone()).or_default();
+            *attempt += 1;
+            tracing::warn!(
+                "Leader metadata is stale for {:?}, refreshing and retrying (attempt {})",
+                topic_partition,
+                attempt
+            );
+        }
+        cluster_metadata.refresh().await?;
+
+        let retry_messages: Vec<ProduceMessage> = messages
+            .iter()
+            .filter(|message| stale.contains(&(message.topic.clone(), message.partition_id)))
+            .cloned()
+            .collect();
+        produce_to_leaders(
+            cluster_metadata,
+            produce_params,
+            &retry_messages,
+            &attributes,
+            &batch_producers,
+            responses,
+            parts,
+        )
+        .await?;
+    }
+
+    let too_large: Vec<(String, i32)> = failed_partitions(responses)
+        .into_iter()
+        .filter(|(_, error_code)| *error_code == KafkaCode::MessageSizeTooLarge)
+        .map(|(topic_partition, _)| topic_partition)
+        .collect();
+    if !too_large.is_empty() {
+        remove_partitions(responses, parts, &too_large);
+        for topic_partition in too_large {
+            let partition_messages: Vec<ProduceMessage> = messages
+                .iter()
+                .filter(|message| (message.topic.clone(), message.partition_id) == topic_partition)
+                .cloned()
+                .collect();
+            produce_split(
+                cluster_metadata,
+                produce_params,
+                partition_messages,
+                &attributes,
+                batch_producers.get(&topic_partition).copied(),
+                responses,
+                parts,
+            )
+            .await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Mark an idempotent producer as fenced, so it refuses every later write.
+fn poison(idempotence: &Mutex<IdempotentProducer>) -> Error {
+    tracing::error!("Producer was fenced by a newer instance, it cannot write anymore");
+    idempotence.lock().unwrap().fenced = true;
+    Error::ProducerFenced
+}
+
+/// Write the messages of a topic partition that were rejected as too large,
+/// halving the batch until the broker accepts it.
+///
+/// The halves are written one after the other, each starting at the
+/// sequence number of its first record, so an idempotent producer still
+/// writes the records with contiguous sequence numbers. Once a part fails
+/// for another reason, the parts after it are not written and fail with the
+/// same error.
+async fn produce_split<T: BrokerConnection + Clone + Debug + Send + 'static>(
     cluster_metadata: &ClusterMetadata<T>,
     produce_params: &ProduceParams,
     messages: Vec<ProduceMessage>,
-    attributes: Attributes,
-) -> Result<Vec<Option<ProduceResponse>>> {
+    attributes: &RecordBatchAttributes,
+    batch_producer: Option<BatchProducer>,
+    responses: &mut Vec<Option<ProduceResponse>>,
+    parts: &mut WrittenParts,
+) -> Result<()> {
+    let mut pending = VecDeque::from([(messages, batch_producer)]);
+    while let Some((messages, batch_producer)) = pending.pop_front() {
+        let Some(message) = messages.first() else {
+            continue;
+        };
+        let topic_partition = (message.topic.clone(), message.partition_id);
+        let batch_producers = batch_producer
+            .map(|producer| HashMap::from([(topic_partition.clone(), producer)]))
+            .unwrap_or_default();
+        let mut part_responses = vec![];
+        let mut written_parts = WrittenParts::new();
+        produce_to_leaders(
+            cluster_metadata,
+            produce_params,
+            &messages,
+            attributes,
+            &batch_producers,
+            &mut part_responses,
+            &mut written_parts,
+        )
+        .await?;
+
+        let error_code = failed_partitions(&part_responses)
+            .into_iter()
+            .map(|(_, error_code)| error_code)
+            .next();
+        match error_code {
+            Some(KafkaCode::MessageSizeTooLarge) if messages.len() > 1 => {
+                let mut first = messages;
+                let second = first.split_off(first.len() / 2);
+                tracing::warn!(
+                    "Batch for {:?} is too large, splitting it into {} and {} records",
+                    topic_partition,
+                    first.len(),
+                    second.len()
+                );
+                let second_producer = batch_producer.map(|producer| BatchProducer {
+                    base_sequence: producer.base_sequence.wrapping_add(first.len() as i32),
+                    ..producer
+                });
+                pending.push_front((second, second_producer));
+                pending.push_front((first, batch_producer));
+            }
+            Some(error_code) => {
+                responses.extend(part_responses);
+                let topic_parts = parts.entry(topic_partition).or_default();
+                topic_parts.extend(written_parts.into_values().flatten());
+                for (messages, _) in pending {
+                    topic_parts.push((messages.len(), Err(error_code)));
+                }
+                break;
+            }
+            None => {
+                responses.extend(part_responses);
+                let topic_parts = parts.entry(topic_partition).or_default();
+                topic_parts.extend(written_parts.into_values().flatten());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Hand out the base sequence of the batch for each topic partition of the
+/// messages, asking the cluster for a producer id first if there is none yet.
+async fn assign_sequences<T: BrokerConnection + Clone + Debug + Send + 'static>(
+    cluster_metadata: &ClusterMetadata<T>,
+    produce_params: &ProduceParams,
+    idempotence: &Mutex<IdempotentProducer>,
+    messages: &[ProduceMessage],
+) -> Result<HashMap<TopicPartition, BatchProducer>> {
+    let producer = idempotence.lock().unwrap().producer;
+    let (producer_id, producer_epoch) = match producer {
+        Some(producer) => producer,
+        None => {
+            let init = |conn| {
+                init_producer_id(
+                    conn,
+                    produce_params.correlation_id,
+                    &produce_params.client_id,
+                    produce_params.transactional_id.as_deref(),
+                    produce_params.transaction_timeout_ms,
+                )
+            };
+            let response = match produce_params.transactional_id.as_deref() {
+                // a transactional id is only known to its transaction coordinator
+                Some(transactional_id) => {
+                    with_transaction_coordinator(
+                        cluster_metadata,
+                        produce_params.correlation_id,
+                        &produce_params.client_id,
+                        transactional_id,
+                        init,
+                    )
+                    .await?
+                }
+                None => {
+                    let conn = cluster_metadata
+                        .broker_connections
+                        .get(&cluster_metadata.controller_id)
+                        .ok_or(Error::NoConnectionForBroker(cluster_metadata.controller_id))?
+                        .to_owned();
+                    init(conn).await?
+                }
+            };
+            let producer = (response.producer_id, response.producer_epoch);
+            idempotence.lock().unwrap().producer = Some(producer);
+            producer
+        }
+    };
+
+    let mut state = idempotence.lock().unwrap();
+    let mut batch_producers = HashMap::new();
+    for message in messages {
+        let topic_partition = (message.topic.clone(), message.partition_id);
+        let sequence = state.sequences.entry(topic_partition.clone()).or_insert(0);
+        batch_producers
+            .entry(topic_partition)
+            .or_insert(BatchProducer {
+                producer_id,
+                producer_epoch,
+                base_sequence: *sequence,
+            });
+        // sequence numbers wrap around to 0, like the broker expects
+        *sequence = sequence.wrapping_add(1);
+    }
+
+    Ok(batch_producers)
+}
+
+async fn produce_to_leaders<T: BrokerConnection + Clone + Debug + Send + 'static>(
+    cluster_metadata: &ClusterMetadata<T>,
+    produce_params: &ProduceParams,
+    messages: &[ProduceMessage],
+    attributes: &RecordBatchAttributes,
+    batch_producers: &HashMap<TopicPartition, BatchProducer>,
+    responses: &mut Vec<Option<ProduceResponse>>,
+    parts: &mut WrittenParts,
+) -> Result<()> {
     let mut brokers_and_messages = HashMap::new();
     tracing::debug!("Producing {} messages", messages.len());
     for message in messages {
+        if produce_params.validate_partitions {
+            validate_partition(cluster_metadata, message)?;
+        }
+        validate_size(produce_params, message)?;
         let broker_id = cluster_metadata
             .get_leader_id_for_topic_partition(&message.topic, message.partition_id)
             .ok_or(Error::NoLeaderForTopicPartition(
@@ -127,9 +642,9 @@ pub(crate) async fn flush_producer<T: BrokerConnection + Clone + Debug + Send +
 
         match brokers_and_messages.get_mut(&broker_id) {
             None => {
-                brokers_and_messages.insert(broker_id, vec![message]);
+                brokers_and_messages.insert(broker_id, vec![message.clone()]);
             }
-            Some(messages) => messages.push(message),
+            Some(messages) => messages.push(message.clone()),
         };
     }
 
@@ -143,8 +658,9 @@ pub(crate) async fn flush_producer<T: BrokerConnection + Clone + Debug + Send +
             .to_owned();
         let p = produce_params.clone();
         let a = attributes.clone();
+        let b = batch_producers.clone();
         set.spawn(async move {
-            produce(
+            let result = produce_with_producers(
                 broker_conn,
                 p.correlation_id,
                 &p.client_id,
@@ -152,35 +668,576 @@ pub(crate) async fn flush_producer<T: BrokerConnection + Clone + Debug + Send +
                 p.timeout_ms,
                 &messages,
                 a,
+                p.transactional_id.as_deref(),
+                &b,
+                p.initial_batch_capacity,
+                p.produce_version,
             )
-            .await
+            .await;
+            (messages, result)
         });
     }
 
-    let mut responses = vec![];
-
+    // wait for every broker, so the records the others wrote are known
+    // when one of them fails
+    let mut result = Ok(());
     while let Some(res) = set.join_next().await {
-        let produce_response = res.unwrap()?;
-        responses.push(produce_response);
+        let (messages, produced) = res.unwrap();
+        match produced {
+            Ok(produce_response) => {
+                add_parts(parts, &messages, produce_response.as_ref());
+                responses.push(produce_response);
+            }
+            Err(err) => {
+                if result.is_ok() {
+                    result = Err(err);
+                }
+            }
+        }
+    }
+
+    result
+}
+
+/// Add the part each topic partition of the messages was written in by one
+/// request, with the result the broker answered for it, or a base offset of
+/// -1 when no acks were required.
+fn add_parts(
+    parts: &mut WrittenParts,
+    messages: &[ProduceMessage],
+    response: Option<&ProduceResponse>,
+) {
+    let mut records: Vec<(TopicPartition, usize)> = vec![];
+    for message in messages {
+        let topic_partition = (message.topic.clone(), message.partition_id);
+        match records
+            .iter_mut()
+            .find(|(written, _)| *written == topic_partition)
+        {
+            Some((_, count)) => *count += 1,
+            None => records.push((topic_partition, 1)),
+        }
+    }
+    let results: HashMap<TopicPartition, PartResult> = response
+        .map(|response| {
+            response
+                .partition_results()
+                .into_iter()
+                .map(|result| {
+                    let topic = String::from_utf8_lossy(&result.topic).to_string();
+                    ((topic, result.partition), result.result)
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    for (topic_partition, count) in records {
+        let result = match response {
+            // a partition the broker did not answer for was not written
+            Some(_) => match results.get(&topic_partition) {
+                Some(result) => *result,
+                None => continue,
+            },
+            None => Ok(-1),
+        };
+        parts
+            .entry(topic_partition)
+            .or_default()
+            .push((count, result));
+    }
+}
+
+/// Put the default headers in front of the headers of a message, leaving
+/// out those whose key the message already has a header for.
+pub(crate) fn add_default_headers(default_headers: &[Header], message: &mut ProduceMessage) {
+    if default_headers.is_empty() {
+        return;
+    }
+    let mut headers: Vec<Header> = default_headers
+        .iter()
+        .filter(|default| {
+            !message
+                .headers
+                .iter()
+                .any(|header| header.key() == default.key())
+        })
+        .cloned()
+        .collect();
+    headers.append(&mut message.headers);
+    message.headers = headers;
+}
+
+/// Route the messages without a partition, i.e. a partition of -1. A keyed
+/// message goes to the partition the default partitioner picks for its key,
+/// the others take turns over the partitions that have a leader.
+///
+/// Topics missing from the metadata are left for the leader lookup to reject.
+pub(crate) fn assign_partitions<T: BrokerConnection + Clone + Debug>(
+    cluster_metadata: &ClusterMetadata<T>,
+    next_partition: &AtomicUsize,
+    messages: Vec<ProduceMessage>,
+) -> Vec<ProduceMessage> {
+    messages
+        .into_iter()
+        .map(|mut message| {
+            if message.partition_id != -1 {
+                return message;
+            }
+            match &message.key {
+                Some(key) => {
+                    let partition_count = cluster_metadata.get_partition_count(&message.topic);
+                    if let Some(partition_count) = partition_count.filter(|count| *count > 0) {
+                        message.partition_id = partition_for_key(key, partition_count);
+                    }
+                }
+                None => {
+                    let partitions = cluster_metadata.get_available_partitions(&message.topic);
+                    if let Some(partitions) = partitions.filter(|p| !p.is_empty()) {
+                        let turn = next_partition.fetch_add(1, Ordering::Relaxed);
+                        message.partition_id = partitions[turn % partitions.len()];
+                    }
+                }
+            }
+            message
+        })
+        .collect()
+}
+
+/// Check the partition of a message against the partition count of its
+/// topic, so an out of range partition fails before a round trip to the broker.
+///
+/// Topics missing from the metadata are left for the leader lookup to reject.
+fn validate_partition<T: BrokerConnection + Clone + Debug>(
+    cluster_metadata: &ClusterMetadata<T>,
+    message: &ProduceMessage,
+) -> Result<()> {
+    let Some(partition_count) = cluster_metadata.get_partition_count(&message.topic) else {
+        return Ok(());
+    };
+    if message.partition_id < 0 || message.partition_id as usize >= partition_count {
+        return Err(Error::InvalidPartition(
+            message.topic.clone(),
+            message.partition_id,
+        ));
+    }
+
+    Ok(())
+}
+
+/// Check the key and value of a message against the largest ones the
+/// producer allows, so an oversized message fails before it is sent.
+fn validate_size(produce_params: &ProduceParams, message: &ProduceMessage) -> Result<()> {
+    let checks = [
+        (message.key.as_ref(), produce_params.max_key_bytes),
+        (message.value.as_ref(), produce_params.max_value_bytes),
+    ];
+    for (bytes, max_bytes) in checks {
+        let (Some(bytes), Some(max_bytes)) = (bytes, max_bytes) else {
+            continue;
+        };
+        if bytes.len() > max_bytes {
+            return Err(Error::MessageTooLarge(
+                message.topic.clone(),
+                bytes.len(),
+                max_bytes,
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// The topic partitions that failed because our view of the partition
+/// leader is out of date.
+fn stale_partitions(responses: &[Option<ProduceResponse>]) -> Vec<(String, i32)> {
+    failed_partitions(responses)
+        .into_iter()
+        .filter(|(_, error_code)| is_stale_leader_error(*error_code))
+        .map(|(topic_partition, _)| topic_partition)
+        .collect()
+}
+
+/// The partition and offset each message was written at, going by the part
+/// of its topic partition it was written in.
+///
+/// Messages without a part, as the flush failed before they were written,
+/// fail with the `unwritten` error. Messages written without acks get an
+/// offset of -1.
+pub(crate) fn delivered_offsets(
+    messages: &[ProduceMessage],
+    parts: &WrittenParts,
+    unwritten: &Error,
+) -> Vec<Result<(i32, i64)>> {
+    // the part the next message of each topic partition is in, and its
+    // index in that part
+    let mut positions: HashMap<TopicPartition, (usize, usize)> = HashMap::new();
+    messages
+        .iter()
+        .map(|message| {
+            let topic_partition = (message.topic.clone(), message.partition_id);
+            let part = parts.get(&topic_partition);
+            let (part_index, index) = positions.entry(topic_partition).or_default();
+            let offset = match part.and_then(|part| part.get(*part_index)) {
+                Some((count, result)) => {
+                    let offset = match result {
+                        Ok(-1) => Ok(-1),
+                        Ok(base_offset) => Ok(base_offset + *index as i64),
+                        Err(error_code) => Err(Error::KafkaError(*error_code)),
+                    };
+                    *index += 1;
+                    if *index == *count {
+                        *part_index += 1;
+                        *index = 0;
+                    }
+                    offset
+                }
+                None => Err(unwritten.clone()),
+            };
+            offset.map(|offset| (message.partition_id, offset))
+        })
+        .collect()
+}
+
+/// The topic partitions that a broker failed to write, with the reason why.
+pub(crate) fn failed_partitions(
+    responses: &[Option<ProduceResponse>],
+) -> Vec<((String, i32), KafkaCode)> {
+    let mut failed = vec![];
+    for response in responses.iter().flatten() {
+        for topic in response.responses.iter() {
+            let name = String::from_utf8_lossy(&topic.name).to_string();
+            for partition in topic.partition_responses.iter() {
+                if partition.error_code != KafkaCode::None {
+                    failed.push(((name.clone(), partition.index), partition.error_code));
+                }
+            }
+        }
+    }
+
+    failed
+}
+
+/// Remove the responses and written parts for the given topic partitions,
+/// so they can be replaced by those of a retry.
+fn remove_partitions(
+    responses: &mut Vec<Option<ProduceResponse>>,
+    parts: &mut WrittenParts,
+    topic_partitions: &[(String, i32)],
+) {
+    for topic_partition in topic_partitions {
+        parts.remove(topic_partition);
+    }
+    for response in responses.iter_mut().flatten() {
+        for topic in response.responses.iter_mut() {
+            let name = String::from_utf8_lossy(&topic.name).to_string();
+            topic
+                .partition_responses
+                .retain(|partition| !topic_partitions.contains(&(name.clone(), partition.index)));
+        }
+        response
+            .responses
+            .retain(|topic| !topic.partition_responses.is_empty());
+    }
+    responses.retain(|response| !matches!(response, Some(r) if r.responses.is_empty()));
+}
+
+fn is_stale_leader_error(error_code: KafkaCode) -> bool {
+    matches!(
+        error_code,
+        KafkaCode::NotLeaderForPartition
+            | KafkaCode::FencedLeaderEpoch
+            | KafkaCode::UnknownLeaderEpoch
+    )
+}
+
+/// Ask the cluster for a producer id and epoch, to write idempotently or,
+/// with a transactional id, transactionally.
+///
+/// See this [protocol spec](crate::prelude::protocol::init_producer_id) for more information.
+pub async fn init_producer_id(
+    mut broker_conn: impl BrokerConnection,
+    correlation_id: i32,
+    client_id: &str,
+    transactional_id: Option<&str>,
+    transaction_timeout_ms: i32,
+) -> Result<protocol::InitProducerIdResponse> {
+    // the upper bound is up to the broker, see `transaction.max.timeout.ms`
+    if transaction_timeout_ms <= 0 {
+        return Err(Error::ArgError(format!(
+            "transaction_timeout_ms must be positive, got {}",
+            transaction_timeout_ms
+        )));
+    }
+    let init_producer_id = protocol::InitProducerIdRequest::new(
+        correlation_id,
+        client_id,
+        transactional_id,
+        transaction_timeout_ms,
+    );
+    broker_conn.send_request(&init_producer_id).await?;
+
+    let response =
+        protocol::InitProducerIdResponse::try_from(broker_conn.receive_response().await?.freeze())?;
+    response.is_error()?;
+
+    Ok(response)
+}
+
+/// Register partitions with the transaction of a transactional producer,
+/// before it writes to them for the first time in the transaction.
+///
+/// The request is sent to the transaction coordinator of the transactional
+/// id, looked up again when it has moved.
+///
+/// See this [protocol spec](crate::prelude::protocol::add_partitions_to_txn) for more information.
+pub async fn add_partitions_to_txn<T: BrokerConnection + Clone + Debug + Send + 'static>(
+    cluster_metadata: &ClusterMetadata<T>,
+    correlation_id: i32,
+    client_id: &str,
+    transactional_id: &str,
+    producer_id: i64,
+    producer_epoch: i16,
+    partitions: &[TopicPartition],
+) -> Result<protocol::AddPartitionsToTxnResponse> {
+    let mut add_partitions = protocol::AddPartitionsToTxnRequest::new(
+        correlation_id,
+        client_id,
+        transactional_id,
+        producer_id,
+        producer_epoch,
+    );
+    for (topic_name, partition_index) in partitions {
+        add_partitions.add(topic_name, *partition_index);
     }
 
-    Ok(responses)
+    with_transaction_coordinator(
+        cluster_metadata,
+        correlation_id,
+        client_id,
+        transactional_id,
+        |mut coordinator_conn| {
+            let add_partitions = &add_partitions;
+            async move {
+                coordinator_conn.send_request(add_partitions).await?;
+                let response = protocol::AddPartitionsToTxnResponse::try_from(
+                    coordinator_conn.receive_response().await?.freeze(),
+                )?;
+                response.is_error()?;
+                Ok(response)
+            }
+        },
+    )
+    .await
+}
+
+/// Commit, or abort, the transaction of a transactional producer.
+///
+/// The request is sent to the transaction coordinator of the transactional
+/// id, looked up again when it has moved.
+///
+/// See this [protocol spec](crate::prelude::protocol::end_txn) for more information.
+pub async fn end_txn<T: BrokerConnection + Clone + Debug + Send + 'static>(
+    cluster_metadata: &ClusterMetadata<T>,
+    correlation_id: i32,
+    client_id: &str,
+    transactional_id: &str,
+    producer_id: i64,
+    producer_epoch: i16,
+    committed: bool,
+) -> Result<protocol::EndTxnResponse> {
+    let end_txn = protocol::EndTxnRequest::new(
+        correlation_id,
+        client_id,
+        transactional_id,
+        producer_id,
+        producer_epoch,
+        committed,
+    );
+
+    with_transaction_coordinator(
+        cluster_metadata,
+        correlation_id,
+        client_id,
+        transactional_id,
+        |mut coordinator_conn| {
+            let end_txn = &end_txn;
+            async move {
+                coordinator_conn.send_request(end_txn).await?;
+                let response = protocol::EndTxnResponse::try_from(
+                    coordinator_conn.receive_response().await?.freeze(),
+                )?;
+                response.is_error()?;
+                Ok(response)
+            }
+        },
+    )
+    .await
+}
+
+/// Connect to the transaction coordinator of a transactional id, as told by
+/// the controller.
+pub async fn transaction_coordinator<T: BrokerConnection + Clone + Debug + Send + 'static>(
+    cluster_metadata: &ClusterMetadata<T>,
+    correlation_id: i32,
+    client_id: &str,
+    transactional_id: &str,
+) -> Result<T> {
+    let conn = cluster_metadata
+        .broker_connections
+        .get(&cluster_metadata.controller_id)
+        .ok_or(Error::NoConnectionForBroker(cluster_metadata.controller_id))?
+        .to_owned();
+    let addr = find_coordinator_broker(
+        conn,
+        correlation_id,
+        client_id,
+        transactional_id,
+        protocol::CoordinatorType::Transaction,
+    )
+    .await?;
+    tracing::debug!(
+        "Transaction coordinator of {} is {}:{}",
+        transactional_id,
+        addr.host,
+        addr.port
+    );
+    T::from_addr(cluster_metadata.connection_params.clone(), addr).await
+}
+
+/// Send a transaction request to the transaction coordinator, looking the
+/// coordinator up again while it is loading, not available or has moved.
+///
+/// The connection to the coordinator is kept in the cluster metadata, so the
+/// requests of a transactional id share it instead of each looking up the
+/// coordinator and connecting to it.
+async fn with_transaction_coordinator<T, R, F, Fut>(
+    cluster_metadata: &ClusterMetadata<T>,
+    correlation_id: i32,
+    client_id: &str,
+    transactional_id: &str,
+    send: F,
+) -> Result<R>
+where
+    T: BrokerConnection + Clone + Debug + Send + 'static,
+    F: Fn(T) -> Fut,
+    Fut: Future<Output = Result<R>>,
+{
+    let coordinators = &cluster_metadata.transaction_coordinators;
+    let mut attempts = 0;
+    loop {
+        let known = coordinators.lock().unwrap().get(transactional_id).cloned();
+        let coordinator_conn = match known {
+            Some(coordinator_conn) => coordinator_conn,
+            None => {
+                let coordinator_conn = transaction_coordinator(
+                    cluster_metadata,
+                    correlation_id,
+                    client_id,
+                    transactional_id,
+                )
+                .await?;
+                coordinators
+                    .lock()
+                    .unwrap()
+                    .insert(transactional_id.to_owned(), coordinator_conn.clone());
+                coordinator_conn
+            }
+        };
+        let result = send(coordinator_conn).await;
+        let coordinator_gone = match &result {
+            Err(Error::KafkaError(error_code)) => error_code.is_coordinator_error(),
+            Err(Error::IoError(_)) => true,
+            _ => false,
+        };
+        if coordinator_gone {
+            // the coordinator moved or the connection to it broke
+            coordinators.lock().unwrap().remove(transactional_id);
+        }
+        match result {
+            Err(Error::KafkaError(error_code))
+                if error_code.is_coordinator_error() && attempts < MAX_COORDINATOR_RETRIES =>
+            {
+                attempts += 1;
+                tracing::warn!(
+                    "Transaction coordinator of {} is not ready ({:?}), looking it up again",
+                    transactional_id,
+                    error_code
+                );
+                tokio::time::sleep(retry_backoff(attempts as u32)).await;
+            }
+            result => return result,
+        }
+    }
 }
 
 /// Produce messages to a broker.
 ///
 /// See this [protocol spec](crate::prelude::protocol::produce) for more information.
+#[allow(clippy::ptr_arg)]
 pub async fn produce(
-    mut broker_conn: impl BrokerConnection,
+    broker_conn: impl BrokerConnection,
     correlation_id: i32,
     client_id: &str,
     required_acks: i16,
     timeout_ms: i32,
     messages: &Vec<ProduceMessage>,
-    attributes: Attributes,
+    attributes: RecordBatchAttributes,
 ) -> Result<Option<ProduceResponse>> {
-    tracing::debug!("Producing {} messages", messages.len());
+    produce_with_producers(
+        broker_conn,
+        correlation_id,
+        client_id,
+        required_acks,
+        timeout_ms,
+        messages,
+        attributes,
+        None,
+        &HashMap::new(),
+        0,
+        protocol::produce::request::API_VERSION,
+    )
+    .await
+}
+
+/// Encode the Produce request for the given messages, as it is written to
+/// the socket, size included.
+///
+/// This is what [`produce`] sends, without needing a broker to send it to.
+/// The records are timestamped with the current time.
+pub fn build_produce_request(
+    messages: &[ProduceMessage],
+    required_acks: i16,
+    timeout_ms: i32,
+    compression: Option<Compression>,
+    client_id: &str,
+    correlation_id: i32,
+) -> Result<Bytes> {
+    let produce_request = produce_request(
+        messages,
+        required_acks,
+        timeout_ms,
+        RecordBatchAttributes::new(compression),
+        None,
+        client_id,
+        correlation_id,
+    );
 
+    let mut buffer = vec![0, 0, 0, 0];
+    produce_request.encode(&mut buffer)?;
+    let size = try_usize_to_int!(buffer.len() - 4, i32);
+    size.encode(&mut &mut buffer[..4])?;
+
+    Ok(Bytes::from(buffer))
+}
+
+fn produce_request<'a>(
+    messages: &'a [ProduceMessage],
+    required_acks: i16,
+    timeout_ms: i32,
+    attributes: RecordBatchAttributes,
+    transactional_id: Option<&str>,
+    client_id: &'a str,
+    correlation_id: i32,
+) -> ProduceRequest<'a> {
     let mut produce_request = ProduceRequest::new(
         required_acks,
         timeout_ms,
@@ -188,7 +1245,9 @@ pub async fn produce(
         client_id,
         attributes,
     );
-
+    if let Some(transactional_id) = transactional_id {
+        produce_request.set_transactional_id(transactional_id.to_owned());
+    }
     for message in messages {
         produce_request.add(
             &message.topic,
@@ -198,12 +1257,1633 @@ pub async fn produce(
             message.headers.clone(),
         );
     }
+    produce_request
+}
+
+/// Produce messages to a broker, writing the batch of each topic partition
+/// in `batch_producers` as an idempotent producer, as part of the transaction
+/// of `transactional_id` if there is one, reserving
+/// `initial_batch_capacity` bytes for encoding each batch. Produce versions
+/// up to 2 write legacy message sets.
+#[allow(clippy::too_many_arguments)]
+async fn produce_with_producers(
+    mut broker_conn: impl BrokerConnection,
+    correlation_id: i32,
+    client_id: &str,
+    required_acks: i16,
+    timeout_ms: i32,
+    messages: &[ProduceMessage],
+    attributes: RecordBatchAttributes,
+    transactional_id: Option<&str>,
+    batch_producers: &HashMap<TopicPartition, BatchProducer>,
+    initial_batch_capacity: usize,
+    api_version: i16,
+) -> Result<Option<ProduceResponse>> {
+    tracing::debug!("Producing {} messages", messages.len());
+
+    let mut produce_request = produce_request(
+        messages,
+        required_acks,
+        timeout_ms,
+        attributes,
+        transactional_id,
+        client_id,
+        correlation_id,
+    );
+    produce_request.header.api_version = api_version;
+    for ((topic, partition), producer) in batch_producers {
+        produce_request.set_partition_producer(topic, *partition, *producer);
+    }
+    produce_request.set_batch_capacity(initial_batch_capacity);
+    if produce_request.is_empty() {
+        tracing::debug!("No records to produce, not sending an empty request");
+        return Ok(None);
+    }
 
     broker_conn.send_request(&produce_request).await?;
-    if required_acks > 0 {
-        let response = ProduceResponse::try_from(broker_conn.receive_response().await?.freeze())?;
+    // with -1 the broker answers once the full ISR has the records
+    if required_acks != 0 {
+        let response = ProduceResponse::try_from_version(
+            broker_conn.receive_response().await?.freeze(),
+            produce_request.header.api_version,
+        )?;
         Ok(Some(response))
     } else {
         Ok(None)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicBool, AtomicI16, AtomicI32, AtomicU16, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use bytes::BufMut;
+    use tokio::net::TcpStream;
+    use tokio_stream::StreamExt;
+
+    use super::*;
+    use crate::{
+        clock::MockClock,
+        encode::ToByte,
+        network::{
+            boxed::{BoxedConnection, BoxedConnectionConfig},
+            tcp::TcpConnection,
+        },
+        producer_builder::ProducerBuilder,
+        test_support::{
+            api_key, api_version, body_offset, broker_address, listen, put_string, read_i16,
+            read_i32, read_request, read_string, write_response,
+        },
+    };
+
+    const TOPIC: &str = "purchases";
+    const PRODUCER_ID: i64 = 42;
+
+    struct MockBroker {
+        port: u16,
+        metadata_requests: AtomicI32,
+        produce_requests: AtomicI32,
+        /// How many produce requests to fail before accepting them.
+        failing_produce_requests: i32,
+        /// The index of a later produce request to fail, -1 for none.
+        rejected_produce_request: AtomicI32,
+        produce_error_code: KafkaCode,
+        /// How many partitions the topic has.
+        partitions: i32,
+        /// A partition that always fails with the produce error code.
+        failing_partition: Option<i32>,
+        /// The accepted produce requests, in the order they were written.
+        log: std::sync::Mutex<Vec<u8>>,
+        /// The received init producer id requests.
+        init_producer_id_requests: std::sync::Mutex<Vec<Vec<u8>>>,
+        /// Batches with more records are rejected as too large.
+        max_records: AtomicI32,
+        /// The base sequence and record count of each accepted batch.
+        accepted_batches: std::sync::Mutex<Vec<(i32, i32)>>,
+        /// When set, the offset the next accepted batch is written at,
+        /// instead of 100 plus the partition. It advances past the batch and
+        /// the records other producers write after it.
+        log_end_offset: std::sync::Mutex<Option<i64>>,
+        /// The port of the transaction coordinator to answer lookups with.
+        coordinator_port: AtomicU16,
+        find_coordinator_requests: AtomicI32,
+        /// How many end txn requests to answer with a not coordinator error.
+        not_coordinator_responses: AtomicI32,
+        /// The received end txn requests.
+        end_txn_requests: std::sync::Mutex<Vec<Vec<u8>>>,
+        /// The port of a second broker leading the odd partitions, if any.
+        second_leader_port: AtomicU16,
+        /// Drop the connection instead of answering produce requests.
+        drops_produce_requests: AtomicBool,
+        /// The last received produce request.
+        last_produce_request: std::sync::Mutex<Vec<u8>>,
+        /// The highest Produce version to answer ApiVersions requests with.
+        max_produce_version: AtomicI16,
+        connections: AtomicI32,
+    }
+
+    impl MockBroker {
+        /// Start a broker on a random port, accepting any number of connections.
+        async fn start(failing_produce_requests: i32, produce_error_code: KafkaCode) -> Arc<Self> {
+            Self::start_partitioned(failing_produce_requests, produce_error_code, 1, None).await
+        }
+
+        /// Start a broker for a topic with the given number of partitions.
+        async fn start_partitioned(
+            failing_produce_requests: i32,
+            produce_error_code: KafkaCode,
+            partitions: i32,
+            failing_partition: Option<i32>,
+        ) -> Arc<Self> {
+            let (listener, port) = listen().await;
+            let broker = Arc::new(MockBroker {
+                port,
+                metadata_requests: AtomicI32::new(0),
+                produce_requests: AtomicI32::new(0),
+                failing_produce_requests,
+                rejected_produce_request: AtomicI32::new(-1),
+                produce_error_code,
+                partitions,
+                failing_partition,
+                log: std::sync::Mutex::new(vec![]),
+                init_producer_id_requests: std::sync::Mutex::new(vec![]),
+                max_records: AtomicI32::new(i32::MAX),
+                accepted_batches: std::sync::Mutex::new(vec![]),
+                log_end_offset: std::sync::Mutex::new(None),
+                coordinator_port: AtomicU16::new(port),
+                find_coordinator_requests: AtomicI32::new(0),
+                not_coordinator_responses: AtomicI32::new(0),
+                end_txn_requests: std::sync::Mutex::new(vec![]),
+                second_leader_port: AtomicU16::new(0),
+                drops_produce_requests: AtomicBool::new(false),
+                last_produce_request: std::sync::Mutex::new(vec![]),
+                max_produce_version: AtomicI16::new(protocol::produce::request::API_VERSION),
+                connections: AtomicI32::new(0),
+            });
+            let accepting = broker.clone();
+            tokio::spawn(async move {
+                while let Ok((socket, _)) = listener.accept().await {
+                    accepting.connections.fetch_add(1, Ordering::SeqCst);
+                    tokio::spawn(accepting.clone().serve(socket));
+                }
+            });
+
+            broker
+        }
+
+        async fn producer(&self) -> ProducerBuilder<TcpConnection> {
+            ProducerBuilder::<TcpConnection>::new(
+                vec![broker_address(self.port)],
+                vec![TOPIC.to_owned()],
+            )
+            .await
+            .unwrap()
+        }
+
+        /// One broker leading every partition, unless a second leader
+        /// takes the odd ones, whose leader epoch goes up every time
+        /// metadata is requested.
+        fn metadata_response(&self, request: &[u8]) -> Vec<u8> {
+            let leader_epoch = self.metadata_requests.fetch_add(1, Ordering::SeqCst) + 1;
+            let second_leader_port = self.second_leader_port.load(Ordering::SeqCst);
+            let mut brokers = vec![(1, self.port)];
+            if second_leader_port != 0 {
+                brokers.push((2, second_leader_port));
+            }
+            let mut buf = vec![];
+            buf.put_i32(0); // throttle_time_ms
+            buf.put_i32(brokers.len() as i32);
+            for (node_id, port) in brokers {
+                buf.put_i32(node_id);
+                put_string(&mut buf, "127.0.0.1");
+                buf.put_i32(port as i32);
+                buf.put_i16(-1); // rack
+            }
+            buf.put_i16(-1); // cluster_id
+            buf.put_i32(1); // controller_id
+            let topics = Self::requested_topics(request);
+            buf.put_i32(topics.len() as i32);
+            for topic in topics {
+                buf.put_i16(0);
+                put_string(&mut buf, &topic);
+                buf.put_i8(0); // is_internal
+                buf.put_i32(self.partitions);
+                for partition in 0..self.partitions {
+                    buf.put_i16(0);
+                    buf.put_i32(partition); // partition_index
+                    let leader_id = if second_leader_port != 0 && partition % 2 == 1 {
+                        2
+                    } else {
+                        1
+                    };
+                    buf.put_i32(leader_id);
+                    buf.put_i32(leader_epoch);
+                    buf.put_slice(&[0, 0, 0, 1, 0, 0, 0, 1]); // replica_nodes
+                    buf.put_slice(&[0, 0, 0, 1, 0, 0, 0, 1]); // isr_nodes
+                    buf.put_i32(0); // offline_replicas
+                }
+            }
+            buf
+        }
+
+        /// The topics of a metadata request, past the client id.
+        fn requested_topics(request: &[u8]) -> Vec<String> {
+            let topics = body_offset(request);
+            let mut offset = topics + 4;
+            (0..read_i32(request, topics))
+                .map(|_| {
+                    let (topic, next) = read_string(request, offset);
+                    offset = next;
+                    topic.unwrap()
+                })
+                .collect()
+        }
+
+        fn produce_response(&self, request: &[u8]) -> Vec<u8> {
+            let produce_request = self.produce_requests.fetch_add(1, Ordering::SeqCst);
+            let (base_sequence, record_count) = Self::produced_batch(request);
+            let mut log_end_offset = self.log_end_offset.lock().unwrap();
+            let base_offset = *log_end_offset;
+            let error_code = if record_count > self.max_records.load(Ordering::SeqCst) {
+                KafkaCode::MessageSizeTooLarge
+            } else if produce_request < self.failing_produce_requests
+                || produce_request == self.rejected_produce_request.load(Ordering::SeqCst)
+            {
+                self.produce_error_code
+            } else {
+                self.log.lock().unwrap().extend_from_slice(request);
+                let batches = &self.accepted_batches;
+                batches.lock().unwrap().push((base_sequence, record_count));
+                if let Some(offset) = log_end_offset.as_mut() {
+                    // another producer writes 10 records after the batch
+                    *offset += record_count as i64 + 10;
+                }
+                KafkaCode::None
+            };
+            // the odd partitions are written by the second leader, if any
+            let led: Vec<i32> = (0..self.partitions)
+                .filter(|partition| {
+                    self.second_leader_port.load(Ordering::SeqCst) == 0 || partition % 2 == 0
+                })
+                .collect();
+            let mut buf = vec![];
+            buf.put_i32(1);
+            put_string(&mut buf, &Self::produced_topic(request));
+            buf.put_i32(led.len() as i32);
+            for partition in led {
+                buf.put_i32(partition);
+                match self.failing_partition {
+                    Some(failing) if failing == partition => {
+                        buf.put_i16(self.produce_error_code as i16);
+                        buf.put_i64(-1); // base_offset
+                    }
+                    _ => {
+                        buf.put_i16(error_code as i16);
+                        buf.put_i64(base_offset.unwrap_or(100 + partition as i64));
+                    }
+                }
+                buf.put_i64(-1); // log_append_time
+            }
+            buf.put_i32(0); // throttle_time_ms
+            buf
+        }
+
+        /// Rejects transaction timeouts beyond the default
+        /// `transaction.max.timeout.ms` of a broker.
+        fn init_producer_id_response(request: &[u8]) -> Vec<u8> {
+            let (_, timeout) = read_string(request, body_offset(request));
+            let transaction_timeout_ms = read_i32(request, timeout);
+            let error_code = if transaction_timeout_ms > 900000 {
+                KafkaCode::InvalidTransactionTimeout
+            } else {
+                KafkaCode::None
+            };
+            let mut buf = vec![];
+            buf.put_i32(0); // throttle_time_ms
+            buf.put_i16(error_code as i16);
+            buf.put_i64(PRODUCER_ID);
+            buf.put_i16(0); // producer_epoch
+            buf
+        }
+
+        /// Only lists Produce, from version 0.
+        fn api_versions_response(&self) -> Vec<u8> {
+            let mut buf = vec![];
+            buf.put_i16(0); // error_code
+            buf.put_u8(2); // api_keys, as a compact array
+            buf.put_i16(0);
+            buf.put_i16(0); // min_version
+            buf.put_i16(self.max_produce_version.load(Ordering::SeqCst));
+            buf.put_u8(0); // tagged fields
+            buf.put_i32(0); // throttle_time_ms
+            buf.put_u8(0); // tagged fields
+            buf
+        }
+
+        fn find_coordinator_response(&self) -> Vec<u8> {
+            self.find_coordinator_requests
+                .fetch_add(1, Ordering::SeqCst);
+            let mut buf = vec![];
+            buf.put_i32(0); // throttle_time_ms
+            buf.put_i16(0); // error_code
+            buf.put_i16(-1); // error_message
+            buf.put_i32(2); // node_id
+            put_string(&mut buf, "127.0.0.1");
+            buf.put_i32(self.coordinator_port.load(Ordering::SeqCst) as i32);
+            buf
+        }
+
+        fn end_txn_response(&self, request: &[u8]) -> Vec<u8> {
+            self.end_txn_requests.lock().unwrap().push(request.to_vec());
+            let error_code = if self
+                .not_coordinator_responses
+                .fetch_sub(1, Ordering::SeqCst)
+                > 0
+            {
+                KafkaCode::NotCoordinatorForGroup
+            } else {
+                KafkaCode::None
+            };
+            let mut buf = vec![];
+            buf.put_i32(0); // throttle_time_ms
+            buf.put_i16(error_code as i16);
+            buf
+        }
+
+        /// The required acks of a produce request, past the client id and
+        /// the transactional id.
+        fn acks(request: &[u8]) -> i16 {
+            let (_, acks) = read_string(request, body_offset(request));
+            read_i16(request, acks)
+        }
+
+        /// The first topic of a produce request, past the acks and timeout.
+        fn produced_topic(request: &[u8]) -> String {
+            read_string(request, Self::topic_offset(request)).0.unwrap()
+        }
+
+        /// Where the first topic of a produce request starts, past the
+        /// transactional id, acks, timeout and topic count.
+        fn topic_offset(request: &[u8]) -> usize {
+            read_string(request, body_offset(request)).1 + 2 + 4 + 4
+        }
+
+        /// The base sequence and record count of the first record batch of
+        /// a produce request.
+        fn produced_batch(request: &[u8]) -> (i32, i32) {
+            // past the topic name, partition count, partition index and records size
+            let batch = read_string(request, Self::topic_offset(request)).1 + 4 + 4 + 4;
+            let last_offset_delta = read_i32(request, batch + 23);
+            (read_i32(request, batch + 53), last_offset_delta + 1)
+        }
+
+        /// The transactional id of a produce request, and the attributes of
+        /// its first record batch.
+        fn produced_transaction(request: &[u8]) -> (Option<String>, i16) {
+            let (id, _) = read_string(request, body_offset(request));
+            // past the topic name, partition count, partition index and records size
+            let batch = read_string(request, Self::topic_offset(request)).1 + 4 + 4 + 4;
+            (id, read_i16(request, batch + 21))
+        }
+
+        /// Where a record value was written in the log.
+        fn log_position(&self, value: &[u8]) -> usize {
+            self.log
+                .lock()
+                .unwrap()
+                .windows(value.len())
+                .position(|window| window == value)
+                .unwrap()
+        }
+
+        async fn serve(self: Arc<Self>, mut socket: TcpStream) {
+            while let Some(request) = read_request(&mut socket).await {
+                if api_key(&request) == 0 {
+                    *self.last_produce_request.lock().unwrap() = request.clone();
+                }
+                let body = match api_key(&request) {
+                    0 if self.drops_produce_requests.load(Ordering::SeqCst) => return,
+                    // legacy message sets are only checked for their version
+                    0 if api_version(&request) < 3 => {
+                        self.produce_requests.fetch_add(1, Ordering::SeqCst);
+                        continue;
+                    }
+                    0 if Self::acks(&request) == 0 => {
+                        // the broker does not answer when no acks are required
+                        self.produce_requests.fetch_add(1, Ordering::SeqCst);
+                        continue;
+                    }
+                    0 => self.produce_response(&request),
+                    3 => self.metadata_response(&request),
+                    10 => self.find_coordinator_response(),
+                    18 => self.api_versions_response(),
+                    22 => {
+                        let requests = &self.init_producer_id_requests;
+                        requests.lock().unwrap().push(request.clone());
+                        Self::init_producer_id_response(&request)
+                    }
+                    26 => self.end_txn_response(&request),
+                    api_key => panic!("Unexpected api key {}", api_key),
+                };
+                write_response(&mut socket, &request, &body).await;
+            }
+        }
+    }
+
+    fn message(value: &'static [u8]) -> ProduceMessage {
+        ProduceMessage {
+            key: None,
+            value: Some(Bytes::from_static(value)),
+            headers: vec![],
+            topic: TOPIC.to_owned(),
+            partition_id: 0,
+        }
+    }
+
+    #[test]
+    fn it_builds_the_bytes_of_a_produce_request() {
+        // size, header, acks 1, timeout 1000, then a record batch holding
+        // "key" and "value" for partition 0, with zeroed timestamps
+        let encoded_buf = [
+            0, 0, 0, 125, 0, 0, 0, 3, 0, 0, 0, 2, 0, 4, 114, 117, 115, 116, 255, 255, 0, 1, 0, 0,
+            3, 232, 0, 0, 0, 1, 0, 9, 112, 117, 114, 99, 104, 97, 115, 101, 115, 0, 0, 0, 1, 0, 0,
+            0, 0, 0, 0, 0, 76, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 64, 255, 255, 255, 255, 2, 28, 7,
+            119, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 255, 255,
+            255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 0, 0, 0, 1, 28, 0, 0, 0, 6,
+            107, 101, 121, 10, 118, 97, 108, 117, 101, 0,
+        ];
+        let mut message = message(b"value");
+        message.key = Some(Bytes::from_static(b"key"));
+
+        let buffer = build_produce_request(&[message], 1, 1000, None, "rust", 2).unwrap();
+
+        // the timestamps come from the clock, zero them and the delta of the
+        // record, then recompute the CRC over the rest of the batch
+        let mut buffer = buffer.to_vec();
+        buffer[80..96].fill(0);
+        buffer[116] = 0;
+        let crc = crate::utils::to_crc(&buffer[74..]);
+        buffer[70..74].copy_from_slice(&crc.to_be_bytes());
+        assert_eq!(buffer, encoded_buf);
+    }
+
+    #[tokio::test]
+    async fn it_refreshes_metadata_and_retries_on_stale_leader_epoch() {
+        let broker = MockBroker::start(1, KafkaCode::FencedLeaderEpoch).await;
+        let mut producer = broker
+            .producer()
+            .await
+            .required_acks(1)
+            .batch_timeout_ms(1)
+            .clone()
+            .build()
+            .await;
+
+        producer.produce(message(b"value")).await;
+
+        let responses = producer.receiver.recv().await.unwrap();
+        assert_eq!(responses.len(), 1);
+        let response = responses[0].as_ref().unwrap();
+        assert_eq!(
+            response.responses[0].partition_responses[0].error_code,
+            KafkaCode::None
+        );
+        assert_eq!(broker.produce_requests.load(Ordering::SeqCst), 2);
+        assert_eq!(broker.metadata_requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn it_flushes_once_the_batch_timeout_passes_on_the_clock() {
+        let broker = MockBroker::start(0, KafkaCode::None).await;
+        let clock = MockClock::new();
+        let mut producer = broker
+            .producer()
+            .await
+            .required_acks(1)
+            .batch_timeout_ms(100)
+            .clock(clock.clone())
+            .clone()
+            .build()
+            .await;
+
+        producer.produce(message(b"value")).await;
+        // wait for the batch to start lingering
+        while clock.sleeping() == 0 {
+            tokio::task::yield_now().await;
+        }
+        clock.advance(Duration::from_millis(99));
+        for _ in 0..10 {
+            tokio::task::yield_now().await;
+        }
+        assert!(producer.receiver.try_recv().is_err());
+        assert_eq!(broker.produce_requests.load(Ordering::SeqCst), 0);
+
+        clock.advance(Duration::from_millis(1));
+        let responses = producer.receiver.recv().await.unwrap();
+
+        assert_eq!(responses.len(), 1);
+        assert_eq!(broker.produce_requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn it_reports_the_result_of_each_partition() {
+        let broker =
+            MockBroker::start_partitioned(0, KafkaCode::NotEnoughReplicas, 2, Some(1)).await;
+        let mut producer = broker
+            .producer()
+            .await
+            .required_acks(1)
+            .max_batch_size(2)
+            .clone()
+            .build()
+            .await;
+
+        producer.produce(message(b"first")).await;
+        producer
+            .produce(ProduceMessage {
+                partition_id: 1,
+                ..message(b"second")
+            })
+            .await;
+
+        let responses = producer.receiver.recv().await.unwrap();
+        assert_eq!(responses.len(), 1);
+        let results = responses[0].as_ref().unwrap().partition_results();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].partition, 0);
+        assert_eq!(results[0].result, Ok(100));
+        assert!(!results[0].is_retriable());
+        assert_eq!(results[1].partition, 1);
+        assert_eq!(results[1].result, Err(KafkaCode::NotEnoughReplicas));
+        assert!(results[1].is_retriable());
+
+        let retriable = responses[0].as_ref().unwrap().retriable_partitions();
+        assert_eq!(retriable, vec![results[1].clone()]);
+    }
+
+    #[tokio::test]
+    async fn it_resolves_each_send_with_the_offset_of_its_message() {
+        let broker = MockBroker::start(0, KafkaCode::None).await;
+        let producer = broker
+            .producer()
+            .await
+            .required_acks(1)
+            .max_batch_size(3)
+            .batch_timeout_ms(100)
+            .clone()
+            .build()
+            .await;
+
+        let (first, second, third) = tokio::join!(
+            producer.send(message(b"first")),
+            producer.send(message(b"second")),
+            producer.send(message(b"third")),
+        );
+
+        // all three went out in one batch, written from offset 100
+        assert_eq!(broker.produce_requests.load(Ordering::SeqCst), 1);
+        assert_eq!(first, Ok((0, 100)));
+        assert_eq!(second, Ok((0, 101)));
+        assert_eq!(third, Ok((0, 102)));
+    }
+
+    struct TraceInterceptor {
+        acknowledged: Arc<std::sync::Mutex<Vec<DeliveryReport>>>,
+    }
+
+    impl Interceptor for TraceInterceptor {
+        fn on_send(&self, message: &mut ProduceMessage) {
+            message.headers.push(Header::new(
+                "trace-id".to_owned(),
+                Bytes::from_static(b"abc123"),
+            ));
+        }
+
+        fn on_acknowledgement(&self, report: &DeliveryReport) {
+            self.acknowledged.lock().unwrap().push(report.clone());
+        }
+    }
+
+    #[tokio::test]
+    async fn it_runs_interceptors_on_send_and_acknowledgement() {
+        let broker = MockBroker::start(0, KafkaCode::None).await;
+        let acknowledged = Arc::new(std::sync::Mutex::new(vec![]));
+        let producer = broker
+            .producer()
+            .await
+            .required_acks(1)
+            .max_batch_size(2)
+            .batch_timeout_ms(100)
+            .interceptor(TraceInterceptor {
+                acknowledged: acknowledged.clone(),
+            })
+            .clone()
+            .build()
+            .await;
+
+        let (first, second) = tokio::join!(
+            producer.send(message(b"first")),
+            producer.send(message(b"second")),
+        );
+        assert_eq!(first, Ok((0, 100)));
+        assert_eq!(second, Ok((0, 101)));
+
+        // the header the interceptor added was written with each record
+        let log = broker.log.lock().unwrap().clone();
+        let written = |value: &[u8]| log.windows(value.len()).filter(|w| *w == value).count();
+        assert_eq!(written(b"trace-id"), 2);
+        assert_eq!(written(b"abc123"), 2);
+
+        let acknowledged = acknowledged.lock().unwrap();
+        assert_eq!(
+            *acknowledged,
+            vec![
+                DeliveryReport {
+                    topic: TOPIC.to_owned(),
+                    partition_id: 0,
+                    offset: Ok(100),
+                },
+                DeliveryReport {
+                    topic: TOPIC.to_owned(),
+                    partition_id: 0,
+                    offset: Ok(101),
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn it_merges_the_default_headers_into_each_message() {
+        let broker = MockBroker::start(0, KafkaCode::None).await;
+        let producer = broker
+            .producer()
+            .await
+            .required_acks(1)
+            .max_batch_size(1)
+            .default_headers(vec![
+                ("environment".to_owned(), Bytes::from_static(b"staging")),
+                ("app-version".to_owned(), Bytes::from_static(b"v1.2.0")),
+            ])
+            .clone()
+            .build()
+            .await;
+
+        let offset = producer
+            .send(ProduceMessage {
+                headers: vec![
+                    Header::new("request-id".to_owned(), Bytes::from_static(b"r-42")),
+                    Header::new("app-version".to_owned(), Bytes::from_static(b"v1.3.0")),
+                ],
+                ..message(b"first")
+            })
+            .await;
+        assert_eq!(offset, Ok((0, 100)));
+
+        let log = broker.log.lock().unwrap().clone();
+        let written = |value: &[u8]| log.windows(value.len()).filter(|w| *w == value).count();
+        assert_eq!(written(b"environment"), 1);
+        assert_eq!(written(b"staging"), 1);
+        assert_eq!(written(b"request-id"), 1);
+        assert_eq!(written(b"r-42"), 1);
+        // the header of the message wins over the default one
+        assert_eq!(written(b"app-version"), 1);
+        assert_eq!(written(b"v1.3.0"), 1);
+        assert_eq!(written(b"v1.2.0"), 0);
+    }
+
+    #[tokio::test]
+    async fn it_rejects_a_value_over_the_max_value_bytes_locally() {
+        let broker = MockBroker::start(0, KafkaCode::None).await;
+        let producer = broker
+            .producer()
+            .await
+            .required_acks(1)
+            .max_batch_size(1)
+            .max_value_bytes(8)
+            .clone()
+            .build()
+            .await;
+
+        let offset = producer.send(message(b"far too large")).await;
+
+        assert_eq!(offset, Err(Error::MessageTooLarge(TOPIC.to_owned(), 13, 8)));
+        assert_eq!(broker.produce_requests.load(Ordering::SeqCst), 0);
+        assert_eq!(producer.send(message(b"fits")).await, Ok((0, 100)));
+    }
+
+    #[tokio::test]
+    async fn it_writes_each_partition_of_a_chunk_at_contiguous_offsets() {
+        let broker = MockBroker::start_partitioned(0, KafkaCode::None, 2, None).await;
+        let producer = broker
+            .producer()
+            .await
+            .required_acks(1)
+            .max_batch_size(5)
+            .batch_timeout_ms(100)
+            .clone()
+            .build()
+            .await;
+        let on = |partition_id, value| ProduceMessage {
+            partition_id,
+            ..message(value)
+        };
+
+        let offsets = tokio::join!(
+            producer.send(on(0, b"a")),
+            producer.send(on(1, b"b")),
+            producer.send(on(0, b"c")),
+            producer.send(on(1, b"d")),
+            producer.send(on(0, b"e")),
+        );
+
+        // one request with a batch per partition, which the mock broker
+        // writes from offset 100 and 101
+        assert_eq!(broker.produce_requests.load(Ordering::SeqCst), 1);
+        assert_eq!(
+            offsets,
+            (
+                Ok((0, 100)),
+                Ok((1, 101)),
+                Ok((0, 101)),
+                Ok((1, 102)),
+                Ok((0, 102)),
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn it_stops_writing_once_the_producer_is_fenced() {
+        let broker = MockBroker::start(i32::MAX, KafkaCode::ProducerFenced).await;
+        let failures = Arc::new(std::sync::Mutex::new(vec![]));
+        let dead_letters = failures.clone();
+        let mut producer = broker
+            .producer()
+            .await
+            .idempotent(true)
+            .batch_timeout_ms(10)
+            .on_delivery_failure(move |message, err| {
+                dead_letters.lock().unwrap().push((message, err));
+            })
+            .clone()
+            .build()
+            .await;
+
+        assert_eq!(
+            producer.send(message(b"first")).await,
+            Err(Error::ProducerFenced)
+        );
+        // fencing is not retried
+        assert_eq!(broker.produce_requests.load(Ordering::SeqCst), 1);
+        assert!(producer.is_fenced());
+
+        // later records fail without reaching the broker
+        assert_eq!(
+            producer.send(message(b"second")).await,
+            Err(Error::ProducerFenced)
+        );
+        producer.produce(message(b"third")).await;
+        producer.close().await;
+        assert_eq!(broker.produce_requests.load(Ordering::SeqCst), 1);
+
+        let failures = failures.lock().unwrap();
+        let (message, err) = failures.last().unwrap();
+        assert_eq!(message.value, Some(Bytes::from_static(b"third")));
+        assert_eq!(*err, Error::ProducerFenced);
+    }
+
+    #[tokio::test]
+    async fn it_hands_undeliverable_messages_to_the_failure_callback() {
+        let broker = MockBroker::start(i32::MAX, KafkaCode::NotLeaderForPartition).await;
+        let failures = Arc::new(std::sync::Mutex::new(vec![]));
+        let dead_letters = failures.clone();
+        let mut producer = broker
+            .producer()
+            .await
+            .required_acks(1)
+            .max_batch_size(2)
+            .on_delivery_failure(move |message, err| {
+                dead_letters.lock().unwrap().push((message, err));
+            })
+            .clone()
+            .build()
+            .await;
+
+        producer.produce(message(b"first")).await;
+        producer.produce(message(b"second")).await;
+        producer.receiver.recv().await.unwrap();
+
+        let failures = failures.lock().unwrap();
+        assert_eq!(failures.len(), 2);
+        for ((message, err), value) in failures.iter().zip([&b"first"[..], b"second"]) {
+            assert_eq!(message.value, Some(Bytes::from_static(value)));
+            assert_eq!(message.topic, TOPIC);
+            assert_eq!(*err, Error::KafkaError(KafkaCode::NotLeaderForPartition));
+        }
+        assert_eq!(
+            broker.produce_requests.load(Ordering::SeqCst),
+            1 + MAX_STALE_METADATA_RETRIES as i32
+        );
+    }
+
+    #[tokio::test]
+    async fn it_hands_only_the_undelivered_messages_to_the_failure_callback() {
+        let unreachable = MockBroker::start(0, KafkaCode::None).await;
+        unreachable
+            .drops_produce_requests
+            .store(true, Ordering::SeqCst);
+        let broker = MockBroker::start_partitioned(0, KafkaCode::None, 2, None).await;
+        broker
+            .second_leader_port
+            .store(unreachable.port, Ordering::SeqCst);
+        let failures = Arc::new(std::sync::Mutex::new(vec![]));
+        let dead_letters = failures.clone();
+        let producer = broker
+            .producer()
+            .await
+            .required_acks(1)
+            .max_batch_size(2)
+            .on_delivery_failure(move |message, _| {
+                dead_letters.lock().unwrap().push(message);
+            })
+            .clone()
+            .build()
+            .await;
+
+        let mut delivered = message(b"delivered");
+        delivered.partition_id = 0;
+        let mut undelivered = message(b"undelivered");
+        undelivered.partition_id = 1;
+        let (delivered, undelivered) =
+            tokio::join!(producer.send(delivered), producer.send(undelivered));
+
+        // the first leader wrote its partition before the second one failed
+        assert_eq!(delivered, Ok((0, 100)));
+        assert!(undelivered.is_err());
+        let failures = failures.lock().unwrap();
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].value, Some(Bytes::from_static(b"undelivered")));
+        assert_eq!(failures[0].partition_id, 1);
+    }
+
+    #[tokio::test]
+    async fn it_gives_each_partition_its_own_retry_budget() {
+        let broker =
+            MockBroker::start_partitioned(0, KafkaCode::NotLeaderForPartition, 2, Some(1)).await;
+        let failures = Arc::new(std::sync::Mutex::new(vec![]));
+        let dead_letters = failures.clone();
+        let mut producer = broker
+            .producer()
+            .await
+            .required_acks(1)
+            .max_batch_size(2)
+            .on_delivery_failure(move |message, err| {
+                dead_letters.lock().unwrap().push((message, err));
+            })
+            .clone()
+            .build()
+            .await;
+
+        producer.produce(message(b"healthy")).await;
+        producer
+            .produce(ProduceMessage {
+                partition_id: 1,
+                ..message(b"flaky")
+            })
+            .await;
+        producer.receiver.recv().await.unwrap();
+
+        // only the flaky partition was retried, until its budget ran out
+        assert_eq!(
+            broker.produce_requests.load(Ordering::SeqCst),
+            1 + MAX_STALE_METADATA_RETRIES as i32
+        );
+        let log = broker.log.lock().unwrap().clone();
+        let written = |value: &[u8]| log.windows(value.len()).filter(|w| *w == value).count();
+        assert_eq!(written(b"healthy"), 1);
+        assert_eq!(written(b"flaky"), 1 + MAX_STALE_METADATA_RETRIES);
+
+        let failures = failures.lock().unwrap();
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].0.value, Some(Bytes::from_static(b"flaky")));
+        assert_eq!(
+            failures[0].1,
+            Error::KafkaError(KafkaCode::NotLeaderForPartition)
+        );
+    }
+
+    #[tokio::test]
+    async fn it_keeps_the_send_order_when_retrying_a_batch() {
+        let broker = MockBroker::start(1, KafkaCode::NotLeaderForPartition).await;
+        let mut producer = broker
+            .producer()
+            .await
+            .required_acks(1)
+            .max_batch_size(1)
+            .max_in_flight_requests(1)
+            .clone()
+            .build()
+            .await;
+
+        let values = [&b"batch-1"[..], b"batch-2", b"batch-3"];
+        for value in values {
+            producer.produce(message(value)).await;
+        }
+        for _ in values {
+            producer.receiver.recv().await.unwrap();
+        }
+
+        assert_eq!(broker.produce_requests.load(Ordering::SeqCst), 4);
+        let positions: Vec<usize> = values
+            .iter()
+            .map(|value| broker.log_position(value))
+            .collect();
+        assert!(positions.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[tokio::test]
+    async fn it_completes_without_a_response_when_no_acks_are_required() {
+        let broker = MockBroker::start(0, KafkaCode::None).await;
+        let mut producer = broker
+            .producer()
+            .await
+            .required_acks(0)
+            .batch_timeout_ms(1)
+            .clone()
+            .build()
+            .await;
+
+        producer.produce(message(b"value")).await;
+
+        let responses =
+            tokio::time::timeout(std::time::Duration::from_secs(1), producer.receiver.recv())
+                .await
+                .expect("producer is waiting for a response")
+                .unwrap();
+        assert_eq!(responses.len(), 1);
+        assert!(responses[0].is_none());
+        // the send completes before the broker gets to the request
+        for _ in 0..100 {
+            if broker.produce_requests.load(Ordering::SeqCst) > 0 {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert_eq!(broker.produce_requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn it_negotiates_the_produce_version_while_chaining_setters() {
+        let broker = MockBroker::start(0, KafkaCode::None).await;
+        broker.max_produce_version.store(2, Ordering::SeqCst);
+        let mut builder = broker.producer().await;
+        let mut producer = builder
+            .negotiate_produce_version()
+            .await
+            .unwrap()
+            .batch_timeout_ms(1)
+            .clone()
+            .build()
+            .await;
+
+        producer.produce(message(b"value")).await;
+        producer.close().await;
+
+        // without acks the producer does not wait for the broker to read it
+        for _ in 0..100 {
+            if broker.produce_requests.load(Ordering::SeqCst) > 0 {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        let request = broker.last_produce_request.lock().unwrap().clone();
+        assert_eq!(api_version(&request), 2);
+    }
+
+    #[tokio::test]
+    async fn it_produces_over_a_boxed_connection() {
+        let broker = MockBroker::start(0, KafkaCode::None).await;
+        let options = BoxedConnectionConfig::Tcp(vec![broker_address(broker.port)]);
+        let mut producer = ProducerBuilder::<BoxedConnection>::new(options, vec![TOPIC.to_owned()])
+            .await
+            .unwrap()
+            .required_acks(1)
+            .batch_timeout_ms(1)
+            .clone()
+            .build()
+            .await;
+
+        producer.produce(message(b"value")).await;
+
+        let responses = producer.receiver.recv().await.unwrap();
+        let response = responses[0].as_ref().unwrap();
+        assert_eq!(
+            response.responses[0].partition_responses[0].error_code,
+            KafkaCode::None
+        );
+        assert_eq!(broker.produce_requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn it_rejects_partitions_beyond_the_partition_count() {
+        let broker = MockBroker::start(0, KafkaCode::None).await;
+        let mut cluster_metadata = ClusterMetadata::<TcpConnection>::new(
+            vec![broker_address(broker.port)],
+            DEFAULT_CORRELATION_ID,
+            DEFAULT_CLIENT_ID.to_owned(),
+            vec![TOPIC.to_owned()],
+        )
+        .await
+        .unwrap();
+        let mut produce_params = ProduceParams::new();
+        produce_params.required_acks = 1;
+        produce_params.validate_partitions = true;
+        let mut out_of_range = message(b"value");
+        out_of_range.partition_id = 99;
+
+        let result = flush_producer(
+            &mut cluster_metadata,
+            &produce_params,
+            &[message(b"value"), out_of_range],
+            RecordBatchAttributes::new(None),
+            &mut vec![],
+            &mut WrittenParts::new(),
+        )
+        .await;
+
+        assert_eq!(
+            result.unwrap_err(),
+            Error::InvalidPartition(TOPIC.to_owned(), 99)
+        );
+        assert_eq!(broker.produce_requests.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn it_routes_messages_without_a_partition() {
+        let broker = MockBroker::start_partitioned(0, KafkaCode::None, 10, None).await;
+        let cluster_metadata = ClusterMetadata::<TcpConnection>::new(
+            vec![broker_address(broker.port)],
+            DEFAULT_CORRELATION_ID,
+            DEFAULT_CLIENT_ID.to_owned(),
+            vec![TOPIC.to_owned()],
+        )
+        .await
+        .unwrap();
+        let keyed = ProduceMessage {
+            key: Some(Bytes::from_static(b"foobar")),
+            partition_id: -1,
+            ..message(b"keyed")
+        };
+        let unkeyed = ProduceMessage {
+            partition_id: -1,
+            ..message(b"unkeyed")
+        };
+        let pinned = ProduceMessage {
+            key: Some(Bytes::from_static(b"foobar")),
+            partition_id: 3,
+            ..message(b"pinned")
+        };
+
+        let next_partition = AtomicUsize::new(4);
+
+        let messages = assign_partitions(
+            &cluster_metadata,
+            &next_partition,
+            vec![keyed, unkeyed, pinned],
+        );
+
+        assert_eq!(messages[0].partition_id, 6);
+        assert_eq!(messages[1].partition_id, 4);
+        assert_eq!(messages[2].partition_id, 3);
+        assert_eq!(next_partition.load(Ordering::SeqCst), 5);
+    }
+
+    #[tokio::test]
+    async fn it_spreads_messages_without_a_key_over_the_partitions() {
+        let broker = MockBroker::start_partitioned(0, KafkaCode::None, 3, None).await;
+        let producer = broker
+            .producer()
+            .await
+            .required_acks(1)
+            .batch_timeout_ms(1)
+            .clone()
+            .build()
+            .await;
+
+        let mut partitions = vec![];
+        for _ in 0..6 {
+            let unpartitioned = ProduceMessage {
+                partition_id: -1,
+                ..message(b"value")
+            };
+            let (partition, _) = producer.send(unpartitioned).await.unwrap();
+            partitions.push(partition);
+        }
+
+        assert_eq!(partitions, vec![0, 1, 2, 0, 1, 2]);
+    }
+
+    #[cfg(feature = "test-internals")]
+    #[tokio::test]
+    async fn it_advances_the_sequence_by_the_records_of_each_batch() {
+        let broker = MockBroker::start(0, KafkaCode::None).await;
+        let mut producer = broker
+            .producer()
+            .await
+            .idempotent(true)
+            .max_batch_size(3)
+            .batch_timeout_ms(50)
+            .clone()
+            .build()
+            .await;
+        let topic_partition = (TOPIC.to_owned(), 0);
+        assert_eq!(producer.current_sequence(&topic_partition), None);
+
+        for value in [&b"first"[..], b"second", b"third"] {
+            producer.produce(message(value)).await;
+        }
+        producer.receiver.recv().await.unwrap();
+        assert_eq!(producer.current_sequence(&topic_partition), Some(3));
+
+        for value in [&b"fourth"[..], b"fifth"] {
+            producer.produce(message(value)).await;
+        }
+        producer.receiver.recv().await.unwrap();
+        assert_eq!(producer.current_sequence(&topic_partition), Some(5));
+    }
+
+    #[tokio::test]
+    async fn it_requires_acks_from_the_full_isr_when_idempotent() {
+        let broker = MockBroker::start(0, KafkaCode::None).await;
+        let mut idempotent_first = broker.producer().await;
+        idempotent_first.idempotent(true).required_acks(1);
+        let mut acks_first = broker.producer().await;
+        acks_first.required_acks(1).idempotent(true);
+
+        for mut builder in [idempotent_first, acks_first] {
+            let producer = builder.batch_timeout_ms(1).clone().build().await;
+            producer.send(message(b"value")).await.unwrap();
+
+            let request = broker.last_produce_request.lock().unwrap().clone();
+            assert_eq!(MockBroker::acks(&request), -1);
+        }
+    }
+
+    #[tokio::test]
+    async fn it_splits_too_large_batches_keeping_sequences_contiguous() {
+        let broker = MockBroker::start(0, KafkaCode::None).await;
+        broker.max_records.store(2, Ordering::SeqCst);
+        *broker.log_end_offset.lock().unwrap() = Some(100);
+        let producer = broker
+            .producer()
+            .await
+            .idempotent(true)
+            .required_acks(1)
+            .max_batch_size(5)
+            .batch_timeout_ms(50)
+            .clone()
+            .build()
+            .await;
+
+        let values = [&b"first"[..], b"second", b"third", b"fourth", b"fifth"];
+        let offsets = tokio::join!(
+            producer.send(message(values[0])),
+            producer.send(message(values[1])),
+            producer.send(message(values[2])),
+            producer.send(message(values[3])),
+            producer.send(message(values[4])),
+        );
+
+        // 5 records are split into 2 and 3, then the 3 into 1 and 2
+        assert_eq!(
+            *broker.accepted_batches.lock().unwrap(),
+            vec![(0, 2), (2, 1), (3, 2)]
+        );
+        let positions: Vec<usize> = values
+            .iter()
+            .map(|value| broker.log_position(value))
+            .collect();
+        assert!(positions.windows(2).all(|pair| pair[0] < pair[1]));
+        // each part is written at its own base offset, after the records
+        // other producers wrote in between
+        assert_eq!(
+            offsets,
+            (
+                Ok((0, 100)),
+                Ok((0, 101)),
+                Ok((0, 112)),
+                Ok((0, 123)),
+                Ok((0, 124)),
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn it_fails_only_the_records_of_the_rejected_part_of_a_split_batch() {
+        let broker = MockBroker::start(0, KafkaCode::CorruptMessage).await;
+        broker.max_records.store(2, Ordering::SeqCst);
+        // the batch of 5 twice, the first part of 2, the second part of 3,
+        // then the first half of the second part
+        broker.rejected_produce_request.store(4, Ordering::SeqCst);
+        let failures = Arc::new(std::sync::Mutex::new(vec![]));
+        let dead_letters = failures.clone();
+        let producer = broker
+            .producer()
+            .await
+            .required_acks(1)
+            .max_batch_size(5)
+            .batch_timeout_ms(50)
+            .on_delivery_failure(move |message, err| {
+                dead_letters.lock().unwrap().push((message, err));
+            })
+            .clone()
+            .build()
+            .await;
+
+        let offsets = tokio::join!(
+            producer.send(message(b"first")),
+            producer.send(message(b"second")),
+            producer.send(message(b"third")),
+            producer.send(message(b"fourth")),
+            producer.send(message(b"fifth")),
+        );
+
+        // the first part was written, the part after the rejected one not
+        let rejected = Err(Error::KafkaError(KafkaCode::CorruptMessage));
+        assert_eq!(
+            offsets,
+            (
+                Ok((0, 100)),
+                Ok((0, 101)),
+                rejected.clone(),
+                rejected.clone(),
+                rejected,
+            )
+        );
+        assert_eq!(broker.produce_requests.load(Ordering::SeqCst), 5);
+        let failures = failures.lock().unwrap();
+        let failed: Vec<Option<Bytes>> = failures
+            .iter()
+            .map(|(message, _)| message.value.clone())
+            .collect();
+        assert_eq!(
+            failed,
+            vec![
+                Some(Bytes::from_static(b"third")),
+                Some(Bytes::from_static(b"fourth")),
+                Some(Bytes::from_static(b"fifth")),
+            ]
+        );
+        assert!(failures
+            .iter()
+            .all(|(_, err)| *err == Error::KafkaError(KafkaCode::CorruptMessage)));
+    }
+
+    #[tokio::test]
+    async fn it_sends_the_transactional_id_and_timeout() {
+        let broker = MockBroker::start(0, KafkaCode::None).await;
+        let mut producer = broker
+            .producer()
+            .await
+            .transactional_id("payments".to_owned())
+            .transaction_timeout_ms(30000)
+            .batch_timeout_ms(1)
+            .clone()
+            .build()
+            .await;
+
+        producer.produce(message(b"value")).await;
+        producer.receiver.recv().await.unwrap();
+
+        let mut expected = vec![];
+        protocol::InitProducerIdRequest::new(
+            DEFAULT_CORRELATION_ID,
+            DEFAULT_CLIENT_ID,
+            Some("payments"),
+            30000,
+        )
+        .encode(&mut expected)
+        .unwrap();
+        let requests = broker.init_producer_id_requests.lock().unwrap();
+        assert_eq!(requests.len(), 1);
+        // the connection hands out its own correlation ids
+        assert_eq!(requests[0][..4], expected[..4]);
+        assert_eq!(requests[0][8..], expected[8..]);
+        // the producer id of a transactional id comes from its coordinator
+        assert_eq!(broker.find_coordinator_requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn it_marks_the_batches_of_a_transactional_producer() {
+        let broker = MockBroker::start(0, KafkaCode::None).await;
+        let mut producer = broker
+            .producer()
+            .await
+            .transactional_id("payments".to_owned())
+            .required_acks(1)
+            .batch_timeout_ms(1)
+            .clone()
+            .build()
+            .await;
+
+        producer.produce(message(b"value")).await;
+        producer.receiver.recv().await.unwrap();
+
+        let request = broker.last_produce_request.lock().unwrap().clone();
+        let (transactional_id, attributes) = MockBroker::produced_transaction(&request);
+        assert_eq!(transactional_id.as_deref(), Some("payments"));
+        assert_eq!(attributes & 0x10, 0x10);
+    }
+
+    async fn cluster_metadata(broker: &MockBroker) -> ClusterMetadata<TcpConnection> {
+        ClusterMetadata::new(
+            vec![broker_address(broker.port)],
+            DEFAULT_CORRELATION_ID,
+            DEFAULT_CLIENT_ID.to_owned(),
+            vec![TOPIC.to_owned()],
+        )
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn it_ends_a_transaction_on_its_coordinator() {
+        let bootstrap = MockBroker::start(0, KafkaCode::None).await;
+        let coordinator = MockBroker::start(0, KafkaCode::None).await;
+        bootstrap
+            .coordinator_port
+            .store(coordinator.port, Ordering::SeqCst);
+        let cluster_metadata = cluster_metadata(&bootstrap).await;
+
+        end_txn(
+            &cluster_metadata,
+            DEFAULT_CORRELATION_ID,
+            DEFAULT_CLIENT_ID,
+            "payments",
+            PRODUCER_ID,
+            0,
+            true,
+        )
+        .await
+        .unwrap();
+
+        let mut expected = vec![];
+        protocol::EndTxnRequest::new(
+            DEFAULT_CORRELATION_ID,
+            DEFAULT_CLIENT_ID,
+            "payments",
+            PRODUCER_ID,
+            0,
+            true,
+        )
+        .encode(&mut expected)
+        .unwrap();
+        assert_eq!(
+            bootstrap.find_coordinator_requests.load(Ordering::SeqCst),
+            1
+        );
+        assert!(bootstrap.end_txn_requests.lock().unwrap().is_empty());
+        let requests = coordinator.end_txn_requests.lock().unwrap();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0][8..], expected[8..]);
+    }
+
+    #[tokio::test]
+    async fn it_reuses_the_connection_to_the_transaction_coordinator() {
+        let bootstrap = MockBroker::start(0, KafkaCode::None).await;
+        let coordinator = MockBroker::start(0, KafkaCode::None).await;
+        bootstrap
+            .coordinator_port
+            .store(coordinator.port, Ordering::SeqCst);
+        let cluster_metadata = cluster_metadata(&bootstrap).await;
+
+        for committed in [true, false] {
+            end_txn(
+                &cluster_metadata.clone(),
+                DEFAULT_CORRELATION_ID,
+                DEFAULT_CLIENT_ID,
+                "payments",
+                PRODUCER_ID,
+                0,
+                committed,
+            )
+            .await
+            .unwrap();
+        }
+
+        assert_eq!(
+            bootstrap.find_coordinator_requests.load(Ordering::SeqCst),
+            1
+        );
+        assert_eq!(coordinator.connections.load(Ordering::SeqCst), 1);
+        assert_eq!(coordinator.end_txn_requests.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn it_looks_up_the_transaction_coordinator_again_when_it_moved() {
+        let bootstrap = MockBroker::start(0, KafkaCode::None).await;
+        let coordinator = MockBroker::start(0, KafkaCode::None).await;
+        bootstrap
+            .coordinator_port
+            .store(coordinator.port, Ordering::SeqCst);
+        coordinator
+            .not_coordinator_responses
+            .store(1, Ordering::SeqCst);
+        let cluster_metadata = cluster_metadata(&bootstrap).await;
+
+        end_txn(
+            &cluster_metadata,
+            DEFAULT_CORRELATION_ID,
+            DEFAULT_CLIENT_ID,
+            "payments",
+            PRODUCER_ID,
+            0,
+            false,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            bootstrap.find_coordinator_requests.load(Ordering::SeqCst),
+            2
+        );
+        assert_eq!(coordinator.end_txn_requests.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn it_does_not_send_a_request_when_there_is_nothing_to_flush() {
+        let broker = MockBroker::start(0, KafkaCode::None).await;
+
+        // closing an idle producer flushes nothing
+        let mut producer = broker
+            .producer()
+            .await
+            .idempotent(true)
+            .required_acks(1)
+            .clone()
+            .build()
+            .await;
+        producer.close().await;
+        assert!(producer.receiver.recv().await.is_none());
+
+        // neither does an empty batch of a stream
+        let responses: Vec<_> = broker
+            .producer()
+            .await
+            .idempotent(true)
+            .required_acks(1)
+            .clone()
+            .build_from_stream(tokio_stream::iter(vec![vec![]]))
+            .await
+            .collect()
+            .await;
+        assert!(responses.is_empty());
+
+        let conn = TcpConnection::new_(vec![broker_address(broker.port)])
+            .await
+            .unwrap();
+        let response = produce(
+            conn,
+            DEFAULT_CORRELATION_ID,
+            DEFAULT_CLIENT_ID,
+            1,
+            1000,
+            &vec![],
+            RecordBatchAttributes::new(None),
+        )
+        .await
+        .unwrap();
+        assert!(response.is_none());
+
+        assert_eq!(broker.produce_requests.load(Ordering::SeqCst), 0);
+        assert!(broker.init_producer_id_requests.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn it_leaves_the_transaction_timeout_limit_to_the_broker() {
+        let broker = MockBroker::start(0, KafkaCode::None).await;
+        let conn = TcpConnection::new_(vec![broker_address(broker.port)])
+            .await
+            .unwrap();
+        let init = |transaction_timeout_ms| {
+            init_producer_id(
+                conn.clone(),
+                DEFAULT_CORRELATION_ID,
+                DEFAULT_CLIENT_ID,
+                Some("payments"),
+                transaction_timeout_ms,
+            )
+        };
+
+        assert!(matches!(init(0).await, Err(Error::ArgError(_))));
+        assert!(broker.init_producer_id_requests.lock().unwrap().is_empty());
+
+        assert_eq!(
+            init(900001).await.unwrap_err(),
+            Error::KafkaError(KafkaCode::InvalidTransactionTimeout)
+        );
+        assert!(init(900000).await.is_ok());
+        assert_eq!(broker.init_producer_id_requests.lock().unwrap().len(), 2);
+    }
+
+    /// Collects everything a tracing subscriber writes.
+    #[derive(Clone, Default)]
+    struct CapturedLogs(Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for CapturedLogs {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn it_flushes_buffered_records_on_close() {
+        let broker = MockBroker::start(0, KafkaCode::None).await;
+        let mut producer = broker
+            .producer()
+            .await
+            .required_acks(1)
+            .batch_timeout_ms(60000)
+            .clone()
+            .build()
+            .await;
+
+        producer.produce(message(b"value")).await;
+        assert_eq!(producer.unflushed_records(), 1);
+        producer.close().await;
+
+        assert_eq!(producer.unflushed_records(), 0);
+        assert_eq!(broker.produce_requests.load(Ordering::SeqCst), 1);
+        assert!(producer.receiver.recv().await.is_some());
+    }
+
+    #[tokio::test]
+    async fn it_warns_when_dropped_with_unflushed_records() {
+        let broker = MockBroker::start(0, KafkaCode::None).await;
+        let producer = broker
+            .producer()
+            .await
+            .required_acks(1)
+            .batch_timeout_ms(60000)
+            .clone()
+            .build()
+            .await;
+        producer.produce(message(b"value")).await;
+
+        let logs = CapturedLogs::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(move || writer.clone())
+            .finish();
+        tracing::subscriber::with_default(subscriber, || drop(producer));
+
+        let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        assert!(logs.contains("1 unflushed records"), "{logs}");
+    }
+
+    #[tokio::test]
+    async fn it_produces_to_a_topic_that_was_not_passed_to_the_builder() {
+        let broker = MockBroker::start(0, KafkaCode::None).await;
+        let mut producer = broker
+            .producer()
+            .await
+            .required_acks(1)
+            .batch_timeout_ms(1)
+            .clone()
+            .build()
+            .await;
+        let metadata_requests = broker.metadata_requests.load(Ordering::SeqCst);
+
+        let mut message = message(b"value");
+        message.topic = "refunds".to_owned();
+        producer.produce(message).await;
+
+        let responses = producer.receiver.recv().await.unwrap();
+        let response = responses[0].as_ref().unwrap();
+        assert_eq!(response.responses[0].name, Bytes::from_static(b"refunds"));
+        assert_eq!(
+            response.responses[0].partition_responses[0].error_code,
+            KafkaCode::None
+        );
+        assert_eq!(
+            broker.metadata_requests.load(Ordering::SeqCst),
+            metadata_requests + 1
+        );
+
+        // the metadata of the topic is cached
+        let mut message = self::message(b"again");
+        message.topic = "refunds".to_owned();
+        producer.produce(message).await;
+        producer.receiver.recv().await.unwrap();
+        assert_eq!(
+            broker.metadata_requests.load(Ordering::SeqCst),
+            metadata_requests + 1
+        );
+    }
+}
@@ -1,19 +1,34 @@
 use std::fmt::Debug;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 use std::time::Duration;
 
+use bytes::Bytes;
 use tokio::sync::mpsc::{channel, unbounded_channel, Receiver, UnboundedSender};
+use tokio::task::JoinSet;
 use tokio_stream::{Stream, StreamExt};
 
+use crate::admin;
+use crate::clock::{Clock, TokioClock};
 use crate::network::BrokerConnection;
 use crate::prelude::Compression;
-use crate::producer::{flush_producer, ProduceMessage, ProduceParams, Producer};
-use crate::protocol::produce::request::Attributes;
-use crate::protocol::ProduceResponse;
+use crate::producer::{
+    add_default_headers, assign_partitions, delivered_offsets, flush_producer,
+    DeliveryFailureCallback, DeliveryReport, DeliverySender, Interceptor, ProduceMessage,
+    ProduceParams, Producer, WrittenParts,
+};
+use crate::protocol::produce::request::{Header, RecordBatchAttributes, TimestampType};
+use crate::protocol::{self, ProduceResponse};
 use crate::DEFAULT_CORRELATION_ID;
-use crate::{error::Result, metadata::ClusterMetadata, DEFAULT_CLIENT_ID};
+use crate::{
+    error::{Error, KafkaCode, Result},
+    metadata::ClusterMetadata,
+    DEFAULT_CLIENT_ID,
+};
 
 const DEFAULT_MAX_BATCH_SIZE: usize = 100;
 const DEFAULT_BATCH_TIMEOUT_MS: u64 = 1000;
+const DEFAULT_MAX_IN_FLIGHT_REQUESTS: usize = 1;
 
 /// Configure a [`Producer`].
 ///
@@ -49,7 +64,11 @@ pub struct ProducerBuilder<T: BrokerConnection> {
     produce_params: ProduceParams,
     max_batch_size: usize,
     batch_timeout_ms: u64,
-    attributes: Attributes,
+    attributes: RecordBatchAttributes,
+    on_delivery_failure: Option<DeliveryFailureCallback>,
+    idempotent: bool,
+    max_in_flight_requests: usize,
+    clock: Arc<dyn Clock>,
 }
 
 impl<T> ProducerBuilder<T>
@@ -57,6 +76,10 @@ where
     T: BrokerConnection + Clone + Debug + Send + Sync + 'static,
 {
     /// Start a producer builder. To complete, use the [`build`](Self::build) method.
+    ///
+    /// The metadata of the given topics is fetched up front. Messages can
+    /// also go to other topics, whose metadata is fetched and cached the
+    /// first time they are produced to.
     pub async fn new(connection_params: T::ConnConfig, topics: Vec<String>) -> Result<Self> {
         let cluster_metadata = ClusterMetadata::new(
             connection_params,
@@ -71,7 +94,11 @@ where
             produce_params: ProduceParams::new(),
             max_batch_size: DEFAULT_MAX_BATCH_SIZE,
             batch_timeout_ms: DEFAULT_BATCH_TIMEOUT_MS,
-            attributes: Attributes::new(None),
+            attributes: RecordBatchAttributes::new(None),
+            on_delivery_failure: None,
+            idempotent: false,
+            max_in_flight_requests: DEFAULT_MAX_IN_FLIGHT_REQUESTS,
+            clock: Arc::new(TokioClock),
         })
     }
 
@@ -110,6 +137,14 @@ where
     }
 
     /// The number of acknowledgments the producer requires the leader to have received before considering a request complete. Allowed values: 0 for no acknowledgments, 1 for only the leader and -1 for the full ISR.
+    ///
+    /// With 0, the default, the broker sends no response. Each batch counts as
+    /// complete as soon as it is written to the socket, and the output holds
+    /// `None` for it. This gives the highest throughput, but delivery is not
+    /// guaranteed and failed writes are never retried or reported.
+    ///
+    /// Idempotent and transactional producers always require -1, whatever is
+    /// set here.
     pub fn required_acks(&mut self, required_acks: i16) -> &mut Self {
         self.produce_params.required_acks = required_acks;
         self
@@ -126,28 +161,257 @@ where
         self
     }
 
+    /// The gzip level to compress batches at, from 0 for no compression to
+    /// 9, the default, for the best. Higher levels are clamped to 9.
+    ///
+    /// Lower levels use less CPU for larger batches.
+    pub fn compression_level(&mut self, compression_level: u32) -> &mut Self {
+        self.attributes.set_compression_level(compression_level);
+        self
+    }
+
+    /// The bytes reserved for each record batch before it is encoded.
+    ///
+    /// Uncompressed batches already reserve their exact size, so this helps
+    /// compressed batches, or workloads with predictable batch sizes, to be
+    /// encoded without growing the buffer.
+    pub fn initial_batch_capacity(&mut self, initial_batch_capacity: usize) -> &mut Self {
+        self.produce_params.initial_batch_capacity = initial_batch_capacity;
+        self
+    }
+
+    /// Check the partition of every message against the partition count in the
+    /// cached metadata before producing. Messages to a partition that does not
+    /// exist then fail locally with [`Error::InvalidPartition`], instead of being
+    /// rejected by the broker after a round trip.
+    pub fn validate_partitions(&mut self, validate_partitions: bool) -> &mut Self {
+        self.produce_params.validate_partitions = validate_partitions;
+        self
+    }
+
+    /// The largest key, in bytes, a message may have.
+    ///
+    /// Messages with a larger key fail locally with
+    /// [`Error::MessageTooLarge`], failing the flush they are in like
+    /// [`validate_partitions`](Self::validate_partitions) does.
+    pub fn max_key_bytes(&mut self, max_key_bytes: usize) -> &mut Self {
+        self.produce_params.max_key_bytes = Some(max_key_bytes);
+        self
+    }
+
+    /// The largest value, in bytes, a message may have.
+    ///
+    /// Messages with a larger value fail locally with
+    /// [`Error::MessageTooLarge`], failing the flush they are in like
+    /// [`validate_partitions`](Self::validate_partitions) does.
+    pub fn max_value_bytes(&mut self, max_value_bytes: usize) -> &mut Self {
+        self.produce_params.max_value_bytes = Some(max_value_bytes);
+        self
+    }
+
+    /// Handle messages that could not be delivered.
+    ///
+    /// Once a message has failed all of its retries, it is handed back to this
+    /// callback along with the error, e.g. to write it to a dead-letter topic
+    /// or to disk. Without a callback, failures are only logged.
+    ///
+    /// Only the messages the brokers did not write are handed back, even
+    /// when other messages of the same partition were written by another
+    /// part of a split batch, so replaying them does not write duplicates.
+    pub fn on_delivery_failure(
+        &mut self,
+        callback: impl Fn(ProduceMessage, Error) + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.on_delivery_failure = Some(Arc::new(callback));
+        self
+    }
+
+    /// Add an interceptor that sees each message before it is sent, and may
+    /// change it, and sees how each message was acknowledged.
+    ///
+    /// Interceptors are called in the order they were added.
+    pub fn interceptor(&mut self, interceptor: impl Interceptor + 'static) -> &mut Self {
+        self.produce_params.interceptors.push(Arc::new(interceptor));
+        self
+    }
+
+    /// Headers added to every message, e.g. the environment or the version
+    /// of the application.
+    ///
+    /// They come before the headers of the message itself, and a header of
+    /// the message wins over a default header with the same key.
+    pub fn default_headers(&mut self, default_headers: Vec<(String, Bytes)>) -> &mut Self {
+        self.produce_params.default_headers = default_headers
+            .into_iter()
+            .map(|(key, value)| Header::new(key, value))
+            .collect();
+        self
+    }
+
+    /// The clock batches linger on, the tokio runtime by default.
+    ///
+    /// Mostly useful to step through the [`batch_timeout_ms`](Self::batch_timeout_ms)
+    /// in tests with the `MockClock` of the `test-internals` feature.
+    pub fn clock(&mut self, clock: impl Clock + 'static) -> &mut Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// The timestamp type of the produced record batches. CreateTime uses the time the record was created by the producer, LogAppendTime asks for the time the broker appended it to the log.
+    pub fn timestamp_type(&mut self, timestamp_type: TimestampType) -> &mut Self {
+        self.attributes.timestamp_type = timestamp_type;
+        self
+    }
+
+    /// Write every record batch as an idempotent producer.
+    ///
+    /// The producer asks the cluster for a producer id before its first write
+    /// and numbers the records of each partition, so the broker drops the
+    /// duplicates that retries would otherwise write. This requires acks from
+    /// the full ISR, so the producer is built with a
+    /// [`required_acks`](Self::required_acks) of -1, whether it is set before
+    /// or after this.
+    pub fn idempotent(&mut self, idempotent: bool) -> &mut Self {
+        self.idempotent = idempotent;
+        self
+    }
+
+    /// Register a transactional id with the producer id.
+    ///
+    /// A new producer with the same transactional id fences off the older
+    /// one, whose writes are then rejected. Transactional producers are
+    /// always idempotent.
+    pub fn transactional_id(&mut self, transactional_id: String) -> &mut Self {
+        self.produce_params.transactional_id = Some(transactional_id);
+        self.idempotent(true)
+    }
+
+    /// How long in milliseconds the coordinator waits for a transaction
+    /// before aborting it. Only used by transactional producers.
+    ///
+    /// It must be positive, or asking for a producer id fails with
+    /// [`Error::ArgError`]. A timeout beyond the `transaction.max.timeout.ms`
+    /// of the broker is rejected by the broker with
+    /// [`KafkaCode::InvalidTransactionTimeout`].
+    pub fn transaction_timeout_ms(&mut self, transaction_timeout_ms: i32) -> &mut Self {
+        self.produce_params.transaction_timeout_ms = transaction_timeout_ms;
+        self
+    }
+
+    /// How many times to try reconnecting to the cluster before giving up.
+    ///
+    /// The producer reconnects, backing off between attempts, when it has to
+    /// refresh its metadata. Once the attempts run out the flush fails with
+    /// [`Error::ConnectionClosed`]. `None` keeps trying forever.
+    pub fn max_reconnect_attempts(&mut self, max_reconnect_attempts: Option<u32>) -> &mut Self {
+        self.cluster_metadata.max_reconnect_attempts = max_reconnect_attempts;
+        self
+    }
+
+    /// The max number of batches that are flushed at the same time.
+    ///
+    /// With 1, the default, a batch is only sent once the previous batch and
+    /// all of its retries are done, so records are written in the order they
+    /// were produced. Higher values increase throughput, but a retried batch
+    /// may then land after a later one. Idempotent producers always use 1.
+    pub fn max_in_flight_requests(&mut self, max_in_flight_requests: usize) -> &mut Self {
+        self.max_in_flight_requests = max_in_flight_requests;
+        self
+    }
+
+    /// Produce to brokers that predate record batches.
+    ///
+    /// Asks a broker for its API versions and, when it does not support
+    /// Produce v3, sends the highest version it does support. Versions 0 and
+    /// 1 write legacy message sets of magic 0, version 2 of magic 1. These
+    /// cannot carry headers, and an idempotent or transactional producer fails
+    /// with [`KafkaCode::UnsupportedVersion`].
+    pub async fn negotiate_produce_version(&mut self) -> Result<&mut Self> {
+        let conn = self
+            .cluster_metadata
+            .broker_connections
+            .values()
+            .next()
+            .ok_or(Error::MetadataNeedsSync)?
+            .clone();
+        let supported = admin::api_versions(
+            conn,
+            self.produce_params.correlation_id,
+            &self.produce_params.client_id,
+        )
+        .await?;
+
+        let produce_max = supported
+            .max_version(protocol::produce::request::API_KEY_PRODUCE)
+            .unwrap_or_default();
+        if produce_max >= protocol::produce::request::API_VERSION {
+            return Ok(self);
+        }
+        if self.idempotent {
+            return Err(Error::KafkaError(KafkaCode::UnsupportedVersion));
+        }
+        tracing::debug!(
+            "Producing legacy message sets, the broker supports Produce v{}",
+            produce_max
+        );
+        self.produce_params.produce_version = produce_max;
+
+        Ok(self)
+    }
+
+    /// Batches flushed at the same time by a new worker.
+    fn worker_max_in_flight_requests(&self) -> usize {
+        if self.idempotent {
+            1
+        } else {
+            self.max_in_flight_requests.max(1)
+        }
+    }
+
+    /// Produce params for a new worker, with its own idempotent state.
+    fn worker_params(&self) -> ProduceParams {
+        let mut produce_params = self.produce_params.clone();
+        if self.idempotent {
+            produce_params.idempotence = Some(Default::default());
+            // the broker only deduplicates writes acknowledged by the full ISR
+            produce_params.required_acks = -1;
+        }
+        produce_params
+    }
+
     pub async fn build(self) -> Producer {
         let (input_sender, input_receiver) = channel(self.max_batch_size);
         // unbounded because you don't want to force the reading.
         let (output_sender, output_receiver) = unbounded_channel();
 
-        // we should make this chunks timeout optional
-        let produce_stream = into_produce_stream(input_receiver).chunks_timeout(
+        let produce_stream = chunks_timeout(
+            into_produce_stream(input_receiver),
             self.max_batch_size,
             Duration::from_millis(self.batch_timeout_ms),
+            self.clock.clone(),
         );
 
-        tokio::spawn(producer(
+        let mut produce_params = self.worker_params();
+        let unflushed_records = Arc::new(AtomicUsize::new(0));
+        produce_params.unflushed_records = Some(unflushed_records.clone());
+        let max_in_flight_requests = self.worker_max_in_flight_requests();
+        let idempotence = produce_params.idempotence.clone();
+        let worker = tokio::spawn(producer(
             produce_stream,
             output_sender,
             self.cluster_metadata,
-            self.produce_params,
+            produce_params,
             self.attributes,
+            self.on_delivery_failure,
+            max_in_flight_requests,
         ));
 
         Producer {
             sender: input_sender,
             receiver: output_receiver,
+            unflushed_records,
+            worker: Some(worker),
+            idempotence,
         }
     }
 
@@ -158,12 +422,23 @@ where
         // unbounded because you don't want to force the reading.
         let (output_sender, mut output_receiver) = unbounded_channel();
 
+        let produce_params = self.worker_params();
+        let max_in_flight_requests = self.worker_max_in_flight_requests();
+        // nobody waits on the messages of a stream one by one
+        let stream = stream.map(|messages| {
+            messages
+                .into_iter()
+                .map(|message| (message, None))
+                .collect::<Vec<_>>()
+        });
         tokio::spawn(producer(
             stream,
             output_sender,
             self.cluster_metadata,
-            self.produce_params,
+            produce_params,
             self.attributes,
+            self.on_delivery_failure,
+            max_in_flight_requests,
         ));
 
         async_stream::stream! {
@@ -175,8 +450,8 @@ where
 }
 
 fn into_produce_stream(
-    mut receiver: Receiver<ProduceMessage>,
-) -> impl Stream<Item = ProduceMessage> {
+    mut receiver: Receiver<(ProduceMessage, Option<DeliverySender>)>,
+) -> impl Stream<Item = (ProduceMessage, Option<DeliverySender>)> {
     async_stream::stream! {
         while let Some(message) = receiver.recv().await {
             yield message;
@@ -184,31 +459,185 @@ fn into_produce_stream(
     }
 }
 
-async fn producer<T: BrokerConnection + Clone + Debug + Send + 'static>(
-    stream: impl Stream<Item = Vec<ProduceMessage>> + Send + 'static,
+/// Group the items of a stream into chunks of up to `max_size`.
+///
+/// A chunk is yielded once it is full, or once `timeout` has passed on the
+/// clock since its first item arrived, whichever comes first.
+fn chunks_timeout<I: Send + 'static>(
+    stream: impl Stream<Item = I> + Send + 'static,
+    max_size: usize,
+    timeout: Duration,
+    clock: Arc<dyn Clock>,
+) -> impl Stream<Item = Vec<I>> + Send + 'static {
+    async_stream::stream! {
+        tokio::pin!(stream);
+        while let Some(first) = stream.next().await {
+            let mut chunk = vec![first];
+            let linger = clock.sleep(timeout);
+            tokio::pin!(linger);
+            let mut finished = false;
+            while chunk.len() < max_size {
+                tokio::select! {
+                    item = stream.next() => match item {
+                        Some(item) => chunk.push(item),
+                        None => {
+                            finished = true;
+                            break;
+                        }
+                    },
+                    _ = &mut linger => break,
+                }
+            }
+            yield chunk;
+            if finished {
+                break;
+            }
+        }
+    }
+}
+
+async fn producer<T: BrokerConnection + Clone + Debug + Send + Sync + 'static>(
+    stream: impl Stream<Item = Vec<(ProduceMessage, Option<DeliverySender>)>> + Send + 'static,
     output_sender: UnboundedSender<Vec<Option<ProduceResponse>>>,
-    cluster_metadata: ClusterMetadata<T>,
+    mut cluster_metadata: ClusterMetadata<T>,
     produce_params: ProduceParams,
-    attributes: Attributes,
+    attributes: RecordBatchAttributes,
+    on_delivery_failure: Option<DeliveryFailureCallback>,
+    max_in_flight_requests: usize,
 ) {
+    if produce_params.required_acks == 0 {
+        tracing::warn!("Producing without acks, delivery of the records is not guaranteed");
+    }
     tokio::pin!(stream);
+    let mut in_flight = JoinSet::new();
     while let Some(messages) = stream.next().await {
-        match flush_producer(
-            &cluster_metadata,
-            &produce_params,
+        if messages.is_empty() {
+            continue;
+        }
+        if in_flight.len() >= max_in_flight_requests {
+            // pick up the metadata that the flush may have refreshed
+            if let Some(Ok(refreshed)) = in_flight.join_next().await {
+                cluster_metadata = refreshed;
+            }
+        }
+        in_flight.spawn(flush_and_report(
+            cluster_metadata.clone(),
+            produce_params.clone(),
             messages,
             attributes.clone(),
-        )
-        .await
-        {
-            Err(err) => {
-                tracing::error!("Error in producer agent {:?}", err);
-            }
-            Ok(r) => {
-                if let Err(err) = output_sender.send(r) {
-                    tracing::error!("Error sending results from producer agent {:?}", err);
-                }
+            output_sender.clone(),
+            on_delivery_failure.clone(),
+        ));
+    }
+    while in_flight.join_next().await.is_some() {}
+}
+
+/// Flush a batch of messages, then hand the responses to the output channel,
+/// the undeliverable messages to the failure callback and the offset of each
+/// message to whoever is waiting on it.
+async fn flush_and_report<T: BrokerConnection + Clone + Debug + Send + Sync + 'static>(
+    mut cluster_metadata: ClusterMetadata<T>,
+    produce_params: ProduceParams,
+    messages: Vec<(ProduceMessage, Option<DeliverySender>)>,
+    attributes: RecordBatchAttributes,
+    output_sender: UnboundedSender<Vec<Option<ProduceResponse>>>,
+    on_delivery_failure: Option<DeliveryFailureCallback>,
+) -> ClusterMetadata<T> {
+    let (mut messages, deliveries): (Vec<ProduceMessage>, Vec<Option<DeliverySender>>) =
+        messages.into_iter().unzip();
+    for message in messages.iter_mut() {
+        add_default_headers(&produce_params.default_headers, message);
+    }
+    let interceptors = produce_params.interceptors.clone();
+    for interceptor in &interceptors {
+        for message in messages.iter_mut() {
+            interceptor.on_send(message);
+        }
+    }
+    let messages_len = messages.len();
+    // topics that were not passed to the builder are looked up on first use
+    let topics: Vec<String> = messages
+        .iter()
+        .map(|message| message.topic.clone())
+        .collect();
+    let mut responses = vec![];
+    let mut parts = WrittenParts::new();
+    let result = match cluster_metadata.add_topics(&topics).await {
+        Ok(()) => {
+            messages =
+                assign_partitions(&cluster_metadata, &produce_params.next_partition, messages);
+            flush_producer(
+                &mut cluster_metadata,
+                &produce_params,
+                &messages,
+                attributes,
+                &mut responses,
+                &mut parts,
+            )
+            .await
+        }
+        Err(err) => Err(err),
+    };
+    let offsets: Vec<Result<(i32, i64)>> = match &result {
+        Ok(()) => {
+            let unwritten = Error::MissingData("No produce result for the record".to_owned());
+            delivered_offsets(&messages, &parts, &unwritten)
+        }
+        Err(err) => {
+            tracing::error!("Error in producer agent {:?}", err);
+            // the records the brokers wrote before the flush failed were
+            // delivered all the same, only the others fail with its error
+            delivered_offsets(&messages, &parts, err)
+                .into_iter()
+                .map(|offset| offset.map_err(|_| err.clone()))
+                .collect()
+        }
+    };
+    acknowledge(&interceptors, &messages, |index| {
+        offsets[index].clone().map(|(_, offset)| offset)
+    });
+    if let Some(on_delivery_failure) = &on_delivery_failure {
+        for (message, offset) in messages.into_iter().zip(offsets.iter()) {
+            if let Err(err) = offset {
+                on_delivery_failure(message, err.clone());
             }
         }
     }
+    for (delivery, offset) in deliveries.into_iter().zip(offsets) {
+        if let Some(delivery) = delivery {
+            // the sender may have stopped waiting
+            let _ = delivery.send(offset);
+        }
+    }
+    if result.is_ok() {
+        if let Err(err) = output_sender.send(responses) {
+            tracing::error!("Error sending results from producer agent {:?}", err);
+        }
+    }
+    if let Some(unflushed_records) = &produce_params.unflushed_records {
+        unflushed_records.fetch_sub(messages_len, Ordering::SeqCst);
+    }
+
+    cluster_metadata
+}
+
+/// Hand each interceptor the outcome of every message in a flushed batch.
+fn acknowledge(
+    interceptors: &[Arc<dyn Interceptor>],
+    messages: &[ProduceMessage],
+    offset: impl Fn(usize) -> Result<i64>,
+) {
+    if interceptors.is_empty() {
+        return;
+    }
+    for (index, message) in messages.iter().enumerate() {
+        let report = DeliveryReport {
+            topic: message.topic.clone(),
+            partition_id: message.partition_id,
+            offset: offset(index),
+        };
+        for interceptor in interceptors {
+            interceptor.on_acknowledgement(&report);
+        }
+    }
 }
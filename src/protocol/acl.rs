@@ -0,0 +1,185 @@
+//! Resource patterns and access control entries shared by the ACL requests.
+//!
+//! An ACL binding pairs a resource pattern, e.g. every topic prefixed with
+//! "orders-", with an entry allowing or denying a principal an operation on
+//! it from a host. Describe and delete requests match bindings with a
+//! filter, where the `Any` variants and `None` fields match everything.
+
+use bytes::BufMut;
+use nom::{combinator::map, number::complete::be_i8, IResult};
+use nombytes::NomBytes;
+use num_derive::FromPrimitive;
+use num_traits::FromPrimitive;
+
+use crate::{encode::ToByte, error::Result};
+
+/// The kind of resource an ACL applies to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromPrimitive)]
+pub enum ResourceType {
+    Unknown = 0,
+    /// Only valid in a filter, matches any resource type.
+    Any = 1,
+    Topic = 2,
+    Group = 3,
+    Cluster = 4,
+    TransactionalId = 5,
+    DelegationToken = 6,
+    User = 7,
+}
+
+/// How the resource name of an ACL is matched against resources.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromPrimitive)]
+pub enum PatternType {
+    Unknown = 0,
+    /// Only valid in a filter, matches any pattern type.
+    Any = 1,
+    /// Only valid in a filter, matches the bindings that apply to the
+    /// resource name, whether literal, prefixed or the wildcard.
+    Match = 2,
+    /// The resource name is the name of the resource, or "*" for all of them.
+    Literal = 3,
+    /// The resource name is a prefix of the names of the resources.
+    Prefixed = 4,
+}
+
+/// The operation an ACL allows or denies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromPrimitive)]
+pub enum AclOperation {
+    Unknown = 0,
+    /// Only valid in a filter, matches any operation.
+    Any = 1,
+    All = 2,
+    Read = 3,
+    Write = 4,
+    Create = 5,
+    Delete = 6,
+    Alter = 7,
+    Describe = 8,
+    ClusterAction = 9,
+    DescribeConfigs = 10,
+    AlterConfigs = 11,
+    IdempotentWrite = 12,
+    CreateTokens = 13,
+    DescribeTokens = 14,
+}
+
+/// Whether an ACL allows or denies the operation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromPrimitive)]
+pub enum PermissionType {
+    Unknown = 0,
+    /// Only valid in a filter, matches either permission.
+    Any = 1,
+    Deny = 2,
+    Allow = 3,
+}
+
+/// An ACL, binding an access control entry to a resource pattern.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AclBinding {
+    pub resource_type: ResourceType,
+    pub resource_name: String,
+    pub pattern_type: PatternType,
+    /// The principal the ACL applies to, e.g. "User:alice".
+    pub principal: String,
+    /// The host the ACL applies to, or "*" for all hosts.
+    pub host: String,
+    pub operation: AclOperation,
+    pub permission_type: PermissionType,
+}
+
+/// Matches ACL bindings, to describe or delete them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AclBindingFilter {
+    pub resource_type: ResourceType,
+    /// The resource name to match, or `None` to match any.
+    pub resource_name: Option<String>,
+    pub pattern_type: PatternType,
+    /// The principal to match, or `None` to match any.
+    pub principal: Option<String>,
+    /// The host to match, or `None` to match any.
+    pub host: Option<String>,
+    pub operation: AclOperation,
+    pub permission_type: PermissionType,
+}
+
+impl AclBindingFilter {
+    /// A filter matching every ACL binding.
+    pub fn any() -> Self {
+        Self {
+            resource_type: ResourceType::Any,
+            resource_name: None,
+            pattern_type: PatternType::Any,
+            principal: None,
+            host: None,
+            operation: AclOperation::Any,
+            permission_type: PermissionType::Any,
+        }
+    }
+}
+
+impl From<&AclBinding> for AclBindingFilter {
+    /// A filter matching exactly the given binding.
+    fn from(binding: &AclBinding) -> Self {
+        Self {
+            resource_type: binding.resource_type,
+            resource_name: Some(binding.resource_name.clone()),
+            pattern_type: binding.pattern_type,
+            principal: Some(binding.principal.clone()),
+            host: Some(binding.host.clone()),
+            operation: binding.operation,
+            permission_type: binding.permission_type,
+        }
+    }
+}
+
+impl ToByte for AclBinding {
+    fn encode<T: BufMut>(&self, buffer: &mut T) -> Result<()> {
+        (self.resource_type as i8).encode(buffer)?;
+        self.resource_name.encode(buffer)?;
+        (self.pattern_type as i8).encode(buffer)?;
+        self.principal.encode(buffer)?;
+        self.host.encode(buffer)?;
+        (self.operation as i8).encode(buffer)?;
+        (self.permission_type as i8).encode(buffer)?;
+        Ok(())
+    }
+}
+
+impl ToByte for AclBindingFilter {
+    fn encode<T: BufMut>(&self, buffer: &mut T) -> Result<()> {
+        (self.resource_type as i8).encode(buffer)?;
+        self.resource_name.encode(buffer)?;
+        (self.pattern_type as i8).encode(buffer)?;
+        self.principal.encode(buffer)?;
+        self.host.encode(buffer)?;
+        (self.operation as i8).encode(buffer)?;
+        (self.permission_type as i8).encode(buffer)?;
+        Ok(())
+    }
+}
+
+// values added by newer brokers are read as unknown
+
+pub(crate) fn parse_resource_type(s: NomBytes) -> IResult<NomBytes, ResourceType> {
+    map(be_i8, |n| {
+        ResourceType::from_i8(n).unwrap_or(ResourceType::Unknown)
+    })(s)
+}
+
+pub(crate) fn parse_pattern_type(s: NomBytes) -> IResult<NomBytes, PatternType> {
+    map(be_i8, |n| {
+        PatternType::from_i8(n).unwrap_or(PatternType::Unknown)
+    })(s)
+}
+
+pub(crate) fn parse_operation(s: NomBytes) -> IResult<NomBytes, AclOperation> {
+    map(be_i8, |n| {
+        AclOperation::from_i8(n).unwrap_or(AclOperation::Unknown)
+    })(s)
+}
+
+pub(crate) fn parse_permission_type(s: NomBytes) -> IResult<NomBytes, PermissionType> {
+    map(be_i8, |n| {
+        PermissionType::from_i8(n).unwrap_or(PermissionType::Unknown)
+    })(s)
+}
@@ -0,0 +1,67 @@
+//! Add partitions to a transaction.
+
+pub mod request;
+pub mod response;
+
+#[cfg(test)]
+mod test {
+    use bytes::Bytes;
+    use nombytes::NomBytes;
+
+    use super::*;
+    use crate::{encode::ToByte, error::KafkaCode, protocol};
+
+    #[test]
+    fn encode() {
+        let b = [
+            0, 24, 0, 0, 0, 0, 0, 1, 0, 4, 114, 117, 115, 116, 0, 3, 116, 120, 110, 0, 0, 0, 0, 0,
+            0, 0, 42, 0, 3, 0, 0, 0, 1, 0, 9, 112, 117, 114, 99, 104, 97, 115, 101, 115, 0, 0, 0,
+            2, 0, 0, 0, 0, 0, 0, 0, 1,
+        ];
+
+        let mut req = request::AddPartitionsToTxnRequest::new(1, "rust", "txn", 42, 3);
+        req.add("purchases", 0);
+        req.add("purchases", 1);
+        req.add("purchases", 1);
+
+        let mut buffer: Vec<u8> = vec![];
+        req.encode(&mut buffer).unwrap();
+
+        assert_eq!(buffer, b);
+    }
+
+    #[test]
+    fn parse() {
+        let b = [
+            0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 1, 0, 9, 112, 117, 114, 99, 104, 97, 115, 101, 115, 0,
+            0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 51,
+        ];
+
+        let res = response::AddPartitionsToTxnResponse {
+            header: protocol::HeaderResponse { correlation_id: 1 },
+            throttle_time_ms: 0,
+            results: vec![response::Topic {
+                name: Bytes::from_static(b"purchases"),
+                results: vec![
+                    response::Partition {
+                        partition_index: 0,
+                        partition_error_code: KafkaCode::None,
+                    },
+                    response::Partition {
+                        partition_index: 1,
+                        partition_error_code: KafkaCode::ConcurrentTransactions,
+                    },
+                ],
+            }],
+        };
+
+        let x = response::parse_add_partitions_to_txn_response(NomBytes::new(
+            Bytes::copy_from_slice(&b),
+        ))
+        .unwrap()
+        .1;
+
+        assert_eq!(res, x);
+        assert_eq!(x.error_code(), KafkaCode::ConcurrentTransactions);
+    }
+}
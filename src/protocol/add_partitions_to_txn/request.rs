@@ -0,0 +1,143 @@
+//! Encoding and creation for AddPartitionsToTxn requests.
+//!
+//! Before a transactional producer writes to a partition for the first time
+//! in a transaction, it registers the partition with the transaction
+//! coordinator, which writes the transaction markers there when the
+//! transaction ends.
+//!
+//! ### Example
+//! ```rust
+//! let mut add_partitions = protocol::AddPartitionsToTxnRequest::new(
+//!     CORRELATION_ID,
+//!     CLIENT_ID,
+//!     transactional_id,
+//!     producer_id,
+//!     producer_epoch,
+//! );
+//! add_partitions.add(topic_name, partition_index);
+//! coordinator_conn.send_request(&add_partitions).await?;
+//! ```
+//!
+//! ### Protocol Def
+//! ```text
+//! AddPartitionsToTxn Request (Version: 0) => transactional_id producer_id producer_epoch [topics]
+//!   transactional_id => STRING
+//!   producer_id => INT64
+//!   producer_epoch => INT16
+//!   topics => name [partitions]
+//!     name => STRING
+//!     partitions => INT32
+//! ```
+//!
+//! Note that we are using version 0 of this API.
+
+use bytes::BufMut;
+
+use crate::{encode::ToByte, error::Result, protocol::HeaderRequest};
+
+const API_KEY_ADD_PARTITIONS_TO_TXN: i16 = 24;
+const API_VERSION: i16 = 0;
+
+/// The base AddPartitionsToTxn request object.
+///
+/// ### Example
+/// ```rust
+/// let mut add_partitions = protocol::AddPartitionsToTxnRequest::new(
+///     CORRELATION_ID,
+///     CLIENT_ID,
+///     transactional_id,
+///     producer_id,
+///     producer_epoch,
+/// );
+/// add_partitions.add(topic_name, partition_index);
+/// coordinator_conn.send_request(&add_partitions).await?;
+/// ```
+#[derive(Debug)]
+pub struct AddPartitionsToTxnRequest<'a> {
+    pub header: HeaderRequest<'a>,
+    /// The transactional id corresponding to the transaction.
+    pub transactional_id: &'a str,
+    /// Current producer id in use by the transactional id.
+    pub producer_id: i64,
+    /// Current epoch associated with the producer id.
+    pub producer_epoch: i16,
+    /// The partitions to add to the transaction.
+    pub topics: Vec<Topic<'a>>,
+}
+
+/// The partitions to add to the transaction.
+#[derive(Debug)]
+pub struct Topic<'a> {
+    /// The name of the topic.
+    pub name: &'a str,
+    /// The partition indexes to add to the transaction.
+    pub partitions: Vec<i32>,
+}
+
+impl<'a> AddPartitionsToTxnRequest<'a> {
+    /// Create a new AddPartitionsToTxn Request
+    ///
+    /// This request needs to be given topics and partitions to add before
+    /// being sent to the broker. You can do this by using the `add` method.
+    pub fn new(
+        correlation_id: i32,
+        client_id: &'a str,
+        transactional_id: &'a str,
+        producer_id: i64,
+        producer_epoch: i16,
+    ) -> Self {
+        Self {
+            header: HeaderRequest::new(
+                API_KEY_ADD_PARTITIONS_TO_TXN,
+                API_VERSION,
+                correlation_id,
+                client_id,
+            ),
+            transactional_id,
+            producer_id,
+            producer_epoch,
+            topics: vec![],
+        }
+    }
+
+    /// Add a topic partition to the transaction.
+    ///
+    /// If the same topic partition is used twice, it will do nothing the second time
+    pub fn add(&mut self, topic_name: &'a str, partition_index: i32) {
+        match self
+            .topics
+            .iter_mut()
+            .find(|topic| topic.name == topic_name)
+        {
+            None => self.topics.push(Topic {
+                name: topic_name,
+                partitions: vec![partition_index],
+            }),
+            Some(topic) => {
+                if !topic.partitions.contains(&partition_index) {
+                    topic.partitions.push(partition_index);
+                }
+            }
+        }
+    }
+}
+
+impl ToByte for AddPartitionsToTxnRequest<'_> {
+    fn encode<T: BufMut>(&self, buffer: &mut T) -> Result<()> {
+        tracing::trace!("Encoding AddPartitionsToTxnRequest {:?}", self);
+        self.header.encode(buffer)?;
+        self.transactional_id.encode(buffer)?;
+        self.producer_id.encode(buffer)?;
+        self.producer_epoch.encode(buffer)?;
+        self.topics.encode(buffer)?;
+        Ok(())
+    }
+}
+
+impl ToByte for Topic<'_> {
+    fn encode<T: BufMut>(&self, buffer: &mut T) -> Result<()> {
+        self.name.encode(buffer)?;
+        self.partitions.encode(buffer)?;
+        Ok(())
+    }
+}
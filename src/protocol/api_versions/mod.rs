@@ -0,0 +1,74 @@
+//! Discover the API versions and features a broker supports.
+
+pub mod request;
+pub mod response;
+
+#[cfg(test)]
+mod test {
+    use bytes::Bytes;
+
+    use super::*;
+    use crate::{encode::ToByte, error::KafkaCode, protocol};
+
+    #[test]
+    fn encode() {
+        let b = [
+            0, 18, 0, 3, 0, 0, 0, 1, 0, 4, 114, 117, 115, 116, 0, 6, 115, 97, 109, 115, 97, 4, 49,
+            46, 48, 0,
+        ];
+
+        let mut req = request::ApiVersionsRequest::new(1, "rust");
+        req.client_software_version = "1.0";
+
+        let mut buffer: Vec<u8> = vec![];
+        req.encode(&mut buffer).unwrap();
+
+        assert_eq!(buffer, b);
+    }
+
+    #[test]
+    fn parse_features() {
+        // a v3 response advertising transaction.version in both feature arrays
+        let b = [
+            0, 0, 0, 1, 0, 0, 3, 0, 18, 0, 0, 0, 3, 0, 0, 22, 0, 0, 0, 4, 0, 0, 0, 0, 0, 3, 0, 26,
+            2, 20, 116, 114, 97, 110, 115, 97, 99, 116, 105, 111, 110, 46, 118, 101, 114, 115, 105,
+            111, 110, 0, 0, 0, 2, 0, 1, 8, 0, 0, 0, 0, 0, 0, 0, 5, 2, 26, 2, 20, 116, 114, 97, 110,
+            115, 97, 99, 116, 105, 111, 110, 46, 118, 101, 114, 115, 105, 111, 110, 0, 2, 0, 1, 0,
+        ];
+
+        let res = response::ApiVersionsResponse {
+            header: protocol::HeaderResponse { correlation_id: 1 },
+            error_code: KafkaCode::None,
+            api_keys: vec![
+                response::ApiVersion {
+                    api_key: 18,
+                    min_version: 0,
+                    max_version: 3,
+                },
+                response::ApiVersion {
+                    api_key: 22,
+                    min_version: 0,
+                    max_version: 4,
+                },
+            ],
+            throttle_time_ms: 0,
+            supported_features: vec![response::SupportedFeature {
+                name: Bytes::from("transaction.version"),
+                min_version: 0,
+                max_version: 2,
+            }],
+            finalized_features_epoch: 5,
+            finalized_features: vec![response::FinalizedFeature {
+                name: Bytes::from("transaction.version"),
+                max_version_level: 2,
+                min_version_level: 1,
+            }],
+        };
+
+        let x = response::ApiVersionsResponse::try_from(Bytes::copy_from_slice(&b)).unwrap();
+
+        assert_eq!(res, x);
+        assert_eq!(x.max_version(22), Some(4));
+        assert_eq!(x.finalized_feature_level("transaction.version"), Some(2));
+    }
+}
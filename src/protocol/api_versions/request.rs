@@ -0,0 +1,87 @@
+//! Encoding and creation for ApiVersions requests.
+//!
+//! ### Example
+//! ```rust
+//! let api_versions_request = protocol::ApiVersionsRequest::new(
+//!     correlation_id,
+//!     client_id,
+//! );
+//! conn.send_request(&api_versions_request).await?;
+//! ```
+//!
+//! ### Protocol Def
+//! ```text
+//! ApiVersions Request (Version: 3) => client_software_name client_software_version TAG_BUFFER
+//!   client_software_name => COMPACT_STRING
+//!   client_software_version => COMPACT_STRING
+//! ```
+//!
+//! Note that we are using version 3 of this API, which is a flexible version.
+
+use bytes::BufMut;
+
+use crate::{
+    encode::{CompactString, TaggedFields, ToByte},
+    error::Result,
+    protocol::{Describe, HeaderRequest},
+};
+
+pub const API_KEY_API_VERSIONS: i16 = 18;
+const API_VERSION: i16 = 3;
+const CLIENT_SOFTWARE_NAME: &str = "samsa";
+
+/// The base ApiVersions request object.
+///
+/// ### Example
+/// ```rust
+/// let api_versions_request = protocol::ApiVersionsRequest::new(
+///     correlation_id,
+///     client_id,
+/// );
+/// conn.send_request(&api_versions_request).await?;
+/// ```
+#[derive(Debug)]
+pub struct ApiVersionsRequest<'a> {
+    pub header: HeaderRequest<'a>,
+    /// The name of the client.
+    pub client_software_name: &'a str,
+    /// The version of the client.
+    pub client_software_version: &'a str,
+}
+
+impl<'a> ApiVersionsRequest<'a> {
+    /// Create a new ApiVersions Request
+    pub fn new(correlation_id: i32, client_id: &'a str) -> Self {
+        Self {
+            header: HeaderRequest::new(
+                API_KEY_API_VERSIONS,
+                API_VERSION,
+                correlation_id,
+                client_id,
+            ),
+            client_software_name: CLIENT_SOFTWARE_NAME,
+            client_software_version: env!("CARGO_PKG_VERSION"),
+        }
+    }
+}
+
+impl ToByte for ApiVersionsRequest<'_> {
+    fn encode<T: BufMut>(&self, buffer: &mut T) -> Result<()> {
+        tracing::trace!("Encoding ApiVersionsRequest {:?}", self);
+        self.header.encode_flexible(buffer)?;
+        CompactString(self.client_software_name).encode(buffer)?;
+        CompactString(self.client_software_version).encode(buffer)?;
+        TaggedFields.encode(buffer)?;
+        Ok(())
+    }
+}
+
+impl Describe for ApiVersionsRequest<'_> {
+    fn api_key(&self) -> i16 {
+        self.header.api_key
+    }
+
+    fn api_version(&self) -> Option<i16> {
+        Some(self.header.api_version)
+    }
+}
@@ -0,0 +1,241 @@
+//! Parsing and processing for ApiVersions responses.
+//!
+//! ### Example
+//! ```rust
+//! let response_bytes = conn.receive_response().await?;
+//! let api_versions_response = protocol::ApiVersionsResponse::try_from(response_bytes.freeze())?;
+//! ```
+//!
+//! ### Protocol Def
+//! ```text
+//! ApiVersions Response (Version: 3) => error_code [api_keys] throttle_time_ms TAG_BUFFER
+//!   error_code => INT16
+//!   api_keys => api_key min_version max_version TAG_BUFFER
+//!     api_key => INT16
+//!     min_version => INT16
+//!     max_version => INT16
+//!   throttle_time_ms => INT32
+//!
+//! Tagged fields:
+//!   0: supported_features => name min_version max_version TAG_BUFFER
+//!     name => COMPACT_STRING
+//!     min_version => INT16
+//!     max_version => INT16
+//!   1: finalized_features_epoch => INT64
+//!   2: finalized_features => name max_version_level min_version_level TAG_BUFFER
+//!     name => COMPACT_STRING
+//!     max_version_level => INT16
+//!     min_version_level => INT16
+//!   3: zk_migration_ready => BOOLEAN
+//! ```
+//!
+//! Note we are using version 3 of this response. Its header is not flexible,
+//! so a client that does not know the broker yet can always read the error.
+
+use bytes::Bytes;
+use nom::{
+    bytes::complete::take,
+    number::complete::{be_i16, be_i32, be_i64},
+    IResult,
+};
+use nombytes::NomBytes;
+
+use crate::{
+    error::{Error, KafkaCode, Result},
+    parser::{self, parse_compact_array, parse_tagged_fields, take_varint},
+    protocol::{
+        api_versions::request::API_KEY_API_VERSIONS, parse_header_response, Describe,
+        HeaderResponse,
+    },
+};
+
+const TAG_SUPPORTED_FEATURES: usize = 0;
+const TAG_FINALIZED_FEATURES_EPOCH: usize = 1;
+const TAG_FINALIZED_FEATURES: usize = 2;
+
+/// The base ApiVersions response object.
+///
+/// ### Example
+/// ```rust
+/// let response_bytes = conn.receive_response().await?;
+/// let api_versions_response = protocol::ApiVersionsResponse::try_from(response_bytes.freeze())?;
+/// ```
+#[derive(Debug, PartialEq)]
+pub struct ApiVersionsResponse {
+    pub header: HeaderResponse,
+    /// The top-level error code.
+    pub error_code: KafkaCode,
+    /// The APIs supported by the broker.
+    pub api_keys: Vec<ApiVersion>,
+    /// The duration in milliseconds for which the request was throttled due to a quota violation, or zero if the request did not violate any quota.
+    pub throttle_time_ms: i32,
+    /// Features supported by the broker, see KIP-584.
+    pub supported_features: Vec<SupportedFeature>,
+    /// The epoch of the finalized features, or -1 if there are none.
+    pub finalized_features_epoch: i64,
+    /// Features finalized for the whole cluster, see KIP-584.
+    pub finalized_features: Vec<FinalizedFeature>,
+}
+
+/// An API supported by the broker.
+#[derive(Debug, PartialEq)]
+pub struct ApiVersion {
+    pub api_key: i16,
+    pub min_version: i16,
+    pub max_version: i16,
+}
+
+/// A feature the broker supports, and the range of versions of it.
+#[derive(Debug, PartialEq)]
+pub struct SupportedFeature {
+    pub name: Bytes,
+    pub min_version: i16,
+    pub max_version: i16,
+}
+
+/// A feature enabled across the cluster, and the range of levels of it.
+#[derive(Debug, PartialEq)]
+pub struct FinalizedFeature {
+    pub name: Bytes,
+    pub max_version_level: i16,
+    pub min_version_level: i16,
+}
+
+// this helps us cast the server response into this type
+impl TryFrom<Bytes> for ApiVersionsResponse {
+    type Error = Error;
+
+    fn try_from(s: Bytes) -> Result<Self> {
+        tracing::trace!("Parsing ApiVersionsResponse {:?}", s);
+        let (_, api_versions) =
+            parse_api_versions_response(NomBytes::new(s.clone())).map_err(|err| {
+                tracing::error!("ERROR: Failed parsing ApiVersionsResponse {:?}", err);
+                tracing::error!("ERROR: ApiVersionsResponse Bytes {:?}", s);
+                Error::ParsingError(s)
+            })?;
+        tracing::trace!("Parsed ApiVersionsResponse {:?}", api_versions);
+        Ok(api_versions)
+    }
+}
+
+impl ApiVersionsResponse {
+    /// Surface a KafkaError.
+    pub fn is_error(&self) -> Result<()> {
+        match self.error_code {
+            KafkaCode::None => Ok(()),
+            _ => Err(Error::KafkaError(self.error_code)),
+        }
+    }
+
+    /// The highest version of an API the broker supports.
+    pub fn max_version(&self, api_key: i16) -> Option<i16> {
+        self.api_keys
+            .iter()
+            .find(|api| api.api_key == api_key)
+            .map(|api| api.max_version)
+    }
+
+    /// The finalized level of a feature, e.g. "transaction.version".
+    pub fn finalized_feature_level(&self, name: &str) -> Option<i16> {
+        self.finalized_features
+            .iter()
+            .find(|feature| feature.name == name.as_bytes())
+            .map(|feature| feature.max_version_level)
+    }
+}
+
+pub fn parse_api_versions_response(s: NomBytes) -> IResult<NomBytes, ApiVersionsResponse> {
+    let (s, header) = parse_header_response(s)?;
+    let (s, error_code) = parser::parse_kafka_code(s)?;
+    let (s, api_keys) = parse_compact_array(parse_api_version)(s)?;
+    let (s, throttle_time_ms) = be_i32(s)?;
+
+    let mut response = ApiVersionsResponse {
+        header,
+        error_code,
+        api_keys,
+        throttle_time_ms,
+        supported_features: vec![],
+        finalized_features_epoch: -1,
+        finalized_features: vec![],
+    };
+
+    // the features are tagged fields, the others are skipped
+    let (mut s, count) = take_varint(s)?;
+    for _ in 0..count {
+        let (rest, tag) = take_varint(s)?;
+        let (rest, size) = take_varint(rest)?;
+        let (rest, field) = take(size)(rest)?;
+        match tag {
+            TAG_SUPPORTED_FEATURES => {
+                (_, response.supported_features) =
+                    parse_compact_array(parse_supported_feature)(field)?;
+            }
+            TAG_FINALIZED_FEATURES_EPOCH => {
+                (_, response.finalized_features_epoch) = be_i64(field)?;
+            }
+            TAG_FINALIZED_FEATURES => {
+                (_, response.finalized_features) =
+                    parse_compact_array(parse_finalized_feature)(field)?;
+            }
+            _ => {}
+        }
+        s = rest;
+    }
+
+    Ok((s, response))
+}
+
+fn parse_api_version(s: NomBytes) -> IResult<NomBytes, ApiVersion> {
+    let (s, api_key) = be_i16(s)?;
+    let (s, min_version) = be_i16(s)?;
+    let (s, max_version) = be_i16(s)?;
+    let (s, _) = parse_tagged_fields(s)?;
+
+    Ok((
+        s,
+        ApiVersion {
+            api_key,
+            min_version,
+            max_version,
+        },
+    ))
+}
+
+fn parse_supported_feature(s: NomBytes) -> IResult<NomBytes, SupportedFeature> {
+    let (s, name) = parser::parse_compact_string(s)?;
+    let (s, min_version) = be_i16(s)?;
+    let (s, max_version) = be_i16(s)?;
+    let (s, _) = parse_tagged_fields(s)?;
+
+    Ok((
+        s,
+        SupportedFeature {
+            name,
+            min_version,
+            max_version,
+        },
+    ))
+}
+
+fn parse_finalized_feature(s: NomBytes) -> IResult<NomBytes, FinalizedFeature> {
+    let (s, name) = parser::parse_compact_string(s)?;
+    let (s, max_version_level) = be_i16(s)?;
+    let (s, min_version_level) = be_i16(s)?;
+    let (s, _) = parse_tagged_fields(s)?;
+
+    Ok((
+        s,
+        FinalizedFeature {
+            name,
+            max_version_level,
+            min_version_level,
+        },
+    ))
+}
+
+impl Describe for ApiVersionsResponse {
+    fn api_key(&self) -> i16 {
+        API_KEY_API_VERSIONS
+    }
+}
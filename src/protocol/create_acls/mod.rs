@@ -0,0 +1,60 @@
+//! Add ACL bindings.
+
+pub mod request;
+pub mod response;
+
+#[cfg(test)]
+mod test {
+    use bytes::Bytes;
+
+    use super::*;
+    use crate::{
+        encode::ToByte,
+        error::{Error, KafkaCode},
+        protocol::acl::{AclBinding, AclOperation, PatternType, PermissionType, ResourceType},
+    };
+
+    #[test]
+    fn encode() {
+        let b = [
+            0, 30, 0, 1, 0, 0, 0, 1, 0, 4, 114, 117, 115, 116, 0, 0, 0, 1, 2, 0, 9, 112, 117, 114,
+            99, 104, 97, 115, 101, 115, 3, 0, 10, 85, 115, 101, 114, 58, 97, 108, 105, 99, 101, 0,
+            1, 42, 3, 3,
+        ];
+        let binding = AclBinding {
+            resource_type: ResourceType::Topic,
+            resource_name: "purchases".to_owned(),
+            pattern_type: PatternType::Literal,
+            principal: "User:alice".to_owned(),
+            host: "*".to_owned(),
+            operation: AclOperation::Read,
+            permission_type: PermissionType::Allow,
+        };
+
+        let mut req = request::CreateAclsRequest::new(1, "rust");
+        req.add(&binding);
+        let mut buffer: Vec<u8> = vec![];
+        req.encode(&mut buffer).unwrap();
+
+        assert_eq!(buffer, b);
+    }
+
+    #[test]
+    fn parse() {
+        let b = b"\x00\x00\x00\x01\x00\x00\x00\x00\x00\x00\x00\x02\x00\x00\xff\xff\x00\x36\x00\x10SecurityDisabled";
+
+        let res = response::CreateAclsResponse::try_from(Bytes::from_static(b)).unwrap();
+
+        assert_eq!(res.results.len(), 2);
+        assert_eq!(res.results[0].error_code, KafkaCode::None);
+        assert_eq!(res.results[1].error_code, KafkaCode::SecurityDisabled);
+        assert_eq!(
+            res.results[1].error_message,
+            Some(Bytes::from_static(b"SecurityDisabled"))
+        );
+        assert_eq!(
+            res.is_error(),
+            Err(Error::KafkaError(KafkaCode::SecurityDisabled))
+        );
+    }
+}
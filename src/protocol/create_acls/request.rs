@@ -0,0 +1,76 @@
+//! Encoding and creation for Create Acls requests.
+//!
+//! Adds ACL bindings. The broker must have an authorizer configured,
+//! otherwise the request fails with SECURITY_DISABLED.
+//!
+//! ### Example
+//! ```rust
+//! let mut create_acls_request = protocol::CreateAclsRequest::new(correlation_id, client_id);
+//! create_acls_request.add(&binding);
+//! conn.send_request(&create_acls_request).await?;
+//! ```
+//!
+//! ### Protocol Def
+//! ```text
+//! CreateAcls Request (Version: 1) => [creations]
+//!   creations => resource_type resource_name resource_pattern_type principal host operation permission_type
+//!     resource_type => INT8
+//!     resource_name => STRING
+//!     resource_pattern_type => INT8
+//!     principal => STRING
+//!     host => STRING
+//!     operation => INT8
+//!     permission_type => INT8
+//! ```
+//!
+//! Note we are using version 1 of the request.
+
+use bytes::BufMut;
+
+use crate::{
+    encode::ToByte,
+    error::Result,
+    protocol::{acl::AclBinding, HeaderRequest},
+};
+
+const API_KEY_CREATE_ACLS: i16 = 30;
+const API_VERSION: i16 = 1;
+
+/// The base Create Acls request object.
+///
+/// ### Example
+/// ```rust
+/// let mut create_acls_request = protocol::CreateAclsRequest::new(correlation_id, client_id);
+/// create_acls_request.add(&binding);
+/// conn.send_request(&create_acls_request).await?;
+/// ```
+#[derive(Debug)]
+pub struct CreateAclsRequest<'a> {
+    pub header: HeaderRequest<'a>,
+    /// The ACLs that we want to create.
+    pub creations: Vec<&'a AclBinding>,
+}
+
+impl<'a> CreateAclsRequest<'a> {
+    pub fn new(correlation_id: i32, client_id: &'a str) -> Self {
+        let header =
+            HeaderRequest::new(API_KEY_CREATE_ACLS, API_VERSION, correlation_id, client_id);
+        Self {
+            header,
+            creations: vec![],
+        }
+    }
+
+    pub fn add(&mut self, binding: &'a AclBinding) {
+        self.creations.push(binding);
+    }
+}
+
+impl ToByte for CreateAclsRequest<'_> {
+    fn encode<T: BufMut>(&self, buffer: &mut T) -> Result<()> {
+        tracing::trace!("Encoding CreateAclsRequest {:?}", self);
+        self.header.encode(buffer)?;
+        self.creations.encode(buffer)?;
+        Ok(())
+    }
+}
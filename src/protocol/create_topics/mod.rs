@@ -36,6 +36,23 @@ mod test {
         assert_eq!(buffer, b);
     }
 
+    #[test]
+    fn encode_with_assignments() {
+        let mut req = request::CreateTopicsRequest::new(1, "rust", 2000, false).unwrap();
+
+        req.add_with_assignments("assigned", vec![(0, vec![1, 2])]);
+
+        let mut buffer: Vec<u8> = vec![];
+        req.encode(&mut buffer).unwrap();
+
+        let b = [
+            0, 19, 0, 3, 0, 0, 0, 1, 0, 4, 114, 117, 115, 116, 0, 0, 0, 1, 0, 8, 97, 115, 115, 105,
+            103, 110, 101, 100, 255, 255, 255, 255, 255, 255, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 2,
+            0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 7, 208, 0,
+        ];
+        assert_eq!(buffer, b);
+    }
+
     #[test]
     fn parse() {
         let b = b"\0\0\0\x01\0\0\0\0\0\0\0\x01\0\x0ftester-creation\0\0\xff\xff";
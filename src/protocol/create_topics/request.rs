@@ -141,6 +141,40 @@ impl<'a> CreateTopicsRequest<'a> {
             }
         }
     }
+
+    /// Add a topic to be created with a manual partition assignment.
+    ///
+    /// Each assignment maps a partition index to the brokers holding its
+    /// replicas, the first of which is the preferred leader. The partition
+    /// count and replication factor follow from the assignment.
+    ///
+    /// If the same topic is used twice, it will do nothing the second time
+    ///
+    /// ### Example
+    /// ```rust
+    /// create_topics_request.add_with_assignments(
+    ///     topic_name,
+    ///     vec![(0, vec![1, 2]), (1, vec![2, 3])],
+    /// );
+    /// ```
+    pub fn add_with_assignments(&mut self, topic_name: &'a str, assignments: Vec<(i32, Vec<i32>)>) {
+        if self.topics.iter().any(|topic| topic.name == topic_name) {
+            return;
+        }
+        self.topics.push(Topic {
+            name: topic_name,
+            num_partitions: -1,
+            replication_factor: -1,
+            assignments: assignments
+                .into_iter()
+                .map(|(partition_index, broker_ids)| Assignment {
+                    partition_index,
+                    broker_ids,
+                })
+                .collect(),
+            configs: vec![],
+        });
+    }
 }
 
 impl ToByte for CreateTopicsRequest<'_> {
@@ -0,0 +1,66 @@
+//! Remove the ACL bindings matching filters.
+
+pub mod request;
+pub mod response;
+
+#[cfg(test)]
+mod test {
+    use bytes::Bytes;
+
+    use super::*;
+    use crate::{
+        encode::ToByte,
+        protocol::acl::{
+            AclBinding, AclBindingFilter, AclOperation, PatternType, PermissionType, ResourceType,
+        },
+    };
+
+    #[test]
+    fn encode() {
+        let b = [
+            0, 31, 0, 1, 0, 0, 0, 1, 0, 4, 114, 117, 115, 116, 0, 0, 0, 1, 2, 0, 9, 112, 117, 114,
+            99, 104, 97, 115, 101, 115, 3, 0, 10, 85, 115, 101, 114, 58, 97, 108, 105, 99, 101, 0,
+            1, 42, 3, 3,
+        ];
+        let filter = AclBindingFilter::from(&AclBinding {
+            resource_type: ResourceType::Topic,
+            resource_name: "purchases".to_owned(),
+            pattern_type: PatternType::Literal,
+            principal: "User:alice".to_owned(),
+            host: "*".to_owned(),
+            operation: AclOperation::Read,
+            permission_type: PermissionType::Allow,
+        });
+
+        let mut req = request::DeleteAclsRequest::new(1, "rust");
+        req.add(&filter);
+        let mut buffer: Vec<u8> = vec![];
+        req.encode(&mut buffer).unwrap();
+
+        assert_eq!(buffer, b);
+    }
+
+    #[test]
+    fn parse() {
+        let b = b"\x00\x00\x00\x01\x00\x00\x00\x00\x00\x00\x00\x01\x00\x00\xff\xff\x00\x00\x00\x01\x00\x00\xff\xff\x02\x00\tpurchases\x03\x00\nUser:alice\x00\x01*\x03\x03";
+
+        let res = response::DeleteAclsResponse::try_from(Bytes::from_static(b)).unwrap();
+
+        assert!(res.is_error().is_ok());
+        assert_eq!(res.filter_results.len(), 1);
+        let matching_acls = &res.filter_results[0].matching_acls;
+        assert_eq!(matching_acls.len(), 1);
+        assert_eq!(matching_acls[0].resource_type, ResourceType::Topic);
+        assert_eq!(
+            matching_acls[0].resource_name,
+            Bytes::from_static(b"purchases")
+        );
+        assert_eq!(matching_acls[0].pattern_type, PatternType::Literal);
+        assert_eq!(
+            matching_acls[0].principal,
+            Bytes::from_static(b"User:alice")
+        );
+        assert_eq!(matching_acls[0].operation, AclOperation::Read);
+        assert_eq!(matching_acls[0].permission_type, PermissionType::Allow);
+    }
+}
@@ -0,0 +1,76 @@
+//! Encoding and creation for Delete Acls requests.
+//!
+//! Removes the ACL bindings matching each filter. The broker must have an
+//! authorizer configured, otherwise the request fails with SECURITY_DISABLED.
+//!
+//! ### Example
+//! ```rust
+//! let mut delete_acls_request = protocol::DeleteAclsRequest::new(correlation_id, client_id);
+//! delete_acls_request.add(&filter);
+//! conn.send_request(&delete_acls_request).await?;
+//! ```
+//!
+//! ### Protocol Def
+//! ```text
+//! DeleteAcls Request (Version: 1) => [filters]
+//!   filters => resource_type_filter resource_name_filter pattern_type_filter principal_filter host_filter operation permission_type
+//!     resource_type_filter => INT8
+//!     resource_name_filter => NULLABLE_STRING
+//!     pattern_type_filter => INT8
+//!     principal_filter => NULLABLE_STRING
+//!     host_filter => NULLABLE_STRING
+//!     operation => INT8
+//!     permission_type => INT8
+//! ```
+//!
+//! Note we are using version 1 of the request.
+
+use bytes::BufMut;
+
+use crate::{
+    encode::ToByte,
+    error::Result,
+    protocol::{acl::AclBindingFilter, HeaderRequest},
+};
+
+const API_KEY_DELETE_ACLS: i16 = 31;
+const API_VERSION: i16 = 1;
+
+/// The base Delete Acls request object.
+///
+/// ### Example
+/// ```rust
+/// let mut delete_acls_request = protocol::DeleteAclsRequest::new(correlation_id, client_id);
+/// delete_acls_request.add(&filter);
+/// conn.send_request(&delete_acls_request).await?;
+/// ```
+#[derive(Debug)]
+pub struct DeleteAclsRequest<'a> {
+    pub header: HeaderRequest<'a>,
+    /// The filters to use when deleting ACLs.
+    pub filters: Vec<&'a AclBindingFilter>,
+}
+
+impl<'a> DeleteAclsRequest<'a> {
+    pub fn new(correlation_id: i32, client_id: &'a str) -> Self {
+        let header =
+            HeaderRequest::new(API_KEY_DELETE_ACLS, API_VERSION, correlation_id, client_id);
+        Self {
+            header,
+            filters: vec![],
+        }
+    }
+
+    pub fn add(&mut self, filter: &'a AclBindingFilter) {
+        self.filters.push(filter);
+    }
+}
+
+impl ToByte for DeleteAclsRequest<'_> {
+    fn encode<T: BufMut>(&self, buffer: &mut T) -> Result<()> {
+        tracing::trace!("Encoding DeleteAclsRequest {:?}", self);
+        self.header.encode(buffer)?;
+        self.filters.encode(buffer)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,179 @@
+//! Parsing and processing for Delete Acls responses.
+//!
+//! ### Example
+//! ```rust
+//! let response_bytes = conn.receive_response().await?;
+//! let delete_acls_response = protocol::DeleteAclsResponse::try_from(response_bytes.freeze())?;
+//! ```
+//!
+//! ### Protocol Def
+//! ```text
+//! DeleteAcls Response (Version: 1) => throttle_time_ms [filter_results]
+//!   throttle_time_ms => INT32
+//!   filter_results => error_code error_message [matching_acls]
+//!     error_code => INT16
+//!     error_message => NULLABLE_STRING
+//!     matching_acls => error_code error_message resource_type resource_name pattern_type principal host operation permission_type
+//!       error_code => INT16
+//!       error_message => NULLABLE_STRING
+//!       resource_type => INT8
+//!       resource_name => STRING
+//!       pattern_type => INT8
+//!       principal => STRING
+//!       host => STRING
+//!       operation => INT8
+//!       permission_type => INT8
+//! ```
+//!
+//! Note we are using version 1 of this response
+
+use bytes::Bytes;
+use nom::{number::complete::be_i32, IResult};
+use nombytes::NomBytes;
+
+use crate::{
+    error::{Error, KafkaCode, Result},
+    parser,
+    protocol::{
+        acl::{
+            parse_operation, parse_pattern_type, parse_permission_type, parse_resource_type,
+            AclOperation, PatternType, PermissionType, ResourceType,
+        },
+        parse_header_response, HeaderResponse,
+    },
+};
+
+/// The base Delete Acls response object.
+///
+/// ### Example
+/// ```rust
+/// let response_bytes = conn.receive_response().await?;
+/// let delete_acls_response = protocol::DeleteAclsResponse::try_from(response_bytes.freeze())?;
+/// ```
+#[derive(Debug, PartialEq)]
+pub struct DeleteAclsResponse {
+    pub header: HeaderResponse,
+    /// The duration in milliseconds for which the request was throttled due to a quota violation, or zero if the request did not violate any quota.
+    pub throttle_time_ms: i32,
+    /// The results for each filter, in the order of the request.
+    pub filter_results: Vec<FilterResult>,
+}
+
+/// The ACLs deleted by a filter.
+#[derive(Debug, PartialEq)]
+pub struct FilterResult {
+    /// The error code, or 0 if the filter succeeded.
+    pub error_code: KafkaCode,
+    /// The error message, or null if the filter succeeded.
+    pub error_message: Option<Bytes>,
+    /// The ACLs which matched this filter.
+    pub matching_acls: Vec<MatchingAcl>,
+}
+
+/// An ACL that matched a filter.
+#[derive(Debug, PartialEq)]
+pub struct MatchingAcl {
+    /// The deletion error code, or 0 if the deletion succeeded.
+    pub error_code: KafkaCode,
+    /// The deletion error message, or null if the deletion succeeded.
+    pub error_message: Option<Bytes>,
+    pub resource_type: ResourceType,
+    pub resource_name: Bytes,
+    pub pattern_type: PatternType,
+    pub principal: Bytes,
+    pub host: Bytes,
+    pub operation: AclOperation,
+    pub permission_type: PermissionType,
+}
+
+// this helps us cast the server response into this type
+impl TryFrom<Bytes> for DeleteAclsResponse {
+    type Error = Error;
+
+    fn try_from(s: Bytes) -> Result<Self> {
+        tracing::trace!("Parsing DeleteAclsResponse {:?}", s);
+        let (_, delete_acls) =
+            parse_delete_acls_response(NomBytes::new(s.clone())).map_err(|err| {
+                tracing::error!("ERROR: Failed parsing DeleteAclsResponse {:?}", err);
+                tracing::error!("ERROR: DeleteAclsResponse Bytes {:?}", s);
+                Error::ParsingError(s)
+            })?;
+        tracing::trace!("Parsed DeleteAclsResponse {:?}", delete_acls);
+        Ok(delete_acls)
+    }
+}
+
+impl DeleteAclsResponse {
+    /// Surface a KafkaError, of a filter or of one of the deletions.
+    pub fn is_error(&self) -> Result<()> {
+        for filter_result in self.filter_results.iter() {
+            if filter_result.error_code != KafkaCode::None {
+                return Err(Error::KafkaError(filter_result.error_code));
+            }
+            for matching_acl in filter_result.matching_acls.iter() {
+                if matching_acl.error_code != KafkaCode::None {
+                    return Err(Error::KafkaError(matching_acl.error_code));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub fn parse_delete_acls_response(s: NomBytes) -> IResult<NomBytes, DeleteAclsResponse> {
+    let (s, header) = parse_header_response(s)?;
+    let (s, throttle_time_ms) = be_i32(s)?;
+    let (s, filter_results) = parser::parse_array(parse_filter_result)(s)?;
+
+    Ok((
+        s,
+        DeleteAclsResponse {
+            header,
+            throttle_time_ms,
+            filter_results,
+        },
+    ))
+}
+
+fn parse_filter_result(s: NomBytes) -> IResult<NomBytes, FilterResult> {
+    let (s, error_code) = parser::parse_kafka_code(s)?;
+    let (s, error_message) = parser::parse_nullable_string(s)?;
+    let (s, matching_acls) = parser::parse_array(parse_matching_acl)(s)?;
+
+    Ok((
+        s,
+        FilterResult {
+            error_code,
+            error_message,
+            matching_acls,
+        },
+    ))
+}
+
+fn parse_matching_acl(s: NomBytes) -> IResult<NomBytes, MatchingAcl> {
+    let (s, error_code) = parser::parse_kafka_code(s)?;
+    let (s, error_message) = parser::parse_nullable_string(s)?;
+    let (s, resource_type) = parse_resource_type(s)?;
+    let (s, resource_name) = parser::parse_string(s)?;
+    let (s, pattern_type) = parse_pattern_type(s)?;
+    let (s, principal) = parser::parse_string(s)?;
+    let (s, host) = parser::parse_string(s)?;
+    let (s, operation) = parse_operation(s)?;
+    let (s, permission_type) = parse_permission_type(s)?;
+
+    Ok((
+        s,
+        MatchingAcl {
+            error_code,
+            error_message,
+            resource_type,
+            resource_name,
+            pattern_type,
+            principal,
+            host,
+            operation,
+            permission_type,
+        },
+    ))
+}
@@ -42,7 +42,9 @@ mod test {
             throttle_time_ms: 0,
             topics: vec![response::Topic {
                 name: Bytes::from("tester-creation"),
+                topic_id: [0; 16],
                 error_code: crate::prelude::KafkaCode::None,
+                error_message: None,
             }],
         };
 
@@ -52,4 +54,53 @@ mod test {
 
         assert_eq!(res, x);
     }
+
+    #[test]
+    fn encode_by_topic_id() {
+        let mut b = vec![0, 20, 0, 6, 0, 0, 0, 1, 0, 4, 114, 117, 115, 116, 0, 2, 0];
+        b.extend([7; 16]);
+        b.extend([0, 0, 0, 7, 208, 0]);
+
+        let mut req = request::DeleteTopicsRequest::new(1, "rust", 2000).unwrap();
+        req.header.api_version = request::FIRST_TOPIC_ID_VERSION;
+        req.add_id([7; 16]);
+        req.add_id([7; 16]);
+
+        let mut buffer: Vec<u8> = vec![];
+
+        req.encode(&mut buffer).unwrap();
+
+        assert_eq!(buffer, b);
+    }
+
+    #[test]
+    fn parse_unknown_topic_id() {
+        let mut b = vec![0, 0, 0, 1, 0, 0, 0, 0, 0, 2, 0];
+        b.extend([7; 16]);
+        b.extend([0, 100, 8]);
+        b.extend(b"unknown");
+        b.extend([0, 0]);
+
+        let res = response::DeleteTopicsResponse::try_from_version(
+            Bytes::from(b),
+            request::FIRST_TOPIC_ID_VERSION,
+        )
+        .unwrap();
+
+        assert_eq!(
+            res.topics,
+            vec![response::Topic {
+                name: Bytes::new(),
+                topic_id: [7; 16],
+                error_code: crate::prelude::KafkaCode::UnknownTopicId,
+                error_message: Some(Bytes::from("unknown")),
+            }]
+        );
+        assert_eq!(
+            res.is_error(),
+            Err(crate::error::Error::KafkaError(
+                crate::prelude::KafkaCode::UnknownTopicId
+            ))
+        );
+    }
 }
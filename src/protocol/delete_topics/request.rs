@@ -15,14 +15,35 @@
 //! DeleteTopics Request (Version: 3) => [topic_names] timeout_ms
 //!   topic_names => STRING
 //!   timeout_ms => INT32
+//!
+//! DeleteTopics Request (Version: 6) => [topics] timeout_ms TAG_BUFFER
+//!   topics => name topic_id TAG_BUFFER
+//!     name => COMPACT_NULLABLE_STRING
+//!     topic_id => UUID
+//!   timeout_ms => INT32
 //! ```
 //!
-//! Note that we are using version 3 of this API
+//! Note that we are using version 3 of this API by default. Versions 4 and
+//! up are flexible, and from version 6 topics can be deleted by topic id.
+
+use bytes::BufMut;
 
-use crate::{encode::ToByte, error::Result, protocol::HeaderRequest};
+use crate::{
+    encode::{
+        encode_as_compact_array, CompactNullableString, CompactString, RawBytes, TaggedFields,
+        ToByte,
+    },
+    error::Result,
+    protocol::{HeaderRequest, RequestHeader},
+};
 
-const API_KEY_METADATA: i16 = 20;
-const API_VERSION: i16 = 3;
+pub const API_KEY_DELETE_TOPICS: i16 = 20;
+/// The version of the request sent unless another one is asked for.
+pub const API_VERSION: i16 = 3;
+/// The first version of the DeleteTopics request using the flexible encoding.
+pub const FIRST_FLEXIBLE_VERSION: i16 = 4;
+/// The first version where topics can be deleted by topic id.
+pub const FIRST_TOPIC_ID_VERSION: i16 = 6;
 
 /// The base Delete Topics request object.
 ///
@@ -44,11 +65,13 @@ pub struct DeleteTopicsRequest<'a> {
     pub timeout_ms: i32,
 }
 
-/// The topics to create.
+/// The topics to delete.
 #[derive(Debug)]
 pub struct Topic<'a> {
-    /// The topic name.
+    /// The topic name, empty when deleting by topic id.
     pub name: &'a str,
+    /// The topic id, all zeroes when deleting by name.
+    pub topic_id: [u8; 16],
 }
 
 impl<'a> DeleteTopicsRequest<'a> {
@@ -57,7 +80,12 @@ impl<'a> DeleteTopicsRequest<'a> {
     /// This request needs to be given topics and partitions to be deleted
     /// before being sent to the broker. You can do this by using the `add` method.
     pub fn new(correlation_id: i32, client_id: &'a str, timeout_ms: i32) -> Result<Self> {
-        let header = HeaderRequest::new(API_KEY_METADATA, API_VERSION, correlation_id, client_id);
+        let header = HeaderRequest::new(
+            API_KEY_DELETE_TOPICS,
+            API_VERSION,
+            correlation_id,
+            client_id,
+        );
         Ok(Self {
             header,
             timeout_ms,
@@ -81,26 +109,59 @@ impl<'a> DeleteTopicsRequest<'a> {
             .iter_mut()
             .find(|topic| topic.name == topic_name)
         {
-            None => self.topics.push(Topic { name: topic_name }),
+            None => self.topics.push(Topic {
+                name: topic_name,
+                topic_id: [0; 16],
+            }),
             Some(_) => {
                 // do nothing
             }
         }
     }
+
+    /// Add a topic to be deleted by its topic id.
+    ///
+    /// This needs version [`FIRST_TOPIC_ID_VERSION`] of the request, set
+    /// through `header.api_version`. If the same topic id is used twice, it
+    /// will do nothing the second time.
+    pub fn add_id(&mut self, topic_id: [u8; 16]) {
+        if !self.topics.iter().any(|topic| topic.topic_id == topic_id) {
+            self.topics.push(Topic { name: "", topic_id });
+        }
+    }
 }
 
 impl ToByte for DeleteTopicsRequest<'_> {
     fn encode<T: bytes::BufMut>(&self, buffer: &mut T) -> crate::error::Result<()> {
         tracing::trace!("Encoding DeleteTopicsRequest {:?}", self);
-        self.header.encode(buffer)?;
-        self.topics.encode(buffer)?;
+        let api_version = self.header.api_version;
+        RequestHeader::for_api_version(self.header.clone(), FIRST_FLEXIBLE_VERSION)
+            .encode(buffer)?;
+        if api_version >= FIRST_TOPIC_ID_VERSION {
+            encode_as_compact_array(buffer, &self.topics, |buffer, topic| {
+                // the broker looks a topic up by its id unless it is all zeroes
+                CompactNullableString((!topic.name.is_empty()).then_some(topic.name))
+                    .encode(buffer)?;
+                RawBytes(&topic.topic_id).encode(buffer)?;
+                TaggedFields.encode(buffer)
+            })?;
+        } else if api_version >= FIRST_FLEXIBLE_VERSION {
+            encode_as_compact_array(buffer, &self.topics, |buffer, topic| {
+                CompactString(topic.name).encode(buffer)
+            })?;
+        } else {
+            self.topics.encode(buffer)?;
+        }
         self.timeout_ms.encode(buffer)?;
+        if api_version >= FIRST_FLEXIBLE_VERSION {
+            TaggedFields.encode(buffer)?;
+        }
         Ok(())
     }
 }
 
 impl ToByte for Topic<'_> {
-    fn encode<T: bytes::BufMut>(&self, buffer: &mut T) -> crate::error::Result<()> {
+    fn encode<T: BufMut>(&self, buffer: &mut T) -> crate::error::Result<()> {
         self.name.encode(buffer)?;
         Ok(())
     }
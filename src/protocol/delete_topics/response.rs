@@ -13,8 +13,17 @@
 //!    responses => name error_code
 //!      name => STRING
 //!      error_code => INT16
+//!
+//!  DeleteTopics Response (Version: 6) => throttle_time_ms [responses] TAG_BUFFER
+//!    throttle_time_ms => INT32
+//!    responses => name topic_id error_code error_message TAG_BUFFER
+//!      name => COMPACT_NULLABLE_STRING
+//!      topic_id => UUID
+//!      error_code => INT16
+//!      error_message => COMPACT_NULLABLE_STRING
 //! ```
-//! Note we are using version 3 of this response
+//! Note we are using version 3 of this response by default, see
+//! [`DeleteTopicsResponse::try_from_version`] for the others.
 
 use bytes::Bytes;
 use nom::{number::complete::be_i32, IResult};
@@ -22,10 +31,16 @@ use nombytes::NomBytes;
 
 use crate::{
     error::{Error, KafkaCode, Result},
-    parser::{self, parse_array},
-    protocol::{parse_header_response, HeaderResponse},
+    parser::{self, parse_array, parse_compact_array, parse_tagged_fields},
+    protocol::{
+        delete_topics::request::{API_VERSION, FIRST_FLEXIBLE_VERSION, FIRST_TOPIC_ID_VERSION},
+        parse_flexible_header_response, parse_header_response, HeaderResponse,
+    },
 };
 
+/// The first version of the response with an error message for each topic.
+const FIRST_ERROR_MESSAGE_VERSION: i16 = 5;
+
 /// The base Offset Commit response object.
 ///
 /// ### Example
@@ -45,10 +60,16 @@ pub struct DeleteTopicsResponse {
 /// Results for each topic we tried to delete.
 #[derive(Debug, PartialEq)]
 pub struct Topic {
-    /// The topic name.
+    /// The topic name, empty when a topic id that the cluster does not know
+    /// was deleted.
     pub name: Bytes,
-    /// The error code, or 0 if there was no error.
+    /// The topic id, all zeroes before version 6.
+    pub topic_id: [u8; 16],
+    /// The error code, or 0 if there was no error. Deleting by a topic id
+    /// that the cluster does not know fails with `UNKNOWN_TOPIC_ID`.
     pub error_code: KafkaCode,
+    /// The error message, from version 5.
+    pub error_message: Option<Bytes>,
 }
 
 // this helps us cast the server response into this type
@@ -69,6 +90,21 @@ impl TryFrom<Bytes> for DeleteTopicsResponse {
 }
 
 impl DeleteTopicsResponse {
+    /// Parse a response of the given version of the API.
+    pub fn try_from_version(s: Bytes, api_version: i16) -> Result<Self> {
+        tracing::trace!("Parsing DeleteTopicsResponse v{} {:?}", api_version, s);
+        let (_, delete_topics) = parse_delete_topics_response_version(api_version)(NomBytes::new(
+            s.clone(),
+        ))
+        .map_err(|err| {
+            tracing::error!("ERROR: Failed parsing DeleteTopicsResponse {:?}", err);
+            tracing::error!("ERROR: DeleteTopicsResponse Bytes {:?}", s);
+            Error::ParsingError(s)
+        })?;
+        tracing::trace!("Parsed DeleteTopicsResponse {:?}", delete_topics);
+        Ok(delete_topics)
+    }
+
     /// Surface a KafkaError.
     pub fn is_error(&self) -> Result<()> {
         self.topics
@@ -91,23 +127,78 @@ impl Topic {
 }
 
 pub fn parse_delete_topics_response(s: NomBytes) -> IResult<NomBytes, DeleteTopicsResponse> {
-    let (s, header) = parse_header_response(s)?;
-    let (s, throttle_time_ms) = be_i32(s)?;
-    let (s, topics) = parse_array(parse_topic)(s)?;
-
-    Ok((
-        s,
-        DeleteTopicsResponse {
-            header,
-            throttle_time_ms,
-            topics,
-        },
-    ))
+    parse_delete_topics_response_version(API_VERSION)(s)
+}
+
+pub fn parse_delete_topics_response_version(
+    api_version: i16,
+) -> impl Fn(NomBytes) -> IResult<NomBytes, DeleteTopicsResponse> {
+    move |s: NomBytes| {
+        let flexible = api_version >= FIRST_FLEXIBLE_VERSION;
+        let (s, header) = if flexible {
+            parse_flexible_header_response(s)?
+        } else {
+            parse_header_response(s)?
+        };
+        let (s, throttle_time_ms) = be_i32(s)?;
+        let (s, topics) = if flexible {
+            parse_compact_array(parse_topic_version(api_version))(s)?
+        } else {
+            parse_array(parse_topic_version(api_version))(s)?
+        };
+        let (s, _) = if flexible {
+            parse_tagged_fields(s)?
+        } else {
+            (s, ())
+        };
+
+        Ok((
+            s,
+            DeleteTopicsResponse {
+                header,
+                throttle_time_ms,
+                topics,
+            },
+        ))
+    }
 }
 
-fn parse_topic(s: NomBytes) -> IResult<NomBytes, Topic> {
-    let (s, name) = parser::parse_string(s)?;
-    let (s, error_code) = parser::parse_kafka_code(s)?;
+fn parse_topic_version(api_version: i16) -> impl Fn(NomBytes) -> IResult<NomBytes, Topic> + Copy {
+    move |s: NomBytes| {
+        let flexible = api_version >= FIRST_FLEXIBLE_VERSION;
+        let (s, name) = if api_version >= FIRST_TOPIC_ID_VERSION {
+            let (s, name) = parser::parse_compact_nullable_string(s)?;
+            (s, name.unwrap_or_default())
+        } else if flexible {
+            parser::parse_compact_string(s)?
+        } else {
+            parser::parse_string(s)?
+        };
+        let (s, topic_id) = if api_version >= FIRST_TOPIC_ID_VERSION {
+            parser::parse_uuid(s)?
+        } else {
+            (s, [0; 16])
+        };
+        let (s, error_code) = parser::parse_kafka_code(s)?;
+        let (s, error_message) = if api_version >= FIRST_ERROR_MESSAGE_VERSION {
+            parser::parse_compact_nullable_string(s)?
+        } else {
+            (s, None)
+        };
+        let (s, _) = if flexible {
+            parse_tagged_fields(s)?
+        } else {
+            (s, ())
+        };
 
-    Ok((s, Topic { name, error_code }))
+        Ok((
+            s,
+            Topic {
+                name,
+                topic_id,
+                error_code,
+                error_message,
+            },
+        ))
+    }
 }
@@ -0,0 +1,60 @@
+//! List the ACL bindings matching a filter.
+
+pub mod request;
+pub mod response;
+
+#[cfg(test)]
+mod test {
+    use bytes::Bytes;
+
+    use super::*;
+    use crate::{
+        encode::ToByte,
+        error::KafkaCode,
+        protocol::acl::{
+            AclBinding, AclBindingFilter, AclOperation, PatternType, PermissionType, ResourceType,
+        },
+    };
+
+    #[test]
+    fn encode() {
+        let b = [
+            0, 29, 0, 1, 0, 0, 0, 1, 0, 4, 114, 117, 115, 116, 2, 0, 9, 112, 117, 114, 99, 104, 97,
+            115, 101, 115, 3, 255, 255, 255, 255, 1, 1,
+        ];
+        let filter = AclBindingFilter {
+            resource_type: ResourceType::Topic,
+            resource_name: Some("purchases".to_owned()),
+            pattern_type: PatternType::Literal,
+            ..AclBindingFilter::any()
+        };
+
+        let req = request::DescribeAclsRequest::new(1, "rust", &filter);
+        let mut buffer: Vec<u8> = vec![];
+        req.encode(&mut buffer).unwrap();
+
+        assert_eq!(buffer, b);
+    }
+
+    #[test]
+    fn parse() {
+        let b = b"\x00\x00\x00\x01\x00\x00\x00\x00\x00\x00\xff\xff\x00\x00\x00\x01\x02\x00\tpurchases\x03\x00\x00\x00\x01\x00\nUser:alice\x00\x01*\x03\x03";
+
+        let res = response::DescribeAclsResponse::try_from(Bytes::from_static(b)).unwrap();
+
+        assert_eq!(res.error_code, KafkaCode::None);
+        assert!(res.is_error().is_ok());
+        assert_eq!(
+            res.bindings(),
+            vec![AclBinding {
+                resource_type: ResourceType::Topic,
+                resource_name: "purchases".to_owned(),
+                pattern_type: PatternType::Literal,
+                principal: "User:alice".to_owned(),
+                host: "*".to_owned(),
+                operation: AclOperation::Read,
+                permission_type: PermissionType::Allow,
+            }]
+        );
+    }
+}
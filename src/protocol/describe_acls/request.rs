@@ -0,0 +1,72 @@
+//! Encoding and creation for Describe Acls requests.
+//!
+//! Lists the ACL bindings matching a filter. The broker must have an
+//! authorizer configured, otherwise the request fails with SECURITY_DISABLED.
+//!
+//! ### Example
+//! ```rust
+//! let describe_acls_request =
+//!     protocol::DescribeAclsRequest::new(correlation_id, client_id, &AclBindingFilter::any());
+//! conn.send_request(&describe_acls_request).await?;
+//! ```
+//!
+//! ### Protocol Def
+//! ```text
+//! DescribeAcls Request (Version: 1) => resource_type_filter resource_name_filter pattern_type_filter principal_filter host_filter operation permission_type
+//!   resource_type_filter => INT8
+//!   resource_name_filter => NULLABLE_STRING
+//!   pattern_type_filter => INT8
+//!   principal_filter => NULLABLE_STRING
+//!   host_filter => NULLABLE_STRING
+//!   operation => INT8
+//!   permission_type => INT8
+//! ```
+//!
+//! Note we are using version 1 of the request.
+
+use bytes::BufMut;
+
+use crate::{
+    encode::ToByte,
+    error::Result,
+    protocol::{acl::AclBindingFilter, HeaderRequest},
+};
+
+const API_KEY_DESCRIBE_ACLS: i16 = 29;
+const API_VERSION: i16 = 1;
+
+/// The base Describe Acls request object.
+///
+/// ### Example
+/// ```rust
+/// let describe_acls_request =
+///     protocol::DescribeAclsRequest::new(correlation_id, client_id, &AclBindingFilter::any());
+/// conn.send_request(&describe_acls_request).await?;
+/// ```
+#[derive(Debug)]
+pub struct DescribeAclsRequest<'a> {
+    pub header: HeaderRequest<'a>,
+    /// The bindings to describe.
+    pub filter: &'a AclBindingFilter,
+}
+
+impl<'a> DescribeAclsRequest<'a> {
+    pub fn new(correlation_id: i32, client_id: &'a str, filter: &'a AclBindingFilter) -> Self {
+        let header = HeaderRequest::new(
+            API_KEY_DESCRIBE_ACLS,
+            API_VERSION,
+            correlation_id,
+            client_id,
+        );
+        Self { header, filter }
+    }
+}
+
+impl ToByte for DescribeAclsRequest<'_> {
+    fn encode<T: BufMut>(&self, buffer: &mut T) -> Result<()> {
+        tracing::trace!("Encoding DescribeAclsRequest {:?}", self);
+        self.header.encode(buffer)?;
+        self.filter.encode(buffer)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,179 @@
+//! Parsing and processing for Describe Acls responses.
+//!
+//! ### Example
+//! ```rust
+//! let response_bytes = conn.receive_response().await?;
+//! let describe_acls_response = protocol::DescribeAclsResponse::try_from(response_bytes.freeze())?;
+//! ```
+//!
+//! ### Protocol Def
+//! ```text
+//! DescribeAcls Response (Version: 1) => throttle_time_ms error_code error_message [resources]
+//!   throttle_time_ms => INT32
+//!   error_code => INT16
+//!   error_message => NULLABLE_STRING
+//!   resources => resource_type resource_name pattern_type [acls]
+//!     resource_type => INT8
+//!     resource_name => STRING
+//!     pattern_type => INT8
+//!     acls => principal host operation permission_type
+//!       principal => STRING
+//!       host => STRING
+//!       operation => INT8
+//!       permission_type => INT8
+//! ```
+//!
+//! Note we are using version 1 of this response
+
+use bytes::Bytes;
+use nom::{number::complete::be_i32, IResult};
+use nombytes::NomBytes;
+
+use crate::{
+    error::{Error, KafkaCode, Result},
+    parser,
+    protocol::{
+        acl::{
+            parse_operation, parse_pattern_type, parse_permission_type, parse_resource_type,
+            AclBinding, AclOperation, PatternType, PermissionType, ResourceType,
+        },
+        parse_header_response, HeaderResponse,
+    },
+};
+
+/// The base Describe Acls response object.
+///
+/// ### Example
+/// ```rust
+/// let response_bytes = conn.receive_response().await?;
+/// let describe_acls_response = protocol::DescribeAclsResponse::try_from(response_bytes.freeze())?;
+/// ```
+#[derive(Debug, PartialEq)]
+pub struct DescribeAclsResponse {
+    pub header: HeaderResponse,
+    /// The duration in milliseconds for which the request was throttled due to a quota violation, or zero if the request did not violate any quota.
+    pub throttle_time_ms: i32,
+    /// The error code, or 0 if there was no error.
+    pub error_code: KafkaCode,
+    /// The error message, or null if there was no error.
+    pub error_message: Option<Bytes>,
+    /// Each resource pattern with ACLs matching the filter.
+    pub resources: Vec<Resource>,
+}
+
+/// A resource pattern and its ACLs.
+#[derive(Debug, PartialEq)]
+pub struct Resource {
+    pub resource_type: ResourceType,
+    pub resource_name: Bytes,
+    pub pattern_type: PatternType,
+    /// The access control entries bound to the resource pattern.
+    pub acls: Vec<Acl>,
+}
+
+/// An access control entry.
+#[derive(Debug, PartialEq)]
+pub struct Acl {
+    pub principal: Bytes,
+    pub host: Bytes,
+    pub operation: AclOperation,
+    pub permission_type: PermissionType,
+}
+
+// this helps us cast the server response into this type
+impl TryFrom<Bytes> for DescribeAclsResponse {
+    type Error = Error;
+
+    fn try_from(s: Bytes) -> Result<Self> {
+        tracing::trace!("Parsing DescribeAclsResponse {:?}", s);
+        let (_, describe_acls) =
+            parse_describe_acls_response(NomBytes::new(s.clone())).map_err(|err| {
+                tracing::error!("ERROR: Failed parsing DescribeAclsResponse {:?}", err);
+                tracing::error!("ERROR: DescribeAclsResponse Bytes {:?}", s);
+                Error::ParsingError(s)
+            })?;
+        tracing::trace!("Parsed DescribeAclsResponse {:?}", describe_acls);
+        Ok(describe_acls)
+    }
+}
+
+impl DescribeAclsResponse {
+    /// Surface a KafkaError.
+    pub fn is_error(&self) -> Result<()> {
+        match self.error_code {
+            KafkaCode::None => Ok(()),
+            _ => Err(Error::KafkaError(self.error_code)),
+        }
+    }
+
+    /// Each ACL in the response, together with its resource pattern.
+    pub fn bindings(&self) -> Vec<AclBinding> {
+        self.resources
+            .iter()
+            .flat_map(|resource| {
+                resource.acls.iter().map(move |acl| AclBinding {
+                    resource_type: resource.resource_type,
+                    resource_name: String::from_utf8_lossy(&resource.resource_name).to_string(),
+                    pattern_type: resource.pattern_type,
+                    principal: String::from_utf8_lossy(&acl.principal).to_string(),
+                    host: String::from_utf8_lossy(&acl.host).to_string(),
+                    operation: acl.operation,
+                    permission_type: acl.permission_type,
+                })
+            })
+            .collect()
+    }
+}
+
+pub fn parse_describe_acls_response(s: NomBytes) -> IResult<NomBytes, DescribeAclsResponse> {
+    let (s, header) = parse_header_response(s)?;
+    let (s, throttle_time_ms) = be_i32(s)?;
+    let (s, error_code) = parser::parse_kafka_code(s)?;
+    let (s, error_message) = parser::parse_nullable_string(s)?;
+    let (s, resources) = parser::parse_array(parse_resource)(s)?;
+
+    Ok((
+        s,
+        DescribeAclsResponse {
+            header,
+            throttle_time_ms,
+            error_code,
+            error_message,
+            resources,
+        },
+    ))
+}
+
+fn parse_resource(s: NomBytes) -> IResult<NomBytes, Resource> {
+    let (s, resource_type) = parse_resource_type(s)?;
+    let (s, resource_name) = parser::parse_string(s)?;
+    let (s, pattern_type) = parse_pattern_type(s)?;
+    let (s, acls) = parser::parse_array(parse_acl)(s)?;
+
+    Ok((
+        s,
+        Resource {
+            resource_type,
+            resource_name,
+            pattern_type,
+            acls,
+        },
+    ))
+}
+
+fn parse_acl(s: NomBytes) -> IResult<NomBytes, Acl> {
+    let (s, principal) = parser::parse_string(s)?;
+    let (s, host) = parser::parse_string(s)?;
+    let (s, operation) = parse_operation(s)?;
+    let (s, permission_type) = parse_permission_type(s)?;
+
+    Ok((
+        s,
+        Acl {
+            principal,
+            host,
+            operation,
+            permission_type,
+        },
+    ))
+}
@@ -0,0 +1,68 @@
+//! Describe the active producers of topic partitions.
+
+pub mod request;
+pub mod response;
+
+#[cfg(test)]
+mod test {
+    use bytes::Bytes;
+    use nombytes::NomBytes;
+
+    use super::*;
+    use crate::{encode::ToByte, error::KafkaCode, protocol};
+
+    #[test]
+    fn encode() {
+        let b = [
+            0, 61, 0, 0, 0, 0, 0, 1, 0, 4, 114, 117, 115, 116, 0, 2, 10, 112, 117, 114, 99, 104,
+            97, 115, 101, 115, 3, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0,
+        ];
+
+        let mut req = request::DescribeProducersRequest::new(1, "rust");
+        req.add("purchases", 0);
+        req.add("purchases", 1);
+        req.add("purchases", 1);
+
+        let mut buffer: Vec<u8> = vec![];
+        req.encode(&mut buffer).unwrap();
+
+        assert_eq!(buffer, b);
+    }
+
+    #[test]
+    fn parse() {
+        let b = [
+            0, 0, 0, 1, 0, 0, 0, 0, 0, 2, 10, 112, 117, 114, 99, 104, 97, 115, 101, 115, 2, 0, 0,
+            0, 0, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 42, 0, 0, 0, 0, 0, 0, 0, 4, 0, 0, 1, 139, 72,
+            32, 239, 192, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 0, 0, 0, 0,
+        ];
+
+        let res = response::DescribeProducersResponse {
+            header: protocol::HeaderResponse { correlation_id: 1 },
+            throttle_time_ms: 0,
+            topics: vec![response::Topic {
+                name: Bytes::from("purchases"),
+                partitions: vec![response::Partition {
+                    partition_index: 0,
+                    error_code: KafkaCode::None,
+                    error_message: None,
+                    active_producers: vec![response::ActiveProducer {
+                        producer_id: 42,
+                        producer_epoch: 0,
+                        last_sequence: 4,
+                        last_timestamp: 1697722200000,
+                        coordinator_epoch: -1,
+                        current_txn_start_offset: -1,
+                    }],
+                }],
+            }],
+        };
+
+        let x =
+            response::parse_describe_producers_response(NomBytes::new(Bytes::copy_from_slice(&b)))
+                .unwrap()
+                .1;
+
+        assert_eq!(res, x);
+    }
+}
@@ -0,0 +1,117 @@
+//! Encoding and creation for Describe Producers requests.
+//!
+//! ### Example
+//! ```rust
+//! let mut describe_producers_request = protocol::DescribeProducersRequest::new(
+//!     correlation_id,
+//!     client_id,
+//! );
+//! describe_producers_request.add(topic_name, partition_index);
+//! conn.send_request(&describe_producers_request).await?;
+//! ```
+//!
+//! ### Protocol Def
+//! ```text
+//! DescribeProducers Request (Version: 0) => [topics] TAG_BUFFER
+//!   topics => name [partition_indexes] TAG_BUFFER
+//!     name => COMPACT_STRING
+//!     partition_indexes => INT32
+//! ```
+//!
+//! Note that we are using version 0 of this API, which is a flexible version.
+
+use bytes::BufMut;
+
+use crate::{
+    encode::{CompactArray, CompactString, TaggedFields, ToByte},
+    error::Result,
+    protocol::HeaderRequest,
+};
+
+const API_KEY_DESCRIBE_PRODUCERS: i16 = 61;
+const API_VERSION: i16 = 0;
+
+/// The base Describe Producers request object.
+///
+/// ### Example
+/// ```rust
+/// let mut describe_producers_request = protocol::DescribeProducersRequest::new(
+///     correlation_id,
+///     client_id,
+/// );
+/// describe_producers_request.add(topic_name, partition_index);
+/// conn.send_request(&describe_producers_request).await?;
+/// ```
+#[derive(Debug)]
+pub struct DescribeProducersRequest<'a> {
+    pub header: HeaderRequest<'a>,
+    /// The topics to describe the active producers of.
+    pub topics: Vec<Topic<'a>>,
+}
+
+/// The topics to describe the active producers of.
+#[derive(Debug)]
+pub struct Topic<'a> {
+    /// The topic name.
+    pub name: &'a str,
+    /// The indexes of the partitions to describe.
+    pub partition_indexes: Vec<i32>,
+}
+
+impl<'a> DescribeProducersRequest<'a> {
+    /// Create a new Describe Producers Request
+    ///
+    /// This request needs to be given topics and partitions to describe
+    /// before being sent to the broker. You can do this by using the `add` method.
+    pub fn new(correlation_id: i32, client_id: &'a str) -> Self {
+        Self {
+            header: HeaderRequest::new(
+                API_KEY_DESCRIBE_PRODUCERS,
+                API_VERSION,
+                correlation_id,
+                client_id,
+            ),
+            topics: vec![],
+        }
+    }
+
+    /// Add a topic partition to describe.
+    ///
+    /// If the same topic partition is used twice, it will do nothing the second time
+    pub fn add(&mut self, topic_name: &'a str, partition_index: i32) {
+        match self
+            .topics
+            .iter_mut()
+            .find(|topic| topic.name == topic_name)
+        {
+            None => self.topics.push(Topic {
+                name: topic_name,
+                partition_indexes: vec![partition_index],
+            }),
+            Some(topic) => {
+                if !topic.partition_indexes.contains(&partition_index) {
+                    topic.partition_indexes.push(partition_index);
+                }
+            }
+        }
+    }
+}
+
+impl ToByte for DescribeProducersRequest<'_> {
+    fn encode<T: BufMut>(&self, buffer: &mut T) -> Result<()> {
+        tracing::trace!("Encoding DescribeProducersRequest {:?}", self);
+        self.header.encode_flexible(buffer)?;
+        CompactArray(&self.topics).encode(buffer)?;
+        TaggedFields.encode(buffer)?;
+        Ok(())
+    }
+}
+
+impl ToByte for Topic<'_> {
+    fn encode<T: BufMut>(&self, buffer: &mut T) -> Result<()> {
+        CompactString(self.name).encode(buffer)?;
+        CompactArray(&self.partition_indexes).encode(buffer)?;
+        TaggedFields.encode(buffer)?;
+        Ok(())
+    }
+}
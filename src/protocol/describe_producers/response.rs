@@ -0,0 +1,199 @@
+//! Parsing and processing for Describe Producers responses.
+//!
+//! ### Example
+//! ```rust
+//! let response_bytes = conn.receive_response().await?;
+//! let describe_producers_response = protocol::DescribeProducersResponse::try_from(response_bytes.freeze())?;
+//! ```
+//!
+//! ### Protocol Def
+//! ```text
+//! DescribeProducers Response (Version: 0) => throttle_time_ms [topics] TAG_BUFFER
+//!   throttle_time_ms => INT32
+//!   topics => name [partitions] TAG_BUFFER
+//!     name => COMPACT_STRING
+//!     partitions => partition_index error_code error_message [active_producers] TAG_BUFFER
+//!       partition_index => INT32
+//!       error_code => INT16
+//!       error_message => COMPACT_NULLABLE_STRING
+//!       active_producers => producer_id producer_epoch last_sequence last_timestamp coordinator_epoch current_txn_start_offset TAG_BUFFER
+//!         producer_id => INT64
+//!         producer_epoch => INT32
+//!         last_sequence => INT32
+//!         last_timestamp => INT64
+//!         coordinator_epoch => INT32
+//!         current_txn_start_offset => INT64
+//! ```
+//!
+//! Note we are using version 0 of this response
+
+use bytes::Bytes;
+use nom::{
+    number::complete::{be_i32, be_i64},
+    IResult,
+};
+use nombytes::NomBytes;
+
+use crate::{
+    error::{Error, KafkaCode, Result},
+    parser::{self, parse_compact_array, parse_tagged_fields},
+    protocol::{parse_flexible_header_response, HeaderResponse},
+};
+
+/// The base Describe Producers response object.
+///
+/// ### Example
+/// ```rust
+/// let response_bytes = conn.receive_response().await?;
+/// let describe_producers_response = protocol::DescribeProducersResponse::try_from(response_bytes.freeze())?;
+/// ```
+#[derive(Debug, PartialEq)]
+pub struct DescribeProducersResponse {
+    pub header: HeaderResponse,
+    /// The duration in milliseconds for which the request was throttled due to a quota violation, or zero if the request did not violate any quota.
+    pub throttle_time_ms: i32,
+    /// Each topic in the response.
+    pub topics: Vec<Topic>,
+}
+
+/// Each topic in the response.
+#[derive(Debug, PartialEq)]
+pub struct Topic {
+    /// The topic name.
+    pub name: Bytes,
+    /// Each partition in the response.
+    pub partitions: Vec<Partition>,
+}
+
+/// Each partition in the response.
+#[derive(Debug, PartialEq)]
+pub struct Partition {
+    /// The partition index.
+    pub partition_index: i32,
+    /// The partition error code, or 0 if there was no error.
+    pub error_code: KafkaCode,
+    /// The partition error message, which may be null if no additional details are available.
+    pub error_message: Option<Bytes>,
+    /// The producers that are writing to this partition.
+    pub active_producers: Vec<ActiveProducer>,
+}
+
+/// A producer that is writing to a partition.
+#[derive(Debug, PartialEq)]
+pub struct ActiveProducer {
+    pub producer_id: i64,
+    pub producer_epoch: i32,
+    /// The sequence number of the last record written by the producer.
+    pub last_sequence: i32,
+    /// The timestamp of the last record written by the producer.
+    pub last_timestamp: i64,
+    pub coordinator_epoch: i32,
+    /// The offset of the open transaction of the producer, or -1 if there is none.
+    pub current_txn_start_offset: i64,
+}
+
+// this helps us cast the server response into this type
+impl TryFrom<Bytes> for DescribeProducersResponse {
+    type Error = Error;
+
+    fn try_from(s: Bytes) -> Result<Self> {
+        tracing::trace!("Parsing DescribeProducersResponse {:?}", s);
+        let (_, describe_producers) = parse_describe_producers_response(NomBytes::new(s.clone()))
+            .map_err(|err| {
+            tracing::error!("ERROR: Failed parsing DescribeProducersResponse {:?}", err);
+            tracing::error!("ERROR: DescribeProducersResponse Bytes {:?}", s);
+            Error::ParsingError(s)
+        })?;
+        tracing::trace!("Parsed DescribeProducersResponse {:?}", describe_producers);
+        Ok(describe_producers)
+    }
+}
+
+impl DescribeProducersResponse {
+    /// Surface a KafkaError.
+    pub fn is_error(&self) -> Result<()> {
+        for topic in self.topics.iter() {
+            for partition in topic.partitions.iter() {
+                partition.is_error()?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Partition {
+    /// Surface a KafkaError.
+    pub fn is_error(&self) -> Result<()> {
+        match self.error_code {
+            KafkaCode::None => Ok(()),
+            _ => Err(Error::KafkaError(self.error_code)),
+        }
+    }
+}
+
+pub fn parse_describe_producers_response(
+    s: NomBytes,
+) -> IResult<NomBytes, DescribeProducersResponse> {
+    let (s, header) = parse_flexible_header_response(s)?;
+    let (s, throttle_time_ms) = be_i32(s)?;
+    let (s, topics) = parse_compact_array(parse_topic)(s)?;
+    let (s, _) = parse_tagged_fields(s)?;
+
+    Ok((
+        s,
+        DescribeProducersResponse {
+            header,
+            throttle_time_ms,
+            topics,
+        },
+    ))
+}
+
+fn parse_topic(s: NomBytes) -> IResult<NomBytes, Topic> {
+    let (s, name) = parser::parse_compact_string(s)?;
+    let (s, partitions) = parse_compact_array(parse_partition)(s)?;
+    let (s, _) = parse_tagged_fields(s)?;
+
+    Ok((s, Topic { name, partitions }))
+}
+
+fn parse_partition(s: NomBytes) -> IResult<NomBytes, Partition> {
+    let (s, partition_index) = be_i32(s)?;
+    let (s, error_code) = parser::parse_kafka_code(s)?;
+    let (s, error_message) = parser::parse_compact_nullable_string(s)?;
+    let (s, active_producers) = parse_compact_array(parse_active_producer)(s)?;
+    let (s, _) = parse_tagged_fields(s)?;
+
+    Ok((
+        s,
+        Partition {
+            partition_index,
+            error_code,
+            error_message,
+            active_producers,
+        },
+    ))
+}
+
+fn parse_active_producer(s: NomBytes) -> IResult<NomBytes, ActiveProducer> {
+    let (s, producer_id) = be_i64(s)?;
+    let (s, producer_epoch) = be_i32(s)?;
+    let (s, last_sequence) = be_i32(s)?;
+    let (s, last_timestamp) = be_i64(s)?;
+    let (s, coordinator_epoch) = be_i32(s)?;
+    let (s, current_txn_start_offset) = be_i64(s)?;
+    let (s, _) = parse_tagged_fields(s)?;
+
+    Ok((
+        s,
+        ActiveProducer {
+            producer_id,
+            producer_epoch,
+            last_sequence,
+            last_timestamp,
+            coordinator_epoch,
+            current_txn_start_offset,
+        },
+    ))
+}
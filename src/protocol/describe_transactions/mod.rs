@@ -0,0 +1,64 @@
+//! Describe the state of transactions.
+
+pub mod request;
+pub mod response;
+
+#[cfg(test)]
+mod test {
+    use bytes::Bytes;
+    use nombytes::NomBytes;
+
+    use super::*;
+    use crate::{encode::ToByte, error::KafkaCode, protocol};
+
+    #[test]
+    fn encode() {
+        let b = [
+            0, 65, 0, 0, 0, 0, 0, 1, 0, 4, 114, 117, 115, 116, 0, 2, 4, 116, 120, 110, 0,
+        ];
+
+        let mut req = request::DescribeTransactionsRequest::new(1, "rust");
+        req.add("txn");
+        req.add("txn");
+
+        let mut buffer: Vec<u8> = vec![];
+        req.encode(&mut buffer).unwrap();
+
+        assert_eq!(buffer, b);
+    }
+
+    #[test]
+    fn parse() {
+        let b = [
+            0, 0, 0, 1, 0, 0, 0, 0, 0, 2, 0, 0, 4, 116, 120, 110, 8, 79, 110, 103, 111, 105, 110,
+            103, 0, 0, 234, 96, 0, 0, 1, 139, 72, 32, 239, 192, 0, 0, 0, 0, 0, 0, 0, 42, 0, 0, 2,
+            10, 112, 117, 114, 99, 104, 97, 115, 101, 115, 2, 0, 0, 0, 0, 0, 0, 0,
+        ];
+
+        let res = response::DescribeTransactionsResponse {
+            header: protocol::HeaderResponse { correlation_id: 1 },
+            throttle_time_ms: 0,
+            transaction_states: vec![response::TransactionState {
+                error_code: KafkaCode::None,
+                transactional_id: Bytes::from("txn"),
+                transaction_state: Bytes::from("Ongoing"),
+                transaction_timeout_ms: 60000,
+                transaction_start_time_ms: 1697722200000,
+                producer_id: 42,
+                producer_epoch: 0,
+                topics: vec![response::Topic {
+                    topic: Bytes::from("purchases"),
+                    partitions: vec![0],
+                }],
+            }],
+        };
+
+        let x = response::parse_describe_transactions_response(NomBytes::new(
+            Bytes::copy_from_slice(&b),
+        ))
+        .unwrap()
+        .1;
+
+        assert_eq!(res, x);
+    }
+}
@@ -0,0 +1,87 @@
+//! Encoding and creation for Describe Transactions requests.
+//!
+//! ### Example
+//! ```rust
+//! let mut describe_transactions_request = protocol::DescribeTransactionsRequest::new(
+//!     correlation_id,
+//!     client_id,
+//! );
+//! describe_transactions_request.add(transactional_id);
+//! coordinator_conn.send_request(&describe_transactions_request).await?;
+//! ```
+//!
+//! ### Protocol Def
+//! ```text
+//! DescribeTransactions Request (Version: 0) => [transactional_ids] TAG_BUFFER
+//!   transactional_ids => COMPACT_STRING
+//! ```
+//!
+//! Note that we are using version 0 of this API, which is a flexible version.
+
+use bytes::BufMut;
+
+use crate::{
+    encode::{encode_as_compact_array, CompactString, TaggedFields, ToByte},
+    error::Result,
+    protocol::HeaderRequest,
+};
+
+const API_KEY_DESCRIBE_TRANSACTIONS: i16 = 65;
+const API_VERSION: i16 = 0;
+
+/// The base Describe Transactions request object.
+///
+/// ### Example
+/// ```rust
+/// let mut describe_transactions_request = protocol::DescribeTransactionsRequest::new(
+///     correlation_id,
+///     client_id,
+/// );
+/// describe_transactions_request.add(transactional_id);
+/// coordinator_conn.send_request(&describe_transactions_request).await?;
+/// ```
+#[derive(Debug)]
+pub struct DescribeTransactionsRequest<'a> {
+    pub header: HeaderRequest<'a>,
+    /// The transactional ids to describe.
+    pub transactional_ids: Vec<&'a str>,
+}
+
+impl<'a> DescribeTransactionsRequest<'a> {
+    /// Create a new Describe Transactions Request
+    ///
+    /// This request needs to be given transactional ids to describe
+    /// before being sent to the broker. You can do this by using the `add` method.
+    pub fn new(correlation_id: i32, client_id: &'a str) -> Self {
+        Self {
+            header: HeaderRequest::new(
+                API_KEY_DESCRIBE_TRANSACTIONS,
+                API_VERSION,
+                correlation_id,
+                client_id,
+            ),
+            transactional_ids: vec![],
+        }
+    }
+
+    /// Add a transactional id to describe.
+    ///
+    /// If the same transactional id is used twice, it will do nothing the second time
+    pub fn add(&mut self, transactional_id: &'a str) {
+        if !self.transactional_ids.contains(&transactional_id) {
+            self.transactional_ids.push(transactional_id);
+        }
+    }
+}
+
+impl ToByte for DescribeTransactionsRequest<'_> {
+    fn encode<T: BufMut>(&self, buffer: &mut T) -> Result<()> {
+        tracing::trace!("Encoding DescribeTransactionsRequest {:?}", self);
+        self.header.encode_flexible(buffer)?;
+        encode_as_compact_array(buffer, &self.transactional_ids, |buffer, id| {
+            CompactString(id).encode(buffer)
+        })?;
+        TaggedFields.encode(buffer)?;
+        Ok(())
+    }
+}
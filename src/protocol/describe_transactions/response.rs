@@ -0,0 +1,175 @@
+//! Parsing and processing for Describe Transactions responses.
+//!
+//! ### Example
+//! ```rust
+//! let response_bytes = coordinator_conn.receive_response().await?;
+//! let describe_transactions_response = protocol::DescribeTransactionsResponse::try_from(response_bytes.freeze())?;
+//! ```
+//!
+//! ### Protocol Def
+//! ```text
+//! DescribeTransactions Response (Version: 0) => throttle_time_ms [transaction_states] TAG_BUFFER
+//!   throttle_time_ms => INT32
+//!   transaction_states => error_code transactional_id transaction_state transaction_timeout_ms transaction_start_time_ms producer_id producer_epoch [topics] TAG_BUFFER
+//!     error_code => INT16
+//!     transactional_id => COMPACT_STRING
+//!     transaction_state => COMPACT_STRING
+//!     transaction_timeout_ms => INT32
+//!     transaction_start_time_ms => INT64
+//!     producer_id => INT64
+//!     producer_epoch => INT16
+//!     topics => topic [partitions] TAG_BUFFER
+//!       topic => COMPACT_STRING
+//!       partitions => INT32
+//! ```
+//!
+//! Note we are using version 0 of this response
+
+use bytes::Bytes;
+use nom::{
+    number::complete::{be_i16, be_i32, be_i64},
+    IResult,
+};
+use nombytes::NomBytes;
+
+use crate::{
+    error::{Error, KafkaCode, Result},
+    parser::{self, parse_compact_array, parse_tagged_fields},
+    protocol::{parse_flexible_header_response, HeaderResponse},
+};
+
+/// The base Describe Transactions response object.
+///
+/// ### Example
+/// ```rust
+/// let response_bytes = coordinator_conn.receive_response().await?;
+/// let describe_transactions_response = protocol::DescribeTransactionsResponse::try_from(response_bytes.freeze())?;
+/// ```
+#[derive(Debug, PartialEq)]
+pub struct DescribeTransactionsResponse {
+    pub header: HeaderResponse,
+    /// The duration in milliseconds for which the request was throttled due to a quota violation, or zero if the request did not violate any quota.
+    pub throttle_time_ms: i32,
+    /// The state of each transaction that was asked about.
+    pub transaction_states: Vec<TransactionState>,
+}
+
+/// The state of a transaction.
+#[derive(Debug, PartialEq)]
+pub struct TransactionState {
+    /// The error code, or 0 if there was no error.
+    pub error_code: KafkaCode,
+    pub transactional_id: Bytes,
+    /// The current transaction state of the producer, e.g. "Ongoing".
+    pub transaction_state: Bytes,
+    pub transaction_timeout_ms: i32,
+    /// When the current transaction started, or -1 if there is none.
+    pub transaction_start_time_ms: i64,
+    pub producer_id: i64,
+    pub producer_epoch: i16,
+    /// The topic partitions enrolled in the current transaction.
+    pub topics: Vec<Topic>,
+}
+
+/// The partitions of a topic enrolled in a transaction.
+#[derive(Debug, PartialEq)]
+pub struct Topic {
+    pub topic: Bytes,
+    pub partitions: Vec<i32>,
+}
+
+// this helps us cast the server response into this type
+impl TryFrom<Bytes> for DescribeTransactionsResponse {
+    type Error = Error;
+
+    fn try_from(s: Bytes) -> Result<Self> {
+        tracing::trace!("Parsing DescribeTransactionsResponse {:?}", s);
+        let (_, describe_transactions) =
+            parse_describe_transactions_response(NomBytes::new(s.clone())).map_err(|err| {
+                tracing::error!(
+                    "ERROR: Failed parsing DescribeTransactionsResponse {:?}",
+                    err
+                );
+                tracing::error!("ERROR: DescribeTransactionsResponse Bytes {:?}", s);
+                Error::ParsingError(s)
+            })?;
+        tracing::trace!(
+            "Parsed DescribeTransactionsResponse {:?}",
+            describe_transactions
+        );
+        Ok(describe_transactions)
+    }
+}
+
+impl DescribeTransactionsResponse {
+    /// Surface a KafkaError.
+    pub fn is_error(&self) -> Result<()> {
+        for transaction_state in self.transaction_states.iter() {
+            transaction_state.is_error()?;
+        }
+
+        Ok(())
+    }
+}
+
+impl TransactionState {
+    /// Surface a KafkaError.
+    pub fn is_error(&self) -> Result<()> {
+        match self.error_code {
+            KafkaCode::None => Ok(()),
+            _ => Err(Error::KafkaError(self.error_code)),
+        }
+    }
+}
+
+pub fn parse_describe_transactions_response(
+    s: NomBytes,
+) -> IResult<NomBytes, DescribeTransactionsResponse> {
+    let (s, header) = parse_flexible_header_response(s)?;
+    let (s, throttle_time_ms) = be_i32(s)?;
+    let (s, transaction_states) = parse_compact_array(parse_transaction_state)(s)?;
+    let (s, _) = parse_tagged_fields(s)?;
+
+    Ok((
+        s,
+        DescribeTransactionsResponse {
+            header,
+            throttle_time_ms,
+            transaction_states,
+        },
+    ))
+}
+
+fn parse_transaction_state(s: NomBytes) -> IResult<NomBytes, TransactionState> {
+    let (s, error_code) = parser::parse_kafka_code(s)?;
+    let (s, transactional_id) = parser::parse_compact_string(s)?;
+    let (s, transaction_state) = parser::parse_compact_string(s)?;
+    let (s, transaction_timeout_ms) = be_i32(s)?;
+    let (s, transaction_start_time_ms) = be_i64(s)?;
+    let (s, producer_id) = be_i64(s)?;
+    let (s, producer_epoch) = be_i16(s)?;
+    let (s, topics) = parse_compact_array(parse_topic)(s)?;
+    let (s, _) = parse_tagged_fields(s)?;
+
+    Ok((
+        s,
+        TransactionState {
+            error_code,
+            transactional_id,
+            transaction_state,
+            transaction_timeout_ms,
+            transaction_start_time_ms,
+            producer_id,
+            producer_epoch,
+            topics,
+        },
+    ))
+}
+
+fn parse_topic(s: NomBytes) -> IResult<NomBytes, Topic> {
+    let (s, topic) = parser::parse_compact_string(s)?;
+    let (s, partitions) = parse_compact_array(be_i32)(s)?;
+    let (s, _) = parse_tagged_fields(s)?;
+
+    Ok((s, Topic { topic, partitions }))
+}
@@ -0,0 +1,45 @@
+//! Commit or abort a transaction.
+
+pub mod request;
+pub mod response;
+
+#[cfg(test)]
+mod test {
+    use bytes::Bytes;
+    use nombytes::NomBytes;
+
+    use super::*;
+    use crate::{encode::ToByte, error::KafkaCode, protocol};
+
+    #[test]
+    fn encode() {
+        let b = [
+            0, 26, 0, 0, 0, 0, 0, 1, 0, 4, 114, 117, 115, 116, 0, 3, 116, 120, 110, 0, 0, 0, 0, 0,
+            0, 0, 42, 0, 3, 1,
+        ];
+
+        let req = request::EndTxnRequest::new(1, "rust", "txn", 42, 3, true);
+
+        let mut buffer: Vec<u8> = vec![];
+        req.encode(&mut buffer).unwrap();
+
+        assert_eq!(buffer, b);
+    }
+
+    #[test]
+    fn parse() {
+        let b = [0, 0, 0, 1, 0, 0, 0, 0, 0, 48];
+
+        let res = response::EndTxnResponse {
+            header: protocol::HeaderResponse { correlation_id: 1 },
+            throttle_time_ms: 0,
+            error_code: KafkaCode::InvalidTxnState,
+        };
+
+        let x = response::parse_end_txn_response(NomBytes::new(Bytes::copy_from_slice(&b)))
+            .unwrap()
+            .1;
+
+        assert_eq!(res, x);
+    }
+}
@@ -0,0 +1,94 @@
+//! Encoding and creation for EndTxn requests.
+//!
+//! A transactional producer ends its transaction by asking the transaction
+//! coordinator to commit or abort it. The coordinator then writes the
+//! transaction markers to every partition of the transaction.
+//!
+//! ### Example
+//! ```rust
+//! let end_txn = protocol::EndTxnRequest::new(
+//!     CORRELATION_ID,
+//!     CLIENT_ID,
+//!     transactional_id,
+//!     producer_id,
+//!     producer_epoch,
+//!     true,
+//! );
+//! coordinator_conn.send_request(&end_txn).await?;
+//! ```
+//!
+//! ### Protocol Def
+//! ```text
+//! EndTxn Request (Version: 0) => transactional_id producer_id producer_epoch committed
+//!   transactional_id => STRING
+//!   producer_id => INT64
+//!   producer_epoch => INT16
+//!   committed => BOOLEAN
+//! ```
+//!
+//! Note that we are using version 0 of this API.
+
+use bytes::BufMut;
+
+use crate::{encode::ToByte, error::Result, protocol::HeaderRequest};
+
+const API_KEY_END_TXN: i16 = 26;
+const API_VERSION: i16 = 0;
+
+/// The base EndTxn request object.
+///
+/// ### Example
+/// ```rust
+/// let end_txn = protocol::EndTxnRequest::new(
+///     CORRELATION_ID,
+///     CLIENT_ID,
+///     transactional_id,
+///     producer_id,
+///     producer_epoch,
+///     true,
+/// );
+/// coordinator_conn.send_request(&end_txn).await?;
+/// ```
+#[derive(Debug)]
+pub struct EndTxnRequest<'a> {
+    pub header: HeaderRequest<'a>,
+    /// The ID of the transaction to end.
+    pub transactional_id: &'a str,
+    /// The producer ID.
+    pub producer_id: i64,
+    /// The current epoch associated with the producer.
+    pub producer_epoch: i16,
+    /// True if the transaction was committed, false if it was aborted.
+    pub committed: bool,
+}
+
+impl<'a> EndTxnRequest<'a> {
+    pub fn new(
+        correlation_id: i32,
+        client_id: &'a str,
+        transactional_id: &'a str,
+        producer_id: i64,
+        producer_epoch: i16,
+        committed: bool,
+    ) -> Self {
+        Self {
+            header: HeaderRequest::new(API_KEY_END_TXN, API_VERSION, correlation_id, client_id),
+            transactional_id,
+            producer_id,
+            producer_epoch,
+            committed,
+        }
+    }
+}
+
+impl ToByte for EndTxnRequest<'_> {
+    fn encode<T: BufMut>(&self, buffer: &mut T) -> Result<()> {
+        tracing::trace!("Encoding EndTxnRequest {:?}", self);
+        self.header.encode(buffer)?;
+        self.transactional_id.encode(buffer)?;
+        self.producer_id.encode(buffer)?;
+        self.producer_epoch.encode(buffer)?;
+        self.committed.encode(buffer)?;
+        Ok(())
+    }
+}
@@ -13,15 +13,21 @@ mod test {
     use crate::{
         encode::ToByte,
         error::KafkaCode,
-        protocol::{produce::request::Attributes, HeaderResponse},
+        prelude::Compression,
+        protocol::{
+            produce::{self, request::RecordBatchAttributes},
+            HeaderResponse,
+        },
     };
 
     #[test]
     fn encode() {
         let b = [
-            0, 1, 0, 4, 0, 0, 0, 1, 0, 4, 114, 117, 115, 116, 255, 255, 255, 255, 0, 0, 7, 208, 0,
-            0, 0, 100, 0, 0, 117, 48, 0, 0, 0, 0, 1, 0, 9, 112, 117, 114, 99, 104, 97, 115, 101,
-            115, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 117, 48, 0, 0, 117, 48,
+            0, 1, 0, 11, 0, 0, 0, 1, 0, 4, 114, 117, 115, 116, 255, 255, 255, 255, 0, 0, 7, 208, 0,
+            0, 0, 100, 0, 0, 117, 48, 0, 0, 0, 0, 0, 255, 255, 255, 255, 0, 0, 0, 1, 0, 9, 112,
+            117, 114, 99, 104, 97, 115, 101, 115, 0, 0, 0, 1, 0, 0, 0, 1, 255, 255, 255, 255, 0, 0,
+            0, 0, 0, 0, 117, 48, 255, 255, 255, 255, 255, 255, 255, 255, 0, 0, 117, 48, 0, 0, 0, 0,
+            0, 3, 97, 122, 49,
         ];
 
         let correlation_id = 1;
@@ -46,6 +52,7 @@ mod test {
         // for partition in partitions {
         req.add(topic_name, partition, committed_offset, max_bytes);
         // }
+        req.rack_id = "az1";
 
         let mut buffer: Vec<u8> = vec![];
 
@@ -54,42 +61,66 @@ mod test {
         assert_eq!(buffer, b);
     }
 
+    #[test]
+    fn encode_by_topic_id() {
+        let topic_id = [7; 16];
+        let mut req = request::FetchRequest::new(1, "rust", 2000, 100, 30000, 0);
+        req.header.api_version = request::FIRST_TOPIC_ID_VERSION;
+        req.add_with_topic_id("purchases", topic_id, 1, 30000, 3, 30000);
+        req.rack_id = "az1";
+
+        let mut buffer: Vec<u8> = vec![];
+        req.encode(&mut buffer).unwrap();
+
+        let mut b = vec![
+            0, 1, 0, 13, 0, 0, 0, 1, 0, 4, 114, 117, 115, 116, 0, 255, 255, 255, 255, 0, 0, 7, 208,
+            0, 0, 0, 100, 0, 0, 117, 48, 0, 0, 0, 0, 0, 255, 255, 255, 255, 2,
+        ];
+        // the topic is sent by id, its name is left out
+        b.extend_from_slice(&topic_id);
+        b.extend_from_slice(&[
+            2, 0, 0, 0, 1, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 117, 48, 255, 255, 255, 255, 255, 255,
+            255, 255, 255, 255, 255, 255, 0, 0, 117, 48, 0, 0, 1, 4, 97, 122, 49, 0,
+        ]);
+        assert_eq!(buffer, b);
+    }
+
     #[test]
     fn parse() {
-        let b = b"\0\0\0\x01\0\0\0\0\0\0\0\x01\0\rprice-updates\0\0\0\x01\0\0\0\0\0\0\0\0\0\0\0\0\0\x0e\0\0\0\0\0\0\0\x0e\xff\xff\xff\xff\0\0\x0e\xde\0\0\0\0\0\0\0\0\0\0\x01\x04\0\0\0\x01\x02\xd7\x8d\xc7G\0\0\0\0\0\0\0\0\x01\x8bH \xef\xc0\0\0\x01\x8bH \xef\xc0\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\0\0\0\x01\xa2\x03\0\0\0\x08TSLA\x8c\x03{\"symbol\": \"TSLA\", \"timestamp\": 1697722200000, \"open\": 225.56, \"high\": 227.17, \"low\": 224.44, \"close\": 227.17, \"volume\": 24265.0, \"trade_count\": 502.0, \"vwap\": 225.508012, \"data_provider\": \"alpaca\"}\0\0\0\0\0\0\0\0\x01\0\0\x01\x07\0\0\0\x01\x02\x0e\xbd[\xd6\0\0\0\0\0\0\0\0\x01\x8bH!\xda \0\0\x01\x8bH!\xda \xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\0\0\0\x01\xa8\x03\0\0\0\x08TSLA\x92\x03{\"symbol\": \"TSLA\", \"timestamp\": 1697722260000, \"open\": 227.215, \"high\": 228.88, \"low\": 226.955, \"close\": 228.845, \"volume\": 28919.0, \"trade_count\": 303.0, \"vwap\": 227.811826, \"data_provider\": \"alpaca\"}\0\0\0\0\0\0\0\0\x02\0\0\x01\x06\0\0\0\x01\x02\x85\xc3\xb3\xb3\0\0\0\0\0\0\0\0\x01\x8bH\"\xc4\x80\0\0\x01\x8bH\"\xc4\x80\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\0\0\0\x01\xa6\x03\0\0\0\x08TSLA\x90\x03{\"symbol\": \"TSLA\", \"timestamp\": 1697722320000, \"open\": 229.12, \"high\": 230.17, \"low\": 227.915, \"close\": 230.165, \"volume\": 33891.0, \"trade_count\": 390.0, \"vwap\": 229.520416, \"data_provider\": \"alpaca\"}\0\0\0\0\0\0\0\0\x03\0\0\x01\x05\0\0\0\x01\x02\xea&\xce\x0f\0\0\0\0\0\0\0\0\x01\x8bH#\xae\xe0\0\0\x01\x8bH#\xae\xe0\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\0\0\0\x01\xa4\x03\0\0\0\x08TSLA\x8e\x03{\"symbol\": \"TSLA\", \"timestamp\": 1697722380000, \"open\": 230.21, \"high\": 230.525, \"low\": 229.13, \"close\": 229.22, \"volume\": 33625.0, \"trade_count\": 401.0, \"vwap\": 229.998015, \"data_provider\": \"alpaca\"}\0\0\0\0\0\0\0\0\x04\0\0\x01\x05\0\0\0\x01\x02s\x95\x0c\x8f\0\0\0\0\0\0\0\0\x01\x8bH$\x99@\0\0\x01\x8bH$\x99@\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\0\0\0\x01\xa4\x03\0\0\0\x08TSLA\x8e\x03{\"symbol\": \"TSLA\", \"timestamp\": 1697722440000, \"open\": 228.84, \"high\": 229.305, \"low\": 227.93, \"close\": 228.44, \"volume\": 26574.0, \"trade_count\": 362.0, \"vwap\": 228.548357, \"data_provider\": \"alpaca\"}\0\0\0\0\0\0\0\0\x05\0\0\x01\x04\0\0\0\x01\x029@Eu\0\0\0\0\0\0\0\0\x01\x8bH%\x83\xa0\0\0\x01\x8bH%\x83\xa0\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\0\0\0\x01\xa2\x03\0\0\0\x08TSLA\x8c\x03{\"symbol\": \"TSLA\", \"timestamp\": 1697722500000, \"open\": 228.53, \"high\": 229.22, \"low\": 228.3, \"close\": 228.995, \"volume\": 11997.0, \"trade_count\": 142.0, \"vwap\": 228.818005, \"data_provider\": \"alpaca\"}\0\0\0\0\0\0\0\0\x06\0\0\x01\x03\0\0\0\x01\x02\xf5k\x0c\x83\0\0\0\0\0\0\0\0\x01\x8bH&n\0\0\0\x01\x8bH&n\0\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\0\0\0\x01\xa0\x03\0\0\0\x08TSLA\x8a\x03{\"symbol\": \"TSLA\", \"timestamp\": 1697722560000, \"open\": 228.88, \"high\": 229.4, \"low\": 228.3, \"close\": 228.375, \"volume\": 17851.0, \"trade_count\": 259.0, \"vwap\": 228.727112, \"data_provider\": \"alpaca\"}\0\0\0\0\0\0\0\0\x07\0\0\x01\x05\0\0\0\x01\x02\x9bu\x82,\0\0\0\0\0\0\0\0\x01\x8bH'X`\0\0\x01\x8bH'X`\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\0\0\0\x01\xa4\x03\0\0\0\x08TSLA\x8e\x03{\"symbol\": \"TSLA\", \"timestamp\": 1697722620000, \"open\": 228.39, \"high\": 228.39, \"low\": 226.89, \"close\": 227.425, \"volume\": 12807.0, \"trade_count\": 254.0, \"vwap\": 227.514886, \"data_provider\": \"alpaca\"}\0\0\0\0\0\0\0\0\x08\0\0\x01\x03\0\0\0\x01\x02\xdcI\xc6\xc9\0\0\0\0\0\0\0\0\x01\x8bH(B\xc0\0\0\x01\x8bH(B\xc0\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\0\0\0\x01\xa0\x03\0\0\0\x08TSLA\x8a\x03{\"symbol\": \"TSLA\", \"timestamp\": 1697722680000, \"open\": 227.13, \"high\": 228.53, \"low\": 226.78, \"close\": 228.53, \"volume\": 7273.0, \"trade_count\": 123.0, \"vwap\": 227.633268, \"data_provider\": \"alpaca\"}\0\0\0\0\0\0\0\0\t\0\0\x01\x05\0\0\0\x01\x02\xf9\xd5\x0f\xd7\0\0\0\0\0\0\0\0\x01\x8bH+\xec@\0\0\x01\x8bH+\xec@\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\0\0\0\x01\xa4\x03\0\0\0\x08TSLA\x8e\x03{\"symbol\": \"TSLA\", \"timestamp\": 1697722920000, \"open\": 225.41, \"high\": 226.87, \"low\": 225.22, \"close\": 226.045, \"volume\": 10062.0, \"trade_count\": 159.0, \"vwap\": 226.119019, \"data_provider\": \"alpaca\"}\0\0\0\0\0\0\0\0\n\0\0\x01\x03\0\0\0\x01\x02KhN\x01\0\0\0\0\0\0\0\0\x01\x8bH,\xd6\xa0\0\0\x01\x8bH,\xd6\xa0\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\0\0\0\x01\xa0\x03\0\0\0\x08TSLA\x8a\x03{\"symbol\": \"TSLA\", \"timestamp\": 1697722980000, \"open\": 226.05, \"high\": 226.69, \"low\": 225.45, \"close\": 225.45, \"volume\": 7281.0, \"trade_count\": 129.0, \"vwap\": 225.980049, \"data_provider\": \"alpaca\"}\0\0\0\0\0\0\0\0\x0b\0\0\x01\x01\0\0\0\x01\x02\xe8\xd9yi\0\0\0\0\0\0\0\0\x01\x8bHI8@\0\0\x01\x8bHI8@\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\0\0\0\x01\x9c\x03\0\0\0\x08TSLA\x86\x03{\"symbol\": \"TSLA\", \"timestamp\": 1697724840000, \"open\": 225.89, \"high\": 226.0, \"low\": 225.46, \"close\": 225.47, \"volume\": 3886.0, \"trade_count\": 90.0, \"vwap\": 225.741834, \"data_provider\": \"alpaca\"}\0\0\0\0\0\0\0\0\x0c\0\0\x01\x01\0\0\0\x01\x02\xb2`\x9e\x15\0\0\0\0\0\0\0\0\x01\x8bHJ\"\xa0\0\0\x01\x8bHJ\"\xa0\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\0\0\0\x01\x9c\x03\0\0\0\x08TSLA\x86\x03{\"symbol\": \"TSLA\", \"timestamp\": 1697724900000, \"open\": 225.7, \"high\": 225.96, \"low\": 225.34, \"close\": 225.55, \"volume\": 3588.0, \"trade_count\": 74.0, \"vwap\": 225.642698, \"data_provider\": \"alpaca\"}\0\0\0\0\0\0\0\0\r\0\0\x01\x02\0\0\0\x01\x02\xb4\x02\xa4\x1c\0\0\0\0\0\0\0\0\x01\x8bHK\r\0\0\0\x01\x8bHK\r\0\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\0\0\0\x01\x9e\x03\0\0\0\x08TSLA\x88\x03{\"symbol\": \"TSLA\", \"timestamp\": 1697724960000, \"open\": 225.55, \"high\": 225.55, \"low\": 225.07, \"close\": 225.07, \"volume\": 1674.0, \"trade_count\": 38.0, \"vwap\": 225.256195, \"data_provider\": \"alpaca\"}\0";
+        let b = b"\0\0\0\x01\0\0\0\0\0\0\0\0\0\0\0\0\0\x01\0\rprice-updates\0\0\0\x01\0\0\0\0\0\0\0\0\0\0\0\0\0\x0e\0\0\0\0\0\0\0\x0e\0\0\0\0\0\0\0\0\xff\xff\xff\xff\xff\xff\xff\xff\0\0\x0e\xde\0\0\0\0\0\0\0\0\0\0\x01\x04\0\0\0\x01\x02\xd7\x8d\xc7G\0\0\0\0\0\0\0\0\x01\x8bH \xef\xc0\0\0\x01\x8bH \xef\xc0\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\0\0\0\x01\xa2\x03\0\0\0\x08TSLA\x8c\x03{\"symbol\": \"TSLA\", \"timestamp\": 1697722200000, \"open\": 225.56, \"high\": 227.17, \"low\": 224.44, \"close\": 227.17, \"volume\": 24265.0, \"trade_count\": 502.0, \"vwap\": 225.508012, \"data_provider\": \"alpaca\"}\0\0\0\0\0\0\0\0\x01\0\0\x01\x07\0\0\0\x01\x02\x0e\xbd[\xd6\0\0\0\0\0\0\0\0\x01\x8bH!\xda \0\0\x01\x8bH!\xda \xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\0\0\0\x01\xa8\x03\0\0\0\x08TSLA\x92\x03{\"symbol\": \"TSLA\", \"timestamp\": 1697722260000, \"open\": 227.215, \"high\": 228.88, \"low\": 226.955, \"close\": 228.845, \"volume\": 28919.0, \"trade_count\": 303.0, \"vwap\": 227.811826, \"data_provider\": \"alpaca\"}\0\0\0\0\0\0\0\0\x02\0\0\x01\x06\0\0\0\x01\x02\x85\xc3\xb3\xb3\0\0\0\0\0\0\0\0\x01\x8bH\"\xc4\x80\0\0\x01\x8bH\"\xc4\x80\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\0\0\0\x01\xa6\x03\0\0\0\x08TSLA\x90\x03{\"symbol\": \"TSLA\", \"timestamp\": 1697722320000, \"open\": 229.12, \"high\": 230.17, \"low\": 227.915, \"close\": 230.165, \"volume\": 33891.0, \"trade_count\": 390.0, \"vwap\": 229.520416, \"data_provider\": \"alpaca\"}\0\0\0\0\0\0\0\0\x03\0\0\x01\x05\0\0\0\x01\x02\xea&\xce\x0f\0\0\0\0\0\0\0\0\x01\x8bH#\xae\xe0\0\0\x01\x8bH#\xae\xe0\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\0\0\0\x01\xa4\x03\0\0\0\x08TSLA\x8e\x03{\"symbol\": \"TSLA\", \"timestamp\": 1697722380000, \"open\": 230.21, \"high\": 230.525, \"low\": 229.13, \"close\": 229.22, \"volume\": 33625.0, \"trade_count\": 401.0, \"vwap\": 229.998015, \"data_provider\": \"alpaca\"}\0\0\0\0\0\0\0\0\x04\0\0\x01\x05\0\0\0\x01\x02s\x95\x0c\x8f\0\0\0\0\0\0\0\0\x01\x8bH$\x99@\0\0\x01\x8bH$\x99@\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\0\0\0\x01\xa4\x03\0\0\0\x08TSLA\x8e\x03{\"symbol\": \"TSLA\", \"timestamp\": 1697722440000, \"open\": 228.84, \"high\": 229.305, \"low\": 227.93, \"close\": 228.44, \"volume\": 26574.0, \"trade_count\": 362.0, \"vwap\": 228.548357, \"data_provider\": \"alpaca\"}\0\0\0\0\0\0\0\0\x05\0\0\x01\x04\0\0\0\x01\x029@Eu\0\0\0\0\0\0\0\0\x01\x8bH%\x83\xa0\0\0\x01\x8bH%\x83\xa0\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\0\0\0\x01\xa2\x03\0\0\0\x08TSLA\x8c\x03{\"symbol\": \"TSLA\", \"timestamp\": 1697722500000, \"open\": 228.53, \"high\": 229.22, \"low\": 228.3, \"close\": 228.995, \"volume\": 11997.0, \"trade_count\": 142.0, \"vwap\": 228.818005, \"data_provider\": \"alpaca\"}\0\0\0\0\0\0\0\0\x06\0\0\x01\x03\0\0\0\x01\x02\xf5k\x0c\x83\0\0\0\0\0\0\0\0\x01\x8bH&n\0\0\0\x01\x8bH&n\0\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\0\0\0\x01\xa0\x03\0\0\0\x08TSLA\x8a\x03{\"symbol\": \"TSLA\", \"timestamp\": 1697722560000, \"open\": 228.88, \"high\": 229.4, \"low\": 228.3, \"close\": 228.375, \"volume\": 17851.0, \"trade_count\": 259.0, \"vwap\": 228.727112, \"data_provider\": \"alpaca\"}\0\0\0\0\0\0\0\0\x07\0\0\x01\x05\0\0\0\x01\x02\x9bu\x82,\0\0\0\0\0\0\0\0\x01\x8bH'X`\0\0\x01\x8bH'X`\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\0\0\0\x01\xa4\x03\0\0\0\x08TSLA\x8e\x03{\"symbol\": \"TSLA\", \"timestamp\": 1697722620000, \"open\": 228.39, \"high\": 228.39, \"low\": 226.89, \"close\": 227.425, \"volume\": 12807.0, \"trade_count\": 254.0, \"vwap\": 227.514886, \"data_provider\": \"alpaca\"}\0\0\0\0\0\0\0\0\x08\0\0\x01\x03\0\0\0\x01\x02\xdcI\xc6\xc9\0\0\0\0\0\0\0\0\x01\x8bH(B\xc0\0\0\x01\x8bH(B\xc0\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\0\0\0\x01\xa0\x03\0\0\0\x08TSLA\x8a\x03{\"symbol\": \"TSLA\", \"timestamp\": 1697722680000, \"open\": 227.13, \"high\": 228.53, \"low\": 226.78, \"close\": 228.53, \"volume\": 7273.0, \"trade_count\": 123.0, \"vwap\": 227.633268, \"data_provider\": \"alpaca\"}\0\0\0\0\0\0\0\0\t\0\0\x01\x05\0\0\0\x01\x02\xf9\xd5\x0f\xd7\0\0\0\0\0\0\0\0\x01\x8bH+\xec@\0\0\x01\x8bH+\xec@\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\0\0\0\x01\xa4\x03\0\0\0\x08TSLA\x8e\x03{\"symbol\": \"TSLA\", \"timestamp\": 1697722920000, \"open\": 225.41, \"high\": 226.87, \"low\": 225.22, \"close\": 226.045, \"volume\": 10062.0, \"trade_count\": 159.0, \"vwap\": 226.119019, \"data_provider\": \"alpaca\"}\0\0\0\0\0\0\0\0\n\0\0\x01\x03\0\0\0\x01\x02KhN\x01\0\0\0\0\0\0\0\0\x01\x8bH,\xd6\xa0\0\0\x01\x8bH,\xd6\xa0\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\0\0\0\x01\xa0\x03\0\0\0\x08TSLA\x8a\x03{\"symbol\": \"TSLA\", \"timestamp\": 1697722980000, \"open\": 226.05, \"high\": 226.69, \"low\": 225.45, \"close\": 225.45, \"volume\": 7281.0, \"trade_count\": 129.0, \"vwap\": 225.980049, \"data_provider\": \"alpaca\"}\0\0\0\0\0\0\0\0\x0b\0\0\x01\x01\0\0\0\x01\x02\xe8\xd9yi\0\0\0\0\0\0\0\0\x01\x8bHI8@\0\0\x01\x8bHI8@\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\0\0\0\x01\x9c\x03\0\0\0\x08TSLA\x86\x03{\"symbol\": \"TSLA\", \"timestamp\": 1697724840000, \"open\": 225.89, \"high\": 226.0, \"low\": 225.46, \"close\": 225.47, \"volume\": 3886.0, \"trade_count\": 90.0, \"vwap\": 225.741834, \"data_provider\": \"alpaca\"}\0\0\0\0\0\0\0\0\x0c\0\0\x01\x01\0\0\0\x01\x02\xb2`\x9e\x15\0\0\0\0\0\0\0\0\x01\x8bHJ\"\xa0\0\0\x01\x8bHJ\"\xa0\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\0\0\0\x01\x9c\x03\0\0\0\x08TSLA\x86\x03{\"symbol\": \"TSLA\", \"timestamp\": 1697724900000, \"open\": 225.7, \"high\": 225.96, \"low\": 225.34, \"close\": 225.55, \"volume\": 3588.0, \"trade_count\": 74.0, \"vwap\": 225.642698, \"data_provider\": \"alpaca\"}\0\0\0\0\0\0\0\0\r\0\0\x01\x02\0\0\0\x01\x02\xb4\x02\xa4\x1c\0\0\0\0\0\0\0\0\x01\x8bHK\r\0\0\0\x01\x8bHK\r\0\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\0\0\0\x01\x9e\x03\0\0\0\x08TSLA\x88\x03{\"symbol\": \"TSLA\", \"timestamp\": 1697724960000, \"open\": 225.55, \"high\": 225.55, \"low\": 225.07, \"close\": 225.07, \"volume\": 1674.0, \"trade_count\": 38.0, \"vwap\": 225.256195, \"data_provider\": \"alpaca\"}\0";
 
         let res = response::FetchResponse {
              header_response: HeaderResponse {
-             correlation_id: 1 }, trottle_time: 0, topics: vec![response::Topic {
-             name: Bytes::from_static(b"price-updates"), partitions: vec![response::Partition {
-             id: 0, error_code: KafkaCode::None, high_water_mark: 14, last_stable_offset: 14, aborted_transactions: vec![], record_batch: vec![response::RecordBatch {
-             base_offset: 0, batch_length: 260, partition_leader_epoch: 1, magic: 2, crc: -678574265, attributes: Attributes { compression: None }, last_offset_delta: 0, base_timestamp: 1697722200000, max_timestamp: 1697722200000, producer_id: -1, producer_epoch: -1, base_sequence: -1, records: vec![response::Record {
+             correlation_id: 1 }, trottle_time: 0, error_code: KafkaCode::None, session_id: 0, topics: vec![response::Topic {
+             name: Bytes::from_static(b"price-updates"), topic_id: [0; 16], partitions: vec![response::Partition {
+             id: 0, error_code: KafkaCode::None, high_water_mark: 14, last_stable_offset: 14, log_start_offset: 0, aborted_transactions: vec![], preferred_read_replica: -1, record_batch: vec![response::RecordBatch {
+             base_offset: 0, batch_length: 260, partition_leader_epoch: 1, magic: 2, crc: -678574265, attributes: RecordBatchAttributes::new(None), last_offset_delta: 0, base_timestamp: 1697722200000, max_timestamp: 1697722200000, producer_id: -1, producer_epoch: -1, base_sequence: -1, compressed_records: None, records: vec![response::Record {
              length: 418, attributes: 0, timestamp_delta: 0, offset_delta: 0, key_length: 8, key: Bytes::from_static(b"TSLA"), value_len: 396, value: Bytes::from_static(b"{\"symbol\": \"TSLA\", \"timestamp\": 1697722200000, \"open\": 225.56, \"high\": 227.17, \"low\": 224.44, \"close\": 227.17, \"volume\": 24265.0, \"trade_count\": 502.0, \"vwap\": 225.508012, \"data_provider\": \"alpaca\"}"), headers: vec![] }] }, response::RecordBatch {
-             base_offset: 1, batch_length: 263, partition_leader_epoch: 1, magic: 2, crc: 247290838, attributes: Attributes { compression: None }, last_offset_delta: 0, base_timestamp: 1697722260000, max_timestamp: 1697722260000, producer_id: -1, producer_epoch: -1, base_sequence: -1, records: vec![response::Record {
+             base_offset: 1, batch_length: 263, partition_leader_epoch: 1, magic: 2, crc: 247290838, attributes: RecordBatchAttributes::new(None), last_offset_delta: 0, base_timestamp: 1697722260000, max_timestamp: 1697722260000, producer_id: -1, producer_epoch: -1, base_sequence: -1, compressed_records: None, records: vec![response::Record {
              length: 424, attributes: 0, timestamp_delta: 0, offset_delta: 0, key_length: 8, key: Bytes::from_static(b"TSLA"), value_len: 402, value: Bytes::from_static(b"{\"symbol\": \"TSLA\", \"timestamp\": 1697722260000, \"open\": 227.215, \"high\": 228.88, \"low\": 226.955, \"close\": 228.845, \"volume\": 28919.0, \"trade_count\": 303.0, \"vwap\": 227.811826, \"data_provider\": \"alpaca\"}"), headers: vec![] }] }, response::RecordBatch {
-             base_offset: 2, batch_length: 262, partition_leader_epoch: 1, magic: 2, crc: -2050772045, attributes: Attributes { compression: None }, last_offset_delta: 0, base_timestamp: 1697722320000, max_timestamp: 1697722320000, producer_id: -1, producer_epoch: -1, base_sequence: -1, records: vec![response::Record {
+             base_offset: 2, batch_length: 262, partition_leader_epoch: 1, magic: 2, crc: -2050772045, attributes: RecordBatchAttributes::new(None), last_offset_delta: 0, base_timestamp: 1697722320000, max_timestamp: 1697722320000, producer_id: -1, producer_epoch: -1, base_sequence: -1, compressed_records: None, records: vec![response::Record {
              length: 422, attributes: 0, timestamp_delta: 0, offset_delta: 0, key_length: 8, key: Bytes::from_static(b"TSLA"), value_len: 400, value: Bytes::from_static(b"{\"symbol\": \"TSLA\", \"timestamp\": 1697722320000, \"open\": 229.12, \"high\": 230.17, \"low\": 227.915, \"close\": 230.165, \"volume\": 33891.0, \"trade_count\": 390.0, \"vwap\": 229.520416, \"data_provider\": \"alpaca\"}"), headers: vec![] }] }, response::RecordBatch {
-             base_offset: 3, batch_length: 261, partition_leader_epoch: 1, magic: 2, crc: -366555633, attributes: Attributes { compression: None }, last_offset_delta: 0, base_timestamp: 1697722380000, max_timestamp: 1697722380000, producer_id: -1, producer_epoch: -1, base_sequence: -1, records: vec![response::Record {
+             base_offset: 3, batch_length: 261, partition_leader_epoch: 1, magic: 2, crc: -366555633, attributes: RecordBatchAttributes::new(None), last_offset_delta: 0, base_timestamp: 1697722380000, max_timestamp: 1697722380000, producer_id: -1, producer_epoch: -1, base_sequence: -1, compressed_records: None, records: vec![response::Record {
              length: 420, attributes: 0, timestamp_delta: 0, offset_delta: 0, key_length: 8, key: Bytes::from_static(b"TSLA"), value_len: 398, value: Bytes::from_static(b"{\"symbol\": \"TSLA\", \"timestamp\": 1697722380000, \"open\": 230.21, \"high\": 230.525, \"low\": 229.13, \"close\": 229.22, \"volume\": 33625.0, \"trade_count\": 401.0, \"vwap\": 229.998015, \"data_provider\": \"alpaca\"}"), headers: vec![] }] }, response::RecordBatch {
-             base_offset: 4, batch_length: 261, partition_leader_epoch: 1, magic: 2, crc: 1939147919, attributes: Attributes { compression: None }, last_offset_delta: 0, base_timestamp: 1697722440000, max_timestamp: 1697722440000, producer_id: -1, producer_epoch: -1, base_sequence: -1, records: vec![response::Record {
+             base_offset: 4, batch_length: 261, partition_leader_epoch: 1, magic: 2, crc: 1939147919, attributes: RecordBatchAttributes::new(None), last_offset_delta: 0, base_timestamp: 1697722440000, max_timestamp: 1697722440000, producer_id: -1, producer_epoch: -1, base_sequence: -1, compressed_records: None, records: vec![response::Record {
              length: 420, attributes: 0, timestamp_delta: 0, offset_delta: 0, key_length: 8, key: Bytes::from_static(b"TSLA"), value_len: 398, value: Bytes::from_static(b"{\"symbol\": \"TSLA\", \"timestamp\": 1697722440000, \"open\": 228.84, \"high\": 229.305, \"low\": 227.93, \"close\": 228.44, \"volume\": 26574.0, \"trade_count\": 362.0, \"vwap\": 228.548357, \"data_provider\": \"alpaca\"}"), headers: vec![] }] }, response::RecordBatch {
-             base_offset: 5, batch_length: 260, partition_leader_epoch: 1, magic: 2, crc: 960513397, attributes: Attributes { compression: None }, last_offset_delta: 0, base_timestamp: 1697722500000, max_timestamp: 1697722500000, producer_id: -1, producer_epoch: -1, base_sequence: -1, records: vec![response::Record {
+             base_offset: 5, batch_length: 260, partition_leader_epoch: 1, magic: 2, crc: 960513397, attributes: RecordBatchAttributes::new(None), last_offset_delta: 0, base_timestamp: 1697722500000, max_timestamp: 1697722500000, producer_id: -1, producer_epoch: -1, base_sequence: -1, compressed_records: None, records: vec![response::Record {
              length: 418, attributes: 0, timestamp_delta: 0, offset_delta: 0, key_length: 8, key: Bytes::from_static(b"TSLA"), value_len: 396, value: Bytes::from_static(b"{\"symbol\": \"TSLA\", \"timestamp\": 1697722500000, \"open\": 228.53, \"high\": 229.22, \"low\": 228.3, \"close\": 228.995, \"volume\": 11997.0, \"trade_count\": 142.0, \"vwap\": 228.818005, \"data_provider\": \"alpaca\"}"), headers: vec![] }] }, response::RecordBatch {
-             base_offset: 6, batch_length: 259, partition_leader_epoch: 1, magic: 2, crc: -177533821, attributes: Attributes { compression: None }, last_offset_delta: 0, base_timestamp: 1697722560000, max_timestamp: 1697722560000, producer_id: -1, producer_epoch: -1, base_sequence: -1, records: vec![response::Record {
+             base_offset: 6, batch_length: 259, partition_leader_epoch: 1, magic: 2, crc: -177533821, attributes: RecordBatchAttributes::new(None), last_offset_delta: 0, base_timestamp: 1697722560000, max_timestamp: 1697722560000, producer_id: -1, producer_epoch: -1, base_sequence: -1, compressed_records: None, records: vec![response::Record {
              length: 416, attributes: 0, timestamp_delta: 0, offset_delta: 0, key_length: 8, key: Bytes::from_static(b"TSLA"), value_len: 394, value: Bytes::from_static(b"{\"symbol\": \"TSLA\", \"timestamp\": 1697722560000, \"open\": 228.88, \"high\": 229.4, \"low\": 228.3, \"close\": 228.375, \"volume\": 17851.0, \"trade_count\": 259.0, \"vwap\": 228.727112, \"data_provider\": \"alpaca\"}"), headers: vec![] }] }, response::RecordBatch {
-             base_offset: 7, batch_length: 261, partition_leader_epoch: 1, magic: 2, crc: -1686797780, attributes: Attributes { compression: None }, last_offset_delta: 0, base_timestamp: 1697722620000, max_timestamp: 1697722620000, producer_id: -1, producer_epoch: -1, base_sequence: -1, records: vec![response::Record {
+             base_offset: 7, batch_length: 261, partition_leader_epoch: 1, magic: 2, crc: -1686797780, attributes: RecordBatchAttributes::new(None), last_offset_delta: 0, base_timestamp: 1697722620000, max_timestamp: 1697722620000, producer_id: -1, producer_epoch: -1, base_sequence: -1, compressed_records: None, records: vec![response::Record {
              length: 420, attributes: 0, timestamp_delta: 0, offset_delta: 0, key_length: 8, key: Bytes::from_static(b"TSLA"), value_len: 398, value: Bytes::from_static(b"{\"symbol\": \"TSLA\", \"timestamp\": 1697722620000, \"open\": 228.39, \"high\": 228.39, \"low\": 226.89, \"close\": 227.425, \"volume\": 12807.0, \"trade_count\": 254.0, \"vwap\": 227.514886, \"data_provider\": \"alpaca\"}"), headers: vec![] }] }, response::RecordBatch {
-             base_offset: 8, batch_length: 259, partition_leader_epoch: 1, magic: 2, crc: -599144759, attributes: Attributes { compression: None }, last_offset_delta: 0, base_timestamp: 1697722680000, max_timestamp: 1697722680000, producer_id: -1, producer_epoch: -1, base_sequence: -1, records: vec![response::Record {
+             base_offset: 8, batch_length: 259, partition_leader_epoch: 1, magic: 2, crc: -599144759, attributes: RecordBatchAttributes::new(None), last_offset_delta: 0, base_timestamp: 1697722680000, max_timestamp: 1697722680000, producer_id: -1, producer_epoch: -1, base_sequence: -1, compressed_records: None, records: vec![response::Record {
              length: 416, attributes: 0, timestamp_delta: 0, offset_delta: 0, key_length: 8, key: Bytes::from_static(b"TSLA"), value_len: 394, value: Bytes::from_static(b"{\"symbol\": \"TSLA\", \"timestamp\": 1697722680000, \"open\": 227.13, \"high\": 228.53, \"low\": 226.78, \"close\": 228.53, \"volume\": 7273.0, \"trade_count\": 123.0, \"vwap\": 227.633268, \"data_provider\": \"alpaca\"}"), headers: vec![] }] }, response::RecordBatch {
-             base_offset: 9, batch_length: 261, partition_leader_epoch: 1, magic: 2, crc: -103477289, attributes: Attributes { compression: None }, last_offset_delta: 0, base_timestamp: 1697722920000, max_timestamp: 1697722920000, producer_id: -1, producer_epoch: -1, base_sequence: -1, records: vec![response::Record {
+             base_offset: 9, batch_length: 261, partition_leader_epoch: 1, magic: 2, crc: -103477289, attributes: RecordBatchAttributes::new(None), last_offset_delta: 0, base_timestamp: 1697722920000, max_timestamp: 1697722920000, producer_id: -1, producer_epoch: -1, base_sequence: -1, compressed_records: None, records: vec![response::Record {
              length: 420, attributes: 0, timestamp_delta: 0, offset_delta: 0, key_length: 8, key: Bytes::from_static(b"TSLA"), value_len: 398, value: Bytes::from_static(b"{\"symbol\": \"TSLA\", \"timestamp\": 1697722920000, \"open\": 225.41, \"high\": 226.87, \"low\": 225.22, \"close\": 226.045, \"volume\": 10062.0, \"trade_count\": 159.0, \"vwap\": 226.119019, \"data_provider\": \"alpaca\"}"), headers: vec![] }] }, response::RecordBatch {
-             base_offset: 10, batch_length: 259, partition_leader_epoch: 1, magic: 2, crc: 1265126913, attributes: Attributes { compression: None }, last_offset_delta: 0, base_timestamp: 1697722980000, max_timestamp: 1697722980000, producer_id: -1, producer_epoch: -1, base_sequence: -1, records: vec![response::Record {
+             base_offset: 10, batch_length: 259, partition_leader_epoch: 1, magic: 2, crc: 1265126913, attributes: RecordBatchAttributes::new(None), last_offset_delta: 0, base_timestamp: 1697722980000, max_timestamp: 1697722980000, producer_id: -1, producer_epoch: -1, base_sequence: -1, compressed_records: None, records: vec![response::Record {
              length: 416, attributes: 0, timestamp_delta: 0, offset_delta: 0, key_length: 8, key: Bytes::from_static(b"TSLA"), value_len: 394, value: Bytes::from_static(b"{\"symbol\": \"TSLA\", \"timestamp\": 1697722980000, \"open\": 226.05, \"high\": 226.69, \"low\": 225.45, \"close\": 225.45, \"volume\": 7281.0, \"trade_count\": 129.0, \"vwap\": 225.980049, \"data_provider\": \"alpaca\"}"), headers: vec![] }] }, response::RecordBatch {
-             base_offset: 11, batch_length: 257, partition_leader_epoch: 1, magic: 2, crc: -388400791, attributes: Attributes { compression: None }, last_offset_delta: 0, base_timestamp: 1697724840000, max_timestamp: 1697724840000, producer_id: -1, producer_epoch: -1, base_sequence: -1, records: vec![response::Record {
+             base_offset: 11, batch_length: 257, partition_leader_epoch: 1, magic: 2, crc: -388400791, attributes: RecordBatchAttributes::new(None), last_offset_delta: 0, base_timestamp: 1697724840000, max_timestamp: 1697724840000, producer_id: -1, producer_epoch: -1, base_sequence: -1, compressed_records: None, records: vec![response::Record {
              length: 412, attributes: 0, timestamp_delta: 0, offset_delta: 0, key_length: 8, key: Bytes::from_static(b"TSLA"), value_len: 390, value: Bytes::from_static(b"{\"symbol\": \"TSLA\", \"timestamp\": 1697724840000, \"open\": 225.89, \"high\": 226.0, \"low\": 225.46, \"close\": 225.47, \"volume\": 3886.0, \"trade_count\": 90.0, \"vwap\": 225.741834, \"data_provider\": \"alpaca\"}"), headers: vec![] }] }, response::RecordBatch {
-             base_offset: 12, batch_length: 257, partition_leader_epoch: 1, magic: 2, crc: -1302290923, attributes: Attributes { compression: None }, last_offset_delta: 0, base_timestamp: 1697724900000, max_timestamp: 1697724900000, producer_id: -1, producer_epoch: -1, base_sequence: -1, records: vec![response::Record {
+             base_offset: 12, batch_length: 257, partition_leader_epoch: 1, magic: 2, crc: -1302290923, attributes: RecordBatchAttributes::new(None), last_offset_delta: 0, base_timestamp: 1697724900000, max_timestamp: 1697724900000, producer_id: -1, producer_epoch: -1, base_sequence: -1, compressed_records: None, records: vec![response::Record {
              length: 412, attributes: 0, timestamp_delta: 0, offset_delta: 0, key_length: 8, key: Bytes::from_static(b"TSLA"), value_len: 390, value: Bytes::from_static(b"{\"symbol\": \"TSLA\", \"timestamp\": 1697724900000, \"open\": 225.7, \"high\": 225.96, \"low\": 225.34, \"close\": 225.55, \"volume\": 3588.0, \"trade_count\": 74.0, \"vwap\": 225.642698, \"data_provider\": \"alpaca\"}"), headers: vec![] }] }, response::RecordBatch {
-             base_offset: 13, batch_length: 258, partition_leader_epoch: 1, magic: 2, crc: -1274895332, attributes: Attributes { compression: None }, last_offset_delta: 0, base_timestamp: 1697724960000, max_timestamp: 1697724960000, producer_id: -1, producer_epoch: -1, base_sequence: -1, records: vec![response::Record {
+             base_offset: 13, batch_length: 258, partition_leader_epoch: 1, magic: 2, crc: -1274895332, attributes: RecordBatchAttributes::new(None), last_offset_delta: 0, base_timestamp: 1697724960000, max_timestamp: 1697724960000, producer_id: -1, producer_epoch: -1, base_sequence: -1, compressed_records: None, records: vec![response::Record {
              length: 414, attributes: 0, timestamp_delta: 0, offset_delta: 0, key_length: 8, key: Bytes::from_static(b"TSLA"), value_len: 392, value: Bytes::from_static(b"{\"symbol\": \"TSLA\", \"timestamp\": 1697724960000, \"open\": 225.55, \"high\": 225.55, \"low\": 225.07, \"close\": 225.07, \"volume\": 1674.0, \"trade_count\": 38.0, \"vwap\": 225.256195, \"data_provider\": \"alpaca\"}"), headers: vec![] }] }] }] }] };
 
         let x = response::parse_fetch_response(NomBytes::new(Bytes::from_static(b)))
@@ -99,6 +130,131 @@ mod test {
         assert_eq!(res, x);
     }
 
+    #[test]
+    fn parse_aborted_transactions() {
+        let b = [
+            0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 9, 112, 117, 114, 99, 104, 97,
+            115, 101, 115, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 5, 0, 0, 0, 0, 0, 0,
+            0, 5, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 42, 0, 0, 0, 0, 0, 0, 0,
+            2, 255, 255, 255, 255, 0, 0, 0, 0,
+        ];
+
+        let x = response::parse_fetch_response(NomBytes::new(Bytes::copy_from_slice(&b)))
+            .unwrap()
+            .1;
+
+        let partition = &x.topics[0].partitions[0];
+        assert_eq!(
+            partition.aborted_transactions,
+            vec![response::AbortedTransactions {
+                producer_id: 42,
+                first_offset: 2
+            }]
+        );
+        assert_eq!(partition.aborted_transaction_offsets(), vec![(42, 2)]);
+        assert_eq!(partition.record_count(), 0);
+    }
+
+    #[test]
+    fn parse_flexible() {
+        let mut record_batch = produce::request::RecordBatch::new(RecordBatchAttributes::new(None));
+        record_batch.push(produce::request::Record::new(
+            produce::request::Message {
+                key: None,
+                value: Some(Bytes::from("value")),
+                headers: vec![],
+            },
+            0,
+            0,
+        ));
+        let mut records = vec![];
+        record_batch.encode(&mut records).unwrap();
+
+        let mut b = vec![
+            0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 10, 112, 117, 114, 99, 104, 97, 115,
+            101, 115, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0,
+            0, 0, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 42, 0, 0, 0, 0, 0, 0, 0, 0, 0, 255, 255, 255,
+            255,
+        ];
+        b.push(records.len() as u8 + 1);
+        b.extend_from_slice(&records);
+        b.extend_from_slice(&[0, 0, 0]);
+
+        let x = response::FetchResponse::try_from_version(Bytes::from(b), 12).unwrap();
+
+        let partition = &x.topics[0].partitions[0];
+        assert_eq!(x.topics[0].name, Bytes::from_static(b"purchases"));
+        assert_eq!(partition.high_water_mark, 1);
+        assert_eq!(partition.aborted_transaction_offsets(), vec![(42, 0)]);
+        assert_eq!(partition.record_count(), 1);
+        assert_eq!(
+            partition.record_batch[0].records[0].value,
+            Bytes::from_static(b"value")
+        );
+    }
+
+    #[test]
+    fn parse_without_copying_values() {
+        let large_value = Bytes::from(vec![7; 1024 * 1024]);
+        let mut record_batch = produce::request::RecordBatch::new(RecordBatchAttributes::new(None));
+        record_batch.add(produce::request::Message {
+            key: Some(Bytes::from("key")),
+            value: Some(large_value.clone()),
+            headers: vec![],
+        });
+        let mut buf = vec![];
+        record_batch._encode_to_buf(&mut buf).unwrap();
+        let buf = Bytes::from(buf);
+
+        let (_, batch) =
+            response::parse_record_batch_with(NomBytes::new(buf.clone()), false).unwrap();
+        let record = batch.into_records().next().unwrap();
+
+        assert_eq!(record.key(), Some(Bytes::from("key")));
+        let value = record.value().unwrap();
+        assert_eq!(value, large_value);
+        // the value points into the buffer it was parsed from
+        let buf_range = buf.as_ptr_range();
+        assert!(buf_range.contains(&value.as_ptr()));
+        assert!(value.as_ptr_range().end <= buf_range.end);
+
+        // a length of -1 marks a null value
+        let tombstone = response::Record {
+            value_len: 1,
+            value: Bytes::new(),
+            ..record
+        };
+        assert_eq!(tombstone.value(), None);
+    }
+
+    #[test]
+    fn parse_lazy_gzip_batch() {
+        let mut record_batch =
+            produce::request::RecordBatch::new(RecordBatchAttributes::new(Some(Compression::Gzip)));
+        for i in 0..10_000 {
+            record_batch.add(produce::request::Message {
+                key: None,
+                value: Some(Bytes::from(format!("{i:0100}"))),
+                headers: vec![],
+            });
+        }
+        let mut buf = vec![];
+        record_batch._encode_to_buf(&mut buf).unwrap();
+
+        let (_, batch) =
+            response::parse_record_batch_with(NomBytes::new(Bytes::from(buf)), true).unwrap();
+        assert!(batch.records.is_empty());
+        assert_eq!(batch.record_count(), 10_000);
+
+        let mut records = batch.into_records();
+        let first = records.next().unwrap();
+        assert_eq!(first.value, Bytes::from(format!("{:0100}", 0)));
+        // only the first record has been decompressed, not the whole ~1MB batch
+        assert!(records.decompressed_bytes() < 1024);
+
+        assert_eq!(records.count(), 9_999);
+    }
+
     #[test]
     fn add_to_req() {
         let correlation_id = 1;
@@ -2,28 +2,69 @@
 //!
 //! ### Protocol Def
 //! ```text
-//! Fetch Request (Version: 4) => replica_id max_wait_ms min_bytes max_bytes isolation_level [topics]
+//! Fetch Request (Version: 11) => replica_id max_wait_ms min_bytes max_bytes isolation_level session_id session_epoch [topics] [forgotten_topics_data] rack_id
 //!   replica_id => INT32
 //!   max_wait_ms => INT32
 //!   min_bytes => INT32
 //!   max_bytes => INT32
 //!   isolation_level => INT8
+//!   session_id => INT32
+//!   session_epoch => INT32
 //!   topics => topic [partitions]
 //!     topic => STRING
-//!     partitions => partition fetch_offset partition_max_bytes
+//!     partitions => partition current_leader_epoch fetch_offset log_start_offset partition_max_bytes
 //!       partition => INT32
+//!       current_leader_epoch => INT32
 //!       fetch_offset => INT64
+//!       log_start_offset => INT64
 //!       partition_max_bytes => INT32
+//!   forgotten_topics_data => topic [partitions]
+//!     topic => STRING
+//!     partitions => INT32
+//!   rack_id => STRING
+//!
+//! Fetch Request (Version: 13) => replica_id max_wait_ms min_bytes max_bytes isolation_level session_id session_epoch [topics] [forgotten_topics_data] rack_id TAG_BUFFER
+//!   replica_id => INT32
+//!   max_wait_ms => INT32
+//!   min_bytes => INT32
+//!   max_bytes => INT32
+//!   isolation_level => INT8
+//!   session_id => INT32
+//!   session_epoch => INT32
+//!   topics => topic_id [partitions] TAG_BUFFER
+//!     topic_id => UUID
+//!     partitions => partition current_leader_epoch fetch_offset last_fetched_epoch log_start_offset partition_max_bytes TAG_BUFFER
+//!       partition => INT32
+//!       current_leader_epoch => INT32
+//!       fetch_offset => INT64
+//!       last_fetched_epoch => INT32
+//!       log_start_offset => INT64
+//!       partition_max_bytes => INT32
+//!   forgotten_topics_data => topic_id [partitions] TAG_BUFFER
+//!     topic_id => UUID
+//!     partitions => INT32
+//!   rack_id => COMPACT_STRING
 //! ```
 //!
-//! Note we are using version 4 of the request.
+//! Note we are using version 11 of the request by default. Version 12 is
+//! flexible and version 13 identifies topics by their id instead of their
+//! name, see [`FIRST_TOPIC_ID_VERSION`].
 
 use bytes::BufMut;
 
-use crate::{encode::ToByte, error::Result, protocol::HeaderRequest};
+use crate::{
+    encode::{
+        encode_as_compact_array, CompactArray, CompactString, RawBytes, TaggedFields, ToByte,
+    },
+    error::Result,
+    protocol::{fetch::response::FIRST_FLEXIBLE_VERSION, Describe, HeaderRequest},
+};
 
-const API_KEY_FETCH: i16 = 1;
-const API_VERSION: i16 = 4;
+pub const API_KEY_FETCH: i16 = 1;
+/// The version of the request sent unless another one is negotiated.
+pub const API_VERSION: i16 = 11;
+/// The first version identifying topics by their id instead of their name.
+pub const FIRST_TOPIC_ID_VERSION: i16 = 13;
 
 #[derive(Debug, Clone)]
 pub struct FetchRequest<'a> {
@@ -38,8 +79,16 @@ pub struct FetchRequest<'a> {
     pub max_bytes: i32,
     /// This setting controls the visibility of transactional records. Using READ_UNCOMMITTED (isolation_level = 0) makes all records visible. With READ_COMMITTED (isolation_level = 1), non-transactional and COMMITTED transactional records are visible. To be more concrete, READ_COMMITTED returns all data from offsets smaller than the current LSO (last stable offset), and enables the inclusion of the list of aborted transactions in the result, which allows consumers to discard ABORTED transactional records
     pub isolation_level: i8,
+    /// The fetch session ID, 0 when not using fetch sessions.
+    pub session_id: i32,
+    /// The fetch session epoch, -1 when not using fetch sessions.
+    pub session_epoch: i32,
     /// The topics to fetch.
     pub topics: Vec<TopicPartition<'a>>,
+    /// In an incremental fetch request, the partitions to remove.
+    pub forgotten_topics: Vec<ForgottenTopic<'a>>,
+    /// Rack ID of the consumer making this request, used by the broker to pick a replica close to it.
+    pub rack_id: &'a str,
 }
 
 /// The topics to fetch.
@@ -47,6 +96,8 @@ pub struct FetchRequest<'a> {
 pub struct TopicPartition<'a> {
     /// The name of the topic to fetch.
     pub topic_name: &'a str,
+    /// The id of the topic to fetch, sent instead of the name from version 13.
+    pub topic_id: [u8; 16],
     /// The partitions to fetch.
     pub partitions: Vec<Partition>,
 }
@@ -56,12 +107,29 @@ pub struct TopicPartition<'a> {
 pub struct Partition {
     /// The partition index.
     pub partition_index: i32,
+    /// The current leader epoch of the partition, -1 if unknown.
+    pub current_leader_epoch: i32,
     /// The message offset.
     pub offset: i64,
+    /// The epoch of the last fetched record, -1 if unknown. Sent from version 12.
+    pub last_fetched_epoch: i32,
+    /// The earliest available offset of the follower replica, -1 for consumers.
+    pub log_start_offset: i64,
     /// The maximum bytes to fetch from this partition. See KIP-74 for cases where this limit may not be honored.
     pub max_bytes: i32,
 }
 
+/// The topics to remove from an incremental fetch session.
+#[derive(Debug, Clone)]
+pub struct ForgottenTopic<'a> {
+    /// The name of the topic.
+    pub topic_name: &'a str,
+    /// The id of the topic, sent instead of the name from version 13.
+    pub topic_id: [u8; 16],
+    /// The partitions indexes to forget.
+    pub partitions: Vec<i32>,
+}
+
 impl<'a> FetchRequest<'a> {
     pub fn new(
         correlation_id: i32,
@@ -78,11 +146,49 @@ impl<'a> FetchRequest<'a> {
             min_bytes,
             max_bytes,
             isolation_level,
+            session_id: 0,
+            session_epoch: -1,
             topics: vec![],
+            forgotten_topics: vec![],
+            rack_id: "",
         }
     }
 
     pub fn add(&mut self, topic_name: &'a str, partition_index: i32, offset: i64, max_bytes: i32) {
+        self.add_with_leader_epoch(topic_name, partition_index, offset, -1, max_bytes);
+    }
+
+    /// Add a partition together with the leader epoch the client knows for
+    /// it, so a fetch from a deposed leader is fenced instead of served.
+    pub fn add_with_leader_epoch(
+        &mut self,
+        topic_name: &'a str,
+        partition_index: i32,
+        offset: i64,
+        current_leader_epoch: i32,
+        max_bytes: i32,
+    ) {
+        self.add_with_topic_id(
+            topic_name,
+            [0; 16],
+            partition_index,
+            offset,
+            current_leader_epoch,
+            max_bytes,
+        );
+    }
+
+    /// Add a partition of a topic known by its id, as needed by requests
+    /// from version [`FIRST_TOPIC_ID_VERSION`] on.
+    pub fn add_with_topic_id(
+        &mut self,
+        topic_name: &'a str,
+        topic_id: [u8; 16],
+        partition_index: i32,
+        offset: i64,
+        current_leader_epoch: i32,
+        max_bytes: i32,
+    ) {
         match self
             .topics
             .iter_mut()
@@ -90,9 +196,13 @@ impl<'a> FetchRequest<'a> {
         {
             None => self.topics.push(TopicPartition {
                 topic_name,
+                topic_id,
                 partitions: vec![Partition {
                     partition_index,
+                    current_leader_epoch,
                     offset,
+                    last_fetched_epoch: -1,
+                    log_start_offset: -1,
                     max_bytes,
                 }],
             }),
@@ -104,29 +214,102 @@ impl<'a> FetchRequest<'a> {
                 {
                     topic.partitions.push(Partition {
                         partition_index,
+                        current_leader_epoch,
                         offset,
+                        last_fetched_epoch: -1,
+                        log_start_offset: -1,
                         max_bytes,
                     })
                 }
             }
         }
     }
+
+    /// Remove a partition from the fetch session, sent in the
+    /// `forgotten_topics_data` of an incremental fetch.
+    pub fn forget(&mut self, topic_name: &'a str, topic_id: [u8; 16], partition_index: i32) {
+        match self
+            .forgotten_topics
+            .iter_mut()
+            .find(|topic| topic.topic_name == topic_name)
+        {
+            None => self.forgotten_topics.push(ForgottenTopic {
+                topic_name,
+                topic_id,
+                partitions: vec![partition_index],
+            }),
+            Some(topic) => {
+                if !topic.partitions.contains(&partition_index) {
+                    topic.partitions.push(partition_index);
+                }
+            }
+        }
+    }
 }
 
 impl ToByte for FetchRequest<'_> {
     fn encode<W: BufMut>(&self, buffer: &mut W) -> Result<()> {
         tracing::trace!("Encoding FetchRequest {:?}", self);
-        self.header.encode(buffer)?;
+        let api_version = self.header.api_version;
+        let flexible = api_version >= FIRST_FLEXIBLE_VERSION;
+        if flexible {
+            self.header.encode_flexible(buffer)?;
+        } else {
+            self.header.encode(buffer)?;
+        }
         self.replica.encode(buffer)?;
         self.max_wait_ms.encode(buffer)?;
         self.min_bytes.encode(buffer)?;
         self.max_bytes.encode(buffer)?;
         self.isolation_level.encode(buffer)?;
-        self.topics.encode(buffer)?;
+        self.session_id.encode(buffer)?;
+        self.session_epoch.encode(buffer)?;
+        if !flexible {
+            self.topics.encode(buffer)?;
+            self.forgotten_topics.encode(buffer)?;
+            self.rack_id.encode(buffer)?;
+            return Ok(());
+        }
+
+        encode_as_compact_array(buffer, &self.topics, |buffer, topic| {
+            encode_topic(buffer, api_version, topic.topic_name, &topic.topic_id)?;
+            encode_as_compact_array(buffer, &topic.partitions, |buffer, partition| {
+                partition.partition_index.encode(buffer)?;
+                partition.current_leader_epoch.encode(buffer)?;
+                partition.offset.encode(buffer)?;
+                partition.last_fetched_epoch.encode(buffer)?;
+                partition.log_start_offset.encode(buffer)?;
+                partition.max_bytes.encode(buffer)?;
+                TaggedFields.encode(buffer)
+            })?;
+            TaggedFields.encode(buffer)
+        })?;
+        encode_as_compact_array(buffer, &self.forgotten_topics, |buffer, topic| {
+            encode_topic(buffer, api_version, topic.topic_name, &topic.topic_id)?;
+            CompactArray(&topic.partitions).encode(buffer)?;
+            TaggedFields.encode(buffer)
+        })?;
+        CompactString(self.rack_id).encode(buffer)?;
+        TaggedFields.encode(buffer)?;
         Ok(())
     }
 }
 
+/// Flexible versions identify a topic by its name, or by its id from
+/// version 13.
+fn encode_topic<W: BufMut>(
+    buffer: &mut W,
+    api_version: i16,
+    topic_name: &str,
+    topic_id: &[u8; 16],
+) -> Result<()> {
+    if api_version >= FIRST_TOPIC_ID_VERSION {
+        RawBytes(topic_id).encode(buffer)
+    } else {
+        CompactString(topic_name).encode(buffer)
+    }
+}
+
 impl ToByte for TopicPartition<'_> {
     fn encode<W: BufMut>(&self, buffer: &mut W) -> Result<()> {
         self.topic_name.encode(buffer)?;
@@ -138,8 +321,28 @@ impl ToByte for TopicPartition<'_> {
 impl ToByte for Partition {
     fn encode<T: BufMut>(&self, buffer: &mut T) -> Result<()> {
         self.partition_index.encode(buffer)?;
+        self.current_leader_epoch.encode(buffer)?;
         self.offset.encode(buffer)?;
+        self.log_start_offset.encode(buffer)?;
         self.max_bytes.encode(buffer)?;
         Ok(())
     }
 }
+
+impl ToByte for ForgottenTopic<'_> {
+    fn encode<T: BufMut>(&self, buffer: &mut T) -> Result<()> {
+        self.topic_name.encode(buffer)?;
+        self.partitions.encode(buffer)?;
+        Ok(())
+    }
+}
+
+impl Describe for FetchRequest<'_> {
+    fn api_key(&self) -> i16 {
+        self.header.api_key
+    }
+
+    fn api_version(&self) -> Option<i16> {
+        Some(self.header.api_version)
+    }
+}
@@ -1,8 +1,12 @@
 //! Parsing and processing for Fetch responses.
 
-use bytes::Bytes;
+use std::io::Read;
+
+use bytes::{buf::Reader, Buf, Bytes};
+use flate2::read::GzDecoder;
 use nom::{
     bytes::complete::take,
+    error::ErrorKind,
     multi::{many0, many_m_n},
     number::complete::{be_i16, be_i32, be_i64, be_i8},
     IResult,
@@ -11,27 +15,44 @@ use nombytes::NomBytes;
 
 use crate::{
     error::{Error, KafkaCode, Result},
-    parser,
+    parser::{self, parse_compact_array, parse_tagged_fields},
     prelude::Compression,
-    protocol::{parse_header_response, produce::request::Attributes, HeaderResponse},
+    protocol::{
+        fetch::request::{API_KEY_FETCH, FIRST_TOPIC_ID_VERSION},
+        parse_flexible_header_response, parse_header_response,
+        produce::request::RecordBatchAttributes,
+        Describe, HeaderResponse,
+    },
     utils::uncompress,
 };
 
+/// The first version of the Fetch response using the flexible encoding.
+pub const FIRST_FLEXIBLE_VERSION: i16 = 12;
+
 /*
-Fetch Response (Version: 4) => throttle_time_ms [responses]
+Fetch Response (Version: 11) => throttle_time_ms error_code session_id [responses]
   throttle_time_ms => INT32
+  error_code => INT16
+  session_id => INT32
   responses => topic [partitions]
     topic => STRING
-    partitions => partition_index error_code high_watermark last_stable_offset [aborted_transactions] records
+    partitions => partition_index error_code high_watermark last_stable_offset log_start_offset [aborted_transactions] preferred_read_replica records
       partition_index => INT32
       error_code => INT16
       high_watermark => INT64
       last_stable_offset => INT64
+      log_start_offset => INT64
       aborted_transactions => producer_id first_offset
         producer_id => INT64
         first_offset => INT64
+      preferred_read_replica => INT32
       records => RECORD BATCH
 
+Versions 12 and up are flexible: strings, arrays and records are compact
+(prefixed with an unsigned varint of length + 1) and each structure ends
+with a TAG_BUFFER. From version 13 the responses are keyed by topic_id (UUID)
+instead of the topic name.
+
 RECORD BATCH
     baseOffset: int64
     batchLength: int32
@@ -77,11 +98,39 @@ Header
     Value: byte[]
 */
 
-#[derive(Debug, Default, PartialEq)]
+/// By default the records of a batch may decompress to at most 256 MiB.
+pub const DEFAULT_MAX_DECOMPRESSED_BATCH_BYTES: usize = 256 * 1024 * 1024;
+
+/// How the compressed record batches of a response are decompressed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Decompression {
+    /// Keep compressed batches compressed and only decompress them, record
+    /// by record, when iterating over [`RecordBatch::into_records`].
+    pub lazy: bool,
+    /// The most bytes the records of a single batch may decompress to, so a
+    /// small batch that decompresses to gigabytes is rejected instead of
+    /// exhausting the memory.
+    pub max_batch_bytes: usize,
+}
+
+impl Decompression {
+    pub fn new(lazy: bool) -> Self {
+        Self {
+            lazy,
+            max_batch_bytes: DEFAULT_MAX_DECOMPRESSED_BATCH_BYTES,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
 pub struct FetchResponse {
     pub header_response: HeaderResponse,
     /// The duration in milliseconds for which the request was throttled due to a quota violation, or zero if the request did not violate any quota.
     pub trottle_time: i32,
+    /// The top level response error code.
+    pub error_code: KafkaCode,
+    /// The fetch session ID, or 0 if this is not part of a fetch session.
+    pub session_id: i32,
     /// The response topics.
     pub topics: Vec<Topic>,
 }
@@ -90,6 +139,54 @@ impl FetchResponse {
     pub fn record_count(&self) -> usize {
         self.topics.iter().map(|batch| batch.record_count()).sum()
     }
+
+    /// Parse the response to a Fetch request sent with the given api
+    /// version, decoding the flexible framing from version 12 on.
+    ///
+    /// From version 13 only the [`topic_id`](Topic::topic_id) of the topics
+    /// is known, their names are left empty.
+    pub fn try_from_version(s: Bytes, api_version: i16) -> Result<Self> {
+        Self::decompressing_from_version(s, api_version, Decompression::new(false))
+    }
+
+    /// Like [`try_from_version`](Self::try_from_version), but compressed
+    /// batches are kept compressed and only decompressed, record by record,
+    /// when iterating over [`RecordBatch::into_records`].
+    pub fn lazy_from_version(s: Bytes, api_version: i16) -> Result<Self> {
+        Self::decompressing_from_version(s, api_version, Decompression::new(true))
+    }
+
+    /// Parse the response to a Fetch request sent with the given api
+    /// version, decompressing the record batches as configured.
+    ///
+    /// Fails with [`Error::DecompressedSizeExceeded`] when the records of
+    /// a batch decompress to more than
+    /// [`max_batch_bytes`](Decompression::max_batch_bytes).
+    pub fn decompressing_from_version(
+        s: Bytes,
+        api_version: i16,
+        decompression: Decompression,
+    ) -> Result<Self> {
+        tracing::trace!("Parsing FetchResponse {:?}", s);
+        let parsed = if api_version < FIRST_FLEXIBLE_VERSION {
+            parse_fetch_response_with(NomBytes::new(s.clone()), decompression)
+        } else {
+            let by_topic_id = api_version >= FIRST_TOPIC_ID_VERSION;
+            parse_flexible_fetch_response_with(NomBytes::new(s.clone()), decompression, by_topic_id)
+        };
+        let (_, fetch_response) = parsed.map_err(|err| {
+            if let nom::Err::Failure(ref failure) = err {
+                if failure.code == ErrorKind::TooLarge {
+                    return Error::DecompressedSizeExceeded;
+                }
+            }
+            tracing::error!("ERROR: Failed parsing FetchResponse {:?}", err);
+            tracing::error!("ERROR: FetchResponse Bytes {:?}", s);
+            Error::ParsingError(s)
+        })?;
+        tracing::trace!("Parsed FetchResponse {:?}", fetch_response);
+        Ok(fetch_response)
+    }
 }
 
 // this helps us cast the server response into this type
@@ -113,6 +210,8 @@ impl TryFrom<Bytes> for FetchResponse {
 #[derive(Debug, Clone, PartialEq)]
 pub struct Topic {
     pub name: Bytes,
+    /// The topic id, all zeros before version 13.
+    pub topic_id: [u8; 16],
     pub partitions: Vec<Partition>,
 }
 
@@ -131,7 +230,10 @@ pub struct Partition {
     pub error_code: KafkaCode,
     pub high_water_mark: i64,
     pub last_stable_offset: i64,
+    pub log_start_offset: i64,
     pub aborted_transactions: Vec<AbortedTransactions>,
+    /// The broker the consumer should fetch this partition from next, or -1 to keep using this one.
+    pub preferred_read_replica: i32,
     pub record_batch: Vec<RecordBatch>,
 }
 
@@ -139,12 +241,13 @@ impl Partition {
     // this should probably be a type?
     pub fn into_box_iter(self) -> Box<impl Iterator<Item = (i32, KafkaCode, i64, i64, Record)>> {
         Box::new(self.record_batch.into_iter().flat_map(move |batch| {
-            batch.records.into_iter().map(move |record| {
+            let (base_offset, base_timestamp) = (batch.base_offset, batch.base_timestamp);
+            batch.into_records().map(move |record| {
                 (
                     self.id,
                     self.error_code,
-                    batch.base_offset,
-                    batch.base_timestamp,
+                    base_offset,
+                    base_timestamp,
                     record,
                 )
             })
@@ -157,6 +260,15 @@ impl Partition {
             .map(|batch| batch.record_count())
             .sum()
     }
+
+    /// The producer id and first offset of each aborted transaction in the
+    /// fetched range. The broker only returns these for READ_COMMITTED fetches.
+    pub fn aborted_transaction_offsets(&self) -> Vec<(i64, i64)> {
+        self.aborted_transactions
+            .iter()
+            .map(|aborted| (aborted.producer_id, aborted.first_offset))
+            .collect()
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -172,7 +284,7 @@ pub struct RecordBatch {
     pub partition_leader_epoch: i32,
     pub magic: i8,
     pub crc: i32,
-    pub attributes: Attributes,
+    pub attributes: RecordBatchAttributes,
     pub last_offset_delta: i32,
     pub base_timestamp: i64,
     pub max_timestamp: i64,
@@ -180,11 +292,135 @@ pub struct RecordBatch {
     pub producer_epoch: i16,
     pub base_sequence: i32,
     pub records: Vec<Record>,
+    /// The still compressed records of a batch parsed lazily, in which
+    /// case `records` is empty.
+    pub compressed_records: Option<Bytes>,
 }
 
 impl RecordBatch {
     pub fn record_count(&self) -> usize {
-        self.records.len()
+        match self.compressed_records {
+            Some(_) => (self.last_offset_delta + 1) as usize,
+            None => self.records.len(),
+        }
+    }
+
+    /// The records of the batch, decompressing a lazily parsed batch one
+    /// record at a time while iterating.
+    pub fn into_records(self) -> Records {
+        self.into_records_with_limit(DEFAULT_MAX_DECOMPRESSED_BATCH_BYTES)
+    }
+
+    /// Like [`into_records`](Self::into_records), but a lazily parsed batch
+    /// stops yielding records once more than `max_decompressed_bytes` have
+    /// been decompressed.
+    pub fn into_records_with_limit(self, max_decompressed_bytes: usize) -> Records {
+        let decoder = self
+            .compressed_records
+            .map(|compressed| GzDecoder::new(compressed.reader()));
+        Records {
+            decoded: self.records.into_iter(),
+            decoder,
+            decompressed_bytes: 0,
+            max_decompressed_bytes,
+        }
+    }
+}
+
+/// Iterator over the records of a [`RecordBatch`].
+///
+/// Compressed records are read from a streaming decoder, so the batch is
+/// never decompressed as a whole.
+pub struct Records {
+    decoded: std::vec::IntoIter<Record>,
+    decoder: Option<GzDecoder<Reader<Bytes>>>,
+    decompressed_bytes: usize,
+    max_decompressed_bytes: usize,
+}
+
+impl Records {
+    /// How many bytes have been decompressed so far.
+    pub fn decompressed_bytes(&self) -> usize {
+        self.decompressed_bytes
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<()> {
+        let decoder = self.decoder.as_mut().ok_or(std::io::ErrorKind::NotFound)?;
+        if self.decompressed_bytes + buf.len() > self.max_decompressed_bytes {
+            tracing::error!(
+                "Record batch decompresses to more than {} bytes",
+                self.max_decompressed_bytes
+            );
+            return Err(std::io::ErrorKind::InvalidData.into());
+        }
+        decoder.read_exact(buf)?;
+        self.decompressed_bytes += buf.len();
+        Ok(())
+    }
+
+    /// Read the next record from the decoder, length varint included, or
+    /// `None` once all of them have been read.
+    fn read_record(&mut self) -> std::io::Result<Option<Bytes>> {
+        let mut record = vec![];
+        let mut length = 0_usize;
+        let mut shift = 0;
+        loop {
+            let mut byte = [0];
+            match self.read(&mut byte) {
+                Err(err)
+                    if err.kind() == std::io::ErrorKind::UnexpectedEof && record.is_empty() =>
+                {
+                    return Ok(None);
+                }
+                result => result?,
+            }
+            record.push(byte[0]);
+            if shift >= usize::BITS {
+                return Err(std::io::ErrorKind::InvalidData.into());
+            }
+            length |= ((byte[0] & 0x7f) as usize) << shift;
+            shift += 7;
+            if byte[0] & 0x80 == 0 {
+                break;
+            }
+        }
+
+        // the length is zigzag encoded
+        let start = record.len();
+        record.resize(start + length / 2, 0);
+        self.read(&mut record[start..])?;
+        Ok(Some(Bytes::from(record)))
+    }
+}
+
+impl Iterator for Records {
+    type Item = Record;
+
+    fn next(&mut self) -> Option<Record> {
+        if let Some(record) = self.decoded.next() {
+            return Some(record);
+        }
+        self.decoder.as_ref()?;
+
+        let record = match self.read_record() {
+            Ok(record) => record,
+            Err(err) => {
+                tracing::error!("Error decompressing record {:?}", err);
+                None
+            }
+        };
+        let Some(record) = record else {
+            self.decoder = None;
+            return None;
+        };
+        match parse_record(NomBytes::new(record)) {
+            Ok((_, record)) => Some(record),
+            Err(err) => {
+                tracing::error!("Error parsing decompressed record {:?}", err);
+                self.decoder = None;
+                None
+            }
+        }
     }
 }
 
@@ -201,6 +437,25 @@ pub struct Record {
     pub headers: Vec<Header>,
 }
 
+/// Lengths are zigzag encoded, so the -1 of a null key or value reads as 1.
+const NULL_LENGTH: usize = 1;
+
+impl Record {
+    /// The key of the record, or `None` for a null key.
+    ///
+    /// The bytes are a slice of the fetch response, not a copy.
+    pub fn key(&self) -> Option<Bytes> {
+        (self.key_length != NULL_LENGTH).then(|| self.key.clone())
+    }
+
+    /// The value of the record, or `None` for a null value, e.g. a tombstone.
+    ///
+    /// The bytes are a slice of the fetch response, not a copy.
+    pub fn value(&self) -> Option<Bytes> {
+        (self.value_len != NULL_LENGTH).then(|| self.value.clone())
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Header {
     pub header_key_length: usize,
@@ -210,36 +465,56 @@ pub struct Header {
 }
 
 pub fn parse_fetch_response(s: NomBytes) -> IResult<NomBytes, FetchResponse> {
+    parse_fetch_response_with(s, Decompression::new(false))
+}
+
+fn parse_fetch_response_with(
+    s: NomBytes,
+    decompression: Decompression,
+) -> IResult<NomBytes, FetchResponse> {
     let (s, header_response) = parse_header_response(s)?;
     let (s, trottle_time) = be_i32::<NomBytes, nom::error::Error<NomBytes>>(s)?;
-    let (s, topics) = parser::parse_array(parse_topic)(s)?;
+    let (s, error_code) = parser::parse_kafka_code(s)?;
+    let (s, session_id) = be_i32(s)?;
+    let (s, topics) = parser::parse_array(move |s| parse_topic(s, decompression))(s)?;
 
     Ok((
         s,
         FetchResponse {
             header_response,
             trottle_time,
+            error_code,
+            session_id,
             topics,
         },
     ))
 }
 
-fn parse_topic(s: NomBytes) -> IResult<NomBytes, Topic> {
+fn parse_topic(s: NomBytes, decompression: Decompression) -> IResult<NomBytes, Topic> {
     let (s, name) = parser::parse_string(s)?;
-    let (s, partitions) = parser::parse_array(parse_partition)(s)?;
+    let (s, partitions) = parser::parse_array(move |s| parse_partition(s, decompression))(s)?;
 
-    Ok((s, Topic { name, partitions }))
+    Ok((
+        s,
+        Topic {
+            name,
+            topic_id: [0; 16],
+            partitions,
+        },
+    ))
 }
 
-fn parse_partition(s: NomBytes) -> IResult<NomBytes, Partition> {
+fn parse_partition(s: NomBytes, decompression: Decompression) -> IResult<NomBytes, Partition> {
     let (s, id) = be_i32(s)?;
     let (s, error_code) = parser::parse_kafka_code(s)?;
     let (s, high_water_mark) = be_i64(s)?;
     let (s, last_stable_offset) = be_i64(s)?;
+    let (s, log_start_offset) = be_i64(s)?;
     let (s, aborted_transactions) = parser::parse_array(parse_aborted_transactions)(s)?;
+    let (s, preferred_read_replica) = be_i32(s)?;
     let (s, _) = be_i32(s)?;
 
-    let (s, record_batch) = many0(parse_record_batch)(s)?;
+    let (s, record_batch) = many0(move |s| parse_record_batch_decompressing(s, decompression))(s)?;
 
     Ok((
         s,
@@ -248,7 +523,9 @@ fn parse_partition(s: NomBytes) -> IResult<NomBytes, Partition> {
             error_code,
             high_water_mark,
             last_stable_offset,
+            log_start_offset,
             aborted_transactions,
+            preferred_read_replica,
             record_batch,
         },
     ))
@@ -267,14 +544,127 @@ fn parse_aborted_transactions(s: NomBytes) -> IResult<NomBytes, AbortedTransacti
     ))
 }
 
+pub fn parse_flexible_fetch_response(s: NomBytes) -> IResult<NomBytes, FetchResponse> {
+    parse_flexible_fetch_response_with(s, Decompression::new(false), false)
+}
+
+fn parse_flexible_fetch_response_with(
+    s: NomBytes,
+    decompression: Decompression,
+    by_topic_id: bool,
+) -> IResult<NomBytes, FetchResponse> {
+    let (s, header_response) = parse_flexible_header_response(s)?;
+    let (s, trottle_time) = be_i32::<NomBytes, nom::error::Error<NomBytes>>(s)?;
+    let (s, error_code) = parser::parse_kafka_code(s)?;
+    let (s, session_id) = be_i32(s)?;
+    let (s, topics) =
+        parse_compact_array(move |s| parse_flexible_topic(s, decompression, by_topic_id))(s)?;
+    let (s, _) = parse_tagged_fields(s)?;
+
+    Ok((
+        s,
+        FetchResponse {
+            header_response,
+            trottle_time,
+            error_code,
+            session_id,
+            topics,
+        },
+    ))
+}
+
+fn parse_flexible_topic(
+    s: NomBytes,
+    decompression: Decompression,
+    by_topic_id: bool,
+) -> IResult<NomBytes, Topic> {
+    let (s, name, topic_id) = if by_topic_id {
+        let (s, topic_id) = parser::parse_uuid(s)?;
+        (s, Bytes::new(), topic_id)
+    } else {
+        let (s, name) = parser::parse_compact_string(s)?;
+        (s, name, [0; 16])
+    };
+    let (s, partitions) =
+        parse_compact_array(move |s| parse_flexible_partition(s, decompression))(s)?;
+    let (s, _) = parse_tagged_fields(s)?;
+
+    Ok((
+        s,
+        Topic {
+            name,
+            topic_id,
+            partitions,
+        },
+    ))
+}
+
+fn parse_flexible_partition(
+    s: NomBytes,
+    decompression: Decompression,
+) -> IResult<NomBytes, Partition> {
+    let (s, id) = be_i32(s)?;
+    let (s, error_code) = parser::parse_kafka_code(s)?;
+    let (s, high_water_mark) = be_i64(s)?;
+    let (s, last_stable_offset) = be_i64(s)?;
+    let (s, log_start_offset) = be_i64(s)?;
+    let (s, aborted_transactions) = parse_compact_array(parse_flexible_aborted_transactions)(s)?;
+    let (s, preferred_read_replica) = be_i32(s)?;
+
+    // Compact records are bounded by their length, since tagged fields
+    // follow them rather than the next partition.
+    let (s, records_length) = parser::take_varint(s)?;
+    let (s, records) = take(records_length.saturating_sub(1))(s)?;
+    let (_, record_batch) =
+        many0(move |s| parse_record_batch_decompressing(s, decompression))(records)?;
+    let (s, _) = parse_tagged_fields(s)?;
+
+    Ok((
+        s,
+        Partition {
+            id,
+            error_code,
+            high_water_mark,
+            last_stable_offset,
+            log_start_offset,
+            aborted_transactions,
+            preferred_read_replica,
+            record_batch,
+        },
+    ))
+}
+
+fn parse_flexible_aborted_transactions(s: NomBytes) -> IResult<NomBytes, AbortedTransactions> {
+    let (s, aborted_transactions) = parse_aborted_transactions(s)?;
+    let (s, _) = parse_tagged_fields(s)?;
+
+    Ok((s, aborted_transactions))
+}
+
 pub fn parse_record_batch(s: NomBytes) -> IResult<NomBytes, RecordBatch> {
+    parse_record_batch_with(s, false)
+}
+
+/// Parse a record batch, keeping compressed records compressed when `lazy`.
+pub fn parse_record_batch_with(s: NomBytes, lazy: bool) -> IResult<NomBytes, RecordBatch> {
+    parse_record_batch_decompressing(s, Decompression::new(lazy))
+}
+
+/// Parse a record batch, decompressing its records as configured.
+///
+/// Records decompressing to more than the limit fail with a
+/// [`TooLarge`](ErrorKind::TooLarge) failure.
+pub fn parse_record_batch_decompressing(
+    s: NomBytes,
+    decompression: Decompression,
+) -> IResult<NomBytes, RecordBatch> {
     let (s, base_offset) = be_i64(s)?;
     let (s, batch_length) = be_i32(s)?;
     let (s, partition_leader_epoch) = be_i32(s)?;
     let (s, magic) = be_i8(s)?;
     let (s, crc) = be_i32(s)?;
     let (s, attributes) = be_i16(s)?;
-    let attributes = Attributes::from(attributes);
+    let attributes = RecordBatchAttributes::from(attributes);
     let (s, last_offset_delta) = be_i32(s)?;
     let (s, base_timestamp) = be_i64(s)?;
     let (s, max_timestamp) = be_i64(s)?;
@@ -284,8 +674,16 @@ pub fn parse_record_batch(s: NomBytes) -> IResult<NomBytes, RecordBatch> {
 
     // When compression is enabled, the RecordBatch header remains
     // uncompressed, but the Records are compressed together
+    let mut compressed_records = None;
     let (s, records) = match attributes.compression {
         None => parser::parse_array(parse_record)(s)?,
+        Some(Compression::Gzip) if decompression.lazy => {
+            let (s, _record_count) = be_i32(s)?;
+            let (s, compressed) = take((batch_length - 49) as usize)(s)?;
+            compressed_records = Some(compressed.into_bytes());
+
+            (s, vec![])
+        }
         Some(Compression::Gzip) => {
             tracing::debug!("Decompressing with GZIP");
             let (s, record_count) = be_i32(s)?;
@@ -293,7 +691,18 @@ pub fn parse_record_batch(s: NomBytes) -> IResult<NomBytes, RecordBatch> {
 
             // 49 is magic number is because of how many bytes between now and batch length
             let (s, compressed_records) = take((batch_length - 49) as usize)(s)?;
-            let records_bytes = uncompress(compressed_records.into_bytes().as_ref()).unwrap();
+            let records_bytes = uncompress(
+                compressed_records.into_bytes().as_ref(),
+                decompression.max_batch_bytes,
+            )
+            .map_err(|err| {
+                tracing::error!("Error decompressing record batch {:?}", err);
+                let kind = match err {
+                    Error::DecompressedSizeExceeded => ErrorKind::TooLarge,
+                    _ => ErrorKind::Fail,
+                };
+                nom::Err::Failure(nom::error::Error::new(s.clone(), kind))
+            })?;
             let (_, records) = many_m_n(record_count, record_count, parse_record)(NomBytes::new(
                 Bytes::from(records_bytes),
             ))?;
@@ -318,6 +727,7 @@ pub fn parse_record_batch(s: NomBytes) -> IResult<NomBytes, RecordBatch> {
             producer_epoch,
             base_sequence,
             records,
+            compressed_records,
         },
     ))
 }
@@ -366,3 +776,26 @@ fn parse_header(s: NomBytes) -> IResult<NomBytes, Header> {
         },
     ))
 }
+
+impl Describe for FetchResponse {
+    fn api_key(&self) -> i16 {
+        API_KEY_FETCH
+    }
+
+    fn summary(&self) -> Vec<String> {
+        let mut summary = vec![];
+        for topic in &self.topics {
+            for partition in &topic.partitions {
+                summary.push(format!(
+                    "topic {} partition {}: {:?}, high watermark {}, {} batches",
+                    String::from_utf8_lossy(&topic.name),
+                    partition.id,
+                    partition.error_code,
+                    partition.high_water_mark,
+                    partition.record_batch.len()
+                ));
+            }
+        }
+        summary
+    }
+}
@@ -37,7 +37,9 @@ mod test {
 
         let res = response::FindCoordinatorResponse {
             header: protocol::HeaderResponse { correlation_id: 1 },
+            throttle_time_ms: 0,
             error_code: KafkaCode::None,
+            error_message: None,
             node_id: 1,
             host: Bytes::from_static(b"localhost"),
             port: 9092,
@@ -49,4 +51,43 @@ mod test {
 
         assert_eq!(res, x);
     }
+
+    #[test]
+    fn encode_with_key_type() {
+        let b = [
+            0, 10, 0, 1, 0, 0, 0, 1, 0, 4, 114, 117, 115, 116, 0, 3, 116, 120, 110, 1,
+        ];
+
+        let req = request::FindCoordinatorRequest::with_key_type(
+            1,
+            "rust",
+            "txn",
+            request::CoordinatorType::Transaction,
+        );
+
+        let mut buffer: Vec<u8> = vec![];
+        req.encode(&mut buffer).unwrap();
+
+        assert_eq!(buffer, b);
+    }
+
+    #[test]
+    fn parse_v1() {
+        let b = b"\0\0\0\x01\0\0\0\0\0\x0f\0\x04busy\xff\xff\xff\xff\0\0\xff\xff\xff\xff";
+
+        let res = response::FindCoordinatorResponse {
+            header: protocol::HeaderResponse { correlation_id: 1 },
+            throttle_time_ms: 0,
+            error_code: KafkaCode::GroupCoordinatorNotAvailable,
+            error_message: Some(Bytes::from_static(b"busy")),
+            node_id: -1,
+            host: Bytes::from_static(b""),
+            port: -1,
+        };
+
+        let x =
+            response::FindCoordinatorResponse::try_from_version(Bytes::from_static(b), 1).unwrap();
+
+        assert_eq!(res, x);
+    }
 }
@@ -17,14 +17,29 @@
 //! ```text
 //! FindCoordinator Request (Version: 0) => key
 //!   key => STRING
+//!
+//! FindCoordinator Request (Version: 1) => key key_type
+//!   key => STRING
+//!   key_type => INT8
 //! ```
 //!
-//! Note we are using version 0 of the request.
+//! Note we are using version 0 of the request, or version 1 when looking up
+//! a coordinator by key type.
 
 use crate::{encode::ToByte, protocol::HeaderRequest};
 
 const API_KEY_METADATA: i16 = 10;
 const API_VERSION: i16 = 0;
+const API_VERSION_WITH_KEY_TYPE: i16 = 1;
+
+/// The kind of coordinator to find.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CoordinatorType {
+    /// The coordinator of a consumer group, the key is the group id.
+    Group = 0,
+    /// The coordinator of a transactional producer, the key is the transactional id.
+    Transaction = 1,
+}
 
 /// The base Find Coordinator request object.
 ///
@@ -39,12 +54,38 @@ pub struct FindCoordinatorRequest<'a> {
     pub header: HeaderRequest<'a>,
     /// The coordinator key.
     pub key: &'a str,
+    /// The coordinator key type, only sent from version 1.
+    pub key_type: CoordinatorType,
 }
 
 impl<'a> FindCoordinatorRequest<'a> {
     pub fn new(correlation_id: i32, client_id: &'a str, key: &'a str) -> Self {
         let header = HeaderRequest::new(API_KEY_METADATA, API_VERSION, correlation_id, client_id);
-        Self { header, key }
+        Self {
+            header,
+            key,
+            key_type: CoordinatorType::Group,
+        }
+    }
+
+    /// Find the coordinator of a group or of a transactional id.
+    pub fn with_key_type(
+        correlation_id: i32,
+        client_id: &'a str,
+        key: &'a str,
+        key_type: CoordinatorType,
+    ) -> Self {
+        let header = HeaderRequest::new(
+            API_KEY_METADATA,
+            API_VERSION_WITH_KEY_TYPE,
+            correlation_id,
+            client_id,
+        );
+        Self {
+            header,
+            key,
+            key_type,
+        }
     }
 }
 
@@ -53,6 +94,9 @@ impl ToByte for FindCoordinatorRequest<'_> {
         tracing::trace!("Encoding FindCoordinatorRequest {:?}", self);
         self.header.encode(buffer)?;
         self.key.encode(buffer)?;
+        if self.header.api_version >= API_VERSION_WITH_KEY_TYPE {
+            (self.key_type as i8).encode(buffer)?;
+        }
         Ok(())
     }
 }
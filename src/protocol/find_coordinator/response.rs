@@ -13,9 +13,18 @@
 //!   node_id => INT32
 //!   host => STRING
 //!   port => INT32
+//!
+//! FindCoordinator Response (Version: 1) => throttle_time_ms error_code error_message node_id host port
+//!   throttle_time_ms => INT32
+//!   error_code => INT16
+//!   error_message => NULLABLE_STRING
+//!   node_id => INT32
+//!   host => STRING
+//!   port => INT32
 //! ```
 //!
-//! Note we are using version 0 of the response.
+//! Note we are using version 0 of the response, or version 1 when looking up
+//! a coordinator by key type.
 
 use bytes::Bytes;
 use nom::{number::complete::be_i32, IResult};
@@ -37,12 +46,37 @@ use crate::{
 #[derive(Debug, PartialEq)]
 pub struct FindCoordinatorResponse {
     pub header: HeaderResponse,
+    /// The duration in milliseconds for which the request was throttled, zero before version 1.
+    pub throttle_time_ms: i32,
     pub error_code: KafkaCode,
+    /// The error message, or null if there was no error. Always null before version 1.
+    pub error_message: Option<Bytes>,
     pub node_id: i32,
     pub host: Bytes,
     pub port: i32,
 }
 
+impl FindCoordinatorResponse {
+    /// Parse the response to a FindCoordinator request sent with the given
+    /// api version, decoding the throttle time and error message from
+    /// version 1 on.
+    pub fn try_from_version(s: Bytes, api_version: i16) -> Result<Self> {
+        if api_version < 1 {
+            return Self::try_from(s);
+        }
+
+        tracing::trace!("Parsing FindCoordinatorResponse v1 {:?}", s);
+        let (_, find_coordinator) = parse_find_coordinator_response_v1(NomBytes::new(s.clone()))
+            .map_err(|err| {
+                tracing::error!("ERROR: Failed parsing FindCoordinatorResponse {:?}", err);
+                tracing::error!("ERROR: FindCoordinatorResponse Bytes {:?}", s);
+                Error::ParsingError(s)
+            })?;
+        tracing::trace!("Parsed FindCoordinatorResponse {:?}", find_coordinator);
+        Ok(find_coordinator)
+    }
+}
+
 // this helps us cast the server response into this type
 impl TryFrom<Bytes> for FindCoordinatorResponse {
     type Error = Error;
@@ -71,7 +105,34 @@ pub fn parse_find_coordinator_response(s: NomBytes) -> IResult<NomBytes, FindCoo
         s,
         FindCoordinatorResponse {
             header,
+            throttle_time_ms: 0,
+            error_code,
+            error_message: None,
+            node_id,
+            host,
+            port,
+        },
+    ))
+}
+
+pub fn parse_find_coordinator_response_v1(
+    s: NomBytes,
+) -> IResult<NomBytes, FindCoordinatorResponse> {
+    let (s, header) = parse_header_response(s)?;
+    let (s, throttle_time_ms) = be_i32(s)?;
+    let (s, error_code) = parser::parse_kafka_code(s)?;
+    let (s, error_message) = parser::parse_nullable_string(s)?;
+    let (s, node_id) = be_i32(s)?;
+    let (s, host) = parser::parse_string(s)?;
+    let (s, port) = be_i32(s)?;
+
+    Ok((
+        s,
+        FindCoordinatorResponse {
+            header,
+            throttle_time_ms,
             error_code,
+            error_message,
             node_id,
             host,
             port,
@@ -0,0 +1,47 @@
+//! Get a producer id and epoch for idempotent or transactional writes.
+
+pub mod request;
+pub mod response;
+
+#[cfg(test)]
+mod test {
+    use bytes::Bytes;
+    use nombytes::NomBytes;
+
+    use super::*;
+    use crate::{encode::ToByte, error::KafkaCode, protocol};
+
+    #[test]
+    fn encode() {
+        let b = [
+            0, 22, 0, 1, 0, 0, 0, 1, 0, 4, 114, 117, 115, 116, 255, 255, 0, 0, 234, 96,
+        ];
+
+        let req = request::InitProducerIdRequest::new(1, "rust", None, 60000);
+
+        let mut buffer: Vec<u8> = vec![];
+        req.encode(&mut buffer).unwrap();
+
+        assert_eq!(buffer, b);
+    }
+
+    #[test]
+    fn parse() {
+        let b = [0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 42, 0, 3];
+
+        let res = response::InitProducerIdResponse {
+            header: protocol::HeaderResponse { correlation_id: 1 },
+            throttle_time_ms: 0,
+            error_code: KafkaCode::None,
+            producer_id: 42,
+            producer_epoch: 3,
+        };
+
+        let x =
+            response::parse_init_producer_id_response(NomBytes::new(Bytes::copy_from_slice(&b)))
+                .unwrap()
+                .1;
+
+        assert_eq!(res, x);
+    }
+}
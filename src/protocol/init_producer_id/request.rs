@@ -0,0 +1,87 @@
+//! Encoding and creation for InitProducerId requests.
+//!
+//! Before writing idempotently or transactionally, a producer asks the
+//! cluster for a producer id and epoch. The broker uses these together with
+//! the sequence number of each record batch to drop duplicates.
+//!
+//! ### Example
+//! ```rust
+//! let init_producer_id = protocol::InitProducerIdRequest::new(
+//!     CORRELATION_ID,
+//!     CLIENT_ID,
+//!     None,
+//!     60000,
+//! );
+//! conn.send_request(&init_producer_id).await?;
+//! ```
+//!
+//! ### Protocol Def
+//! ```text
+//! InitProducerId Request (Version: 1) => transactional_id transaction_timeout_ms
+//!   transactional_id => NULLABLE_STRING
+//!   transaction_timeout_ms => INT32
+//! ```
+//!
+//! Note that we are using version 1 of this API.
+
+use bytes::BufMut;
+
+use crate::{encode::ToByte, error::Result, protocol::HeaderRequest};
+
+const API_KEY_INIT_PRODUCER_ID: i16 = 22;
+const API_VERSION: i16 = 1;
+
+/// The base InitProducerId request object.
+///
+/// ### Example
+/// ```rust
+/// let init_producer_id = protocol::InitProducerIdRequest::new(
+///     CORRELATION_ID,
+///     CLIENT_ID,
+///     None,
+///     60000,
+/// );
+/// conn.send_request(&init_producer_id).await?;
+/// ```
+#[derive(Debug)]
+pub struct InitProducerIdRequest<'a> {
+    pub header: HeaderRequest<'a>,
+    /// The transactional id, or null if the producer is not transactional.
+    pub transactional_id: Option<&'a str>,
+    /// The time in ms to wait before aborting idle transactions sent by this producer. This is only relevant if a transactional id is set.
+    pub transaction_timeout_ms: i32,
+}
+
+impl<'a> InitProducerIdRequest<'a> {
+    pub fn new(
+        correlation_id: i32,
+        client_id: &'a str,
+        transactional_id: Option<&'a str>,
+        transaction_timeout_ms: i32,
+    ) -> Self {
+        Self {
+            header: HeaderRequest::new(
+                API_KEY_INIT_PRODUCER_ID,
+                API_VERSION,
+                correlation_id,
+                client_id,
+            ),
+            transactional_id,
+            transaction_timeout_ms,
+        }
+    }
+}
+
+impl ToByte for InitProducerIdRequest<'_> {
+    fn encode<T: BufMut>(&self, buffer: &mut T) -> Result<()> {
+        tracing::trace!("Encoding InitProducerIdRequest {:?}", self);
+        self.header.encode(buffer)?;
+        // a null string is a length of -1 as an INT16
+        match self.transactional_id {
+            Some(transactional_id) => transactional_id.encode(buffer)?,
+            None => (-1_i16).encode(buffer)?,
+        }
+        self.transaction_timeout_ms.encode(buffer)?;
+        Ok(())
+    }
+}
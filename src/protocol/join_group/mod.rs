@@ -78,6 +78,41 @@ mod test {
         assert_eq!(buffer, b);
     }
 
+    #[test]
+    fn encode_flexible() {
+        let metadata = [
+            0, 3, 0, 0, 0, 1, 0, 9, 112, 117, 114, 99, 104, 97, 115, 101, 115, 255, 255, 255, 255,
+        ];
+        let mut b = vec![
+            0, 11, 0, 6, 0, 0, 0, 1, 0, 4, 114, 117, 115, 116, 0, 9, 66, 105, 103, 32, 68, 111,
+            103, 115, 0, 0, 39, 16, 0, 0, 39, 16, 1, 3, 99, 49, 9, 99, 111, 110, 115, 117, 109,
+            101, 114, 2, 6, 114, 97, 110, 103, 101, 22,
+        ];
+        b.extend_from_slice(&metadata);
+        // the tagged fields of the protocol, then of the request
+        b.extend_from_slice(&[0, 0]);
+
+        let protocol = request::Protocol::new("range", vec!["purchases"]);
+        let mut req = request::JoinGroupRequest::new(
+            1,
+            "rust",
+            "Big Dogs",
+            10000,
+            10000,
+            "".into(),
+            "consumer",
+            vec![protocol],
+        )
+        .unwrap();
+        req.header.api_version = request::FIRST_FLEXIBLE_VERSION;
+        req.group_instance_id = Some("c1");
+
+        let mut buffer: Vec<u8> = vec![];
+        req.encode(&mut buffer).unwrap();
+
+        assert_eq!(buffer, b);
+    }
+
     #[test]
     fn parse() {
         let b = b"\0\0\0\x01\0\0\0\0\0\0\0\0\0\x02\0\x08consumer\0;group integration test-1fdacda0-218b-4c93-aa1d-bfe1ee48e9c9\0;group integration test-1fdacda0-218b-4c93-aa1d-bfe1ee48e9c9\0\0\0\x02\0;group integration test-1fdacda0-218b-4c93-aa1d-bfe1ee48e9c9\0\0\0\x15\0\x03\0\0\0\x01\0\tpurchases\xff\xff\xff\xff\0;group integration test-f92a30c7-3927-4817-8a13-7949b4688680\0\0\0\x15\0\x03\0\0\0\x01\0\tpurchases\xff\xff\xff\xff";
@@ -38,21 +38,41 @@
 //!   protocols => name metadata
 //!     name => STRING
 //!     metadata => BYTES
+//!
+//! JoinGroup Request (Version: 6) => group_id session_timeout_ms rebalance_timeout_ms member_id group_instance_id protocol_type [protocols] TAG_BUFFER
+//!   group_id => COMPACT_STRING
+//!   session_timeout_ms => INT32
+//!   rebalance_timeout_ms => INT32
+//!   member_id => COMPACT_STRING
+//!   group_instance_id => COMPACT_NULLABLE_STRING
+//!   protocol_type => COMPACT_STRING
+//!   protocols => name metadata TAG_BUFFER
+//!     name => COMPACT_STRING
+//!     metadata => COMPACT_BYTES
 //! ```
 //!
-//! Note we are using version 2 of the request.
+//! Note we are using version 2 of the request by default. Setting the
+//! version in the header to 5 also sends the group instance id, and from
+//! version 6 the request is flexible.
 
-use bytes::Bytes;
+use bytes::{BufMut, Bytes};
 use nom::AsBytes;
 
 use crate::{
-    encode::ToByte,
+    encode::{
+        encode_as_compact_array, CompactBytes, CompactNullableString, CompactString, TaggedFields,
+        ToByte,
+    },
     error::{Error, Result},
     protocol::HeaderRequest,
 };
 
 const API_KEY_METADATA: i16 = 11;
 const API_VERSION: i16 = 2;
+/// The first version carrying the group instance id of static members.
+pub const FIRST_GROUP_INSTANCE_ID_VERSION: i16 = 5;
+/// The first version using compact strings and tagged fields.
+pub const FIRST_FLEXIBLE_VERSION: i16 = 6;
 
 /// The base Sync Group request object.
 ///
@@ -81,6 +101,9 @@ pub struct JoinGroupRequest<'a> {
     pub rebalance_timeout_ms: i32,
     /// The member id assigned by the group coordinator. Empty if the member is joining for the first time.
     pub member_id: String,
+    /// The unique identifier of the consumer instance provided by end user,
+    /// only sent from version 5.
+    pub group_instance_id: Option<&'a str>,
     /// The unique name the for class of protocols implemented by the group we want to join.
     pub protocol_type: &'a str,
     /// The list of protocols that the member supports.
@@ -134,6 +157,7 @@ impl<'a> JoinGroupRequest<'a> {
             rebalance_timeout_ms,
             member_id: String::from_utf8(member_id.as_bytes().to_vec())
                 .map_err(|_| Error::DecodingUtf8Error)?,
+            group_instance_id: None,
             protocol_type,
             protocols,
         })
@@ -143,17 +167,47 @@ impl<'a> JoinGroupRequest<'a> {
 impl ToByte for JoinGroupRequest<'_> {
     fn encode<T: bytes::BufMut>(&self, buffer: &mut T) -> crate::error::Result<()> {
         tracing::trace!("Encoding JoinGroupRequest {:?}", self);
+        let api_version = self.header.api_version;
+        if api_version >= FIRST_FLEXIBLE_VERSION {
+            return self.encode_flexible(buffer);
+        }
         self.header.encode(buffer)?;
         self.group_id.encode(buffer)?;
         self.session_timeout_ms.encode(buffer)?;
         self.rebalance_timeout_ms.encode(buffer)?;
         self.member_id.encode(buffer)?;
+        if api_version >= FIRST_GROUP_INSTANCE_ID_VERSION {
+            self.group_instance_id.encode(buffer)?;
+        }
         self.protocol_type.encode(buffer)?;
         self.protocols.encode(buffer)?;
         Ok(())
     }
 }
 
+impl JoinGroupRequest<'_> {
+    /// Each protocol ends with its own tagged fields, written before the
+    /// tagged fields of the request.
+    fn encode_flexible<T: BufMut>(&self, buffer: &mut T) -> Result<()> {
+        self.header.encode_flexible(buffer)?;
+        CompactString(self.group_id).encode(buffer)?;
+        self.session_timeout_ms.encode(buffer)?;
+        self.rebalance_timeout_ms.encode(buffer)?;
+        CompactString(&self.member_id).encode(buffer)?;
+        CompactNullableString(self.group_instance_id).encode(buffer)?;
+        CompactString(self.protocol_type).encode(buffer)?;
+        encode_as_compact_array(buffer, &self.protocols, |buffer, protocol| {
+            CompactString(protocol.name).encode(buffer)?;
+            let mut metadata = Vec::with_capacity(4);
+            protocol.metadata.encode(&mut metadata)?;
+            CompactBytes(&metadata).encode(buffer)?;
+            TaggedFields.encode(buffer)
+        })?;
+        TaggedFields.encode(buffer)?;
+        Ok(())
+    }
+}
+
 impl ToByte for Protocol<'_> {
     fn encode<T: bytes::BufMut>(&self, buffer: &mut T) -> crate::error::Result<()> {
         self.name.encode(buffer)?;
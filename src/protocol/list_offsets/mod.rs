@@ -47,6 +47,39 @@ mod test {
         assert_eq!(res, x);
     }
 
+    #[test]
+    fn encode_v5() {
+        let b = [
+            0, 2, 0, 5, 0, 0, 0, 1, 0, 4, 114, 117, 115, 116, 255, 255, 255, 255, 1, 0, 0, 0, 1, 0,
+            9, 112, 117, 114, 99, 104, 97, 115, 101, 115, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 7, 255,
+            255, 255, 255, 255, 255, 255, 255,
+        ];
+
+        let mut req = request::ListOffsetsRequest::with_isolation_level(1, "rust", -1, 1);
+        req.add_with_leader_epoch("purchases", 1, -1, 7);
+
+        let mut buffer: Vec<u8> = vec![];
+        req.encode(&mut buffer).unwrap();
+
+        assert_eq!(buffer, b);
+    }
+
+    #[test]
+    fn parse_v5() {
+        let b = b"\0\0\0\x01\0\0\0\x0a\0\0\0\x01\0\tpurchases\0\0\0\x01\0\0\0\x02\0\0\xff\xff\xff\xff\xff\xff\xff\xff\0\0\0\0\0\0\x01\xc8\0\0\0\x04";
+
+        let res =
+            response::ListOffsetsResponse::try_from_version(Bytes::from_static(b), 5).unwrap();
+
+        assert_eq!(res.throttle_time_ms, 10);
+        let (topic_name, partition) = res.into_box_iter().next().unwrap();
+        assert_eq!(topic_name, Bytes::from_static(b"purchases"));
+        assert_eq!(partition.partition_index, 2);
+        assert_eq!(partition.error_code, KafkaCode::None);
+        assert_eq!(partition.offset, 456);
+        assert_eq!(partition.leader_epoch, 4);
+    }
+
     #[test]
     fn add_to_req() {
         let correlation_id = 1;
@@ -98,6 +131,7 @@ mod test {
     fn example_res() -> response::ListOffsetsResponse {
         response::ListOffsetsResponse {
             header: protocol::HeaderResponse { correlation_id: 1 },
+            throttle_time_ms: 0,
             topics: vec![response::Topic {
                 name: Bytes::from_static(b"purchases"),
                 partitions: vec![response::Partition {
@@ -105,6 +139,7 @@ mod test {
                     error_code: KafkaCode::UnknownTopicOrPartition,
                     timestamp: -1,
                     offset: -1,
+                    leader_epoch: -1,
                 }],
             }],
         }
@@ -17,14 +17,29 @@
 //!     partitions => partition_index timestamp
 //!       partition_index => INT32
 //!       timestamp => INT64
+//!
+//! ListOffsets Request (Version: 5) => replica_id isolation_level [topics]
+//!   replica_id => INT32
+//!   isolation_level => INT8
+//!   topics => name [partitions]
+//!     name => STRING
+//!     partitions => partition_index current_leader_epoch timestamp
+//!       partition_index => INT32
+//!       current_leader_epoch => INT32
+//!       timestamp => INT64
 //! ```
 //!
-//! Note we are using version 1 of the request.
+//! Note we are using version 1 of the request, or version 5 when created
+//! with an isolation level.
 
-use crate::{encode::ToByte, protocol::HeaderRequest};
+use crate::{
+    encode::{try_usize_to_int, ToByte},
+    protocol::{Describe, HeaderRequest},
+};
 
-const API_KEY_METADATA: i16 = 2;
+pub const API_KEY_LIST_OFFSETS: i16 = 2;
 const API_VERSION: i16 = 1;
+const API_VERSION_WITH_LEADER_EPOCH: i16 = 5;
 
 /// The base List Offsets request object.
 ///
@@ -38,6 +53,8 @@ pub struct ListOffsetsRequest<'a> {
     pub header: HeaderRequest<'a>,
     /// The broker ID of the requester, or -1 if this request is being made by a normal consumer.
     pub replica_id: i32,
+    /// This setting controls the visibility of transactional records. With READ_COMMITTED (isolation_level = 1) the last stable offset is returned instead of the high watermark. Only sent from version 2 onwards.
+    pub isolation_level: i8,
     /// Each topic in the request.
     pub topics: Vec<Topic<'a>>,
 }
@@ -56,21 +73,59 @@ pub struct Topic<'a> {
 pub struct Partition {
     /// The partition index.
     pub partition_index: i32,
+    /// The leader epoch known to the client, or -1. The broker rejects the request with FENCED_LEADER_EPOCH or UNKNOWN_LEADER_EPOCH when it does not match. Only sent from version 4 onwards.
+    pub current_leader_epoch: i32,
     /// The current timestamp.
     pub timestamp: i64,
 }
 
 impl<'a> ListOffsetsRequest<'a> {
     pub fn new(correlation_id: i32, client_id: &'a str, replica_id: i32) -> Self {
-        let header = HeaderRequest::new(API_KEY_METADATA, API_VERSION, correlation_id, client_id);
+        let header =
+            HeaderRequest::new(API_KEY_LIST_OFFSETS, API_VERSION, correlation_id, client_id);
+        Self {
+            header,
+            replica_id,
+            isolation_level: 0,
+            topics: vec![],
+        }
+    }
+
+    /// Create a version 5 request, which carries the isolation level and
+    /// the current leader epoch of each partition.
+    pub fn with_isolation_level(
+        correlation_id: i32,
+        client_id: &'a str,
+        replica_id: i32,
+        isolation_level: i8,
+    ) -> Self {
+        let header = HeaderRequest::new(
+            API_KEY_LIST_OFFSETS,
+            API_VERSION_WITH_LEADER_EPOCH,
+            correlation_id,
+            client_id,
+        );
         Self {
             header,
             replica_id,
+            isolation_level,
             topics: vec![],
         }
     }
 
     pub fn add(&mut self, topic_name: &'a str, partition_index: i32, timestamp: i64) {
+        self.add_with_leader_epoch(topic_name, partition_index, timestamp, -1);
+    }
+
+    /// Add a partition together with the leader epoch the client last saw
+    /// for it, so the broker can detect a stale or truncated log.
+    pub fn add_with_leader_epoch(
+        &mut self,
+        topic_name: &'a str,
+        partition_index: i32,
+        timestamp: i64,
+        current_leader_epoch: i32,
+    ) {
         match self
             .topics
             .iter_mut()
@@ -80,6 +135,7 @@ impl<'a> ListOffsetsRequest<'a> {
                 name: topic_name,
                 partitions: vec![Partition {
                     partition_index,
+                    current_leader_epoch,
                     timestamp,
                 }],
             }),
@@ -91,6 +147,7 @@ impl<'a> ListOffsetsRequest<'a> {
                 {
                     topic.partitions.push(Partition {
                         partition_index,
+                        current_leader_epoch,
                         timestamp,
                     })
                 }
@@ -104,23 +161,32 @@ impl ToByte for ListOffsetsRequest<'_> {
         tracing::trace!("Encoding ListOffsetRequest {:?}", self);
         self.header.encode(buffer)?;
         self.replica_id.encode(buffer)?;
-        self.topics.encode(buffer)?;
+        let api_version = self.header.api_version;
+        if api_version >= 2 {
+            self.isolation_level.encode(buffer)?;
+        }
+        try_usize_to_int!(self.topics.len(), i32).encode(buffer)?;
+        for topic in &self.topics {
+            topic.name.encode(buffer)?;
+            try_usize_to_int!(topic.partitions.len(), i32).encode(buffer)?;
+            for partition in &topic.partitions {
+                partition.partition_index.encode(buffer)?;
+                if api_version >= 4 {
+                    partition.current_leader_epoch.encode(buffer)?;
+                }
+                partition.timestamp.encode(buffer)?;
+            }
+        }
         Ok(())
     }
 }
 
-impl ToByte for Topic<'_> {
-    fn encode<T: bytes::BufMut>(&self, buffer: &mut T) -> crate::error::Result<()> {
-        self.name.encode(buffer)?;
-        self.partitions.encode(buffer)?;
-        Ok(())
+impl Describe for ListOffsetsRequest<'_> {
+    fn api_key(&self) -> i16 {
+        self.header.api_key
     }
-}
 
-impl ToByte for Partition {
-    fn encode<T: bytes::BufMut>(&self, buffer: &mut T) -> crate::error::Result<()> {
-        self.partition_index.encode(buffer)?;
-        self.timestamp.encode(buffer)?;
-        Ok(())
+    fn api_version(&self) -> Option<i16> {
+        Some(self.header.api_version)
     }
 }
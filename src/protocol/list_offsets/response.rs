@@ -16,9 +16,21 @@
 //!     error_code => INT16
 //!     timestamp => INT64
 //!     offset => INT64
+//!
+//! ListOffsets Response (Version: 5) => throttle_time_ms [topics]
+//! throttle_time_ms => INT32
+//! topics => name [partitions]
+//! name => STRING
+//! partitions => partition_index error_code timestamp offset leader_epoch
+//!     partition_index => INT32
+//!     error_code => INT16
+//!     timestamp => INT64
+//!     offset => INT64
+//!     leader_epoch => INT32
 //! ```
 //!
-//! Note we are using version 1 of the response.
+//! Note we are using version 1 of the response, or version 5 when parsed
+//! with [`ListOffsetsResponse::try_from_version`].
 
 use bytes::Bytes;
 use nom::{
@@ -30,7 +42,10 @@ use nombytes::NomBytes;
 use crate::{
     error::{Error, KafkaCode, Result},
     parser,
-    protocol::{parse_header_response, HeaderResponse},
+    protocol::{
+        list_offsets::request::API_KEY_LIST_OFFSETS, parse_header_response, Describe,
+        HeaderResponse,
+    },
 };
 
 /// The base List Offsets response object.
@@ -43,6 +58,8 @@ use crate::{
 #[derive(Debug, PartialEq)]
 pub struct ListOffsetsResponse {
     pub header: HeaderResponse,
+    /// The duration in milliseconds for which the request was throttled due to a quota violation, or zero if the request did not violate any quota. Always zero before version 2.
+    pub throttle_time_ms: i32,
     /// Each topic in the response.
     pub topics: Vec<Topic>,
 }
@@ -67,6 +84,8 @@ pub struct Partition {
     pub timestamp: i64,
     /// The returned offset.
     pub offset: i64,
+    /// The leader epoch of the returned offset, or -1 before version 4.
+    pub leader_epoch: i32,
 }
 
 // this helps us cast the server response into this type
@@ -87,6 +106,21 @@ impl TryFrom<Bytes> for ListOffsetsResponse {
 }
 
 impl ListOffsetsResponse {
+    /// Parse the response to a request of the given version.
+    pub fn try_from_version(s: Bytes, api_version: i16) -> Result<Self> {
+        tracing::trace!("Parsing ListOffsetsResponse v{} {:?}", api_version, s);
+        let (_, list_offsets) = parse_list_offsets_response_version(api_version)(NomBytes::new(
+            s.clone(),
+        ))
+        .map_err(|err| {
+            tracing::error!("ERROR: Failed parsing ListOffsetsResponse {:?}", err);
+            tracing::error!("ERROR: ListOffsetsResponse Bytes {:?}", s);
+            Error::ParsingError(s)
+        })?;
+        tracing::trace!("Parsed ListOffsetsResponse {:?}", list_offsets);
+        Ok(list_offsets)
+    }
+
     pub fn into_box_iter(self) -> Box<impl Iterator<Item = (Bytes, Partition)>> {
         Box::new(self.topics.into_iter().flat_map(|topic| {
             topic
@@ -98,32 +132,64 @@ impl ListOffsetsResponse {
 }
 
 pub fn parse_list_offsets_response(s: NomBytes) -> IResult<NomBytes, ListOffsetsResponse> {
-    let (s, header) = parse_header_response(s)?;
-    let (s, topics) = parser::parse_array(parse_topic)(s)?;
+    parse_list_offsets_response_version(1)(s)
+}
+
+pub fn parse_list_offsets_response_version(
+    api_version: i16,
+) -> impl Fn(NomBytes) -> IResult<NomBytes, ListOffsetsResponse> {
+    move |s: NomBytes| {
+        let (s, header) = parse_header_response(s)?;
+        let (s, throttle_time_ms) = if api_version >= 2 { be_i32(s)? } else { (s, 0) };
+        let (s, topics) = parser::parse_array(parse_topic(api_version))(s)?;
+
+        Ok((
+            s,
+            ListOffsetsResponse {
+                header,
+                throttle_time_ms,
+                topics,
+            },
+        ))
+    }
+}
+
+fn parse_topic(api_version: i16) -> impl Fn(NomBytes) -> IResult<NomBytes, Topic> + Copy {
+    move |s: NomBytes| {
+        let (s, name) = parser::parse_string(s)?;
+        let (s, partitions) = parser::parse_array(parse_partition(api_version))(s)?;
 
-    Ok((s, ListOffsetsResponse { header, topics }))
+        Ok((s, Topic { name, partitions }))
+    }
 }
 
-fn parse_topic(s: NomBytes) -> IResult<NomBytes, Topic> {
-    let (s, name) = parser::parse_string(s)?;
-    let (s, partitions) = parser::parse_array(parse_partition)(s)?;
+fn parse_partition(api_version: i16) -> impl Fn(NomBytes) -> IResult<NomBytes, Partition> + Copy {
+    move |s: NomBytes| {
+        let (s, partition_index) = be_i32(s)?;
+        let (s, error_code) = parser::parse_kafka_code(s)?;
+        let (s, timestamp) = be_i64(s)?;
+        let (s, offset) = be_i64(s)?;
+        let (s, leader_epoch) = if api_version >= 4 {
+            be_i32(s)?
+        } else {
+            (s, -1)
+        };
 
-    Ok((s, Topic { name, partitions }))
+        Ok((
+            s,
+            Partition {
+                partition_index,
+                error_code,
+                timestamp,
+                offset,
+                leader_epoch,
+            },
+        ))
+    }
 }
 
-fn parse_partition(s: NomBytes) -> IResult<NomBytes, Partition> {
-    let (s, partition_index) = be_i32(s)?;
-    let (s, error_code) = parser::parse_kafka_code(s)?;
-    let (s, timestamp) = be_i64(s)?;
-    let (s, offset) = be_i64(s)?;
-
-    Ok((
-        s,
-        Partition {
-            partition_index,
-            error_code,
-            timestamp,
-            offset,
-        },
-    ))
+impl Describe for ListOffsetsResponse {
+    fn api_key(&self) -> i16 {
+        API_KEY_LIST_OFFSETS
+    }
 }
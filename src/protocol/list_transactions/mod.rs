@@ -0,0 +1,56 @@
+//! List the transactions a broker is the coordinator of.
+
+pub mod request;
+pub mod response;
+
+#[cfg(test)]
+mod test {
+    use bytes::Bytes;
+    use nombytes::NomBytes;
+
+    use super::*;
+    use crate::{encode::ToByte, error::KafkaCode, protocol};
+
+    #[test]
+    fn encode() {
+        let b = [
+            0, 66, 0, 0, 0, 0, 0, 1, 0, 4, 114, 117, 115, 116, 0, 2, 8, 79, 110, 103, 111, 105,
+            110, 103, 1, 0,
+        ];
+
+        let mut req = request::ListTransactionsRequest::new(1, "rust");
+        req.state_filters.push("Ongoing");
+
+        let mut buffer: Vec<u8> = vec![];
+        req.encode(&mut buffer).unwrap();
+
+        assert_eq!(buffer, b);
+    }
+
+    #[test]
+    fn parse() {
+        let b = [
+            0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 1, 2, 4, 116, 120, 110, 0, 0, 0, 0, 0, 0, 0, 42, 8,
+            79, 110, 103, 111, 105, 110, 103, 0, 0,
+        ];
+
+        let res = response::ListTransactionsResponse {
+            header: protocol::HeaderResponse { correlation_id: 1 },
+            throttle_time_ms: 0,
+            error_code: KafkaCode::None,
+            unknown_state_filters: vec![],
+            transaction_states: vec![response::TransactionState {
+                transactional_id: Bytes::from("txn"),
+                producer_id: 42,
+                transaction_state: Bytes::from("Ongoing"),
+            }],
+        };
+
+        let x =
+            response::parse_list_transactions_response(NomBytes::new(Bytes::copy_from_slice(&b)))
+                .unwrap()
+                .1;
+
+        assert_eq!(res, x);
+    }
+}
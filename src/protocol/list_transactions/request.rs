@@ -0,0 +1,81 @@
+//! Encoding and creation for List Transactions requests.
+//!
+//! ### Example
+//! ```rust
+//! let list_transactions_request = protocol::ListTransactionsRequest::new(
+//!     correlation_id,
+//!     client_id,
+//! );
+//! conn.send_request(&list_transactions_request).await?;
+//! ```
+//!
+//! ### Protocol Def
+//! ```text
+//! ListTransactions Request (Version: 0) => [state_filters] [producer_id_filters] TAG_BUFFER
+//!   state_filters => COMPACT_STRING
+//!   producer_id_filters => INT64
+//! ```
+//!
+//! Note that we are using version 0 of this API, which is a flexible version.
+
+use bytes::BufMut;
+
+use crate::{
+    encode::{encode_as_compact_array, CompactArray, CompactString, TaggedFields, ToByte},
+    error::Result,
+    protocol::HeaderRequest,
+};
+
+const API_KEY_LIST_TRANSACTIONS: i16 = 66;
+const API_VERSION: i16 = 0;
+
+/// The base List Transactions request object.
+///
+/// ### Example
+/// ```rust
+/// let list_transactions_request = protocol::ListTransactionsRequest::new(
+///     correlation_id,
+///     client_id,
+/// );
+/// conn.send_request(&list_transactions_request).await?;
+/// ```
+#[derive(Debug)]
+pub struct ListTransactionsRequest<'a> {
+    pub header: HeaderRequest<'a>,
+    /// The transaction states to filter by, e.g. "Ongoing". If empty, all transactions are returned.
+    pub state_filters: Vec<&'a str>,
+    /// The producer ids to filter by. If empty, all transactions are returned.
+    pub producer_id_filters: Vec<i64>,
+}
+
+impl<'a> ListTransactionsRequest<'a> {
+    /// Create a new List Transactions Request
+    ///
+    /// Without any filters, this lists every transaction the broker is
+    /// the coordinator of.
+    pub fn new(correlation_id: i32, client_id: &'a str) -> Self {
+        Self {
+            header: HeaderRequest::new(
+                API_KEY_LIST_TRANSACTIONS,
+                API_VERSION,
+                correlation_id,
+                client_id,
+            ),
+            state_filters: vec![],
+            producer_id_filters: vec![],
+        }
+    }
+}
+
+impl ToByte for ListTransactionsRequest<'_> {
+    fn encode<T: BufMut>(&self, buffer: &mut T) -> Result<()> {
+        tracing::trace!("Encoding ListTransactionsRequest {:?}", self);
+        self.header.encode_flexible(buffer)?;
+        encode_as_compact_array(buffer, &self.state_filters, |buffer, state| {
+            CompactString(state).encode(buffer)
+        })?;
+        CompactArray(&self.producer_id_filters).encode(buffer)?;
+        TaggedFields.encode(buffer)?;
+        Ok(())
+    }
+}
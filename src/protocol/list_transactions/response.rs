@@ -0,0 +1,128 @@
+//! Parsing and processing for List Transactions responses.
+//!
+//! ### Example
+//! ```rust
+//! let response_bytes = conn.receive_response().await?;
+//! let list_transactions_response = protocol::ListTransactionsResponse::try_from(response_bytes.freeze())?;
+//! ```
+//!
+//! ### Protocol Def
+//! ```text
+//! ListTransactions Response (Version: 0) => throttle_time_ms error_code [unknown_state_filters] [transaction_states] TAG_BUFFER
+//!   throttle_time_ms => INT32
+//!   error_code => INT16
+//!   unknown_state_filters => COMPACT_STRING
+//!   transaction_states => transactional_id producer_id transaction_state TAG_BUFFER
+//!     transactional_id => COMPACT_STRING
+//!     producer_id => INT64
+//!     transaction_state => COMPACT_STRING
+//! ```
+//!
+//! Note we are using version 0 of this response
+
+use bytes::Bytes;
+use nom::{
+    number::complete::{be_i32, be_i64},
+    IResult,
+};
+use nombytes::NomBytes;
+
+use crate::{
+    error::{Error, KafkaCode, Result},
+    parser::{self, parse_compact_array, parse_tagged_fields},
+    protocol::{parse_flexible_header_response, HeaderResponse},
+};
+
+/// The base List Transactions response object.
+///
+/// ### Example
+/// ```rust
+/// let response_bytes = conn.receive_response().await?;
+/// let list_transactions_response = protocol::ListTransactionsResponse::try_from(response_bytes.freeze())?;
+/// ```
+#[derive(Debug, PartialEq)]
+pub struct ListTransactionsResponse {
+    pub header: HeaderResponse,
+    /// The duration in milliseconds for which the request was throttled due to a quota violation, or zero if the request did not violate any quota.
+    pub throttle_time_ms: i32,
+    /// The error code, or 0 if there was no error.
+    pub error_code: KafkaCode,
+    /// State filters from the request that the broker does not know about.
+    pub unknown_state_filters: Vec<Bytes>,
+    /// The transactions matching the filters.
+    pub transaction_states: Vec<TransactionState>,
+}
+
+/// A transaction matching the filters.
+#[derive(Debug, PartialEq)]
+pub struct TransactionState {
+    pub transactional_id: Bytes,
+    pub producer_id: i64,
+    /// The current transaction state of the producer, e.g. "Ongoing".
+    pub transaction_state: Bytes,
+}
+
+// this helps us cast the server response into this type
+impl TryFrom<Bytes> for ListTransactionsResponse {
+    type Error = Error;
+
+    fn try_from(s: Bytes) -> Result<Self> {
+        tracing::trace!("Parsing ListTransactionsResponse {:?}", s);
+        let (_, list_transactions) = parse_list_transactions_response(NomBytes::new(s.clone()))
+            .map_err(|err| {
+                tracing::error!("ERROR: Failed parsing ListTransactionsResponse {:?}", err);
+                tracing::error!("ERROR: ListTransactionsResponse Bytes {:?}", s);
+                Error::ParsingError(s)
+            })?;
+        tracing::trace!("Parsed ListTransactionsResponse {:?}", list_transactions);
+        Ok(list_transactions)
+    }
+}
+
+impl ListTransactionsResponse {
+    /// Surface a KafkaError.
+    pub fn is_error(&self) -> Result<()> {
+        match self.error_code {
+            KafkaCode::None => Ok(()),
+            _ => Err(Error::KafkaError(self.error_code)),
+        }
+    }
+}
+
+pub fn parse_list_transactions_response(
+    s: NomBytes,
+) -> IResult<NomBytes, ListTransactionsResponse> {
+    let (s, header) = parse_flexible_header_response(s)?;
+    let (s, throttle_time_ms) = be_i32(s)?;
+    let (s, error_code) = parser::parse_kafka_code(s)?;
+    let (s, unknown_state_filters) = parse_compact_array(parser::parse_compact_string)(s)?;
+    let (s, transaction_states) = parse_compact_array(parse_transaction_state)(s)?;
+    let (s, _) = parse_tagged_fields(s)?;
+
+    Ok((
+        s,
+        ListTransactionsResponse {
+            header,
+            throttle_time_ms,
+            error_code,
+            unknown_state_filters,
+            transaction_states,
+        },
+    ))
+}
+
+fn parse_transaction_state(s: NomBytes) -> IResult<NomBytes, TransactionState> {
+    let (s, transactional_id) = parser::parse_compact_string(s)?;
+    let (s, producer_id) = be_i64(s)?;
+    let (s, transaction_state) = parser::parse_compact_string(s)?;
+    let (s, _) = parse_tagged_fields(s)?;
+
+    Ok((
+        s,
+        TransactionState {
+            transactional_id,
+            producer_id,
+            transaction_state,
+        },
+    ))
+}
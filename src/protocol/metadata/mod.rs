@@ -31,13 +31,17 @@ mod test {
 
     use super::response::*;
     use super::*;
-    use crate::{encode::ToByte, error::KafkaCode, protocol};
+    use crate::{
+        encode::ToByte,
+        error::KafkaCode,
+        protocol::{self, Describe},
+    };
 
     #[test]
     fn encode() {
         let b = [
-            0, 3, 0, 1, 0, 0, 0, 1, 0, 4, 114, 117, 115, 116, 0, 0, 0, 1, 0, 9, 112, 117, 114, 99,
-            104, 97, 115, 101, 115,
+            0, 3, 0, 7, 0, 0, 0, 1, 0, 4, 114, 117, 115, 116, 0, 0, 0, 1, 0, 9, 112, 117, 114, 99,
+            104, 97, 115, 101, 115, 1,
         ];
         let correlation_id = 1;
         let client_id = "rust";
@@ -54,7 +58,7 @@ mod test {
 
     #[test]
     fn parse() {
-        let buf = b"\0\0\0\x01\0\0\0\x02\0\0\0\x01\0\tlocalhost\0\0#\x84\xff\xff\0\0\0\x02\0\tlocalhost\0\0#\x85\xff\xff\0\0\0\x01\0\0\0\x01\0\0\0\tbenchmark\0\0\0\0\x03\0\0\0\0\0\0\0\0\0\x02\0\0\0\x01\0\0\0\x02\0\0\0\x01\0\0\0\x02\0\0\0\0\0\x01\0\0\0\x02\0\0\0\x01\0\0\0\x02\0\0\0\x01\0\0\0\x02\0\0\0\0\0\x02\0\0\0\x01\0\0\0\x01\0\0\0\x01\0\0\0\x01\0\0\0\x01";
+        let buf = b"\0\0\0\x01\0\0\0\0\0\0\0\x02\0\0\0\x01\0\tlocalhost\0\0#\x84\xff\xff\0\0\0\x02\0\tlocalhost\0\0#\x85\xff\xff\0\x07cluster\0\0\0\x01\0\0\0\x01\0\0\0\tbenchmark\0\0\0\0\x03\0\0\0\0\0\0\0\0\0\x02\0\0\0\x03\0\0\0\x01\0\0\0\x02\0\0\0\x01\0\0\0\x02\0\0\0\0\0\0\0\0\0\x01\0\0\0\x02\0\0\0\x01\0\0\0\x01\0\0\0\x02\0\0\0\x01\0\0\0\x02\0\0\0\0\0\0\0\0\0\x02\0\0\0\x01\0\0\0\x04\0\0\0\x01\0\0\0\x01\0\0\0\x01\0\0\0\x01\0\0\0\0";
         let res = test_metadata();
 
         let (_, parsed) =
@@ -62,9 +66,25 @@ mod test {
         assert_eq!(parsed, res);
     }
 
+    #[test]
+    fn describe() {
+        let description = test_metadata().describe();
+
+        assert!(description.starts_with("api key 3 unknown version\n"));
+        assert!(description.contains("2 brokers, controller 1"));
+        assert!(description.contains("topic benchmark: 3 partitions, None"));
+        // followed by every field
+        assert!(description.contains("leader_epoch: 4,"));
+
+        let topics = vec!["benchmark"];
+        let req = request::MetadataRequest::new(1, "rust", &topics);
+        assert!(req.describe().starts_with("api key 3 v7\n"));
+    }
+
     fn test_metadata() -> MetadataResponse {
         MetadataResponse {
             header_response: protocol::HeaderResponse { correlation_id: 1 },
+            throttle_time_ms: 0,
             brokers: vec![
                 Broker {
                     node_id: 1,
@@ -79,32 +99,40 @@ mod test {
                     rack: None,
                 },
             ],
+            cluster_id: Some(Bytes::from("cluster")),
             controller_id: 1,
             topics: vec![Topic {
                 error_code: KafkaCode::None,
                 name: Bytes::from("benchmark"),
+                topic_id: [0; 16],
                 is_internal: false,
                 partitions: vec![
                     Partition {
                         error_code: KafkaCode::None,
                         partition_index: 0,
                         leader_id: 2,
+                        leader_epoch: 3,
                         replica_nodes: vec![2],
                         isr_nodes: vec![2],
+                        offline_replicas: vec![],
                     },
                     Partition {
                         error_code: KafkaCode::None,
                         partition_index: 1,
                         leader_id: 2,
+                        leader_epoch: 1,
                         replica_nodes: vec![2],
                         isr_nodes: vec![2],
+                        offline_replicas: vec![],
                     },
                     Partition {
                         error_code: KafkaCode::None,
                         partition_index: 2,
                         leader_id: 1,
+                        leader_epoch: 4,
                         replica_nodes: vec![1],
                         isr_nodes: vec![1],
+                        offline_replicas: vec![],
                     },
                 ],
             }],
@@ -8,23 +8,43 @@
 //!
 //! ### Protocol Def
 //! ```text
-//! Metadata Request (Version: 1) => [topics]
+//! Metadata Request (Version: 7) => [topics] allow_auto_topic_creation
 //!   topics => name
 //!     name => STRING
+//!   allow_auto_topic_creation => BOOLEAN
+//!
+//! Metadata Request (Version: 10) => [topics] allow_auto_topic_creation include_cluster_authorized_operations include_topic_authorized_operations TAG_BUFFER
+//!   topics => topic_id name TAG_BUFFER
+//!     topic_id => UUID
+//!     name => COMPACT_NULLABLE_STRING
+//!   allow_auto_topic_creation => BOOLEAN
+//!   include_cluster_authorized_operations => BOOLEAN
+//!   include_topic_authorized_operations => BOOLEAN
 //! ```
 //!
-//! Note we are using version 0 of the request.
+//! Note we are using version 7 of the request by default. Versions 9 and up
+//! are flexible, and from version 10 the response carries the topic ids
+//! needed by topic id based [Fetch](crate::protocol::fetch) requests.
 
 use bytes::BufMut;
 
 use crate::{
-    encode::{AsStrings, ToByte},
+    encode::{encode_as_compact_array, AsStrings, CompactString, RawBytes, TaggedFields, ToByte},
     error::Result,
-    protocol::HeaderRequest,
+    protocol::{Describe, HeaderRequest, RequestHeader},
 };
 
-const API_KEY_METADATA: i16 = 3;
-const API_VERSION: i16 = 1;
+pub const API_KEY_METADATA: i16 = 3;
+/// The version of the request sent unless another one is negotiated.
+pub const API_VERSION: i16 = 7;
+/// The first version adding the authorized operations flags.
+const FIRST_AUTHORIZED_OPERATIONS_VERSION: i16 = 8;
+/// The first version of the Metadata request using the flexible encoding.
+pub const FIRST_FLEXIBLE_VERSION: i16 = 9;
+/// The first version where topics are identified by a topic id as well.
+pub const FIRST_TOPIC_ID_VERSION: i16 = 10;
+/// The last version still asking for the cluster authorized operations.
+const LAST_CLUSTER_AUTHORIZED_OPERATIONS_VERSION: i16 = 10;
 
 /// The base Metadata request object.
 ///
@@ -39,6 +59,8 @@ pub struct MetadataRequest<'a, T> {
     /// The topics to fetch metadata for.
     /// Kafka expects the topic to be None for it to return all topics
     pub topics: Option<&'a [T]>,
+    /// If this is true, the broker may auto-create topics that we requested which do not already exist, if it is configured to do so.
+    pub allow_auto_topic_creation: bool,
 }
 
 impl<'a, T: AsRef<str>> MetadataRequest<'a, T> {
@@ -53,21 +75,62 @@ impl<'a, T: AsRef<str>> MetadataRequest<'a, T> {
         MetadataRequest {
             header: HeaderRequest::new(API_KEY_METADATA, API_VERSION, correlation_id, client_id),
             topics,
+            allow_auto_topic_creation: true,
         }
     }
 }
 
 impl<'a, T: AsRef<str> + 'a> ToByte for MetadataRequest<'a, T> {
     fn encode<W: BufMut>(&self, buffer: &mut W) -> Result<()> {
-        self.header.encode(buffer)?;
+        let api_version = self.header.api_version;
+        RequestHeader::for_api_version(self.header.clone(), FIRST_FLEXIBLE_VERSION)
+            .encode(buffer)?;
 
         match self.topics {
+            Some(topics) if api_version >= FIRST_FLEXIBLE_VERSION => {
+                encode_as_compact_array(buffer, topics, |buffer, topic| {
+                    if api_version >= FIRST_TOPIC_ID_VERSION {
+                        // topics are looked up by name, the id is left null
+                        RawBytes(&[0; 16]).encode(buffer)?;
+                    }
+                    CompactString(topic.as_ref()).encode(buffer)?;
+                    TaggedFields.encode(buffer)
+                })?
+            }
             Some(topics) => AsStrings(topics).encode(buffer)?,
+            None if api_version >= FIRST_FLEXIBLE_VERSION => {
+                // a compact null array has a length of 0
+                buffer.put_u8(0);
+            }
             None => {
                 // Kafka protocol uses -1 to signal a null array
                 buffer.put_i32(-1);
             }
         }
+        self.allow_auto_topic_creation.encode(buffer)?;
+        if (FIRST_AUTHORIZED_OPERATIONS_VERSION..=LAST_CLUSTER_AUTHORIZED_OPERATIONS_VERSION)
+            .contains(&api_version)
+        {
+            // include_cluster_authorized_operations
+            false.encode(buffer)?;
+        }
+        if api_version >= FIRST_AUTHORIZED_OPERATIONS_VERSION {
+            // include_topic_authorized_operations
+            false.encode(buffer)?;
+        }
+        if api_version >= FIRST_FLEXIBLE_VERSION {
+            TaggedFields.encode(buffer)?;
+        }
         Ok(())
     }
 }
+
+impl<T: std::fmt::Debug> Describe for MetadataRequest<'_, T> {
+    fn api_key(&self) -> i16 {
+        self.header.api_key
+    }
+
+    fn api_version(&self) -> Option<i16> {
+        Some(self.header.api_version)
+    }
+}
@@ -13,24 +13,57 @@
 //!
 //! ### Protocol Def
 //! ```text
-//! Metadata Response (Version: 1) => [brokers] controller_id [topics]
+//! Metadata Response (Version: 7) => throttle_time_ms [brokers] cluster_id controller_id [topics]
+//!   throttle_time_ms => INT32
 //!   brokers => node_id host port rack
 //!     node_id => INT32
 //!     host => STRING
 //!     port => INT32
 //!     rack => NULLABLE_STRING
+//!   cluster_id => NULLABLE_STRING
 //!   controller_id => INT32
 //!   topics => error_code name is_internal [partitions]
 //!     error_code => INT16
 //!     name => STRING
 //!     is_internal => BOOLEAN
-//!     partitions => error_code partition_index leader_id [replica_nodes] [isr_nodes]
+//!     partitions => error_code partition_index leader_id leader_epoch [replica_nodes] [isr_nodes] [offline_replicas]
 //!       error_code => INT16
 //!       partition_index => INT32
 //!       leader_id => INT32
+//!       leader_epoch => INT32
 //!       replica_nodes => INT32
 //!       isr_nodes => INT32
+//!       offline_replicas => INT32
+//!
+//! Metadata Response (Version: 10) => throttle_time_ms [brokers] cluster_id controller_id [topics] cluster_authorized_operations TAG_BUFFER
+//!   throttle_time_ms => INT32
+//!   brokers => node_id host port rack TAG_BUFFER
+//!     node_id => INT32
+//!     host => COMPACT_STRING
+//!     port => INT32
+//!     rack => COMPACT_NULLABLE_STRING
+//!   cluster_id => COMPACT_NULLABLE_STRING
+//!   controller_id => INT32
+//!   topics => error_code name topic_id is_internal [partitions] topic_authorized_operations TAG_BUFFER
+//!     error_code => INT16
+//!     name => COMPACT_STRING
+//!     topic_id => UUID
+//!     is_internal => BOOLEAN
+//!     partitions => error_code partition_index leader_id leader_epoch [replica_nodes] [isr_nodes] [offline_replicas] TAG_BUFFER
+//!       error_code => INT16
+//!       partition_index => INT32
+//!       leader_id => INT32
+//!       leader_epoch => INT32
+//!       replica_nodes => INT32
+//!       isr_nodes => INT32
+//!       offline_replicas => INT32
+//!     topic_authorized_operations => INT32
+//!   cluster_authorized_operations => INT32
 //! ```
+//!
+//! Versions 7 through 12 are parsed with
+//! [`MetadataResponse::try_from_version`], the name of a topic is nullable
+//! from version 12.
 
 use bytes::Bytes;
 use nom::{number::complete::be_i32, IResult};
@@ -38,9 +71,21 @@ use nombytes::NomBytes;
 
 use crate::{
     error::{Error, KafkaCode, Result},
-    parser, protocol,
+    parser::{self, parse_compact_array, parse_tagged_fields},
+    protocol::{
+        self,
+        metadata::request::{API_KEY_METADATA, FIRST_FLEXIBLE_VERSION, FIRST_TOPIC_ID_VERSION},
+        Describe,
+    },
 };
 
+/// The first version adding the authorized operations to the response.
+const FIRST_AUTHORIZED_OPERATIONS_VERSION: i16 = 8;
+/// The last version returning the cluster authorized operations.
+const LAST_CLUSTER_AUTHORIZED_OPERATIONS_VERSION: i16 = 10;
+/// The first version where the name of a topic is nullable.
+const FIRST_NULLABLE_NAME_VERSION: i16 = 12;
+
 /// The base Metadata response object.
 ///
 /// ### Example
@@ -51,8 +96,12 @@ use crate::{
 #[derive(Debug, Default, PartialEq)]
 pub struct MetadataResponse {
     pub header_response: protocol::HeaderResponse,
+    /// The duration in milliseconds for which the request was throttled due to a quota violation, or zero if the request did not violate any quota.
+    pub throttle_time_ms: i32,
     /// Each broker in the response.
     pub brokers: Vec<Broker>,
+    /// The cluster ID that responding broker belongs to.
+    pub cluster_id: Option<Bytes>,
     /// The ID of the controller broker.
     pub controller_id: i32,
     /// Each topic in the response.
@@ -85,21 +134,79 @@ impl TryFrom<Bytes> for MetadataResponse {
     }
 }
 
+impl MetadataResponse {
+    /// Parse the response to a Metadata request sent with the given api
+    /// version.
+    pub fn try_from_version(s: Bytes, api_version: i16) -> Result<Self> {
+        tracing::trace!("Parsing MetadataResponse v{} {:?}", api_version, s);
+        let (_, metadata) = parse_metadata_response_version(api_version)(NomBytes::new(s.clone()))
+            .map_err(|err| {
+                tracing::error!("ERROR: Failed parsing MetadataResponse {:?}", err);
+                tracing::error!("ERROR: MetadataResponse Bytes {:?}", s);
+                Error::ParsingError(s)
+            })?;
+        tracing::trace!("Parsed MetadataResponse {:?}", metadata);
+        Ok(metadata)
+    }
+}
+
 pub fn parse_metadata_response(s: NomBytes) -> IResult<NomBytes, MetadataResponse> {
-    let (s, header_response) = protocol::parse_header_response(s)?;
-    let (s, brokers) = parser::parse_array(parse_broker)(s)?;
-    let (s, controller_id) = be_i32(s)?;
-    let (s, topics) = parser::parse_array(parse_topic)(s)?;
+    parse_metadata_response_version(protocol::metadata::request::API_VERSION)(s)
+}
 
-    Ok((
-        s,
-        MetadataResponse {
-            header_response,
-            brokers,
-            controller_id,
-            topics,
-        },
-    ))
+pub fn parse_metadata_response_version(
+    api_version: i16,
+) -> impl Fn(NomBytes) -> IResult<NomBytes, MetadataResponse> {
+    move |s: NomBytes| {
+        let flexible = api_version >= FIRST_FLEXIBLE_VERSION;
+        let (s, header_response) = if flexible {
+            protocol::parse_flexible_header_response(s)?
+        } else {
+            protocol::parse_header_response(s)?
+        };
+        let (s, throttle_time_ms) = be_i32(s)?;
+        let (s, brokers) = if flexible {
+            parse_compact_array(parse_flexible_broker)(s)?
+        } else {
+            parser::parse_array(parse_broker)(s)?
+        };
+        let (s, cluster_id) = if flexible {
+            parser::parse_compact_nullable_string(s)?
+        } else {
+            parser::parse_nullable_string(s)?
+        };
+        let (s, controller_id) = be_i32(s)?;
+        let (s, topics) = if flexible {
+            parse_compact_array(parse_topic_version(api_version))(s)?
+        } else {
+            parser::parse_array(parse_topic_version(api_version))(s)?
+        };
+        let (s, _cluster_authorized_operations) = if (FIRST_AUTHORIZED_OPERATIONS_VERSION
+            ..=LAST_CLUSTER_AUTHORIZED_OPERATIONS_VERSION)
+            .contains(&api_version)
+        {
+            be_i32(s)?
+        } else {
+            (s, 0)
+        };
+        let (s, _) = if flexible {
+            parse_tagged_fields(s)?
+        } else {
+            (s, ())
+        };
+
+        Ok((
+            s,
+            MetadataResponse {
+                header_response,
+                throttle_time_ms,
+                brokers,
+                cluster_id,
+                controller_id,
+                topics,
+            },
+        ))
+    }
 }
 
 /// Each broker in the response.
@@ -132,6 +239,24 @@ fn parse_broker(s: NomBytes) -> IResult<NomBytes, Broker> {
     ))
 }
 
+fn parse_flexible_broker(s: NomBytes) -> IResult<NomBytes, Broker> {
+    let (s, node_id) = be_i32(s)?;
+    let (s, host) = parser::parse_compact_string(s)?;
+    let (s, port) = be_i32(s)?;
+    let (s, rack) = parser::parse_compact_nullable_string(s)?;
+    let (s, _) = parse_tagged_fields(s)?;
+
+    Ok((
+        s,
+        Broker {
+            node_id,
+            host,
+            port,
+            rack,
+        },
+    ))
+}
+
 /// Each topic in the response.
 #[derive(Debug, Clone, PartialEq)]
 pub struct Topic {
@@ -139,6 +264,8 @@ pub struct Topic {
     pub error_code: KafkaCode,
     /// The topic name.
     pub name: Bytes,
+    /// The topic id, all zeros before version 10.
+    pub topic_id: [u8; 16],
     /// True if the topic is internal.
     pub is_internal: bool,
     /// Each partition in the topic.
@@ -165,21 +292,52 @@ impl Topic {
     }
 }
 
-fn parse_topic(s: NomBytes) -> IResult<NomBytes, Topic> {
-    let (s, error_code) = parser::parse_kafka_code(s)?;
-    let (s, name) = parser::parse_string(s)?;
-    let (s, is_internal) = parser::parse_boolean(s)?;
-    let (s, partitions) = parser::parse_array(parse_partition)(s)?;
+fn parse_topic_version(api_version: i16) -> impl Fn(NomBytes) -> IResult<NomBytes, Topic> + Copy {
+    move |s: NomBytes| {
+        let flexible = api_version >= FIRST_FLEXIBLE_VERSION;
+        let (s, error_code) = parser::parse_kafka_code(s)?;
+        let (s, name) = if api_version >= FIRST_NULLABLE_NAME_VERSION {
+            let (s, name) = parser::parse_compact_nullable_string(s)?;
+            (s, name.unwrap_or_default())
+        } else if flexible {
+            parser::parse_compact_string(s)?
+        } else {
+            parser::parse_string(s)?
+        };
+        let (s, topic_id) = if api_version >= FIRST_TOPIC_ID_VERSION {
+            parser::parse_uuid(s)?
+        } else {
+            (s, [0; 16])
+        };
+        let (s, is_internal) = parser::parse_boolean(s)?;
+        let (s, partitions) = if flexible {
+            parse_compact_array(parse_flexible_partition)(s)?
+        } else {
+            parser::parse_array(parse_partition)(s)?
+        };
+        let (s, _topic_authorized_operations) =
+            if api_version >= FIRST_AUTHORIZED_OPERATIONS_VERSION {
+                be_i32(s)?
+            } else {
+                (s, 0)
+            };
+        let (s, _) = if flexible {
+            parse_tagged_fields(s)?
+        } else {
+            (s, ())
+        };
 
-    Ok((
-        s,
-        Topic {
-            error_code,
-            name,
-            is_internal,
-            partitions,
-        },
-    ))
+        Ok((
+            s,
+            Topic {
+                error_code,
+                name,
+                topic_id,
+                is_internal,
+                partitions,
+            },
+        ))
+    }
 }
 
 /// Each partition in the topic.
@@ -191,13 +349,23 @@ pub struct Partition {
     pub partition_index: i32,
     /// The ID of the leader broker.
     pub leader_id: i32,
+    /// The leader epoch of this partition.
+    pub leader_epoch: i32,
     /// The set of all nodes that host this partition.
     pub replica_nodes: Vec<i32>,
     /// The set of nodes that are in sync with the leader for this partition.
     pub isr_nodes: Vec<i32>,
+    /// The set of offline replicas of this partition.
+    pub offline_replicas: Vec<i32>,
 }
 
 impl Partition {
+    /// Whether the partition has a leader to route requests to, it does not
+    /// while a leader election is in progress.
+    pub fn has_leader(&self) -> bool {
+        self.leader_id >= 0 && self.error_code != KafkaCode::LeaderNotAvailable
+    }
+
     pub fn is_error(&self, topic_name: Bytes) -> Result<()> {
         if self.error_code != KafkaCode::None {
             tracing::error!(
@@ -217,8 +385,34 @@ fn parse_partition(s: NomBytes) -> IResult<NomBytes, Partition> {
     let (s, error_code) = parser::parse_kafka_code(s)?;
     let (s, partition_index) = be_i32(s)?;
     let (s, leader_id) = be_i32(s)?;
+    let (s, leader_epoch) = be_i32(s)?;
     let (s, replica_nodes) = parser::parse_array(be_i32)(s)?;
     let (s, isr_nodes) = parser::parse_array(be_i32)(s)?;
+    let (s, offline_replicas) = parser::parse_array(be_i32)(s)?;
+
+    Ok((
+        s,
+        Partition {
+            error_code,
+            partition_index,
+            leader_id,
+            leader_epoch,
+            replica_nodes,
+            isr_nodes,
+            offline_replicas,
+        },
+    ))
+}
+
+fn parse_flexible_partition(s: NomBytes) -> IResult<NomBytes, Partition> {
+    let (s, error_code) = parser::parse_kafka_code(s)?;
+    let (s, partition_index) = be_i32(s)?;
+    let (s, leader_id) = be_i32(s)?;
+    let (s, leader_epoch) = be_i32(s)?;
+    let (s, replica_nodes) = parse_compact_array(be_i32)(s)?;
+    let (s, isr_nodes) = parse_compact_array(be_i32)(s)?;
+    let (s, offline_replicas) = parse_compact_array(be_i32)(s)?;
+    let (s, _) = parse_tagged_fields(s)?;
 
     Ok((
         s,
@@ -226,8 +420,33 @@ fn parse_partition(s: NomBytes) -> IResult<NomBytes, Partition> {
             error_code,
             partition_index,
             leader_id,
+            leader_epoch,
             replica_nodes,
             isr_nodes,
+            offline_replicas,
         },
     ))
 }
+
+impl Describe for MetadataResponse {
+    fn api_key(&self) -> i16 {
+        API_KEY_METADATA
+    }
+
+    fn summary(&self) -> Vec<String> {
+        let mut summary = vec![format!(
+            "{} brokers, controller {}",
+            self.brokers.len(),
+            self.controller_id
+        )];
+        for topic in &self.topics {
+            summary.push(format!(
+                "topic {}: {} partitions, {:?}",
+                String::from_utf8_lossy(&topic.name),
+                topic.partitions.len(),
+                topic.error_code
+            ));
+        }
+        summary
+    }
+}
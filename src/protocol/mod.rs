@@ -10,17 +10,29 @@
 //! will be sent to the broker. The response files hold the logic for parsing
 //! and processing the messages coming from the broker.
 
+pub mod acl;
+pub mod add_partitions_to_txn;
+pub mod api_versions;
 pub mod commit_offset;
+pub mod create_acls;
 pub mod create_topics;
+pub mod delete_acls;
 pub mod delete_topics;
+pub mod describe_acls;
+pub mod describe_producers;
+pub mod describe_transactions;
+pub mod end_txn;
 pub mod fetch;
 pub mod find_coordinator;
 pub mod heartbeat;
+pub mod init_producer_id;
 pub mod join_group;
 pub mod leave_group;
 pub mod list_offsets;
+pub mod list_transactions;
 pub mod metadata;
 pub mod offset_fetch;
+pub mod offset_for_leader_epoch;
 pub mod produce;
 pub mod sasl_authenticate;
 pub mod sasl_handshake;
@@ -32,17 +44,37 @@ use nombytes::NomBytes;
 
 // re exporting these for ease
 pub use self::{
+    add_partitions_to_txn::{
+        request::AddPartitionsToTxnRequest, response::AddPartitionsToTxnResponse,
+    },
+    api_versions::{request::ApiVersionsRequest, response::ApiVersionsResponse},
     commit_offset::{request::OffsetCommitRequest, response::OffsetCommitResponse},
+    create_acls::{request::CreateAclsRequest, response::CreateAclsResponse},
     create_topics::{request::CreateTopicsRequest, response::CreateTopicsResponse},
+    delete_acls::{request::DeleteAclsRequest, response::DeleteAclsResponse},
     delete_topics::{request::DeleteTopicsRequest, response::DeleteTopicsResponse},
+    describe_acls::{request::DescribeAclsRequest, response::DescribeAclsResponse},
+    describe_producers::{request::DescribeProducersRequest, response::DescribeProducersResponse},
+    describe_transactions::{
+        request::DescribeTransactionsRequest, response::DescribeTransactionsResponse,
+    },
+    end_txn::{request::EndTxnRequest, response::EndTxnResponse},
     fetch::{request::FetchRequest, response::FetchResponse},
-    find_coordinator::{request::FindCoordinatorRequest, response::FindCoordinatorResponse},
+    find_coordinator::{
+        request::{CoordinatorType, FindCoordinatorRequest},
+        response::FindCoordinatorResponse,
+    },
     heartbeat::{request::HeartbeatRequest, response::HeartbeatResponse},
+    init_producer_id::{request::InitProducerIdRequest, response::InitProducerIdResponse},
     join_group::{request::JoinGroupRequest, response::JoinGroupResponse},
     leave_group::{request::LeaveGroupRequest, response::LeaveGroupResponse},
     list_offsets::{request::ListOffsetsRequest, response::ListOffsetsResponse},
+    list_transactions::{request::ListTransactionsRequest, response::ListTransactionsResponse},
     metadata::{request::MetadataRequest, response::MetadataResponse},
     offset_fetch::{request::OffsetFetchRequest, response::OffsetFetchResponse},
+    offset_for_leader_epoch::{
+        request::OffsetForLeaderEpochRequest, response::OffsetForLeaderEpochResponse,
+    },
     produce::{
         request::{Header, ProduceRequest},
         response::ProduceResponse,
@@ -54,7 +86,45 @@ pub use self::{
         response::SyncGroupResponse,
     },
 };
-use crate::{encode::ToByte, error::Result};
+use crate::{
+    encode::{TaggedFields, ToByte},
+    error::{Error, Result},
+    parser::parse_tagged_fields,
+};
+
+/// A readable dump of a decoded request or response, to debug protocol
+/// mismatches and to paste into issue reports.
+pub trait Describe: std::fmt::Debug {
+    /// The API key of the message.
+    fn api_key(&self) -> i16;
+
+    /// The API version of the message, when it is known. Responses do not
+    /// carry their version.
+    fn api_version(&self) -> Option<i16> {
+        None
+    }
+
+    /// A few lines summing up the message, e.g. the partitions of each topic.
+    fn summary(&self) -> Vec<String> {
+        vec![]
+    }
+
+    /// The API key and version, the summary, then every field as an
+    /// indented tree.
+    fn describe(&self) -> String {
+        let version = match self.api_version() {
+            Some(api_version) => format!("v{}", api_version),
+            None => "unknown version".to_owned(),
+        };
+        let mut description = format!("api key {} {}\n", self.api_key(), version);
+        for line in self.summary() {
+            description.push_str(&line);
+            description.push('\n');
+        }
+        description.push_str(&format!("{:#?}", self));
+        description
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct HeaderRequest<'a> {
@@ -87,6 +157,15 @@ impl<'a> HeaderRequest<'a> {
     }
 }
 
+impl HeaderRequest<'_> {
+    /// Encode the header used by flexible versions of an API, which ends
+    /// with tagged fields.
+    pub fn encode_flexible<W: BufMut>(&self, buffer: &mut W) -> Result<()> {
+        self.encode(buffer)?;
+        TaggedFields.encode(buffer)
+    }
+}
+
 impl ToByte for HeaderRequest<'_> {
     fn encode<W: BufMut>(&self, buffer: &mut W) -> Result<()> {
         self.api_key.encode(buffer)?;
@@ -97,6 +176,52 @@ impl ToByte for HeaderRequest<'_> {
     }
 }
 
+/// A request header encoded in a given header version.
+///
+/// Version 0 has no client id, version 1 adds it and version 2, used by the
+/// flexible versions of an API, ends with tagged fields.
+#[derive(Debug, Clone)]
+pub struct RequestHeader<'a> {
+    pub header: HeaderRequest<'a>,
+    /// The version of the header, not of the API.
+    pub version: i16,
+}
+
+impl<'a> RequestHeader<'a> {
+    pub fn new(header: HeaderRequest<'a>, version: i16) -> RequestHeader<'a> {
+        RequestHeader { header, version }
+    }
+
+    /// The header for the API version of the request, given the first
+    /// flexible version of the API.
+    pub fn for_api_version(
+        header: HeaderRequest<'a>,
+        first_flexible_version: i16,
+    ) -> RequestHeader<'a> {
+        let version = if header.api_version >= first_flexible_version {
+            2
+        } else {
+            1
+        };
+        RequestHeader { header, version }
+    }
+}
+
+impl ToByte for RequestHeader<'_> {
+    fn encode<W: BufMut>(&self, buffer: &mut W) -> Result<()> {
+        match self.version {
+            0 => {
+                self.header.api_key.encode(buffer)?;
+                self.header.api_version.encode(buffer)?;
+                self.header.correlation_id.encode(buffer)
+            }
+            1 => self.header.encode(buffer),
+            2 => self.header.encode_flexible(buffer),
+            _ => Err(Error::EncodingError),
+        }
+    }
+}
+
 #[derive(Debug, Default, PartialEq)]
 pub struct HeaderResponse {
     /// The correlation ID of this response.
@@ -107,3 +232,71 @@ pub fn parse_header_response(s: NomBytes) -> IResult<NomBytes, HeaderResponse> {
     let (s, correlation_id) = be_i32(s)?;
     Ok((s, HeaderResponse { correlation_id }))
 }
+
+/// Parse the header of responses in flexible versions, which ends with
+/// tagged fields.
+pub fn parse_flexible_header_response(s: NomBytes) -> IResult<NomBytes, HeaderResponse> {
+    let (s, header) = parse_header_response(s)?;
+    let (s, _) = parse_tagged_fields(s)?;
+    Ok((s, header))
+}
+
+/// Parse a response header of a given header version. Version 1, used by
+/// the flexible versions of an API, ends with tagged fields.
+pub fn parse_versioned_header_response(
+    s: NomBytes,
+    version: i16,
+) -> IResult<NomBytes, HeaderResponse> {
+    if version >= 1 {
+        parse_flexible_header_response(s)
+    } else {
+        parse_header_response(s)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bytes::Bytes;
+
+    use super::*;
+
+    #[test]
+    fn encode_request_header_versions() {
+        let header = HeaderRequest::new(3, 12, 1, "rust");
+
+        let mut v0 = vec![];
+        RequestHeader::new(header.clone(), 0)
+            .encode(&mut v0)
+            .unwrap();
+        assert_eq!(v0, [0, 3, 0, 12, 0, 0, 0, 1]);
+
+        let mut v1 = vec![];
+        RequestHeader::new(header.clone(), 1)
+            .encode(&mut v1)
+            .unwrap();
+        assert_eq!(v1, [0, 3, 0, 12, 0, 0, 0, 1, 0, 4, 114, 117, 115, 116]);
+
+        // flexible headers end with an empty tag buffer
+        let mut v2 = vec![];
+        RequestHeader::for_api_version(header, 9)
+            .encode(&mut v2)
+            .unwrap();
+        assert_eq!(v2, [0, 3, 0, 12, 0, 0, 0, 1, 0, 4, 114, 117, 115, 116, 0]);
+    }
+
+    #[test]
+    fn parse_response_header_versions() {
+        let b = [0, 0, 0, 7, 0, 42];
+
+        let (s, header) =
+            parse_versioned_header_response(NomBytes::new(Bytes::copy_from_slice(&b)), 0).unwrap();
+        assert_eq!(header.correlation_id, 7);
+        assert_eq!(s.into_bytes(), Bytes::from_static(&[0, 42]));
+
+        // the empty tag buffer is consumed with the header
+        let (s, header) =
+            parse_versioned_header_response(NomBytes::new(Bytes::copy_from_slice(&b)), 1).unwrap();
+        assert_eq!(header.correlation_id, 7);
+        assert_eq!(s.into_bytes(), Bytes::from_static(&[42]));
+    }
+}
@@ -0,0 +1,45 @@
+//! Find the end offset of a leader epoch, to detect log truncation.
+
+pub mod request;
+pub mod response;
+
+#[cfg(test)]
+mod test {
+    use bytes::Bytes;
+
+    use super::*;
+    use crate::{encode::ToByte, error::KafkaCode};
+
+    #[test]
+    fn encode() {
+        let b = [
+            0, 23, 0, 3, 0, 0, 0, 1, 0, 4, 114, 117, 115, 116, 255, 255, 255, 255, 0, 0, 0, 1, 0,
+            9, 112, 117, 114, 99, 104, 97, 115, 101, 115, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 5, 0, 0,
+            0, 4,
+        ];
+
+        let mut req = request::OffsetForLeaderEpochRequest::new(1, "rust");
+        req.add("purchases", 0, 5, 4);
+        // wont allow duplicates
+        req.add("purchases", 0, 5, 4);
+
+        let mut buffer: Vec<u8> = vec![];
+        req.encode(&mut buffer).unwrap();
+
+        assert_eq!(buffer, b);
+    }
+
+    #[test]
+    fn parse() {
+        let b = b"\0\0\0\x01\0\0\0\0\0\0\0\x01\0\tpurchases\0\0\0\x01\0\0\0\0\0\0\0\0\0\x03\0\0\0\0\0\0\0\x05";
+
+        let res = response::OffsetForLeaderEpochResponse::try_from(Bytes::from_static(b)).unwrap();
+
+        let (topic_name, partition) = res.into_box_iter().next().unwrap();
+        assert_eq!(topic_name, Bytes::from_static(b"purchases"));
+        assert_eq!(partition.error_code, KafkaCode::None);
+        assert_eq!(partition.partition, 0);
+        assert_eq!(partition.leader_epoch, 3);
+        assert_eq!(partition.end_offset, 5);
+    }
+}
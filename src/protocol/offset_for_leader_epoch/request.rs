@@ -0,0 +1,139 @@
+//! Encoding and creation for Offset For Leader Epoch requests.
+//!
+//! Used to look up where a leader epoch ends in the log of a partition. A
+//! consumer holding an offset together with the epoch of the record before
+//! it compares the two to find out whether the log was truncated under it.
+//!
+//! ### Example
+//! ```rust
+//! let mut request = protocol::OffsetForLeaderEpochRequest::new(correlation_id, client_id);
+//! request.add("purchases", 0, 5, 4);
+//! broker_conn.send_request(&request).await?;
+//! ```
+//!
+//! ### Protocol Def
+//! ```text
+//! OffsetForLeaderEpoch Request (Version: 3) => replica_id [topics]
+//!   replica_id => INT32
+//!   topics => topic [partitions]
+//!     topic => STRING
+//!     partitions => partition current_leader_epoch leader_epoch
+//!       partition => INT32
+//!       current_leader_epoch => INT32
+//!       leader_epoch => INT32
+//! ```
+//!
+//! Note we are using version 3 of the request.
+
+use crate::{
+    encode::{try_usize_to_int, ToByte},
+    protocol::HeaderRequest,
+};
+
+const API_KEY_OFFSET_FOR_LEADER_EPOCH: i16 = 23;
+const API_VERSION: i16 = 3;
+
+/// The base Offset For Leader Epoch request object.
+///
+/// ### Example
+/// ```rust
+/// let mut request = protocol::OffsetForLeaderEpochRequest::new(correlation_id, client_id);
+/// request.add("purchases", 0, 5, 4);
+/// broker_conn.send_request(&request).await?;
+/// ```
+#[derive(Debug)]
+pub struct OffsetForLeaderEpochRequest<'a> {
+    pub header: HeaderRequest<'a>,
+    /// The broker ID of the follower, or -1 if this request is from a consumer.
+    pub replica_id: i32,
+    /// Each topic to get offsets for.
+    pub topics: Vec<Topic<'a>>,
+}
+
+/// Each topic to get offsets for.
+#[derive(Debug)]
+pub struct Topic<'a> {
+    /// The topic name.
+    pub name: &'a str,
+    /// Each partition to get offsets for.
+    pub partitions: Vec<Partition>,
+}
+
+/// Each partition to get offsets for.
+#[derive(Debug)]
+pub struct Partition {
+    /// The partition index.
+    pub partition: i32,
+    /// The leader epoch known to the client, or -1. The broker rejects the request with FENCED_LEADER_EPOCH or UNKNOWN_LEADER_EPOCH when it does not match.
+    pub current_leader_epoch: i32,
+    /// The epoch to look up an offset for.
+    pub leader_epoch: i32,
+}
+
+impl<'a> OffsetForLeaderEpochRequest<'a> {
+    pub fn new(correlation_id: i32, client_id: &'a str) -> Self {
+        let header = HeaderRequest::new(
+            API_KEY_OFFSET_FOR_LEADER_EPOCH,
+            API_VERSION,
+            correlation_id,
+            client_id,
+        );
+        Self {
+            header,
+            replica_id: -1,
+            topics: vec![],
+        }
+    }
+
+    pub fn add(
+        &mut self,
+        topic_name: &'a str,
+        partition: i32,
+        current_leader_epoch: i32,
+        leader_epoch: i32,
+    ) {
+        let new_partition = Partition {
+            partition,
+            current_leader_epoch,
+            leader_epoch,
+        };
+        match self
+            .topics
+            .iter_mut()
+            .find(|topic| topic.name == topic_name)
+        {
+            None => self.topics.push(Topic {
+                name: topic_name,
+                partitions: vec![new_partition],
+            }),
+            Some(topic) => {
+                if !topic
+                    .partitions
+                    .iter()
+                    .any(|existing| existing.partition == partition)
+                {
+                    topic.partitions.push(new_partition)
+                }
+            }
+        }
+    }
+}
+
+impl ToByte for OffsetForLeaderEpochRequest<'_> {
+    fn encode<T: bytes::BufMut>(&self, buffer: &mut T) -> crate::error::Result<()> {
+        tracing::trace!("Encoding OffsetForLeaderEpochRequest {:?}", self);
+        self.header.encode(buffer)?;
+        self.replica_id.encode(buffer)?;
+        try_usize_to_int!(self.topics.len(), i32).encode(buffer)?;
+        for topic in &self.topics {
+            topic.name.encode(buffer)?;
+            try_usize_to_int!(topic.partitions.len(), i32).encode(buffer)?;
+            for partition in &topic.partitions {
+                partition.partition.encode(buffer)?;
+                partition.current_leader_epoch.encode(buffer)?;
+                partition.leader_epoch.encode(buffer)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,144 @@
+//! Parsing and processing for Offset For Leader Epoch responses.
+//!
+//! ### Example
+//! ```rust
+//! let response_bytes = broker_conn.receive_response().await?;
+//! let response = protocol::OffsetForLeaderEpochResponse::try_from(response_bytes.freeze())?;
+//! ```
+//!
+//! ### Protocol Def
+//! ```text
+//! OffsetForLeaderEpoch Response (Version: 3) => throttle_time_ms [topics]
+//!   throttle_time_ms => INT32
+//!   topics => topic [partitions]
+//!     topic => STRING
+//!     partitions => error_code partition leader_epoch end_offset
+//!       error_code => INT16
+//!       partition => INT32
+//!       leader_epoch => INT32
+//!       end_offset => INT64
+//! ```
+//!
+//! Note we are using version 3 of the response.
+
+use bytes::Bytes;
+use nom::{
+    number::complete::{be_i32, be_i64},
+    IResult,
+};
+use nombytes::NomBytes;
+
+use crate::{
+    error::{Error, KafkaCode, Result},
+    parser,
+    protocol::{parse_header_response, HeaderResponse},
+};
+
+/// The base Offset For Leader Epoch response object.
+///
+/// ### Example
+/// ```rust
+/// let response_bytes = broker_conn.receive_response().await?;
+/// let response = protocol::OffsetForLeaderEpochResponse::try_from(response_bytes.freeze())?;
+/// ```
+#[derive(Debug, PartialEq)]
+pub struct OffsetForLeaderEpochResponse {
+    pub header: HeaderResponse,
+    /// The duration in milliseconds for which the request was throttled due to a quota violation, or zero if the request did not violate any quota.
+    pub throttle_time_ms: i32,
+    /// Each topic we fetched offsets for.
+    pub topics: Vec<Topic>,
+}
+
+/// Each topic we fetched offsets for.
+#[derive(Debug, PartialEq)]
+pub struct Topic {
+    /// The topic name.
+    pub name: Bytes,
+    /// Each partition in the topic we fetched offsets for.
+    pub partitions: Vec<Partition>,
+}
+
+/// Each partition in the topic we fetched offsets for.
+#[derive(Debug, PartialEq)]
+pub struct Partition {
+    /// The error code, or 0 if there was no error.
+    pub error_code: KafkaCode,
+    /// The partition index.
+    pub partition: i32,
+    /// The largest epoch of the log that is not larger than the requested one, or -1 if unknown.
+    pub leader_epoch: i32,
+    /// The end offset of that epoch, or -1 if unknown.
+    pub end_offset: i64,
+}
+
+impl TryFrom<Bytes> for OffsetForLeaderEpochResponse {
+    type Error = Error;
+
+    fn try_from(s: Bytes) -> Result<Self> {
+        tracing::trace!("Parsing OffsetForLeaderEpochResponse {:?}", s);
+        let (_, response) = parse_offset_for_leader_epoch_response(NomBytes::new(s.clone()))
+            .map_err(|err| {
+                tracing::error!(
+                    "ERROR: Failed parsing OffsetForLeaderEpochResponse {:?}",
+                    err
+                );
+                tracing::error!("ERROR: OffsetForLeaderEpochResponse Bytes {:?}", s);
+                Error::ParsingError(s)
+            })?;
+        tracing::trace!("Parsed OffsetForLeaderEpochResponse {:?}", response);
+        Ok(response)
+    }
+}
+
+impl OffsetForLeaderEpochResponse {
+    pub fn into_box_iter(self) -> Box<impl Iterator<Item = (Bytes, Partition)>> {
+        Box::new(self.topics.into_iter().flat_map(|topic| {
+            topic
+                .partitions
+                .into_iter()
+                .map(move |partition| (topic.name.clone(), partition))
+        }))
+    }
+}
+
+pub fn parse_offset_for_leader_epoch_response(
+    s: NomBytes,
+) -> IResult<NomBytes, OffsetForLeaderEpochResponse> {
+    let (s, header) = parse_header_response(s)?;
+    let (s, throttle_time_ms) = be_i32(s)?;
+    let (s, topics) = parser::parse_array(parse_topic)(s)?;
+
+    Ok((
+        s,
+        OffsetForLeaderEpochResponse {
+            header,
+            throttle_time_ms,
+            topics,
+        },
+    ))
+}
+
+fn parse_topic(s: NomBytes) -> IResult<NomBytes, Topic> {
+    let (s, name) = parser::parse_string(s)?;
+    let (s, partitions) = parser::parse_array(parse_partition)(s)?;
+
+    Ok((s, Topic { name, partitions }))
+}
+
+fn parse_partition(s: NomBytes) -> IResult<NomBytes, Partition> {
+    let (s, error_code) = parser::parse_kafka_code(s)?;
+    let (s, partition) = be_i32(s)?;
+    let (s, leader_epoch) = be_i32(s)?;
+    let (s, end_offset) = be_i64(s)?;
+
+    Ok((
+        s,
+        Partition {
+            error_code,
+            partition,
+            leader_epoch,
+            end_offset,
+        },
+    ))
+}
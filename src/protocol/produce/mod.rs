@@ -8,7 +8,7 @@ mod test {
     use bytes::Bytes;
     use nombytes::NomBytes;
 
-    use self::request::Attributes;
+    use self::request::RecordBatchAttributes;
 
     use super::*;
     use crate::{
@@ -16,7 +16,7 @@ mod test {
         error::KafkaCode,
         prelude::Compression,
         protocol::{self, fetch::response::parse_record_batch},
-        utils::{compress, uncompress},
+        utils::{compress, uncompress, DEFAULT_GZIP_LEVEL},
     };
 
     #[test]
@@ -65,7 +65,7 @@ mod test {
             1000,
             correlation_id,
             client_id,
-            request::Attributes::new(Some(Compression::Gzip)),
+            request::RecordBatchAttributes::new(Some(Compression::Gzip)),
         );
         produce_req.add(
             topic_name,
@@ -235,6 +235,62 @@ mod test {
         // assert_eq!(buffer, encoded_buf);
     }
 
+    #[test]
+    fn encode_v1_message_set() {
+        // two magic 0 messages, each with its offset, size and CRC-32 in front
+        let encoded_buf = [
+            0, 0, 0, 1, 0, 0, 0, 2, 0, 4, 114, 117, 115, 116, 0, 1, 0, 0, 3, 232, 0, 0, 0, 1, 0, 9,
+            112, 117, 114, 99, 104, 97, 115, 101, 115, 0, 0, 0, 1, 0, 0, 0, 3, 0, 0, 0, 72, 0, 0,
+            0, 0, 0, 0, 0, 0, 0, 0, 0, 27, 58, 153, 7, 97, 0, 0, 0, 0, 0, 6, 84, 101, 115, 116,
+            101, 114, 0, 0, 0, 7, 86, 97, 108, 117, 101, 32, 49, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0,
+            21, 123, 199, 178, 197, 0, 0, 255, 255, 255, 255, 0, 0, 0, 7, 86, 97, 108, 117, 101,
+            32, 50,
+        ];
+
+        let mut produce_req =
+            request::ProduceRequest::new(1, 1000, 2, "rust", RecordBatchAttributes::new(None));
+        produce_req.header.api_version = 1;
+        produce_req.add(
+            "purchases",
+            3,
+            Some(Bytes::from_static(b"Tester")),
+            Some(Bytes::from_static(b"Value 1")),
+            vec![],
+        );
+        produce_req.add(
+            "purchases",
+            3,
+            None,
+            Some(Bytes::from_static(b"Value 2")),
+            vec![],
+        );
+        let mut buffer = vec![];
+        produce_req.encode(&mut buffer).unwrap();
+
+        assert_eq!(buffer, encoded_buf);
+
+        // legacy messages cannot carry headers
+        produce_req.add(
+            "purchases",
+            3,
+            None,
+            None,
+            vec![request::Header::new("a".to_owned(), Bytes::new())],
+        );
+        assert!(produce_req.encode(&mut vec![]).is_err());
+    }
+
+    #[test]
+    fn parse_v0() {
+        // no log append time, nor throttle time
+        let buf = b"\0\0\0\x01\0\0\0\x01\0\x06tester\0\0\0\x01\0\0\0\0\0\0\0\0\0\0\0\0\0\x02";
+        let parsed =
+            response::ProduceResponse::try_from_version(Bytes::from_static(buf), 0).unwrap();
+        let partition = &parsed.responses[0].partition_responses[0];
+        assert_eq!(partition.base_offset, 2);
+        assert_eq!(partition.log_append_time, -1);
+    }
+
     #[test]
     fn parse() {
         let buf = b"\0\0\0\x01\0\0\0\x01\0\x06tester\0\0\0\x01\0\0\0\0\0\0\0\0\0\0\0\0\0\x02\xff\xff\xff\xff\xff\xff\xff\xff\0\0\0\0";
@@ -247,6 +303,7 @@ mod test {
                     error_code: KafkaCode::None,
                     base_offset: 2,
                     log_append_time: -1,
+                    log_start_offset: -1,
                 }],
             }],
         };
@@ -256,6 +313,62 @@ mod test {
         assert_eq!(parsed, res);
     }
 
+    #[test]
+    fn parse_v5() {
+        let mut buf = vec![];
+        1i32.encode(&mut buf).unwrap(); // correlation_id
+        1i32.encode(&mut buf).unwrap();
+        "tester".encode(&mut buf).unwrap();
+        1i32.encode(&mut buf).unwrap();
+        0i32.encode(&mut buf).unwrap(); // partition
+        0i16.encode(&mut buf).unwrap(); // error_code
+        2i64.encode(&mut buf).unwrap(); // base_offset
+        1_700_000_000_000i64.encode(&mut buf).unwrap(); // log_append_time
+        42i64.encode(&mut buf).unwrap(); // log_start_offset
+        0i32.encode(&mut buf).unwrap(); // throttle_time_ms
+
+        let parsed = response::ProduceResponse::try_from_version(Bytes::from(buf), 5).unwrap();
+        assert_eq!(
+            parsed.partition_results(),
+            vec![response::PartitionResult {
+                topic: Bytes::from_static(b"tester"),
+                partition: 0,
+                result: Ok(2),
+                log_append_time: 1_700_000_000_000,
+                log_start_offset: 42,
+            }]
+        );
+    }
+
+    #[test]
+    fn parse_flexible() {
+        let buf = [
+            0, 0, 0, 7, 0, 2, 7, 116, 101, 115, 116, 101, 114, 2, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0,
+            0, 1, 44, 255, 255, 255, 255, 255, 255, 255, 255, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0,
+            0, 0, 0, 0, 0, 0,
+        ];
+
+        let parsed =
+            response::ProduceResponse::try_from_version(Bytes::copy_from_slice(&buf), 9).unwrap();
+        assert_eq!(parsed.header.correlation_id, 7);
+        assert_eq!(parsed.responses[0].name, Bytes::from_static(b"tester"));
+        assert_eq!(
+            parsed.responses[0].partition_responses,
+            vec![response::PartitionResponse {
+                index: 1,
+                error_code: KafkaCode::None,
+                base_offset: 300,
+                log_append_time: -1,
+                log_start_offset: 0,
+            }]
+        );
+
+        // the same bytes are not a valid version 3 response
+        assert!(
+            response::ProduceResponse::try_from_version(Bytes::copy_from_slice(&buf), 3).is_err()
+        );
+    }
+
     #[test]
     fn it_compresses_a_record_correctly() {
         let record = request::Record::new(
@@ -272,16 +385,17 @@ mod test {
 
         record.encode(&mut buf).unwrap();
 
-        let compressed = compress(&buf).unwrap();
+        let compressed = compress(&buf, DEFAULT_GZIP_LEVEL).unwrap();
 
-        let uncompressed = uncompress(Bytes::from(compressed).as_ref()).unwrap();
+        let uncompressed = uncompress(Bytes::from(compressed).as_ref(), usize::MAX).unwrap();
 
         assert_eq!(buf, uncompressed);
     }
 
     #[test]
     fn it_compresses_many_records_correctly() {
-        let mut record_batch = request::RecordBatch::new(Attributes::new(Some(Compression::Gzip)));
+        let mut record_batch =
+            request::RecordBatch::new(RecordBatchAttributes::new(Some(Compression::Gzip)));
         record_batch.add(request::Message {
             key: Some(Bytes::from("key")),
             value: Some(Bytes::from("1")),
@@ -305,4 +419,320 @@ mod test {
             parse_record_batch(nombytes::NomBytes::new(Bytes::from(buf))).unwrap();
         assert_eq!(unparsed_batch.records.len(), 3);
     }
+
+    #[test]
+    fn it_compresses_at_the_given_level() {
+        let mut sizes = vec![];
+        for level in [1, 9] {
+            let mut attributes = RecordBatchAttributes::new(Some(Compression::Gzip));
+            attributes.set_compression_level(level);
+            let mut record_batch = request::RecordBatch::new(attributes);
+            for i in 0..200 {
+                record_batch.add(request::Message {
+                    key: Some(Bytes::from(format!("key {}", i % 7))),
+                    value: Some(Bytes::from(format!("value {} of {}", i * 31 % 97, i % 13))),
+                    headers: vec![],
+                });
+            }
+
+            let buf = record_batch.encode_to_vec().unwrap();
+            sizes.push(buf.len());
+
+            let (_, parsed_batch) =
+                parse_record_batch(nombytes::NomBytes::new(Bytes::from(buf))).unwrap();
+            assert_eq!(parsed_batch.records.len(), 200);
+            assert_eq!(parsed_batch.records[199].value, "value 58 of 4");
+        }
+
+        assert!(sizes[0] > sizes[1], "{:?}", sizes);
+        let mut attributes = RecordBatchAttributes::new(Some(Compression::Gzip));
+        attributes.set_compression_level(42);
+        assert_eq!(attributes.compression_level(), 9);
+    }
+
+    #[test]
+    fn it_encodes_the_timestamp_type_bit() {
+        let mut attributes = RecordBatchAttributes::new(Some(Compression::Gzip));
+        attributes.timestamp_type = request::TimestampType::LogAppendTime;
+        let mut record_batch = request::RecordBatch::new(attributes.clone());
+        record_batch.add(request::Message {
+            key: Some(Bytes::from("key")),
+            value: Some(Bytes::from("1")),
+            headers: vec![],
+        });
+
+        let mut buf = Vec::with_capacity(10);
+        record_batch._encode_to_buf(&mut buf).unwrap();
+
+        // base_offset, batch_length, partition_leader_epoch, magic and crc come first
+        let encoded = i16::from_be_bytes([buf[21], buf[22]]);
+        assert_eq!(encoded, 0b1001);
+
+        let (_, parsed_batch) =
+            parse_record_batch(nombytes::NomBytes::new(Bytes::from(buf))).unwrap();
+        assert_eq!(parsed_batch.attributes, attributes);
+        assert!(!parsed_batch.attributes.is_transactional());
+    }
+
+    #[test]
+    fn it_encodes_every_attribute_bit() {
+        // gzip, log append time, transactional, control and delete horizon
+        let bits: i16 = 0b111_1001;
+        let attributes = RecordBatchAttributes::from(bits);
+        assert_eq!(attributes.compression, Some(Compression::Gzip));
+        assert_eq!(
+            attributes.timestamp_type,
+            request::TimestampType::LogAppendTime
+        );
+        assert!(attributes.is_transactional());
+        assert!(attributes.is_control());
+        assert!(attributes.has_delete_horizon());
+
+        let mut buf = vec![];
+        attributes.encode(&mut buf).unwrap();
+        assert_eq!(i16::from_be_bytes([buf[0], buf[1]]), bits);
+    }
+
+    #[test]
+    fn it_marks_the_batches_of_a_transactional_request() {
+        let mut req = request::ProduceRequest::new(
+            -1,
+            1000,
+            1,
+            "rust",
+            RecordBatchAttributes::new(Some(Compression::Gzip)),
+        );
+        req.set_transactional_id("payments".to_owned());
+        req.add("tester", 0, None, Some(Bytes::from("1")), vec![]);
+
+        let mut buf = vec![];
+        req.encode(&mut buf).unwrap();
+
+        // past the header, transactional id, acks, timeout, topic count,
+        // topic name, partition count, partition index and records size
+        let batch = 10 + 4 + 2 + 8 + 2 + 4 + 4 + 2 + 6 + 4 + 4 + 4;
+        let (_, parsed_batch) =
+            parse_record_batch(NomBytes::new(Bytes::copy_from_slice(&buf[batch..]))).unwrap();
+        assert!(parsed_batch.attributes.is_transactional());
+        assert!(!parsed_batch.attributes.is_control());
+        assert_eq!(parsed_batch.attributes.compression, Some(Compression::Gzip));
+        assert_eq!(
+            i16::from_be_bytes([buf[batch + 21], buf[batch + 22]]),
+            0b1_0001
+        );
+    }
+
+    #[test]
+    fn it_writes_one_batch_per_partition() {
+        let mut req =
+            request::ProduceRequest::new(1, 1000, 1, "rust", RecordBatchAttributes::new(None));
+        for (partition, value) in [(0, "a"), (1, "b"), (0, "c"), (1, "d"), (0, "e")] {
+            req.add("tester", partition, None, Some(Bytes::from(value)), vec![]);
+        }
+
+        let mut buf = vec![];
+        req.encode(&mut buf).unwrap();
+
+        // past the header, transactional id, acks, timeout, topic count,
+        // topic name and partition count
+        let mut offset = 10 + 4 + 2 + 6 + 4 + 2 + 6 + 4;
+        let read_i32 =
+            |offset: usize| i32::from_be_bytes(buf[offset..offset + 4].try_into().unwrap());
+        for (partition, values) in [(0, vec!["a", "c", "e"]), (1, vec!["b", "d"])] {
+            assert_eq!(read_i32(offset), partition);
+            let size = read_i32(offset + 4) as usize;
+            let records = Bytes::copy_from_slice(&buf[offset + 8..offset + 8 + size]);
+            let (rest, batch) = parse_record_batch(NomBytes::new(records)).unwrap();
+            // a single batch holds every record of the partition
+            assert!(rest.to_bytes().is_empty());
+            let records: Vec<_> = batch.into_records().collect();
+            let offset_deltas: Vec<usize> = records
+                .iter()
+                .map(|record| record.offset_delta / 2)
+                .collect();
+            assert_eq!(offset_deltas, (0..values.len()).collect::<Vec<_>>());
+            let written: Vec<_> = records.iter().map(|record| record.value()).collect();
+            let expected: Vec<_> = values.into_iter().map(|v| Some(Bytes::from(v))).collect();
+            assert_eq!(written, expected);
+            offset += 8 + size;
+        }
+        assert_eq!(offset, buf.len());
+    }
+
+    #[test]
+    fn it_encodes_the_batch_producer() {
+        let mut record_batch = request::RecordBatch::new(RecordBatchAttributes::new(None));
+        record_batch.set_producer(request::BatchProducer {
+            producer_id: 42,
+            producer_epoch: 3,
+            base_sequence: 7,
+        });
+        record_batch.add(request::Message {
+            key: Some(Bytes::from("key")),
+            value: Some(Bytes::from("1")),
+            headers: vec![],
+        });
+
+        let mut buf = Vec::with_capacity(10);
+        record_batch._encode_to_buf(&mut buf).unwrap();
+
+        let (_, parsed_batch) =
+            parse_record_batch(nombytes::NomBytes::new(Bytes::from(buf))).unwrap();
+        assert_eq!(parsed_batch.producer_id, 42);
+        assert_eq!(parsed_batch.producer_epoch, 3);
+        assert_eq!(parsed_batch.base_sequence, 7);
+    }
+
+    #[test]
+    fn it_builds_a_record_batch() {
+        let mut record_batch = request::RecordBatch::new(RecordBatchAttributes::new(None));
+        for value in ["1", "2", "3"] {
+            record_batch.push(request::Record::new(
+                request::Message {
+                    key: None,
+                    value: Some(Bytes::from(value)),
+                    headers: vec![],
+                },
+                0,
+                0,
+            ));
+        }
+        record_batch.set_compression(Some(Compression::Gzip));
+        record_batch.set_transactional(true);
+        assert_eq!(record_batch.record_count(), 3);
+
+        let mut buf = Vec::with_capacity(10);
+        record_batch.encode(&mut buf).unwrap();
+
+        // base_offset
+        assert_eq!(i64::from_be_bytes(buf[0..8].try_into().unwrap()), 0);
+        // batch_length covers everything after itself
+        assert_eq!(
+            i32::from_be_bytes(buf[8..12].try_into().unwrap()) as usize,
+            buf.len() - 12
+        );
+        // magic
+        assert_eq!(buf[16], 2);
+        // crc of everything after itself
+        assert_eq!(
+            u32::from_be_bytes(buf[17..21].try_into().unwrap()),
+            crate::utils::to_crc(&buf[21..])
+        );
+        // attributes
+        assert_eq!(i16::from_be_bytes([buf[21], buf[22]]), 0b10001);
+        // last_offset_delta
+        assert_eq!(i32::from_be_bytes(buf[23..27].try_into().unwrap()), 2);
+        // record count
+        assert_eq!(i32::from_be_bytes(buf[57..61].try_into().unwrap()), 3);
+
+        let (_, parsed_batch) =
+            parse_record_batch(nombytes::NomBytes::new(Bytes::from(buf))).unwrap();
+        assert_eq!(parsed_batch.base_offset, 0);
+        assert_eq!(parsed_batch.magic, 2);
+        assert_eq!(parsed_batch.records.len(), 3);
+        assert_eq!(parsed_batch.last_offset_delta, 2);
+    }
+
+    #[test]
+    fn it_encodes_a_batch_without_growing_its_buffer() {
+        let mut record_batch = request::RecordBatch::new(RecordBatchAttributes::new(None));
+        for i in 0..50 {
+            record_batch.push(request::Record::new(
+                request::Message {
+                    key: Some(Bytes::from(format!("key {i}"))),
+                    value: Some(Bytes::from(vec![b'v'; i * 10])),
+                    headers: vec![request::Header::new(
+                        String::from("header"),
+                        Bytes::from(vec![b'h'; i]),
+                    )],
+                },
+                0,
+                0,
+            ));
+        }
+
+        // uncompressed, the exact size is known up front
+        let encoded_len = record_batch.encoded_len().unwrap();
+        let buf = record_batch.encode_to_vec().unwrap();
+        assert_eq!(buf.len(), encoded_len);
+        assert_eq!(buf.capacity(), encoded_len);
+
+        // compressed, a well-sized hint is all the buffer needs
+        record_batch.set_compression(Some(Compression::Gzip));
+        assert_eq!(record_batch.encoded_len(), None);
+        record_batch.set_initial_capacity(encoded_len);
+        let buf = record_batch.encode_to_vec().unwrap();
+        assert!(buf.len() < encoded_len);
+        assert_eq!(buf.capacity(), encoded_len);
+    }
+
+    #[cfg(feature = "test-internals")]
+    fn gzip_batch() -> request::RecordBatch {
+        let mut record_batch =
+            request::RecordBatch::new(RecordBatchAttributes::new(Some(Compression::Gzip)));
+        for i in 0..100 {
+            record_batch.push(request::Record::new(
+                request::Message {
+                    key: Some(Bytes::from(format!("key {i}"))),
+                    value: Some(Bytes::from(format!("value {i}"))),
+                    headers: vec![],
+                },
+                0,
+                0,
+            ));
+        }
+        record_batch.set_producer(request::BatchProducer {
+            producer_id: 42,
+            producer_epoch: 3,
+            base_sequence: 0,
+        });
+        record_batch
+    }
+
+    #[cfg(feature = "test-internals")]
+    #[test]
+    fn it_patches_a_record_batch_template() {
+        let mut record_batch = gzip_batch();
+        let mut template = request::RecordBatchTemplate::new(&record_batch).unwrap();
+
+        record_batch.set_producer(request::BatchProducer {
+            producer_id: 42,
+            producer_epoch: 3,
+            base_sequence: 100,
+        });
+        let mut expected = Vec::new();
+        record_batch.encode(&mut expected).unwrap();
+        let encoded = template.encode_with(0, 100).unwrap();
+        assert_eq!(encoded, &expected[..]);
+
+        let encoded = template.encode_with(7, 100).unwrap();
+        assert_eq!(i64::from_be_bytes(encoded[0..8].try_into().unwrap()), 7);
+        assert_eq!(&encoded[8..], &expected[8..]);
+    }
+
+    #[cfg(feature = "test-internals")]
+    #[test]
+    fn it_writes_a_template_faster_than_encoding_the_batch() {
+        const BATCHES: i32 = 50;
+        let record_batch = gzip_batch();
+
+        let start = std::time::Instant::now();
+        for _ in 0..BATCHES {
+            let mut buf = Vec::new();
+            record_batch.encode(&mut buf).unwrap();
+        }
+        let encoding = start.elapsed();
+
+        let mut template = request::RecordBatchTemplate::new(&record_batch).unwrap();
+        let start = std::time::Instant::now();
+        for i in 0..BATCHES {
+            template.encode_with(i as i64 * 100, i * 100).unwrap();
+        }
+        let patching = start.elapsed();
+
+        assert!(
+            patching < encoding,
+            "patching took {patching:?}, encoding took {encoding:?}"
+        );
+    }
 }
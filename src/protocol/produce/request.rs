@@ -3,18 +3,23 @@
 use bytes::{BufMut, Bytes};
 
 use crate::{
-    encode::ToByte,
-    error::Result,
+    encode::{try_usize_to_int, varint_len, ToByte},
+    error::{Error, Result},
     prelude::Compression,
-    protocol::HeaderRequest,
-    utils::{compress, now, to_crc},
+    protocol::{Describe, HeaderRequest},
+    utils::{compress, now, to_crc, to_legacy_crc, DEFAULT_GZIP_LEVEL},
 };
 
-const API_KEY_PRODUCE: i16 = 0;
-const API_VERSION: i16 = 3;
+pub const API_KEY_PRODUCE: i16 = 0;
+pub const API_VERSION: i16 = 3;
+/// The last Produce version sending legacy message sets rather than record
+/// batches, and without a transactional id.
+pub const LAST_MESSAGE_SET_VERSION: i16 = 2;
 
 /// The magic byte (a.k.a version) we use for sent messages.
 const MESSAGE_MAGIC_BYTE: i8 = 2;
+/// The compression codec bits of the attributes of a legacy message.
+const LEGACY_GZIP: i8 = 1;
 
 /*
 Produce Request (Version: 3) => transactional_id acks timeout [topic_data]
@@ -26,6 +31,19 @@ Produce Request (Version: 3) => transactional_id acks timeout [topic_data]
     data => partition record_set
       partition => INT32
       record_set => RECORDS
+
+Versions 0 to 2 have no transactional_id, and the record_set is a legacy
+message set, magic 0 up to version 1 and magic 1 with timestamps in version 2:
+MessageSet => [offset message_size message]
+  offset => INT64
+  message_size => INT32
+  message => crc magic attributes timestamp key value
+    crc => UINT32, the CRC-32 of everything after it
+    magic => INT8
+    attributes => INT8
+    timestamp => INT64, from magic 1
+    key => BYTES
+    value => BYTES
 */
 
 #[derive(Debug)]
@@ -39,7 +57,19 @@ pub struct ProduceRequest<'a> {
     pub timeout_ms: i32,
     /// Each topic to produce to.
     topic_partitions: Vec<TopicPartition<'a>>,
-    attributes: Attributes,
+    attributes: RecordBatchAttributes,
+    producer: Option<BatchProducer>,
+}
+
+/// The idempotent or transactional producer writing a record batch.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BatchProducer {
+    /// The producer id handed out by the InitProducerId API.
+    pub producer_id: i64,
+    /// The producer epoch handed out by the InitProducerId API.
+    pub producer_epoch: i16,
+    /// The sequence number of the first record in the batch. The broker uses it to drop duplicates.
+    pub base_sequence: i32,
 }
 
 impl<'a> ProduceRequest<'a> {
@@ -48,7 +78,7 @@ impl<'a> ProduceRequest<'a> {
         timeout_ms: i32,
         correlation_id: i32,
         client_id: &'a str,
-        attributes: Attributes,
+        attributes: RecordBatchAttributes,
     ) -> ProduceRequest<'a> {
         ProduceRequest {
             header: HeaderRequest::new(API_KEY_PRODUCE, API_VERSION, correlation_id, client_id),
@@ -57,9 +87,77 @@ impl<'a> ProduceRequest<'a> {
             timeout_ms,
             topic_partitions: vec![],
             attributes,
+            producer: None,
         }
     }
 
+    /// Write the records as an idempotent producer.
+    ///
+    /// The same producer id, epoch and base sequence is used for the batch
+    /// of every partition, so this must be called before adding messages.
+    pub fn set_producer(&mut self, producer: BatchProducer) {
+        self.producer = Some(producer);
+    }
+
+    /// Write the records as part of a transaction of the given transactional id.
+    ///
+    /// Like [`set_producer`](Self::set_producer), this must be called before
+    /// adding messages, so their batches are marked as transactional.
+    pub fn set_transactional_id(&mut self, transactional_id: String) {
+        self.transactional_id = Some(transactional_id);
+        self.attributes.set_transactional(true);
+    }
+
+    /// Write the records of a single topic partition as an idempotent producer.
+    ///
+    /// Unlike [`set_producer`](Self::set_producer), this is called after the
+    /// messages are added, so each partition can have its own base sequence.
+    pub fn set_partition_producer(&mut self, topic: &str, partition: i32, producer: BatchProducer) {
+        let partitions = self
+            .topic_partitions
+            .iter_mut()
+            .filter(|tp| tp.index == topic)
+            .flat_map(|tp| tp.partitions.iter_mut())
+            .filter(|p| p.partition == partition);
+        for p in partitions {
+            p.producer = Some(producer);
+            // a later batch carries on from the sequence of the one before it
+            let mut base_sequence = producer.base_sequence;
+            for batch in p.batches.iter_mut() {
+                batch.set_producer(BatchProducer {
+                    base_sequence,
+                    ..producer
+                });
+                base_sequence = base_sequence.wrapping_add(batch.record_count());
+            }
+        }
+    }
+
+    /// Reserve this many bytes for each record batch when it is encoded.
+    ///
+    /// Like [`set_partition_producer`](Self::set_partition_producer), this
+    /// is called after the messages are added.
+    pub fn set_batch_capacity(&mut self, initial_capacity: usize) {
+        let batches = self
+            .topic_partitions
+            .iter_mut()
+            .flat_map(|tp| tp.partitions.iter_mut())
+            .flat_map(|p| p.batches.iter_mut());
+        for batch in batches {
+            batch.set_initial_capacity(initial_capacity);
+        }
+    }
+
+    /// Whether the request carries no records, brokers reject such requests.
+    pub fn is_empty(&self) -> bool {
+        self.topic_partitions.iter().all(|topic| {
+            topic
+                .partitions
+                .iter()
+                .all(|partition| partition.batches.iter().all(RecordBatch::is_empty))
+        })
+    }
+
     pub fn add(
         &mut self,
         topic: &'a str,
@@ -79,6 +177,7 @@ impl<'a> ProduceRequest<'a> {
             }
             None => {
                 let mut tp = TopicPartition::new(topic, self.attributes.clone());
+                tp.producer = self.producer;
                 tp.add(partition, message);
                 self.topic_partitions.push(tp);
             }
@@ -90,10 +189,35 @@ impl ToByte for ProduceRequest<'_> {
     fn encode<W: BufMut>(&self, buffer: &mut W) -> Result<()> {
         tracing::trace!("Encoding ProduceRequest {:?}", self);
         self.header.encode(buffer)?;
-        self.transactional_id.encode(buffer)?;
+        let api_version = self.header.api_version;
+        if api_version > LAST_MESSAGE_SET_VERSION {
+            self.transactional_id.encode(buffer)?;
+        }
         self.required_acks.encode(buffer)?;
         self.timeout_ms.encode(buffer)?;
-        self.topic_partitions.encode(buffer)?;
+        if api_version > LAST_MESSAGE_SET_VERSION {
+            return self.topic_partitions.encode(buffer);
+        }
+
+        // older brokers only understand the legacy message sets
+        let magic = if api_version < LAST_MESSAGE_SET_VERSION {
+            0
+        } else {
+            1
+        };
+        try_usize_to_int!(self.topic_partitions.len(), i32).encode(buffer)?;
+        for topic in &self.topic_partitions {
+            topic.index.encode(buffer)?;
+            try_usize_to_int!(topic.partitions.len(), i32).encode(buffer)?;
+            for partition in &topic.partitions {
+                partition.partition.encode(buffer)?;
+                let mut message_set = vec![];
+                for batch in &partition.batches {
+                    batch.encode_message_set(magic, &mut message_set)?;
+                }
+                message_set.encode(buffer)?;
+            }
+        }
         Ok(())
     }
 }
@@ -104,15 +228,17 @@ struct TopicPartition<'a> {
     pub index: &'a str,
     /// Each partition to produce to.
     pub partitions: Vec<Partition>,
-    attributes: Attributes,
+    attributes: RecordBatchAttributes,
+    producer: Option<BatchProducer>,
 }
 
 impl<'a> TopicPartition<'a> {
-    pub fn new(index: &'a str, attributes: Attributes) -> TopicPartition<'a> {
+    pub fn new(index: &'a str, attributes: RecordBatchAttributes) -> TopicPartition<'a> {
         TopicPartition {
             index,
             partitions: vec![],
             attributes,
+            producer: None,
         }
     }
 
@@ -127,6 +253,7 @@ impl<'a> TopicPartition<'a> {
             }
             None => {
                 let mut p = Partition::new(partition, self.attributes.clone());
+                p.producer = self.producer;
                 p.add(message);
                 self.partitions.push(p);
             }
@@ -149,22 +276,34 @@ struct Partition {
     pub partition: i32,
     /// The record data to be produced.
     pub batches: Vec<RecordBatch>,
-    attributes: Attributes,
+    attributes: RecordBatchAttributes,
+    producer: Option<BatchProducer>,
 }
 
 impl Partition {
-    pub fn new(partition: i32, attributes: Attributes) -> Partition {
+    pub fn new(partition: i32, attributes: RecordBatchAttributes) -> Partition {
         Partition {
             partition,
             batches: Vec::new(),
             attributes,
+            producer: None,
         }
     }
 
-    // all records go into one batch, we have to find out how to
+    /// Append a message to the batch of the partition.
+    ///
+    /// All the messages of a partition go into a single batch, so the broker
+    /// writes them at contiguous offsets from the base offset it assigns the
+    /// batch, in the order they were added. Several batches for a partition
+    /// would each get their own base offset, following on from the batch
+    /// before, and their base sequences would have to follow on as well.
     pub fn add(&mut self, message: Message) {
         if self.batches.is_empty() {
-            self.batches.push(RecordBatch::new(self.attributes.clone()));
+            let mut batch = RecordBatch::new(self.attributes.clone());
+            if let Some(producer) = self.producer {
+                batch.set_producer(producer);
+            }
+            self.batches.push(batch);
         }
 
         self.batches[0].add(message);
@@ -177,9 +316,10 @@ impl ToByte for Partition {
         self.partition.encode(out)?;
 
         // encode the record batches as a bytestring not array
-        let mut buf = Vec::with_capacity(4);
-        for msg in &self.batches {
-            msg._encode_to_buf(&mut buf)?;
+        let capacity = self.batches.iter().map(RecordBatch::buffer_capacity).sum();
+        let mut buf = Vec::with_capacity(capacity);
+        for batch in &self.batches {
+            batch.encode(&mut buf)?;
         }
 
         buf.encode(out)
@@ -227,6 +367,10 @@ impl Message {
 // producerEpoch: int16
 // baseSequence: int32
 // records: [Record]
+/// A v2 record batch.
+///
+/// Records are appended with `add` or `push`. The batch length and CRC are
+/// computed when the batch is encoded.
 #[derive(Debug)]
 pub struct RecordBatch {
     /// Denotes the first offset in the RecordBatch. The 'offsetDelta' of each Record in the batch would be be computed relative to this FirstOffset. In particular, the offset of each Record in the Batch is its 'OffsetDelta' + 'FirstOffset'.
@@ -237,7 +381,7 @@ pub struct RecordBatch {
     magic: i8,
     /// The CRC is the CRC32 of the remainder of the message bytes. This is used to check the integrity of the message on the broker and consumer.
     crc: u32,
-    attributes: Attributes,
+    attributes: RecordBatchAttributes,
     /// The offset of the last message in the RecordBatch. This is used by the broker to ensure correct behavior even when Records within a batch are compacted out.
     last_offset_delta: i32,
     /// The timestamp of the first Record in the batch. The timestamp of each Record in the RecordBatch is its 'TimestampDelta' + 'FirstTimestamp'.
@@ -251,6 +395,18 @@ pub struct RecordBatch {
     /// Introduced in 0.11.0.0 for KIP-98, this is the producer assigned sequence number which is used by the broker to deduplicate messages. Clients which want to support idempotent message delivery and transactions must set this field. The sequence number for each Record in the RecordBatch is its OffsetDelta + FirstSequence.
     base_sequence: i32,
     records: Vec<Record>,
+    /// Bytes reserved up front when the batch is encoded.
+    initial_capacity: usize,
+}
+
+/// The timestamp type of the records in a batch.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum TimestampType {
+    /// The timestamp is set by the producer when the record is created.
+    #[default]
+    CreateTime,
+    /// The timestamp is set by the broker when the record is appended to the log.
+    LogAppendTime,
 }
 
 //     bit 0~2:
@@ -264,32 +420,103 @@ pub struct RecordBatch {
 //     bit 5: isControlBatch (0 means not a control batch)
 //     bit 6: hasDeleteHorizonMs (0 means baseTimestamp is not set as the delete horizon for compaction)
 //     bit 7~15: unused
+/// Attributes of a record batch.
+///
+/// The compression and timestamp type can be chosen by the producer. The
+/// transactional, control and delete horizon bits are managed by the client
+/// and broker.
 #[derive(Clone, Debug, PartialEq)]
-pub struct Attributes {
+pub struct RecordBatchAttributes {
     pub compression: Option<Compression>,
+    pub timestamp_type: TimestampType,
+    pub(crate) is_transactional: bool,
+    pub(crate) is_control: bool,
+    pub(crate) has_delete_horizon: bool,
+    /// The gzip level, from 0 to 9. It is not part of the wire format, so
+    /// parsed batches have the default level.
+    pub(crate) compression_level: u32,
 }
 
-impl Attributes {
+/// Previous name of [`RecordBatchAttributes`].
+pub type Attributes = RecordBatchAttributes;
+
+const COMPRESSION_MASK: i16 = 0b111;
+const TIMESTAMP_TYPE_BIT: i16 = 1 << 3;
+const TRANSACTIONAL_BIT: i16 = 1 << 4;
+const CONTROL_BIT: i16 = 1 << 5;
+const DELETE_HORIZON_BIT: i16 = 1 << 6;
+
+impl RecordBatchAttributes {
     pub fn new(compression: Option<Compression>) -> Self {
-        Attributes { compression }
+        RecordBatchAttributes {
+            compression,
+            timestamp_type: TimestampType::CreateTime,
+            is_transactional: false,
+            is_control: false,
+            has_delete_horizon: false,
+            compression_level: DEFAULT_GZIP_LEVEL,
+        }
+    }
+
+    /// The level batches are compressed at, from 0 to 9.
+    pub fn compression_level(&self) -> u32 {
+        self.compression_level
+    }
+
+    /// Trade CPU for a better compression ratio, from 0 for no compression
+    /// to 9, the default, for the best. Higher levels are clamped to 9.
+    pub fn set_compression_level(&mut self, compression_level: u32) {
+        self.compression_level = compression_level.min(9);
+    }
+
+    /// Whether the batch is part of a transaction.
+    pub fn is_transactional(&self) -> bool {
+        self.is_transactional
+    }
+
+    /// Whether the batch holds control records, e.g. transaction markers.
+    pub fn is_control(&self) -> bool {
+        self.is_control
+    }
+
+    /// Whether the base timestamp is the delete horizon set by compaction,
+    /// after which its tombstones and transaction markers are removed.
+    pub fn has_delete_horizon(&self) -> bool {
+        self.has_delete_horizon
+    }
+
+    /// Mark the batches as part of a transaction. This is only valid for a
+    /// producer with a transactional id.
+    pub fn set_transactional(&mut self, is_transactional: bool) {
+        self.is_transactional = is_transactional;
     }
 }
 
-impl From<i16> for Attributes {
+impl From<i16> for RecordBatchAttributes {
     fn from(n: i16) -> Self {
-        // if the 1 bit is on, then its GZIP compression
         // technically ignoring other compression types for now
-        let compression = if n % 2 == 1 {
-            Some(Compression::Gzip)
+        let compression = match n & COMPRESSION_MASK {
+            1 => Some(Compression::Gzip),
+            _ => None,
+        };
+        let timestamp_type = if n & TIMESTAMP_TYPE_BIT != 0 {
+            TimestampType::LogAppendTime
         } else {
-            None
+            TimestampType::CreateTime
         };
 
-        Self::new(compression)
+        Self {
+            compression,
+            timestamp_type,
+            is_transactional: n & TRANSACTIONAL_BIT != 0,
+            is_control: n & CONTROL_BIT != 0,
+            has_delete_horizon: n & DELETE_HORIZON_BIT != 0,
+            compression_level: DEFAULT_GZIP_LEVEL,
+        }
     }
 }
 
-impl ToByte for Attributes {
+impl ToByte for RecordBatchAttributes {
     fn encode<W: BufMut>(&self, out: &mut W) -> Result<()> {
         let mut attr: i16 = 0;
 
@@ -297,6 +524,18 @@ impl ToByte for Attributes {
             Some(Compression::Gzip) => attr + 1,
             _ => attr,
         };
+        if self.timestamp_type == TimestampType::LogAppendTime {
+            attr |= TIMESTAMP_TYPE_BIT;
+        }
+        if self.is_transactional {
+            attr |= TRANSACTIONAL_BIT;
+        }
+        if self.is_control {
+            attr |= CONTROL_BIT;
+        }
+        if self.has_delete_horizon {
+            attr |= DELETE_HORIZON_BIT;
+        }
 
         attr.encode(out)?;
         Ok(())
@@ -304,7 +543,7 @@ impl ToByte for Attributes {
 }
 
 impl RecordBatch {
-    pub fn new(attributes: Attributes) -> Self {
+    pub fn new(attributes: RecordBatchAttributes) -> Self {
         Self {
             base_offset: 0,
             partition_leader_epoch: -1,
@@ -318,42 +557,132 @@ impl RecordBatch {
             producer_epoch: -1,
             base_sequence: -1,
             records: Vec::new(),
+            initial_capacity: 0,
         }
     }
 
+    /// Mark the batch as written by an idempotent or transactional producer.
+    pub fn set_producer(&mut self, producer: BatchProducer) {
+        self.producer_id = producer.producer_id;
+        self.producer_epoch = producer.producer_epoch;
+        self.base_sequence = producer.base_sequence;
+    }
+
+    /// Choose how the records of the batch are compressed.
+    pub fn set_compression(&mut self, compression: Option<Compression>) {
+        self.attributes.compression = compression;
+    }
+
+    /// Choose the timestamp type of the records in the batch.
+    pub fn set_timestamp_type(&mut self, timestamp_type: TimestampType) {
+        self.attributes.timestamp_type = timestamp_type;
+    }
+
+    /// Mark the batch as part of a transaction.
+    pub fn set_transactional(&mut self, is_transactional: bool) {
+        self.attributes.is_transactional = is_transactional;
+    }
+
+    pub fn attributes(&self) -> &RecordBatchAttributes {
+        &self.attributes
+    }
+
+    pub fn base_offset(&self) -> i64 {
+        self.base_offset
+    }
+
+    pub fn magic(&self) -> i8 {
+        self.magic
+    }
+
+    /// The number of records in the batch.
+    pub fn record_count(&self) -> i32 {
+        self.records.len() as i32
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// Reserve this many bytes up front when the batch is encoded.
+    ///
+    /// The size of an uncompressed batch is known before encoding, so this
+    /// only matters for compressed batches, or to leave room for more.
+    pub fn set_initial_capacity(&mut self, initial_capacity: usize) {
+        self.initial_capacity = initial_capacity;
+    }
+
+    /// The size of the encoded batch, when it is known before encoding,
+    /// i.e. when the records are not compressed.
+    pub fn encoded_len(&self) -> Option<usize> {
+        if self.attributes.compression.is_some() {
+            return None;
+        }
+        let records: usize = self
+            .records
+            .iter()
+            .map(|record| {
+                let length = record.encoded_len();
+                varint_len(length) + length
+            })
+            .sum();
+        Some(BATCH_OVERHEAD + records)
+    }
+
+    /// The bytes to reserve for encoding the batch.
+    pub(crate) fn buffer_capacity(&self) -> usize {
+        self.initial_capacity
+            .max(self.encoded_len().unwrap_or_default())
+    }
+
+    /// Encode the batch into a buffer sized by [`buffer_capacity`](Self::buffer_capacity).
+    pub(crate) fn encode_to_vec(&self) -> Result<Vec<u8>> {
+        let mut buf = Vec::with_capacity(self.buffer_capacity());
+        self._encode_to_buf(&mut buf)?;
+        Ok(buf)
+    }
+
     pub fn add(&mut self, message: Message) {
+        let timestamp_delta = now() - self.base_timestamp;
+        self.push(Record::new(message, timestamp_delta as usize, 0));
+    }
+
+    /// Append a record to the batch.
+    ///
+    /// The offset delta of the record is overwritten with its position in
+    /// the batch, its timestamp delta is kept relative to the base timestamp.
+    pub fn push(&mut self, mut record: Record) {
         // update the state of the batch
         self.last_offset_delta += 1;
-        self.max_timestamp = now();
+        self.max_timestamp = self
+            .max_timestamp
+            .max(self.base_timestamp + record.timestamp_delta as i64);
 
-        // calculate our deltas
-        let timestamp_delta = self.max_timestamp - self.base_timestamp;
-        let offset_delta = self.last_offset_delta;
-
-        let record = Record::new(message, timestamp_delta as usize, offset_delta as usize);
+        record.offset_delta = self.last_offset_delta as usize;
         self.records.push(record);
     }
 
     pub fn _encode_to_buf(&self, out: &mut Vec<u8>) -> Result<()> {
+        let start = out.len();
         self.base_offset.encode(out)?;
 
-        // delaying record length calculation
+        // the batch length is filled in once the rest is encoded
+        0i32.encode(out)?;
+        let batch_start = out.len();
 
-        let mut buf = Vec::with_capacity(4);
-        self.partition_leader_epoch.encode(&mut buf)?;
-        self.magic.encode(&mut buf)?;
+        self.partition_leader_epoch.encode(out)?;
+        self.magic.encode(out)?;
 
         // will replace crc once we can calculate it
-        let crc_pos = 5;
-        self.crc.encode(&mut buf)?;
+        self.crc.encode(out)?;
 
-        self.attributes.encode(&mut buf)?;
-        self.last_offset_delta.encode(&mut buf)?;
-        self.base_timestamp.encode(&mut buf)?;
-        self.max_timestamp.encode(&mut buf)?;
-        self.producer_id.encode(&mut buf)?;
-        self.producer_epoch.encode(&mut buf)?;
-        self.base_sequence.encode(&mut buf)?;
+        self.attributes.encode(out)?;
+        self.last_offset_delta.encode(out)?;
+        self.base_timestamp.encode(out)?;
+        self.max_timestamp.encode(out)?;
+        self.producer_id.encode(out)?;
+        self.producer_epoch.encode(out)?;
+        self.base_sequence.encode(out)?;
 
         // Note that when compression is enabled, the compressed record data is
         // serialized directly following the count of the number of records.
@@ -363,26 +692,178 @@ impl RecordBatch {
                 for record in &self.records {
                     record.encode(&mut compressed)?;
                 }
-                compressed = compress(&compressed)?;
+                compressed = compress(&compressed, self.attributes.compression_level)?;
 
                 // first the count
-                (self.records.len() as i32).encode(&mut buf)?;
+                try_usize_to_int!(self.records.len(), i32).encode(out)?;
                 // then the compressed data without the bytestring length in front
-                buf.put(compressed.as_ref());
+                out.put(compressed.as_ref());
+            }
+            _ => self.records.encode(out)?,
+        }
+
+        finalize_crc(&mut out[batch_start..])?;
+
+        let batch_length = try_usize_to_int!(out.len() - batch_start, i32);
+        batch_length.encode(&mut &mut out[start + 8..batch_start])?;
+
+        Ok(())
+    }
+}
+
+impl RecordBatch {
+    /// Encode the records as a legacy message set of the given magic, for
+    /// the Produce versions before record batches.
+    ///
+    /// A compressed batch becomes a single wrapper message, whose value is the
+    /// compressed message set of the records. Legacy messages have no headers.
+    pub fn encode_message_set(&self, magic: i8, out: &mut Vec<u8>) -> Result<()> {
+        let timestamp_type =
+            if magic > 0 && self.attributes.timestamp_type == TimestampType::LogAppendTime {
+                TIMESTAMP_TYPE_BIT as i8
+            } else {
+                0
+            };
+
+        let mut messages = Vec::new();
+        for record in &self.records {
+            if !record.headers.is_empty() {
+                return Err(Error::ArgError(format!(
+                    "Record headers need Produce v{} or later",
+                    LAST_MESSAGE_SET_VERSION + 1
+                )));
+            }
+            encode_legacy_message(
+                &mut messages,
+                record.offset_delta as i64,
+                LegacyMessage {
+                    magic,
+                    attributes: timestamp_type,
+                    timestamp: self.base_timestamp + record.timestamp_delta as i64,
+                    key: &record.key,
+                    value: &record.value,
+                },
+            )?;
+        }
+
+        match self.attributes.compression {
+            Some(Compression::Gzip) => encode_legacy_message(
+                out,
+                // the wrapper carries the offset of the last inner message
+                self.last_offset_delta as i64,
+                LegacyMessage {
+                    magic,
+                    attributes: LEGACY_GZIP | timestamp_type,
+                    timestamp: self.max_timestamp,
+                    key: &None,
+                    value: &Some(Bytes::from(compress(
+                        &messages,
+                        self.attributes.compression_level,
+                    )?)),
+                },
+            ),
+            None => {
+                out.extend_from_slice(&messages);
+                Ok(())
             }
-            _ => self.records.encode(&mut buf)?,
         }
+    }
+}
+
+/// A message of the legacy message set format.
+struct LegacyMessage<'a> {
+    magic: i8,
+    attributes: i8,
+    /// Only written from magic 1 on.
+    timestamp: i64,
+    key: &'a Option<Bytes>,
+    value: &'a Option<Bytes>,
+}
 
-        let crc = to_crc(&buf[(crc_pos + 4)..]);
-        crc.encode(&mut &mut buf[crc_pos..crc_pos + 4])?;
+fn encode_legacy_message(out: &mut Vec<u8>, offset: i64, message: LegacyMessage) -> Result<()> {
+    offset.encode(out)?;
+    // the size and crc are filled in once the rest is encoded
+    let size_pos = out.len();
+    0i32.encode(out)?;
+    0u32.encode(out)?;
+    let crc_start = out.len();
+
+    message.magic.encode(out)?;
+    message.attributes.encode(out)?;
+    if message.magic > 0 {
+        message.timestamp.encode(out)?;
+    }
+    message.key.encode(out)?;
+    message.value.encode(out)?;
 
-        // encode the record as bytes with the length in front
-        buf.encode(out)?;
+    let crc = to_legacy_crc(&out[crc_start..]);
+    crc.encode(&mut &mut out[crc_start - 4..crc_start])?;
+    let size = try_usize_to_int!(out.len() - size_pos - 4, i32);
+    size.encode(&mut &mut out[size_pos..size_pos + 4])
+}
 
+impl ToByte for RecordBatch {
+    fn encode<W: BufMut>(&self, out: &mut W) -> Result<()> {
+        let buf = self.encode_to_vec()?;
+        out.put(buf.as_ref());
         Ok(())
     }
 }
 
+/// A record batch that was encoded once and is written again and again.
+///
+/// Only the base offset and base sequence change between copies, so
+/// instead of encoding the records again only the crc is recomputed. Meant
+/// for benchmarks, where the cost of encoding would hide the cost of
+/// everything else.
+#[cfg(feature = "test-internals")]
+#[derive(Clone, Debug)]
+pub struct RecordBatchTemplate {
+    encoded: Vec<u8>,
+}
+
+#[cfg(feature = "test-internals")]
+impl RecordBatchTemplate {
+    /// Position of the batch after base_offset and batch_length.
+    const BATCH_POS: usize = 12;
+    /// Position of base_sequence in the encoded batch.
+    const BASE_SEQUENCE_POS: usize = 53;
+
+    pub fn new(batch: &RecordBatch) -> Result<Self> {
+        let mut encoded = Vec::new();
+        batch._encode_to_buf(&mut encoded)?;
+        Ok(Self { encoded })
+    }
+
+    /// The encoded batch, with the given base offset and base sequence.
+    pub fn encode_with(&mut self, base_offset: i64, base_sequence: i32) -> Result<&[u8]> {
+        base_offset.encode(&mut &mut self.encoded[..8])?;
+        base_sequence
+            .encode(&mut &mut self.encoded[Self::BASE_SEQUENCE_POS..Self::BASE_SEQUENCE_POS + 4])?;
+        // the base offset is not covered by the crc, the base sequence is
+        finalize_crc(&mut self.encoded[Self::BATCH_POS..])?;
+        Ok(&self.encoded)
+    }
+}
+
+/// The bytes of a batch before its records: base_offset, batch_length,
+/// partition_leader_epoch, magic, crc, attributes, last_offset_delta,
+/// base_timestamp, max_timestamp, producer_id, producer_epoch,
+/// base_sequence and the record count.
+const BATCH_OVERHEAD: usize = 8 + 4 + 4 + 1 + 4 + 2 + 4 + 8 + 8 + 8 + 2 + 4 + 4;
+
+/// Position of the crc in a batch, after partition_leader_epoch and magic.
+const CRC_POS: usize = 5;
+
+/// Overwrite the crc placeholder with the CRC32 of everything following it.
+///
+/// The buffer starts at partition_leader_epoch, i.e. without the base offset
+/// and batch length.
+fn finalize_crc(buf: &mut [u8]) -> Result<()> {
+    let crc = to_crc(&buf[(CRC_POS + 4)..]);
+    crc.encode(&mut &mut buf[CRC_POS..CRC_POS + 4])
+}
+
 // length: varint
 // attributes: int8
 //     bit 0~7: unused
@@ -425,6 +906,28 @@ impl Record {
         }
     }
 
+    /// The size of the encoded record, without its length in front.
+    fn encoded_len(&self) -> usize {
+        let headers: usize = self
+            .headers
+            .iter()
+            .map(|header| {
+                varint_len(header.header_key_length)
+                    + header.header_key_length
+                    + varint_len(header.header_value_length)
+                    + header.header_value_length
+            })
+            .sum();
+        1 + varint_len(self.timestamp_delta)
+            + varint_len(self.offset_delta)
+            + varint_len(self.key_length)
+            + self.key_length
+            + varint_len(self.value_length)
+            + self.value_length
+            + varint_len(self.headers.len())
+            + headers
+    }
+
     pub fn _encode_to_buf(&self, out: &mut Vec<u8>) -> Result<()> {
         self.attributes.encode(out)?;
         self.timestamp_delta.encode(out)?;
@@ -451,7 +954,7 @@ impl Record {
 
 impl ToByte for Record {
     fn encode<W: BufMut>(&self, out: &mut W) -> Result<()> {
-        let mut buf = Vec::with_capacity(4);
+        let mut buf = Vec::with_capacity(self.encoded_len());
         self._encode_to_buf(&mut buf)?;
         let length = buf.len();
 
@@ -484,6 +987,10 @@ impl Header {
             value,
         }
     }
+
+    pub fn key(&self) -> &str {
+        &self.header_key
+    }
 }
 
 impl ToByte for Header {
@@ -495,3 +1002,13 @@ impl ToByte for Header {
         Ok(())
     }
 }
+
+impl Describe for ProduceRequest<'_> {
+    fn api_key(&self) -> i16 {
+        self.header.api_key
+    }
+
+    fn api_version(&self) -> Option<i16> {
+        Some(self.header.api_version)
+    }
+}
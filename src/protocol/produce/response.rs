@@ -19,7 +19,45 @@
 //!   throttle_time_ms => INT32
 //! ```
 //!
-//! Note we are using version 3 for the response.
+//! Versions 0 and 1 lack the log append time, and version 0 the throttle
+//! time. Note we are using version 3 for the response. Versions 5 to 8 add the
+//! log start offset of each partition, and version 8 the errors of the
+//! individual records:
+//! ```text
+//! Produce Response (Version: 8) => [responses] throttle_time_ms
+//!   responses => topic [partition_responses]
+//!     topic => STRING
+//!     partition_responses => partition error_code base_offset log_append_time log_start_offset [record_errors] error_message
+//!       partition => INT32
+//!       error_code => INT16
+//!       base_offset => INT64
+//!       log_append_time => INT64
+//!       log_start_offset => INT64
+//!       record_errors => batch_index batch_index_error_message
+//!         batch_index => INT32
+//!         batch_index_error_message => NULLABLE_STRING
+//!       error_message => NULLABLE_STRING
+//!   throttle_time_ms => INT32
+//! ```
+//!
+//! Versions 9 and up are flexible, using compact strings and arrays and
+//! ending each structure with tagged fields:
+//! ```text
+//! Produce Response (Version: 9) => [responses] throttle_time_ms TAG_BUFFER
+//!   responses => name [partition_responses] TAG_BUFFER
+//!     name => COMPACT_STRING
+//!     partition_responses => index error_code base_offset log_append_time_ms log_start_offset [record_errors] error_message TAG_BUFFER
+//!       index => INT32
+//!       error_code => INT16
+//!       base_offset => INT64
+//!       log_append_time_ms => INT64
+//!       log_start_offset => INT64
+//!       record_errors => batch_index batch_index_error_message TAG_BUFFER
+//!         batch_index => INT32
+//!         batch_index_error_message => COMPACT_NULLABLE_STRING
+//!       error_message => COMPACT_NULLABLE_STRING
+//!   throttle_time_ms => INT32
+//! ```
 
 use bytes::Bytes;
 use nom::{
@@ -31,9 +69,22 @@ use nombytes::NomBytes;
 use crate::{
     error::{Error, KafkaCode, Result},
     parser,
-    protocol::{parse_header_response, HeaderResponse},
+    parser::{parse_compact_array, parse_tagged_fields},
+    protocol::{
+        parse_flexible_header_response, parse_header_response, produce::request::API_KEY_PRODUCE,
+        Describe, HeaderResponse,
+    },
 };
 
+/// The first version of the Produce response with the log append time.
+const FIRST_LOG_APPEND_TIME_VERSION: i16 = 2;
+/// The first version of the Produce response with the log start offset.
+pub const FIRST_LOG_START_OFFSET_VERSION: i16 = 5;
+/// The first version of the Produce response with the record errors.
+const FIRST_RECORD_ERRORS_VERSION: i16 = 8;
+/// The first version of the Produce response using the flexible encoding.
+pub const FIRST_FLEXIBLE_VERSION: i16 = 9;
+
 /// The base Produce Fetch response object.
 ///
 /// Note, the request needs to have a non-zero value for `required_acks` to receive a response.
@@ -66,8 +117,96 @@ pub struct PartitionResponse {
     pub error_code: KafkaCode,
     /// The base offset.
     pub base_offset: i64,
-    /// The timestamp returned by broker after appending the messages. If CreateTime is used for the topic, the timestamp will be -1. If LogAppendTime is used for the topic, the timestamp will be the broker local time when the messages are appended.
+    /// The timestamp returned by broker after appending the messages. If CreateTime is used for the topic, the timestamp will be -1. If LogAppendTime is used for the topic, the timestamp will be the broker local time when the messages are appended. It is -1 before version 2.
     pub log_append_time: i64,
+    /// The log start offset of the partition, or -1 before version 5.
+    pub log_start_offset: i64,
+}
+
+/// The outcome of producing to a single topic partition.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PartitionResult {
+    /// The topic name.
+    pub topic: Bytes,
+    /// The partition index.
+    pub partition: i32,
+    /// The base offset of the written records, or the error of the broker.
+    pub result: std::result::Result<i64, KafkaCode>,
+    /// The time the broker appended the records, or -1 with CreateTime.
+    pub log_append_time: i64,
+    /// The log start offset of the partition, or -1 before version 5.
+    pub log_start_offset: i64,
+}
+
+impl PartitionResult {
+    /// Whether the partition failed with an error worth retrying.
+    pub fn is_retriable(&self) -> bool {
+        matches!(self.result, Err(error_code) if error_code.is_retriable())
+    }
+}
+
+impl ProduceResponse {
+    /// The outcome for each topic partition in the response, so a partial
+    /// failure can be handled partition by partition.
+    pub fn partition_results(&self) -> Vec<PartitionResult> {
+        self.responses
+            .iter()
+            .flat_map(|topic| {
+                topic
+                    .partition_responses
+                    .iter()
+                    .map(|partition| PartitionResult {
+                        topic: topic.name.clone(),
+                        partition: partition.index,
+                        result: match partition.error_code {
+                            KafkaCode::None => Ok(partition.base_offset),
+                            error_code => Err(error_code),
+                        },
+                        log_append_time: partition.log_append_time,
+                        log_start_offset: partition.log_start_offset,
+                    })
+            })
+            .collect()
+    }
+
+    /// The topic partitions that failed with an error worth retrying.
+    pub fn retriable_partitions(&self) -> Vec<PartitionResult> {
+        self.partition_results()
+            .into_iter()
+            .filter(PartitionResult::is_retriable)
+            .collect()
+    }
+
+    /// Parse the response to a Produce request sent with the given api
+    /// version, decoding the flexible framing from version 9 on.
+    pub fn try_from_version(s: Bytes, api_version: i16) -> Result<Self> {
+        if (FIRST_LOG_APPEND_TIME_VERSION..FIRST_LOG_START_OFFSET_VERSION).contains(&api_version) {
+            return Self::try_from(s);
+        }
+        if api_version < FIRST_FLEXIBLE_VERSION {
+            tracing::trace!("Parsing ProduceResponse v{} {:?}", api_version, s);
+            let (_, produce_fetch) = parse_produce_response_version(api_version)(NomBytes::new(
+                s.clone(),
+            ))
+            .map_err(|err| {
+                tracing::error!("ERROR: Failed parsing ProduceResponse {:?}", err);
+                tracing::error!("ERROR: ProduceResponse Bytes {:?}", s);
+                Error::ParsingError(s)
+            })?;
+            tracing::trace!("Parsed ProduceResponse {:?}", produce_fetch);
+            return Ok(produce_fetch);
+        }
+
+        tracing::trace!("Parsing flexible ProduceResponse {:?}", s);
+        let (_, produce_fetch) = parse_flexible_produce_response(NomBytes::new(s.clone()))
+            .map_err(|err| {
+                tracing::error!("ERROR: Failed parsing flexible ProduceResponse {:?}", err);
+                tracing::error!("ERROR: ProduceResponse Bytes {:?}", s);
+                Error::ParsingError(s)
+            })?;
+        tracing::trace!("Parsed ProduceResponse {:?}", produce_fetch);
+        Ok(produce_fetch)
+    }
 }
 
 impl TryFrom<Bytes> for ProduceResponse {
@@ -87,15 +226,105 @@ impl TryFrom<Bytes> for ProduceResponse {
 }
 
 pub fn parse_produce_fetch_response(s: NomBytes) -> IResult<NomBytes, ProduceResponse> {
-    let (s, header) = parse_header_response(s)?;
-    let (s, responses) = parser::parse_array(parse_response)(s)?;
+    parse_produce_response_version(3)(s)
+}
 
-    Ok((s, ProduceResponse { header, responses }))
+/// Parse a non-flexible Produce response of the given version.
+pub fn parse_produce_response_version(
+    api_version: i16,
+) -> impl Fn(NomBytes) -> IResult<NomBytes, ProduceResponse> + Copy {
+    move |s: NomBytes| {
+        let (s, header) = parse_header_response(s)?;
+        let (s, responses) = parser::parse_array(parse_response_version(api_version))(s)?;
+
+        Ok((s, ProduceResponse { header, responses }))
+    }
 }
 
 pub fn parse_response(s: NomBytes) -> IResult<NomBytes, Response> {
-    let (s, name) = parser::parse_string(s)?;
-    let (s, partition_responses) = parser::parse_array(parse_partition_response)(s)?;
+    parse_response_version(3)(s)
+}
+
+fn parse_response_version(
+    api_version: i16,
+) -> impl Fn(NomBytes) -> IResult<NomBytes, Response> + Copy {
+    move |s: NomBytes| {
+        let (s, name) = parser::parse_string(s)?;
+        let (s, partition_responses) =
+            parser::parse_array(parse_partition_response_version(api_version))(s)?;
+
+        Ok((
+            s,
+            Response {
+                name,
+                partition_responses,
+            },
+        ))
+    }
+}
+
+pub fn parse_partition_response(s: NomBytes) -> IResult<NomBytes, PartitionResponse> {
+    parse_partition_response_version(3)(s)
+}
+
+fn parse_partition_response_version(
+    api_version: i16,
+) -> impl Fn(NomBytes) -> IResult<NomBytes, PartitionResponse> + Copy {
+    move |s: NomBytes| {
+        let (s, index) = be_i32(s)?;
+        let (s, error_code) = parser::parse_kafka_code(s)?;
+        let (s, base_offset) = be_i64(s)?;
+        let (s, log_append_time) = if api_version >= FIRST_LOG_APPEND_TIME_VERSION {
+            be_i64(s)?
+        } else {
+            (s, -1)
+        };
+        let (s, log_start_offset) = if api_version >= FIRST_LOG_START_OFFSET_VERSION {
+            be_i64(s)?
+        } else {
+            (s, -1)
+        };
+        let s = if api_version >= FIRST_RECORD_ERRORS_VERSION {
+            let (s, _record_errors) = parser::parse_array(parse_legacy_record_error)(s)?;
+            let (s, _error_message) = parser::parse_nullable_string(s)?;
+            s
+        } else {
+            s
+        };
+
+        Ok((
+            s,
+            PartitionResponse {
+                index,
+                error_code,
+                base_offset,
+                log_append_time,
+                log_start_offset,
+            },
+        ))
+    }
+}
+
+fn parse_legacy_record_error(s: NomBytes) -> IResult<NomBytes, (i32, Option<Bytes>)> {
+    let (s, batch_index) = be_i32(s)?;
+    let (s, batch_index_error_message) = parser::parse_nullable_string(s)?;
+
+    Ok((s, (batch_index, batch_index_error_message)))
+}
+
+pub fn parse_flexible_produce_response(s: NomBytes) -> IResult<NomBytes, ProduceResponse> {
+    let (s, header) = parse_flexible_header_response(s)?;
+    let (s, responses) = parse_compact_array(parse_flexible_response)(s)?;
+    let (s, _throttle_time_ms) = be_i32(s)?;
+    let (s, _) = parse_tagged_fields(s)?;
+
+    Ok((s, ProduceResponse { header, responses }))
+}
+
+fn parse_flexible_response(s: NomBytes) -> IResult<NomBytes, Response> {
+    let (s, name) = parser::parse_compact_string(s)?;
+    let (s, partition_responses) = parse_compact_array(parse_flexible_partition_response)(s)?;
+    let (s, _) = parse_tagged_fields(s)?;
 
     Ok((
         s,
@@ -106,11 +335,15 @@ pub fn parse_response(s: NomBytes) -> IResult<NomBytes, Response> {
     ))
 }
 
-pub fn parse_partition_response(s: NomBytes) -> IResult<NomBytes, PartitionResponse> {
+fn parse_flexible_partition_response(s: NomBytes) -> IResult<NomBytes, PartitionResponse> {
     let (s, index) = be_i32(s)?;
     let (s, error_code) = parser::parse_kafka_code(s)?;
     let (s, base_offset) = be_i64(s)?;
     let (s, log_append_time) = be_i64(s)?;
+    let (s, log_start_offset) = be_i64(s)?;
+    let (s, _record_errors) = parse_compact_array(parse_record_error)(s)?;
+    let (s, _error_message) = parser::parse_compact_nullable_string(s)?;
+    let (s, _) = parse_tagged_fields(s)?;
 
     Ok((
         s,
@@ -119,6 +352,35 @@ pub fn parse_partition_response(s: NomBytes) -> IResult<NomBytes, PartitionRespo
             error_code,
             base_offset,
             log_append_time,
+            log_start_offset,
         },
     ))
 }
+
+fn parse_record_error(s: NomBytes) -> IResult<NomBytes, (i32, Option<Bytes>)> {
+    let (s, batch_index) = be_i32(s)?;
+    let (s, batch_index_error_message) = parser::parse_compact_nullable_string(s)?;
+    let (s, _) = parse_tagged_fields(s)?;
+
+    Ok((s, (batch_index, batch_index_error_message)))
+}
+
+impl Describe for ProduceResponse {
+    fn api_key(&self) -> i16 {
+        API_KEY_PRODUCE
+    }
+
+    fn summary(&self) -> Vec<String> {
+        self.partition_results()
+            .into_iter()
+            .map(|result| {
+                format!(
+                    "topic {} partition {}: {:?}",
+                    String::from_utf8_lossy(&result.topic),
+                    result.partition,
+                    result.result
+                )
+            })
+            .collect()
+    }
+}
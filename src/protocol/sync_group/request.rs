@@ -111,7 +111,7 @@ impl Assignment<'_> {
 }
 
 impl PartitionAssignment<'_> {
-    pub fn new(topic_name: &str, partitions: Vec<i32>) -> PartitionAssignment {
+    pub fn new(topic_name: &str, partitions: Vec<i32>) -> PartitionAssignment<'_> {
         PartitionAssignment {
             topic_name,
             partitions,
@@ -0,0 +1,118 @@
+//! Helpers for the mock brokers of the unit tests, which speak just enough
+//! of the protocol to answer the requests of each test.
+//!
+//! Requests are handled without their size, so the header starts with the
+//! api key at 0, the api version at 2, the correlation id at 4 and the
+//! client id at 8.
+
+use std::sync::Arc;
+
+use bytes::BufMut;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::network::BrokerAddress;
+
+/// Where the client id starts, past the api key, api version and
+/// correlation id of the header.
+const CLIENT_ID: usize = 8;
+
+/// Bind a listener on a random local port.
+pub(crate) async fn listen() -> (TcpListener, u16) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    (listener, port)
+}
+
+pub(crate) fn broker_address(port: u16) -> BrokerAddress {
+    BrokerAddress {
+        host: "127.0.0.1".to_owned(),
+        port,
+    }
+}
+
+pub(crate) fn api_key(request: &[u8]) -> i16 {
+    read_i16(request, 0)
+}
+
+pub(crate) fn api_version(request: &[u8]) -> i16 {
+    read_i16(request, 2)
+}
+
+pub(crate) fn client_id(request: &[u8]) -> String {
+    read_string(request, CLIENT_ID).0.unwrap_or_default()
+}
+
+/// Where the body of a request starts, past the client id.
+pub(crate) fn body_offset(request: &[u8]) -> usize {
+    read_string(request, CLIENT_ID).1
+}
+
+pub(crate) fn read_i16(request: &[u8], offset: usize) -> i16 {
+    i16::from_be_bytes(request[offset..offset + 2].try_into().unwrap())
+}
+
+pub(crate) fn read_i32(request: &[u8], offset: usize) -> i32 {
+    i32::from_be_bytes(request[offset..offset + 4].try_into().unwrap())
+}
+
+pub(crate) fn read_i64(request: &[u8], offset: usize) -> i64 {
+    i64::from_be_bytes(request[offset..offset + 8].try_into().unwrap())
+}
+
+/// The nullable string at the offset, and where the field after it starts.
+pub(crate) fn read_string(request: &[u8], offset: usize) -> (Option<String>, usize) {
+    let len = read_i16(request, offset);
+    let start = offset + 2;
+    let end = start + len.max(0) as usize;
+    let string = (len >= 0).then(|| String::from_utf8(request[start..end].to_vec()).unwrap());
+    (string, end)
+}
+
+pub(crate) fn put_string(buf: &mut Vec<u8>, s: &str) {
+    buf.put_i16(s.len() as i16);
+    buf.put_slice(s.as_bytes());
+}
+
+pub(crate) fn put_compact_string(buf: &mut Vec<u8>, s: &str) {
+    buf.put_u8(s.len() as u8 + 1);
+    buf.put_slice(s.as_bytes());
+}
+
+/// Read the next request, or `None` once the client hangs up.
+pub(crate) async fn read_request(socket: &mut TcpStream) -> Option<Vec<u8>> {
+    let size = socket.read_u32().await.ok()?;
+    let mut request = vec![0; size as usize];
+    socket.read_exact(&mut request).await.unwrap();
+    Some(request)
+}
+
+/// Answer a request with the body, under its correlation id.
+pub(crate) async fn write_response(socket: &mut TcpStream, request: &[u8], body: &[u8]) {
+    let mut response = vec![];
+    response.put_i32(body.len() as i32 + 4);
+    response.put_slice(&request[4..8]);
+    response.put_slice(body);
+    socket.write_all(&response).await.unwrap();
+}
+
+/// Accept any number of connections, answering each request with the body
+/// the handler returns for it, or leaving it unanswered on `None`.
+pub(crate) fn serve<F>(listener: TcpListener, handler: F)
+where
+    F: Fn(&[u8]) -> Option<Vec<u8>> + Send + Sync + 'static,
+{
+    let handler = Arc::new(handler);
+    tokio::spawn(async move {
+        while let Ok((mut socket, _)) = listener.accept().await {
+            let handler = handler.clone();
+            tokio::spawn(async move {
+                while let Some(request) = read_request(&mut socket).await {
+                    if let Some(body) = handler(&request) {
+                        write_response(&mut socket, &request, &body).await;
+                    }
+                }
+            });
+        }
+    });
+}
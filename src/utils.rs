@@ -1,6 +1,6 @@
 use crc::Crc;
 use std::io::{Read, Write};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use crate::prelude::{Error, Result};
 use flate2::read::GzDecoder;
@@ -11,6 +11,11 @@ pub fn to_crc(data: &[u8]) -> u32 {
     Crc::<u32>::new(&crc::CRC_32_ISCSI).checksum(data)
 }
 
+/// The CRC-32 of a legacy message, where record batches use CRC-32C.
+pub fn to_legacy_crc(data: &[u8]) -> u32 {
+    Crc::<u32>::new(&crc::CRC_32_ISO_HDLC).checksum(data)
+}
+
 pub fn now() -> i64 {
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
@@ -18,21 +23,46 @@ pub fn now() -> i64 {
         .as_millis() as i64
 }
 
-pub fn compress(src: &[u8]) -> Result<Vec<u8>> {
-    let mut e = GzEncoder::new(Vec::new(), Compression::best());
+/// The wait before the first retry of a request, doubled for each retry
+/// after it.
+pub const RETRY_BACKOFF: Duration = Duration::from_millis(100);
+/// The longest wait before a retry.
+pub const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(5);
+
+/// How long to wait before a retry, counting retries from 1: the wait
+/// doubles from [`RETRY_BACKOFF`] with each retry, up to [`MAX_RETRY_BACKOFF`].
+pub fn retry_backoff(retry: u32) -> Duration {
+    let doublings = retry.saturating_sub(1).min(31);
+    RETRY_BACKOFF
+        .saturating_mul(1 << doublings)
+        .min(MAX_RETRY_BACKOFF)
+}
+
+/// The gzip level used unless another is set, the best compression.
+pub const DEFAULT_GZIP_LEVEL: u32 = 9;
+
+/// Gzip data at a level from 0, no compression, to 9, the best compression.
+/// Higher levels are clamped to 9.
+pub fn compress(src: &[u8], level: u32) -> Result<Vec<u8>> {
+    let mut e = GzEncoder::new(Vec::new(), Compression::new(level.min(9)));
 
     e.write_all(src).map_err(|e| Error::IoError(e.kind()))?;
     e.finish().map_err(|e| Error::IoError(e.kind()))
 }
 
-pub fn uncompress<T: Read>(src: T) -> Result<Vec<u8>> {
-    let mut d = GzDecoder::new(src);
+/// Decompress gzip data, failing with [`Error::DecompressedSizeExceeded`]
+/// rather than decompressing more than `max_bytes`.
+pub fn uncompress<T: Read>(src: T, max_bytes: usize) -> Result<Vec<u8>> {
+    let mut d = GzDecoder::new(src).take((max_bytes as u64).saturating_add(1));
 
     let mut buffer: Vec<u8> = Vec::new();
     d.read_to_end(&mut buffer).map_err(|e| {
         tracing::error!("Error uncompressing buffer {:?}", e);
         Error::IoError(e.kind())
     })?;
+    if buffer.len() > max_bytes {
+        return Err(Error::DecompressedSizeExceeded);
+    }
     Ok(buffer)
 }
 
@@ -44,7 +74,7 @@ fn test_uncompress() {
         31, 139, 8, 0, 192, 248, 79, 85, 2, 255, 43, 73, 45, 46, 1, 0, 12, 126, 127, 216, 4, 0, 0,
         0,
     ];
-    let uncomp_msg = String::from_utf8(uncompress(Cursor::new(msg)).unwrap()).unwrap();
+    let uncomp_msg = String::from_utf8(uncompress(Cursor::new(msg), usize::MAX).unwrap()).unwrap();
     assert_eq!(&uncomp_msg[..], "test");
 }
 
@@ -55,6 +85,15 @@ fn test_uncompress_panic() {
     let msg: Vec<u8> = vec![
         12, 42, 84, 104, 105, 115, 32, 105, 115, 32, 116, 101, 115, 116,
     ];
-    let uncomp_msg = String::from_utf8(uncompress(Cursor::new(msg)).unwrap()).unwrap();
+    let uncomp_msg = String::from_utf8(uncompress(Cursor::new(msg), usize::MAX).unwrap()).unwrap();
     assert_eq!(&uncomp_msg[..], "This is test");
 }
+
+#[test]
+fn test_retry_backoff() {
+    assert_eq!(retry_backoff(1), Duration::from_millis(100));
+    assert_eq!(retry_backoff(2), Duration::from_millis(200));
+    assert_eq!(retry_backoff(4), Duration::from_millis(800));
+    assert_eq!(retry_backoff(7), MAX_RETRY_BACKOFF);
+    assert_eq!(retry_backoff(u32::MAX), MAX_RETRY_BACKOFF);
+}
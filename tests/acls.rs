@@ -0,0 +1,91 @@
+#![cfg(feature = "authorizer-tests")]
+
+mod testsupport;
+
+use samsa::prelude::{
+    self, AclBinding, AclBindingFilter, AclOperation, ClusterMetadata, Error, PatternType,
+    PermissionType, ResourceType, TcpConnection,
+};
+
+const CLIENT_ID: &str = "acls integration test";
+const CORRELATION_ID: i32 = 1;
+
+#[tokio::test]
+async fn it_can_create_describe_and_delete_acls() -> Result<(), Box<Error>> {
+    let (skip, brokers, topic) = testsupport::get_brokers_and_topic()?;
+    if skip {
+        return Ok(());
+    }
+    let mut metadata = ClusterMetadata::new(
+        brokers.clone(),
+        CORRELATION_ID,
+        CLIENT_ID.to_owned(),
+        vec![],
+    )
+    .await?;
+    let conn: &mut TcpConnection = metadata
+        .broker_connections
+        .get_mut(&metadata.controller_id)
+        .unwrap();
+    testsupport::ensure_topic_creation(conn.clone(), &topic, CORRELATION_ID, CLIENT_ID).await?;
+
+    let binding = AclBinding {
+        resource_type: ResourceType::Topic,
+        resource_name: topic.clone(),
+        pattern_type: PatternType::Literal,
+        principal: "User:samsa-acl-test".to_owned(),
+        host: "*".to_owned(),
+        operation: AclOperation::Read,
+        permission_type: PermissionType::Allow,
+    };
+    let filter = AclBindingFilter::from(&binding);
+
+    //
+    // Test creating
+    //
+    let create_response = prelude::create_acls(
+        conn.clone(),
+        CORRELATION_ID,
+        CLIENT_ID,
+        std::slice::from_ref(&binding),
+    )
+    .await?;
+    assert_eq!(create_response.results.len(), 1);
+
+    //
+    // Test describing
+    //
+    let describe_response =
+        prelude::describe_acls(conn.clone(), CORRELATION_ID, CLIENT_ID, &filter).await?;
+    assert_eq!(describe_response.bindings(), vec![binding.clone()]);
+
+    //
+    // Test deleting
+    //
+    let delete_response = prelude::delete_acls(
+        conn.clone(),
+        CORRELATION_ID,
+        CLIENT_ID,
+        std::slice::from_ref(&filter),
+    )
+    .await?;
+    assert_eq!(delete_response.filter_results.len(), 1);
+    assert_eq!(delete_response.filter_results[0].matching_acls.len(), 1);
+
+    let describe_response =
+        prelude::describe_acls(conn.clone(), CORRELATION_ID, CLIENT_ID, &filter).await?;
+    assert!(describe_response.bindings().is_empty());
+
+    //
+    // Delete topic
+    //
+    prelude::delete_topics(
+        conn.clone(),
+        CORRELATION_ID,
+        CLIENT_ID,
+        vec![topic.as_str()],
+    )
+    .await?;
+
+    Ok(())
+}
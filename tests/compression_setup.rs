@@ -90,7 +90,7 @@ async fn writing_and_reading_using_compression_setup() -> Result<(), Box<Error>>
             None => break,
             Some(r) => {
                 assert_eq!(r.topic_name, bytes::Bytes::from(topic.to_string()));
-                assert_eq!(r.value, bytes::Bytes::from_static(b"0123456789"));
+                assert_eq!(r.value, Some(bytes::Bytes::from_static(b"0123456789")));
             }
         }
     }
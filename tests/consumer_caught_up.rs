@@ -0,0 +1,91 @@
+mod testsupport;
+
+use futures::FutureExt;
+use samsa::prelude::{
+    self, protocol::produce::request::RecordBatchAttributes, BrokerConnection, ClusterMetadata,
+    ConsumerBuilder, Error, ProduceMessage, TcpConnection, TopicPartitionsBuilder,
+};
+
+const CLIENT_ID: &str = "consumer caught up integration test";
+const CORRELATION_ID: i32 = 1;
+const PARTITION_ID: i32 = 0;
+const NUMBER_OF_RECORDS: usize = 100;
+const RECORDS_PER_BATCH: usize = 25;
+
+#[tokio::test]
+async fn it_signals_when_caught_up() -> Result<(), Box<Error>> {
+    let (skip, brokers, topic) = testsupport::get_brokers_and_topic()?;
+    if skip {
+        return Ok(());
+    }
+    let conn = TcpConnection::new(brokers.clone()).await?;
+    testsupport::ensure_topic_creation(conn.clone(), &topic, CORRELATION_ID, CLIENT_ID).await?;
+
+    let cluster_metadata = ClusterMetadata::<TcpConnection>::new(
+        brokers.clone(),
+        CORRELATION_ID,
+        CLIENT_ID.to_owned(),
+        vec![topic.clone()],
+    )
+    .await?;
+    let assignment = TopicPartitionsBuilder::new()
+        .assign(topic.clone(), vec![PARTITION_ID])
+        .build();
+    let (leader_conn, _) =
+        cluster_metadata.get_connections_for_topic_partitions(&assignment)?[0].to_owned();
+
+    //
+    // Produce the records to bootstrap from
+    //
+    for batch in 0..NUMBER_OF_RECORDS / RECORDS_PER_BATCH {
+        let messages: Vec<ProduceMessage> = (0..RECORDS_PER_BATCH)
+            .map(|i| ProduceMessage {
+                key: None,
+                value: Some(bytes::Bytes::from(format!("{}-{}", batch, i))),
+                headers: vec![],
+                topic: topic.clone(),
+                partition_id: PARTITION_ID,
+            })
+            .collect();
+        prelude::produce(
+            leader_conn.clone(),
+            CORRELATION_ID,
+            CLIENT_ID,
+            1,
+            1000,
+            &messages,
+            RecordBatchAttributes::new(None),
+        )
+        .await?;
+    }
+
+    //
+    // Test catching up
+    //
+    let mut consumer = ConsumerBuilder::<TcpConnection>::new(brokers, assignment)
+        .await?
+        .max_partition_bytes(100)
+        .build();
+    let mut caught_up = Box::pin(consumer.caught_up());
+
+    let mut consumed = 0;
+    for _ in 0..NUMBER_OF_RECORDS {
+        assert!((&mut caught_up).now_or_never().is_none());
+        let (batch, _) = consumer.next_batch().await?;
+        consumed += batch.count();
+        assert_eq!(consumer.is_caught_up(), consumed == NUMBER_OF_RECORDS);
+        if consumed == NUMBER_OF_RECORDS {
+            break;
+        }
+    }
+
+    assert_eq!(consumed, NUMBER_OF_RECORDS);
+    assert!(caught_up.now_or_never().is_some());
+
+    //
+    // Delete topic
+    //
+    prelude::delete_topics(conn, CORRELATION_ID, CLIENT_ID, vec![topic.as_str()]).await?;
+
+    Ok(())
+}
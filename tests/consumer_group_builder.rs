@@ -1,6 +1,7 @@
 mod testsupport;
 
-use samsa::prelude::{ConsumerGroupBuilder, Error, TcpConnection, TopicPartitions};
+use samsa::prelude::{ConsumerGroupBuilder, Error, KafkaCode, TcpConnection, TopicPartitions};
+use tokio_stream::StreamExt;
 
 #[tokio::test]
 async fn it_can_build_with_minimal_args() -> Result<(), Box<Error>> {
@@ -34,3 +35,31 @@ async fn it_can_build_with_ref_to_builder() -> Result<(), Box<Error>> {
     let _consumer = builder_ref.clone().build();
     Ok(())
 }
+
+#[tokio::test]
+async fn it_surfaces_an_out_of_range_session_timeout() -> Result<(), Box<Error>> {
+    let (skip, brokers) = testsupport::get_brokers()?;
+    if skip {
+        return Ok(());
+    }
+    // far below the default group.min.session.timeout.ms of 6 seconds
+    let consumer = ConsumerGroupBuilder::<TcpConnection>::new(
+        brokers,
+        "session timeout integration test".to_string(),
+        TopicPartitions::default(),
+    )
+    .await?
+    .session_timeout_ms(1)
+    .build()
+    .await?;
+
+    let stream = consumer.into_stream();
+    tokio::pin!(stream);
+    let first = stream.next().await.expect("stream ended without an error");
+
+    assert!(matches!(
+        first,
+        Err(Error::KafkaError(KafkaCode::InvalidSessionTimeout))
+    ));
+    Ok(())
+}
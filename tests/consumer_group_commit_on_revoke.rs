@@ -0,0 +1,133 @@
+mod testsupport;
+
+use std::{
+    collections::HashSet,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use samsa::prelude::{
+    self, protocol::produce::request::RecordBatchAttributes, BrokerConnection, ClusterMetadata,
+    ConsumerGroupBuilder, Error, ProduceMessage, TcpConnection, TopicPartitionsBuilder,
+};
+
+const CLIENT_ID: &str = "consumer group commit on revoke integration test";
+const CORRELATION_ID: i32 = 1;
+const GROUP_ID: &str = "commit on revoke integration test";
+const PARTITION_ID: i32 = 0;
+const RECORDS_PER_ROUND: usize = 10;
+
+#[tokio::test]
+async fn it_does_not_hand_records_to_the_next_owner_twice() -> Result<(), Box<Error>> {
+    let (skip, brokers, topic) = testsupport::get_brokers_and_topic()?;
+    if skip {
+        return Ok(());
+    }
+    let conn = TcpConnection::new(brokers.clone()).await?;
+    testsupport::ensure_topic_creation(conn.clone(), &topic, CORRELATION_ID, CLIENT_ID).await?;
+
+    let cluster_metadata = ClusterMetadata::<TcpConnection>::new(
+        brokers.clone(),
+        CORRELATION_ID,
+        CLIENT_ID.to_owned(),
+        vec![topic.clone()],
+    )
+    .await?;
+    let assignment = TopicPartitionsBuilder::new()
+        .assign(topic.clone(), vec![PARTITION_ID])
+        .build();
+    let (leader_conn, _) =
+        cluster_metadata.get_connections_for_topic_partitions(&assignment)?[0].to_owned();
+    let produce_round = |round: usize| {
+        let messages: Vec<ProduceMessage> = (0..RECORDS_PER_ROUND)
+            .map(|i| ProduceMessage {
+                key: None,
+                value: Some(bytes::Bytes::from(format!(
+                    "{}",
+                    round * RECORDS_PER_ROUND + i
+                ))),
+                headers: vec![],
+                topic: topic.clone(),
+                partition_id: PARTITION_ID,
+            })
+            .collect();
+        let leader_conn = leader_conn.clone();
+        async move {
+            prelude::produce(
+                leader_conn,
+                CORRELATION_ID,
+                CLIENT_ID,
+                1,
+                1000,
+                &messages,
+                RecordBatchAttributes::new(None),
+            )
+            .await
+        }
+    };
+
+    let handled = Arc::new(Mutex::new(vec![]));
+    let start_member = || {
+        let brokers = brokers.clone();
+        let assignment = assignment.clone();
+        let handled = handled.clone();
+        tokio::spawn(async move {
+            let member = ConsumerGroupBuilder::<TcpConnection>::new(
+                brokers,
+                GROUP_ID.to_owned(),
+                assignment,
+            )
+            .await?
+            .build()
+            .await?;
+            member
+                .consume_with(|messages| {
+                    let handled = handled.clone();
+                    async move {
+                        // slow enough for the rebalance to land mid-processing
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                        handled
+                            .lock()
+                            .unwrap()
+                            .extend(messages.into_iter().map(|message| message.offset));
+                        Ok(())
+                    }
+                })
+                .await
+        })
+    };
+    let handled_count = || handled.lock().unwrap().len();
+    let wait_for = |count: usize| async move {
+        for _ in 0..100 {
+            if handled_count() >= count {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    };
+
+    produce_round(0).await?;
+    let first = start_member();
+    wait_for(RECORDS_PER_ROUND).await;
+
+    // a second member joining revokes the partition of the first
+    let second = start_member();
+    produce_round(1).await?;
+    wait_for(2 * RECORDS_PER_ROUND).await;
+    // give a duplicate the chance to show up
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    first.abort();
+    second.abort();
+
+    let handled = handled.lock().unwrap().clone();
+    let distinct: HashSet<_> = handled.iter().collect();
+    assert_eq!(distinct.len(), 2 * RECORDS_PER_ROUND);
+    assert_eq!(handled.len(), distinct.len());
+
+    //
+    // Delete topic
+    //
+    prelude::delete_topics(conn, CORRELATION_ID, CLIENT_ID, vec![topic.as_str()]).await?;
+
+    Ok(())
+}
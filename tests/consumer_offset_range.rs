@@ -0,0 +1,92 @@
+mod testsupport;
+
+use std::collections::HashMap;
+
+use samsa::prelude::{
+    self, protocol::produce::request::RecordBatchAttributes, BrokerConnection, ClusterMetadata,
+    ConsumerBuilder, Error, ProduceMessage, TcpConnection, TopicPartitionsBuilder,
+};
+use tokio_stream::StreamExt;
+
+const CLIENT_ID: &str = "consumer offset range integration test";
+const CORRELATION_ID: i32 = 1;
+const PARTITION_ID: i32 = 0;
+const NUMBER_OF_RECORDS: usize = 30;
+const RECORDS_PER_BATCH: usize = 6;
+const START_OFFSET: i64 = 10;
+const END_OFFSET: i64 = 20;
+
+#[tokio::test]
+async fn it_consumes_a_bounded_offset_range() -> Result<(), Box<Error>> {
+    let (skip, brokers, topic) = testsupport::get_brokers_and_topic()?;
+    if skip {
+        return Ok(());
+    }
+    let conn = TcpConnection::new(brokers.clone()).await?;
+    testsupport::ensure_topic_creation(conn.clone(), &topic, CORRELATION_ID, CLIENT_ID).await?;
+
+    let cluster_metadata = ClusterMetadata::<TcpConnection>::new(
+        brokers.clone(),
+        CORRELATION_ID,
+        CLIENT_ID.to_owned(),
+        vec![topic.clone()],
+    )
+    .await?;
+    let assignment = TopicPartitionsBuilder::new()
+        .assign(topic.clone(), vec![PARTITION_ID])
+        .build();
+    let (leader_conn, _) =
+        cluster_metadata.get_connections_for_topic_partitions(&assignment)?[0].to_owned();
+
+    //
+    // Seed the topic
+    //
+    for batch in 0..NUMBER_OF_RECORDS / RECORDS_PER_BATCH {
+        let messages: Vec<ProduceMessage> = (0..RECORDS_PER_BATCH)
+            .map(|i| ProduceMessage {
+                key: None,
+                value: Some(bytes::Bytes::from(format!("{}-{}", batch, i))),
+                headers: vec![],
+                topic: topic.clone(),
+                partition_id: PARTITION_ID,
+            })
+            .collect();
+        prelude::produce(
+            leader_conn.clone(),
+            CORRELATION_ID,
+            CLIENT_ID,
+            1,
+            1000,
+            &messages,
+            RecordBatchAttributes::new(None),
+        )
+        .await?;
+    }
+
+    //
+    // Test consuming the range
+    //
+    let topic_partition = (topic.clone(), PARTITION_ID);
+    let stream = ConsumerBuilder::<TcpConnection>::new(brokers, assignment)
+        .await?
+        .seek(&HashMap::from([(topic_partition.clone(), START_OFFSET)]))
+        .end_offsets(&HashMap::from([(topic_partition, END_OFFSET)]))
+        .max_partition_bytes(100)
+        .build()
+        .into_stream();
+    tokio::pin!(stream);
+
+    let mut offsets = vec![];
+    while let Some(batch) = stream.next().await {
+        offsets.extend(batch?.map(|message| message.offset as i64));
+    }
+
+    assert_eq!(offsets, (START_OFFSET..END_OFFSET).collect::<Vec<i64>>());
+
+    //
+    // Delete topic
+    //
+    prelude::delete_topics(conn, CORRELATION_ID, CLIENT_ID, vec![topic.as_str()]).await?;
+
+    Ok(())
+}
@@ -0,0 +1,127 @@
+mod testsupport;
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use samsa::prelude::{
+    self, protocol::produce::request::RecordBatchAttributes, ClusterMetadata, ConsumerBuilder,
+    Error, ProduceMessage, TcpConnection, TopicPartitionsBuilder,
+};
+
+const CLIENT_ID: &str = "consumer seek integration test";
+const CORRELATION_ID: i32 = 1;
+const PARTITION_ID: i32 = 0;
+
+fn messages(topic: &str, prefix: &str) -> Vec<ProduceMessage> {
+    (0..3)
+        .map(|i| ProduceMessage {
+            key: None,
+            value: Some(bytes::Bytes::from(format!("{}-{}", prefix, i))),
+            headers: vec![],
+            topic: topic.to_owned(),
+            partition_id: PARTITION_ID,
+        })
+        .collect()
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as i64
+}
+
+#[tokio::test]
+async fn it_can_seek_to_timestamp() -> Result<(), Box<Error>> {
+    let (skip, brokers, topic) = testsupport::get_brokers_and_topic()?;
+    if skip {
+        return Ok(());
+    }
+    let mut metadata = ClusterMetadata::new(
+        brokers.clone(),
+        CORRELATION_ID,
+        CLIENT_ID.to_owned(),
+        vec![],
+    )
+    .await?;
+    let conn: &mut TcpConnection = metadata
+        .broker_connections
+        .get_mut(&metadata.controller_id)
+        .unwrap();
+    testsupport::ensure_topic_creation(conn.clone(), &topic, CORRELATION_ID, CLIENT_ID).await?;
+
+    let cluster_metadata = ClusterMetadata::<TcpConnection>::new(
+        brokers.clone(),
+        CORRELATION_ID,
+        CLIENT_ID.to_owned(),
+        vec![topic.clone()],
+    )
+    .await?;
+    let assignment = TopicPartitionsBuilder::new()
+        .assign(topic.clone(), vec![PARTITION_ID])
+        .build();
+    let (leader_conn, _) =
+        cluster_metadata.get_connections_for_topic_partitions(&assignment)?[0].to_owned();
+
+    //
+    // Produce records on either side of a timestamp
+    //
+    prelude::produce(
+        leader_conn.clone(),
+        CORRELATION_ID,
+        CLIENT_ID,
+        1,
+        1000,
+        &messages(&topic, "before"),
+        RecordBatchAttributes::new(None),
+    )
+    .await?;
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let timestamp = now();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    prelude::produce(
+        leader_conn.clone(),
+        CORRELATION_ID,
+        CLIENT_ID,
+        1,
+        1000,
+        &messages(&topic, "after"),
+        RecordBatchAttributes::new(None),
+    )
+    .await?;
+
+    //
+    // Test seeking
+    //
+    let mut consumer = ConsumerBuilder::<TcpConnection>::new(brokers, assignment)
+        .await?
+        .build();
+    consumer
+        .seek_to_timestamp(&[(topic.clone(), PARTITION_ID)], timestamp)
+        .await?;
+
+    let mut first = None;
+    for _ in 0..10 {
+        let (mut batch, _) = consumer.next_batch().await?;
+        first = batch.next();
+        if first.is_some() {
+            break;
+        }
+    }
+    let first = first.expect("no records after seeking");
+
+    assert_eq!(first.offset, 3);
+    assert_eq!(first.value, Some(bytes::Bytes::from("after-0")));
+
+    //
+    // Delete topic
+    //
+    prelude::delete_topics(
+        conn.clone(),
+        CORRELATION_ID,
+        CLIENT_ID,
+        vec![topic.as_str()],
+    )
+    .await?;
+
+    Ok(())
+}
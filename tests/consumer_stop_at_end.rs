@@ -0,0 +1,82 @@
+mod testsupport;
+
+use samsa::prelude::{
+    self, protocol::produce::request::RecordBatchAttributes, BrokerConnection, ClusterMetadata,
+    ConsumerBuilder, Error, ProduceMessage, TcpConnection, TopicPartitionsBuilder,
+};
+use tokio_stream::StreamExt;
+
+const CLIENT_ID: &str = "consumer stop at end integration test";
+const CORRELATION_ID: i32 = 1;
+const PARTITION_ID: i32 = 0;
+const NUMBER_OF_RECORDS: usize = 100;
+
+#[tokio::test]
+async fn it_completes_the_stream_at_the_end_of_the_log() -> Result<(), Box<Error>> {
+    let (skip, brokers, topic) = testsupport::get_brokers_and_topic()?;
+    if skip {
+        return Ok(());
+    }
+    let conn = TcpConnection::new(brokers.clone()).await?;
+    testsupport::ensure_topic_creation(conn.clone(), &topic, CORRELATION_ID, CLIENT_ID).await?;
+
+    let cluster_metadata = ClusterMetadata::<TcpConnection>::new(
+        brokers.clone(),
+        CORRELATION_ID,
+        CLIENT_ID.to_owned(),
+        vec![topic.clone()],
+    )
+    .await?;
+    let assignment = TopicPartitionsBuilder::new()
+        .assign(topic.clone(), vec![PARTITION_ID])
+        .build();
+    let (leader_conn, _) =
+        cluster_metadata.get_connections_for_topic_partitions(&assignment)?[0].to_owned();
+
+    //
+    // Seed the topic
+    //
+    let messages: Vec<ProduceMessage> = (0..NUMBER_OF_RECORDS)
+        .map(|i| ProduceMessage {
+            key: None,
+            value: Some(bytes::Bytes::from(i.to_string())),
+            headers: vec![],
+            topic: topic.clone(),
+            partition_id: PARTITION_ID,
+        })
+        .collect();
+    prelude::produce(
+        leader_conn,
+        CORRELATION_ID,
+        CLIENT_ID,
+        1,
+        1000,
+        &messages,
+        RecordBatchAttributes::new(None),
+    )
+    .await?;
+
+    //
+    // Test the stream completes by itself
+    //
+    let stream = ConsumerBuilder::<TcpConnection>::new(brokers, assignment)
+        .await?
+        .stop_at_end()
+        .build()
+        .into_stream();
+    tokio::pin!(stream);
+
+    let mut consumed = 0;
+    while let Some(batch) = stream.next().await {
+        consumed += batch?.count();
+    }
+
+    assert_eq!(consumed, NUMBER_OF_RECORDS);
+
+    //
+    // Delete topic
+    //
+    prelude::delete_topics(conn, CORRELATION_ID, CLIENT_ID, vec![topic.as_str()]).await?;
+
+    Ok(())
+}
@@ -0,0 +1,92 @@
+mod testsupport;
+
+use samsa::prelude::{
+    self, protocol::produce::request::RecordBatchAttributes, BrokerConnection, ClusterMetadata,
+    Error, ProduceMessage, TcpConnection, TopicPartitionsBuilder,
+};
+
+const CLIENT_ID: &str = "consumer tail integration test";
+const CORRELATION_ID: i32 = 1;
+const PARTITION_ID: i32 = 0;
+const NUMBER_OF_RECORDS: usize = 5;
+
+#[tokio::test]
+async fn it_returns_every_record_when_there_are_fewer_than_asked_for() -> Result<(), Box<Error>> {
+    let (skip, brokers, topic) = testsupport::get_brokers_and_topic()?;
+    if skip {
+        return Ok(());
+    }
+    let conn = TcpConnection::new(brokers.clone()).await?;
+    testsupport::ensure_topic_creation(conn.clone(), &topic, CORRELATION_ID, CLIENT_ID).await?;
+
+    let cluster_metadata = ClusterMetadata::<TcpConnection>::new(
+        brokers.clone(),
+        CORRELATION_ID,
+        CLIENT_ID.to_owned(),
+        vec![topic.clone()],
+    )
+    .await?;
+    let assignment = TopicPartitionsBuilder::new()
+        .assign(topic.clone(), vec![PARTITION_ID])
+        .build();
+    let (leader_conn, _) =
+        cluster_metadata.get_connections_for_topic_partitions(&assignment)?[0].to_owned();
+
+    let messages: Vec<ProduceMessage> = (0..NUMBER_OF_RECORDS)
+        .map(|i| ProduceMessage {
+            key: None,
+            value: Some(bytes::Bytes::from(format!("{}", i))),
+            headers: vec![],
+            topic: topic.clone(),
+            partition_id: PARTITION_ID,
+        })
+        .collect();
+    prelude::produce(
+        leader_conn.clone(),
+        CORRELATION_ID,
+        CLIENT_ID,
+        1,
+        1000,
+        &messages,
+        RecordBatchAttributes::new(None),
+    )
+    .await?;
+
+    let records = prelude::tail(
+        leader_conn.clone(),
+        CORRELATION_ID,
+        CLIENT_ID,
+        &(topic.clone(), PARTITION_ID),
+        10,
+    )
+    .await?;
+    assert_eq!(records.len(), NUMBER_OF_RECORDS);
+    for (i, record) in records.iter().enumerate() {
+        assert_eq!(record.value, Some(bytes::Bytes::from(format!("{}", i))));
+    }
+
+    // only the newest records when there are more than asked for
+    let records = prelude::tail(
+        leader_conn,
+        CORRELATION_ID,
+        CLIENT_ID,
+        &(topic.clone(), PARTITION_ID),
+        2,
+    )
+    .await?;
+    let values: Vec<_> = records.into_iter().map(|record| record.value).collect();
+    assert_eq!(
+        values,
+        vec![
+            Some(bytes::Bytes::from_static(b"3")),
+            Some(bytes::Bytes::from_static(b"4"))
+        ]
+    );
+
+    //
+    // Delete topic
+    //
+    prelude::delete_topics(conn, CORRELATION_ID, CLIENT_ID, vec![topic.as_str()]).await?;
+
+    Ok(())
+}
@@ -1,9 +1,10 @@
 mod testsupport;
 
 use samsa::prelude::{
-    self, protocol, BrokerConnection, ClusterMetadata, Error, KafkaCode, TcpConnection,
+    self, protocol, protocol::produce::request::RecordBatchAttributes, BrokerConnection,
+    ClusterMetadata, Error, KafkaCode, TcpConnection,
 };
-use std::collections::HashMap;
+use std::{collections::HashMap, time::Duration};
 
 const CLIENT_ID: &str = "create delete topic integration test";
 const CORRELATION_ID: i32 = 1;
@@ -75,6 +76,7 @@ async fn it_can_create_and_delete_topics_with_functions() -> Result<(), Box<Erro
         CORRELATION_ID,
         CLIENT_ID,
         HashMap::from([("function-topic", 2)]),
+        false,
     )
     .await?;
     assert_eq!(create_res.topics[0].error_code, KafkaCode::None);
@@ -93,3 +95,229 @@ async fn it_can_create_and_delete_topics_with_functions() -> Result<(), Box<Erro
 
     Ok(())
 }
+
+#[tokio::test]
+async fn it_only_validates_topics_when_asked_to() -> Result<(), Box<Error>> {
+    let (skip, brokers) = testsupport::get_brokers()?;
+    if skip {
+        return Ok(());
+    }
+
+    let mut metadata =
+        ClusterMetadata::<TcpConnection>::new(brokers, 1, "rust".to_string(), vec![]).await?;
+
+    let conn: &mut TcpConnection = metadata
+        .broker_connections
+        .get_mut(&metadata.controller_id)
+        .unwrap();
+    let topic = "validate-only-topic";
+
+    let create_res = prelude::create_topics(
+        conn.clone(),
+        CORRELATION_ID,
+        CLIENT_ID,
+        HashMap::from([(topic, 2)]),
+        true,
+    )
+    .await?;
+    assert_eq!(create_res.topics[0].error_code, KafkaCode::None);
+
+    //
+    // The topic is not listed
+    //
+    let no_topics: [&str; 0] = [];
+    let metadata_req = protocol::MetadataRequest::new(CORRELATION_ID, CLIENT_ID, &no_topics);
+    conn.send_request(&metadata_req).await?;
+    let metadata_res =
+        protocol::MetadataResponse::try_from(conn.receive_response().await?.freeze())?;
+    assert!(!metadata_res
+        .topics
+        .iter()
+        .any(|metadata| metadata.name == topic.as_bytes()));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn it_can_await_a_created_topic_before_producing() -> Result<(), Box<Error>> {
+    let (skip, brokers) = testsupport::get_brokers()?;
+    if skip {
+        return Ok(());
+    }
+
+    let mut metadata =
+        ClusterMetadata::<TcpConnection>::new(brokers, 1, "rust".to_string(), vec![]).await?;
+
+    let conn: &mut TcpConnection = metadata
+        .broker_connections
+        .get_mut(&metadata.controller_id)
+        .unwrap();
+    let topic = "await-ready-topic";
+
+    //
+    // Create topic and wait for it to be ready
+    //
+    let create_res = prelude::create_topics(
+        conn.clone(),
+        CORRELATION_ID,
+        CLIENT_ID,
+        HashMap::from([(topic, 1)]),
+        false,
+    )
+    .await?;
+    assert_eq!(create_res.topics[0].error_code, KafkaCode::None);
+
+    prelude::await_topic_ready(
+        conn.clone(),
+        CORRELATION_ID,
+        CLIENT_ID,
+        topic,
+        Duration::from_secs(10),
+    )
+    .await?;
+
+    //
+    // Produce right away
+    //
+    let produce_message = prelude::ProduceMessage {
+        key: None,
+        value: Some(bytes::Bytes::from("ready")),
+        topic: topic.to_string(),
+        partition_id: 0,
+        headers: vec![],
+    };
+    let produce_res = prelude::produce(
+        conn.clone(),
+        CORRELATION_ID,
+        CLIENT_ID,
+        1,
+        1000,
+        &vec![produce_message],
+        RecordBatchAttributes::new(None),
+    )
+    .await?
+    .unwrap();
+    assert_eq!(
+        produce_res.responses[0].partition_responses[0].error_code,
+        KafkaCode::None
+    );
+
+    //
+    // Delete topic
+    //
+    let delete_res =
+        prelude::delete_topics(conn.clone(), CORRELATION_ID, CLIENT_ID, vec![topic]).await?;
+    assert_eq!(delete_res.topics[0].error_code, KafkaCode::None);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn it_can_ensure_topics_exist() -> Result<(), Box<Error>> {
+    let (skip, brokers) = testsupport::get_brokers()?;
+    if skip {
+        return Ok(());
+    }
+
+    let mut metadata =
+        ClusterMetadata::<TcpConnection>::new(brokers, 1, "rust".to_string(), vec![]).await?;
+
+    let conn: &mut TcpConnection = metadata
+        .broker_connections
+        .get_mut(&metadata.controller_id)
+        .unwrap();
+    let topic = "ensure-topic";
+
+    //
+    // Create topic, then create it again
+    //
+    let created = prelude::ensure_topics(
+        conn.clone(),
+        CORRELATION_ID,
+        CLIENT_ID,
+        HashMap::from([(topic, 2)]),
+    )
+    .await?;
+    assert_eq!(created, vec![topic.to_string()]);
+
+    let created = prelude::ensure_topics(
+        conn.clone(),
+        CORRELATION_ID,
+        CLIENT_ID,
+        HashMap::from([(topic, 2)]),
+    )
+    .await?;
+    assert!(created.is_empty());
+
+    //
+    // Delete topic
+    //
+    let delete_res =
+        prelude::delete_topics(conn.clone(), CORRELATION_ID, CLIENT_ID, vec![topic]).await?;
+    assert_eq!(delete_res.topics[0].error_code, KafkaCode::None);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn it_can_delete_topics_by_id() -> Result<(), Box<Error>> {
+    let (skip, brokers) = testsupport::get_brokers()?;
+    if skip {
+        return Ok(());
+    }
+
+    let mut metadata =
+        ClusterMetadata::<TcpConnection>::new(brokers, 1, "rust".to_string(), vec![]).await?;
+
+    let conn = metadata
+        .broker_connections
+        .get(&metadata.controller_id)
+        .unwrap()
+        .clone();
+    let topic = "delete-by-id-topic";
+
+    //
+    // Create topic
+    //
+    let create_res = prelude::create_topics(
+        conn.clone(),
+        CORRELATION_ID,
+        CLIENT_ID,
+        HashMap::from([(topic, 1)]),
+        false,
+    )
+    .await?;
+    assert_eq!(create_res.topics[0].error_code, KafkaCode::None);
+    prelude::await_topic_ready(
+        conn.clone(),
+        CORRELATION_ID,
+        CLIENT_ID,
+        topic,
+        Duration::from_secs(10),
+    )
+    .await?;
+
+    //
+    // Resolve its id
+    //
+    metadata.metadata_version = protocol::metadata::request::FIRST_TOPIC_ID_VERSION;
+    metadata.add_topics(&[topic.to_string()]).await?;
+    metadata.refresh().await?;
+    let topic_id = metadata.get_topic_id(topic).unwrap();
+
+    //
+    // Delete topic by id, then again once it is gone
+    //
+    let delete_res =
+        prelude::delete_topics_by_id(conn.clone(), CORRELATION_ID, CLIENT_ID, vec![topic_id])
+            .await?;
+    assert_eq!(delete_res.topics[0].error_code, KafkaCode::None);
+    assert_eq!(delete_res.topics[0].name, topic);
+
+    let delete_res =
+        prelude::delete_topics_by_id(conn.clone(), CORRELATION_ID, CLIENT_ID, vec![topic_id])
+            .await?;
+    assert_eq!(delete_res.topics[0].error_code, KafkaCode::UnknownTopicId);
+
+    Ok(())
+}
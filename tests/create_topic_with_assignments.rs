@@ -0,0 +1,75 @@
+mod testsupport;
+
+use samsa::prelude::{self, ClusterMetadata, Error, KafkaCode, TcpConnection, TopicSpec};
+use std::{collections::HashMap, time::Duration};
+
+const CLIENT_ID: &str = "create topic with assignments integration test";
+const CORRELATION_ID: i32 = 1;
+
+#[tokio::test]
+async fn it_creates_a_topic_with_a_manual_assignment() -> Result<(), Box<Error>> {
+    let (skip, brokers) = testsupport::get_brokers()?;
+    if skip {
+        return Ok(());
+    }
+    let topic = "tester-manual-assignment";
+
+    let mut metadata =
+        ClusterMetadata::<TcpConnection>::new(brokers, 1, "rust".to_string(), vec![]).await?;
+    let conn: TcpConnection = metadata
+        .broker_connections
+        .get(&metadata.controller_id)
+        .unwrap()
+        .clone();
+
+    // a spec with both a replication factor and assignments is rejected
+    let mut conflicting = TopicSpec::with_assignments(vec![(0, vec![metadata.controller_id])]);
+    conflicting.replication_factor = 1;
+    let res = prelude::create_topics_with_specs(
+        conn.clone(),
+        CORRELATION_ID,
+        CLIENT_ID,
+        HashMap::from([(topic, conflicting)]),
+        false,
+    )
+    .await;
+    assert!(matches!(res, Err(Error::ArgError(_))));
+
+    // two replicas need two brokers
+    if metadata.brokers.len() < 2 {
+        return Ok(());
+    }
+    let replicas = vec![metadata.brokers[1].node_id, metadata.brokers[0].node_id];
+
+    let create_res = prelude::create_topics_with_specs(
+        conn.clone(),
+        CORRELATION_ID,
+        CLIENT_ID,
+        HashMap::from([(
+            topic,
+            TopicSpec::with_assignments(vec![(0, replicas.clone())]),
+        )]),
+        false,
+    )
+    .await?;
+    assert_eq!(create_res.topics[0].error_code, KafkaCode::None);
+
+    prelude::await_topic_ready(
+        conn.clone(),
+        CORRELATION_ID,
+        CLIENT_ID,
+        topic,
+        Duration::from_secs(10),
+    )
+    .await?;
+    metadata.add_topics(&[topic.to_string()]).await?;
+    let partition = metadata
+        .get_topic_partition_by_id(topic, 0)
+        .expect("the new topic should have partition 0");
+    assert_eq!(partition.replica_nodes, replicas);
+
+    // Delete topic
+    prelude::delete_topics(conn, CORRELATION_ID, CLIENT_ID, vec![topic]).await?;
+
+    Ok(())
+}
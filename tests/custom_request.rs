@@ -0,0 +1,68 @@
+mod testsupport;
+
+use bytes::{Buf, BufMut, Bytes};
+use samsa::prelude::{
+    encode::ToByte, parser::FromByte, BrokerConnection, Error, Result, TcpConnection,
+};
+
+const API_KEY_API_VERSIONS: i16 = 18;
+const API_KEY_FETCH: i16 = 1;
+
+/// ApiVersions (Version: 0) has an empty request body.
+struct ApiVersionsRequest;
+
+impl ToByte for ApiVersionsRequest {
+    fn encode<T: BufMut>(&self, _buffer: &mut T) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// ApiVersions Response (Version: 0) => error_code [api_keys]
+struct ApiVersionsResponse {
+    error_code: i16,
+    /// api_key, min_version, max_version
+    api_keys: Vec<(i16, i16, i16)>,
+}
+
+impl FromByte for ApiVersionsResponse {
+    fn decode(mut body: Bytes) -> Result<Self> {
+        if body.remaining() < 6 {
+            return Err(Error::ParsingError(body));
+        }
+        let error_code = body.get_i16();
+        let count = body.get_i32();
+        let mut api_keys = vec![];
+        for _ in 0..count {
+            if body.remaining() < 6 {
+                return Err(Error::ParsingError(body));
+            }
+            api_keys.push((body.get_i16(), body.get_i16(), body.get_i16()));
+        }
+
+        Ok(Self {
+            error_code,
+            api_keys,
+        })
+    }
+}
+
+#[tokio::test]
+async fn it_can_send_a_custom_request() -> std::result::Result<(), Box<Error>> {
+    let (skip, brokers) = testsupport::get_brokers()?;
+    if skip {
+        return Ok(());
+    }
+    let mut conn = TcpConnection::new(brokers).await?;
+
+    let response: ApiVersionsResponse = conn
+        .send_custom_request(API_KEY_API_VERSIONS, 0, &ApiVersionsRequest)
+        .await?;
+
+    assert_eq!(response.error_code, 0);
+    assert!(response
+        .api_keys
+        .iter()
+        .any(|(api_key, _, _)| *api_key == API_KEY_FETCH));
+
+    Ok(())
+}
@@ -0,0 +1,144 @@
+mod testsupport;
+
+use bytes::{Buf, BufMut, Bytes};
+use samsa::prelude::{
+    self,
+    encode::ToByte,
+    parser::FromByte,
+    protocol::{
+        self,
+        produce::request::{BatchProducer, RecordBatchAttributes},
+    },
+    BrokerConnection, ClusterMetadata, Error, KafkaCode, TcpConnection, TopicPartitionsBuilder,
+};
+
+const CLIENT_ID: &str = "describe producers integration test";
+const CORRELATION_ID: i32 = 1;
+const PARTITION_ID: i32 = 0;
+const API_KEY_INIT_PRODUCER_ID: i16 = 22;
+
+/// InitProducerId (Version: 1) for a producer that is not transactional.
+struct InitProducerIdRequest;
+
+impl ToByte for InitProducerIdRequest {
+    fn encode<T: BufMut>(&self, buffer: &mut T) -> prelude::Result<()> {
+        buffer.put_i16(-1); // transactional_id
+        buffer.put_i32(60000); // transaction_timeout_ms
+        Ok(())
+    }
+}
+
+struct InitProducerIdResponse {
+    error_code: i16,
+    producer_id: i64,
+    producer_epoch: i16,
+}
+
+impl FromByte for InitProducerIdResponse {
+    fn decode(mut body: Bytes) -> prelude::Result<Self> {
+        if body.remaining() < 16 {
+            return Err(Error::ParsingError(body));
+        }
+        body.get_i32(); // throttle_time_ms
+        Ok(Self {
+            error_code: body.get_i16(),
+            producer_id: body.get_i64(),
+            producer_epoch: body.get_i16(),
+        })
+    }
+}
+
+#[tokio::test]
+async fn it_can_describe_producers() -> Result<(), Box<Error>> {
+    let (skip, brokers, topic) = testsupport::get_brokers_and_topic()?;
+    if skip {
+        return Ok(());
+    }
+    let mut metadata = ClusterMetadata::new(
+        brokers.clone(),
+        CORRELATION_ID,
+        CLIENT_ID.to_owned(),
+        vec![],
+    )
+    .await?;
+    let conn: &mut TcpConnection = metadata
+        .broker_connections
+        .get_mut(&metadata.controller_id)
+        .unwrap();
+    testsupport::ensure_topic_creation(conn.clone(), &topic, CORRELATION_ID, CLIENT_ID).await?;
+
+    let cluster_metadata = ClusterMetadata::<TcpConnection>::new(
+        brokers.clone(),
+        CORRELATION_ID,
+        CLIENT_ID.to_owned(),
+        vec![topic.clone()],
+    )
+    .await?;
+    let assignment = TopicPartitionsBuilder::new()
+        .assign(topic.clone(), vec![PARTITION_ID])
+        .build();
+    let (mut leader_conn, _) =
+        cluster_metadata.get_connections_for_topic_partitions(&assignment)?[0].to_owned();
+
+    //
+    // Produce idempotently
+    //
+    let init: InitProducerIdResponse = leader_conn
+        .send_custom_request(API_KEY_INIT_PRODUCER_ID, 1, &InitProducerIdRequest)
+        .await?;
+    assert_eq!(init.error_code, 0);
+
+    let mut produce_request = protocol::ProduceRequest::new(
+        -1,
+        1000,
+        CORRELATION_ID,
+        CLIENT_ID,
+        RecordBatchAttributes::new(None),
+    );
+    produce_request.set_producer(BatchProducer {
+        producer_id: init.producer_id,
+        producer_epoch: init.producer_epoch,
+        base_sequence: 0,
+    });
+    for value in ["first", "second", "third"] {
+        produce_request.add(&topic, PARTITION_ID, None, Some(Bytes::from(value)), vec![]);
+    }
+    leader_conn.send_request(&produce_request).await?;
+    let produce_response =
+        protocol::ProduceResponse::try_from(leader_conn.receive_response().await?.freeze())?;
+    assert_eq!(
+        produce_response.responses[0].partition_responses[0].error_code,
+        KafkaCode::None
+    );
+
+    //
+    // Test describing producers
+    //
+    let describe_response =
+        prelude::describe_producers(leader_conn.clone(), CORRELATION_ID, CLIENT_ID, &assignment)
+            .await?;
+
+    assert_eq!(describe_response.topics.len(), 1);
+    let partition = &describe_response.topics[0].partitions[0];
+    assert_eq!(partition.partition_index, PARTITION_ID);
+    let producer = partition
+        .active_producers
+        .iter()
+        .find(|producer| producer.producer_id == init.producer_id)
+        .expect("idempotent producer is not active");
+    assert_eq!(producer.producer_epoch, init.producer_epoch as i32);
+    assert_eq!(producer.last_sequence, 2);
+
+    //
+    // Delete topic
+    //
+    prelude::delete_topics(
+        conn.clone(),
+        CORRELATION_ID,
+        CLIENT_ID,
+        vec![topic.as_str()],
+    )
+    .await?;
+
+    Ok(())
+}
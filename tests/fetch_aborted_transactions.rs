@@ -0,0 +1,295 @@
+mod testsupport;
+
+use bytes::{Buf, BufMut, Bytes};
+use samsa::prelude::{
+    self,
+    encode::ToByte,
+    parser::FromByte,
+    protocol::{
+        self,
+        produce::request::{BatchProducer, RecordBatchAttributes},
+    },
+    BrokerConnection, ClusterMetadata, Error, KafkaCode, TcpConnection, TopicPartitionsBuilder,
+};
+
+const CLIENT_ID: &str = "fetch aborted transactions integration test";
+const CORRELATION_ID: i32 = 1;
+const PARTITION_ID: i32 = 0;
+const TRANSACTION_TIMEOUT_MS: i32 = 60000;
+const READ_COMMITTED: i8 = 1;
+const API_KEY_FIND_COORDINATOR: i16 = 10;
+const API_KEY_ADD_PARTITIONS_TO_TXN: i16 = 24;
+const API_KEY_END_TXN: i16 = 26;
+const COORDINATOR_NOT_AVAILABLE: i16 = 15;
+
+/// FindCoordinator (Version: 1) for a transaction coordinator.
+struct FindTransactionCoordinatorRequest<'a> {
+    transactional_id: &'a str,
+}
+
+impl ToByte for FindTransactionCoordinatorRequest<'_> {
+    fn encode<T: BufMut>(&self, buffer: &mut T) -> prelude::Result<()> {
+        self.transactional_id.encode(buffer)?;
+        buffer.put_i8(1); // key_type TRANSACTION
+        Ok(())
+    }
+}
+
+struct FindCoordinatorResponse {
+    error_code: i16,
+    node_id: i32,
+}
+
+impl FromByte for FindCoordinatorResponse {
+    fn decode(mut body: Bytes) -> prelude::Result<Self> {
+        if body.remaining() < 8 {
+            return Err(Error::ParsingError(body));
+        }
+        body.get_i32(); // throttle_time_ms
+        let error_code = body.get_i16();
+        let message_len = body.get_i16();
+        if message_len > 0 {
+            body.advance(message_len as usize);
+        }
+        if body.remaining() < 4 {
+            return Err(Error::ParsingError(body));
+        }
+        Ok(Self {
+            error_code,
+            node_id: body.get_i32(),
+        })
+    }
+}
+
+/// AddPartitionsToTxn (Version: 1) for a single topic partition.
+struct AddPartitionsToTxnRequest<'a> {
+    transactional_id: &'a str,
+    producer_id: i64,
+    producer_epoch: i16,
+    topic: &'a str,
+}
+
+impl ToByte for AddPartitionsToTxnRequest<'_> {
+    fn encode<T: BufMut>(&self, buffer: &mut T) -> prelude::Result<()> {
+        self.transactional_id.encode(buffer)?;
+        buffer.put_i64(self.producer_id);
+        buffer.put_i16(self.producer_epoch);
+        buffer.put_i32(1); // topics
+        self.topic.encode(buffer)?;
+        [PARTITION_ID].encode(buffer)?;
+        Ok(())
+    }
+}
+
+struct AddPartitionsToTxnResponse {
+    error_code: i16,
+}
+
+impl FromByte for AddPartitionsToTxnResponse {
+    fn decode(mut body: Bytes) -> prelude::Result<Self> {
+        // throttle_time_ms, then a single topic with a single partition
+        if body.remaining() < 4 + 4 + 2 {
+            return Err(Error::ParsingError(body));
+        }
+        body.get_i32(); // throttle_time_ms
+        body.get_i32(); // results length
+        let name_len = body.get_i16() as usize;
+        if body.remaining() < name_len + 4 + 4 + 2 {
+            return Err(Error::ParsingError(body));
+        }
+        body.advance(name_len);
+        body.get_i32(); // results length
+        body.get_i32(); // partition_index
+        Ok(Self {
+            error_code: body.get_i16(),
+        })
+    }
+}
+
+/// EndTxn (Version: 1) to abort the transaction.
+struct AbortTxnRequest<'a> {
+    transactional_id: &'a str,
+    producer_id: i64,
+    producer_epoch: i16,
+}
+
+impl ToByte for AbortTxnRequest<'_> {
+    fn encode<T: BufMut>(&self, buffer: &mut T) -> prelude::Result<()> {
+        self.transactional_id.encode(buffer)?;
+        buffer.put_i64(self.producer_id);
+        buffer.put_i16(self.producer_epoch);
+        false.encode(buffer)?; // committed
+        Ok(())
+    }
+}
+
+struct EndTxnResponse {
+    error_code: i16,
+}
+
+impl FromByte for EndTxnResponse {
+    fn decode(mut body: Bytes) -> prelude::Result<Self> {
+        if body.remaining() < 6 {
+            return Err(Error::ParsingError(body));
+        }
+        body.get_i32(); // throttle_time_ms
+        Ok(Self {
+            error_code: body.get_i16(),
+        })
+    }
+}
+
+#[tokio::test]
+async fn it_returns_the_aborted_transactions() -> Result<(), Box<Error>> {
+    let (skip, brokers, topic) = testsupport::get_brokers_and_topic()?;
+    if skip {
+        return Ok(());
+    }
+    let transactional_id = format!("{topic}-txn");
+    let metadata = ClusterMetadata::<TcpConnection>::new(
+        brokers.clone(),
+        CORRELATION_ID,
+        CLIENT_ID.to_owned(),
+        vec![],
+    )
+    .await?;
+    let mut conn = metadata
+        .broker_connections
+        .get(&metadata.controller_id)
+        .unwrap()
+        .clone();
+    testsupport::ensure_topic_creation(conn.clone(), &topic, CORRELATION_ID, CLIENT_ID).await?;
+
+    let cluster_metadata = ClusterMetadata::<TcpConnection>::new(
+        brokers.clone(),
+        CORRELATION_ID,
+        CLIENT_ID.to_owned(),
+        vec![topic.clone()],
+    )
+    .await?;
+    let assignment = TopicPartitionsBuilder::new()
+        .assign(topic.clone(), vec![PARTITION_ID])
+        .build();
+    let (mut leader_conn, _) =
+        cluster_metadata.get_connections_for_topic_partitions(&assignment)?[0].to_owned();
+
+    //
+    // Find the transaction coordinator
+    //
+    let find_coordinator = FindTransactionCoordinatorRequest {
+        transactional_id: &transactional_id,
+    };
+    let mut coordinator: FindCoordinatorResponse = conn
+        .send_custom_request(API_KEY_FIND_COORDINATOR, 1, &find_coordinator)
+        .await?;
+    for _ in 0..10 {
+        if coordinator.error_code != COORDINATOR_NOT_AVAILABLE {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+        coordinator = conn
+            .send_custom_request(API_KEY_FIND_COORDINATOR, 1, &find_coordinator)
+            .await?;
+    }
+    assert_eq!(coordinator.error_code, 0);
+    let mut coordinator_conn = metadata
+        .broker_connections
+        .get(&coordinator.node_id)
+        .unwrap()
+        .clone();
+
+    //
+    // Produce in a transaction, then abort it
+    //
+    let init = prelude::init_producer_id(
+        coordinator_conn.clone(),
+        CORRELATION_ID,
+        CLIENT_ID,
+        Some(&transactional_id),
+        TRANSACTION_TIMEOUT_MS,
+    )
+    .await?;
+
+    let add_partitions: AddPartitionsToTxnResponse = coordinator_conn
+        .send_custom_request(
+            API_KEY_ADD_PARTITIONS_TO_TXN,
+            1,
+            &AddPartitionsToTxnRequest {
+                transactional_id: &transactional_id,
+                producer_id: init.producer_id,
+                producer_epoch: init.producer_epoch,
+                topic: &topic,
+            },
+        )
+        .await?;
+    assert_eq!(add_partitions.error_code, 0);
+
+    let mut attributes = RecordBatchAttributes::new(None);
+    attributes.set_transactional(true);
+    let mut produce_request =
+        protocol::ProduceRequest::new(-1, 1000, CORRELATION_ID, CLIENT_ID, attributes);
+    produce_request.transactional_id = Some(transactional_id.clone());
+    produce_request.set_producer(BatchProducer {
+        producer_id: init.producer_id,
+        producer_epoch: init.producer_epoch,
+        base_sequence: 0,
+    });
+    for value in ["first", "second"] {
+        produce_request.add(&topic, PARTITION_ID, None, Some(Bytes::from(value)), vec![]);
+    }
+    leader_conn.send_request(&produce_request).await?;
+    let produce_response =
+        protocol::ProduceResponse::try_from(leader_conn.receive_response().await?.freeze())?;
+    assert_eq!(
+        produce_response.responses[0].partition_responses[0].error_code,
+        KafkaCode::None
+    );
+
+    let end_txn: EndTxnResponse = coordinator_conn
+        .send_custom_request(
+            API_KEY_END_TXN,
+            1,
+            &AbortTxnRequest {
+                transactional_id: &transactional_id,
+                producer_id: init.producer_id,
+                producer_epoch: init.producer_epoch,
+            },
+        )
+        .await?;
+    assert_eq!(end_txn.error_code, 0);
+
+    //
+    // Test fetching the aborted transactions
+    //
+    let mut fetch_req = protocol::FetchRequest::new(
+        CORRELATION_ID,
+        CLIENT_ID,
+        1000,
+        1,
+        1_000_000,
+        READ_COMMITTED,
+    );
+    fetch_req.add(&topic, PARTITION_ID, 0, 1_000_000);
+    leader_conn.send_request(&fetch_req).await?;
+    let fetch_response =
+        protocol::FetchResponse::try_from(leader_conn.receive_response().await?.freeze())?;
+    let partition = &fetch_response.topics[0].partitions[0];
+    assert_eq!(partition.error_code, KafkaCode::None);
+    assert_eq!(
+        partition.aborted_transaction_offsets(),
+        vec![(init.producer_id, 0)]
+    );
+
+    //
+    // Delete topic
+    //
+    prelude::delete_topics(
+        conn.clone(),
+        CORRELATION_ID,
+        CLIENT_ID,
+        vec![topic.as_str()],
+    )
+    .await?;
+
+    Ok(())
+}
@@ -0,0 +1,92 @@
+mod testsupport;
+
+use bytes::Bytes;
+use samsa::prelude::{
+    self,
+    protocol::{self, produce::request::RecordBatchAttributes},
+    BrokerConnection, ClusterMetadata, Error, KafkaCode, TcpConnection, TopicPartitionsBuilder,
+};
+
+const CLIENT_ID: &str = "fetch max bytes integration test";
+const CORRELATION_ID: i32 = 1;
+const PARTITION_ID: i32 = 0;
+const NUMBER_OF_BATCHES: usize = 5;
+
+async fn fetch_record_count(
+    conn: &mut TcpConnection,
+    topic: &str,
+    max_bytes: i32,
+) -> Result<usize, Box<Error>> {
+    let mut fetch_req =
+        protocol::FetchRequest::new(CORRELATION_ID, CLIENT_ID, 1000, 1, max_bytes, 0);
+    fetch_req.add(topic, PARTITION_ID, 0, 1_000_000);
+    conn.send_request(&fetch_req).await?;
+    let fetch_response =
+        protocol::FetchResponse::try_from(conn.receive_response().await?.freeze())?;
+    let partition = &fetch_response.topics[0].partitions[0];
+    assert_eq!(partition.error_code, KafkaCode::None);
+
+    Ok(partition.clone().into_box_iter().count())
+}
+
+#[tokio::test]
+async fn it_respects_the_fetch_max_bytes() -> Result<(), Box<Error>> {
+    let (skip, brokers, topic) = testsupport::get_brokers_and_topic()?;
+    if skip {
+        return Ok(());
+    }
+    let conn = TcpConnection::new(brokers.clone()).await?;
+    testsupport::ensure_topic_creation(conn.clone(), &topic, CORRELATION_ID, CLIENT_ID).await?;
+
+    let cluster_metadata = ClusterMetadata::<TcpConnection>::new(
+        brokers.clone(),
+        CORRELATION_ID,
+        CLIENT_ID.to_owned(),
+        vec![topic.clone()],
+    )
+    .await?;
+    let assignment = TopicPartitionsBuilder::new()
+        .assign(topic.clone(), vec![PARTITION_ID])
+        .build();
+    let (mut leader_conn, _) =
+        cluster_metadata.get_connections_for_topic_partitions(&assignment)?[0].to_owned();
+
+    //
+    // Produce one batch per request
+    //
+    let value = Bytes::from(vec![b'a'; 1000]);
+    for _ in 0..NUMBER_OF_BATCHES {
+        let mut produce_request = protocol::ProduceRequest::new(
+            1,
+            1000,
+            CORRELATION_ID,
+            CLIENT_ID,
+            RecordBatchAttributes::new(None),
+        );
+        produce_request.add(&topic, PARTITION_ID, None, Some(value.clone()), vec![]);
+        leader_conn.send_request(&produce_request).await?;
+        let produce_response =
+            protocol::ProduceResponse::try_from(leader_conn.receive_response().await?.freeze())?;
+        assert_eq!(
+            produce_response.responses[0].partition_responses[0].error_code,
+            KafkaCode::None
+        );
+    }
+
+    //
+    // Test fetching under a small cap
+    //
+    // The first batch is always returned, even if it is larger than the cap.
+    let capped = fetch_record_count(&mut leader_conn, &topic, 1).await?;
+    assert_eq!(capped, 1);
+
+    let uncapped = fetch_record_count(&mut leader_conn, &topic, 1_000_000).await?;
+    assert_eq!(uncapped, NUMBER_OF_BATCHES);
+
+    //
+    // Delete topic
+    //
+    prelude::delete_topics(conn, CORRELATION_ID, CLIENT_ID, vec![topic.as_str()]).await?;
+
+    Ok(())
+}
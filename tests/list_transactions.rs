@@ -0,0 +1,302 @@
+mod testsupport;
+
+use bytes::{Buf, BufMut, Bytes};
+use samsa::prelude::{
+    self, encode::ToByte, parser::FromByte, BrokerConnection, ClusterMetadata, Error, TcpConnection,
+};
+
+const CLIENT_ID: &str = "list transactions integration test";
+const CORRELATION_ID: i32 = 1;
+const PARTITION_ID: i32 = 0;
+const TRANSACTION_TIMEOUT_MS: i32 = 60000;
+const API_KEY_FIND_COORDINATOR: i16 = 10;
+const API_KEY_INIT_PRODUCER_ID: i16 = 22;
+const API_KEY_ADD_PARTITIONS_TO_TXN: i16 = 24;
+const API_KEY_END_TXN: i16 = 26;
+const COORDINATOR_NOT_AVAILABLE: i16 = 15;
+
+/// FindCoordinator (Version: 1) for a transaction coordinator.
+struct FindTransactionCoordinatorRequest<'a> {
+    transactional_id: &'a str,
+}
+
+impl ToByte for FindTransactionCoordinatorRequest<'_> {
+    fn encode<T: BufMut>(&self, buffer: &mut T) -> prelude::Result<()> {
+        self.transactional_id.encode(buffer)?;
+        buffer.put_i8(1); // key_type TRANSACTION
+        Ok(())
+    }
+}
+
+struct FindCoordinatorResponse {
+    error_code: i16,
+    node_id: i32,
+}
+
+impl FromByte for FindCoordinatorResponse {
+    fn decode(mut body: Bytes) -> prelude::Result<Self> {
+        if body.remaining() < 8 {
+            return Err(Error::ParsingError(body));
+        }
+        body.get_i32(); // throttle_time_ms
+        let error_code = body.get_i16();
+        let message_len = body.get_i16();
+        if message_len > 0 {
+            body.advance(message_len as usize);
+        }
+        if body.remaining() < 4 {
+            return Err(Error::ParsingError(body));
+        }
+        Ok(Self {
+            error_code,
+            node_id: body.get_i32(),
+        })
+    }
+}
+
+/// InitProducerId (Version: 1) for a transactional producer.
+struct InitProducerIdRequest<'a> {
+    transactional_id: &'a str,
+}
+
+impl ToByte for InitProducerIdRequest<'_> {
+    fn encode<T: BufMut>(&self, buffer: &mut T) -> prelude::Result<()> {
+        self.transactional_id.encode(buffer)?;
+        buffer.put_i32(TRANSACTION_TIMEOUT_MS);
+        Ok(())
+    }
+}
+
+struct InitProducerIdResponse {
+    error_code: i16,
+    producer_id: i64,
+    producer_epoch: i16,
+}
+
+impl FromByte for InitProducerIdResponse {
+    fn decode(mut body: Bytes) -> prelude::Result<Self> {
+        if body.remaining() < 16 {
+            return Err(Error::ParsingError(body));
+        }
+        body.get_i32(); // throttle_time_ms
+        Ok(Self {
+            error_code: body.get_i16(),
+            producer_id: body.get_i64(),
+            producer_epoch: body.get_i16(),
+        })
+    }
+}
+
+/// AddPartitionsToTxn (Version: 1) for a single topic partition.
+struct AddPartitionsToTxnRequest<'a> {
+    transactional_id: &'a str,
+    producer_id: i64,
+    producer_epoch: i16,
+    topic: &'a str,
+}
+
+impl ToByte for AddPartitionsToTxnRequest<'_> {
+    fn encode<T: BufMut>(&self, buffer: &mut T) -> prelude::Result<()> {
+        self.transactional_id.encode(buffer)?;
+        buffer.put_i64(self.producer_id);
+        buffer.put_i16(self.producer_epoch);
+        buffer.put_i32(1); // topics
+        self.topic.encode(buffer)?;
+        [PARTITION_ID].encode(buffer)?;
+        Ok(())
+    }
+}
+
+struct AddPartitionsToTxnResponse {
+    error_code: i16,
+}
+
+impl FromByte for AddPartitionsToTxnResponse {
+    fn decode(mut body: Bytes) -> prelude::Result<Self> {
+        // throttle_time_ms, then a single topic with a single partition
+        if body.remaining() < 4 + 4 + 2 {
+            return Err(Error::ParsingError(body));
+        }
+        body.get_i32(); // throttle_time_ms
+        body.get_i32(); // results length
+        let name_len = body.get_i16() as usize;
+        if body.remaining() < name_len + 4 + 4 + 2 {
+            return Err(Error::ParsingError(body));
+        }
+        body.advance(name_len);
+        body.get_i32(); // results length
+        body.get_i32(); // partition_index
+        Ok(Self {
+            error_code: body.get_i16(),
+        })
+    }
+}
+
+/// EndTxn (Version: 1) to abort the transaction.
+struct AbortTxnRequest<'a> {
+    transactional_id: &'a str,
+    producer_id: i64,
+    producer_epoch: i16,
+}
+
+impl ToByte for AbortTxnRequest<'_> {
+    fn encode<T: BufMut>(&self, buffer: &mut T) -> prelude::Result<()> {
+        self.transactional_id.encode(buffer)?;
+        buffer.put_i64(self.producer_id);
+        buffer.put_i16(self.producer_epoch);
+        false.encode(buffer)?; // committed
+        Ok(())
+    }
+}
+
+struct EndTxnResponse {
+    error_code: i16,
+}
+
+impl FromByte for EndTxnResponse {
+    fn decode(mut body: Bytes) -> prelude::Result<Self> {
+        if body.remaining() < 6 {
+            return Err(Error::ParsingError(body));
+        }
+        body.get_i32(); // throttle_time_ms
+        Ok(Self {
+            error_code: body.get_i16(),
+        })
+    }
+}
+
+#[tokio::test]
+async fn it_can_list_and_describe_transactions() -> Result<(), Box<Error>> {
+    let (skip, brokers, topic) = testsupport::get_brokers_and_topic()?;
+    if skip {
+        return Ok(());
+    }
+    let transactional_id = format!("{topic}-txn");
+    let metadata = ClusterMetadata::<TcpConnection>::new(
+        brokers.clone(),
+        CORRELATION_ID,
+        CLIENT_ID.to_owned(),
+        vec![],
+    )
+    .await?;
+    let mut conn = metadata
+        .broker_connections
+        .get(&metadata.controller_id)
+        .unwrap()
+        .clone();
+    testsupport::ensure_topic_creation(conn.clone(), &topic, CORRELATION_ID, CLIENT_ID).await?;
+
+    //
+    // Find the transaction coordinator
+    //
+    let find_coordinator = FindTransactionCoordinatorRequest {
+        transactional_id: &transactional_id,
+    };
+    let mut coordinator: FindCoordinatorResponse = conn
+        .send_custom_request(API_KEY_FIND_COORDINATOR, 1, &find_coordinator)
+        .await?;
+    for _ in 0..10 {
+        if coordinator.error_code != COORDINATOR_NOT_AVAILABLE {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+        coordinator = conn
+            .send_custom_request(API_KEY_FIND_COORDINATOR, 1, &find_coordinator)
+            .await?;
+    }
+    assert_eq!(coordinator.error_code, 0);
+    let mut coordinator_conn = metadata
+        .broker_connections
+        .get(&coordinator.node_id)
+        .unwrap()
+        .clone();
+
+    //
+    // Begin a transaction
+    //
+    let init: InitProducerIdResponse = coordinator_conn
+        .send_custom_request(
+            API_KEY_INIT_PRODUCER_ID,
+            1,
+            &InitProducerIdRequest {
+                transactional_id: &transactional_id,
+            },
+        )
+        .await?;
+    assert_eq!(init.error_code, 0);
+
+    let add_partitions: AddPartitionsToTxnResponse = coordinator_conn
+        .send_custom_request(
+            API_KEY_ADD_PARTITIONS_TO_TXN,
+            1,
+            &AddPartitionsToTxnRequest {
+                transactional_id: &transactional_id,
+                producer_id: init.producer_id,
+                producer_epoch: init.producer_epoch,
+                topic: &topic,
+            },
+        )
+        .await?;
+    assert_eq!(add_partitions.error_code, 0);
+
+    //
+    // Test listing transactions
+    //
+    let list_response =
+        prelude::list_transactions(coordinator_conn.clone(), CORRELATION_ID, CLIENT_ID).await?;
+    let listed = list_response
+        .transaction_states
+        .iter()
+        .find(|state| state.transactional_id == transactional_id.as_bytes())
+        .expect("transaction is not listed");
+    assert_eq!(listed.producer_id, init.producer_id);
+    assert_eq!(listed.transaction_state, Bytes::from("Ongoing"));
+
+    //
+    // Test describing transactions
+    //
+    let describe_response = prelude::describe_transactions(
+        coordinator_conn.clone(),
+        CORRELATION_ID,
+        CLIENT_ID,
+        vec![transactional_id.as_str()],
+    )
+    .await?;
+    assert_eq!(describe_response.transaction_states.len(), 1);
+    let described = &describe_response.transaction_states[0];
+    assert_eq!(described.transaction_state, Bytes::from("Ongoing"));
+    assert_eq!(described.producer_id, init.producer_id);
+    assert_eq!(described.producer_epoch, init.producer_epoch);
+    assert_eq!(described.topics.len(), 1);
+    assert_eq!(described.topics[0].topic, Bytes::from(topic.clone()));
+    assert_eq!(described.topics[0].partitions, vec![PARTITION_ID]);
+
+    //
+    // Abort the transaction
+    //
+    let end_txn: EndTxnResponse = coordinator_conn
+        .send_custom_request(
+            API_KEY_END_TXN,
+            1,
+            &AbortTxnRequest {
+                transactional_id: &transactional_id,
+                producer_id: init.producer_id,
+                producer_epoch: init.producer_epoch,
+            },
+        )
+        .await?;
+    assert_eq!(end_txn.error_code, 0);
+
+    //
+    // Delete topic
+    //
+    prelude::delete_topics(
+        conn.clone(),
+        CORRELATION_ID,
+        CLIENT_ID,
+        vec![topic.as_str()],
+    )
+    .await?;
+
+    Ok(())
+}
@@ -94,3 +94,37 @@ async fn it_can_return_all_the_broker_topics() -> Result<(), Box<Error>> {
 
     Ok(())
 }
+
+#[tokio::test]
+async fn it_describes_the_replicas_of_a_partition() -> Result<(), Box<Error>> {
+    let (skip, brokers, topic) = testsupport::get_brokers_and_topic()?;
+    if skip {
+        return Ok(());
+    }
+    let conn = TcpConnection::new_(brokers.clone()).await?;
+    testsupport::ensure_topic_creation(conn.clone(), &topic, CORRELATION_ID, CLIENT_ID).await?;
+
+    let mut metadata = ClusterMetadata::<TcpConnection>::new(
+        brokers,
+        CORRELATION_ID,
+        CLIENT_ID.to_owned(),
+        vec![],
+    )
+    .await?;
+    let state = metadata
+        .partition_replication_state(&topic, 0)
+        .await?
+        .expect("the topic has a partition 0");
+
+    // the topic is created with a replication factor of 1
+    assert_eq!(state.replicas, vec![state.leader]);
+    assert_eq!(state.isr, vec![state.leader]);
+    assert!(!state.is_under_replicated());
+
+    //
+    // Delete topic
+    //
+    prelude::delete_topics(conn, CORRELATION_ID, CLIENT_ID, vec![topic.as_str()]).await?;
+
+    Ok(())
+}
@@ -43,6 +43,7 @@ async fn multi_partition_writing_and_reading() -> Result<(), Box<Error>> {
         CORRELATION_ID,
         CLIENT_ID,
         HashMap::from([(topic_name.as_str(), NUMBER_OF_PARTITIONS)]),
+        false,
     )
     .await?;
 
@@ -109,7 +110,7 @@ async fn multi_partition_writing_and_reading() -> Result<(), Box<Error>> {
             None => break,
             Some(r) => {
                 assert_eq!(r.topic_name, topic_name.to_string());
-                assert_eq!(r.value, bytes::Bytes::from_static(b"0123456789"));
+                assert_eq!(r.value, Some(bytes::Bytes::from_static(b"0123456789")));
             }
         }
     }
@@ -228,3 +228,92 @@ async fn it_can_commit_and_fetch_offsets_with_functions() -> Result<(), Box<Erro
 
     Ok(())
 }
+
+#[tokio::test]
+async fn it_can_commit_and_fetch_offset_metadata() -> Result<(), Box<Error>> {
+    let (skip, brokers, topic) = testsupport::get_brokers_and_topic()?;
+    if skip {
+        return Ok(());
+    }
+    let topic = format!("{}-metadata", topic);
+    let group_id = "offset metadata integration test";
+    let mut metadata = ClusterMetadata::new(
+        brokers.clone(),
+        CORRELATION_ID,
+        CLIENT_ID.to_owned(),
+        vec![],
+    )
+    .await?;
+    let conn: &mut TcpConnection = metadata
+        .broker_connections
+        .get_mut(&metadata.controller_id)
+        .unwrap();
+    testsupport::ensure_topic_creation(conn.clone(), &topic, CORRELATION_ID, CLIENT_ID).await?;
+
+    //
+    // Get coordinator for this group
+    //
+    let coordinator_res =
+        samsa::prelude::find_coordinator(conn.clone(), CORRELATION_ID, CLIENT_ID, group_id).await?;
+    assert_eq!(coordinator_res.error_code, KafkaCode::None);
+    let host = std::str::from_utf8(coordinator_res.host.as_bytes()).unwrap();
+    let coordinator_conn = TcpConnection::new(vec![BrokerAddress {
+        host: host.to_owned(),
+        port: coordinator_res.port as u16,
+    }])
+    .await?;
+
+    // idk why this helps... maybe redpanda needs a second to accept for the coordinator
+    tokio::time::sleep(tokio::time::Duration::from_secs(3)).await;
+
+    //
+    // Commit an offset with metadata
+    //
+    let offsets = vec![(
+        (topic.clone(), PARTITION_ID),
+        OFFSET,
+        Some("checkpoint-42".to_owned()),
+    )];
+    let offset_commit_response = samsa::prelude::commit_offsets(
+        CORRELATION_ID,
+        CLIENT_ID,
+        group_id,
+        coordinator_conn.clone(),
+        -1,
+        bytes::Bytes::from(""),
+        offsets,
+        1000,
+    )
+    .await?;
+    assert!(offset_commit_response.is_error().is_ok());
+
+    //
+    // Fetch it back
+    //
+    let topic_partitions = HashMap::from([(topic.clone(), vec![PARTITION_ID])]);
+    let committed = samsa::prelude::fetch_committed_offsets(
+        CORRELATION_ID,
+        CLIENT_ID,
+        group_id,
+        coordinator_conn.clone(),
+        &topic_partitions,
+    )
+    .await?;
+    assert_eq!(
+        committed.get(&(topic.clone(), PARTITION_ID)),
+        Some(&(OFFSET, Some("checkpoint-42".to_owned())))
+    );
+
+    //
+    // Delete topic
+    //
+    prelude::delete_topics(
+        conn.clone(),
+        CORRELATION_ID,
+        CLIENT_ID,
+        vec![topic.as_str()],
+    )
+    .await?;
+
+    Ok(())
+}
@@ -2,7 +2,7 @@ mod testsupport;
 
 use samsa::prelude::{self, ClusterMetadata};
 use samsa::prelude::{
-    protocol::{self, produce::request::Attributes},
+    protocol::{self, produce::request::RecordBatchAttributes},
     BrokerConnection, Error, KafkaCode, TcpConnection,
 };
 use std::collections::HashMap;
@@ -47,8 +47,13 @@ async fn it_can_produce_and_fetch() -> Result<(), Box<Error>> {
     //
     // Test producing
     //
-    let mut produce_request =
-        protocol::ProduceRequest::new(1, 1000, CORRELATION_ID, CLIENT_ID, Attributes::new(None));
+    let mut produce_request = protocol::ProduceRequest::new(
+        1,
+        1000,
+        CORRELATION_ID,
+        CLIENT_ID,
+        RecordBatchAttributes::new(None),
+    );
     let header = protocol::Header::new(
         String::from("Header key"),
         bytes::Bytes::from("Header value"),
@@ -161,7 +166,7 @@ async fn it_can_produce_and_fetch_with_functions() -> Result<(), Box<Error>> {
         1,
         1000,
         &vec![produce_message],
-        Attributes::new(None),
+        RecordBatchAttributes::new(None),
     )
     .await?
     .unwrap();
@@ -189,6 +194,7 @@ async fn it_can_produce_and_fetch_with_functions() -> Result<(), Box<Error>> {
         1000,
         1000,
         0,
+        "",
         &HashMap::from([(topic.clone(), vec![PARTITION_ID])]),
         &HashMap::from([((topic.clone(), PARTITION_ID), 0)]),
     )
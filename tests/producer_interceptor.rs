@@ -0,0 +1,78 @@
+mod testsupport;
+
+use samsa::prelude::{
+    self, BrokerConnection, ConsumerBuilder, Error, Header, Interceptor, ProduceMessage,
+    ProducerBuilder, TcpConnection, TopicPartitionsBuilder,
+};
+
+const CLIENT_ID: &str = "producer interceptor integration test";
+const CORRELATION_ID: i32 = 1;
+const PARTITION_ID: i32 = 0;
+const TRACE_ID: &[u8] = b"4bf92f3577b34da6a3ce929d0e0e4736";
+
+struct TraceInterceptor;
+
+impl Interceptor for TraceInterceptor {
+    fn on_send(&self, message: &mut ProduceMessage) {
+        message.headers.push(Header::new(
+            "trace-id".to_owned(),
+            bytes::Bytes::from_static(TRACE_ID),
+        ));
+    }
+}
+
+#[tokio::test]
+async fn it_delivers_the_headers_an_interceptor_adds() -> Result<(), Box<Error>> {
+    let (skip, brokers, topic) = testsupport::get_brokers_and_topic()?;
+    if skip {
+        return Ok(());
+    }
+    let conn = TcpConnection::new(brokers.clone()).await?;
+    testsupport::ensure_topic_creation(conn.clone(), &topic, CORRELATION_ID, CLIENT_ID).await?;
+
+    let producer = ProducerBuilder::<TcpConnection>::new(brokers.clone(), vec![topic.clone()])
+        .await?
+        .required_acks(1)
+        .batch_timeout_ms(1)
+        .interceptor(TraceInterceptor)
+        .clone()
+        .build()
+        .await;
+    producer
+        .send(ProduceMessage {
+            key: None,
+            value: Some(bytes::Bytes::from_static(b"traced")),
+            headers: vec![],
+            topic: topic.clone(),
+            partition_id: PARTITION_ID,
+        })
+        .await?;
+
+    let assignment = TopicPartitionsBuilder::new()
+        .assign(topic.clone(), vec![PARTITION_ID])
+        .build();
+    let mut consumer = ConsumerBuilder::<TcpConnection>::new(brokers, assignment)
+        .await?
+        .build();
+    let mut record = None;
+    for _ in 0..10 {
+        let (mut batch, _) = consumer.next_batch().await?;
+        record = batch.next();
+        if record.is_some() {
+            break;
+        }
+    }
+    let record = record.expect("the traced record was not consumed");
+
+    assert_eq!(record.value, Some(bytes::Bytes::from_static(b"traced")));
+    assert_eq!(record.headers.len(), 1);
+    assert_eq!(record.headers[0].header_key, bytes::Bytes::from("trace-id"));
+    assert_eq!(record.headers[0].value, bytes::Bytes::from_static(TRACE_ID));
+
+    //
+    // Delete topic
+    //
+    prelude::delete_topics(conn, CORRELATION_ID, CLIENT_ID, vec![topic.as_str()]).await?;
+
+    Ok(())
+}
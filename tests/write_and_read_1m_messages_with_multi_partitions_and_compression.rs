@@ -42,6 +42,7 @@ async fn write_and_read_1m_messages_with_multi_partitions_and_compression() -> R
         CORRELATION_ID,
         CLIENT_ID,
         HashMap::from([(topic_name.as_str(), NUMBER_OF_PARTITIONS)]),
+        false,
     )
     .await?;
 